@@ -0,0 +1,86 @@
+use std::io::Error;
+use std::os::fd::AsRawFd;
+
+#[cfg(all(
+    feature = "async",
+    not(any(feature = "tokio", feature = "async-io"))
+))]
+compile_error!(
+    "the \"async\" feature needs a runtime: enable \"tokio\" or \"async-io\""
+);
+
+/** A non-blocking socket, registered with an async runtime's reactor
+
+With the "async-io" feature, this uses the `async-io` crate, which
+works with any executor (async-std, smol, or indeed Tokio); otherwise
+it uses Tokio's `AsyncFd`, which must be used from within a Tokio
+runtime. If both features are enabled, "async-io" wins, as it works
+everywhere.
+ */
+pub(crate) struct AsyncSocket<T: AsRawFd>(Inner<T>);
+
+#[cfg(not(feature = "async-io"))]
+type Inner<T> = tokio::io::unix::AsyncFd<T>;
+
+#[cfg(feature = "async-io")]
+type Inner<T> = async_io::Async<Fd<T>>;
+
+/// async-io wants `AsFd`, which not everything implements yet
+#[cfg(feature = "async-io")]
+struct Fd<T>(T);
+
+#[cfg(feature = "async-io")]
+impl<T: AsRawFd> std::os::fd::AsFd for Fd<T> {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        // SAFETY: the fd is owned by self.0, which outlives the borrow
+        unsafe { std::os::fd::BorrowedFd::borrow_raw(self.0.as_raw_fd()) }
+    }
+}
+
+impl<T: AsRawFd> AsyncSocket<T> {
+    /// Register a socket, which must already be non-blocking
+    pub(crate) fn new(socket: T) -> Result<Self, Error> {
+        #[cfg(not(feature = "async-io"))]
+        return Ok(Self(tokio::io::unix::AsyncFd::new(socket)?));
+
+        #[cfg(feature = "async-io")]
+        return Ok(Self(async_io::Async::new_nonblocking(Fd(socket))?));
+    }
+
+    /// Wait until `op` (a non-blocking read) no longer returns
+    /// `WouldBlock`, and return what it returned
+    pub(crate) async fn read_with<R>(
+        &self,
+        mut op: impl FnMut(&T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        #[cfg(not(feature = "async-io"))]
+        loop {
+            let mut guard = self.0.readable().await?;
+            if let Ok(result) = guard.try_io(|fd| op(fd.get_ref())) {
+                return result;
+            }
+        }
+
+        #[cfg(feature = "async-io")]
+        self.0.read_with(|fd| op(&fd.0)).await
+    }
+
+    /// Wait until `op` (a non-blocking write) no longer returns
+    /// `WouldBlock`, and return what it returned
+    #[cfg(target_os = "linux")]
+    pub(crate) async fn write_with<R>(
+        &self,
+        mut op: impl FnMut(&T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        #[cfg(not(feature = "async-io"))]
+        loop {
+            let mut guard = self.0.writable().await?;
+            if let Ok(result) = guard.try_io(|fd| op(fd.get_ref())) {
+                return result;
+            }
+        }
+
+        #[cfg(feature = "async-io")]
+        self.0.write_with(|fd| op(&fd.0)).await
+    }
+}
@@ -0,0 +1,922 @@
+use crate::async_socket::AsyncSocket;
+use crate::getifaddrs::{get_interfaces, map_interface_flags};
+use crate::interface_names::indextoname;
+use crate::network_event::{
+    AddrInfo, AddressFlags, InterfaceIndex, KnownLinks, LinkInfo, LinkType,
+    NetworkEvent, Scope,
+};
+use async_stream::stream;
+use futures_util::stream::Stream;
+use nix::net::if_::InterfaceFlags;
+use std::io::Error;
+use std::net::IpAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// The type of a function like if_indextoname(3)
+type IndexToNameFn = fn(u32) -> Option<String>;
+
+/// Sockaddrs in routing messages are padded to a multiple of this
+#[cfg(target_vendor = "apple")]
+const SA_ALIGN: usize = 4;
+#[cfg(not(target_vendor = "apple"))]
+const SA_ALIGN: usize = core::mem::size_of::<libc::c_long>();
+
+/// Offsets of the fields which if_msghdr and ifa_msghdr have in common
+/// (after rtm_msglen, rtm_version and rtm_type)
+const OFFSET_ADDRS: usize = 4;
+const OFFSET_FLAGS: usize = 8;
+const OFFSET_INDEX: usize = 12;
+
+/// The largest message expected; reads are one message at a time
+const BUFFER_BYTES: usize = 2048;
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(
+        buf.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> Option<i32> {
+    Some(i32::from_ne_bytes(
+        buf.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Walk the list of sockaddrs which follows a routing message header
+///
+/// There's one sockaddr for each RTA_ bit set in `addrs`, each
+/// `sa_len` bytes long but padded to [`SA_ALIGN`]; a zero-length
+/// sockaddr (e.g. an all-zeroes netmask) still takes up [`SA_ALIGN`]
+/// bytes. The result is indexed by RTAX_ number.
+fn sockaddrs(mut buf: &[u8], addrs: i32) -> [Option<&[u8]>; 8] {
+    let mut result = [None; 8];
+    for (i, slot) in result.iter_mut().enumerate() {
+        if addrs & (1 << i) == 0 {
+            continue;
+        }
+        let Some(&len) = buf.first() else {
+            break;
+        };
+        let len = len as usize;
+        if len > buf.len() {
+            break;
+        }
+        *slot = Some(&buf[..len]);
+        let padded = if len == 0 {
+            SA_ALIGN
+        } else {
+            len.div_ceil(SA_ALIGN) * SA_ALIGN
+        };
+        buf = buf.get(padded..).unwrap_or(&[]);
+    }
+    result
+}
+
+/// The IP address in a sockaddr_in or sockaddr_in6
+fn ip(sa: &[u8]) -> Option<IpAddr> {
+    match *sa.get(1)? as i32 {
+        libc::AF_INET => {
+            let b: [u8; 4] = sa.get(4..8)?.try_into().ok()?;
+            Some(IpAddr::from(b))
+        }
+        libc::AF_INET6 => {
+            let mut b: [u8; 16] = sa.get(8..24)?.try_into().ok()?;
+            // The kernel may embed the scope (interface index) of a
+            // link-local address in its second 16-bit word ("KAME
+            // hack"); the real address has zeroes there
+            if b[0] == 0xFE && (b[1] & 0xC0) == 0x80 {
+                b[2] = 0;
+                b[3] = 0;
+            }
+            Some(IpAddr::from(b))
+        }
+        _ => None,
+    }
+}
+
+/// The prefix length of the netmask for `ip`
+///
+/// The kernel truncates netmasks after their last non-zero byte, and
+/// doesn't always fill in their family, so this goes by the family of
+/// the address, and treats missing bytes as zero.
+fn prefix_len(mask: &[u8], ip: &IpAddr) -> u8 {
+    let (start, len) = match ip {
+        IpAddr::V4(_) => (4, 4),
+        IpAddr::V6(_) => (8, 16),
+    };
+    let mut bytes = [0u8; 16];
+    for (b, m) in bytes.iter_mut().zip(mask.iter().skip(start).take(len)) {
+        *b = *m;
+    }
+    u128::from_be_bytes(bytes).leading_ones() as u8
+}
+
+/// The details of address `ip`/`prefix`, whose RTAX_BRD is `brd`
+///
+/// That's the broadcast address or, on a point-to-point link, the
+/// peer's; the message doesn't say which, but only a broadcast address
+/// has all the host bits of `ip` set. There are no address flags, so
+/// the scope is deduced from the address.
+fn addr_info(ip: &IpAddr, prefix: u8, brd: Option<IpAddr>) -> AddrInfo {
+    let (broadcast, peer) = match (ip, brd) {
+        (IpAddr::V4(v4), Some(IpAddr::V4(b)))
+            if prefix < 31
+                && u32::from(b) == u32::from(*v4) | (u32::MAX >> prefix) =>
+        {
+            (brd, None)
+        }
+        (_, Some(b)) if b != *ip && !b.is_unspecified() => (None, brd),
+        _ => (None, None),
+    };
+    AddrInfo {
+        broadcast,
+        peer,
+        flags: AddressFlags::default(),
+        scope: Scope::from_address(ip),
+    }
+}
+
+/// The interface name in a sockaddr_dl
+fn link_name(sa: &[u8]) -> Option<String> {
+    if *sa.get(1)? as i32 != libc::AF_LINK {
+        return None;
+    }
+    let nlen = *sa.get(5)? as usize;
+    let name = sa.get(8..8 + nlen)?;
+    (!name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned())
+}
+
+fn translate_message(
+    buf: &[u8],
+    indextoname: IndexToNameFn,
+) -> Option<NetworkEvent> {
+    let msglen = read_u16(buf, 0)? as usize;
+    let buf = buf.get(..msglen)?;
+    if *buf.get(2)? as i32 != libc::RTM_VERSION {
+        return None;
+    }
+    match *buf.get(3)? as i32 {
+        libc::RTM_IFINFO => {
+            let addrs = read_i32(buf, OFFSET_ADDRS)?;
+            let flags = read_i32(buf, OFFSET_FLAGS)?;
+            let index = read_u16(buf, OFFSET_INDEX)? as u32;
+            let index = core::num::NonZeroU32::new(index)?;
+            let sas = sockaddrs(
+                buf.get(core::mem::size_of::<libc::if_msghdr>()..)?,
+                addrs,
+            );
+            let name = sas[libc::RTAX_IFP as usize]
+                .and_then(link_name)
+                .or_else(|| indextoname(index.get()))?;
+            // SAFETY: the slicing above checked the length, and any
+            // bit-pattern is a valid if_msghdr
+            let hdr: libc::if_msghdr =
+                unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const _) };
+            Some(NetworkEvent::NewLink(
+                InterfaceIndex(index),
+                name,
+                map_interface_flags(InterfaceFlags::from_bits_truncate(flags)),
+                LinkInfo {
+                    mtu: Some(hdr.ifm_data.ifi_mtu as u32).filter(|m| *m != 0),
+                    link_type: LinkType::from_iftype(
+                        hdr.ifm_data.ifi_type as u32,
+                    ),
+                    ..LinkInfo::default()
+                },
+            ))
+        }
+        libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+            let addrs = read_i32(buf, OFFSET_ADDRS)?;
+            let index = read_u16(buf, OFFSET_INDEX)? as u32;
+            let index = InterfaceIndex(core::num::NonZeroU32::new(index)?);
+            let sas = sockaddrs(
+                buf.get(core::mem::size_of::<libc::ifa_msghdr>()..)?,
+                addrs,
+            );
+            let brd = sas[libc::RTAX_BRD as usize].and_then(ip);
+            let ip = ip(sas[libc::RTAX_IFA as usize]?)?;
+            let prefix = prefix_len(
+                sas[libc::RTAX_NETMASK as usize].unwrap_or(&[]),
+                &ip,
+            );
+            if buf[3] as i32 == libc::RTM_NEWADDR {
+                Some(NetworkEvent::NewAddr(
+                    index,
+                    ip,
+                    prefix,
+                    addr_info(&ip, prefix, brd),
+                ))
+            } else {
+                Some(NetworkEvent::DelAddr(index, ip, prefix))
+            }
+        }
+        #[cfg(target_os = "freebsd")]
+        libc::RTM_IFANNOUNCE => {
+            // if_announcemsghdr has a different layout
+            let index = core::num::NonZeroU32::new(read_u16(buf, 4)? as u32)?;
+            let what = read_u16(buf, 6 + libc::IFNAMSIZ)? as i32;
+            (what == libc::IFAN_DEPARTURE)
+                .then_some(NetworkEvent::DelLink(InterfaceIndex(index)))
+        }
+        _ => None,
+    }
+}
+
+/// Put an event in context, given what's already been announced
+///
+/// NewAddr for an interface not yet seen (the routing socket doesn't
+/// promise any particular order) is preceded by that interface's
+/// NewLink, as obtained by `describe`; RTM_IFINFO for an interface
+/// already seen becomes LinkChanged, or nothing, as by
+/// [`KnownLinks::filter`].
+fn sequence(
+    event: NetworkEvent,
+    known: &mut KnownLinks,
+    describe: impl FnOnce(InterfaceIndex) -> Option<NetworkEvent>,
+) -> Vec<NetworkEvent> {
+    match event {
+        NetworkEvent::NewAddr(ix, ..) if !known.contains(ix) => {
+            match describe(ix).and_then(|link| known.filter(link)) {
+                Some(link) => vec![link, event],
+                None => Vec::new(),
+            }
+        }
+        _ => known.filter(event).into_iter().collect(),
+    }
+}
+
+/// The events announcing a complete listing, as if for the first time
+fn announce(
+    listing: impl Iterator<Item = NetworkEvent>,
+    known: &mut KnownLinks,
+) -> Vec<NetworkEvent> {
+    *known = KnownLinks::default();
+    let mut events = listing
+        .flat_map(|e| sequence(e, known, |_| None))
+        .collect::<Vec<_>>();
+    events.push(NetworkEvent::EnumerationComplete);
+    events
+}
+
+/// Find the NewLink for interface `ix` in a fresh getifaddrs listing
+fn describe_link(ix: InterfaceIndex) -> Option<NetworkEvent> {
+    get_interfaces()
+        .ok()?
+        .find(|e| matches!(e, NetworkEvent::NewLink(i, ..) if *i == ix))
+}
+
+fn open_route_socket() -> Result<OwnedFd, Error> {
+    // SAFETY: no pointers involved
+    let fd = unsafe {
+        libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC)
+    };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    // SAFETY: fd is a newly-opened descriptor, owned by nobody else
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: no pointers involved, and fd is valid
+    unsafe {
+        let fl = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+        if fl < 0
+            || libc::fcntl(
+                fd.as_raw_fd(),
+                libc::F_SETFL,
+                fl | libc::O_NONBLOCK,
+            ) < 0
+            || libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) < 0
+        {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(fd)
+}
+
+/** Obtain the current list of network interfaces and a stream of future events
+
+This is the macOS and FreeBSD implementation of
+`get_interfaces_async`, using a PF_ROUTE routing socket (see route(4));
+see the Linux version for examples, which work unchanged here. As
+there, all interfaces and addresses already present (as listed by
+[`get_interfaces`](crate::get_interfaces)) are first announced as if
+newly-added, and [`NetworkEvent::NewLink`] events always precede the
+[`NetworkEvent::NewAddr`] events for that interface; then a
+[`NetworkEvent::EnumerationComplete`] marks the end of that listing.
+
+A [`NetworkEvent::LinkChanged`] is generated whenever a known
+interface's flags change; [`NetworkEvent::DelLink`] is
+generated only on FreeBSD, as macOS doesn't announce departures on
+the routing socket. If the routing socket overflows, a
+[`NetworkEvent::Overflow`] is followed by a fresh listing, as at the
+start.
+
+NetBSD and OpenBSD lay out their routing messages differently, and
+aren't (yet) supported.
+
+# Errors
+
+Returns Err if the routing socket failed to open, or if the initial
+listing fails. Unless the "async-io" feature is enabled, must be
+called from within a Tokio runtime.
+
+ */
+pub fn get_interfaces_async(
+) -> Result<impl Stream<Item = Result<NetworkEvent, Error>>, Error> {
+    // Open the socket before taking the initial listing, so that no
+    // change can fall between the two
+    let fd = AsyncSocket::new(open_route_socket()?)?;
+    let initial = get_interfaces()?.collect::<Vec<_>>();
+
+    Ok(Box::pin(stream! {
+        let mut known = KnownLinks::default();
+        for event in announce(initial.into_iter(), &mut known) {
+            yield Ok(event);
+        }
+
+        let mut buf = [0u8; BUFFER_BYTES];
+        loop {
+            let rc = fd.read_with(|fd| {
+                // SAFETY: buf is valid for writes of its length
+                let n = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    )
+                };
+                if n < 0 {
+                    Err(Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }).await;
+            match rc {
+                Ok(n) => {
+                    let event = translate_message(&buf[..n], indextoname);
+                    for event in event.into_iter().flat_map(|e| {
+                        sequence(e, &mut known, describe_link)
+                    }) {
+                        yield Ok(event);
+                    }
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // Messages were dropped, so start again
+                    yield Ok(NetworkEvent::Overflow);
+                    match get_interfaces() {
+                        Ok(listing) => {
+                            for event in announce(listing, &mut known) {
+                                yield Ok(event);
+                            }
+                        }
+                        Err(e) => yield Err(e),
+                    }
+                }
+                Err(e) => yield Err(e),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_event::Flags;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    #[allow(clippy::unnecessary_wraps)]
+    fn name_1(index: u32) -> Option<String> {
+        Some(format!("en{index}"))
+    }
+
+    fn no_name(_index: u32) -> Option<String> {
+        None
+    }
+
+    fn pad(v: &mut Vec<u8>) {
+        while v.len() % SA_ALIGN != 0 {
+            v.push(0);
+        }
+    }
+
+    /// A routing message as the kernel would send it: header of
+    /// `header_len` bytes, then the sockaddrs, each padded
+    fn message(
+        msg_type: i32,
+        header_len: usize,
+        addrs: i32,
+        flags: i32,
+        index: u16,
+        sockaddrs: &[&[u8]],
+    ) -> Vec<u8> {
+        let mut v = vec![0u8; header_len];
+        v[2] = libc::RTM_VERSION as u8;
+        v[3] = msg_type as u8;
+        v[OFFSET_ADDRS..OFFSET_ADDRS + 4]
+            .copy_from_slice(&addrs.to_ne_bytes());
+        v[OFFSET_FLAGS..OFFSET_FLAGS + 4]
+            .copy_from_slice(&flags.to_ne_bytes());
+        v[OFFSET_INDEX..OFFSET_INDEX + 2]
+            .copy_from_slice(&index.to_ne_bytes());
+        for sa in sockaddrs {
+            v.extend_from_slice(sa);
+            if sa.is_empty() {
+                v.resize(v.len() + SA_ALIGN, 0);
+            }
+            pad(&mut v);
+        }
+        let len = v.len() as u16;
+        v[0..2].copy_from_slice(&len.to_ne_bytes());
+        v
+    }
+
+    fn sockaddr_in(ip: [u8; 4], len: u8) -> Vec<u8> {
+        let mut v = vec![16, libc::AF_INET as u8, 0, 0];
+        v.extend_from_slice(&ip);
+        v.resize(16, 0);
+        v.truncate(len as usize);
+        v[0] = len;
+        v
+    }
+
+    fn sockaddr_in6(ip: [u8; 16], len: u8) -> Vec<u8> {
+        let mut v = vec![28, libc::AF_INET6 as u8, 0, 0, 0, 0, 0, 0];
+        v.extend_from_slice(&ip);
+        v.resize(28, 0);
+        v.truncate(len as usize);
+        v[0] = len;
+        v
+    }
+
+    fn sockaddr_dl(name: &str) -> Vec<u8> {
+        let mut v = vec![0, libc::AF_LINK as u8, 3, 0, 6, name.len() as u8];
+        v.extend_from_slice(&[0, 0]);
+        v.extend_from_slice(name.as_bytes());
+        v.resize(20.max(v.len()), 0);
+        v[0] = v.len() as u8;
+        v
+    }
+
+    fn ifinfo(
+        flags: i32,
+        index: u16,
+        sockaddrs: &[&[u8]],
+        addrs: i32,
+    ) -> Vec<u8> {
+        message(
+            libc::RTM_IFINFO,
+            core::mem::size_of::<libc::if_msghdr>(),
+            addrs,
+            flags,
+            index,
+            sockaddrs,
+        )
+    }
+
+    fn ifaddr(msg_type: i32, index: u16, mask: &[u8], addr: &[u8]) -> Vec<u8> {
+        message(
+            msg_type,
+            core::mem::size_of::<libc::ifa_msghdr>(),
+            libc::RTA_NETMASK | libc::RTA_IFA,
+            0,
+            index,
+            &[mask, addr],
+        )
+    }
+
+    #[test]
+    fn link_by_index() {
+        let msg = ifinfo(libc::IFF_UP | libc::IFF_RUNNING, 4, &[], 0);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewLink(
+                make_index(4),
+                "en4".to_string(),
+                Flags::UP | Flags::RUNNING,
+                LinkInfo::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn link_mtu_and_type() {
+        let mut msg = ifinfo(libc::IFF_UP, 4, &[], 0);
+        // SAFETY: msg begins with an if_msghdr
+        unsafe {
+            let p = msg.as_mut_ptr() as *mut libc::if_msghdr;
+            let mut hdr = core::ptr::read_unaligned(p);
+            hdr.ifm_data.ifi_mtu = 1500;
+            hdr.ifm_data.ifi_type = 6; // IFT_ETHER
+            core::ptr::write_unaligned(p, hdr);
+        }
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewLink(
+                make_index(4),
+                "en4".to_string(),
+                Flags::UP,
+                LinkInfo {
+                    mtu: Some(1500),
+                    link_type: LinkType::Ethernet,
+                    ..LinkInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn link_by_sockaddr_dl() {
+        let dl = sockaddr_dl("bridge0");
+        let msg = ifinfo(libc::IFF_MULTICAST, 7, &[&dl], libc::RTA_IFP);
+        assert_eq!(
+            translate_message(&msg, no_name),
+            Some(NetworkEvent::NewLink(
+                make_index(7),
+                "bridge0".to_string(),
+                Flags::MULTICAST,
+                LinkInfo::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn link_no_name_ignored() {
+        let msg = ifinfo(libc::IFF_UP, 4, &[], 0);
+        assert_eq!(translate_message(&msg, no_name), None);
+    }
+
+    #[test]
+    fn link_zero_index_ignored() {
+        let msg = ifinfo(libc::IFF_UP, 0, &[], 0);
+        assert_eq!(translate_message(&msg, name_1), None);
+    }
+
+    #[test]
+    fn new_ipv4() {
+        // Netmask truncated after its last non-zero byte, and with no
+        // family
+        let mut mask = sockaddr_in([255, 255, 255, 0], 7);
+        mask[1] = 0;
+        let addr = sockaddr_in([192, 168, 1, 20], 16);
+        let msg = ifaddr(libc::RTM_NEWADDR, 2, &mask, &addr);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(2),
+                Ipv4Addr::new(192, 168, 1, 20).into(),
+                24,
+                AddrInfo::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn del_ipv4() {
+        let mask = sockaddr_in([255, 0, 0, 0], 5);
+        let addr = sockaddr_in([10, 1, 2, 3], 16);
+        let msg = ifaddr(libc::RTM_DELADDR, 2, &mask, &addr);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::DelAddr(
+                make_index(2),
+                Ipv4Addr::new(10, 1, 2, 3).into(),
+                8
+            ))
+        );
+    }
+
+    #[test]
+    fn zero_netmask() {
+        let addr = sockaddr_in([10, 1, 2, 3], 16);
+        let msg = ifaddr(libc::RTM_NEWADDR, 2, &[], &addr);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(2),
+                Ipv4Addr::new(10, 1, 2, 3).into(),
+                0,
+                AddrInfo::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn new_ipv6_link_local() {
+        let mut mask = [0u8; 16];
+        mask[0..8].fill(0xFF);
+        let mask = sockaddr_in6(mask, 16);
+        // Scope embedded in the address, as the kernel does
+        let addr = sockaddr_in6(
+            [0xFE, 0x80, 0, 5, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8],
+            28,
+        );
+        let msg = ifaddr(libc::RTM_NEWADDR, 5, &mask, &addr);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(5),
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 0x102, 0x304, 0x506, 0x708)
+                    .into(),
+                64,
+                AddrInfo {
+                    scope: Scope::Link,
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_ipv6_global() {
+        let mut mask = [0u8; 16];
+        mask[0..6].fill(0xFF);
+        let mask = sockaddr_in6(mask, 14);
+        let addr = sockaddr_in6(
+            [0x20, 0x01, 0x0d, 0xb8, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            28,
+        );
+        let msg = ifaddr(libc::RTM_NEWADDR, 5, &mask, &addr);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(5),
+                Ipv6Addr::new(0x2001, 0xdb8, 5, 0, 0, 0, 0, 1).into(),
+                48,
+                AddrInfo::default()
+            ))
+        );
+    }
+
+    fn ifaddr_brd(
+        index: u16,
+        mask: &[u8],
+        addr: &[u8],
+        brd: &[u8],
+    ) -> Vec<u8> {
+        message(
+            libc::RTM_NEWADDR,
+            core::mem::size_of::<libc::ifa_msghdr>(),
+            libc::RTA_NETMASK | libc::RTA_IFA | libc::RTA_BRD,
+            0,
+            index,
+            &[mask, addr, brd],
+        )
+    }
+
+    #[test]
+    fn new_ipv4_broadcast() {
+        let mask = sockaddr_in([255, 255, 255, 0], 7);
+        let addr = sockaddr_in([192, 168, 1, 20], 16);
+        let brd = sockaddr_in([192, 168, 1, 255], 16);
+        let msg = ifaddr_brd(2, &mask, &addr, &brd);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(2),
+                Ipv4Addr::new(192, 168, 1, 20).into(),
+                24,
+                AddrInfo {
+                    broadcast: Some(Ipv4Addr::new(192, 168, 1, 255).into()),
+                    peer: None,
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_ipv4_peer() {
+        let mask = sockaddr_in([255, 255, 255, 255], 8);
+        let addr = sockaddr_in([10, 0, 0, 2], 16);
+        let peer = sockaddr_in([10, 0, 0, 1], 16);
+        let msg = ifaddr_brd(9, &mask, &addr, &peer);
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(9),
+                Ipv4Addr::new(10, 0, 0, 2).into(),
+                32,
+                AddrInfo {
+                    broadcast: None,
+                    peer: Some(Ipv4Addr::new(10, 0, 0, 1).into()),
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn addr_not_ip_ignored() {
+        let mask = sockaddr_in([255, 0, 0, 0], 5);
+        let dl = sockaddr_dl("en0");
+        let msg = ifaddr(libc::RTM_NEWADDR, 2, &mask, &dl);
+        assert_eq!(translate_message(&msg, name_1), None);
+    }
+
+    #[test]
+    fn addr_missing_ifa_ignored() {
+        let mask = sockaddr_in([255, 0, 0, 0], 5);
+        let msg = message(
+            libc::RTM_NEWADDR,
+            core::mem::size_of::<libc::ifa_msghdr>(),
+            libc::RTA_NETMASK,
+            0,
+            2,
+            &[&mask],
+        );
+        assert_eq!(translate_message(&msg, name_1), None);
+    }
+
+    #[test]
+    fn sockaddrs_skips_unwanted() {
+        // DST, NETMASK and IFA; the walk must step over DST, and
+        // notice GATEWAY's absence
+        let dst = sockaddr_in([192, 168, 1, 0], 16);
+        let mask = sockaddr_in([255, 255, 255, 0], 7);
+        let addr = sockaddr_in([192, 168, 1, 20], 16);
+        let msg = message(
+            libc::RTM_NEWADDR,
+            core::mem::size_of::<libc::ifa_msghdr>(),
+            libc::RTA_DST | libc::RTA_NETMASK | libc::RTA_IFA,
+            0,
+            3,
+            &[&dst, &mask, &addr],
+        );
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::NewAddr(
+                make_index(3),
+                Ipv4Addr::new(192, 168, 1, 20).into(),
+                24,
+                AddrInfo::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn truncated_message_ignored() {
+        let mask = sockaddr_in([255, 255, 255, 0], 7);
+        let addr = sockaddr_in([192, 168, 1, 20], 16);
+        let msg = ifaddr(libc::RTM_NEWADDR, 2, &mask, &addr);
+        for n in 0..msg.len() {
+            assert_eq!(translate_message(&msg[..n], name_1), None);
+        }
+    }
+
+    #[test]
+    fn wrong_version_ignored() {
+        let mut msg = ifinfo(libc::IFF_UP, 4, &[], 0);
+        msg[2] += 1;
+        assert_eq!(translate_message(&msg, name_1), None);
+    }
+
+    #[test]
+    fn other_message_ignored() {
+        let msg = message(
+            libc::RTM_ADD,
+            core::mem::size_of::<libc::if_msghdr>(),
+            0,
+            0,
+            1,
+            &[],
+        );
+        assert_eq!(translate_message(&msg, name_1), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn departure() {
+        let mut msg =
+            vec![0u8; core::mem::size_of::<libc::if_announcemsghdr>()];
+        let len = msg.len() as u16;
+        msg[0..2].copy_from_slice(&len.to_ne_bytes());
+        msg[2] = libc::RTM_VERSION as u8;
+        msg[3] = libc::RTM_IFANNOUNCE as u8;
+        msg[4..6].copy_from_slice(&6u16.to_ne_bytes());
+        let what = 6 + libc::IFNAMSIZ;
+        msg[what..what + 2]
+            .copy_from_slice(&(libc::IFAN_DEPARTURE as u16).to_ne_bytes());
+        assert_eq!(
+            translate_message(&msg, name_1),
+            Some(NetworkEvent::DelLink(make_index(6)))
+        );
+    }
+
+    #[test]
+    fn sequence_link_first() {
+        let mut known = KnownLinks::default();
+        let addr = NetworkEvent::NewAddr(
+            make_index(3),
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            8,
+            AddrInfo::default(),
+        );
+        let link = NetworkEvent::NewLink(
+            make_index(3),
+            "en3".to_string(),
+            Flags::UP,
+            LinkInfo::default(),
+        );
+        assert_eq!(
+            sequence(addr.clone(), &mut known, |_| Some(link.clone())),
+            vec![link.clone(), addr.clone()]
+        );
+        // Now it's known, no need to ask again
+        assert_eq!(
+            sequence(addr.clone(), &mut known, |_| panic!()),
+            vec![addr.clone()]
+        );
+        // Until it goes away
+        assert_eq!(
+            sequence(NetworkEvent::DelLink(make_index(3)), &mut known, |_| {
+                panic!()
+            }),
+            vec![NetworkEvent::DelLink(make_index(3))]
+        );
+        assert_eq!(sequence(addr, &mut known, |_| None), vec![]);
+    }
+
+    #[test]
+    fn sequence_link_changed() {
+        let mut known = KnownLinks::default();
+        let link = |flags| {
+            NetworkEvent::NewLink(
+                make_index(3),
+                "en3".to_string(),
+                flags,
+                LinkInfo::default(),
+            )
+        };
+        let mut script = |event| sequence(event, &mut known, |_| panic!());
+        assert_eq!(
+            script(link(Flags::MULTICAST)),
+            vec![link(Flags::MULTICAST)]
+        );
+        assert_eq!(
+            script(link(Flags::UP | Flags::MULTICAST)),
+            vec![NetworkEvent::LinkChanged(
+                make_index(3),
+                Flags::UP | Flags::MULTICAST
+            )]
+        );
+        assert_eq!(script(link(Flags::UP | Flags::MULTICAST)), vec![]);
+        assert_eq!(
+            script(link(Flags::MULTICAST)),
+            vec![NetworkEvent::LinkChanged(make_index(3), Flags::MULTICAST)]
+        );
+        assert_eq!(
+            script(NetworkEvent::DelLink(make_index(3))),
+            vec![NetworkEvent::DelLink(make_index(3))]
+        );
+        assert_eq!(
+            script(link(Flags::MULTICAST)),
+            vec![link(Flags::MULTICAST)]
+        );
+    }
+
+    #[test]
+    fn announce_afresh() {
+        let link = NetworkEvent::NewLink(
+            make_index(3),
+            "en3".to_string(),
+            Flags::UP,
+            LinkInfo::default(),
+        );
+        let addr = NetworkEvent::NewAddr(
+            make_index(3),
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            8,
+            AddrInfo::default(),
+        );
+        let mut known = KnownLinks::default();
+        let listing = || vec![link.clone(), addr.clone()].into_iter();
+        let expected = vec![
+            link.clone(),
+            addr.clone(),
+            NetworkEvent::EnumerationComplete,
+        ];
+        assert_eq!(announce(listing(), &mut known), expected);
+
+        // After an overflow, known interfaces are announced again
+        assert_eq!(announce(listing(), &mut known), expected);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_instantiate() {
+        assert!(get_interfaces_async().is_ok());
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_instantiate_without_tokio() {
+        async_io::block_on(async {
+            assert!(get_interfaces_async().is_ok());
+        });
+    }
+}
@@ -0,0 +1,696 @@
+use crate::network_event::{
+    AddrInfo, Flags, InterfaceIndex, LinkInfo, NetworkEvent,
+};
+use alloc::collections::VecDeque;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::stream::{Stream, StreamExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Error;
+use std::net::IpAddr;
+
+/** A stream of network events with repeats suppressed, and a cache
+of the current state
+
+The raw stream from [`get_interfaces_async`](crate::get_interfaces_async)
+reports what the kernel says, which isn't always news: Linux repeats
+[`NetworkEvent::NewAddr`] for an address whenever anything about it
+changes, even if nothing the event carries has. A `CoherentStream`
+remembers every interface and address it has passed on, and passes on
+only events which change that picture:
+
+* a `NewLink` or `NewAddr` identical to what's already known is
+  dropped, as is a `LinkChanged` which doesn't change the flags;
+* a `NewLink` for a known interface whose flags alone differ, is
+  passed on as [`NetworkEvent::LinkChanged`];
+* a `DelAddr` or `DelLink` for something not known is dropped; a
+  `DelLink` is preceded by a `DelAddr` for each of the interface's
+  addresses not already removed.
+
+After a [`NetworkEvent::Overflow`], the raw stream announces
+everything afresh. Rather than passing that on (and leaving the
+application to work out what went away), a `CoherentStream` compares
+the fresh listing with its cache: things which are still there aren't
+mentioned again, new things are announced as usual, and, when the
+listing ends, a `DelAddr` or `DelLink` is generated for anything that
+has vanished in the meantime. Neither the `Overflow` nor the second
+[`NetworkEvent::EnumerationComplete`] is passed on: the stream just
+carries on as if no messages had been lost.
+
+At any time, [`CoherentStream::current_state`] returns the cached
+picture, and [`CoherentStream::name_of`] and
+[`CoherentStream::index_of`] look up interface names:
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+let mut s = CoherentStream::get_interfaces_async()?;
+
+while let Some(e) = s.next().await {
+    if let NetworkEvent::EnumerationComplete = e? {
+        for e in s.current_state() {
+            println!("{:?}", e);
+        }
+        break;
+    }
+}
+# Ok::<(), std::io::Error>(())
+# });
+# Ok::<(), std::io::Error>(())
+```
+
+Errors in the underlying stream are passed on unchanged. To report only
+some interfaces, a [`NetworkMonitor`](crate::NetworkMonitor) can
+filter a `CoherentStream` just as it would the raw stream.
+ */
+pub struct CoherentStream<S> {
+    inner: Pin<Box<S>>,
+    cache: Cache,
+    pending: VecDeque<NetworkEvent>,
+}
+
+impl<S> CoherentStream<S>
+where
+    S: Stream<Item = Result<NetworkEvent, Error>>,
+{
+    /// Wrap any stream of network events
+    pub fn new(s: S) -> Self {
+        Self {
+            inner: Box::pin(s),
+            cache: Cache::default(),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl CoherentStream<()> {
+    /// Wrap the stream from [`get_interfaces_async`](crate::get_interfaces_async)
+    ///
+    /// # Errors
+    ///
+    /// As for `get_interfaces_async`.
+    #[cfg(any(unix, windows))]
+    pub fn get_interfaces_async() -> Result<
+        CoherentStream<impl Stream<Item = Result<NetworkEvent, Error>>>,
+        Error,
+    > {
+        Ok(CoherentStream::new(crate::get_interfaces_async()?))
+    }
+}
+
+impl<S> CoherentStream<S> {
+    /// Every interface and address currently known
+    ///
+    /// This is in the same form as a listing from
+    /// [`get_interfaces`](crate::get_interfaces): a
+    /// [`NetworkEvent::NewLink`] for each interface, in index order,
+    /// followed by a [`NetworkEvent::NewAddr`] for each address.
+    #[must_use]
+    pub fn current_state(&self) -> Vec<NetworkEvent> {
+        self.cache.current_state()
+    }
+
+    /// The name of an interface, if it's currently known
+    #[must_use]
+    pub fn name_of(&self, ix: InterfaceIndex) -> Option<&str> {
+        self.cache.links.get(&ix).map(|link| link.name.as_str())
+    }
+
+    /// The index of the interface with this name, if it's currently
+    /// known
+    #[must_use]
+    pub fn index_of(&self, name: &str) -> Option<InterfaceIndex> {
+        self.cache
+            .links
+            .iter()
+            .find_map(|(ix, link)| (link.name == name).then_some(*ix))
+    }
+}
+
+impl<S> Stream for CoherentStream<S>
+where
+    S: Stream<Item = Result<NetworkEvent, Error>>,
+{
+    type Item = Result<NetworkEvent, Error>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(e) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(e)));
+            }
+            match this.inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(e))) => {
+                    this.pending.extend(this.cache.on_event(e));
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+/// Everything known about one interface, bar its addresses
+#[derive(Debug, PartialEq, Eq)]
+struct Link {
+    name: String,
+    flags: Flags,
+    info: LinkInfo,
+}
+
+/// What has been seen again since an overflow
+#[derive(Default)]
+struct Resync {
+    links: BTreeSet<InterfaceIndex>,
+    addrs: BTreeSet<(InterfaceIndex, IpAddr, u8)>,
+}
+
+#[derive(Default)]
+struct Cache {
+    links: BTreeMap<InterfaceIndex, Link>,
+    addrs: BTreeMap<(InterfaceIndex, IpAddr, u8), AddrInfo>,
+    enumerated: bool,
+    resync: Option<Resync>,
+}
+
+impl Cache {
+    /// Turn one event from the underlying stream, into those to report
+    fn on_event(&mut self, e: NetworkEvent) -> Vec<NetworkEvent> {
+        match e {
+            NetworkEvent::NewLink(ix, name, flags, info) => {
+                if let Some(resync) = &mut self.resync {
+                    resync.links.insert(ix);
+                }
+                let link = Link { name, flags, info };
+                match self.links.get_mut(&ix) {
+                    Some(old) if *old == link => Vec::new(),
+                    Some(old)
+                        if old.name == link.name && old.info == link.info =>
+                    {
+                        old.flags = flags;
+                        vec![NetworkEvent::LinkChanged(ix, flags)]
+                    }
+                    _ => {
+                        let e = NetworkEvent::NewLink(
+                            ix,
+                            link.name.clone(),
+                            flags,
+                            link.info.clone(),
+                        );
+                        self.links.insert(ix, link);
+                        vec![e]
+                    }
+                }
+            }
+            NetworkEvent::LinkChanged(ix, flags) => {
+                match self.links.get_mut(&ix) {
+                    Some(link) if link.flags != flags => {
+                        link.flags = flags;
+                        vec![e]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            NetworkEvent::DelLink(ix) => self.del_link(ix),
+            NetworkEvent::NewAddr(ix, ip, prefix, info) => {
+                if let Some(resync) = &mut self.resync {
+                    resync.addrs.insert((ix, ip, prefix));
+                }
+                if self.addrs.insert((ix, ip, prefix), info) == Some(info) {
+                    Vec::new()
+                } else {
+                    vec![e]
+                }
+            }
+            NetworkEvent::DelAddr(ix, ip, prefix) => {
+                if self.addrs.remove(&(ix, ip, prefix)).is_some() {
+                    vec![e]
+                } else {
+                    Vec::new()
+                }
+            }
+            NetworkEvent::Overflow => {
+                // Start (or restart) comparing against the fresh listing
+                self.resync = Some(Resync::default());
+                Vec::new()
+            }
+            NetworkEvent::EnumerationComplete => {
+                let mut events = Vec::new();
+                if let Some(resync) = self.resync.take() {
+                    let gone_addrs = self
+                        .addrs
+                        .keys()
+                        .filter(|k| !resync.addrs.contains(k))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    for (ix, ip, prefix) in gone_addrs {
+                        self.addrs.remove(&(ix, ip, prefix));
+                        events.push(NetworkEvent::DelAddr(ix, ip, prefix));
+                    }
+                    let gone_links = self
+                        .links
+                        .keys()
+                        .filter(|ix| !resync.links.contains(ix))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    for ix in gone_links {
+                        events.extend(self.del_link(ix));
+                    }
+                }
+                if !self.enumerated {
+                    self.enumerated = true;
+                    events.push(e);
+                }
+                events
+            }
+        }
+    }
+
+    /// Remove an interface, and any addresses it still has
+    fn del_link(&mut self, ix: InterfaceIndex) -> Vec<NetworkEvent> {
+        if self.links.remove(&ix).is_none() {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        self.addrs.retain(|(i, ip, prefix), _| {
+            if *i == ix {
+                events.push(NetworkEvent::DelAddr(ix, *ip, *prefix));
+                false
+            } else {
+                true
+            }
+        });
+        events.push(NetworkEvent::DelLink(ix));
+        events
+    }
+
+    fn current_state(&self) -> Vec<NetworkEvent> {
+        self.links
+            .iter()
+            .map(|(ix, link)| {
+                NetworkEvent::NewLink(
+                    *ix,
+                    link.name.clone(),
+                    link.flags,
+                    link.info.clone(),
+                )
+            })
+            .chain(self.addrs.iter().map(|((ix, ip, prefix), info)| {
+                NetworkEvent::NewAddr(*ix, *ip, *prefix, *info)
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_event::AddressFlags;
+    use futures_util::stream;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn new_link(i: u32, name: &str, flags: Flags) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            name.to_string(),
+            flags,
+            LinkInfo::default(),
+        )
+    }
+
+    fn new_addr(i: u32, ip: IpAddr, prefix: u8) -> NetworkEvent {
+        NetworkEvent::NewAddr(make_index(i), ip, prefix, AddrInfo::default())
+    }
+
+    const LAN: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+    const LAN_2: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+    const LINK_LOCAL: IpAddr =
+        IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+
+    const UP: Flags = Flags::UP;
+
+    fn running_flags() -> Flags {
+        Flags::UP | Flags::RUNNING | Flags::MULTICAST
+    }
+
+    /// Feed a script of events through, collecting what's reported
+    fn run(cache: &mut Cache, script: &[NetworkEvent]) -> Vec<NetworkEvent> {
+        script
+            .iter()
+            .flat_map(|e| cache.on_event(e.clone()))
+            .collect()
+    }
+
+    fn enumerated() -> Cache {
+        let mut cache = Cache::default();
+        run(
+            &mut cache,
+            &[
+                new_link(1, "lo", Flags::LOOPBACK | UP),
+                new_link(2, "eth0", running_flags()),
+                new_addr(1, LAN, 8),
+                new_addr(2, LAN, 24),
+                new_addr(2, LINK_LOCAL, 64),
+                NetworkEvent::EnumerationComplete,
+            ],
+        );
+        cache
+    }
+
+    #[test]
+    fn initial_listing_passed_on() {
+        let mut cache = Cache::default();
+        let script = [
+            new_link(2, "eth0", running_flags()),
+            new_addr(2, LAN, 24),
+            NetworkEvent::EnumerationComplete,
+        ];
+        assert_eq!(run(&mut cache, &script), script.to_vec());
+    }
+
+    #[test]
+    fn repeated_addr_suppressed() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(&mut cache, &[new_addr(2, LAN, 24), new_addr(2, LAN, 24)]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn changed_addr_passed_on() {
+        let mut cache = enumerated();
+        let changed = NetworkEvent::NewAddr(
+            make_index(2),
+            LAN,
+            24,
+            AddrInfo {
+                flags: AddressFlags::DEPRECATED,
+                ..AddrInfo::default()
+            },
+        );
+        assert_eq!(
+            run(&mut cache, &[changed.clone(), changed.clone()]),
+            vec![changed]
+        );
+    }
+
+    #[test]
+    fn same_addr_new_prefix_passed_on() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(&mut cache, &[new_addr(2, LAN, 16)]),
+            vec![new_addr(2, LAN, 16)]
+        );
+    }
+
+    #[test]
+    fn repeated_link_suppressed() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    new_link(2, "eth0", running_flags()),
+                    NetworkEvent::LinkChanged(make_index(2), running_flags()),
+                ]
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn link_flags_change_reported_once() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    new_link(2, "eth0", UP),
+                    NetworkEvent::LinkChanged(make_index(2), UP),
+                    NetworkEvent::LinkChanged(make_index(2), running_flags()),
+                ]
+            ),
+            vec![
+                NetworkEvent::LinkChanged(make_index(2), UP),
+                NetworkEvent::LinkChanged(make_index(2), running_flags()),
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_link_announced() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(&mut cache, &[new_link(2, "lan", running_flags())]),
+            vec![new_link(2, "lan", running_flags())]
+        );
+    }
+
+    #[test]
+    fn unknown_deletions_suppressed() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    NetworkEvent::DelAddr(make_index(2), LAN_2, 24),
+                    NetworkEvent::DelLink(make_index(7)),
+                    NetworkEvent::LinkChanged(make_index(7), UP),
+                ]
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn del_addr_reported_once() {
+        let mut cache = enumerated();
+        let del = NetworkEvent::DelAddr(make_index(2), LAN, 24);
+        assert_eq!(run(&mut cache, &[del.clone(), del.clone()]), vec![del]);
+    }
+
+    #[test]
+    fn del_link_removes_addrs() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                    NetworkEvent::DelLink(make_index(2)),
+                ]
+            ),
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::DelAddr(make_index(2), LINK_LOCAL, 64),
+                NetworkEvent::DelLink(make_index(2)),
+            ]
+        );
+        assert_eq!(
+            cache.current_state(),
+            vec![new_link(1, "lo", Flags::LOOPBACK | UP), new_addr(1, LAN, 8)]
+        );
+    }
+
+    #[test]
+    fn overflow_unchanged() {
+        let mut cache = enumerated();
+        let before = cache.current_state();
+        let mut script = vec![NetworkEvent::Overflow];
+        script.extend(before.iter().cloned());
+        script.push(NetworkEvent::EnumerationComplete);
+        assert_eq!(run(&mut cache, &script), vec![]);
+        assert_eq!(cache.current_state(), before);
+    }
+
+    #[test]
+    fn overflow_synthesizes_deletions() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    NetworkEvent::Overflow,
+                    // eth0 has gone, a new interface has appeared, and
+                    // lo has gained an address
+                    new_link(1, "lo", Flags::LOOPBACK | UP),
+                    new_link(3, "wlan0", running_flags()),
+                    new_addr(1, LAN, 8),
+                    new_addr(1, LAN_2, 8),
+                    new_addr(3, LAN, 24),
+                    NetworkEvent::EnumerationComplete,
+                ]
+            ),
+            vec![
+                new_link(3, "wlan0", running_flags()),
+                new_addr(1, LAN_2, 8),
+                new_addr(3, LAN, 24),
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::DelAddr(make_index(2), LINK_LOCAL, 64),
+                NetworkEvent::DelLink(make_index(2)),
+            ]
+        );
+        assert_eq!(
+            cache.current_state(),
+            vec![
+                new_link(1, "lo", Flags::LOOPBACK | UP),
+                new_link(3, "wlan0", running_flags()),
+                new_addr(1, LAN, 8),
+                new_addr(1, LAN_2, 8),
+                new_addr(3, LAN, 24),
+            ]
+        );
+    }
+
+    #[test]
+    fn overflow_vanished_addr_on_surviving_link() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    NetworkEvent::Overflow,
+                    new_link(1, "lo", Flags::LOOPBACK | UP),
+                    new_link(2, "eth0", UP),
+                    new_addr(1, LAN, 8),
+                    new_addr(2, LINK_LOCAL, 64),
+                    NetworkEvent::EnumerationComplete,
+                ]
+            ),
+            vec![
+                NetworkEvent::LinkChanged(make_index(2), UP),
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+            ]
+        );
+    }
+
+    #[test]
+    fn live_events_during_redump() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    NetworkEvent::Overflow,
+                    new_link(1, "lo", Flags::LOOPBACK | UP),
+                    new_link(2, "eth0", running_flags()),
+                    // Live: the address goes while the dump is under way
+                    NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                    new_addr(1, LAN, 8),
+                    new_addr(2, LINK_LOCAL, 64),
+                    NetworkEvent::EnumerationComplete,
+                ]
+            ),
+            vec![NetworkEvent::DelAddr(make_index(2), LAN, 24)]
+        );
+    }
+
+    #[test]
+    fn repeated_overflow_restarts() {
+        let mut cache = enumerated();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    NetworkEvent::Overflow,
+                    new_link(1, "lo", Flags::LOOPBACK | UP),
+                    new_link(2, "eth0", running_flags()),
+                    NetworkEvent::Overflow,
+                    new_link(1, "lo", Flags::LOOPBACK | UP),
+                    new_addr(1, LAN, 8),
+                    NetworkEvent::EnumerationComplete,
+                ]
+            ),
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::DelAddr(make_index(2), LINK_LOCAL, 64),
+                NetworkEvent::DelLink(make_index(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn overflow_during_initial_listing() {
+        let mut cache = Cache::default();
+        assert_eq!(
+            run(
+                &mut cache,
+                &[
+                    new_link(2, "eth0", running_flags()),
+                    new_addr(2, LAN, 24),
+                    NetworkEvent::Overflow,
+                    new_link(2, "eth0", running_flags()),
+                    NetworkEvent::EnumerationComplete,
+                ]
+            ),
+            vec![
+                new_link(2, "eth0", running_flags()),
+                new_addr(2, LAN, 24),
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::EnumerationComplete,
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_names_and_errors() {
+        let script = vec![
+            Ok(new_link(2, "eth0", running_flags())),
+            Ok(new_addr(2, LAN, 24)),
+            Ok(new_addr(2, LAN, 24)),
+            Err(Error::from(std::io::ErrorKind::Other)),
+            Ok(NetworkEvent::EnumerationComplete),
+        ];
+        let mut s = CoherentStream::new(stream::iter(script));
+        tokio_test::block_on(async {
+            assert_eq!(
+                s.next().await.unwrap().unwrap(),
+                new_link(2, "eth0", running_flags())
+            );
+            assert_eq!(s.name_of(make_index(2)), Some("eth0"));
+            assert_eq!(s.index_of("eth0"), Some(make_index(2)));
+            assert_eq!(s.index_of("eth1"), None);
+            assert_eq!(s.next().await.unwrap().unwrap(), new_addr(2, LAN, 24));
+            assert_eq!(
+                s.next().await.unwrap().unwrap_err().kind(),
+                std::io::ErrorKind::Other
+            );
+            assert_eq!(
+                s.next().await.unwrap().unwrap(),
+                NetworkEvent::EnumerationComplete
+            );
+            assert!(s.next().await.is_none());
+        });
+        assert_eq!(
+            s.current_state(),
+            vec![new_link(2, "eth0", running_flags()), new_addr(2, LAN, 24)]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_coherent_enumeration() {
+        let mut s = CoherentStream::get_interfaces_async().unwrap();
+        let mut seen = Vec::new();
+        while let Some(e) = s.next().await {
+            match e.unwrap() {
+                NetworkEvent::EnumerationComplete => break,
+                e => seen.push(e),
+            }
+        }
+        let mut state = s.current_state();
+        state.sort_by_key(|e| format!("{e:?}"));
+        seen.sort_by_key(|e| format!("{e:?}"));
+        assert_eq!(state, seen);
+    }
+}
@@ -0,0 +1,342 @@
+use crate::network_event::{
+    AddrInfo, AddressFlags, Flags, InterfaceIndex, LinkInfo, LinkType,
+    NetworkEvent, OperState, Scope,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use windows_sys::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER,
+    GAA_FLAG_SKIP_MULTICAST, IF_TYPE_SOFTWARE_LOOPBACK,
+    IP_ADAPTER_ADDRESSES_LH, IP_ADAPTER_NO_MULTICAST,
+};
+use windows_sys::Win32::NetworkManagement::Ndis::{
+    IfOperStatusDormant, IfOperStatusLowerLayerDown, IfOperStatusTesting,
+    IfOperStatusUp, IF_OPER_STATUS,
+};
+use windows_sys::Win32::Networking::WinSock::{
+    IpDadStateDeprecated, IpDadStateDuplicate, IpDadStateTentative,
+    IpSuffixOriginRandom, AF_INET, AF_INET6, AF_UNSPEC, NL_DAD_STATE,
+    NL_SUFFIX_ORIGIN, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6,
+};
+
+/// The size of the first buffer tried, as recommended by the
+/// GetAdaptersAddresses documentation
+const INITIAL_BUFFER_BYTES: usize = 15 * 1024;
+
+/** Obtain the current list of network interfaces
+
+This is the Windows implementation of `get_interfaces`, using the IP
+Helper API's `GetAdaptersAddresses`; see the Unix version for
+examples, which work unchanged here. Interfaces are named by their
+"friendly" names (e.g. "Ethernet" or "Loopback Pseudo-Interface 1"),
+and indexed by their IfIndex (or, if IPv4 is disabled, Ipv6IfIndex).
+
+# Errors
+
+Returns Err if the underlying `GetAdaptersAddresses()` call fails.
+
+ */
+pub fn get_interfaces(
+) -> Result<impl Iterator<Item = NetworkEvent>, std::io::Error> {
+    let buf = get_adapters_addresses()?;
+    let mut msgs = Vec::default();
+
+    let mut adapter = buf.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+    // SAFETY: GetAdaptersAddresses has filled `buf` with a linked list
+    // of adapters (and their address lists), all pointing within
+    // `buf`, which lives until the end of this function
+    while let Some(a) = unsafe { adapter.as_ref() } {
+        adapter = a.Next;
+
+        // IfIndex is zero if IPv4 is disabled on this adapter
+        let index = match unsafe { a.Anonymous1.Anonymous.IfIndex } {
+            0 => a.Ipv6IfIndex,
+            n => n,
+        };
+        let Some(index) = core::num::NonZeroU32::new(index) else {
+            continue;
+        };
+        let index = InterfaceIndex(index);
+
+        msgs.push(NetworkEvent::NewLink(
+            index,
+            unsafe { from_wide(a.FriendlyName) },
+            map_adapter_flags(a.IfType, a.OperStatus, unsafe {
+                a.Anonymous2.Flags
+            }),
+            LinkInfo {
+                // Loopback reports an MTU of 0xFFFF_FFFF, i.e. unlimited
+                mtu: Some(a.Mtu).filter(|mtu| *mtu != 0 && *mtu != u32::MAX),
+                link_type: LinkType::from_iftype(a.IfType),
+                oper_state: OperState::from_if_oper_status(
+                    u32::try_from(a.OperStatus).unwrap_or(0),
+                ),
+                ..LinkInfo::default()
+            },
+        ));
+
+        let mut unicast = a.FirstUnicastAddress;
+        while let Some(u) = unsafe { unicast.as_ref() } {
+            unicast = u.Next;
+            if let Some(ip) = unsafe { map_sockaddr(u.Address.lpSockaddr) } {
+                msgs.push(NetworkEvent::NewAddr(
+                    index,
+                    ip,
+                    u.OnLinkPrefixLength,
+                    AddrInfo {
+                        flags: map_address_flags(u.DadState, u.SuffixOrigin),
+                        scope: Scope::from_address(&ip),
+                        ..AddrInfo::default()
+                    },
+                ));
+            }
+        }
+    }
+    Ok(msgs.into_iter())
+}
+
+/// Call GetAdaptersAddresses, growing the buffer until it fits
+///
+/// The buffer is of u64 so that it's suitably aligned for
+/// IP_ADAPTER_ADDRESSES_LH.
+fn get_adapters_addresses() -> Result<Vec<u64>, std::io::Error> {
+    let mut bytes = INITIAL_BUFFER_BYTES as u32;
+    loop {
+        let mut buf = vec![0u64; (bytes as usize).div_ceil(8)];
+        // SAFETY: the buffer is `bytes` long (or more), and aligned
+        let rc = unsafe {
+            GetAdaptersAddresses(
+                AF_UNSPEC as u32,
+                GAA_FLAG_SKIP_ANYCAST
+                    | GAA_FLAG_SKIP_MULTICAST
+                    | GAA_FLAG_SKIP_DNS_SERVER,
+                core::ptr::null(),
+                buf.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH,
+                &mut bytes,
+            )
+        };
+        match rc {
+            NO_ERROR => return Ok(buf),
+            // Adapters can appear between calls, so this can happen
+            // more than once (and `bytes` has been updated)
+            ERROR_BUFFER_OVERFLOW => continue,
+            e => return Err(std::io::Error::from_raw_os_error(e as i32)),
+        }
+    }
+}
+
+/// Convert a NUL-terminated UTF-16 string
+///
+/// # Safety
+///
+/// `p` must be null, or point to a NUL-terminated string
+pub(crate) unsafe fn from_wide(p: *const u16) -> String {
+    if p.is_null() {
+        return String::new();
+    }
+    let mut len = 0;
+    while *p.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(core::slice::from_raw_parts(p, len))
+}
+
+/// Extract the IP address, if any, from a socket address
+///
+/// # Safety
+///
+/// `sa` must be null, or point to a socket address of the size
+/// implied by its address family
+unsafe fn map_sockaddr(sa: *const SOCKADDR) -> Option<IpAddr> {
+    match sa.as_ref()?.sa_family {
+        AF_INET => {
+            let sin = &*(sa as *const SOCKADDR_IN);
+            Some(IpAddr::from(Ipv4Addr::from(u32::from_be(
+                sin.sin_addr.S_un.S_addr,
+            ))))
+        }
+        AF_INET6 => {
+            let sin6 = &*(sa as *const SOCKADDR_IN6);
+            Some(IpAddr::from(Ipv6Addr::from(sin6.sin6_addr.u.Byte)))
+        }
+        _ => None,
+    }
+}
+
+fn map_adapter_flags(
+    if_type: u32,
+    oper_status: IF_OPER_STATUS,
+    adapter_flags: u32,
+) -> Flags {
+    let mut newflags = Flags::default();
+    // Windows reports only the operational status; an interface which
+    // is waiting for something (e.g. a cable) is still "up" in the
+    // Linux sense
+    #[allow(non_upper_case_globals)]
+    match oper_status {
+        IfOperStatusUp => newflags |= Flags::UP | Flags::RUNNING,
+        IfOperStatusDormant
+        | IfOperStatusLowerLayerDown
+        | IfOperStatusTesting => newflags |= Flags::UP,
+        _ => {}
+    }
+    if if_type == IF_TYPE_SOFTWARE_LOOPBACK {
+        newflags |= Flags::LOOPBACK;
+    }
+    if (adapter_flags & IP_ADAPTER_NO_MULTICAST) == 0 {
+        newflags |= Flags::MULTICAST;
+    }
+    newflags
+}
+
+fn map_address_flags(
+    dad_state: NL_DAD_STATE,
+    suffix_origin: NL_SUFFIX_ORIGIN,
+) -> AddressFlags {
+    let mut newflags = AddressFlags::default();
+    #[allow(non_upper_case_globals)]
+    match dad_state {
+        IpDadStateTentative => newflags |= AddressFlags::TENTATIVE,
+        IpDadStateDuplicate => newflags |= AddressFlags::DAD_FAILED,
+        IpDadStateDeprecated => newflags |= AddressFlags::DEPRECATED,
+        _ => {}
+    }
+    // A randomly-generated interface identifier is a privacy address
+    if suffix_origin == IpSuffixOriginRandom {
+        newflags |= AddressFlags::TEMPORARY;
+    }
+    newflags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows_sys::Win32::NetworkManagement::Ndis::{
+        IfOperStatusDown, IfOperStatusNotPresent,
+    };
+
+    const IF_TYPE_ETHERNET_CSMACD: u32 = 6;
+
+    #[test]
+    fn flag_up() {
+        assert_eq!(
+            map_adapter_flags(
+                IF_TYPE_ETHERNET_CSMACD,
+                IfOperStatusUp,
+                IP_ADAPTER_NO_MULTICAST
+            ),
+            Flags::UP | Flags::RUNNING
+        );
+    }
+
+    #[test]
+    fn flag_no_cable() {
+        assert_eq!(
+            map_adapter_flags(
+                IF_TYPE_ETHERNET_CSMACD,
+                IfOperStatusLowerLayerDown,
+                IP_ADAPTER_NO_MULTICAST
+            ),
+            Flags::UP
+        );
+    }
+
+    #[test]
+    fn flag_down() {
+        for status in [IfOperStatusDown, IfOperStatusNotPresent] {
+            assert_eq!(
+                map_adapter_flags(
+                    IF_TYPE_ETHERNET_CSMACD,
+                    status,
+                    IP_ADAPTER_NO_MULTICAST
+                ),
+                Flags::empty()
+            );
+        }
+    }
+
+    #[test]
+    fn flag_loopback() {
+        assert_eq!(
+            map_adapter_flags(
+                IF_TYPE_SOFTWARE_LOOPBACK,
+                IfOperStatusUp,
+                IP_ADAPTER_NO_MULTICAST
+            ),
+            Flags::UP | Flags::RUNNING | Flags::LOOPBACK
+        );
+    }
+
+    #[test]
+    fn flag_multicast() {
+        assert_eq!(
+            map_adapter_flags(IF_TYPE_ETHERNET_CSMACD, IfOperStatusDown, 0),
+            Flags::MULTICAST
+        );
+    }
+
+    #[test]
+    fn address_flags() {
+        use windows_sys::Win32::Networking::WinSock::{
+            IpDadStatePreferred, IpSuffixOriginLinkLayerAddress,
+        };
+        assert_eq!(
+            map_address_flags(
+                IpDadStatePreferred,
+                IpSuffixOriginLinkLayerAddress
+            ),
+            AddressFlags::empty()
+        );
+        assert_eq!(
+            map_address_flags(IpDadStateTentative, IpSuffixOriginRandom),
+            AddressFlags::TENTATIVE | AddressFlags::TEMPORARY
+        );
+        assert_eq!(
+            map_address_flags(
+                IpDadStateDeprecated,
+                IpSuffixOriginLinkLayerAddress
+            ),
+            AddressFlags::DEPRECATED
+        );
+        assert_eq!(
+            map_address_flags(
+                IpDadStateDuplicate,
+                IpSuffixOriginLinkLayerAddress
+            ),
+            AddressFlags::DAD_FAILED
+        );
+    }
+
+    #[test]
+    fn from_wide_null() {
+        assert_eq!(unsafe { from_wide(core::ptr::null()) }, "");
+    }
+
+    #[test]
+    fn from_wide_string() {
+        let s: Vec<u16> = "Ethernet 2\0".encode_utf16().collect();
+        assert_eq!(unsafe { from_wide(s.as_ptr()) }, "Ethernet 2");
+    }
+
+    #[test]
+    fn zzz_instantiate() {
+        let events = get_interfaces().unwrap().collect::<Vec<_>>();
+        let loopback = events
+            .iter()
+            .find_map(|e| match e {
+                NetworkEvent::NewLink(i, _, flags, info)
+                    if flags.contains(Flags::LOOPBACK) =>
+                {
+                    assert_eq!(info.link_type, LinkType::Loopback);
+                    Some(*i)
+                }
+                _ => None,
+            })
+            .expect("no loopback adapter");
+        assert!(events.iter().any(|e| matches!(
+            e,
+            NetworkEvent::NewAddr(i, ip, _, _)
+                if *i == loopback && ip.is_loopback()
+        )));
+    }
+}
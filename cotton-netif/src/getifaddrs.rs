@@ -1,7 +1,11 @@
-use crate::network_event::{Flags, InterfaceIndex, NetworkEvent};
+use crate::network_event::{
+    unalias, AddrInfo, AddressFlags, Flags, InterfaceIndex, LinkInfo,
+    LinkType, NetworkEvent, Scope,
+};
 use nix::ifaddrs;
 use nix::net::if_::InterfaceFlags;
-use std::collections::HashSet;
+use nix::sys::socket::SockaddrStorage;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 
 /// The type of `nix::ifaddrs::getifaddrs`
@@ -10,6 +14,9 @@ type GetIfAddrsFn = fn() -> nix::Result<ifaddrs::InterfaceAddressIterator>;
 /// The type of `nix::net::if_::if_nametoindex`
 type NameToIndexFn = fn(&str) -> nix::Result<libc::c_uint>;
 
+/// The type of `if_mtu`
+type MtuFn = fn(&str) -> Option<u32>;
+
 /** Obtain the current list of network interfaces
 
 The returned iterator provides a sequence of [`NetworkEvent`]
@@ -23,6 +30,19 @@ describing an interface, will be produced before that interface's
 As the list is a snapshot of the current state, no [`NetworkEvent::DelLink`]
 or [`NetworkEvent::DelAddr`] events will be generated.
 
+Every interface gets exactly one [`NetworkEvent::NewLink`], including
+one which is present (and perhaps up) but has no addresses yet, as is
+usual just after boot and before DHCP completes. Entries which don't
+carry a usable IP address and netmask produce no
+[`NetworkEvent::NewAddr`] -- except that, on a point-to-point
+interface, an address with no netmask is given a prefix length of 32
+(or, for IPv6, 128).
+
+The [`InterfaceIndex`] values are the kernel's own interface indexes
+(see if_nametoindex(3)), so they agree with those from
+`get_interfaces_async`, and can be used as, for instance, IPv6 scope
+IDs.
+
 For a simple listing of the returned information, just use println:
 
 ```rust
@@ -34,22 +54,22 @@ for e in get_interfaces()? {
 # Ok::<(), std::io::Error>(())
 ```
 
-The output of that program on an example system might look like this (notice
-that interface `eno1` has three different addresses):
+The output of that program on an example Linux system might look like
+this (notice that interface `eno1` has three different addresses):
 
 ```text
-NewLink(InterfaceIndex(1), "lo", UP | LOOPBACK | RUNNING)
-NewLink(InterfaceIndex(2), "eno1", UP | BROADCAST | RUNNING | MULTICAST)
-NewLink(InterfaceIndex(3), "eno2", UP | BROADCAST | RUNNING | MULTICAST)
-NewLink(InterfaceIndex(4), "imp0", UP | POINTTOPOINT | MULTICAST)
-NewLink(InterfaceIndex(5), "docker0", UP | BROADCAST | MULTICAST)
-NewAddr(InterfaceIndex(1), 127.0.0.1, 8)
-NewAddr(InterfaceIndex(2), 192.168.168.15, 24)
-NewAddr(InterfaceIndex(2), 169.254.100.100, 16)
-NewAddr(InterfaceIndex(4), 169.254.0.1, 24)
-NewAddr(InterfaceIndex(5), 172.17.0.1, 16)
-NewAddr(InterfaceIndex(1), ::1, 128)
-NewAddr(InterfaceIndex(2), fe80::fac0:2a3b:d68e:80a2, 64)
+NewLink(InterfaceIndex(1), "lo", UP | LOOPBACK | RUNNING, LinkInfo { mtu: Some(65536), link_type: Loopback, oper_state: Unknown, parent: None, master: None, kind: None })
+NewLink(InterfaceIndex(2), "eno1", UP | BROADCAST | RUNNING | MULTICAST, LinkInfo { mtu: Some(1500), link_type: Ethernet, oper_state: Unknown, parent: None, master: None, kind: None })
+NewLink(InterfaceIndex(3), "eno2", UP | BROADCAST | RUNNING | MULTICAST, LinkInfo { mtu: Some(1500), link_type: Ethernet, oper_state: Unknown, parent: None, master: None, kind: None })
+NewLink(InterfaceIndex(4), "imp0", UP | POINTTOPOINT | MULTICAST, LinkInfo { mtu: Some(1500), link_type: Tunnel, oper_state: Unknown, parent: None, master: None, kind: None })
+NewLink(InterfaceIndex(5), "docker0", UP | BROADCAST | MULTICAST, LinkInfo { mtu: Some(1500), link_type: Ethernet, oper_state: Unknown, parent: None, master: None, kind: None })
+NewAddr(InterfaceIndex(1), 127.0.0.1, 8, AddrInfo { broadcast: None, peer: None, flags: AddressFlags(0), scope: Host })
+NewAddr(InterfaceIndex(2), 192.168.168.15, 24, AddrInfo { broadcast: Some(192.168.168.255), peer: None, flags: AddressFlags(0), scope: Global })
+NewAddr(InterfaceIndex(2), 169.254.100.100, 16, AddrInfo { broadcast: Some(169.254.255.255), peer: None, flags: AddressFlags(0), scope: Link })
+NewAddr(InterfaceIndex(4), 169.254.0.1, 24, AddrInfo { broadcast: None, peer: Some(169.254.0.2), flags: AddressFlags(0), scope: Link })
+NewAddr(InterfaceIndex(5), 172.17.0.1, 16, AddrInfo { broadcast: Some(172.17.255.255), peer: None, flags: AddressFlags(0), scope: Global })
+NewAddr(InterfaceIndex(1), ::1, 128, AddrInfo { broadcast: None, peer: None, flags: AddressFlags(0), scope: Host })
+NewAddr(InterfaceIndex(2), fe80::fac0:2a3b:d68e:80a2, 64, AddrInfo { broadcast: None, peer: None, flags: AddressFlags(0), scope: Link })
 ```
 
 As another example, here is how to list all available
@@ -60,7 +80,7 @@ multicast-capable interfaces:
 # #[cfg(not(miri))]
 for name in get_interfaces()?
     .filter_map(|e| match e {
-        NetworkEvent::NewLink(_i, name, flags)
+        NetworkEvent::NewLink(_i, name, flags, _info)
             if flags.contains(Flags::RUNNING | Flags::UP | Flags::MULTICAST)
                 => Some(name),
         _ => None,
@@ -70,83 +90,134 @@ for name in get_interfaces()?
 # Ok::<(), std::io::Error>(())
 ```
 
+On Linux, [`Flags::LOWER_UP`] reports the carrier (e.g. whether a
+cable is plugged in) separately, and [`Flags::DORMANT`] an interface
+which has a carrier but isn't yet usable (e.g. awaiting 802.1X
+authentication); either can be added to, or checked alongside, the
+flags above.
+
+And here is how to find the MTU of each interface, skipping tunnels
+(on some platforms, and for some interfaces, the MTU isn't known):
+
+```rust
+# use cotton_netif::*;
+# #[cfg(not(miri))]
+for e in get_interfaces()? {
+    if let NetworkEvent::NewLink(_i, name, _flags, info) = e {
+        if info.link_type != LinkType::Tunnel {
+            println!("{}: MTU {:?}", name, info.mtu);
+        }
+    }
+}
+# Ok::<(), std::io::Error>(())
+```
+
+Being an ordinary iterator, rather than a callback, the listing can
+be stopped early with `break`, errors from the loop body can be
+returned with `?`, and the whole snapshot can be kept by collecting
+it into a `Vec` -- or, to treat it like the dynamic listing, turned
+into a `Stream` with `futures_util::stream::iter`:
+
+```rust
+# use cotton_netif::*;
+# #[cfg(not(miri))]
+# {
+let snapshot = get_interfaces()?.collect::<Vec<_>>();
+let links = snapshot
+    .iter()
+    .filter(|e| matches!(e, NetworkEvent::NewLink(..)))
+    .count();
+println!("{} interfaces, {} addresses", links, snapshot.len() - links);
+# }
+# Ok::<(), std::io::Error>(())
+```
+
 # Errors
 
-Returns Err if the underlying `getifaddrs()` system call fails, see
-getifaddrs(3).
+Returns Err if the underlying system call fails: on Unix, that's
+`getifaddrs()`, see getifaddrs(3); on Windows, it's
+`GetAdaptersAddresses()`.
 
  */
 pub fn get_interfaces(
 ) -> Result<impl Iterator<Item = NetworkEvent>, std::io::Error> {
-    get_interfaces_inner(
+    let events = get_interfaces_inner(
         nix::ifaddrs::getifaddrs,
         nix::net::if_::if_nametoindex::<str>,
-    )
+        if_mtu,
+    )?;
+    #[cfg(all(target_os = "linux", feature = "wireless"))]
+    let events = events.map(crate::linux_wireless::add_wireless);
+    Ok(events)
 }
 
 fn get_interfaces_inner(
     getifaddrs: GetIfAddrsFn,
     nametoindex: NameToIndexFn,
+    mtu: MtuFn,
 ) -> Result<impl Iterator<Item = NetworkEvent>, std::io::Error> {
-    Ok(get_interfaces_inner2(getifaddrs()?.collect(), nametoindex))
+    Ok(get_interfaces_inner2(
+        getifaddrs()?.collect(),
+        nametoindex,
+        mtu,
+    ))
 }
 
 fn get_interfaces_inner2(
     ifaddrs: Vec<nix::ifaddrs::InterfaceAddress>,
     nametoindex: NameToIndexFn,
+    mtu: MtuFn,
 ) -> impl Iterator<Item = NetworkEvent> {
     let mut msgs = Vec::default();
     let mut indexes: HashSet<core::num::NonZeroU32> = HashSet::default();
 
-    for ifaddr in ifaddrs {
-        /* Undo Linux aliasing: "eth0:1" is "eth0" really. */
-        let name = match ifaddr.interface_name.split_once(':') {
-            None => ifaddr.interface_name,
-            Some((prefix, _alias)) => prefix.to_string(),
-        };
-
-        if let Ok(index) = nametoindex(&name[..]) {
+    /* The link type comes from the AF_PACKET/AF_LINK entry, which
+     * needn't be the first for that interface.
+     */
+    let link_types: HashMap<&str, LinkType> = ifaddrs
+        .iter()
+        .filter_map(|ifaddr| {
+            Some((
+                unalias(&ifaddr.interface_name),
+                link_type(ifaddr.address.as_ref()?)?,
+            ))
+        })
+        .collect();
+
+    for ifaddr in &ifaddrs {
+        let name = unalias(&ifaddr.interface_name);
+
+        if let Ok(index) = nametoindex(name) {
             if let Some(index) = core::num::NonZeroU32::new(index) {
                 if indexes.insert(index) {
                     // New entry
+                    let flags = map_interface_flags(ifaddr.flags);
+                    let link_type = link_types.get(name).copied().unwrap_or(
+                        if flags.contains(Flags::LOOPBACK) {
+                            LinkType::Loopback
+                        } else {
+                            LinkType::Other
+                        },
+                    );
                     msgs.push(NetworkEvent::NewLink(
                         InterfaceIndex(index),
-                        name,
-                        map_interface_flags(ifaddr.flags),
+                        name.to_string(),
+                        flags,
+                        LinkInfo {
+                            mtu: mtu(name),
+                            link_type,
+                            ..LinkInfo::default()
+                        },
                     ));
                 }
 
-                if let (Some(addr), Some(mask)) =
-                    (ifaddr.address, ifaddr.netmask)
-                {
-                    if let Some(ipv4) = addr.as_sockaddr_in() {
-                        let ip = IpAddr::from(ipv4.ip());
-                        if let Some(netmask) = mask.as_sockaddr_in() {
-                            msgs.push(NetworkEvent::NewAddr(
-                                InterfaceIndex(index),
-                                ip,
-                                (u32::from_be(
-                                    netmask.as_ref().sin_addr.s_addr,
-                                )
-                                .leading_ones()
-                                    & 0xFF)
-                                    as u8,
-                            ));
-                        }
-                    } else if let Some(ipv6) = addr.as_sockaddr_in6() {
-                        if let Some(netmask) = mask.as_sockaddr_in6() {
-                            msgs.push(NetworkEvent::NewAddr(
-                                InterfaceIndex(index),
-                                IpAddr::from(ipv6.ip()),
-                                (u128::from_be_bytes(
-                                    netmask.as_ref().sin6_addr.s6_addr,
-                                )
-                                .leading_ones()
-                                    & 0xFF)
-                                    as u8,
-                            ));
-                        }
-                    }
+                if let Some((ip, prefix)) = ifaddr_ip(ifaddr) {
+                    msgs.push(NetworkEvent::NewAddr(
+                        InterfaceIndex(index),
+                        ip,
+                        prefix,
+                        addr_info(ifaddr, &ip),
+                    ));
                 }
             }
         }
@@ -154,7 +225,155 @@ fn get_interfaces_inner2(
     msgs.into_iter()
 }
 
-fn map_interface_flags(flags: InterfaceFlags) -> Flags {
+/// The address and prefix length of an entry, if it has both
+///
+/// PPP and WireGuard interfaces, among others, often have no netmask;
+/// on a point-to-point link, the prefix is then the whole address, as
+/// the only other host reachable is the peer.
+fn ifaddr_ip(ifaddr: &ifaddrs::InterfaceAddress) -> Option<(IpAddr, u8)> {
+    let addr = ifaddr.address?;
+    let p2p = ifaddr.flags.contains(InterfaceFlags::IFF_POINTOPOINT);
+    let mask = ifaddr.netmask;
+    if let Some(ipv4) = addr.as_sockaddr_in() {
+        let prefix = match mask.as_ref() {
+            Some(mask) => mask.as_sockaddr_in().map(|netmask| {
+                u32::from_be(netmask.as_ref().sin_addr.s_addr).leading_ones()
+            }),
+            None => p2p.then_some(32),
+        }?;
+        Some((IpAddr::from(ipv4.ip()), (prefix & 0xFF) as u8))
+    } else if let Some(ipv6) = addr.as_sockaddr_in6() {
+        let prefix = match mask.as_ref() {
+            Some(mask) => mask.as_sockaddr_in6().map(|netmask| {
+                u128::from_be_bytes(netmask.as_ref().sin6_addr.s6_addr)
+                    .leading_ones()
+            }),
+            None => p2p.then_some(128),
+        }?;
+        Some((IpAddr::from(ipv6.ip()), (prefix & 0xFF) as u8))
+    } else {
+        None
+    }
+}
+
+/// The IP address in a sockaddr_in or sockaddr_in6
+fn sockaddr_ip(sa: &SockaddrStorage) -> Option<IpAddr> {
+    if let Some(ipv4) = sa.as_sockaddr_in() {
+        Some(IpAddr::from(ipv4.ip()))
+    } else {
+        sa.as_sockaddr_in6().map(|ipv6| IpAddr::from(ipv6.ip()))
+    }
+}
+
+/// The details of address `addr`
+///
+/// The broadcast and peer addresses come from ifa_broadaddr and
+/// ifa_dstaddr, which share a field in `struct ifaddrs`; nix tells
+/// them apart by the interface's IFF_BROADCAST and IFF_POINTOPOINT
+/// flags. There are no address flags, so the scope is deduced from
+/// the address.
+fn addr_info(ifaddr: &ifaddrs::InterfaceAddress, addr: &IpAddr) -> AddrInfo {
+    let ip = |sa: Option<SockaddrStorage>| {
+        sa.as_ref()
+            .and_then(sockaddr_ip)
+            .filter(|ip| !ip.is_unspecified())
+    };
+    AddrInfo {
+        broadcast: ip(ifaddr.broadcast),
+        peer: ip(ifaddr.destination),
+        flags: AddressFlags::default(),
+        scope: Scope::from_address(addr),
+    }
+}
+
+/// The link type, from an AF_PACKET address
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn link_type(addr: &SockaddrStorage) -> Option<LinkType> {
+    Some(LinkType::from_arphrd(addr.as_link_addr()?.hatype()))
+}
+
+/// The link type, from an AF_LINK address
+#[cfg(any(
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn link_type(addr: &SockaddrStorage) -> Option<LinkType> {
+    Some(LinkType::from_iftype(
+        addr.as_link_addr()?.datalink_type() as u32
+    ))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn link_type(_addr: &SockaddrStorage) -> Option<LinkType> {
+    None
+}
+
+/// Find an interface's MTU, using the SIOCGIFMTU ioctl
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd"
+))]
+fn if_mtu(name: &str) -> Option<u32> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[allow(clippy::unnecessary_cast)] // c_ulong is u32 on 32-bit targets
+    const SIOCGIFMTU: u64 = libc::SIOCGIFMTU as u64;
+
+    // _IOWR('i', 51, struct ifreq), which libc doesn't provide here
+    #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+    const SIOCGIFMTU: u64 = 0xC020_6933;
+
+    // SAFETY: ifreq is plain old data, for which zero is valid
+    let mut ifr: libc::ifreq = unsafe { core::mem::zeroed() };
+    if name.len() >= ifr.ifr_name.len() {
+        return None;
+    }
+    for (d, s) in ifr.ifr_name.iter_mut().zip(name.bytes()) {
+        *d = s as libc::c_char;
+    }
+
+    // SAFETY: no pointers involved
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return None;
+    }
+    // SAFETY: fd is a newly-opened descriptor, owned by nobody else
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: SIOCGIFMTU takes a pointer to an ifreq
+    let rc = unsafe { libc::ioctl(fd.as_raw_fd(), SIOCGIFMTU as _, &mut ifr) };
+    if rc < 0 {
+        return None;
+    }
+    // SAFETY: on success, SIOCGIFMTU has filled in ifru_mtu
+    Some(unsafe { ifr.ifr_ifru.ifru_mtu } as u32)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd"
+)))]
+fn if_mtu(_name: &str) -> Option<u32> {
+    None
+}
+
+pub(crate) fn map_interface_flags(flags: InterfaceFlags) -> Flags {
     let mut newflags = Flags::default();
     for (iff, newf) in [
         (InterfaceFlags::IFF_UP, Flags::UP),
@@ -163,6 +382,12 @@ fn map_interface_flags(flags: InterfaceFlags) -> Flags {
         (InterfaceFlags::IFF_POINTOPOINT, Flags::POINTTOPOINT),
         (InterfaceFlags::IFF_BROADCAST, Flags::BROADCAST),
         (InterfaceFlags::IFF_MULTICAST, Flags::MULTICAST),
+        (InterfaceFlags::IFF_NOARP, Flags::NOARP),
+        (InterfaceFlags::IFF_PROMISC, Flags::PROMISC),
+        #[cfg(target_os = "linux")]
+        (InterfaceFlags::IFF_LOWER_UP, Flags::LOWER_UP),
+        #[cfg(target_os = "linux")]
+        (InterfaceFlags::IFF_DORMANT, Flags::DORMANT),
     ] {
         if flags.contains(iff) {
             newflags |= newf;
@@ -195,6 +420,10 @@ mod tests {
         }
     }
 
+    fn no_mtu(_name: &str) -> Option<u32> {
+        None
+    }
+
     #[test]
     fn flag_up() {
         assert_eq!(map_interface_flags(InterfaceFlags::IFF_UP), Flags::UP);
@@ -240,6 +469,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn flag_noarp() {
+        assert_eq!(
+            map_interface_flags(InterfaceFlags::IFF_NOARP),
+            Flags::NOARP
+        );
+    }
+
+    #[test]
+    fn flag_promisc() {
+        assert_eq!(
+            map_interface_flags(InterfaceFlags::IFF_PROMISC),
+            Flags::PROMISC
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn flag_lower_up() {
+        assert_eq!(
+            map_interface_flags(InterfaceFlags::IFF_LOWER_UP),
+            Flags::LOWER_UP
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn flag_dormant() {
+        assert_eq!(
+            map_interface_flags(InterfaceFlags::IFF_DORMANT),
+            Flags::DORMANT
+        );
+    }
+
     #[test]
     fn new_ipv4() {
         let addr = SocketAddrV4::new(Ipv4Addr::new(192, 168, 100, 1), 80);
@@ -254,7 +517,7 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1);
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
 
         let link = iter.next();
 
@@ -265,7 +528,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(1),
                 "eth0".to_string(),
-                Flags::UP
+                Flags::UP,
+                LinkInfo::default()
             )
         );
 
@@ -276,7 +540,8 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(192, 168, 100, 1).into(),
-                24
+                24,
+                AddrInfo::default()
             )
         );
 
@@ -285,6 +550,155 @@ mod tests {
         assert!(fin.is_none());
     }
 
+    #[test]
+    fn new_ipv4_broadcast() {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(192, 168, 100, 1), 0);
+        let mask = SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 0), 0);
+        let brd = SocketAddrV4::new(Ipv4Addr::new(192, 168, 100, 255), 0);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "eth0".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_BROADCAST,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: Some(brd.into()),
+            destination: None,
+        };
+
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
+        iter.next();
+        assert_eq!(
+            iter.next(),
+            Some(NetworkEvent::NewAddr(
+                make_index(1),
+                Ipv4Addr::new(192, 168, 100, 1).into(),
+                24,
+                AddrInfo {
+                    broadcast: Some(Ipv4Addr::new(192, 168, 100, 255).into()),
+                    peer: None,
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_ipv4_peer() {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 0);
+        let mask = SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 255), 0);
+        let peer = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 0);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "ppp0".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_POINTOPOINT,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: None,
+            destination: Some(peer.into()),
+        };
+
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
+        iter.next();
+        assert_eq!(
+            iter.next(),
+            Some(NetworkEvent::NewAddr(
+                make_index(1),
+                Ipv4Addr::new(10, 0, 0, 2).into(),
+                32,
+                AddrInfo {
+                    broadcast: None,
+                    peer: Some(Ipv4Addr::new(10, 0, 0, 1).into()),
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_ipv4_peer_no_netmask() {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 0);
+        let peer = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 0);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "wg0".to_string(),
+            flags: InterfaceFlags::IFF_UP
+                | InterfaceFlags::IFF_RUNNING
+                | InterfaceFlags::IFF_POINTOPOINT,
+            address: Some(addr.into()),
+            netmask: None,
+            broadcast: None,
+            destination: Some(peer.into()),
+        };
+
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
+        iter.next();
+        assert_eq!(
+            iter.next(),
+            Some(NetworkEvent::NewAddr(
+                make_index(1),
+                Ipv4Addr::new(10, 0, 0, 2).into(),
+                32,
+                AddrInfo {
+                    broadcast: None,
+                    peer: Some(Ipv4Addr::new(10, 0, 0, 1).into()),
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn new_ipv6_p2p_no_netmask() {
+        let addr = SocketAddrV6::new(
+            Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2),
+            0,
+            0,
+            0,
+        );
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "wg0".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_POINTOPOINT,
+            address: Some(addr.into()),
+            netmask: None,
+            broadcast: None,
+            destination: None,
+        };
+
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
+        iter.next();
+        assert_eq!(
+            iter.next(),
+            Some(NetworkEvent::NewAddr(
+                make_index(1),
+                Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2).into(),
+                128,
+                AddrInfo::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn new_ipv4_p2p_bad_netmask() {
+        // A netmask of the wrong family is wrong, not absent
+        let addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 0);
+        let mask = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "ppp0".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_POINTOPOINT,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: None,
+            destination: None,
+        };
+
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
+        iter.next();
+        assert_eq!(iter.next(), None);
+    }
+
     #[test]
     fn bad_index_ignored() {
         let addr = SocketAddrV4::new(Ipv4Addr::new(192, 168, 100, 1), 80);
@@ -299,9 +713,11 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr], |_| {
-            Err(nix::errno::Errno::ENOTTY)
-        });
+        let mut iter = get_interfaces_inner2(
+            vec![ifaddr],
+            |_| Err(nix::errno::Errno::ENOTTY),
+            no_mtu,
+        );
 
         let link = iter.next();
 
@@ -322,7 +738,7 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr], |_| Ok(0));
+        let mut iter = get_interfaces_inner2(vec![ifaddr], |_| Ok(0), no_mtu);
 
         let link = iter.next();
 
@@ -354,7 +770,8 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1);
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, no_mtu);
 
         let link = iter.next();
         assert!(link.is_some());
@@ -363,7 +780,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(1),
                 "eth0".to_string(),
-                Flags::UP
+                Flags::UP,
+                LinkInfo::default()
             )
         );
 
@@ -374,7 +792,8 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(192, 168, 100, 1).into(),
-                24
+                24,
+                AddrInfo::default()
             )
         );
 
@@ -416,7 +835,8 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1);
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, no_mtu);
 
         let _a = iter.next(); // link
         let _b = iter.next(); // addr
@@ -451,7 +871,8 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1);
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, no_mtu);
 
         let link = iter.next();
         assert!(link.is_some());
@@ -466,7 +887,11 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(169, 254, 99, 99).into(),
-                16
+                16,
+                AddrInfo {
+                    scope: Scope::Link,
+                    ..AddrInfo::default()
+                }
             )
         );
 
@@ -500,7 +925,8 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1);
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, no_mtu);
 
         let link = iter.next();
         assert!(link.is_some());
@@ -515,7 +941,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(2),
                 "eth1".to_string(),
-                Flags::UP | Flags::RUNNING
+                Flags::UP | Flags::RUNNING,
+                LinkInfo::default()
             )
         );
 
@@ -526,7 +953,11 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(2),
                 Ipv4Addr::new(169, 254, 99, 99).into(),
-                16
+                16,
+                AddrInfo {
+                    scope: Scope::Link,
+                    ..AddrInfo::default()
+                }
             )
         );
 
@@ -570,7 +1001,8 @@ mod tests {
             destination: None,
         };
 
-        let mut iter = get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1);
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, no_mtu);
 
         let link = iter.next(); // Returns IPv4
 
@@ -581,7 +1013,8 @@ mod tests {
             NetworkEvent::NewLink(
                 make_index(1),
                 "eth0".to_string(),
-                Flags::UP
+                Flags::UP,
+                LinkInfo::default()
             )
         );
 
@@ -594,7 +1027,8 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv4Addr::new(192, 168, 100, 1).into(),
-                24
+                24,
+                AddrInfo::default()
             )
         );
 
@@ -607,7 +1041,8 @@ mod tests {
             NetworkEvent::NewAddr(
                 make_index(1),
                 Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
-                32
+                32,
+                AddrInfo::default()
             )
         );
     }
@@ -647,7 +1082,8 @@ mod tests {
             broadcast: None,
             destination: None,
         };
-        let mut iter = get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1);
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, no_mtu);
 
         let link = iter.next();
         assert!(link.is_some());
@@ -657,10 +1093,250 @@ mod tests {
         assert!(fin.is_none());
     }
 
+    #[cfg(target_os = "linux")]
+    #[allow(clippy::unnecessary_wraps)]
+    fn mtu_1500(_name: &str) -> Option<u32> {
+        Some(1500)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn packet_addr(hatype: u16) -> SockaddrStorage {
+        // SAFETY: sockaddr_ll is plain old data
+        let mut sll: libc::sockaddr_ll = unsafe { core::mem::zeroed() };
+        sll.sll_family = libc::AF_PACKET as u16;
+        sll.sll_hatype = hatype;
+        // SAFETY: pointer and length describe sll
+        unsafe {
+            SockaddrStorage::from_raw(
+                &sll as *const libc::sockaddr_ll as *const libc::sockaddr,
+                Some(core::mem::size_of::<libc::sockaddr_ll>() as u32),
+            )
+        }
+        .unwrap()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn link_info_from_packet_entry() {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(192, 168, 100, 1), 80);
+        let mask = SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 0), 80);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "eth0".to_string(),
+            flags: InterfaceFlags::IFF_UP,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: None,
+            destination: None,
+        };
+
+        // The AF_PACKET entry comes after the IPv4 one
+        let ifaddr2 = ifaddrs::InterfaceAddress {
+            interface_name: "eth0".to_string(),
+            flags: InterfaceFlags::IFF_UP,
+            address: Some(packet_addr(libc::ARPHRD_ETHER)),
+            netmask: None,
+            broadcast: None,
+            destination: None,
+        };
+
+        let mut iter =
+            get_interfaces_inner2(vec![ifaddr, ifaddr2], index_1, mtu_1500);
+
+        assert_eq!(
+            iter.next(),
+            Some(NetworkEvent::NewLink(
+                make_index(1),
+                "eth0".to_string(),
+                Flags::UP,
+                LinkInfo {
+                    mtu: Some(1500),
+                    link_type: LinkType::Ethernet,
+                    ..LinkInfo::default()
+                }
+            ))
+        );
+        assert!(matches!(iter.next(), Some(NetworkEvent::NewAddr(..))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn up_but_unaddressed() {
+        // Just the AF_PACKET entry
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "eth0".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING,
+            address: Some(packet_addr(libc::ARPHRD_ETHER)),
+            netmask: None,
+            broadcast: None,
+            destination: None,
+        };
+
+        let events: Vec<_> =
+            get_interfaces_inner2(vec![ifaddr], index_1, mtu_1500).collect();
+
+        assert_eq!(
+            events,
+            vec![NetworkEvent::NewLink(
+                make_index(1),
+                "eth0".to_string(),
+                Flags::UP | Flags::RUNNING,
+                LinkInfo {
+                    mtu: Some(1500),
+                    link_type: LinkType::Ethernet,
+                    ..LinkInfo::default()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn up_but_no_address_entry() {
+        // Some systems have no link-layer entry at all; then there may
+        // be an entry with no address
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "tun0".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_POINTOPOINT,
+            address: None,
+            netmask: None,
+            broadcast: None,
+            destination: None,
+        };
+
+        let events: Vec<_> =
+            get_interfaces_inner2(vec![ifaddr], index_1, no_mtu).collect();
+
+        assert_eq!(
+            events,
+            vec![NetworkEvent::NewLink(
+                make_index(1),
+                "tun0".to_string(),
+                Flags::UP | Flags::POINTTOPOINT,
+                LinkInfo::default()
+            )]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn one_link_per_interface() {
+        let addr = SocketAddrV6::new(
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            80,
+            0,
+            0,
+        );
+        let mask = SocketAddrV6::new(
+            Ipv6Addr::new(0xffff, 0xffff, 0xffff, 0xffff, 0, 0, 0, 0),
+            80,
+            0,
+            0,
+        );
+        let packet = |name: &str| ifaddrs::InterfaceAddress {
+            interface_name: name.to_string(),
+            flags: InterfaceFlags::IFF_UP,
+            address: Some(packet_addr(libc::ARPHRD_ETHER)),
+            netmask: None,
+            broadcast: None,
+            destination: None,
+        };
+        let ipv6 = ifaddrs::InterfaceAddress {
+            interface_name: "eth0".to_string(),
+            flags: InterfaceFlags::IFF_UP,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: None,
+            destination: None,
+        };
+        let index = |name: &str| match name {
+            "eth0" => Ok(1),
+            "eth1" => Ok(2),
+            _ => Err(nix::errno::Errno::ENODEV),
+        };
+
+        // eth1 stays unaddressed
+        let events: Vec<_> = get_interfaces_inner2(
+            vec![packet("eth0"), packet("eth1"), ipv6, packet("eth0")],
+            index,
+            no_mtu,
+        )
+        .collect();
+
+        let links = |ix| {
+            events
+                .iter()
+                .filter(
+                    |e| matches!(e, NetworkEvent::NewLink(i, ..) if *i == ix),
+                )
+                .count()
+        };
+        assert_eq!(links(make_index(1)), 1);
+        assert_eq!(links(make_index(2)), 1);
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, NetworkEvent::NewAddr(..)))
+                .collect::<Vec<_>>(),
+            vec![&NetworkEvent::NewAddr(
+                make_index(1),
+                addr.ip().to_owned().into(),
+                64,
+                AddrInfo {
+                    scope: Scope::Link,
+                    ..AddrInfo::default()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn link_type_loopback_fallback() {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 80);
+        let mask = SocketAddrV4::new(Ipv4Addr::new(255, 0, 0, 0), 80);
+
+        let ifaddr = ifaddrs::InterfaceAddress {
+            interface_name: "lo".to_string(),
+            flags: InterfaceFlags::IFF_UP | InterfaceFlags::IFF_LOOPBACK,
+            address: Some(addr.into()),
+            netmask: Some(mask.into()),
+            broadcast: None,
+            destination: None,
+        };
+
+        let mut iter = get_interfaces_inner2(vec![ifaddr], index_1, no_mtu);
+
+        assert_eq!(
+            iter.next(),
+            Some(NetworkEvent::NewLink(
+                make_index(1),
+                "lo".to_string(),
+                Flags::UP | Flags::LOOPBACK,
+                LinkInfo {
+                    mtu: None,
+                    link_type: LinkType::Loopback,
+                    ..LinkInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[cfg_attr(miri, ignore)]
+    fn mtu_of_loopback() {
+        assert!(if_mtu("lo").is_some_and(|mtu| mtu > 0));
+        assert_eq!(if_mtu("no-such-interface"), None);
+        assert_eq!(if_mtu("a-name-much-too-long-for-ifreq"), None);
+    }
+
     #[test]
     fn get_interfaces_passes_through_errors() {
-        let s =
-            get_interfaces_inner(|| Err(nix::errno::Errno::ENOTTY), index_1);
+        let s = get_interfaces_inner(
+            || Err(nix::errno::Errno::ENOTTY),
+            index_1,
+            no_mtu,
+        );
         assert!(s.is_err());
     }
 
@@ -0,0 +1,272 @@
+use crate::network_event::{InterfaceIndex, NetworkEvent};
+use std::collections::btree_map::{BTreeMap, Entry};
+
+/** A map between interface indexes and names, kept current from events
+
+After the initial [`NetworkEvent::NewLink`], events only identify
+interfaces by their [`InterfaceIndex`]. An `InterfaceNames` watches
+the events as they go by -- from
+[`get_interfaces_async`](crate::get_interfaces_async), or from a
+[`get_interfaces`](crate::get_interfaces) snapshot -- and remembers
+the names, so that they can be looked up later:
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+let mut names = InterfaceNames::new();
+let mut s = get_interfaces_async()?;
+
+while let Some(e) = s.next().await {
+    let e = e?;
+    names.observe(&e);
+    if let NetworkEvent::DelAddr(ix, addr, _) = e {
+        println!("{} removed from {:?}", addr, names.name_of(ix));
+    }
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+# });
+# Ok::<(), std::io::Error>(())
+```
+
+Names are those found in the events, so on Windows they are
+"friendly" names such as "Ethernet".
+ */
+#[derive(Default, Debug)]
+pub struct InterfaceNames {
+    names: BTreeMap<InterfaceIndex, String>,
+}
+
+impl InterfaceNames {
+    /// Create an empty map
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the map from an event
+    ///
+    /// [`NetworkEvent::NewLink`] adds (or renames) an interface, and
+    /// [`NetworkEvent::DelLink`] removes it; an
+    /// [`NetworkEvent::Overflow`] empties the map, as all the
+    /// interfaces are about to be announced again.
+    pub fn observe(&mut self, e: &NetworkEvent) {
+        match e {
+            NetworkEvent::NewLink(ix, name, _, _) => {
+                self.names.insert(*ix, name.clone());
+            }
+            NetworkEvent::DelLink(ix) => {
+                self.names.remove(ix);
+            }
+            NetworkEvent::Overflow => self.names.clear(),
+            _ => {}
+        }
+    }
+
+    /// The name of an interface, if it's been seen
+    #[must_use]
+    pub fn name_of(&self, ix: InterfaceIndex) -> Option<&str> {
+        self.names.get(&ix).map(String::as_str)
+    }
+
+    /// The index of the interface with this name, if it's been seen
+    #[must_use]
+    pub fn index_of(&self, name: &str) -> Option<InterfaceIndex> {
+        self.names
+            .iter()
+            .find_map(|(ix, n)| (n == name).then_some(*ix))
+    }
+
+    /// The name of an interface, asking the system if it hasn't been seen
+    ///
+    /// This covers interfaces which were announced before the map was
+    /// created; a name obtained this way is remembered. Returns None
+    /// if the system doesn't know the interface either (perhaps it has
+    /// since gone away).
+    pub fn resolve(&mut self, ix: InterfaceIndex) -> Option<&str> {
+        self.resolve_inner(ix, indextoname)
+    }
+
+    fn resolve_inner(
+        &mut self,
+        ix: InterfaceIndex,
+        indextoname: fn(u32) -> Option<String>,
+    ) -> Option<&str> {
+        match self.names.entry(ix) {
+            Entry::Occupied(e) => Some(e.into_mut().as_str()),
+            Entry::Vacant(e) => {
+                Some(e.insert(indextoname(ix.0.get())?).as_str())
+            }
+        }
+    }
+}
+
+impl Extend<NetworkEvent> for InterfaceNames {
+    fn extend<T: IntoIterator<Item = NetworkEvent>>(&mut self, iter: T) {
+        for e in iter {
+            self.observe(&e);
+        }
+    }
+}
+
+impl FromIterator<NetworkEvent> for InterfaceNames {
+    /// Build the map from a snapshot, such as from `get_interfaces`
+    fn from_iter<T: IntoIterator<Item = NetworkEvent>>(iter: T) -> Self {
+        let mut names = Self::new();
+        names.extend(iter);
+        names
+    }
+}
+
+/// Ask the system for an interface's name
+#[cfg(unix)]
+pub(crate) fn indextoname(index: u32) -> Option<String> {
+    nix::net::if_::if_indextoname(index)
+        .ok()
+        .map(|s| s.to_string_lossy().into_owned())
+}
+
+/// Ask the system for an interface's name
+///
+/// This is its "alias", which is the same as the adapter's
+/// FriendlyName, as used in `get_interfaces`.
+#[cfg(windows)]
+pub(crate) fn indextoname(index: u32) -> Option<String> {
+    use windows_sys::Win32::Foundation::NO_ERROR;
+    use windows_sys::Win32::NetworkManagement::IpHelper::{
+        ConvertInterfaceIndexToLuid, ConvertInterfaceLuidToAlias,
+    };
+    use windows_sys::Win32::NetworkManagement::Ndis::{
+        IF_MAX_STRING_SIZE, NET_LUID_LH,
+    };
+
+    let mut luid = NET_LUID_LH { Value: 0 };
+    let mut alias = [0u16; IF_MAX_STRING_SIZE as usize + 1];
+    // SAFETY: both calls write only within the buffers passed, and
+    // ConvertInterfaceLuidToAlias NUL-terminates the alias
+    unsafe {
+        if ConvertInterfaceIndexToLuid(index, &mut luid) != NO_ERROR
+            || ConvertInterfaceLuidToAlias(
+                &luid,
+                alias.as_mut_ptr(),
+                alias.len(),
+            ) != NO_ERROR
+        {
+            return None;
+        }
+        Some(crate::getadaptersaddresses::from_wide(alias.as_ptr()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_event::{Flags, LinkInfo};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn new_link(i: u32, name: &str) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            name.to_string(),
+            Flags::UP,
+            LinkInfo::default(),
+        )
+    }
+
+    #[test]
+    fn new_and_del_link() {
+        let mut names = InterfaceNames::new();
+        names.observe(&new_link(2, "eth0"));
+        assert_eq!(names.name_of(make_index(2)), Some("eth0"));
+        assert_eq!(names.index_of("eth0"), Some(make_index(2)));
+
+        names.observe(&NetworkEvent::DelLink(make_index(2)));
+        assert_eq!(names.name_of(make_index(2)), None);
+        assert_eq!(names.index_of("eth0"), None);
+    }
+
+    #[test]
+    fn rename() {
+        let mut names = InterfaceNames::new();
+        names.observe(&new_link(2, "eth0"));
+        names.observe(&new_link(2, "lan"));
+        assert_eq!(names.name_of(make_index(2)), Some("lan"));
+        assert_eq!(names.index_of("eth0"), None);
+    }
+
+    #[test]
+    fn overflow_clears() {
+        let mut names = InterfaceNames::new();
+        names.observe(&new_link(2, "eth0"));
+        names.observe(&NetworkEvent::Overflow);
+        assert_eq!(names.name_of(make_index(2)), None);
+    }
+
+    #[test]
+    fn other_events_ignored() {
+        let mut names = InterfaceNames::new();
+        names.observe(&NetworkEvent::LinkChanged(make_index(2), Flags::UP));
+        names.observe(&NetworkEvent::EnumerationComplete);
+        assert_eq!(names.name_of(make_index(2)), None);
+    }
+
+    #[test]
+    fn from_snapshot() {
+        let names: InterfaceNames =
+            vec![new_link(1, "lo"), new_link(2, "eth0")]
+                .into_iter()
+                .collect();
+        assert_eq!(names.name_of(make_index(1)), Some("lo"));
+        assert_eq!(names.index_of("eth0"), Some(make_index(2)));
+    }
+
+    #[allow(clippy::unnecessary_wraps)]
+    fn name_eth7(_: u32) -> Option<String> {
+        Some("eth7".to_string())
+    }
+
+    fn no_name(_: u32) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn resolve_falls_back() {
+        let mut names = InterfaceNames::new();
+        names.observe(&new_link(2, "eth0"));
+        assert_eq!(names.resolve_inner(make_index(2), no_name), Some("eth0"));
+        assert_eq!(names.resolve_inner(make_index(7), no_name), None);
+        assert_eq!(
+            names.resolve_inner(make_index(7), name_eth7),
+            Some("eth7")
+        );
+        // ...and remembers it
+        assert_eq!(names.name_of(make_index(7)), Some("eth7"));
+        assert_eq!(names.index_of("eth7"), Some(make_index(7)));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_resolve_loopback() {
+        let snapshot: InterfaceNames =
+            crate::get_interfaces().unwrap().collect();
+        let lo = crate::get_interfaces()
+            .unwrap()
+            .find_map(|e| match e {
+                NetworkEvent::NewLink(ix, _, flags, _)
+                    if flags.contains(Flags::LOOPBACK) =>
+                {
+                    Some(ix)
+                }
+                _ => None,
+            })
+            .unwrap();
+        let mut empty = InterfaceNames::new();
+        assert_eq!(empty.resolve(lo), snapshot.name_of(lo));
+    }
+}
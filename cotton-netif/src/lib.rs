@@ -7,9 +7,52 @@
 //! listing (i.e., getting events as network interfaces and addresses
 //! come and go) using [`get_interfaces_async`].
 //!
-//! At present this crate *only works on Linux* (and maybe BSD) but
-//! the structure is such that adding compatibility with other
-//! platforms in future, shouldn't require changes to any client code.
+//! Most applications need both: the current state, and then changes
+//! to it. For that, use [`get_interfaces_async`] on its own -- *not*
+//! [`get_interfaces`] followed by [`get_interfaces_async`]. Its stream
+//! starts by describing everything already present, and it subscribes
+//! to changes *before* taking that initial listing, so nothing can be
+//! missed in between; once the initial listing is over, it generates
+//! [`NetworkEvent::EnumerationComplete`], and everything after that is
+//! a live change. Interface indexes are the kernel's throughout, and
+//! agree with those from [`get_interfaces`].
+//!
+//! Programs without an async runtime can use `watch_interfaces`
+//! (Linux only, for now) instead: a blocking iterator producing the
+//! same events as [`get_interfaces_async`].
+//!
+//! The streams from [`get_interfaces_async`] and `get_routes_async`
+//! need an async runtime to wait on their sockets. By default (the
+//! `tokio` feature) that's Tokio, so they must be used from within a
+//! Tokio runtime; with the `async-io` feature instead, they work with
+//! any executor, such as smol or async-std. The API is the same
+//! either way.
+//!
+//! At present this crate works on Linux, macOS, FreeBSD (and, for
+//! static listing, maybe other BSDs) and Windows, but the structure is
+//! such that adding compatibility with other platforms in future,
+//! shouldn't require changes to any client code. On other Unix-like
+//! platforms, [`get_interfaces_async`] falls back to
+//! [`network_interfaces_poll`], which compares successive static
+//! listings; that's also available everywhere, for use where the
+//! native notifications aren't (such as in a sandbox which forbids
+//! netlink sockets).
+//!
+//! With the `serde` feature, [`NetworkEvent`] and [`RouteEvent`], and
+//! the types they contain, implement `Serialize` and `Deserialize`,
+//! for instance to forward events to another process as JSON. The
+//! names on the wire are the Rust names -- of the enum variants,
+//! struct fields, and flag constants -- and will change only with a
+//! semver-breaking release. [`Flags`] and [`AddressFlags`] are lists
+//! of names (e.g. `["UP","RUNNING"]`) rather than numbers; unknown
+//! names are ignored when deserializing, so that events from a newer
+//! version, with more flags, can still be read.
+//!
+//! With the `wireless` feature, on Linux, [`LinkInfo`] also says, for
+//! Wi-Fi interfaces, which network they've joined and how strong the
+//! signal is (see `wireless_info`), so that an application can prefer
+//! wired paths. That costs a few nl80211 queries per interface, so is
+//! off by default.
 //!
 //! Todo:
 //!  - [x] IPv6 in `linux_netlink`
@@ -34,7 +77,52 @@ extern crate alloc;
 /** Events passed to interface observers
  */
 pub mod network_event;
-pub use network_event::{Flags, InterfaceIndex, NetworkEvent};
+pub use network_event::{
+    AddrInfo, AddressFlags, Flags, InterfaceIndex, LinkInfo, LinkType,
+    NetworkEvent, OperState, Scope,
+};
+
+/** Events describing changes to the routing table
+ */
+pub mod route_event;
+pub use route_event::{Route, RouteEvent};
+
+/** Filtering of dynamic listings, for only some interfaces
+ */
+#[cfg(feature = "async")]
+pub mod network_monitor;
+
+#[cfg(feature = "async")]
+#[doc(inline)]
+pub use network_monitor::NetworkMonitor;
+
+/** De-duplication of dynamic listings, with a cache of the current state
+ */
+#[cfg(feature = "async")]
+pub mod coherent_stream;
+
+#[cfg(feature = "async")]
+#[doc(inline)]
+pub use coherent_stream::CoherentStream;
+
+/** Sockets registered with the configured async runtime
+ */
+#[cfg(all(
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    ),
+    feature = "async"
+))]
+mod async_socket;
+
+/** Parsing of Linux's netlink messages, shared by the async and
+ * blocking listings
+ */
+#[cfg(all(target_os = "linux", any(feature = "sync", feature = "async")))]
+mod linux_messages;
 
 /** Dynamic listing using Linux's netlink socket
  */
@@ -45,15 +133,151 @@ pub mod linux_netlink;
 #[doc(inline)]
 pub use linux_netlink::get_interfaces_async;
 
+/** Dynamic route listing using Linux's netlink socket
+ */
+#[cfg(all(target_os = "linux", feature = "async"))]
+pub mod linux_routes;
+
+#[cfg(all(target_os = "linux", feature = "async"))]
+#[doc(inline)]
+pub use linux_routes::get_routes_async;
+
+/** Blocking dynamic listing using Linux's netlink socket
+ */
+#[cfg(all(target_os = "linux", feature = "sync"))]
+pub mod linux_blocking;
+
+#[cfg(all(target_os = "linux", feature = "sync"))]
+#[doc(inline)]
+pub use linux_blocking::{watch_interfaces, InterfaceWatcher};
+
+/** Dynamic listing using Windows's IP Helper notifications
+ */
+#[cfg(all(windows, feature = "async"))]
+pub mod windows_notify;
+
+#[cfg(all(windows, feature = "async"))]
+#[doc(inline)]
+pub use windows_notify::get_interfaces_async;
+
+/** Dynamic listing using a macOS/FreeBSD PF_ROUTE routing socket
+ */
+#[cfg(all(
+    any(target_os = "macos", target_os = "ios", target_os = "freebsd"),
+    feature = "async"
+))]
+pub mod bsd_route;
+
+#[cfg(all(
+    any(target_os = "macos", target_os = "ios", target_os = "freebsd"),
+    feature = "async"
+))]
+#[doc(inline)]
+pub use bsd_route::get_interfaces_async;
+
 /** Static listing using Linux/glibc's getifaddrs(3)
  */
-#[cfg(all(feature = "sync", not(target_os = "none")))]
+#[cfg(all(any(feature = "sync", feature = "async"), unix))]
 pub mod getifaddrs;
 
-#[cfg(all(feature = "sync", not(target_os = "none")))]
+#[cfg(all(feature = "sync", unix))]
 #[doc(inline)]
 pub use getifaddrs::get_interfaces;
 
+/** Looking up interface names from their indexes, and vice versa
+ */
+#[cfg(all(any(feature = "sync", feature = "async"), any(unix, windows)))]
+pub mod interface_names;
+
+#[cfg(all(any(feature = "sync", feature = "async"), any(unix, windows)))]
+#[doc(inline)]
+pub use interface_names::InterfaceNames;
+
+/** Traffic statistics of network interfaces, using Linux's netlink socket
+ */
+#[cfg(all(target_os = "linux", feature = "sync"))]
+pub mod linux_stats;
+
+#[cfg(all(target_os = "linux", feature = "sync"))]
+#[doc(inline)]
+pub use linux_stats::{link_stats, LinkStats};
+
+#[cfg(all(target_os = "linux", feature = "sync", feature = "async"))]
+#[doc(inline)]
+pub use linux_stats::link_stats_stream;
+
+/** Details of Wi-Fi interfaces, using Linux's nl80211
+ */
+#[cfg(all(target_os = "linux", feature = "wireless"))]
+pub mod linux_wireless;
+
+#[cfg(all(target_os = "linux", feature = "wireless"))]
+#[doc(inline)]
+pub use linux_wireless::wireless_info;
+
+#[cfg(all(target_os = "linux", feature = "wireless"))]
+pub use network_event::WirelessInfo;
+
+/** Multicast group memberships of network interfaces, from Linux's /proc
+ */
+#[cfg(all(target_os = "linux", any(feature = "sync", feature = "async")))]
+pub mod linux_multicast;
+
+#[cfg(all(
+    target_os = "linux",
+    any(feature = "sync", feature = "async")
+))]
+#[doc(inline)]
+pub use linux_multicast::{multicast_groups, MulticastEvent};
+
+#[cfg(all(target_os = "linux", feature = "async"))]
+#[doc(inline)]
+pub use linux_multicast::multicast_groups_stream;
+
+/** Querying the speed of network interfaces
+ */
+#[cfg(all(target_os = "linux", any(feature = "sync", feature = "async")))]
+pub mod link_speed;
+
+#[cfg(all(
+    target_os = "linux",
+    any(feature = "sync", feature = "async")
+))]
+#[doc(inline)]
+pub use link_speed::link_speed;
+
+/** Dynamic listing by polling the static listing, for where there's no
+ * better way
+ */
+#[cfg(all(feature = "async", any(unix, windows)))]
+pub mod polling;
+
+#[cfg(all(feature = "async", any(unix, windows)))]
+#[doc(inline)]
+pub use polling::{network_interfaces_poll, PollInterval};
+
+#[cfg(all(
+    feature = "async",
+    unix,
+    not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    ))
+))]
+#[doc(inline)]
+pub use polling::get_interfaces_async;
+
+/** Static listing using Windows's GetAdaptersAddresses
+ */
+#[cfg(all(any(feature = "sync", feature = "async"), windows))]
+pub mod getadaptersaddresses;
+
+#[cfg(all(feature = "sync", windows))]
+#[doc(inline)]
+pub use getadaptersaddresses::get_interfaces;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,4 +395,262 @@ mod tests {
         let s = format!("{:?}", Flags::MULTICAST);
         assert_eq!(s, "Flags(4096)");
     }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_flags_values() {
+        // These match Linux's IFF_ values, and must not change
+        for (f, s) in [
+            (Flags::NOARP, "Flags(128)"),
+            (Flags::PROMISC, "Flags(256)"),
+            (Flags::LOWER_UP, "Flags(65536)"),
+            (Flags::DORMANT, "Flags(131072)"),
+        ] {
+            assert_eq!(format!("{f:?}"), s);
+        }
+    }
+
+    #[test]
+    fn test_link_type_default() {
+        assert_eq!(LinkInfo::default().link_type, LinkType::Other);
+        assert_eq!(LinkInfo::default().mtu, None);
+        assert_eq!(LinkInfo::default().oper_state, OperState::Unknown);
+    }
+
+    #[test]
+    fn test_link_type_from_arphrd() {
+        assert_eq!(LinkType::from_arphrd(1), LinkType::Ethernet);
+        assert_eq!(LinkType::from_arphrd(772), LinkType::Loopback);
+        assert_eq!(LinkType::from_arphrd(512), LinkType::Ppp);
+        assert_eq!(LinkType::from_arphrd(776), LinkType::Tunnel);
+        assert_eq!(LinkType::from_arphrd(801), LinkType::Ieee80211);
+        assert_eq!(LinkType::from_arphrd(32), LinkType::Other);
+    }
+
+    #[test]
+    fn test_link_type_from_iftype() {
+        assert_eq!(LinkType::from_iftype(6), LinkType::Ethernet);
+        assert_eq!(LinkType::from_iftype(24), LinkType::Loopback);
+        assert_eq!(LinkType::from_iftype(23), LinkType::Ppp);
+        assert_eq!(LinkType::from_iftype(131), LinkType::Tunnel);
+        assert_eq!(LinkType::from_iftype(71), LinkType::Ieee80211);
+        assert_eq!(LinkType::from_iftype(0), LinkType::Other);
+    }
+
+    #[test]
+    fn test_oper_state_from_if_oper_status() {
+        assert_eq!(OperState::from_if_oper_status(1), OperState::Up);
+        assert_eq!(OperState::from_if_oper_status(2), OperState::Down);
+        assert_eq!(OperState::from_if_oper_status(3), OperState::Testing);
+        assert_eq!(OperState::from_if_oper_status(4), OperState::Unknown);
+        assert_eq!(OperState::from_if_oper_status(5), OperState::Dormant);
+        assert_eq!(OperState::from_if_oper_status(6), OperState::NotPresent);
+        assert_eq!(
+            OperState::from_if_oper_status(7),
+            OperState::LowerLayerDown
+        );
+        assert_eq!(OperState::from_if_oper_status(0), OperState::Unknown);
+    }
+
+    #[test]
+    fn test_address_flags() {
+        let f = AddressFlags::TEMPORARY | AddressFlags::DEPRECATED;
+        assert!(f.contains(AddressFlags::TEMPORARY));
+        assert!(!f.contains(AddressFlags::TENTATIVE));
+        assert_eq!(AddressFlags::default(), AddressFlags::empty());
+    }
+
+    #[test]
+    fn test_scope_from_rt_scope() {
+        assert_eq!(Scope::from_rt_scope(0), Some(Scope::Global));
+        assert_eq!(Scope::from_rt_scope(200), Some(Scope::Site));
+        assert_eq!(Scope::from_rt_scope(253), Some(Scope::Link));
+        assert_eq!(Scope::from_rt_scope(254), Some(Scope::Host));
+        assert_eq!(Scope::from_rt_scope(255), None);
+    }
+
+    #[test]
+    fn test_scope_from_address() {
+        for (ip, scope) in [
+            ("127.0.0.1", Scope::Host),
+            ("169.254.1.2", Scope::Link),
+            ("192.168.1.2", Scope::Global),
+            ("::1", Scope::Host),
+            ("fe80::1", Scope::Link),
+            ("febf::1", Scope::Link),
+            ("fec0::1", Scope::Site),
+            ("2001:db8::1", Scope::Global),
+        ] {
+            assert_eq!(Scope::from_address(&ip.parse().unwrap()), scope);
+        }
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    fn round_trip<T>(t: &T) -> String
+    where
+        T: serde::Serialize
+            + serde::de::DeserializeOwned
+            + PartialEq
+            + core::fmt::Debug,
+    {
+        let json = serde_json::to_string(t).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), t);
+        json
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn test_serde_link() {
+        let e = NetworkEvent::NewLink(
+            make_index(2),
+            "eth0".to_string(),
+            Flags::UP | Flags::RUNNING | Flags::LOWER_UP,
+            LinkInfo {
+                mtu: Some(1500),
+                link_type: LinkType::Ethernet,
+                oper_state: OperState::Up,
+                parent: None,
+                master: Some(make_index(5)),
+                kind: Some("veth".to_string()),
+                #[cfg(all(target_os = "linux", feature = "wireless"))]
+                wireless: None,
+            },
+        );
+        #[cfg(not(all(target_os = "linux", feature = "wireless")))]
+        assert_eq!(
+            round_trip(&e),
+            r#"{"NewLink":[2,"eth0",["UP","RUNNING","LOWER_UP"],{"mtu":1500,"link_type":"Ethernet","oper_state":"Up","parent":null,"master":5,"kind":"veth"}]}"#
+        );
+        #[cfg(all(target_os = "linux", feature = "wireless"))]
+        assert_eq!(
+            round_trip(&e),
+            r#"{"NewLink":[2,"eth0",["UP","RUNNING","LOWER_UP"],{"mtu":1500,"link_type":"Ethernet","oper_state":"Up","parent":null,"master":5,"kind":"veth","wireless":null}]}"#
+        );
+
+        // Serialised before oper_state existed
+        let old: NetworkEvent = serde_json::from_str(
+            r#"{"NewLink":[2,"eth0",[],{"mtu":1500,"link_type":"Ethernet"}]}"#,
+        )
+        .unwrap();
+        let NetworkEvent::NewLink(_, _, _, info) = old else {
+            panic!("unexpected {old:?}");
+        };
+        assert_eq!(info.oper_state, OperState::Unknown);
+        assert_eq!(info.kind, None);
+        assert_eq!(
+            round_trip(&NetworkEvent::LinkChanged(
+                make_index(2),
+                Flags::empty()
+            )),
+            r#"{"LinkChanged":[2,[]]}"#
+        );
+        assert_eq!(
+            round_trip(&NetworkEvent::DelLink(make_index(2))),
+            r#"{"DelLink":2}"#
+        );
+    }
+
+    #[cfg(all(feature = "serde", target_os = "linux", feature = "wireless"))]
+    #[test]
+    fn test_serde_wireless() {
+        let info = LinkInfo {
+            wireless: Some(WirelessInfo {
+                ssid: Some("cotton".to_string()),
+                signal_dbm: Some(-52),
+            }),
+            ..LinkInfo::default()
+        };
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(json
+            .ends_with(r#""wireless":{"ssid":"cotton","signal_dbm":-52}}"#));
+        assert_eq!(serde_json::from_str::<LinkInfo>(&json).unwrap(), info);
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn test_serde_addr() {
+        let e = NetworkEvent::NewAddr(
+            make_index(3),
+            "2001:db8::1".parse().unwrap(),
+            64,
+            AddrInfo {
+                flags: AddressFlags::TEMPORARY | AddressFlags::TENTATIVE,
+                ..AddrInfo::default()
+            },
+        );
+        assert_eq!(
+            round_trip(&e),
+            r#"{"NewAddr":[3,"2001:db8::1",64,{"broadcast":null,"peer":null,"flags":["TEMPORARY","TENTATIVE"],"scope":"Global"}]}"#
+        );
+        assert_eq!(
+            round_trip(&NetworkEvent::DelAddr(
+                make_index(3),
+                "192.168.1.2".parse().unwrap(),
+                24
+            )),
+            r#"{"DelAddr":[3,"192.168.1.2",24]}"#
+        );
+        assert_eq!(
+            round_trip(&NetworkEvent::EnumerationComplete),
+            r#""EnumerationComplete""#
+        );
+        assert_eq!(round_trip(&NetworkEvent::Overflow), r#""Overflow""#);
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn test_serde_every_flag() {
+        let all = Flags::UP
+            | Flags::BROADCAST
+            | Flags::LOOPBACK
+            | Flags::POINTTOPOINT
+            | Flags::RUNNING
+            | Flags::NOARP
+            | Flags::PROMISC
+            | Flags::MULTICAST
+            | Flags::LOWER_UP
+            | Flags::DORMANT;
+        assert_eq!(
+            round_trip(&all),
+            r#"["UP","BROADCAST","LOOPBACK","POINTTOPOINT","RUNNING","NOARP","PROMISC","MULTICAST","LOWER_UP","DORMANT"]"#
+        );
+        let all = AddressFlags::TEMPORARY
+            | AddressFlags::DAD_FAILED
+            | AddressFlags::DEPRECATED
+            | AddressFlags::TENTATIVE;
+        assert_eq!(
+            round_trip(&all),
+            r#"["TEMPORARY","DAD_FAILED","DEPRECATED","TENTATIVE"]"#
+        );
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn test_serde_unknown_flag_ignored() {
+        let f: Flags = serde_json::from_str(r#"["UP","WIBBLE"]"#).unwrap();
+        assert_eq!(f, Flags::UP);
+        assert!(serde_json::from_str::<Flags>("3").is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn test_serde_zero_index_rejected() {
+        assert!(serde_json::from_str::<InterfaceIndex>("0").is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn test_serde_route() {
+        let e = RouteEvent::NewRoute(Route {
+            destination: "0.0.0.0".parse().unwrap(),
+            prefix_len: 0,
+            gateway: Some("192.168.1.1".parse().unwrap()),
+            interface: Some(make_index(2)),
+            metric: 100,
+        });
+        assert_eq!(
+            round_trip(&e),
+            r#"{"NewRoute":{"destination":"0.0.0.0","prefix_len":0,"gateway":"192.168.1.1","interface":2,"metric":100}}"#
+        );
+    }
 }
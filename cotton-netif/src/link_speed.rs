@@ -0,0 +1,106 @@
+use crate::interface_names::indextoname;
+use crate::network_event::InterfaceIndex;
+
+/// The type of the function reading an interface's speed file
+type ReadSpeedFn = fn(&str) -> std::io::Result<String>;
+
+/** The negotiated speed of a network interface, in Mb/s
+
+This lets an application prefer, say, a gigabit Ethernet interface
+over a 100Mb/s USB adapter, which [`Flags`](crate::Flags) alone can't
+tell apart. It's read from `/sys/class/net/<name>/speed`, looking the
+name up from the index with `if_indextoname`.
+
+Returns None if the speed isn't known: for instance, if the interface
+is down, is virtual (loopback, bridges, tunnels), is a Wi-Fi interface
+(whose speed varies from moment to moment), or has gone away.
+
+```rust
+# use cotton_netif::*;
+for e in get_interfaces()? {
+    if let NetworkEvent::NewLink(ix, name, _, _) = e {
+        println!("{}: {:?} Mb/s", name, link_speed(ix));
+    }
+}
+# Ok::<(), std::io::Error>(())
+```
+ */
+#[must_use]
+pub fn link_speed(ix: InterfaceIndex) -> Option<u32> {
+    link_speed_inner(ix, indextoname, read_speed)
+}
+
+fn read_speed(name: &str) -> std::io::Result<String> {
+    std::fs::read_to_string(format!("/sys/class/net/{name}/speed"))
+}
+
+fn link_speed_inner(
+    ix: InterfaceIndex,
+    indextoname: fn(u32) -> Option<String>,
+    read_speed: ReadSpeedFn,
+) -> Option<u32> {
+    let name = indextoname(ix.0.get())?;
+    // An interface whose speed isn't known reads as -1 (SPEED_UNKNOWN),
+    // or on some older kernels as that value seen as unsigned; reading
+    // a down interface fails with EINVAL
+    let speed = read_speed(&name).ok()?.trim().parse::<i64>().ok()?;
+    u32::try_from(speed)
+        .ok()
+        .filter(|speed| *speed != 0 && *speed != u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn eth0(index: u32) -> Option<String> {
+        (index == 2).then(|| "eth0".to_string())
+    }
+
+    #[test]
+    fn gigabit() {
+        fn read(name: &str) -> std::io::Result<String> {
+            assert_eq!(name, "eth0");
+            Ok("1000\n".to_string())
+        }
+        assert_eq!(link_speed_inner(make_index(2), eth0, read), Some(1000));
+    }
+
+    #[test]
+    fn unknown_index() {
+        fn read(_: &str) -> std::io::Result<String> {
+            panic!("should not be read");
+        }
+        assert_eq!(link_speed_inner(make_index(3), eth0, read), None);
+    }
+
+    #[test]
+    fn unknown_speed() {
+        fn minus_one(_: &str) -> std::io::Result<String> {
+            Ok("-1\n".to_string())
+        }
+        fn unsigned(_: &str) -> std::io::Result<String> {
+            Ok("4294967295\n".to_string())
+        }
+        fn rubbish(_: &str) -> std::io::Result<String> {
+            Ok("fast\n".to_string())
+        }
+        fn einval(_: &str) -> std::io::Result<String> {
+            Err(std::io::Error::from_raw_os_error(libc::EINVAL))
+        }
+        for read in [minus_one, unsigned, rubbish, einval] {
+            assert_eq!(link_speed_inner(make_index(2), eth0, read), None);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_loopback_has_no_speed() {
+        let lo = nix::net::if_::if_nametoindex("lo").unwrap();
+        assert_eq!(link_speed(make_index(lo)), None);
+    }
+}
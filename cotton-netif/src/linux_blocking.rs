@@ -0,0 +1,606 @@
+use crate::linux_messages::{
+    add_wireless, addr_dump_request, enlarge_receive_buffer,
+    link_dump_request, map_rx_error, map_tx_error, translate_addr_message,
+    translate_error_message, translate_link_message, HEADER_BYTES,
+};
+use crate::network_event::{KnownLinks, NetworkEvent};
+use neli::{
+    consts::{nl::Nlmsg, rtnl::RtAddrFamily, rtnl::Rtm, socket::NlFamily},
+    nl::Nlmsghdr,
+    rtnl::{Ifaddrmsg, Ifinfomsg},
+    socket::NlSocket,
+    FromBytes, ToBytes,
+};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+/// The type of `NlSocket::connect`
+type ConnectFn = fn(NlFamily, Option<u32>, &[u32]) -> Result<NlSocket, Error>;
+
+/// Big enough for any datagram the kernel sends, even in a dump
+const DATAGRAM_BYTES: usize = 64 * 1024;
+
+/// Which dump the watcher is waiting for the end of
+#[derive(Debug, PartialEq, Eq)]
+enum Dump {
+    Links,
+    Ipv4Addrs,
+    Ipv6Addrs,
+    Complete,
+}
+
+/** A blocking iterator over interface and address events
+
+Obtained from [`watch_interfaces`], which see.
+ */
+pub struct InterfaceWatcher {
+    socket: NlSocket,
+    buffer: Vec<u8>,
+    events: VecDeque<Result<NetworkEvent, Error>>,
+    known: KnownLinks,
+    dump: Dump,
+    restart: bool,
+}
+
+/** Obtain the current list of network interfaces and a blocking iterator of future changes
+
+This is the counterpart of `get_interfaces_async` for programs which
+don't use async, such as a small daemon using plain threads: the
+iterator produces the same sequence of [`NetworkEvent`] objects as
+that stream, but each call to `next` blocks until an event is
+available. It never finishes.
+
+```rust
+# use cotton_netif::*;
+# #[cfg(not(miri))]
+for e in watch_interfaces()? {
+    println!("{:?}", e?);
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+```
+
+A single netlink socket is used, so the interfaces, IPv4 addresses,
+and IPv6 addresses are listed one after the other, followed by
+[`NetworkEvent::EnumerationComplete`]. As with the stream, changes
+are subscribed to before the listing starts, so nothing is missed.
+
+To wake up periodically even if nothing has changed (for instance, to
+check whether to exit), see [`InterfaceWatcher::set_read_timeout`].
+
+# Errors
+
+Returns Err if the underlying netlink socket failed to open, see
+netlink(7).
+
+ */
+pub fn watch_interfaces() -> Result<InterfaceWatcher, Error> {
+    watch_interfaces_inner(NlSocket::connect)
+}
+
+fn watch_interfaces_inner(
+    connect_fn: ConnectFn,
+) -> Result<InterfaceWatcher, Error> {
+    // =RTNLGRP_LINK, RTNLGRP_IPV4_IFADDR, RTNLGRP_IPV6_IFADDR
+    InterfaceWatcher::new(connect_fn(NlFamily::Route, None, &[1, 5, 9])?)
+}
+
+impl InterfaceWatcher {
+    fn new(socket: NlSocket) -> Result<Self, Error> {
+        enlarge_receive_buffer(&socket);
+        let watcher = Self {
+            socket,
+            buffer: vec![0; DATAGRAM_BYTES],
+            events: VecDeque::new(),
+            known: KnownLinks::default(),
+            dump: Dump::Links,
+            restart: false,
+        };
+        watcher.send(link_dump_request())?;
+        Ok(watcher)
+    }
+
+    /** Set how long `next` waits for an event before giving up
+
+    If the timeout expires, the iterator produces an Err whose kind
+    is [`ErrorKind::TimedOut`]; this is not fatal, and the next call
+    to `next` carries on waiting. A timeout of None (the default)
+    waits for ever.
+
+    # Errors
+
+    Returns Err if `timeout` is `Some(Duration::ZERO)`, or if the
+    underlying setsockopt(2) fails.
+     */
+    pub fn set_read_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        let timeval = match timeout {
+            Some(Duration::ZERO) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "cannot set a zero timeout",
+                ))
+            }
+            Some(d) => nix::sys::time::TimeVal::new(
+                d.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+                // At least 1us, so that a tiny timeout isn't "for ever"
+                d.subsec_micros().max(1).into(),
+            ),
+            None => nix::sys::time::TimeVal::new(0, 0),
+        };
+        // SAFETY: the socket outlives this borrow of its fd
+        let fd = unsafe {
+            std::os::fd::BorrowedFd::borrow_raw(self.socket.as_raw_fd())
+        };
+        nix::sys::socket::setsockopt(
+            &fd,
+            nix::sys::socket::sockopt::ReceiveTimeout,
+            &timeval,
+        )?;
+        Ok(())
+    }
+
+    fn send<P: ToBytes + core::fmt::Debug>(
+        &self,
+        msg: Nlmsghdr<Rtm, P>,
+    ) -> Result<(), Error> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut bytes).map_err(map_tx_error)?;
+        self.socket.send(bytes.get_ref(), 0)?;
+        Ok(())
+    }
+
+    /// Wait for a datagram, and queue up the events it contains
+    fn receive(&mut self) {
+        let mut buffer = core::mem::take(&mut self.buffer);
+        match self.socket.recv(&mut buffer[..], 0) {
+            Ok(n) => self.parse(&buffer[..n]),
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                self.overflow();
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                self.events.push_back(Err(ErrorKind::TimedOut.into()));
+            }
+            Err(e) => self.events.push_back(Err(e)),
+        }
+        self.buffer = buffer;
+    }
+
+    /// Split a datagram into its messages
+    fn parse(&mut self, mut datagram: &[u8]) {
+        while !datagram.is_empty() {
+            let len = match datagram.get(0..4) {
+                Some(len) => u32::from_ne_bytes(len.try_into().unwrap()),
+                None => 0,
+            } as usize;
+            if len < HEADER_BYTES || len > datagram.len() {
+                self.events.push_back(Err(ErrorKind::InvalidData.into()));
+                return;
+            }
+            let (msg, rest) = datagram.split_at(len);
+            self.translate(msg);
+            // Each message starts on a 4-byte boundary
+            datagram =
+                rest.get(len.next_multiple_of(4) - len..).unwrap_or(&[]);
+        }
+    }
+
+    fn translate(&mut self, msg: &[u8]) {
+        let nl_type = u16::from_ne_bytes([msg[4], msg[5]]);
+        let event = match Rtm::from(nl_type) {
+            Rtm::Newlink | Rtm::Dellink => {
+                match Nlmsghdr::<Rtm, Ifinfomsg>::from_bytes(
+                    &mut std::io::Cursor::new(msg),
+                ) {
+                    Ok(msg) => translate_link_message(&msg)
+                        .and_then(|e| self.known.filter(e))
+                        .map(add_wireless)
+                        .map(Ok),
+                    Err(e) => Some(Err(map_rx_error(e))),
+                }
+            }
+            Rtm::Newaddr | Rtm::Deladdr => {
+                match Nlmsghdr::<Rtm, Ifaddrmsg>::from_bytes(
+                    &mut std::io::Cursor::new(msg),
+                ) {
+                    Ok(msg) => translate_addr_message(&msg).map(Ok),
+                    Err(e) => Some(Err(map_rx_error(e))),
+                }
+            }
+            _ if nl_type == u16::from(Nlmsg::Done) => self.dump_done(),
+            // An ack isn't asked for, but is harmless
+            _ if nl_type == u16::from(Nlmsg::Error) => {
+                translate_error_message(msg).map(Err)
+            }
+            _ => None,
+        };
+        if let Some(event) = event {
+            self.events.push_back(event);
+        }
+    }
+
+    /// A dump has finished, so start the next one
+    ///
+    /// A socket can only do one dump at a time, so the three are done
+    /// in turn.
+    fn dump_done(&mut self) -> Option<Result<NetworkEvent, Error>> {
+        let next = if self.restart {
+            self.restart = false;
+            self.send(link_dump_request()).map(|()| Dump::Links)
+        } else {
+            match self.dump {
+                Dump::Links => self
+                    .send(addr_dump_request(RtAddrFamily::Inet))
+                    .map(|()| Dump::Ipv4Addrs),
+                Dump::Ipv4Addrs => self
+                    .send(addr_dump_request(RtAddrFamily::Inet6))
+                    .map(|()| Dump::Ipv6Addrs),
+                Dump::Ipv6Addrs => {
+                    self.dump = Dump::Complete;
+                    return Some(Ok(NetworkEvent::EnumerationComplete));
+                }
+                // A stray one is ignored
+                Dump::Complete => return None,
+            }
+        };
+        match next {
+            Ok(dump) => {
+                self.dump = dump;
+                None
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// The kernel dropped messages, so announce everything afresh
+    ///
+    /// If a dump is in progress, it's allowed to finish first (the
+    /// kernel would refuse to start another one).
+    fn overflow(&mut self) {
+        self.events.push_back(Ok(NetworkEvent::Overflow));
+        self.known = KnownLinks::default();
+        if self.dump == Dump::Complete {
+            match self.send(link_dump_request()) {
+                Ok(()) => self.dump = Dump::Links,
+                Err(e) => self.events.push_back(Err(e)),
+            }
+        } else {
+            self.restart = true;
+        }
+    }
+}
+
+impl Iterator for InterfaceWatcher {
+    type Item = Result<NetworkEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            self.receive();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_messages::tests::{parse_addr_message, IPV6_NEWADDR};
+    use crate::network_event::{Flags, InterfaceIndex, LinkInfo, LinkType};
+    use neli::consts::nl::{NlmF, NlmFFlags};
+    use neli::consts::rtnl::{Arphrd, Iff, IffFlags, Ifla};
+    use neli::nl::NlPayload;
+    use neli::rtnl::Rtattr;
+    use neli::types::RtBuffer;
+    use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn fake_socket() -> (OwnedFd, NlSocket) {
+        let (infd, outfd) = nix::sys::socket::socketpair(
+            nix::sys::socket::AddressFamily::Unix,
+            nix::sys::socket::SockType::Datagram,
+            None,
+            nix::sys::socket::SockFlag::empty(),
+        )
+        .unwrap();
+
+        let socket = unsafe {
+            // SAFETY: socket becomes only owner of outfd
+            NlSocket::from_raw_fd(outfd.into_raw_fd())
+        };
+        (infd, socket)
+    }
+
+    fn send_bytes(fd: &impl AsRawFd, bytes: &[u8]) {
+        nix::sys::socket::sendto(
+            fd.as_raw_fd(),
+            bytes,
+            &(),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+    }
+
+    fn to_bytes<P: ToBytes + core::fmt::Debug>(
+        msg: &Nlmsghdr<Rtm, P>,
+    ) -> Vec<u8> {
+        let mut v = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut v).unwrap();
+        v.into_inner()
+    }
+
+    fn link_message(index: i32, name: &str) -> Vec<u8> {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, name.to_string()).unwrap());
+        to_bytes(&Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                index,
+                IffFlags::new(&[Iff::Up]),
+                IffFlags::empty(),
+                buf,
+            )),
+        ))
+    }
+
+    fn dump_done() -> Vec<u8> {
+        to_bytes(&Nlmsghdr::<Rtm, Ifinfomsg>::new(
+            None,
+            Rtm::from(u16::from(Nlmsg::Done)),
+            NlmFFlags::new(&[NlmF::Multi]),
+            None,
+            None,
+            NlPayload::Empty,
+        ))
+    }
+
+    fn new_link(index: u32, name: &str) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(index),
+            name.to_string(),
+            Flags::UP,
+            LinkInfo {
+                mtu: None,
+                link_type: LinkType::Ethernet,
+                ..LinkInfo::default()
+            },
+        )
+    }
+
+    /// Read the next request the watcher sent
+    fn expect_request(peer: &impl AsRawFd) -> Vec<u8> {
+        let mut buf = [0u8; 1024];
+        let n = nix::sys::socket::recv(
+            peer.as_raw_fd(),
+            &mut buf,
+            nix::sys::socket::MsgFlags::MSG_DONTWAIT,
+        )
+        .expect("no request sent");
+        buf[..n].to_vec()
+    }
+
+    fn expect_link_request(peer: &impl AsRawFd) {
+        let request = expect_request(peer);
+        let request = Nlmsghdr::<Rtm, Ifinfomsg>::from_bytes(
+            &mut std::io::Cursor::new(&request[..]),
+        )
+        .unwrap();
+        assert_eq!(request.nl_type, Rtm::Getlink);
+    }
+
+    fn expect_addr_request(peer: &impl AsRawFd, family: RtAddrFamily) {
+        let request = parse_addr_message(&expect_request(peer));
+        assert_eq!(request.nl_type, Rtm::Getaddr);
+        assert_eq!(
+            request.nl_payload.get_payload().unwrap().ifa_family,
+            family
+        );
+    }
+
+    fn expect_no_request(peer: &impl AsRawFd) {
+        let mut buf = [0u8; 1024];
+        assert!(nix::sys::socket::recv(
+            peer.as_raw_fd(),
+            &mut buf,
+            nix::sys::socket::MsgFlags::MSG_DONTWAIT,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn connect_error_passed_on() {
+        fn failing_connect(
+            _: NlFamily,
+            _: Option<u32>,
+            _: &[u32],
+        ) -> Result<NlSocket, Error> {
+            Err(Error::from(ErrorKind::PermissionDenied))
+        }
+        let e = watch_interfaces_inner(failing_connect).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn dumps_in_turn() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        expect_link_request(&peer);
+
+        send_bytes(&peer, &link_message(2, "eth0"));
+        send_bytes(&peer, &dump_done());
+        send_bytes(&peer, &dump_done());
+        send_bytes(&peer, &IPV6_NEWADDR);
+        send_bytes(&peer, &dump_done());
+
+        assert_eq!(w.next().unwrap().unwrap(), new_link(2, "eth0"));
+        assert!(matches!(
+            w.next().unwrap().unwrap(),
+            NetworkEvent::NewAddr(ix, addr, 64, _)
+                if ix == make_index(2) && addr.is_ipv6()
+        ));
+        assert_eq!(
+            w.next().unwrap().unwrap(),
+            NetworkEvent::EnumerationComplete
+        );
+        expect_addr_request(&peer, RtAddrFamily::Inet);
+        expect_addr_request(&peer, RtAddrFamily::Inet6);
+        expect_no_request(&peer);
+
+        // A stray NLMSG_DONE is ignored
+        send_bytes(&peer, &dump_done());
+        send_bytes(&peer, &link_message(3, "eth1"));
+        assert_eq!(w.next().unwrap().unwrap(), new_link(3, "eth1"));
+        expect_no_request(&peer);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn several_messages_in_one_datagram() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        let mut datagram = link_message(2, "eth0");
+        datagram.extend(link_message(3, "eth1"));
+        send_bytes(&peer, &datagram);
+        assert_eq!(w.next().unwrap().unwrap(), new_link(2, "eth0"));
+        assert_eq!(w.next().unwrap().unwrap(), new_link(3, "eth1"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn repeated_link_not_passed_on() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        send_bytes(&peer, &link_message(2, "eth0"));
+        send_bytes(&peer, &link_message(2, "eth0"));
+        send_bytes(&peer, &link_message(3, "eth1"));
+        assert_eq!(w.next().unwrap().unwrap(), new_link(2, "eth0"));
+        assert_eq!(w.next().unwrap().unwrap(), new_link(3, "eth1"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn bad_message() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        let mut bytes = link_message(2, "eth0");
+        bytes[0] += 4; // longer than the datagram
+        send_bytes(&peer, &bytes);
+        assert_eq!(
+            w.next().unwrap().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn error_reply() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        let mut bytes = dump_done();
+        bytes[4] = 2; // NLMSG_ERROR
+        bytes.truncate(HEADER_BYTES);
+        bytes.extend((-libc::EBUSY).to_ne_bytes());
+        bytes[0] = bytes.len() as u8;
+        send_bytes(&peer, &bytes);
+        assert_eq!(
+            w.next().unwrap().unwrap_err().raw_os_error(),
+            Some(libc::EBUSY)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn timeout() {
+        let (_peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        w.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(w.next().unwrap().unwrap_err().kind(), ErrorKind::TimedOut);
+        // ...and again
+        assert_eq!(w.next().unwrap().unwrap_err().kind(), ErrorKind::TimedOut);
+        w.set_read_timeout(None).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zero_timeout_rejected() {
+        let (_peer, socket) = fake_socket();
+        let w = InterfaceWatcher::new(socket).unwrap();
+        assert_eq!(
+            w.set_read_timeout(Some(Duration::ZERO)).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn overflow_when_idle_redumps() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        expect_link_request(&peer);
+        w.dump = Dump::Complete;
+        w.overflow();
+        assert_eq!(
+            w.events.pop_front().unwrap().unwrap(),
+            NetworkEvent::Overflow
+        );
+        assert_eq!(w.dump, Dump::Links);
+        expect_link_request(&peer);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn overflow_during_dump_waits() {
+        let (peer, socket) = fake_socket();
+        let mut w = InterfaceWatcher::new(socket).unwrap();
+        expect_link_request(&peer);
+        send_bytes(&peer, &link_message(2, "eth0"));
+        assert_eq!(w.next().unwrap().unwrap(), new_link(2, "eth0"));
+
+        w.dump = Dump::Ipv4Addrs;
+        w.overflow();
+        expect_no_request(&peer);
+        assert_eq!(w.next().unwrap().unwrap(), NetworkEvent::Overflow);
+
+        // When the current dump finishes, the links are dumped again...
+        send_bytes(&peer, &dump_done());
+        send_bytes(&peer, &link_message(2, "eth0"));
+        assert_eq!(w.next().unwrap().unwrap(), new_link(2, "eth0"));
+        assert_eq!(w.dump, Dump::Links);
+        expect_link_request(&peer);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_enumeration_complete() {
+        let w = watch_interfaces().unwrap();
+        w.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut loopback = false;
+        for e in w {
+            match e.unwrap() {
+                NetworkEvent::NewLink(_, _, flags, _)
+                    if flags.contains(Flags::LOOPBACK) =>
+                {
+                    loopback = true;
+                }
+                NetworkEvent::EnumerationComplete => break,
+                _ => {}
+            }
+        }
+        assert!(loopback);
+    }
+}
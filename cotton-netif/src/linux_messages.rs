@@ -0,0 +1,1362 @@
+use crate::network_event::{
+    unalias, AddrInfo, AddressFlags, Flags, InterfaceIndex, LinkInfo,
+    LinkType, NetworkEvent, OperState, Scope,
+};
+use neli::{
+    consts::{
+        nl::{NlmF, NlmFFlags},
+        rtnl::{
+            Arphrd, Ifa, IfaF, IfaFFlags, Iff, IffFlags, Ifla, IflaInfo,
+            RtAddrFamily, Rtm,
+        },
+    },
+    err::DeError,
+    err::SerError,
+    err::WrappedError,
+    nl::{NlPayload, Nlmsghdr},
+    rtnl::Ifaddrmsg,
+    rtnl::Ifinfomsg,
+    types::RtBuffer,
+};
+use std::{
+    io::Error,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::AsRawFd,
+};
+
+pub(crate) fn ip(ip_bytes: &[u8]) -> Option<IpAddr> {
+    match ip_bytes.len() {
+        4 => Some(IpAddr::from(Ipv4Addr::from(u32::from_be_bytes(
+            ip_bytes.try_into().unwrap(),
+        )))),
+
+        16 => Some(IpAddr::from(Ipv6Addr::from(u128::from_be_bytes(
+            ip_bytes.try_into().unwrap(),
+        )))),
+
+        _ => {
+            println!(
+                "Unrecognized address length of {} found",
+                ip_bytes.len()
+            );
+            None
+        }
+    }
+}
+
+pub(crate) fn map_rx_error(err: DeError) -> Error {
+    if let DeError::Wrapped(WrappedError::IOError(io_error)) = err {
+        io_error
+    } else {
+        Error::from(ErrorKind::Other)
+    }
+}
+
+pub(crate) fn map_tx_error(err: SerError) -> Error {
+    if let SerError::Wrapped(WrappedError::IOError(io_error)) = err {
+        io_error
+    } else {
+        Error::from(ErrorKind::Other)
+    }
+}
+
+fn map_flags(flags: &IffFlags) -> Flags {
+    let mut newflags = Flags::default();
+    for (iff, newf) in [
+        (&Iff::Up, Flags::UP),
+        (&Iff::Running, Flags::RUNNING),
+        (&Iff::Loopback, Flags::LOOPBACK),
+        (&Iff::Pointopoint, Flags::POINTTOPOINT),
+        (&Iff::Broadcast, Flags::BROADCAST),
+        (&Iff::Multicast, Flags::MULTICAST),
+        (&Iff::Noarp, Flags::NOARP),
+        (&Iff::Promisc, Flags::PROMISC),
+        (&Iff::LowerUp, Flags::LOWER_UP),
+        (&Iff::Dormant, Flags::DORMANT),
+    ] {
+        if flags.contains(iff) {
+            newflags |= newf;
+        }
+    }
+    newflags
+}
+
+/// Map the address flags, from `ifa_flags` and (as newer kernels send,
+/// as it has room for more) IFA_FLAGS
+fn map_addr_flags(flags: &IfaFFlags, extended: Option<u32>) -> AddressFlags {
+    let mut newflags = AddressFlags::default();
+    for (ifaf, bit, newf) in [
+        (
+            &IfaF::Temporary,
+            libc::IFA_F_TEMPORARY,
+            AddressFlags::TEMPORARY,
+        ),
+        (
+            &IfaF::Dadfailed,
+            libc::IFA_F_DADFAILED,
+            AddressFlags::DAD_FAILED,
+        ),
+        (
+            &IfaF::Deprecated,
+            libc::IFA_F_DEPRECATED,
+            AddressFlags::DEPRECATED,
+        ),
+        (
+            &IfaF::Tentative,
+            libc::IFA_F_TENTATIVE,
+            AddressFlags::TENTATIVE,
+        ),
+    ] {
+        if flags.contains(ifaf) || extended.is_some_and(|x| x & bit != 0) {
+            newflags |= newf;
+        }
+    }
+    newflags
+}
+
+#[allow(clippy::cast_sign_loss)]
+pub(crate) fn translate_link_message(
+    msg: &Nlmsghdr<Rtm, Ifinfomsg>,
+) -> Option<NetworkEvent> {
+    if let NlPayload::Payload(p) = &msg.nl_payload {
+        match msg.nl_type {
+            Rtm::Newlink => {
+                let handle = p.rtattrs.get_attr_handle();
+                // Aliases can't really appear here (the kernel doesn't
+                // allow ':' in link names), but if they did, they'd be
+                // folded as `get_interfaces` does
+                let name = handle
+                    .get_attr_payload_as_with_len::<String>(Ifla::Ifname)
+                    .ok()
+                    .map(|name| unalias(&name).to_string());
+                // An index in another namespace (e.g. the far end of a
+                // veth pair) would mean something else here
+                let local_index = |attr| {
+                    handle
+                        .get_attr_payload_as::<u32>(attr)
+                        .ok()
+                        .and_then(core::num::NonZeroU32::new)
+                        .map(InterfaceIndex)
+                        .filter(|_| {
+                            handle.get_attribute(Ifla::LinkNetnsid).is_none()
+                        })
+                };
+                if let Some(name) = name {
+                    let newflags = map_flags(&p.ifi_flags);
+                    let info = LinkInfo {
+                        mtu: handle.get_attr_payload_as::<u32>(Ifla::Mtu).ok(),
+                        link_type: LinkType::from_arphrd(u16::from(
+                            p.ifi_type,
+                        )),
+                        oper_state: handle
+                            .get_attr_payload_as::<u8>(Ifla::Operstate)
+                            .map_or(OperState::Unknown, OperState::from_linux),
+                        parent: local_index(Ifla::Link)
+                            .filter(|ix| ix.0.get() != p.ifi_index as u32),
+                        master: local_index(Ifla::Master),
+                        kind: handle
+                            .get_attribute(Ifla::Linkinfo)
+                            .and_then(|a| a.get_attr_handle::<IflaInfo>().ok())
+                            .and_then(|h| {
+                                h.get_attr_payload_as_with_len::<String>(
+                                    IflaInfo::Kind,
+                                )
+                                .ok()
+                            }),
+                        #[cfg(feature = "wireless")]
+                        wireless: None,
+                    };
+                    return core::num::NonZeroU32::new(p.ifi_index as u32)
+                        .map(|ix| {
+                            NetworkEvent::NewLink(
+                                InterfaceIndex(ix),
+                                name,
+                                newflags,
+                                info,
+                            )
+                        });
+                }
+            }
+            Rtm::Dellink => {
+                return core::num::NonZeroU32::new(p.ifi_index as u32)
+                    .map(|ix| NetworkEvent::DelLink(InterfaceIndex(ix)));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[allow(clippy::cast_sign_loss)]
+pub(crate) fn translate_addr_message(
+    msg: &Nlmsghdr<Rtm, Ifaddrmsg>,
+) -> Option<NetworkEvent> {
+    if let NlPayload::Payload(p) = &msg.nl_payload {
+        let handle = p.rtattrs.get_attr_handle();
+        /* On point-to-point links, IFA_ADDRESS is the *peer's* address
+         * and IFA_LOCAL our own; otherwise IFA_LOCAL is either the same
+         * as IFA_ADDRESS or (as usually for IPv6) absent.
+         */
+        let attr = |ifa| {
+            handle
+                .get_attr_payload_as_with_len::<&[u8]>(ifa)
+                .ok()
+                .and_then(ip)
+        };
+        let local = attr(Ifa::Local);
+        let address = attr(Ifa::Address);
+        if let Some(addr) = local.or(address) {
+            match msg.nl_type {
+                Rtm::Newaddr => {
+                    let info = AddrInfo {
+                        broadcast: attr(Ifa::Broadcast),
+                        peer: local.and(address).filter(|a| *a != addr),
+                        flags: map_addr_flags(
+                            &p.ifa_flags,
+                            handle.get_attr_payload_as::<u32>(Ifa::Flags).ok(),
+                        ),
+                        scope: Scope::from_rt_scope(p.ifa_scope)
+                            .unwrap_or_else(|| Scope::from_address(&addr)),
+                    };
+                    return core::num::NonZeroU32::new(p.ifa_index as u32)
+                        .map(|ix| {
+                            NetworkEvent::NewAddr(
+                                InterfaceIndex(ix),
+                                addr,
+                                p.ifa_prefixlen,
+                                info,
+                            )
+                        });
+                }
+                Rtm::Deladdr => {
+                    return core::num::NonZeroU32::new(p.ifa_index as u32)
+                        .map(|ix| {
+                            NetworkEvent::DelAddr(
+                                InterfaceIndex(ix),
+                                addr,
+                                p.ifa_prefixlen,
+                            )
+                        });
+                }
+                _ => (),
+            }
+        }
+    }
+    None
+}
+
+/// Fill in the Wi-Fi details of a newly-seen link, with the
+/// `wireless` feature
+#[cfg(feature = "wireless")]
+pub(crate) use crate::linux_wireless::add_wireless;
+
+/// Fill in the Wi-Fi details of a newly-seen link, with the
+/// `wireless` feature (so, here, not)
+#[cfg(not(feature = "wireless"))]
+pub(crate) fn add_wireless(event: NetworkEvent) -> NetworkEvent {
+    event
+}
+
+/// How big a receive buffer to ask for, so that bursts of changes
+/// (e.g. a VPN reconnecting) don't overflow it; the kernel may grant
+/// less (see `net.core.rmem_max`)
+const RECEIVE_BUFFER_BYTES: usize = 1024 * 1024;
+
+pub(crate) fn enlarge_receive_buffer(s: &impl AsRawFd) {
+    // SAFETY: the socket outlives this borrow of its fd
+    let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(s.as_raw_fd()) };
+
+    // Failure is harmless: the default buffer still works, just
+    // overflows sooner
+    let _ = nix::sys::socket::setsockopt(
+        &fd,
+        nix::sys::socket::sockopt::RcvBuf,
+        &RECEIVE_BUFFER_BYTES,
+    );
+}
+
+/// The length of a `struct nlmsghdr`
+#[cfg(feature = "sync")]
+pub(crate) const HEADER_BYTES: usize = 16;
+
+/// The error in an NLMSG_ERROR message, or None if it's an ack
+///
+/// The payload starts with a (negative) errno; zero means success.
+#[cfg(feature = "sync")]
+pub(crate) fn translate_error_message(msg: &[u8]) -> Option<Error> {
+    match msg.get(HEADER_BYTES..HEADER_BYTES + 4) {
+        Some(errno) => match i32::from_ne_bytes(errno.try_into().unwrap()) {
+            0 => None,
+            errno => Some(Error::from_raw_os_error(errno.saturating_neg())),
+        },
+        None => Some(ErrorKind::InvalidData.into()),
+    }
+}
+
+pub(crate) fn link_dump_request() -> Nlmsghdr<Rtm, Ifinfomsg> {
+    let ifinfomsg = Ifinfomsg::new(
+        RtAddrFamily::Unspecified,
+        Arphrd::Ether,
+        0,
+        IffFlags::empty(),
+        IffFlags::empty(),
+        RtBuffer::new(),
+    );
+    Nlmsghdr::new(
+        None,
+        Rtm::Getlink,
+        NlmFFlags::new(&[NlmF::Request, NlmF::Match]),
+        None,
+        None,
+        NlPayload::Payload(ifinfomsg),
+    )
+}
+
+pub(crate) fn addr_dump_request(
+    family: RtAddrFamily,
+) -> Nlmsghdr<Rtm, Ifaddrmsg> {
+    let ifaddrmsg = Ifaddrmsg {
+        ifa_family: family,
+        ifa_prefixlen: 0,
+        ifa_flags: IfaFFlags::empty(),
+        ifa_scope: 0,
+        ifa_index: 0,
+        rtattrs: RtBuffer::new(),
+    };
+    Nlmsghdr::new(
+        None,
+        Rtm::Getaddr,
+        NlmFFlags::new(&[NlmF::Request, NlmF::Root]),
+        None,
+        None,
+        NlPayload::Payload(ifaddrmsg),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::network_event::KnownLinks;
+    use neli::rtnl::Rtattr;
+    use neli::types::Buffer;
+    use neli::FromBytes;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    #[test]
+    fn parse_4byte_addr() {
+        let input = [192u8, 168u8, 0u8, 200u8];
+
+        let result = ip(&input);
+
+        assert_eq!(result, "192.168.0.200".parse().ok());
+    }
+
+    #[test]
+    fn parse_16byte_addr() {
+        let input = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+        let result = ip(&input);
+
+        assert_eq!(result, "::1".parse().ok());
+    }
+
+    #[test]
+    fn no_parse_5byte_addr() {
+        let input = [2u8, 3, 4, 5, 6];
+
+        let result = ip(&input);
+
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_rx_io_error_mapped() {
+        let err = map_rx_error(DeError::Wrapped(WrappedError::IOError(
+            std::io::Error::from(ErrorKind::UnexpectedEof),
+        )));
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_rx_io_error_not_mapped() {
+        let err = map_rx_error(DeError::BufferNotParsed);
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_tx_io_error_mapped() {
+        let err = map_tx_error(SerError::Wrapped(WrappedError::IOError(
+            std::io::Error::from(ErrorKind::UnexpectedEof),
+        )));
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_tx_io_error_not_mapped() {
+        let err = map_tx_error(SerError::BufferNotFilled);
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_map_up() {
+        assert_eq!(map_flags(&IffFlags::new(&[Iff::Up])), Flags::UP);
+    }
+
+    #[test]
+    fn test_map_running() {
+        assert_eq!(map_flags(&IffFlags::new(&[Iff::Running])), Flags::RUNNING);
+    }
+
+    #[test]
+    fn test_map_loopback() {
+        assert_eq!(
+            map_flags(&IffFlags::new(&[Iff::Loopback])),
+            Flags::LOOPBACK
+        );
+    }
+
+    #[test]
+    fn test_map_pointtopoint() {
+        assert_eq!(
+            map_flags(&IffFlags::new(&[Iff::Pointopoint])),
+            Flags::POINTTOPOINT
+        );
+    }
+
+    #[test]
+    fn test_map_broadcast() {
+        assert_eq!(
+            map_flags(&IffFlags::new(&[Iff::Broadcast])),
+            Flags::BROADCAST
+        );
+    }
+
+    #[test]
+    fn test_map_multicast() {
+        assert_eq!(
+            map_flags(&IffFlags::new(&[Iff::Multicast])),
+            Flags::MULTICAST
+        );
+    }
+
+    #[test]
+    fn test_map_noarp() {
+        assert_eq!(map_flags(&IffFlags::new(&[Iff::Noarp])), Flags::NOARP);
+    }
+
+    #[test]
+    fn test_map_promisc() {
+        assert_eq!(map_flags(&IffFlags::new(&[Iff::Promisc])), Flags::PROMISC);
+    }
+
+    #[test]
+    fn test_map_lower_up() {
+        assert_eq!(
+            map_flags(&IffFlags::new(&[Iff::LowerUp])),
+            Flags::LOWER_UP
+        );
+    }
+
+    #[test]
+    fn test_map_dormant() {
+        assert_eq!(map_flags(&IffFlags::new(&[Iff::Dormant])), Flags::DORMANT);
+    }
+
+    #[test]
+    fn test_map_several() {
+        assert_eq!(
+            map_flags(&IffFlags::new(&[
+                Iff::Up,
+                Iff::Running,
+                Iff::Multicast
+            ])),
+            Flags::UP | Flags::RUNNING | Flags::MULTICAST
+        );
+    }
+
+    #[test]
+    fn test_link_message_no_payload() {
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Getlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Empty,
+        );
+
+        assert!(translate_link_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_link_message_no_name() {
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                0,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                RtBuffer::new(),
+            )),
+        );
+
+        assert!(translate_link_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_link_message_no_type() {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Getlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                0,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+
+        assert!(translate_link_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_link_message_new() {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                3,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+
+        let event = translate_link_message(&msg);
+        assert!(event.is_some());
+        assert_eq!(
+            event.unwrap(),
+            NetworkEvent::NewLink(
+                make_index(3),
+                "eth0".to_string(),
+                Flags::default(),
+                LinkInfo {
+                    mtu: None,
+                    link_type: LinkType::Ethernet,
+                    ..LinkInfo::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_link_message_alias() {
+        let alias = |name: &str| {
+            let mut buf = RtBuffer::new();
+            buf.push(
+                Rtattr::new(None, Ifla::Ifname, name.to_string()).unwrap(),
+            );
+            Nlmsghdr::new(
+                None,
+                Rtm::Newlink,
+                NlmFFlags::empty(),
+                None,
+                None,
+                NlPayload::Payload(Ifinfomsg::new(
+                    RtAddrFamily::Inet,
+                    Arphrd::Ether,
+                    3,
+                    IffFlags::empty(),
+                    IffFlags::empty(),
+                    buf,
+                )),
+            )
+        };
+        let eth0 = NetworkEvent::NewLink(
+            make_index(3),
+            "eth0".to_string(),
+            Flags::default(),
+            LinkInfo {
+                mtu: None,
+                link_type: LinkType::Ethernet,
+                ..LinkInfo::default()
+            },
+        );
+
+        assert_eq!(
+            translate_link_message(&alias("eth0:1")),
+            Some(eth0.clone())
+        );
+
+        // ...and only announced once
+        let mut known = KnownLinks::default();
+        assert_eq!(
+            translate_link_message(&alias("eth0"))
+                .and_then(|e| known.filter(e)),
+            Some(eth0)
+        );
+        assert_eq!(
+            translate_link_message(&alias("eth0:1"))
+                .and_then(|e| known.filter(e)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_link_message_mtu_and_type() {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "wg0".to_string()).unwrap());
+        buf.push(Rtattr::new(None, Ifla::Mtu, 1420u32).unwrap());
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::None,
+                7,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+
+        assert_eq!(
+            translate_link_message(&msg),
+            Some(NetworkEvent::NewLink(
+                make_index(7),
+                "wg0".to_string(),
+                Flags::default(),
+                LinkInfo {
+                    mtu: Some(1420),
+                    link_type: LinkType::Tunnel,
+                    ..LinkInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_link_message_operstate() {
+        let operstate = |state: u8| {
+            let mut buf = RtBuffer::new();
+            buf.push(
+                Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap(),
+            );
+            buf.push(Rtattr::new(None, Ifla::Operstate, state).unwrap());
+            let msg = Nlmsghdr::new(
+                None,
+                Rtm::Newlink,
+                NlmFFlags::empty(),
+                None,
+                None,
+                NlPayload::Payload(Ifinfomsg::new(
+                    RtAddrFamily::Inet,
+                    Arphrd::Ether,
+                    2,
+                    IffFlags::empty(),
+                    IffFlags::empty(),
+                    buf,
+                )),
+            );
+            match translate_link_message(&msg) {
+                Some(NetworkEvent::NewLink(_, _, _, info)) => info.oper_state,
+                e => panic!("unexpected {e:?}"),
+            }
+        };
+
+        assert_eq!(operstate(0), OperState::Unknown);
+        assert_eq!(operstate(1), OperState::NotPresent);
+        assert_eq!(operstate(2), OperState::Down);
+        assert_eq!(operstate(3), OperState::LowerLayerDown);
+        assert_eq!(operstate(4), OperState::Testing);
+        assert_eq!(operstate(5), OperState::Dormant);
+        assert_eq!(operstate(6), OperState::Up);
+        assert_eq!(operstate(99), OperState::Unknown);
+    }
+
+    /// An RTM_NEWLINK for one end of a veth pair, as the kernel sends it
+    /// (less the attributes not looked at)
+    const VETH_NEWLINK: [u8; 84] = [
+        84, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        0, 0, 1, 0, 7, 0, 0, 0, // ifinfomsg: ARPHRD_ETHER, index 7
+        2, 16, 0, 0, 0, 0, 0, 0, // IFF_BROADCAST | IFF_MULTICAST
+        10, 0, 3, 0, b'v', b'e', b't', b'h', b'9', 0, 0,
+        0, // IFLA_IFNAME
+        5, 0, 16, 0, 2, 0, 0, 0, // IFLA_OPERSTATE: IF_OPER_DOWN
+        8, 0, 4, 0, 220, 5, 0, 0, // IFLA_MTU
+        16, 0, 18, 0, // IFLA_LINKINFO
+        9, 0, 1, 0, b'v', b'e', b't', b'h', 0, 0, 0, 0, // IFLA_INFO_KIND
+        8, 0, 5, 0, 6, 0, 0, 0, // IFLA_LINK: the other end
+    ];
+
+    /// An RTM_NEWLINK for an interface enslaved to a bridge
+    const BRIDGE_PORT_NEWLINK: [u8; 80] = [
+        80, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        0, 0, 1, 0, 2, 0, 0, 0, // ifinfomsg: ARPHRD_ETHER, index 2
+        130, 0, 0, 0, 0, 0, 0, 0, // IFF_BROADCAST | IFF_NOARP
+        9, 0, 3, 0, b'i', b'f', b'b', b'0', 0, 0, 0, 0, // IFLA_IFNAME
+        5, 0, 16, 0, 2, 0, 0, 0, // IFLA_OPERSTATE: IF_OPER_DOWN
+        8, 0, 4, 0, 220, 5, 0, 0, // IFLA_MTU
+        8, 0, 10, 0, 5, 0, 0, 0, // IFLA_MASTER
+        12, 0, 18, 0, // IFLA_LINKINFO
+        8, 0, 1, 0, b'i', b'f', b'b', 0, // IFLA_INFO_KIND
+    ];
+
+    fn parse_link_message(bytes: &[u8]) -> Nlmsghdr<Rtm, Ifinfomsg> {
+        Nlmsghdr::from_bytes(&mut std::io::Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn test_link_message_veth_blob() {
+        let msg = parse_link_message(&VETH_NEWLINK);
+        assert_eq!(
+            translate_link_message(&msg),
+            Some(NetworkEvent::NewLink(
+                make_index(7),
+                "veth9".to_string(),
+                Flags::BROADCAST | Flags::MULTICAST,
+                LinkInfo {
+                    mtu: Some(1500),
+                    link_type: LinkType::Ethernet,
+                    oper_state: OperState::Down,
+                    parent: Some(make_index(6)),
+                    master: None,
+                    kind: Some("veth".to_string()),
+                    #[cfg(feature = "wireless")]
+                    wireless: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_link_message_bridge_port_blob() {
+        let msg = parse_link_message(&BRIDGE_PORT_NEWLINK);
+        assert_eq!(
+            translate_link_message(&msg),
+            Some(NetworkEvent::NewLink(
+                make_index(2),
+                "ifb0".to_string(),
+                Flags::BROADCAST | Flags::NOARP,
+                LinkInfo {
+                    mtu: Some(1500),
+                    link_type: LinkType::Ethernet,
+                    oper_state: OperState::Down,
+                    parent: None,
+                    master: Some(make_index(5)),
+                    kind: Some("ifb".to_string()),
+                    #[cfg(feature = "wireless")]
+                    wireless: None,
+                }
+            ))
+        );
+    }
+
+    /// An RTM_NEWLINK for the loopback interface, less its statistics
+    const LOOPBACK_NEWLINK: [u8; 56] = [
+        56, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        0, 0, 4, 3, 1, 0, 0, 0, // ifinfomsg: ARPHRD_LOOPBACK, index 1
+        0x49, 0, 1, 0, 0, 0, 0, 0, // UP | LOOPBACK | RUNNING | LOWER_UP
+        7, 0, 3, 0, b'l', b'o', 0, 0, // IFLA_IFNAME
+        8, 0, 4, 0, 0, 0, 1, 0, // IFLA_MTU
+        5, 0, 16, 0, 0, 0, 0, 0, // IFLA_OPERSTATE: IF_OPER_UNKNOWN
+    ];
+
+    #[test]
+    fn test_link_message_loopback_blob() {
+        let msg = parse_link_message(&LOOPBACK_NEWLINK);
+        assert_eq!(
+            translate_link_message(&msg),
+            Some(NetworkEvent::NewLink(
+                make_index(1),
+                "lo".to_string(),
+                Flags::UP | Flags::LOOPBACK | Flags::RUNNING | Flags::LOWER_UP,
+                LinkInfo {
+                    mtu: Some(65536),
+                    link_type: LinkType::Loopback,
+                    ..LinkInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_link_message_blob_without_name() {
+        let mut bytes = LOOPBACK_NEWLINK;
+        bytes[34] = 0xFF; // IFLA_IFNAME -> unknown attribute 0xFF
+        let msg = parse_link_message(&bytes);
+        assert_eq!(translate_link_message(&msg), None);
+    }
+
+    #[test]
+    fn test_link_message_blob_without_attributes() {
+        let mut bytes = LOOPBACK_NEWLINK;
+        bytes[0] = 32; // nlmsg_len: just the headers
+        let msg = parse_link_message(&bytes[..32]);
+        assert_eq!(translate_link_message(&msg), None);
+    }
+
+    #[test]
+    fn test_link_message_blob_del() {
+        // The kernel sends RTM_DELLINK with the link's final attributes
+        let mut bytes = VETH_NEWLINK;
+        bytes[4] = 17; // RTM_DELLINK
+        let msg = parse_link_message(&bytes);
+        assert_eq!(
+            translate_link_message(&msg),
+            Some(NetworkEvent::DelLink(make_index(7)))
+        );
+    }
+
+    #[test]
+    fn test_link_message_blob_del_without_attributes() {
+        let mut bytes = VETH_NEWLINK;
+        bytes[0] = 32;
+        bytes[4] = 17;
+        let msg = parse_link_message(&bytes[..32]);
+        assert_eq!(
+            translate_link_message(&msg),
+            Some(NetworkEvent::DelLink(make_index(7)))
+        );
+    }
+
+    fn link_with(attrs: Vec<Rtattr<Ifla, Buffer>>) -> LinkInfo {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
+        for attr in attrs {
+            buf.push(attr);
+        }
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                2,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+        match translate_link_message(&msg) {
+            Some(NetworkEvent::NewLink(_, _, _, info)) => info,
+            e => panic!("unexpected {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_link_message_self_parent_ignored() {
+        // Current kernels send IFLA_LINK only when it differs from
+        // the interface's own index, but older ones always sent it
+        let info =
+            link_with(vec![Rtattr::new(None, Ifla::Link, 2u32).unwrap()]);
+        assert_eq!(info.parent, None);
+    }
+
+    #[test]
+    fn test_link_message_other_namespace_ignored() {
+        let info = link_with(vec![
+            Rtattr::new(None, Ifla::Link, 9u32).unwrap(),
+            Rtattr::new(None, Ifla::LinkNetnsid, 1i32).unwrap(),
+        ]);
+        assert_eq!(info.parent, None);
+    }
+
+    #[test]
+    fn test_link_message_vlan() {
+        let mut linkinfo =
+            Rtattr::new(None, Ifla::Linkinfo, Buffer::from(Vec::new()))
+                .unwrap();
+        linkinfo
+            .add_nested_attribute(
+                &Rtattr::new(None, IflaInfo::Kind, "vlan".to_string())
+                    .unwrap(),
+            )
+            .unwrap();
+        let info = link_with(vec![
+            Rtattr::new(None, Ifla::Link, 1u32).unwrap(),
+            linkinfo,
+        ]);
+        assert_eq!(info.parent, Some(make_index(1)));
+        assert_eq!(info.master, None);
+        assert_eq!(info.kind.as_deref(), Some("vlan"));
+    }
+
+    #[test]
+    fn test_link_message_physical() {
+        let info = link_with(Vec::new());
+        assert_eq!(info.parent, None);
+        assert_eq!(info.master, None);
+        assert_eq!(info.kind, None);
+    }
+
+    #[test]
+    fn test_link_message_del() {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth1".to_string()).unwrap());
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Dellink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                2,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+
+        let event = translate_link_message(&msg);
+        assert!(event.is_some());
+        assert_eq!(event.unwrap(), NetworkEvent::DelLink(make_index(2)));
+    }
+
+    #[test]
+    fn test_addr_message_no_payload() {
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Getlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Empty,
+        );
+
+        assert!(translate_addr_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_addr_message_no_addr() {
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 0,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: RtBuffer::new(),
+            }),
+        );
+
+        assert!(translate_addr_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_addr_message_bad_addr() {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifa::Address, 65535u16).unwrap());
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 0,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        assert!(translate_addr_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_addr_message_bad_type() {
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 0,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        assert!(translate_addr_message(&msg).is_none());
+    }
+
+    #[test]
+    fn test_addr_message_new() {
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newaddr,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 24,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        let event = translate_addr_message(&msg);
+        assert!(event.is_some());
+        assert_eq!(
+            event.unwrap(),
+            NetworkEvent::NewAddr(
+                make_index(2),
+                ip(&[255, 255, 0, 0]).unwrap(),
+                24,
+                AddrInfo::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_addr_message_del() {
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Deladdr,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 24,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        let event = translate_addr_message(&msg);
+        assert!(event.is_some());
+        assert_eq!(
+            event.unwrap(),
+            NetworkEvent::DelAddr(
+                make_index(2),
+                ip(&[255, 255, 0, 0]).unwrap(),
+                24
+            )
+        );
+    }
+
+    /// An RTM_NEWADDR for a SLAAC address, as the kernel sends it
+    pub(crate) const IPV6_NEWADDR: [u8; 72] = [
+        0x48, 0, 0, 0, 0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        10, 64, 0, 0, 2, 0, 0, 0, // ifaddrmsg: AF_INET6, /64, index 2
+        0x14, 0, 1, 0, // IFA_ADDRESS
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, //
+        0x01, 0xc2, 0x03, 0xff, 0xfe, 0x04, 0x05, 0x06, //
+        0x14, 0, 6, 0, // IFA_CACHEINFO
+        0x80, 0x51, 0x01, 0, 0, 0xa3, 0x02, 0, //
+        0x10, 0x27, 0, 0, 0x20, 0x4e, 0, 0, //
+        8, 0, 8, 0, 0, 1, 0, 0, // IFA_FLAGS
+    ];
+
+    pub(crate) fn parse_addr_message(
+        bytes: &[u8],
+    ) -> Nlmsghdr<Rtm, Ifaddrmsg> {
+        Nlmsghdr::from_bytes(&mut std::io::Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn test_addr_message_ipv6_blob() {
+        let msg = parse_addr_message(&IPV6_NEWADDR);
+        assert_eq!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::NewAddr(
+                make_index(2),
+                "2001:db8::1c2:3ff:fe04:506".parse().unwrap(),
+                64,
+                AddrInfo::default()
+            ))
+        );
+    }
+
+    fn blob_addr_info(bytes: &[u8]) -> AddrInfo {
+        match translate_addr_message(&parse_addr_message(bytes)) {
+            Some(NetworkEvent::NewAddr(_, _, _, info)) => info,
+            e => panic!("unexpected {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_addr_message_ipv6_temporary() {
+        let mut bytes = IPV6_NEWADDR;
+        bytes[68] = 0x01; // IFA_FLAGS |= IFA_F_TEMPORARY
+        let info = blob_addr_info(&bytes);
+        assert_eq!(info.flags, AddressFlags::TEMPORARY);
+        assert_eq!(info.scope, Scope::Global);
+    }
+
+    #[test]
+    fn test_addr_message_ipv6_deprecated() {
+        let mut bytes = IPV6_NEWADDR;
+        bytes[68] = 0x20; // IFA_FLAGS |= IFA_F_DEPRECATED
+        let info = blob_addr_info(&bytes);
+        assert_eq!(info.flags, AddressFlags::DEPRECATED);
+        assert!(!info.flags.contains(AddressFlags::TEMPORARY));
+    }
+
+    #[test]
+    fn test_addr_message_ipv6_tentative_without_ifa_flags() {
+        // Older kernels send only the 8-bit ifa_flags
+        let mut bytes = IPV6_NEWADDR;
+        bytes[0] = 0x40; // nlmsg_len, without the IFA_FLAGS attribute
+        bytes[18] = 0x40; // ifa_flags = IFA_F_TENTATIVE
+        let info = blob_addr_info(&bytes[..0x40]);
+        assert_eq!(info.flags, AddressFlags::TENTATIVE);
+    }
+
+    #[test]
+    fn test_addr_message_scope() {
+        let mut bytes = IPV6_NEWADDR;
+        bytes[19] = 253; // RT_SCOPE_LINK
+        assert_eq!(blob_addr_info(&bytes).scope, Scope::Link);
+        bytes[19] = 254; // RT_SCOPE_HOST
+        assert_eq!(blob_addr_info(&bytes).scope, Scope::Host);
+        bytes[19] = 255; // RT_SCOPE_NOWHERE: go by the address instead
+        assert_eq!(blob_addr_info(&bytes).scope, Scope::Global);
+    }
+
+    #[test]
+    fn test_addr_message_ipv6_blob_del() {
+        let mut bytes = IPV6_NEWADDR;
+        bytes[4] = 0x15; // RTM_DELADDR
+        let msg = parse_addr_message(&bytes);
+        assert_eq!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::DelAddr(
+                make_index(2),
+                "2001:db8::1c2:3ff:fe04:506".parse().unwrap(),
+                64
+            ))
+        );
+    }
+
+    #[test]
+    fn test_addr_message_local_preferred() {
+        // Point-to-point: IFA_ADDRESS is the peer
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0x0A00_0001u32.to_be()).unwrap(),
+        );
+        buf.push(
+            Rtattr::new(None, Ifa::Local, 0x0A00_0002u32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newaddr,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 32,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 4,
+                rtattrs: buf,
+            }),
+        );
+
+        assert_eq!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::NewAddr(
+                make_index(4),
+                ip(&[10, 0, 0, 2]).unwrap(),
+                32,
+                AddrInfo {
+                    broadcast: None,
+                    peer: ip(&[10, 0, 0, 1]),
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_addr_message_broadcast() {
+        let mut buf = RtBuffer::new();
+        buf.push(
+            Rtattr::new(None, Ifa::Address, 0xC0A8_0114u32.to_be()).unwrap(),
+        );
+        buf.push(
+            Rtattr::new(None, Ifa::Local, 0xC0A8_0114u32.to_be()).unwrap(),
+        );
+        buf.push(
+            Rtattr::new(None, Ifa::Broadcast, 0xC0A8_01FFu32.to_be()).unwrap(),
+        );
+
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newaddr,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifaddrmsg {
+                ifa_family: RtAddrFamily::Inet,
+                ifa_prefixlen: 24,
+                ifa_flags: IfaFFlags::empty(),
+                ifa_scope: 0,
+                ifa_index: 2,
+                rtattrs: buf,
+            }),
+        );
+
+        assert_eq!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::NewAddr(
+                make_index(2),
+                ip(&[192, 168, 1, 20]).unwrap(),
+                24,
+                AddrInfo {
+                    broadcast: ip(&[192, 168, 1, 255]),
+                    peer: None,
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    /// An RTM_NEWADDR for an Ethernet interface's IPv4 address, less
+    /// IFA_CACHEINFO
+    const IPV4_NEWADDR: [u8; 68] = [
+        68, 0, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        2, 24, 0x80, 0, 2, 0, 0, 0, // ifaddrmsg: AF_INET, /24, index 2
+        8, 0, 1, 0, 192, 168, 1, 10, // IFA_ADDRESS
+        8, 0, 2, 0, 192, 168, 1, 10, // IFA_LOCAL
+        8, 0, 4, 0, 192, 168, 1, 255, // IFA_BROADCAST
+        9, 0, 3, 0, b'e', b't', b'h', b'0', 0, 0, 0, 0, // IFA_LABEL
+        8, 0, 8, 0, 0x80, 0, 0, 0, // IFA_FLAGS: IFA_F_PERMANENT
+    ];
+
+    #[test]
+    fn test_addr_message_ipv4_blob() {
+        let msg = parse_addr_message(&IPV4_NEWADDR);
+        assert_eq!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::NewAddr(
+                make_index(2),
+                ip(&[192, 168, 1, 10]).unwrap(),
+                24,
+                AddrInfo {
+                    broadcast: ip(&[192, 168, 1, 255]),
+                    scope: Scope::Global,
+                    ..AddrInfo::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_addr_message_ipv4_blob_del() {
+        let mut bytes = IPV4_NEWADDR;
+        bytes[4] = 21; // RTM_DELADDR
+        let msg = parse_addr_message(&bytes);
+        assert_eq!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::DelAddr(
+                make_index(2),
+                ip(&[192, 168, 1, 10]).unwrap(),
+                24
+            ))
+        );
+    }
+
+    #[test]
+    fn test_addr_message_ipv4_blob_without_local() {
+        let mut bytes = IPV4_NEWADDR;
+        bytes[34] = 0xFF; // IFA_LOCAL -> unknown attribute 0xFF
+        let msg = parse_addr_message(&bytes);
+        assert!(matches!(
+            translate_addr_message(&msg),
+            Some(NetworkEvent::NewAddr(_, a, 24, _))
+                if a == ip(&[192, 168, 1, 10]).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_addr_message_blob_without_address() {
+        let mut bytes = IPV4_NEWADDR;
+        bytes[26] = 0xFF; // IFA_ADDRESS, IFA_LOCAL -> unknown
+        bytes[34] = 0xFF;
+        let msg = parse_addr_message(&bytes);
+        assert_eq!(translate_addr_message(&msg), None);
+    }
+
+    #[test]
+    fn test_addr_message_blob_without_attributes() {
+        let mut bytes = IPV4_NEWADDR;
+        bytes[0] = 24;
+        bytes[4] = 21;
+        let msg = parse_addr_message(&bytes[..24]);
+        assert_eq!(translate_addr_message(&msg), None);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::network_event::InterfaceIndex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The type of the function reading a file from /proc
+type ReadFn = fn(&str) -> Result<String, Error>;
+
+/// Every group joined, on every interface
+type Memberships = BTreeMap<InterfaceIndex, BTreeSet<IpAddr>>;
+
+fn index(s: &str) -> Option<InterfaceIndex> {
+    s.parse()
+        .ok()
+        .and_then(core::num::NonZeroU32::new)
+        .map(InterfaceIndex)
+}
+
+/// Parse /proc/net/igmp
+///
+/// Each interface has a line starting with its index and name, then an
+/// indented line for each group it has joined. Groups are printed as
+/// the raw (network-order) 32-bit address, in hex, read as a
+/// host-order integer.
+fn parse_igmp(text: &str, groups: &mut Memberships) {
+    let mut current = None;
+    for line in text.lines().skip(1) {
+        if line.starts_with(char::is_whitespace) {
+            let group = line
+                .split_whitespace()
+                .next()
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+            if let (Some(ix), Some(group)) = (current, group) {
+                groups
+                    .entry(ix)
+                    .or_default()
+                    .insert(IpAddr::V4(Ipv4Addr::from(group.to_ne_bytes())));
+            }
+        } else {
+            current = line.split_whitespace().next().and_then(index);
+        }
+    }
+}
+
+/// Parse /proc/net/igmp6
+///
+/// There's no header: each line is index, name, group (as 32 hex
+/// digits), and then counters which aren't needed here.
+fn parse_igmp6(text: &str, groups: &mut Memberships) {
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let ix = fields.next().and_then(index);
+        let group = fields
+            .nth(1)
+            .and_then(|hex| u128::from_str_radix(hex, 16).ok());
+        if let (Some(ix), Some(group)) = (ix, group) {
+            groups
+                .entry(ix)
+                .or_default()
+                .insert(IpAddr::V6(Ipv6Addr::from(group)));
+        }
+    }
+}
+
+/// Read a file, treating a missing one as empty
+///
+/// /proc/net/igmp6 is absent if IPv6 is disabled.
+fn read_optional(read: ReadFn, path: &str) -> Result<String, Error> {
+    match read(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+fn memberships(read: ReadFn) -> Result<Memberships, Error> {
+    let mut groups = Memberships::new();
+    parse_igmp(&read_optional(read, "/proc/net/igmp")?, &mut groups);
+    parse_igmp6(&read_optional(read, "/proc/net/igmp6")?, &mut groups);
+    Ok(groups)
+}
+
+fn read_proc(path: &str) -> Result<String, Error> {
+    std::fs::read_to_string(path)
+}
+
+/** The multicast groups a network interface has joined
+
+This is for looking into, say, why a multicast receiver isn't
+receiving anything: if the group isn't listed, nothing on this host
+has joined it on that interface (or it's been left again). Groups are
+as reported in `/proc/net/igmp` and `/proc/net/igmp6`, so include
+those which the kernel joins by itself, such as the all-hosts group
+224.0.0.1 and, for IPv6, the solicited-node groups. They're in
+ascending order, IPv4 first.
+
+```rust
+# use cotton_netif::*;
+for e in get_interfaces()? {
+    if let NetworkEvent::NewLink(ix, name, _, _) = e {
+        println!("{}: {:?}", name, multicast_groups(ix)?);
+    }
+}
+# Ok::<(), std::io::Error>(())
+```
+
+# Errors
+
+Returns Err if the files in /proc can't be read (for instance, because
+/proc isn't mounted, as in some sandboxes).
+
+ */
+pub fn multicast_groups(ix: InterfaceIndex) -> Result<Vec<IpAddr>, Error> {
+    Ok(memberships(read_proc)?
+        .remove(&ix)
+        .map(|groups| groups.into_iter().collect())
+        .unwrap_or_default())
+}
+
+/// A change in the multicast groups joined, from [`multicast_groups_stream`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MulticastEvent {
+    /// A group has been joined on an interface
+    Joined(InterfaceIndex, IpAddr),
+
+    /// A group has been left on an interface (or the interface has
+    /// gone away)
+    Left(InterfaceIndex, IpAddr),
+}
+
+/// The events which take `old` to `new`: departures, then arrivals
+#[cfg(feature = "async")]
+fn diff(old: &Memberships, new: &Memberships) -> Vec<MulticastEvent> {
+    let flatten = |m: &Memberships| {
+        m.iter()
+            .flat_map(|(ix, groups)| groups.iter().map(|g| (*ix, *g)))
+            .collect::<BTreeSet<_>>()
+    };
+    let (old, new) = (flatten(old), flatten(new));
+    old.difference(&new)
+        .map(|(ix, g)| MulticastEvent::Left(*ix, *g))
+        .chain(
+            new.difference(&old)
+                .map(|(ix, g)| MulticastEvent::Joined(*ix, *g)),
+        )
+        .collect()
+}
+
+/** A stream of changes to the multicast groups joined, on all
+interfaces
+
+The kernel doesn't announce these changes (at least, not for IPv4),
+so this re-reads the lists in /proc every `interval` and reports the
+differences. It starts with a [`MulticastEvent::Joined`] for every
+group already joined.
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# use std::time::Duration;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+let mut s = multicast_groups_stream(Duration::from_secs(1));
+
+while let Some(e) = s.next().await {
+    println!("{:?}", e?);
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+# });
+```
+
+Errors in reading the lists are passed on as stream items; the
+stream continues after them.
+ */
+#[cfg(feature = "async")]
+pub fn multicast_groups_stream(
+    interval: std::time::Duration,
+) -> impl futures_util::stream::Stream<Item = Result<MulticastEvent, Error>> {
+    multicast_groups_stream_inner(interval, read_proc)
+}
+
+#[cfg(feature = "async")]
+fn multicast_groups_stream_inner(
+    interval: std::time::Duration,
+    read: ReadFn,
+) -> impl futures_util::stream::Stream<Item = Result<MulticastEvent, Error>> {
+    Box::pin(async_stream::stream! {
+        let mut previous = Memberships::new();
+        let mut first = true;
+        loop {
+            if !first {
+                crate::polling::sleep(interval).await;
+            }
+            first = false;
+            match memberships(read) {
+                Ok(groups) => {
+                    for event in diff(&previous, &groups) {
+                        yield Ok(event);
+                    }
+                    previous = groups;
+                }
+                Err(e) => yield Err(e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    /// As captured from a little-endian Linux 6.x system (with one group
+    /// added)
+    const IGMP: &str = "\
+Idx\tDevice    : Count Querier\tGroup    Users Timer\tReporter
+1\tlo        :     1      V3
+\t\t\t\t010000E0     1 0:00000000\t\t0
+4\teth0      :     2      V3
+\t\t\t\tFAFFFFEF     1 0:00000000\t\t0
+\t\t\t\t010000E0     1 0:00000000\t\t0
+";
+
+    const IGMP6: &str = "\
+1    lo              ff020000000000000000000000000001     1 0000000C 0
+1    lo              ff010000000000000000000000000001     1 00000008 0
+4    eth0            ff0200000000000000000001ff000002     1 00000004 0
+4    eth0            ff02000000000000000000000000000c     1 00000004 0
+4    eth0            ff020000000000000000000000000001     1 0000000C 0
+";
+
+    fn all_hosts() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(224, 0, 0, 1))
+    }
+
+    fn ssdp() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250))
+    }
+
+    fn v6(s: &str) -> IpAddr {
+        IpAddr::V6(s.parse().unwrap())
+    }
+
+    fn fixtures(path: &str) -> Result<String, Error> {
+        match path {
+            "/proc/net/igmp" => Ok(IGMP.to_string()),
+            "/proc/net/igmp6" => Ok(IGMP6.to_string()),
+            _ => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn ipv4_only(path: &str) -> Result<String, Error> {
+        match path {
+            "/proc/net/igmp" => Ok(IGMP.to_string()),
+            _ => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn no_proc(_: &str) -> Result<String, Error> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+
+    #[test]
+    fn igmp() {
+        let mut groups = Memberships::new();
+        parse_igmp(IGMP, &mut groups);
+        assert_eq!(
+            groups,
+            Memberships::from([
+                (make_index(1), BTreeSet::from([all_hosts()])),
+                (make_index(4), BTreeSet::from([all_hosts(), ssdp()])),
+            ])
+        );
+    }
+
+    #[test]
+    fn igmp6() {
+        let mut groups = Memberships::new();
+        parse_igmp6(IGMP6, &mut groups);
+        assert_eq!(
+            groups[&make_index(4)],
+            BTreeSet::from([
+                v6("ff02::1"),
+                v6("ff02::c"),
+                v6("ff02::1:ff00:2"),
+            ])
+        );
+        assert_eq!(groups[&make_index(1)].len(), 2);
+    }
+
+    #[test]
+    fn garbage_ignored() {
+        let mut groups = Memberships::new();
+        parse_igmp("header\nnonsense\n\t\tZZZZ 1\n", &mut groups);
+        parse_igmp6("0 none ff02::1\n4 eth0 nothex 1\n\n", &mut groups);
+        assert_eq!(groups, Memberships::new());
+    }
+
+    #[test]
+    fn both_families() {
+        let groups = memberships(fixtures).unwrap();
+        assert_eq!(
+            groups[&make_index(4)].iter().copied().collect::<Vec<_>>(),
+            vec![
+                all_hosts(),
+                ssdp(),
+                v6("ff02::1"),
+                v6("ff02::c"),
+                v6("ff02::1:ff00:2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn without_ipv6() {
+        let groups = memberships(ipv4_only).unwrap();
+        assert_eq!(groups[&make_index(4)].len(), 2);
+    }
+
+    #[test]
+    fn unreadable() {
+        assert_eq!(
+            memberships(no_proc).unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn diffs() {
+        let old = memberships(fixtures).unwrap();
+        let mut new = old.clone();
+        new.get_mut(&make_index(4)).unwrap().remove(&ssdp());
+        new.get_mut(&make_index(1)).unwrap().insert(ssdp());
+        new.remove(&make_index(4))
+            .map(|g| new.insert(make_index(5), g));
+        assert_eq!(diff(&old, &old), vec![]);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                MulticastEvent::Left(make_index(4), all_hosts()),
+                MulticastEvent::Left(make_index(4), ssdp()),
+                MulticastEvent::Left(make_index(4), v6("ff02::1")),
+                MulticastEvent::Left(make_index(4), v6("ff02::c")),
+                MulticastEvent::Left(make_index(4), v6("ff02::1:ff00:2")),
+                MulticastEvent::Joined(make_index(1), ssdp()),
+                MulticastEvent::Joined(make_index(5), all_hosts()),
+                MulticastEvent::Joined(make_index(5), v6("ff02::1")),
+                MulticastEvent::Joined(make_index(5), v6("ff02::c")),
+                MulticastEvent::Joined(make_index(5), v6("ff02::1:ff00:2")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn stream_starts_with_joins() {
+        use futures_util::StreamExt;
+
+        let s = multicast_groups_stream_inner(
+            std::time::Duration::from_millis(1),
+            ipv4_only,
+        );
+        assert_eq!(
+            s.take(3).map(Result::unwrap).collect::<Vec<_>>().await,
+            vec![
+                MulticastEvent::Joined(make_index(1), all_hosts()),
+                MulticastEvent::Joined(make_index(4), all_hosts()),
+                MulticastEvent::Joined(make_index(4), ssdp()),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_join_shows_up() {
+        let lo = make_index(nix::net::if_::if_nametoindex("lo").unwrap());
+        let group = Ipv4Addr::new(239, 1, 2, 3);
+        assert!(!multicast_groups(lo).unwrap().contains(&group.into()));
+
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+        socket
+            .join_multicast_v4(&group, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        assert!(multicast_groups(lo).unwrap().contains(&group.into()));
+
+        drop(socket);
+        assert!(!multicast_groups(lo).unwrap().contains(&group.into()));
+    }
+}
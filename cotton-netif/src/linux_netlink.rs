@@ -1,172 +1,157 @@
-use crate::network_event::{Flags, InterfaceIndex, NetworkEvent};
+use crate::async_socket::AsyncSocket;
+use crate::linux_messages::{
+    add_wireless, addr_dump_request, enlarge_receive_buffer,
+    link_dump_request, map_rx_error, map_tx_error, translate_addr_message,
+    translate_link_message,
+};
+use crate::network_event::{InterfaceIndex, KnownLinks, NetworkEvent};
 use async_stream::stream;
 use futures_util::stream;
 use futures_util::stream::Stream;
+use futures_util::StreamExt;
 use neli::{
     consts::{
-        nl::{NlmF, NlmFFlags},
-        rtnl::{
-            Arphrd, Ifa, IfaFFlags, Iff, IffFlags, Ifla, RtAddrFamily, Rtm,
-        },
-        socket::NlFamily,
+        nl::Nlmsg, rtnl::RtAddrFamily, rtnl::Rtm, socket::NlFamily,
+        MAX_NL_LENGTH,
     },
     err::DeError,
     err::SerError,
     err::WrappedError,
-    nl::{NlPayload, Nlmsghdr},
+    nl::Nlmsghdr,
     rtnl::Ifaddrmsg,
     rtnl::Ifinfomsg,
-    socket::tokio::NlSocket,
     socket::NlSocketHandle,
     types::NlBuffer,
-    types::RtBuffer,
+    FromBytesWithInput, Size, ToBytes,
 };
 use std::{
+    collections::{BTreeSet, VecDeque},
+    io::Cursor,
     io::Error,
-    io::ErrorKind,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
 };
+use tokio::sync::watch;
 
-fn ip(ip_bytes: &[u8]) -> Option<IpAddr> {
-    match ip_bytes.len() {
-        4 => Some(IpAddr::from(Ipv4Addr::from(u32::from_be_bytes(
-            ip_bytes.try_into().unwrap(),
-        )))),
-
-        16 => Some(IpAddr::from(Ipv6Addr::from(u128::from_be_bytes(
-            ip_bytes.try_into().unwrap(),
-        )))),
-
-        _ => {
-            println!(
-                "Unrecognized address length of {} found",
-                ip_bytes.len()
-            );
-            None
-        }
+/** A netlink socket for async code
+
+Like `neli::socket::tokio::NlSocket`, but using [`AsyncSocket`], so
+that it works with whichever runtime is configured.
+ */
+pub(crate) struct NlSocket(AsyncSocket<neli::socket::NlSocket>);
+
+impl NlSocket {
+    /// Make a socket non-blocking and register it
+    pub(crate) fn new<S>(s: S) -> Result<Self, Error>
+    where
+        S: Into<neli::socket::NlSocket>,
+    {
+        let socket = s.into();
+        socket.nonblock()?;
+        Ok(Self(AsyncSocket::new(socket)?))
     }
-}
 
-fn map_rx_error(err: DeError) -> Error {
-    if let DeError::Wrapped(WrappedError::IOError(io_error)) = err {
-        io_error
-    } else {
-        Error::from(ErrorKind::Other)
+    /// Send a message
+    pub(crate) async fn send<P>(
+        &mut self,
+        msg: &Nlmsghdr<Rtm, P>,
+    ) -> Result<(), SerError>
+    where
+        P: Size + ToBytes,
+    {
+        let mut buffer = Cursor::new(Vec::with_capacity(msg.padded_size()));
+        msg.to_bytes(&mut buffer)?;
+        self.0
+            .write_with(|socket| socket.send(buffer.get_ref(), 0))
+            .await?;
+        Ok(())
     }
-}
 
-fn map_tx_error(err: SerError) -> Error {
-    if let SerError::Wrapped(WrappedError::IOError(io_error)) = err {
-        io_error
-    } else {
-        Error::from(ErrorKind::Other)
+    /// Wait for a datagram, and parse the messages in it
+    pub(crate) async fn recv<'a, P>(
+        &mut self,
+        buffer: &'a mut Vec<u8>,
+    ) -> Result<NlBuffer<Rtm, P>, DeError>
+    where
+        P: FromBytesWithInput<'a, Input = usize>,
+    {
+        buffer.resize(MAX_NL_LENGTH, 0);
+        let n = self
+            .0
+            .read_with(|socket| socket.recv(buffer.as_mut_slice(), 0))
+            .await?;
+        buffer.truncate(n);
+        NlBuffer::from_bytes_with_input(&mut Cursor::new(buffer.as_slice()), n)
     }
 }
 
-fn map_flags(flags: &IffFlags) -> Flags {
-    let mut newflags = Flags::default();
-    for (iff, newf) in [
-        (&Iff::Up, Flags::UP),
-        (&Iff::Running, Flags::RUNNING),
-        (&Iff::Loopback, Flags::LOOPBACK),
-        (&Iff::Pointopoint, Flags::POINTTOPOINT),
-        (&Iff::Broadcast, Flags::BROADCAST),
-        (&Iff::Multicast, Flags::MULTICAST),
-    ] {
-        if flags.contains(iff) {
-            newflags |= newf;
-        }
-    }
-    newflags
+/// Whether this is the NLMSG_DONE which ends a dump
+pub(crate) fn is_dump_done<P>(msg: &Nlmsghdr<Rtm, P>) -> bool {
+    u16::from(&msg.nl_type) == u16::from(Nlmsg::Done)
 }
 
-#[allow(clippy::cast_sign_loss)]
-fn translate_link_message(
-    msg: &Nlmsghdr<Rtm, Ifinfomsg>,
-) -> Option<NetworkEvent> {
-    if let NlPayload::Payload(p) = &msg.nl_payload {
-        match msg.nl_type {
-            Rtm::Newlink => {
-                let handle = p.rtattrs.get_attr_handle();
-                let name = handle
-                    .get_attr_payload_as_with_len::<String>(Ifla::Ifname)
-                    .ok();
-                if let Some(name) = name {
-                    let newflags = map_flags(&p.ifi_flags);
-                    return core::num::NonZeroU32::new(p.ifi_index as u32)
-                        .map(|ix| {
-                            NetworkEvent::NewLink(
-                                InterfaceIndex(ix),
-                                name,
-                                newflags,
-                            )
-                        });
-                }
-            }
-            Rtm::Dellink => {
-                return core::num::NonZeroU32::new(p.ifi_index as u32)
-                    .map(|ix| NetworkEvent::DelLink(InterfaceIndex(ix)));
-            }
-            _ => (),
-        }
-    }
-    None
+/// Whether a receive error means that the kernel dropped messages
+///
+/// Netlink sockets report ENOBUFS when their receive buffer overflows;
+/// the next receive carries on, but with events missing.
+pub(crate) fn is_overflow(err: &DeError) -> bool {
+    matches!(
+        err,
+        DeError::Wrapped(WrappedError::IOError(e))
+            if e.raw_os_error() == Some(libc::ENOBUFS)
+    )
 }
 
-#[allow(clippy::cast_sign_loss)]
-fn translate_addr_message(
-    msg: &Nlmsghdr<Rtm, Ifaddrmsg>,
-) -> Option<NetworkEvent> {
-    if let NlPayload::Payload(p) = &msg.nl_payload {
-        let handle = p.rtattrs.get_attr_handle();
-        if let Some(addr) = handle
-            .get_attr_payload_as_with_len::<&[u8]>(Ifa::Address)
-            .ok()
-            .and_then(ip)
-        {
-            match msg.nl_type {
-                Rtm::Newaddr => {
-                    return core::num::NonZeroU32::new(p.ifa_index as u32)
-                        .map(|ix| {
-                            NetworkEvent::NewAddr(
-                                InterfaceIndex(ix),
-                                addr,
-                                p.ifa_prefixlen,
-                            )
-                        });
-                }
-                Rtm::Deladdr => {
-                    return core::num::NonZeroU32::new(p.ifa_index as u32)
-                        .map(|ix| {
-                            NetworkEvent::DelAddr(
-                                InterfaceIndex(ix),
-                                addr,
-                                p.ifa_prefixlen,
-                            )
-                        });
-                }
-                _ => (),
-            }
-        }
-    }
-    None
+/// Shared by the three sockets of one stream; each notification asks
+/// all of them to re-request their dumps
+type RedumpSender = Arc<watch::Sender<()>>;
+
+/// What woke a socket's stream
+enum Wakeup<T> {
+    Received(T),
+    Redump,
 }
 
 fn get_links(
     mut ss: NlSocket,
+    redump: RedumpSender,
 ) -> impl Stream<Item = Result<NetworkEvent, Error>> {
     let mut buffer = Vec::new();
+    let mut known = KnownLinks::default();
+    let mut redumps = redump.subscribe();
     stream! {
         loop {
-            let res: Result<NlBuffer<Rtm, Ifinfomsg>, DeError> =
-                ss.recv(&mut buffer).await;
+            let wakeup = tokio::select! {
+                res = ss.recv(&mut buffer) => Wakeup::Received(res),
+                Ok(()) = redumps.changed() => Wakeup::Redump,
+            };
+            let res: Result<NlBuffer<Rtm, Ifinfomsg>, DeError> = match wakeup {
+                Wakeup::Received(res) => res,
+                Wakeup::Redump => {
+                    // Everything is about to be announced afresh
+                    known = KnownLinks::default();
+                    if let Err(e) = ss.send(&link_dump_request()).await {
+                        yield Err(map_tx_error(e));
+                    }
+                    continue;
+                }
+            };
             match res {
                 Ok(msgs) =>
                     for msg in msgs {
-                        if let Some(event) = translate_link_message(&msg) {
+                        if is_dump_done(&msg) {
+                            yield Ok(NetworkEvent::EnumerationComplete);
+                        } else if let Some(event) =
+                            translate_link_message(&msg)
+                                .and_then(|e| known.filter(e))
+                                .map(add_wireless)
+                        {
                             yield Ok(event);
                         }
                     },
+                Err(e) if is_overflow(&e) => {
+                    yield Ok(NetworkEvent::Overflow);
+                    redump.send_replace(());
+                }
                 Err(e) => yield Err(map_rx_error(e))
             }
         }
@@ -175,19 +160,41 @@ fn get_links(
 
 fn get_addrs(
     mut ss: NlSocket,
+    family: RtAddrFamily,
+    redump: RedumpSender,
 ) -> impl Stream<Item = Result<NetworkEvent, Error>> {
     let mut buffer = Vec::new();
+    let mut redumps = redump.subscribe();
     stream! {
         loop {
-            let res: Result<NlBuffer<Rtm, Ifaddrmsg>, DeError> =
-                ss.recv(&mut buffer).await;
+            let wakeup = tokio::select! {
+                res = ss.recv(&mut buffer) => Wakeup::Received(res),
+                Ok(()) = redumps.changed() => Wakeup::Redump,
+            };
+            let res: Result<NlBuffer<Rtm, Ifaddrmsg>, DeError> = match wakeup {
+                Wakeup::Received(res) => res,
+                Wakeup::Redump => {
+                    if let Err(e) = ss.send(&addr_dump_request(family)).await {
+                        yield Err(map_tx_error(e));
+                    }
+                    continue;
+                }
+            };
             match res {
                 Ok(msgs) =>
                     for msg in msgs {
-                        if let Some(event) = translate_addr_message(&msg) {
+                        if is_dump_done(&msg) {
+                            yield Ok(NetworkEvent::EnumerationComplete);
+                        } else if let Some(event) =
+                            translate_addr_message(&msg)
+                        {
                             yield Ok(event);
                         }
                     },
+                Err(e) if is_overflow(&e) => {
+                    yield Ok(NetworkEvent::Overflow);
+                    redump.send_replace(());
+                }
                 Err(e) => yield Err(map_rx_error(e))
             }
         }
@@ -204,13 +211,40 @@ both IPv4 and IPv6. In all cases, the [`NetworkEvent::NewLink`] event
 describing an interface, will be generated before that interface's
 [`NetworkEvent::NewAddr`] event or events.
 
+Interfaces and addresses come from separate netlink sockets, so their
+reports can arrive in either order; an address on an interface not
+yet announced is held back until the interface is (or dropped, if the
+interface is deleted first). Likewise, a [`NetworkEvent::DelAddr`] is
+only generated for an address which was announced.
+
 All interfaces and addresses already present when `get_interfaces_async`
-is called, will be immediately announced as if newly-added.
+is called, will be immediately announced as if newly-added. Once they
+all have been, a single [`NetworkEvent::EnumerationComplete`] is
+generated; everything after that is a live change. (If the kernel
+fails to complete its initial listing, the marker never arrives.)
+
+Under a burst of changes (e.g. a VPN reconnecting), the kernel can
+run out of room to queue them -- the stream asks for a generous
+receive buffer, but the system may limit it (see `net.core.rmem_max`
+in socket(7)). If that happens, some events are lost, and the stream
+generates [`NetworkEvent::Overflow`] then asks the kernel for a fresh
+listing, which is announced just as the initial one is: every
+interface and address as newly-added, then
+[`NetworkEvent::EnumerationComplete`]. Consumers should discard what
+they knew on seeing `Overflow`.
 
 If addresses are deactivated or interfaces disappear -- such as when a USB
 network adaptor is unplugged -- [`NetworkEvent::DelLink`]
 or [`NetworkEvent::DelAddr`] events will be generated.
 
+Netlink reports an interface again whenever anything about it
+changes. Only the first report is passed on as
+[`NetworkEvent::NewLink`]; after that, a change in the interface's
+flags -- such as when a cable is plugged in, or the interface is
+brought up -- generates a [`NetworkEvent::LinkChanged`], and other
+changes are not reported. (Once an interface has been deleted, its
+next appearance is a [`NetworkEvent::NewLink`] again.)
+
 The stream continues to wait for future events, i.e. the `while` loop
 in the examples is an *infinite* loop. In normal use, an asynchronous
 application would use `tokio::select!` or similar to wait on both
@@ -248,11 +282,16 @@ let mut s = get_interfaces_async()?;
 
 while let Some(e) = s.next().await {
     match e {
-        Ok(NetworkEvent::NewLink(_i, name, flags)) => {
+        Ok(NetworkEvent::NewLink(_i, name, flags, _info)) => {
             if flags.contains(Flags::RUNNING | Flags::UP | Flags::MULTICAST) {
                 println!("New multicast-capable interface: {}", name);
             }
         },
+        Ok(NetworkEvent::LinkChanged(i, flags)) => {
+            if flags.contains(Flags::RUNNING | Flags::UP | Flags::MULTICAST) {
+                println!("Interface {:?} now multicast-capable", i);
+            }
+        },
         _ => {},
     }
 #   break;
@@ -285,11 +324,11 @@ pub fn get_interfaces_async(
 }
 
 /// The type of `NlSocketHandle::connect`
-type HandleFn =
+pub(crate) type HandleFn =
     fn(NlFamily, Option<u32>, &[u32]) -> Result<NlSocketHandle, Error>;
 
 /// The type of `NlSocket::new::<NlSocketHandle>`
-type SocketFn = fn(NlSocketHandle) -> Result<NlSocket, Error>;
+pub(crate) type SocketFn = fn(NlSocketHandle) -> Result<NlSocket, Error>;
 
 /// Like `NlSocketHandle::send::<Nlmsghdr<Rtm, Ifinfomsg>>`
 type SendLinkMessageFn =
@@ -332,23 +371,8 @@ fn create_link_socket(
     socket_fn: SocketFn,
 ) -> Result<NlSocket, Error> {
     let mut s = handle_fn(NlFamily::Route, None, &[1])?; // =RTNLGRP_LINK
-    let ifinfomsg = Ifinfomsg::new(
-        RtAddrFamily::Unspecified,
-        Arphrd::Ether,
-        0,
-        IffFlags::empty(),
-        IffFlags::empty(),
-        RtBuffer::new(),
-    );
-    let nl_link_header = Nlmsghdr::new(
-        None,
-        Rtm::Getlink,
-        NlmFFlags::new(&[NlmF::Request, NlmF::Match]),
-        None,
-        None,
-        NlPayload::Payload(ifinfomsg),
-    );
-    send_link_fn(&mut s, nl_link_header).map_err(map_tx_error)?;
+    enlarge_receive_buffer(&s);
+    send_link_fn(&mut s, link_dump_request()).map_err(map_tx_error)?;
     socket_fn(s)
 }
 
@@ -358,23 +382,9 @@ fn create_ipv4addr_socket(
     socket_fn: SocketFn,
 ) -> Result<NlSocket, Error> {
     let mut s = handle_fn(NlFamily::Route, None, &[5])?; // =RTNLGRP_IPV4_IFADDR
-    let ifaddrmsg = Ifaddrmsg {
-        ifa_family: RtAddrFamily::Inet,
-        ifa_prefixlen: 0,
-        ifa_flags: IfaFFlags::empty(),
-        ifa_scope: 0,
-        ifa_index: 0,
-        rtattrs: RtBuffer::new(),
-    };
-    let nl_addr4_header = Nlmsghdr::new(
-        None,
-        Rtm::Getaddr,
-        NlmFFlags::new(&[NlmF::Request, NlmF::Root]),
-        None,
-        None,
-        NlPayload::Payload(ifaddrmsg),
-    );
-    send_addr_fn(&mut s, nl_addr4_header).map_err(map_tx_error)?;
+    enlarge_receive_buffer(&s);
+    send_addr_fn(&mut s, addr_dump_request(RtAddrFamily::Inet))
+        .map_err(map_tx_error)?;
     socket_fn(s)
 }
 
@@ -384,434 +394,166 @@ fn create_ipv6addr_socket(
     socket_fn: SocketFn,
 ) -> Result<NlSocket, Error> {
     let mut s = handle_fn(NlFamily::Route, None, &[9])?; // =RTNLGRP_IPV6_IFADDR
-    let ifaddrmsg = Ifaddrmsg {
-        ifa_family: RtAddrFamily::Inet6,
-        ifa_prefixlen: 0,
-        ifa_flags: IfaFFlags::empty(),
-        ifa_scope: 0,
-        ifa_index: 0,
-        rtattrs: RtBuffer::new(),
-    };
-    let nl_addr6_header = Nlmsghdr::new(
-        None,
-        Rtm::Getaddr,
-        NlmFFlags::new(&[NlmF::Request, NlmF::Root]),
-        None,
-        None,
-        NlPayload::Payload(ifaddrmsg),
-    );
-    send_addr_fn(&mut s, nl_addr6_header).map_err(map_tx_error)?;
+    enlarge_receive_buffer(&s);
+    send_addr_fn(&mut s, addr_dump_request(RtAddrFamily::Inet6))
+        .map_err(map_tx_error)?;
     socket_fn(s)
 }
 
-fn get_interfaces_async_inner2(
-    link_socket: NlSocket,
-    addr4_socket: NlSocket,
-    addr6_socket: NlSocket,
-) -> impl Stream<Item = Result<NetworkEvent, Error>> {
-    stream::select(
-        Box::pin(get_links(link_socket)),
-        stream::select(
-            Box::pin(get_addrs(addr4_socket)),
-            Box::pin(get_addrs(addr6_socket)),
-        ),
-    )
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use futures_util::StreamExt;
-    use neli::rtnl::Rtattr;
-    use neli::ToBytes;
-    use std::os::unix::io::AsRawFd;
-    use std::os::unix::io::FromRawFd;
-    use std::os::unix::io::IntoRawFd;
-
-    fn make_index(i: u32) -> InterfaceIndex {
-        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
-    }
-
-    #[test]
-    fn parse_4byte_addr() {
-        let input = [192u8, 168u8, 0u8, 200u8];
-
-        let result = ip(&input);
-
-        assert_eq!(result, "192.168.0.200".parse().ok());
-    }
-
-    #[test]
-    fn parse_16byte_addr() {
-        let input = [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
-
-        let result = ip(&input);
-
-        assert_eq!(result, "::1".parse().ok());
-    }
-
-    #[test]
-    fn no_parse_5byte_addr() {
-        let input = [2u8, 3, 4, 5, 6];
-
-        let result = ip(&input);
-
-        assert_eq!(result, None);
-    }
-
-    #[test]
-    fn test_rx_io_error_mapped() {
-        let err = map_rx_error(DeError::Wrapped(WrappedError::IOError(
-            std::io::Error::from(ErrorKind::UnexpectedEof),
-        )));
-        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
-    }
-
-    #[test]
-    fn test_rx_io_error_not_mapped() {
-        let err = map_rx_error(DeError::BufferNotParsed);
-        assert_eq!(err.kind(), std::io::ErrorKind::Other);
-    }
-
-    #[test]
-    fn test_tx_io_error_mapped() {
-        let err = map_tx_error(SerError::Wrapped(WrappedError::IOError(
-            std::io::Error::from(ErrorKind::UnexpectedEof),
-        )));
-        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
-    }
-
-    #[test]
-    fn test_tx_io_error_not_mapped() {
-        let err = map_tx_error(SerError::BufferNotFilled);
-        assert_eq!(err.kind(), std::io::ErrorKind::Other);
-    }
-
-    #[test]
-    fn test_map_up() {
-        assert_eq!(map_flags(&IffFlags::new(&[Iff::Up])), Flags::UP);
-    }
-
-    #[test]
-    fn test_map_running() {
-        assert_eq!(map_flags(&IffFlags::new(&[Iff::Running])), Flags::RUNNING);
-    }
-
-    #[test]
-    fn test_map_loopback() {
-        assert_eq!(
-            map_flags(&IffFlags::new(&[Iff::Loopback])),
-            Flags::LOOPBACK
-        );
-    }
-
-    #[test]
-    fn test_map_pointtopoint() {
-        assert_eq!(
-            map_flags(&IffFlags::new(&[Iff::Pointopoint])),
-            Flags::POINTTOPOINT
-        );
-    }
-
-    #[test]
-    fn test_map_broadcast() {
-        assert_eq!(
-            map_flags(&IffFlags::new(&[Iff::Broadcast])),
-            Flags::BROADCAST
-        );
-    }
-
-    #[test]
-    fn test_map_multicast() {
-        assert_eq!(
-            map_flags(&IffFlags::new(&[Iff::Multicast])),
-            Flags::MULTICAST
-        );
-    }
-
-    #[test]
-    fn test_map_several() {
-        assert_eq!(
-            map_flags(&IffFlags::new(&[
-                Iff::Up,
-                Iff::Running,
-                Iff::Multicast
-            ])),
-            Flags::UP | Flags::RUNNING | Flags::MULTICAST
-        );
-    }
-
-    #[test]
-    fn test_link_message_no_payload() {
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Getlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Empty,
-        );
-
-        assert!(translate_link_message(&msg).is_none());
-    }
-
-    #[test]
-    fn test_link_message_no_name() {
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Newlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifinfomsg::new(
-                RtAddrFamily::Inet,
-                Arphrd::Ether,
-                0,
-                IffFlags::empty(),
-                IffFlags::empty(),
-                RtBuffer::new(),
-            )),
-        );
-
-        assert!(translate_link_message(&msg).is_none());
-    }
-
-    #[test]
-    fn test_link_message_no_type() {
-        let mut buf = RtBuffer::new();
-        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
-
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Getlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifinfomsg::new(
-                RtAddrFamily::Inet,
-                Arphrd::Ether,
-                0,
-                IffFlags::empty(),
-                IffFlags::empty(),
-                buf,
-            )),
-        );
-
-        assert!(translate_link_message(&msg).is_none());
-    }
-
-    #[test]
-    fn test_link_message_new() {
-        let mut buf = RtBuffer::new();
-        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
-
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Newlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifinfomsg::new(
-                RtAddrFamily::Inet,
-                Arphrd::Ether,
-                3,
-                IffFlags::empty(),
-                IffFlags::empty(),
-                buf,
-            )),
-        );
-
-        let event = translate_link_message(&msg);
-        assert!(event.is_some());
-        assert_eq!(
-            event.unwrap(),
-            NetworkEvent::NewLink(
-                make_index(3),
-                "eth0".to_string(),
-                Flags::default()
-            )
-        );
-    }
-
-    #[test]
-    fn test_link_message_del() {
-        let mut buf = RtBuffer::new();
-        buf.push(Rtattr::new(None, Ifla::Ifname, "eth1".to_string()).unwrap());
-
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Dellink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifinfomsg::new(
-                RtAddrFamily::Inet,
-                Arphrd::Ether,
-                2,
-                IffFlags::empty(),
-                IffFlags::empty(),
-                buf,
-            )),
-        );
-
-        let event = translate_link_message(&msg);
-        assert!(event.is_some());
-        assert_eq!(event.unwrap(), NetworkEvent::DelLink(make_index(2)));
-    }
-
-    #[test]
-    fn test_addr_message_no_payload() {
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Getlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Empty,
-        );
-
-        assert!(translate_addr_message(&msg).is_none());
-    }
-
-    #[test]
-    fn test_addr_message_no_addr() {
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Newlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifaddrmsg {
-                ifa_family: RtAddrFamily::Inet,
-                ifa_prefixlen: 0,
-                ifa_flags: IfaFFlags::empty(),
-                ifa_scope: 0,
-                ifa_index: 2,
-                rtattrs: RtBuffer::new(),
-            }),
-        );
-
-        assert!(translate_addr_message(&msg).is_none());
-    }
-
-    #[test]
-    fn test_addr_message_bad_addr() {
-        let mut buf = RtBuffer::new();
-        buf.push(Rtattr::new(None, Ifa::Address, 65535u16).unwrap());
-
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Newlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifaddrmsg {
-                ifa_family: RtAddrFamily::Inet,
-                ifa_prefixlen: 0,
-                ifa_flags: IfaFFlags::empty(),
-                ifa_scope: 0,
-                ifa_index: 2,
-                rtattrs: buf,
-            }),
-        );
-
-        assert!(translate_addr_message(&msg).is_none());
-    }
-
-    #[test]
-    fn test_addr_message_bad_type() {
-        let mut buf = RtBuffer::new();
-        buf.push(
-            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
-        );
+/// The sockets of one stream
+const SOCKETS: usize = 3;
 
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Newlink,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifaddrmsg {
-                ifa_family: RtAddrFamily::Inet,
-                ifa_prefixlen: 0,
-                ifa_flags: IfaFFlags::empty(),
-                ifa_scope: 0,
-                ifa_index: 2,
-                rtattrs: buf,
-            }),
-        );
+/// Counts off the ends of the sockets' dumps, passing on a single
+/// EnumerationComplete once all of them have finished
+struct Enumeration {
+    pending: usize,
+}
 
-        assert!(translate_addr_message(&msg).is_none());
+impl Enumeration {
+    fn filter(
+        &mut self,
+        e: Result<NetworkEvent, Error>,
+    ) -> Option<Result<NetworkEvent, Error>> {
+        match e {
+            Ok(NetworkEvent::EnumerationComplete) => {
+                // A stray one, once complete, is ignored
+                if self.pending == 0 {
+                    return None;
+                }
+                self.pending -= 1;
+                (self.pending == 0).then_some(e)
+            }
+            Ok(NetworkEvent::Overflow) => {
+                // All the sockets are about to dump again
+                self.pending = SOCKETS;
+                Some(e)
+            }
+            e => Some(e),
+        }
     }
+}
 
-    #[test]
-    fn test_addr_message_new() {
-        let mut buf = RtBuffer::new();
-        buf.push(
-            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
-        );
+/// How many addresses of not-yet-announced interfaces are held back
+const MAX_HELD: usize = 256;
+
+/// Holds back addresses until their interface has been announced
+///
+/// The link and address sockets' dumps race one another, so otherwise
+/// an address could arrive before its interface does. (Live changes
+/// are less of a problem, as the kernel announces an interface before
+/// configuring it.) If more than `MAX_HELD` addresses are waiting, the
+/// oldest is dropped; so are the addresses of an interface which is
+/// deleted before it's announced.
+#[derive(Default)]
+struct LinkOrder {
+    links: BTreeSet<InterfaceIndex>,
+    held: VecDeque<NetworkEvent>,
+}
 
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Newaddr,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifaddrmsg {
-                ifa_family: RtAddrFamily::Inet,
-                ifa_prefixlen: 24,
-                ifa_flags: IfaFFlags::empty(),
-                ifa_scope: 0,
-                ifa_index: 2,
-                rtattrs: buf,
-            }),
-        );
+/// Is `e` an address event for the interface `ix`?
+fn held_for(e: &NetworkEvent, ix: &InterfaceIndex) -> bool {
+    matches!(e, NetworkEvent::NewAddr(i, ..) if i == ix)
+}
 
-        let event = translate_addr_message(&msg);
-        assert!(event.is_some());
-        assert_eq!(
-            event.unwrap(),
-            NetworkEvent::NewAddr(
-                make_index(2),
-                ip(&[255, 255, 0, 0]).unwrap(),
-                24
-            )
-        );
+impl LinkOrder {
+    fn filter(
+        &mut self,
+        e: Result<NetworkEvent, Error>,
+    ) -> Vec<Result<NetworkEvent, Error>> {
+        let event = match e {
+            Ok(event) => event,
+            Err(e) => return vec![Err(e)],
+        };
+        match &event {
+            NetworkEvent::NewLink(ix, ..) => {
+                self.links.insert(*ix);
+                let (released, held): (Vec<_>, Vec<_>) =
+                    core::mem::take(&mut self.held)
+                        .into_iter()
+                        .partition(|e| held_for(e, ix));
+                self.held = held.into();
+                core::iter::once(event).chain(released).map(Ok).collect()
+            }
+            NetworkEvent::DelLink(ix) => {
+                self.links.remove(ix);
+                self.held.retain(|e| !held_for(e, ix));
+                vec![Ok(event)]
+            }
+            NetworkEvent::NewAddr(ix, ..) if !self.links.contains(ix) => {
+                if self.held.len() == MAX_HELD {
+                    self.held.pop_front();
+                }
+                self.held.push_back(event);
+                Vec::new()
+            }
+            NetworkEvent::DelAddr(ix, addr, prefix)
+                if !self.links.contains(ix) =>
+            {
+                // Nothing about this address has been passed on: just
+                // forget it
+                self.held.retain(|e| {
+                    !matches!(e, NetworkEvent::NewAddr(i, a, p, _)
+                        if i == ix && a == addr && p == prefix)
+                });
+                Vec::new()
+            }
+            NetworkEvent::Overflow => {
+                // Everything is about to be announced afresh
+                self.links.clear();
+                self.held.clear();
+                vec![Ok(event)]
+            }
+            _ => vec![Ok(event)],
+        }
     }
+}
 
-    #[test]
-    fn test_addr_message_del() {
-        let mut buf = RtBuffer::new();
-        buf.push(
-            Rtattr::new(None, Ifa::Address, 0xFFFF_0000u32.to_be()).unwrap(),
-        );
+fn get_interfaces_async_inner2(
+    link_socket: NlSocket,
+    addr4_socket: NlSocket,
+    addr6_socket: NlSocket,
+) -> impl Stream<Item = Result<NetworkEvent, Error>> {
+    let redump = Arc::new(watch::channel(()).0);
+    let mut enumeration = Enumeration { pending: SOCKETS };
+    let mut order = LinkOrder::default();
+    stream::select(
+        Box::pin(get_links(link_socket, redump.clone())),
+        stream::select(
+            Box::pin(get_addrs(
+                addr4_socket,
+                RtAddrFamily::Inet,
+                redump.clone(),
+            )),
+            Box::pin(get_addrs(addr6_socket, RtAddrFamily::Inet6, redump)),
+        ),
+    )
+    .filter_map(move |e| core::future::ready(enumeration.filter(e)))
+    .flat_map(move |e| stream::iter(order.filter(e)))
+}
 
-        let msg = Nlmsghdr::new(
-            None,
-            Rtm::Deladdr,
-            NlmFFlags::empty(),
-            None,
-            None,
-            NlPayload::Payload(Ifaddrmsg {
-                ifa_family: RtAddrFamily::Inet,
-                ifa_prefixlen: 24,
-                ifa_flags: IfaFFlags::empty(),
-                ifa_scope: 0,
-                ifa_index: 2,
-                rtattrs: buf,
-            }),
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux_messages::ip;
+    use crate::linux_messages::tests::{parse_addr_message, IPV6_NEWADDR};
+    use crate::network_event::{AddrInfo, Flags, LinkInfo, LinkType};
+    use futures_util::StreamExt;
+    use neli::consts::{
+        nl::{NlmF, NlmFFlags, Nlmsg},
+        rtnl::{Arphrd, Ifa, IfaFFlags, Iff, IffFlags, Ifla},
+    };
+    use neli::nl::NlPayload;
+    use neli::rtnl::Rtattr;
+    use neli::types::RtBuffer;
+    use neli::FromBytes;
+    use neli::ToBytes;
+    use std::io::ErrorKind;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::io::IntoRawFd;
 
-        let event = translate_addr_message(&msg);
-        assert!(event.is_some());
-        assert_eq!(
-            event.unwrap(),
-            NetworkEvent::DelAddr(
-                make_index(2),
-                ip(&[255, 255, 0, 0]).unwrap(),
-                24
-            )
-        );
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
     }
 
     #[tokio::test]
@@ -842,7 +584,7 @@ mod tests {
         )
         .unwrap();
 
-        let s = Box::pin(get_links(nlsocket)).next().await;
+        let s = Box::pin(get_links(nlsocket, no_redump())).next().await;
         assert!(s.is_some());
         let result = s.unwrap();
         assert!(result.is_err());
@@ -932,7 +674,7 @@ mod tests {
         )
         .unwrap();
 
-        let s = Box::pin(get_links(nlsocket)).next().await;
+        let s = Box::pin(get_links(nlsocket, no_redump())).next().await;
         assert!(s.is_some());
         let result = s.unwrap();
         assert!(result.is_ok());
@@ -988,7 +730,7 @@ mod tests {
         )
         .unwrap();
 
-        let s = Box::pin(get_links(nlsocket)).next().await;
+        let s = Box::pin(get_links(nlsocket, no_redump())).next().await;
 
         assert!(s.is_some());
         let result = s.unwrap();
@@ -996,6 +738,85 @@ mod tests {
         assert_eq!(result.unwrap(), NetworkEvent::DelLink(make_index(2)));
     }
 
+    fn send_link_message(
+        fd: &impl AsRawFd,
+        nl_type: Rtm,
+        index: i32,
+        flags: &[Iff],
+    ) {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
+        let msg = Nlmsghdr::new(
+            None,
+            nl_type,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Inet,
+                Arphrd::Ether,
+                index,
+                IffFlags::new(flags),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+        let mut v = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut v).unwrap();
+
+        nix::sys::socket::sendto(
+            fd.as_raw_fd(),
+            &v.into_inner(),
+            &(),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn get_links_changed() {
+        let (infd, nlsocket) = fake_socket();
+
+        // Appears, comes up, is reported again unchanged, goes down,
+        // is deleted, and reappears
+        send_link_message(&infd, Rtm::Newlink, 2, &[Iff::Multicast]);
+        send_link_message(&infd, Rtm::Newlink, 2, &[Iff::Up, Iff::Multicast]);
+        send_link_message(&infd, Rtm::Newlink, 2, &[Iff::Up, Iff::Multicast]);
+        send_link_message(&infd, Rtm::Newlink, 2, &[Iff::Multicast]);
+        send_link_message(&infd, Rtm::Dellink, 2, &[Iff::Multicast]);
+        send_link_message(&infd, Rtm::Newlink, 2, &[Iff::Multicast]);
+
+        let events = Box::pin(get_links(nlsocket, no_redump()))
+            .take(5)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        let eth0 = NetworkEvent::NewLink(
+            make_index(2),
+            "eth0".to_string(),
+            Flags::MULTICAST,
+            LinkInfo {
+                mtu: None,
+                link_type: LinkType::Ethernet,
+                ..LinkInfo::default()
+            },
+        );
+        assert_eq!(
+            events,
+            vec![
+                eth0.clone(),
+                NetworkEvent::LinkChanged(
+                    make_index(2),
+                    Flags::UP | Flags::MULTICAST
+                ),
+                NetworkEvent::LinkChanged(make_index(2), Flags::MULTICAST),
+                NetworkEvent::DelLink(make_index(2)),
+                eth0,
+            ]
+        );
+    }
+
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn get_addrs_bad_message() {
@@ -1024,7 +845,9 @@ mod tests {
         )
         .unwrap();
 
-        let s = Box::pin(get_addrs(nlsocket)).next().await;
+        let s = Box::pin(get_addrs(nlsocket, RtAddrFamily::Inet, no_redump()))
+            .next()
+            .await;
         assert!(s.is_some());
         let result = s.unwrap();
         assert!(result.is_err());
@@ -1115,7 +938,9 @@ mod tests {
         )
         .unwrap();
 
-        let s = Box::pin(get_addrs(nlsocket)).next().await;
+        let s = Box::pin(get_addrs(nlsocket, RtAddrFamily::Inet, no_redump()))
+            .next()
+            .await;
         assert!(s.is_some());
         let result = s.unwrap();
         assert!(result.is_ok());
@@ -1173,7 +998,9 @@ mod tests {
         )
         .unwrap();
 
-        let s = Box::pin(get_addrs(nlsocket)).next().await;
+        let s = Box::pin(get_addrs(nlsocket, RtAddrFamily::Inet, no_redump()))
+            .next()
+            .await;
 
         assert!(s.is_some());
         let event = s.unwrap();
@@ -1273,6 +1100,60 @@ mod tests {
         assert!(s.is_err());
     }
 
+    thread_local! {
+        static CALLS: std::cell::RefCell<Vec<&'static str>> =
+            const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    fn recording_handle_fn(
+        family: NlFamily,
+        pid: Option<u32>,
+        groups: &[u32],
+    ) -> Result<NlSocketHandle, Error> {
+        assert!(!groups.is_empty());
+        CALLS.with(|c| c.borrow_mut().push("subscribe"));
+        NlSocketHandle::connect(family, pid, groups)
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn sockets_subscribe_before_dumping() {
+        // Otherwise a change between the dump and the subscription
+        // would be lost
+        CALLS.with(|c| c.borrow_mut().clear());
+        let _ = create_link_socket(
+            recording_handle_fn,
+            |_, _| {
+                CALLS.with(|c| c.borrow_mut().push("dump"));
+                Ok(())
+            },
+            NlSocket::new::<NlSocketHandle>,
+        )
+        .unwrap();
+        for create in [create_ipv4addr_socket, create_ipv6addr_socket] {
+            let _ = create(
+                recording_handle_fn,
+                |_, _| {
+                    CALLS.with(|c| c.borrow_mut().push("dump"));
+                    Ok(())
+                },
+                NlSocket::new::<NlSocketHandle>,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            CALLS.with(|c| c.borrow().clone()),
+            vec![
+                "subscribe",
+                "dump",
+                "subscribe",
+                "dump",
+                "subscribe",
+                "dump"
+            ]
+        );
+    }
+
     #[test]
     fn get_interfaces_passes_on_link_error() {
         let s = get_interfaces_async_inner(
@@ -1323,9 +1204,435 @@ mod tests {
         assert!(s.is_err());
     }
 
+    fn no_redump() -> RedumpSender {
+        Arc::new(watch::channel(()).0)
+    }
+
+    fn fake_socket() -> (std::os::fd::OwnedFd, NlSocket) {
+        let (infd, outfd) = nix::sys::socket::socketpair(
+            nix::sys::socket::AddressFamily::Unix,
+            nix::sys::socket::SockType::Datagram,
+            None,
+            nix::sys::socket::SockFlag::empty(),
+        )
+        .unwrap();
+
+        let nlsocket = NlSocket::new({
+            let outfd = outfd.into_raw_fd();
+            unsafe {
+                // SAFETY: nlsocket becomes only owner of outfd
+                NlSocketHandle::from_raw_fd(outfd)
+            }
+        })
+        .unwrap();
+        (infd, nlsocket)
+    }
+
+    fn send_dump_done(fd: &impl AsRawFd) {
+        let msg: Nlmsghdr<Rtm, Ifinfomsg> = Nlmsghdr::new(
+            None,
+            Rtm::from(u16::from(Nlmsg::Done)),
+            NlmFFlags::new(&[NlmF::Multi]),
+            None,
+            None,
+            NlPayload::Empty,
+        );
+        let mut v = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut v).unwrap();
+
+        nix::sys::socket::sendto(
+            fd.as_raw_fd(),
+            &v.into_inner(),
+            &(),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn enumeration_complete_after_all_dumps() {
+        let (link_fd, link_socket) = fake_socket();
+        let (addr4_fd, addr4_socket) = fake_socket();
+        let (addr6_fd, addr6_socket) = fake_socket();
+
+        send_link_message(&link_fd, Rtm::Newlink, 2, &[Iff::Up]);
+        send_dump_done(&link_fd);
+        send_dump_done(&addr4_fd);
+        nix::sys::socket::sendto(
+            addr6_fd.as_raw_fd(),
+            &IPV6_NEWADDR,
+            &(),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+        send_dump_done(&addr6_fd);
+
+        let mut s = get_interfaces_async_inner2(
+            link_socket,
+            addr4_socket,
+            addr6_socket,
+        );
+
+        let mut initial = Vec::new();
+        for _ in 0..2 {
+            initial.push(s.next().await.unwrap().unwrap());
+        }
+        assert!(initial.iter().any(|e| matches!(
+            e,
+            NetworkEvent::NewLink(ix, _, _, _) if *ix == make_index(2)
+        )));
+        assert!(initial.iter().any(|e| matches!(
+            e,
+            NetworkEvent::NewAddr(ix, _, 64, _) if *ix == make_index(2)
+        )));
+        assert_eq!(
+            s.next().await.unwrap().unwrap(),
+            NetworkEvent::EnumerationComplete
+        );
+
+        // Now a live change
+        send_link_message(&link_fd, Rtm::Dellink, 2, &[]);
+        assert_eq!(
+            s.next().await.unwrap().unwrap(),
+            NetworkEvent::DelLink(make_index(2))
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn get_links_reports_dump_done() {
+        let (infd, nlsocket) = fake_socket();
+        send_dump_done(&infd);
+
+        let s = Box::pin(get_links(nlsocket, no_redump())).next().await;
+        assert_eq!(s.unwrap().unwrap(), NetworkEvent::EnumerationComplete);
+    }
+
+    #[test]
+    fn enobufs_is_overflow() {
+        let err = |errno| {
+            DeError::Wrapped(WrappedError::IOError(
+                std::io::Error::from_raw_os_error(errno),
+            ))
+        };
+        assert!(is_overflow(&err(libc::ENOBUFS)));
+        assert!(!is_overflow(&err(libc::EINTR)));
+        assert!(!is_overflow(&DeError::UnexpectedEOB));
+    }
+
+    #[test]
+    fn enumeration_restarts_after_overflow() {
+        let mut e = Enumeration { pending: SOCKETS };
+        let mut script = |event| e.filter(Ok(event)).map(Result::unwrap);
+        let done = NetworkEvent::EnumerationComplete;
+        let del = NetworkEvent::DelLink(make_index(2));
+
+        assert_eq!(script(done.clone()), None);
+        assert_eq!(script(done.clone()), None);
+        assert_eq!(script(done.clone()), Some(done.clone()));
+        assert_eq!(script(del.clone()), Some(del.clone()));
+        assert_eq!(
+            script(NetworkEvent::Overflow),
+            Some(NetworkEvent::Overflow)
+        );
+        assert_eq!(script(done.clone()), None);
+        assert_eq!(script(done.clone()), None);
+        assert_eq!(script(done.clone()), Some(done.clone()));
+        assert_eq!(script(done), None);
+    }
+
+    fn new_addr(ix: u32, last: u8) -> NetworkEvent {
+        NetworkEvent::NewAddr(
+            make_index(ix),
+            ip(&[192, 168, 1, last]).unwrap(),
+            24,
+            AddrInfo::default(),
+        )
+    }
+
+    fn new_link(ix: u32) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(ix),
+            format!("eth{ix}"),
+            Flags::UP,
+            LinkInfo::default(),
+        )
+    }
+
+    fn order_script(
+        order: &mut LinkOrder,
+        event: NetworkEvent,
+    ) -> Vec<NetworkEvent> {
+        order
+            .filter(Ok(event))
+            .into_iter()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn addresses_held_until_link() {
+        let mut order = LinkOrder::default();
+        assert_eq!(order_script(&mut order, new_addr(2, 1)), vec![]);
+        assert_eq!(order_script(&mut order, new_addr(3, 2)), vec![]);
+        assert_eq!(order_script(&mut order, new_addr(2, 3)), vec![]);
+        assert_eq!(
+            order_script(&mut order, new_link(2)),
+            vec![new_link(2), new_addr(2, 1), new_addr(2, 3)]
+        );
+        // Once the link is known, addresses go straight through
+        assert_eq!(
+            order_script(&mut order, new_addr(2, 4)),
+            vec![new_addr(2, 4)]
+        );
+        assert_eq!(
+            order_script(&mut order, new_link(3)),
+            vec![new_link(3), new_addr(3, 2)]
+        );
+    }
+
+    #[test]
+    fn held_addresses_dropped_with_link() {
+        let mut order = LinkOrder::default();
+        let del = NetworkEvent::DelLink(make_index(2));
+        assert_eq!(order_script(&mut order, new_addr(2, 1)), vec![]);
+        assert_eq!(order_script(&mut order, del.clone()), vec![del]);
+        assert_eq!(order_script(&mut order, new_link(2)), vec![new_link(2)]);
+    }
+
+    #[test]
+    fn held_address_cancelled() {
+        let mut order = LinkOrder::default();
+        let del = NetworkEvent::DelAddr(
+            make_index(2),
+            ip(&[192, 168, 1, 1]).unwrap(),
+            24,
+        );
+        assert_eq!(order_script(&mut order, new_addr(2, 1)), vec![]);
+        assert_eq!(order_script(&mut order, new_addr(2, 2)), vec![]);
+        assert_eq!(order_script(&mut order, del.clone()), vec![]);
+        assert_eq!(
+            order_script(&mut order, new_link(2)),
+            vec![new_link(2), new_addr(2, 2)]
+        );
+        // But, for a known link, deletions are passed on
+        assert_eq!(order_script(&mut order, del.clone()), vec![del]);
+    }
+
+    #[test]
+    fn held_addresses_bounded() {
+        let mut order = LinkOrder::default();
+        for i in 0..=MAX_HELD {
+            let i = u8::try_from(i % 256).unwrap();
+            assert_eq!(order_script(&mut order, new_addr(2, i)), vec![]);
+        }
+        let released = order_script(&mut order, new_link(2));
+        assert_eq!(released.len(), MAX_HELD + 1);
+        // The oldest went
+        assert_eq!(released[1], new_addr(2, 1));
+    }
+
+    #[test]
+    fn order_reset_by_overflow() {
+        let mut order = LinkOrder::default();
+        assert_eq!(order_script(&mut order, new_link(2)), vec![new_link(2)]);
+        assert_eq!(order_script(&mut order, new_addr(3, 1)), vec![]);
+        assert_eq!(
+            order_script(&mut order, NetworkEvent::Overflow),
+            vec![NetworkEvent::Overflow]
+        );
+        // Held back again until re-announced
+        assert_eq!(order_script(&mut order, new_addr(2, 1)), vec![]);
+        assert_eq!(
+            order_script(&mut order, new_link(2)),
+            vec![new_link(2), new_addr(2, 1)]
+        );
+        assert_eq!(order_script(&mut order, new_link(3)), vec![new_link(3)]);
+    }
+
+    #[test]
+    fn order_passes_errors() {
+        let mut order = LinkOrder::default();
+        let out = order.filter(Err(ErrorKind::Other.into()));
+        assert_eq!(out.len(), 1);
+        assert!(out[0].is_err());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn address_before_link() {
+        let (link_fd, link_socket) = fake_socket();
+        let (addr4_fd, addr4_socket) = fake_socket();
+        let (addr6_fd, addr6_socket) = fake_socket();
+
+        // The address dumps finish before the link dump starts
+        send_dump_done(&addr4_fd);
+        nix::sys::socket::sendto(
+            addr6_fd.as_raw_fd(),
+            &IPV6_NEWADDR,
+            &(),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+        send_dump_done(&addr6_fd);
+
+        let mut s = get_interfaces_async_inner2(
+            link_socket,
+            addr4_socket,
+            addr6_socket,
+        );
+
+        tokio::select! {
+            e = s.next() => panic!("unexpected {e:?}"),
+            () = tokio::time::sleep(std::time::Duration::from_millis(50)) => {}
+        }
+
+        send_link_message(&link_fd, Rtm::Newlink, 2, &[Iff::Up]);
+        send_dump_done(&link_fd);
+
+        assert!(matches!(
+            s.next().await,
+            Some(Ok(NetworkEvent::NewLink(ix, ..))) if ix == make_index(2)
+        ));
+        assert!(matches!(
+            s.next().await,
+            Some(Ok(NetworkEvent::NewAddr(ix, _, 64, _))) if ix == make_index(2)
+        ));
+        assert_eq!(
+            s.next().await.unwrap().unwrap(),
+            NetworkEvent::EnumerationComplete
+        );
+    }
+
+    /// Wait for `s` to send something on its socket, and return it
+    async fn expect_request(
+        s: &mut (impl Stream<Item = Result<NetworkEvent, Error>> + Unpin),
+        peer: &tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+    ) -> Vec<u8> {
+        tokio::select! {
+            e = s.next() => panic!("unexpected {e:?}"),
+            guard = peer.readable() => guard.unwrap().clear_ready(),
+        }
+        let mut buf = vec![0u8; 1024];
+        let n = nix::sys::socket::recv(
+            peer.as_raw_fd(),
+            &mut buf,
+            nix::sys::socket::MsgFlags::MSG_DONTWAIT,
+        )
+        .unwrap();
+        buf.truncate(n);
+        buf
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn get_links_redump() {
+        let (infd, nlsocket) = fake_socket();
+        let redump = no_redump();
+        let mut s = Box::pin(get_links(nlsocket, redump.clone()));
+
+        send_link_message(&infd, Rtm::Newlink, 2, &[Iff::Up]);
+        assert!(matches!(
+            s.next().await,
+            Some(Ok(NetworkEvent::NewLink(..)))
+        ));
+
+        redump.send_replace(());
+        let peer = tokio::io::unix::AsyncFd::new(infd).unwrap();
+        let request = expect_request(&mut s, &peer).await;
+        let request: Nlmsghdr<Rtm, Ifinfomsg> =
+            Nlmsghdr::from_bytes(&mut std::io::Cursor::new(&request[..]))
+                .unwrap();
+        assert_eq!(request.nl_type, Rtm::Getlink);
+
+        // The fresh dump announces eth0 anew
+        send_link_message(peer.get_ref(), Rtm::Newlink, 2, &[Iff::Up]);
+        assert!(matches!(
+            s.next().await,
+            Some(Ok(NetworkEvent::NewLink(..)))
+        ));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn get_addrs_redump() {
+        let (infd, nlsocket) = fake_socket();
+        let redump = no_redump();
+        let mut s =
+            Box::pin(get_addrs(nlsocket, RtAddrFamily::Inet6, redump.clone()));
+
+        redump.send_replace(());
+        let peer = tokio::io::unix::AsyncFd::new(infd).unwrap();
+        let request = expect_request(&mut s, &peer).await;
+        let request = parse_addr_message(&request);
+        assert_eq!(request.nl_type, Rtm::Getaddr);
+        assert_eq!(
+            request.nl_payload.get_payload().unwrap().ifa_family,
+            RtAddrFamily::Inet6
+        );
+    }
+
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn zzz_instantiate() {
         assert!(get_interfaces_async().is_ok());
     }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_enumeration_complete() {
+        let mut s = get_interfaces_async().unwrap();
+        let mut links = 0;
+        while let Some(e) = s.next().await {
+            match e.unwrap() {
+                NetworkEvent::NewLink(..) => links += 1,
+                NetworkEvent::EnumerationComplete => break,
+                _ => {}
+            }
+        }
+        // At least the loopback interface
+        assert!(links > 0);
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_enumeration_complete_without_tokio() {
+        async_io::block_on(async {
+            let mut s = get_interfaces_async().unwrap();
+            while let Some(e) = s.next().await {
+                if e.unwrap() == NetworkEvent::EnumerationComplete {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[cfg(feature = "sync")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_loopback_index_matches_snapshot() {
+        let is_loopback = |e: &NetworkEvent| match e {
+            NetworkEvent::NewLink(ix, _, flags, _)
+                if flags.contains(Flags::LOOPBACK) =>
+            {
+                Some(*ix)
+            }
+            _ => None,
+        };
+        let snapshot = crate::getifaddrs::get_interfaces()
+            .unwrap()
+            .find_map(|e| is_loopback(&e))
+            .unwrap();
+
+        let mut s = get_interfaces_async().unwrap();
+        while let Some(e) = s.next().await {
+            if let Some(ix) = is_loopback(&e.unwrap()) {
+                assert_eq!(ix, snapshot);
+                return;
+            }
+        }
+        panic!("no loopback link in netlink dump");
+    }
 }
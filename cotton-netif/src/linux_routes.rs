@@ -0,0 +1,438 @@
+use crate::linux_messages::{
+    enlarge_receive_buffer, ip, map_rx_error, map_tx_error,
+};
+use crate::linux_netlink::{
+    is_dump_done, is_overflow, HandleFn, NlSocket, SocketFn,
+};
+use crate::network_event::InterfaceIndex;
+use crate::route_event::{Route, RouteEvent};
+use async_stream::stream;
+use futures_util::stream::Stream;
+use neli::{
+    consts::{
+        nl::{NlmF, NlmFFlags},
+        rtnl::{
+            RtAddrFamily, RtScope, RtTable, Rta, Rtm, RtmF, RtmFFlags, Rtn,
+            Rtprot,
+        },
+        socket::NlFamily,
+    },
+    err::{DeError, SerError},
+    nl::{NlPayload, Nlmsghdr},
+    rtnl::Rtmsg,
+    socket::NlSocketHandle,
+    types::{NlBuffer, RtBuffer},
+};
+use std::io::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[allow(clippy::cast_sign_loss)]
+fn translate_route_message(msg: &Nlmsghdr<Rtm, Rtmsg>) -> Option<RouteEvent> {
+    let NlPayload::Payload(p) = &msg.nl_payload else {
+        return None;
+    };
+    let handle = p.rtattrs.get_attr_handle();
+
+    /* Only ordinary routes in the main table: not the kernel's local
+     * and broadcast routes (in the local table), nor policy-routing
+     * tables, nor IPv6's cached routes. rtm_table only has room for
+     * the first 256 tables, so RTA_TABLE, if present, is the real one.
+     */
+    let main = match handle.get_attr_payload_as::<u32>(Rta::Table) {
+        Ok(table) => table == libc::RT_TABLE_MAIN as u32,
+        Err(_) => p.rtm_table == RtTable::Main,
+    };
+    if !main
+        || p.rtm_type != Rtn::Unicast
+        || p.rtm_flags.contains(&RtmF::Cloned)
+    {
+        return None;
+    }
+
+    // A default route has no RTA_DST at all
+    let unspecified = match p.rtm_family {
+        RtAddrFamily::Inet => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        RtAddrFamily::Inet6 => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+        _ => return None,
+    };
+    let attr = |rta| {
+        handle
+            .get_attr_payload_as_with_len::<&[u8]>(rta)
+            .ok()
+            .and_then(ip)
+    };
+    let route = Route {
+        destination: attr(Rta::Dst).unwrap_or(unspecified),
+        prefix_len: p.rtm_dst_len,
+        gateway: attr(Rta::Gateway),
+        interface: handle
+            .get_attr_payload_as::<u32>(Rta::Oif)
+            .ok()
+            .and_then(core::num::NonZeroU32::new)
+            .map(InterfaceIndex),
+        metric: handle
+            .get_attr_payload_as::<u32>(Rta::Priority)
+            .unwrap_or(0),
+    };
+    match msg.nl_type {
+        Rtm::Newroute => Some(RouteEvent::NewRoute(route)),
+        Rtm::Delroute => Some(RouteEvent::DelRoute(route)),
+        _ => None,
+    }
+}
+
+fn route_dump_request() -> Nlmsghdr<Rtm, Rtmsg> {
+    let rtmsg = Rtmsg {
+        rtm_family: RtAddrFamily::Unspecified,
+        rtm_dst_len: 0,
+        rtm_src_len: 0,
+        rtm_tos: 0,
+        rtm_table: RtTable::Unspec,
+        rtm_protocol: Rtprot::Unspec,
+        rtm_scope: RtScope::Universe,
+        rtm_type: Rtn::Unspec,
+        rtm_flags: RtmFFlags::empty(),
+        rtattrs: RtBuffer::new(),
+    };
+    Nlmsghdr::new(
+        None,
+        Rtm::Getroute,
+        NlmFFlags::new(&[NlmF::Request, NlmF::Root]),
+        None,
+        None,
+        NlPayload::Payload(rtmsg),
+    )
+}
+
+fn get_routes(
+    mut ss: NlSocket,
+) -> impl Stream<Item = Result<RouteEvent, Error>> {
+    let mut buffer = Vec::new();
+    stream! {
+        loop {
+            let res: Result<NlBuffer<Rtm, Rtmsg>, DeError> =
+                ss.recv(&mut buffer).await;
+            match res {
+                Ok(msgs) =>
+                    for msg in msgs {
+                        if is_dump_done(&msg) {
+                            yield Ok(RouteEvent::EnumerationComplete);
+                        } else if let Some(event) =
+                            translate_route_message(&msg)
+                        {
+                            yield Ok(event);
+                        }
+                    },
+                Err(e) if is_overflow(&e) => {
+                    yield Ok(RouteEvent::Overflow);
+                    if let Err(e) = ss.send(&route_dump_request()).await {
+                        yield Err(map_tx_error(e));
+                    }
+                }
+                Err(e) => yield Err(map_rx_error(e))
+            }
+        }
+    }
+}
+
+/** Obtain the current routing table and a stream of future changes
+
+The stream consists of a sequence of [`RouteEvent`] objects. Every
+route already present is announced as [`RouteEvent::NewRoute`], then
+a single [`RouteEvent::EnumerationComplete`] is generated; after that,
+routes added, changed, or removed generate
+[`RouteEvent::NewRoute`] or [`RouteEvent::DelRoute`]. Only routes in
+the main table are reported, for both IPv4 and IPv6.
+
+This is separate from `get_interfaces_async`, as most applications
+don't need it; but the interface indexes in the two agree.
+
+A default route has a prefix length of zero; [`Route::is_default`]
+checks for that. So, for instance, here is how to follow which
+interface currently carries the IPv4 default route:
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+let mut s = get_routes_async()?;
+
+while let Some(e) = s.next().await {
+    if let Ok(RouteEvent::NewRoute(r)) = e {
+        if r.is_default() && r.destination.is_ipv4() {
+            println!("Default route via {:?} on {:?}", r.gateway, r.interface);
+        }
+    }
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+# });
+# Ok::<(), std::io::Error>(())
+```
+
+If the kernel drops changes, [`RouteEvent::Overflow`] is generated
+and the routes are announced afresh, as for `get_interfaces_async`.
+
+# Errors
+
+Returns Err if the underlying netlink socket failed to open, see netlink(7).
+
+ */
+pub fn get_routes_async(
+) -> Result<impl Stream<Item = Result<RouteEvent, Error>>, Error> {
+    get_routes_async_inner(
+        NlSocketHandle::connect,
+        route_sender,
+        NlSocket::new::<NlSocketHandle>,
+    )
+}
+
+/// Like `NlSocketHandle::send::<Nlmsghdr<Rtm, Rtmsg>>`
+type SendRouteMessageFn =
+    fn(&mut NlSocketHandle, Nlmsghdr<Rtm, Rtmsg>) -> Result<(), SerError>;
+
+fn route_sender(
+    s: &mut NlSocketHandle,
+    m: Nlmsghdr<Rtm, Rtmsg>,
+) -> Result<(), SerError> {
+    s.send(m)
+}
+
+fn get_routes_async_inner(
+    handle_fn: HandleFn,
+    send_route_fn: SendRouteMessageFn,
+    socket_fn: SocketFn,
+) -> Result<impl Stream<Item = Result<RouteEvent, Error>>, Error> {
+    // =RTNLGRP_IPV4_ROUTE, RTNLGRP_IPV6_ROUTE
+    let mut s = handle_fn(NlFamily::Route, None, &[7, 11])?;
+    enlarge_receive_buffer(&s);
+    send_route_fn(&mut s, route_dump_request()).map_err(map_tx_error)?;
+    Ok(Box::pin(get_routes(socket_fn(s)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use neli::consts::nl::Nlmsg;
+    use neli::{FromBytes, ToBytes};
+    use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    /// An RTM_NEWROUTE for "default via 192.168.1.1 dev 2 proto dhcp
+    /// metric 100", as the kernel sends it
+    const IPV4_DEFAULT: [u8; 60] = [
+        0x3c, 0, 0, 0, 0x18, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        2, 0, 0, 0, 254, 16, 0, 1, 0, 0, 0, 0, // rtmsg: AF_INET, main
+        8, 0, 15, 0, 254, 0, 0, 0, // RTA_TABLE
+        8, 0, 6, 0, 100, 0, 0, 0, // RTA_PRIORITY
+        8, 0, 5, 0, 192, 168, 1, 1, // RTA_GATEWAY
+        8, 0, 4, 0, 2, 0, 0, 0, // RTA_OIF
+    ];
+
+    /// An RTM_NEWROUTE for "2001:db8::/64 dev 3 proto kernel metric
+    /// 256 pref medium"
+    const IPV6_PREFIX: [u8; 80] = [
+        0x50, 0, 0, 0, 0x18, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        10, 64, 0, 0, 254, 2, 0, 1, 0, 0, 0, 0, // rtmsg: AF_INET6, /64
+        8, 0, 15, 0, 254, 0, 0, 0, // RTA_TABLE
+        0x14, 0, 1, 0, // RTA_DST
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, //
+        0, 0, 0, 0, 0, 0, 0, 0, //
+        8, 0, 6, 0, 0, 1, 0, 0, // RTA_PRIORITY
+        8, 0, 4, 0, 3, 0, 0, 0, // RTA_OIF
+        5, 0, 20, 0, 0, 0, 0, 0, // RTA_PREF
+    ];
+
+    fn parse_route_message(bytes: &[u8]) -> Nlmsghdr<Rtm, Rtmsg> {
+        Nlmsghdr::from_bytes(&mut std::io::Cursor::new(bytes)).unwrap()
+    }
+
+    fn default_route() -> Route {
+        Route {
+            destination: Ipv4Addr::UNSPECIFIED.into(),
+            prefix_len: 0,
+            gateway: Some(Ipv4Addr::new(192, 168, 1, 1).into()),
+            interface: Some(make_index(2)),
+            metric: 100,
+        }
+    }
+
+    #[test]
+    fn ipv4_default_route() {
+        let msg = parse_route_message(&IPV4_DEFAULT);
+        let event = translate_route_message(&msg);
+        assert_eq!(event, Some(RouteEvent::NewRoute(default_route())));
+        assert!(default_route().is_default());
+    }
+
+    #[test]
+    fn ipv4_default_route_deleted() {
+        let mut bytes = IPV4_DEFAULT;
+        bytes[4] = 0x19; // RTM_DELROUTE
+        let msg = parse_route_message(&bytes);
+        assert_eq!(
+            translate_route_message(&msg),
+            Some(RouteEvent::DelRoute(default_route()))
+        );
+    }
+
+    #[test]
+    fn ipv6_prefix_route() {
+        let msg = parse_route_message(&IPV6_PREFIX);
+        let route = Route {
+            destination: "2001:db8::".parse().unwrap(),
+            prefix_len: 64,
+            gateway: None,
+            interface: Some(make_index(3)),
+            metric: 256,
+        };
+        assert_eq!(
+            translate_route_message(&msg),
+            Some(RouteEvent::NewRoute(route))
+        );
+        assert!(!route.is_default());
+    }
+
+    #[test]
+    fn other_table_ignored() {
+        let mut bytes = IPV4_DEFAULT;
+        bytes[20] = 255; // rtm_table = RT_TABLE_LOCAL
+        bytes[32] = 255; // RTA_TABLE likewise
+        let msg = parse_route_message(&bytes);
+        assert_eq!(translate_route_message(&msg), None);
+    }
+
+    #[test]
+    fn large_table_number_ignored() {
+        // rtm_table says main, but the real table is RTA_TABLE's
+        let mut bytes = IPV4_DEFAULT;
+        bytes[33] = 1; // RTA_TABLE = 510
+        let msg = parse_route_message(&bytes);
+        assert_eq!(translate_route_message(&msg), None);
+    }
+
+    #[test]
+    fn local_route_ignored() {
+        let mut bytes = IPV4_DEFAULT;
+        bytes[23] = 2; // rtm_type = RTN_LOCAL
+        let msg = parse_route_message(&bytes);
+        assert_eq!(translate_route_message(&msg), None);
+    }
+
+    #[test]
+    fn cloned_route_ignored() {
+        let mut bytes = IPV6_PREFIX;
+        bytes[25] = 2; // rtm_flags = RTM_F_CLONED (0x200)
+        let msg = parse_route_message(&bytes);
+        assert_eq!(translate_route_message(&msg), None);
+    }
+
+    #[test]
+    fn no_metric_or_interface() {
+        let mut bytes = IPV4_DEFAULT;
+        bytes[0] = 0x2c; // drop RTA_GATEWAY and RTA_OIF
+        let mut bytes = bytes[..0x2c].to_vec();
+        bytes[38] = 0x13; // RTA_PRIORITY becomes RTA_MARK (unparsed)
+        let msg = parse_route_message(&bytes);
+        assert_eq!(
+            translate_route_message(&msg),
+            Some(RouteEvent::NewRoute(Route {
+                gateway: None,
+                interface: None,
+                metric: 0,
+                ..default_route()
+            }))
+        );
+    }
+
+    #[test]
+    fn dump_request_asks_for_everything() {
+        let req = route_dump_request();
+        assert_eq!(req.nl_type, Rtm::Getroute);
+        assert!(req.nl_flags.contains(&NlmF::Root));
+    }
+
+    fn fake_socket() -> (std::os::fd::OwnedFd, NlSocket) {
+        let (infd, outfd) = nix::sys::socket::socketpair(
+            nix::sys::socket::AddressFamily::Unix,
+            nix::sys::socket::SockType::Datagram,
+            None,
+            nix::sys::socket::SockFlag::empty(),
+        )
+        .unwrap();
+
+        let nlsocket = NlSocket::new({
+            let outfd = outfd.into_raw_fd();
+            unsafe {
+                // SAFETY: nlsocket becomes only owner of outfd
+                NlSocketHandle::from_raw_fd(outfd)
+            }
+        })
+        .unwrap();
+        (infd, nlsocket)
+    }
+
+    fn send_bytes(fd: &impl AsRawFd, bytes: &[u8]) {
+        nix::sys::socket::sendto(
+            fd.as_raw_fd(),
+            bytes,
+            &(),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+    }
+
+    fn send_dump_done(fd: &impl AsRawFd) {
+        let msg: Nlmsghdr<Rtm, Rtmsg> = Nlmsghdr::new(
+            None,
+            Rtm::from(u16::from(Nlmsg::Done)),
+            NlmFFlags::new(&[NlmF::Multi]),
+            None,
+            None,
+            NlPayload::Empty,
+        );
+        let mut v = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut v).unwrap();
+        send_bytes(fd, &v.into_inner());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn get_routes_stream() {
+        let (infd, socket) = fake_socket();
+        send_bytes(&infd, &IPV4_DEFAULT);
+        send_dump_done(&infd);
+        let mut bytes = IPV4_DEFAULT;
+        bytes[4] = 0x19; // RTM_DELROUTE
+        send_bytes(&infd, &bytes);
+
+        let s = get_routes(socket);
+        let events = s.take(3).collect::<Vec<_>>().await;
+        let events =
+            events.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                RouteEvent::NewRoute(default_route()),
+                RouteEvent::EnumerationComplete,
+                RouteEvent::DelRoute(default_route()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_routes_enumerated() {
+        let mut s = get_routes_async().unwrap();
+        while let Some(e) = s.next().await {
+            if e.unwrap() == RouteEvent::EnumerationComplete {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use crate::linux_messages::{
+    map_rx_error, map_tx_error, translate_error_message,
+};
+use crate::network_event::InterfaceIndex;
+use neli::{
+    consts::{
+        nl::{NlmF, NlmFFlags, Nlmsg},
+        rtnl::{Arphrd, IffFlags, Ifla, RtAddrFamily, Rtm},
+        socket::NlFamily,
+    },
+    nl::{NlPayload, Nlmsghdr},
+    rtnl::Ifinfomsg,
+    socket::NlSocket,
+    types::RtBuffer,
+    FromBytes, ToBytes,
+};
+use std::io::{Error, ErrorKind};
+
+/// Big enough for any link message (which, with all its statistics,
+/// may well be several kilobytes)
+const DATAGRAM_BYTES: usize = 64 * 1024;
+
+/// Traffic counters for a network interface
+///
+/// These count from when the interface was created, so are mostly
+/// useful as differences between two readings: see
+/// [`LinkStats::since`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkStats {
+    /// Packets received
+    pub rx_packets: u64,
+
+    /// Packets sent
+    pub tx_packets: u64,
+
+    /// Bytes received
+    pub rx_bytes: u64,
+
+    /// Bytes sent
+    pub tx_bytes: u64,
+
+    /// Bad packets received
+    pub rx_errors: u64,
+
+    /// Packets which couldn't be sent
+    pub tx_errors: u64,
+
+    /// Packets received but dropped (e.g. for lack of buffer space)
+    pub rx_dropped: u64,
+
+    /// Packets dropped rather than sent
+    pub tx_dropped: u64,
+
+    /// Multicast packets received
+    pub multicast: u64,
+}
+
+impl LinkStats {
+    /// The traffic between an earlier reading and this one
+    ///
+    /// If a counter has gone backwards (because the interface was
+    /// re-created, or the driver reset it), its difference is zero.
+    #[must_use]
+    pub fn since(&self, earlier: &LinkStats) -> LinkStats {
+        LinkStats {
+            rx_packets: self.rx_packets.saturating_sub(earlier.rx_packets),
+            tx_packets: self.tx_packets.saturating_sub(earlier.tx_packets),
+            rx_bytes: self.rx_bytes.saturating_sub(earlier.rx_bytes),
+            tx_bytes: self.tx_bytes.saturating_sub(earlier.tx_bytes),
+            rx_errors: self.rx_errors.saturating_sub(earlier.rx_errors),
+            tx_errors: self.tx_errors.saturating_sub(earlier.tx_errors),
+            rx_dropped: self.rx_dropped.saturating_sub(earlier.rx_dropped),
+            tx_dropped: self.tx_dropped.saturating_sub(earlier.tx_dropped),
+            multicast: self.multicast.saturating_sub(earlier.multicast),
+        }
+    }
+}
+
+/// Decode a `struct rtnl_link_stats64`, as in IFLA_STATS64
+///
+/// The struct is all u64s, in the kernel's byte order; newer kernels
+/// append more fields, which are ignored. Returns None if it's too
+/// short to hold the fields which are reported.
+fn decode_stats64(payload: &[u8]) -> Option<LinkStats> {
+    let field = |n: usize| {
+        payload
+            .get(n * 8..n * 8 + 8)
+            .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+    };
+    Some(LinkStats {
+        rx_packets: field(0)?,
+        tx_packets: field(1)?,
+        rx_bytes: field(2)?,
+        tx_bytes: field(3)?,
+        rx_errors: field(4)?,
+        tx_errors: field(5)?,
+        rx_dropped: field(6)?,
+        tx_dropped: field(7)?,
+        multicast: field(8)?,
+    })
+}
+
+fn stats_request(ix: InterfaceIndex) -> Nlmsghdr<Rtm, Ifinfomsg> {
+    let ifinfomsg = Ifinfomsg::new(
+        RtAddrFamily::Unspecified,
+        Arphrd::Ether,
+        i32::try_from(ix.0.get()).unwrap_or(0),
+        IffFlags::empty(),
+        IffFlags::empty(),
+        RtBuffer::new(),
+    );
+    Nlmsghdr::new(
+        None,
+        Rtm::Getlink,
+        NlmFFlags::new(&[NlmF::Request]),
+        None,
+        None,
+        NlPayload::Payload(ifinfomsg),
+    )
+}
+
+/// Turn the kernel's reply to a `stats_request` into statistics
+fn translate_stats_reply(msg: &[u8]) -> Result<LinkStats, Error> {
+    let nl_type = msg
+        .get(4..6)
+        .map(|t| u16::from_ne_bytes(t.try_into().unwrap()))
+        .ok_or(ErrorKind::InvalidData)?;
+    if nl_type == u16::from(Nlmsg::Error) {
+        return Err(translate_error_message(msg)
+            .unwrap_or_else(|| ErrorKind::InvalidData.into()));
+    }
+    let msg =
+        Nlmsghdr::<Rtm, Ifinfomsg>::from_bytes(&mut std::io::Cursor::new(msg))
+            .map_err(map_rx_error)?;
+    if msg.nl_type != Rtm::Newlink {
+        return Err(ErrorKind::InvalidData.into());
+    }
+    let NlPayload::Payload(p) = &msg.nl_payload else {
+        return Err(ErrorKind::InvalidData.into());
+    };
+    p.rtattrs
+        .get_attr_handle()
+        .get_attribute(Ifla::Stats64)
+        .and_then(|a| decode_stats64(a.rta_payload.as_ref()))
+        .ok_or_else(|| ErrorKind::Unsupported.into())
+}
+
+/** The traffic counters of a network interface
+
+This asks the kernel over netlink, as `ip -s link` does, for the
+interface's `IFLA_STATS64`. It blocks, but only briefly: the kernel
+answers straight away.
+
+```rust
+# use cotton_netif::*;
+# #[cfg(not(miri))]
+for e in get_interfaces()? {
+    if let NetworkEvent::NewLink(ix, name, _, _) = e {
+        let s = link_stats(ix)?;
+        println!("{name}: {} bytes in, {} out", s.rx_bytes, s.tx_bytes);
+    }
+}
+# Ok::<(), std::io::Error>(())
+```
+
+# Errors
+
+Returns Err if the netlink socket failed to open, or if the interface
+doesn't exist (`ENODEV`).
+
+ */
+pub fn link_stats(ix: InterfaceIndex) -> Result<LinkStats, Error> {
+    query(&NlSocket::connect(NlFamily::Route, None, &[])?, ix)
+}
+
+fn query(socket: &NlSocket, ix: InterfaceIndex) -> Result<LinkStats, Error> {
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    stats_request(ix)
+        .to_bytes(&mut bytes)
+        .map_err(map_tx_error)?;
+    socket.send(bytes.get_ref(), 0)?;
+    let mut buffer = vec![0; DATAGRAM_BYTES];
+    let n = socket.recv(&mut buffer[..], 0)?;
+    translate_stats_reply(&buffer[..n])
+}
+
+/** A stream of an interface's traffic, every `interval`
+
+Each item is the traffic since the previous one (or, for the first,
+since the stream was created), as in [`LinkStats::since`]: a small
+bandwidth monitor can divide the byte counts by the interval.
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# use std::time::Duration;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+# let lo = get_interfaces()?
+#     .find_map(|e| match e {
+#         NetworkEvent::NewLink(ix, _, flags, _)
+#             if flags.contains(Flags::LOOPBACK) => Some(ix),
+#         _ => None,
+#     })
+#     .unwrap();
+let mut s = link_stats_stream(lo, Duration::from_millis(100));
+
+while let Some(delta) = s.next().await {
+    println!("{} bytes/s in", delta?.rx_bytes * 10);
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+# });
+```
+
+Errors in reading the counters (for instance, because the interface
+has gone away) are passed on as stream items; the stream continues
+after them, and the next reading is compared with the last good one.
+ */
+#[cfg(feature = "async")]
+pub fn link_stats_stream(
+    ix: InterfaceIndex,
+    interval: std::time::Duration,
+) -> impl futures_util::stream::Stream<Item = Result<LinkStats, Error>> {
+    link_stats_stream_inner(ix, interval, link_stats)
+}
+
+#[cfg(feature = "async")]
+fn link_stats_stream_inner(
+    ix: InterfaceIndex,
+    interval: std::time::Duration,
+    link_stats: fn(InterfaceIndex) -> Result<LinkStats, Error>,
+) -> impl futures_util::stream::Stream<Item = Result<LinkStats, Error>> {
+    Box::pin(async_stream::stream! {
+        let mut previous = link_stats(ix).ok();
+        loop {
+            crate::polling::sleep(interval).await;
+            match link_stats(ix) {
+                Ok(stats) => {
+                    yield Ok(stats.since(&previous.unwrap_or_default()));
+                    previous = Some(stats);
+                }
+                Err(e) => yield Err(e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neli::rtnl::Rtattr;
+    use neli::types::Buffer;
+    use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    /// A `struct rtnl_link_stats64` whose fields count 1, 2, 3, ...
+    fn stats64_payload(fields: u64) -> Vec<u8> {
+        (1..=fields).flat_map(u64::to_ne_bytes).collect()
+    }
+
+    fn counting() -> LinkStats {
+        LinkStats {
+            rx_packets: 1,
+            tx_packets: 2,
+            rx_bytes: 3,
+            tx_bytes: 4,
+            rx_errors: 5,
+            tx_errors: 6,
+            rx_dropped: 7,
+            tx_dropped: 8,
+            multicast: 9,
+        }
+    }
+
+    fn link_reply(stats: Option<Vec<u8>>) -> Vec<u8> {
+        let mut buf = RtBuffer::new();
+        buf.push(Rtattr::new(None, Ifla::Ifname, "eth0".to_string()).unwrap());
+        if let Some(stats) = stats {
+            buf.push(
+                Rtattr::new(None, Ifla::Stats64, Buffer::from(stats)).unwrap(),
+            );
+        }
+        let msg = Nlmsghdr::new(
+            None,
+            Rtm::Newlink,
+            NlmFFlags::empty(),
+            None,
+            None,
+            NlPayload::Payload(Ifinfomsg::new(
+                RtAddrFamily::Unspecified,
+                Arphrd::Ether,
+                2,
+                IffFlags::empty(),
+                IffFlags::empty(),
+                buf,
+            )),
+        );
+        let mut v = std::io::Cursor::new(Vec::new());
+        msg.to_bytes(&mut v).unwrap();
+        v.into_inner()
+    }
+
+    fn error_reply(errno: i32) -> Vec<u8> {
+        let mut v = Vec::new();
+        v.extend_from_slice(&36u32.to_ne_bytes());
+        v.extend_from_slice(&u16::from(Nlmsg::Error).to_ne_bytes());
+        v.extend_from_slice(&[0; 10]);
+        v.extend_from_slice(&(-errno).to_ne_bytes());
+        v.extend_from_slice(&[0; 16]); // the original request's header
+        v
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_stats64(&stats64_payload(9)), Some(counting()));
+    }
+
+    #[test]
+    fn decode_newer_kernel() {
+        // Current kernels send 24 or more fields
+        assert_eq!(decode_stats64(&stats64_payload(24)), Some(counting()));
+    }
+
+    #[test]
+    fn decode_too_short() {
+        assert_eq!(decode_stats64(&stats64_payload(8)), None);
+        assert_eq!(decode_stats64(&stats64_payload(9)[..71]), None);
+        assert_eq!(decode_stats64(&[]), None);
+    }
+
+    #[test]
+    fn reply() {
+        assert_eq!(
+            translate_stats_reply(&link_reply(Some(stats64_payload(24))))
+                .unwrap(),
+            counting()
+        );
+    }
+
+    #[test]
+    fn reply_without_stats() {
+        assert_eq!(
+            translate_stats_reply(&link_reply(None)).unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn reply_error() {
+        assert_eq!(
+            translate_stats_reply(&error_reply(libc::ENODEV))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENODEV)
+        );
+    }
+
+    #[test]
+    fn reply_garbage() {
+        assert_eq!(
+            translate_stats_reply(&[1, 2]).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn since() {
+        let later = LinkStats {
+            rx_bytes: 1003,
+            tx_bytes: 2,
+            ..counting()
+        };
+        assert_eq!(
+            later.since(&counting()),
+            LinkStats {
+                rx_bytes: 1000,
+                ..LinkStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn request() {
+        let request = stats_request(make_index(7));
+        let NlPayload::Payload(p) = &request.nl_payload else {
+            panic!("no payload");
+        };
+        assert_eq!(request.nl_type, Rtm::Getlink);
+        assert_eq!(p.ifi_index, 7);
+        assert!(!request.nl_flags.contains(&NlmF::Dump));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn fake_socket() {
+        let (peer, socket) = nix::sys::socket::socketpair(
+            nix::sys::socket::AddressFamily::Unix,
+            nix::sys::socket::SockType::Datagram,
+            None,
+            nix::sys::socket::SockFlag::empty(),
+        )
+        .unwrap();
+        // SAFETY: the NlSocket becomes the only owner of the fd
+        let socket = unsafe { NlSocket::from_raw_fd(socket.into_raw_fd()) };
+
+        // Answer in advance: the datagram waits in the socket
+        nix::sys::socket::send(
+            peer.as_raw_fd(),
+            &link_reply(Some(stats64_payload(24))),
+            nix::sys::socket::MsgFlags::empty(),
+        )
+        .unwrap();
+        assert_eq!(query(&socket, make_index(2)).unwrap(), counting());
+
+        let mut buf = [0u8; 1024];
+        let n = nix::sys::socket::recv(
+            peer.as_raw_fd(),
+            &mut buf,
+            nix::sys::socket::MsgFlags::MSG_DONTWAIT,
+        )
+        .expect("no request sent");
+        let request = Nlmsghdr::<Rtm, Ifinfomsg>::from_bytes(
+            &mut std::io::Cursor::new(&buf[..n]),
+        )
+        .unwrap();
+        assert_eq!(request.nl_type, Rtm::Getlink);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn stream_of_deltas() {
+        use futures_util::StreamExt;
+
+        thread_local! {
+            static READING: core::cell::Cell<u64> =
+                const { core::cell::Cell::new(0) };
+        }
+        fn readings(_: InterfaceIndex) -> Result<LinkStats, Error> {
+            let n = READING.with(|r| r.replace(r.get() + 1));
+            match n {
+                2 => Err(ErrorKind::NotFound.into()),
+                n => Ok(LinkStats {
+                    rx_bytes: n * 100,
+                    ..LinkStats::default()
+                }),
+            }
+        }
+
+        let mut s = link_stats_stream_inner(
+            make_index(2),
+            std::time::Duration::from_millis(1),
+            readings,
+        );
+        assert_eq!(s.next().await.unwrap().unwrap().rx_bytes, 100);
+        assert_eq!(
+            s.next().await.unwrap().unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        // Compared with the last good reading
+        assert_eq!(s.next().await.unwrap().unwrap().rx_bytes, 200);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_loopback_counts_traffic() {
+        let lo = nix::net::if_::if_nametoindex("lo").unwrap();
+        let before = link_stats(make_index(lo)).unwrap();
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_to(b"hello", socket.local_addr().unwrap())
+            .unwrap();
+
+        let after = link_stats(make_index(lo)).unwrap();
+        assert!(after.since(&before).tx_packets >= 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_no_such_interface() {
+        assert_eq!(
+            link_stats(make_index(999_999)).unwrap_err().raw_os_error(),
+            Some(libc::ENODEV)
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use crate::linux_messages::{
+    map_rx_error, map_tx_error, translate_error_message,
+};
+use crate::network_event::{
+    InterfaceIndex, LinkType, NetworkEvent, WirelessInfo,
+};
+use neli::{
+    consts::{
+        nl::{NlmF, NlmFFlags, Nlmsg},
+        socket::NlFamily,
+    },
+    genl::{Genlmsghdr, Nlattr},
+    nl::{NlPayload, Nlmsghdr},
+    socket::NlSocket,
+    types::GenlBuffer,
+    FromBytes, ToBytes,
+};
+use std::io::{Error, ErrorKind};
+
+/// A generic netlink message, with untyped commands and attributes
+type GenlMessage = Nlmsghdr<u16, Genlmsghdr<u8, u16>>;
+
+/// Big enough for the controller's description of a family (which
+/// lists all its operations)
+const DATAGRAM_BYTES: usize = 64 * 1024;
+
+// From <linux/nl80211.h>, which libc doesn't have
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_GET_STATION: u8 = 17;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_STA_INFO: u16 = 21;
+const NL80211_ATTR_SSID: u16 = 52;
+const NL80211_STA_INFO_SIGNAL: u16 = 7;
+
+fn request<P: neli::Size + ToBytes>(
+    family: u16,
+    cmd: u8,
+    attr: u16,
+    payload: P,
+    flags: &[NlmF],
+) -> Result<Vec<u8>, Error> {
+    let mut attrs = GenlBuffer::new();
+    attrs
+        .push(Nlattr::new(false, false, attr, payload).map_err(map_tx_error)?);
+    let msg = Nlmsghdr::new(
+        None,
+        family,
+        NlmFFlags::new(flags),
+        None,
+        None,
+        NlPayload::Payload(Genlmsghdr::new(cmd, 1, attrs)),
+    );
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    msg.to_bytes(&mut bytes).map_err(map_tx_error)?;
+    Ok(bytes.into_inner())
+}
+
+/// Asking the generic netlink controller for a family's ID
+fn family_request(name: &str) -> Result<Vec<u8>, Error> {
+    request(
+        libc::GENL_ID_CTRL as u16,
+        libc::CTRL_CMD_GETFAMILY as u8,
+        libc::CTRL_ATTR_FAMILY_NAME as u16,
+        name,
+        &[NlmF::Request],
+    )
+}
+
+fn interface_request(
+    family: u16,
+    ix: InterfaceIndex,
+) -> Result<Vec<u8>, Error> {
+    request(
+        family,
+        NL80211_CMD_GET_INTERFACE,
+        NL80211_ATTR_IFINDEX,
+        ix.0.get(),
+        &[NlmF::Request],
+    )
+}
+
+/// Asking for all the stations an interface knows: for a Wi-Fi client,
+/// that's just its access point
+fn station_request(family: u16, ix: InterfaceIndex) -> Result<Vec<u8>, Error> {
+    request(
+        family,
+        NL80211_CMD_GET_STATION,
+        NL80211_ATTR_IFINDEX,
+        ix.0.get(),
+        &[NlmF::Request, NlmF::Dump],
+    )
+}
+
+/// Decode a reply: None if it's the end of an (empty) dump
+fn parse_reply(msg: &[u8]) -> Result<Option<Genlmsghdr<u8, u16>>, Error> {
+    let nl_type = msg
+        .get(4..6)
+        .map(|t| u16::from_ne_bytes(t.try_into().unwrap()))
+        .ok_or(ErrorKind::InvalidData)?;
+    if nl_type == u16::from(Nlmsg::Error) {
+        return Err(translate_error_message(msg)
+            .unwrap_or_else(|| ErrorKind::InvalidData.into()));
+    }
+    if nl_type == u16::from(Nlmsg::Done) {
+        return Ok(None);
+    }
+    let msg = GenlMessage::from_bytes(&mut std::io::Cursor::new(msg))
+        .map_err(map_rx_error)?;
+    match msg.nl_payload {
+        NlPayload::Payload(p) => Ok(Some(p)),
+        _ => Err(ErrorKind::InvalidData.into()),
+    }
+}
+
+/// The family ID in the controller's reply to a `family_request`
+fn translate_family_reply(msg: &[u8]) -> Result<u16, Error> {
+    parse_reply(msg)?
+        .and_then(|p| {
+            p.get_attr_handle()
+                .get_attr_payload_as::<u16>(libc::CTRL_ATTR_FAMILY_ID as u16)
+                .ok()
+        })
+        .ok_or_else(|| ErrorKind::InvalidData.into())
+}
+
+/// The SSID in the reply to an `interface_request`, if it's associated
+fn translate_interface_reply(msg: &[u8]) -> Result<Option<String>, Error> {
+    Ok(parse_reply(msg)?.and_then(|p| {
+        p.get_attr_handle()
+            .get_attribute(NL80211_ATTR_SSID)
+            .map(|a| String::from_utf8_lossy(a.nla_payload.as_ref()).into())
+    }))
+}
+
+/// The signal strength in the first reply to a `station_request`
+fn translate_station_reply(msg: &[u8]) -> Result<Option<i8>, Error> {
+    Ok(parse_reply(msg)?.and_then(|p| {
+        p.get_attr_handle()
+            .get_attribute(NL80211_ATTR_STA_INFO)?
+            .get_attr_handle::<u16>()
+            .ok()?
+            .get_attribute(NL80211_STA_INFO_SIGNAL)?
+            .nla_payload
+            .as_ref()
+            .first()
+            .map(|b| i8::from_ne_bytes([*b]))
+    }))
+}
+
+fn exchange(socket: &NlSocket, request: &[u8]) -> Result<Vec<u8>, Error> {
+    socket.send(request, 0)?;
+    let mut buffer = vec![0; DATAGRAM_BYTES];
+    let n = socket.recv(&mut buffer[..], 0)?;
+    buffer.truncate(n);
+    Ok(buffer)
+}
+
+/// Whether an error from nl80211 just means "that's not Wi-Fi"
+fn is_not_wireless(e: &Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENODEV | libc::EOPNOTSUPP | libc::EINVAL)
+    )
+}
+
+/** The current association of a Wi-Fi interface
+
+This asks the kernel's nl80211 interface, as `iw dev ... link` does,
+for the SSID of the network joined and the access point's signal
+strength. It returns `Ok(None)` if the interface isn't Wi-Fi (or if
+the kernel has no Wi-Fi support at all), and a [`WirelessInfo`] with
+no SSID if it is Wi-Fi but isn't associated.
+
+```rust
+# use cotton_netif::*;
+# #[cfg(not(miri))]
+for e in get_interfaces()? {
+    if let NetworkEvent::NewLink(ix, name, _, _) = e {
+        if let Some(w) = wireless_info(ix)? {
+            println!("{name}: {:?} at {:?} dBm", w.ssid, w.signal_dbm);
+        }
+    }
+}
+# Ok::<(), std::io::Error>(())
+```
+
+# Errors
+
+Returns Err if the netlink socket failed to open, or the kernel gave
+an unexpected answer.
+
+ */
+pub fn wireless_info(
+    ix: InterfaceIndex,
+) -> Result<Option<WirelessInfo>, Error> {
+    query(&NlSocket::connect(NlFamily::Generic, None, &[])?, ix)
+}
+
+fn query(
+    socket: &NlSocket,
+    ix: InterfaceIndex,
+) -> Result<Option<WirelessInfo>, Error> {
+    let family = match translate_family_reply(&exchange(
+        socket,
+        &family_request("nl80211")?,
+    )?) {
+        Ok(family) => family,
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let ssid = match translate_interface_reply(&exchange(
+        socket,
+        &interface_request(family, ix)?,
+    )?) {
+        Ok(ssid) => ssid,
+        Err(e) if is_not_wireless(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // Unassociated interfaces just have no stations
+    let signal_dbm = if ssid.is_some() {
+        translate_station_reply(&exchange(
+            socket,
+            &station_request(family, ix)?,
+        )?)?
+    } else {
+        None
+    };
+    Ok(Some(WirelessInfo { ssid, signal_dbm }))
+}
+
+/// Fill in the Wi-Fi details of a newly-seen link
+///
+/// Wi-Fi interfaces are Ethernet-like, and physical (so have no kind);
+/// others aren't asked about. Failures leave the details as None.
+pub(crate) fn add_wireless(event: NetworkEvent) -> NetworkEvent {
+    match event {
+        NetworkEvent::NewLink(ix, name, flags, mut info)
+            if info.link_type == LinkType::Ethernet && info.kind.is_none() =>
+        {
+            info.wireless = wireless_info(ix).ok().flatten();
+            NetworkEvent::NewLink(ix, name, flags, info)
+        }
+        event => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_event::LinkInfo;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    /// The controller's reply about nl80211 (less its lists of
+    /// operations and multicast groups)
+    const FAMILY_REPLY: [u8; 40] = [
+        40, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        1, 2, 0, 0, // genlmsghdr: CTRL_CMD_NEWFAMILY
+        12, 0, 2, 0, b'n', b'l', b'8', b'0', b'2', b'1', b'1',
+        0, // CTRL_ATTR_FAMILY_NAME
+        6, 0, 1, 0, 0x1c, 0, 0, 0, // CTRL_ATTR_FAMILY_ID
+    ];
+
+    /// An NL80211_CMD_NEW_INTERFACE for an associated client
+    const INTERFACE_REPLY: [u8; 60] = [
+        60, 0, 0, 0, 0x1c, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        7, 1, 0, 0, // genlmsghdr: NL80211_CMD_NEW_INTERFACE
+        8, 0, 3, 0, 3, 0, 0, 0, // NL80211_ATTR_IFINDEX
+        10, 0, 4, 0, b'w', b'l', b'a', b'n', b'0', 0, 0,
+        0, // NL80211_ATTR_IFNAME
+        8, 0, 5, 0, 2, 0, 0, 0, // NL80211_ATTR_IFTYPE: STATION
+        10, 0, 52, 0, b'c', b'o', b't', b't', b'o', b'n', 0,
+        0, // NL80211_ATTR_SSID
+    ];
+
+    /// An NL80211_CMD_NEW_STATION for the access point
+    const STATION_REPLY: [u8; 60] = [
+        60, 0, 0, 0, 0x1c, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        19, 1, 0, 0, // genlmsghdr: NL80211_CMD_NEW_STATION
+        8, 0, 3, 0, 3, 0, 0, 0, // NL80211_ATTR_IFINDEX
+        10, 0, 6, 0, 2, 0, 0, 0, 0, 1, 0, 0, // NL80211_ATTR_MAC
+        20, 0, 21, 0x80, // NL80211_ATTR_STA_INFO (nested)
+        8, 0, 1, 0, 0x40, 0x1f, 0, 0, // NL80211_STA_INFO_INACTIVE_TIME
+        5, 0, 7, 0, 0xCC, 0, 0, 0, // NL80211_STA_INFO_SIGNAL: -52
+    ];
+
+    /// The end of an empty dump
+    const DONE: [u8; 20] = [
+        20, 0, 0, 0, 3, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        0, 0, 0, 0,
+    ];
+
+    /// An NLMSG_ERROR: ENODEV
+    const ENODEV_REPLY: [u8; 36] = [
+        36, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // nlmsghdr
+        0xed, 0xff, 0xff, 0xff, // -19
+        28, 0, 0, 0, 0x1c, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // request
+    ];
+
+    #[test]
+    fn family() {
+        assert_eq!(translate_family_reply(&FAMILY_REPLY).unwrap(), 0x1c);
+    }
+
+    #[test]
+    fn family_without_id() {
+        let mut bytes = FAMILY_REPLY;
+        bytes[0] = 32; // drop CTRL_ATTR_FAMILY_ID
+        assert_eq!(
+            translate_family_reply(&bytes[..32]).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn interface() {
+        assert_eq!(
+            translate_interface_reply(&INTERFACE_REPLY).unwrap(),
+            Some("cotton".to_string())
+        );
+    }
+
+    #[test]
+    fn interface_unassociated() {
+        let mut bytes = INTERFACE_REPLY;
+        bytes[0] = 48; // drop NL80211_ATTR_SSID
+        assert_eq!(translate_interface_reply(&bytes[..48]).unwrap(), None);
+    }
+
+    #[test]
+    fn interface_ssid_not_utf8() {
+        let mut bytes = INTERFACE_REPLY;
+        bytes[52] = 0xFF;
+        assert_eq!(
+            translate_interface_reply(&bytes).unwrap(),
+            Some("\u{FFFD}otton".to_string())
+        );
+    }
+
+    #[test]
+    fn interface_not_wireless() {
+        let e = translate_interface_reply(&ENODEV_REPLY).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENODEV));
+        assert!(is_not_wireless(&e));
+        assert!(!is_not_wireless(&Error::from(ErrorKind::InvalidData)));
+    }
+
+    #[test]
+    fn station() {
+        assert_eq!(
+            translate_station_reply(&STATION_REPLY).unwrap(),
+            Some(-52)
+        );
+    }
+
+    #[test]
+    fn station_without_signal() {
+        let mut bytes = STATION_REPLY;
+        bytes[54] = 0xFF; // NL80211_STA_INFO_SIGNAL -> unknown attribute
+        assert_eq!(translate_station_reply(&bytes).unwrap(), None);
+    }
+
+    #[test]
+    fn station_none() {
+        assert_eq!(translate_station_reply(&DONE).unwrap(), None);
+    }
+
+    #[test]
+    fn garbage() {
+        assert_eq!(
+            translate_station_reply(&[1, 2, 3]).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(translate_interface_reply(&STATION_REPLY[..30]).is_err());
+    }
+
+    #[test]
+    fn requests() {
+        let bytes = interface_request(0x1c, make_index(3)).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                28, 0, 0, 0, 0x1c, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+                5, 1, 0, 0, //
+                8, 0, 3, 0, 3, 0, 0, 0,
+            ]
+        );
+        let bytes = station_request(0x1c, make_index(3)).unwrap();
+        assert_eq!(bytes[6..8], [1, 3]); // NLM_F_REQUEST | NLM_F_DUMP
+        assert_eq!(bytes[16], NL80211_CMD_GET_STATION);
+    }
+
+    #[test]
+    fn non_wifi_not_asked() {
+        let event = NetworkEvent::NewLink(
+            make_index(1),
+            "lo".to_string(),
+            crate::Flags::LOOPBACK,
+            LinkInfo {
+                link_type: LinkType::Loopback,
+                ..LinkInfo::default()
+            },
+        );
+        assert_eq!(add_wireless(event.clone()), event);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_family_request() {
+        // The controller itself is always present
+        let socket = NlSocket::connect(NlFamily::Generic, None, &[]).unwrap();
+        let reply =
+            exchange(&socket, &family_request("nlctrl").unwrap()).unwrap();
+        assert_eq!(
+            translate_family_reply(&reply).unwrap(),
+            libc::GENL_ID_CTRL as u16
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn zzz_loopback_not_wireless() {
+        let lo = nix::net::if_::if_nametoindex("lo").unwrap();
+        assert_eq!(wireless_info(make_index(lo)).unwrap(), None);
+    }
+}
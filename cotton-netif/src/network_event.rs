@@ -3,6 +3,8 @@ use core::ops::{BitOr, BitOrAssign};
 /** Kernel network interface index (1-based)
  */
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct InterfaceIndex(pub core::num::NonZeroU32);
 
 /// Flags describing a network interface's features and state
@@ -27,9 +29,21 @@ impl Flags {
     #[doc = "Interface is operational"]
     pub const RUNNING: Self = Self(0x40);
 
+    #[doc = "Interface doesn't use ARP (or neighbour discovery)"]
+    pub const NOARP: Self = Self(0x80);
+
+    #[doc = "Interface is receiving all packets, not just those addressed to it"]
+    pub const PROMISC: Self = Self(0x100);
+
     #[doc = "Interface is multicast-capable"]
     pub const MULTICAST: Self = Self(0x1000);
 
+    #[doc = "Interface has a carrier, e.g. a cable is plugged in (Linux only)"]
+    pub const LOWER_UP: Self = Self(0x10000);
+
+    #[doc = "Interface is waiting for something, e.g. 802.1X authentication (Linux only)"]
+    pub const DORMANT: Self = Self(0x20000);
+
     #[doc = "An empty set of flags"]
     pub fn empty() -> Self {
         Self(0)
@@ -54,22 +68,494 @@ impl BitOrAssign for Flags {
     }
 }
 
+/// Serialize a set of flags as a list of their names (and back again)
+///
+/// The names are those of the constants. When deserializing, unknown
+/// names are ignored, so that data from a newer version, with more
+/// flags, can still be read.
+#[cfg(feature = "serde")]
+macro_rules! serde_flags {
+    ($t:ident, $($flag:ident),+) => {
+        impl serde::Serialize for $t {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(
+                    [$((stringify!($flag), Self::$flag)),+]
+                        .into_iter()
+                        .filter(|(_, flag)| self.contains(*flag))
+                        .map(|(name, _)| name),
+                )
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $t {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Self, D::Error> {
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = $t;
+
+                    fn expecting(
+                        &self,
+                        f: &mut core::fmt::Formatter,
+                    ) -> core::fmt::Result {
+                        f.write_str("a list of flag names")
+                    }
+
+                    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                        self,
+                        mut seq: A,
+                    ) -> Result<$t, A::Error> {
+                        let mut flags = $t::empty();
+                        while let Some(name) =
+                            seq.next_element::<alloc::string::String>()?
+                        {
+                            match name.as_str() {
+                                $(stringify!($flag) => flags |= $t::$flag,)+
+                                _ => {}
+                            }
+                        }
+                        Ok(flags)
+                    }
+                }
+
+                deserializer.deserialize_seq(Visitor)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+serde_flags!(
+    Flags,
+    UP,
+    BROADCAST,
+    LOOPBACK,
+    POINTTOPOINT,
+    RUNNING,
+    NOARP,
+    PROMISC,
+    MULTICAST,
+    LOWER_UP,
+    DORMANT
+);
+
+/// The kind of hardware (or virtual hardware) behind a network interface
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LinkType {
+    #[doc = "Ethernet, or anything presenting as it (which includes most Wi-Fi interfaces)"]
+    Ethernet,
+
+    #[doc = "The loopback interface"]
+    Loopback,
+
+    #[doc = "PPP"]
+    Ppp,
+
+    #[doc = "An IP-in-IP, GRE, or similar tunnel, or a TUN device (as used by many VPNs)"]
+    Tunnel,
+
+    #[doc = "802.11 wireless, when not presenting as Ethernet (e.g. in monitor mode)"]
+    Ieee80211,
+
+    #[doc = "Anything else, or unknown"]
+    #[default]
+    Other,
+}
+
+impl LinkType {
+    /// From a Linux ARPHRD_ value, as in `ifi_type` or `sll_hatype`
+    pub fn from_arphrd(arphrd: u16) -> Self {
+        match arphrd {
+            1 => Self::Ethernet,   // ARPHRD_ETHER
+            772 => Self::Loopback, // ARPHRD_LOOPBACK
+            512 => Self::Ppp,      // ARPHRD_PPP
+            // ARPHRD_TUNNEL, _TUNNEL6, _SIT, _IPGRE, _IP6GRE, _NONE
+            768 | 769 | 776 | 778 | 823 | 0xFFFE => Self::Tunnel,
+            // ARPHRD_IEEE80211, _IEEE80211_PRISM, _IEEE80211_RADIOTAP
+            801..=803 => Self::Ieee80211,
+            _ => Self::Other,
+        }
+    }
+
+    /// From an IANA ifType, as used by BSD's `sdl_type` and Windows's
+    /// `IfType`
+    pub fn from_iftype(iftype: u32) -> Self {
+        match iftype {
+            6 => Self::Ethernet,  // ethernetCsmacd
+            24 => Self::Loopback, // softwareLoopback
+            23 => Self::Ppp,      // ppp
+            // tunnel, and BSD's gif and stf
+            131 | 55 | 57 => Self::Tunnel,
+            71 => Self::Ieee80211, // ieee80211
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Whether a network interface can pass packets, as per RFC 2863
+///
+/// This is finer-grained than [`Flags::RUNNING`]: it says *why* an
+/// interface which is administratively up can't be used.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OperState {
+    #[doc = "Not known (many virtual interfaces, such as loopback, report this even when working)"]
+    #[default]
+    Unknown,
+
+    #[doc = "Some component, typically hardware, is missing"]
+    NotPresent,
+
+    #[doc = "Down"]
+    Down,
+
+    #[doc = "Down because an interface it's stacked on is down (e.g. a VLAN on an unplugged Ethernet)"]
+    LowerLayerDown,
+
+    #[doc = "In a test mode"]
+    Testing,
+
+    #[doc = "Waiting for some external event (e.g. 802.1X authentication)"]
+    Dormant,
+
+    #[doc = "Up, and able to pass packets"]
+    Up,
+}
+
+impl OperState {
+    /// From a Linux IF_OPER_ value, as in `IFLA_OPERSTATE`
+    pub fn from_linux(operstate: u8) -> Self {
+        match operstate {
+            1 => Self::NotPresent,     // IF_OPER_NOTPRESENT
+            2 => Self::Down,           // IF_OPER_DOWN
+            3 => Self::LowerLayerDown, // IF_OPER_LOWERLAYERDOWN
+            4 => Self::Testing,        // IF_OPER_TESTING
+            5 => Self::Dormant,        // IF_OPER_DORMANT
+            6 => Self::Up,             // IF_OPER_UP
+            _ => Self::Unknown,
+        }
+    }
+
+    /// From an RFC 2863 ifOperStatus, as used by Windows's `OperStatus`
+    pub fn from_if_oper_status(status: u32) -> Self {
+        match status {
+            1 => Self::Up,
+            2 => Self::Down,
+            3 => Self::Testing,
+            5 => Self::Dormant,
+            6 => Self::NotPresent,
+            7 => Self::LowerLayerDown,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Further details of a network interface, as carried by
+/// [`NetworkEvent::NewLink`]
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkInfo {
+    /// The largest IP packet the interface can send, in bytes, if known
+    pub mtu: Option<u32>,
+
+    /// What sort of interface it is
+    pub link_type: LinkType,
+
+    /// Its operational state, if known
+    ///
+    /// This is reported by `get_interfaces_async` and
+    /// `watch_interfaces` on Linux, and by both `get_interfaces` and
+    /// `get_interfaces_async` on Windows; elsewhere it's
+    /// [`OperState::Unknown`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub oper_state: OperState,
+
+    /// The interface this one is stacked on, if any
+    ///
+    /// For instance, a VLAN interface `eth0.100`'s parent is `eth0`,
+    /// and a veth interface's is the other end of the pair (if that's
+    /// in the same network namespace). This, `master`, and `kind` are
+    /// reported by `get_interfaces_async` and `watch_interfaces` on
+    /// Linux; elsewhere they're None.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parent: Option<InterfaceIndex>,
+
+    /// The bridge (or bond, or similar) this interface is enslaved to,
+    /// if any
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub master: Option<InterfaceIndex>,
+
+    /// The kind of virtual interface, if it is one: for instance,
+    /// "vlan", "bridge", "veth", or "wireguard"
+    ///
+    /// These are the names used by `ip link add ... type`. Physical
+    /// interfaces have no kind.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub kind: Option<alloc::string::String>,
+
+    /// For a Wi-Fi interface, its current association
+    ///
+    /// This is reported (with the `wireless` feature) by
+    /// `get_interfaces`, `get_interfaces_async`, and `watch_interfaces`
+    /// as each interface is first seen; it's None for interfaces
+    /// which aren't Wi-Fi. [`NetworkEvent::LinkChanged`] doesn't carry
+    /// it, so after one of those (for instance, on joining or leaving
+    /// a network), use `wireless_info` to find the new state.
+    #[cfg(all(target_os = "linux", feature = "wireless"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wireless: Option<WirelessInfo>,
+}
+
+/// The association of a Wi-Fi interface, as in [`LinkInfo::wireless`]
+#[cfg(all(target_os = "linux", feature = "wireless"))]
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WirelessInfo {
+    /// The name of the network it's joined, if any
+    ///
+    /// SSIDs are just up to 32 bytes, and needn't be UTF-8; any which
+    /// aren't are converted lossily.
+    pub ssid: Option<alloc::string::String>,
+
+    /// The strength of the access point's signal, in dBm, if known
+    ///
+    /// Typically this is between -30 (excellent) and -90 (unusable).
+    pub signal_dbm: Option<i8>,
+}
+
 use core::net::IpAddr as IpAddress;
 
+/// Flags describing the state of an address (mostly, an IPv6 one)
+///
+/// Corresponds to Linux's IFA_F_ flags
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AddressFlags(u32);
+
+impl AddressFlags {
+    #[doc = "A temporary (\"privacy\") address, as per RFC 8981"]
+    pub const TEMPORARY: Self = Self(0x1);
+
+    #[doc = "Duplicate address detection failed: another host has this address"]
+    pub const DAD_FAILED: Self = Self(0x8);
+
+    #[doc = "Address is still valid, but shouldn't be used for new connections"]
+    pub const DEPRECATED: Self = Self(0x20);
+
+    #[doc = "Duplicate address detection is still in progress; don't bind to it yet"]
+    pub const TENTATIVE: Self = Self(0x40);
+
+    #[doc = "An empty set of flags"]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    #[doc = "Check whether a subset of flags are set"]
+    pub fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for AddressFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for AddressFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_flags!(AddressFlags, TEMPORARY, DAD_FAILED, DEPRECATED, TENTATIVE);
+
+/// How widely an address is meaningful
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Scope {
+    #[doc = "Anywhere"]
+    #[default]
+    Global,
+
+    #[doc = "Within one site (deprecated IPv6 site-local addresses, fec0::/10)"]
+    Site,
+
+    #[doc = "Only on the attached link (e.g. fe80::/10, or 169.254.0.0/16)"]
+    Link,
+
+    #[doc = "Only within this host (loopback)"]
+    Host,
+}
+
+impl Scope {
+    /// From a Linux RT_SCOPE_ value, as in `ifa_scope`
+    ///
+    /// Returns None for the values which have no equivalent here
+    /// (such as RT_SCOPE_NOWHERE, or user-defined scopes).
+    pub fn from_rt_scope(scope: u8) -> Option<Self> {
+        match scope {
+            0 => Some(Self::Global), // RT_SCOPE_UNIVERSE
+            200 => Some(Self::Site), // RT_SCOPE_SITE
+            253 => Some(Self::Link), // RT_SCOPE_LINK
+            254 => Some(Self::Host), // RT_SCOPE_HOST
+            _ => None,
+        }
+    }
+
+    /// From the address itself, for platforms which don't report it
+    pub fn from_address(ip: &IpAddress) -> Self {
+        match ip {
+            IpAddress::V4(v4) if v4.is_loopback() => Self::Host,
+            IpAddress::V4(v4) if v4.is_link_local() => Self::Link,
+            IpAddress::V6(v6) if v6.is_loopback() => Self::Host,
+            IpAddress::V6(v6) => match v6.segments()[0] & 0xFFC0 {
+                0xFE80 => Self::Link,
+                0xFEC0 => Self::Site,
+                _ => Self::Global,
+            },
+            IpAddress::V4(_) => Self::Global,
+        }
+    }
+}
+
+/// Further details of an address, as carried by
+/// [`NetworkEvent::NewAddr`]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddrInfo {
+    /// The subnet's broadcast address (IPv4 only), if it has one
+    pub broadcast: Option<IpAddress>,
+
+    /// On a point-to-point link, the address of the other end, if known
+    pub peer: Option<IpAddress>,
+
+    /// The address's state, where reported (Linux and Windows only)
+    ///
+    /// In particular, an address which is [`AddressFlags::TENTATIVE`]
+    /// may yet turn out to be a duplicate, so can't be relied on; if
+    /// it passes, it's announced again without that flag.
+    pub flags: AddressFlags,
+
+    /// How widely the address is meaningful
+    pub scope: Scope,
+}
+
 /** Event when a new interface or address is detected, or when one disappears
  */
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NetworkEvent {
-    /** A new network interface is detected. */
-    NewLink(InterfaceIndex, alloc::string::String, Flags),
+    /** A new network interface is detected.
+     *
+     * The name is the interface's own: Linux alias labels such as
+     * "eth0:1" are folded back into the interface they belong to
+     * ("eth0"), by both `get_interfaces` and `get_interfaces_async`,
+     * so that names from the two APIs can be compared.
+     */
+    NewLink(InterfaceIndex, alloc::string::String, Flags, LinkInfo),
 
     /** A previously-seen interface has gone away (e.g. USB unplug). */
     DelLink(InterfaceIndex),
 
+    /** A previously-seen interface's flags have changed.
+     *
+     * For instance, when a cable is plugged in (the interface becomes
+     * [`Flags::RUNNING`]), or when the interface is brought up or
+     * down. Only the streams from `get_interfaces_async` generate this;
+     * the first report of each interface is always a
+     * [`NetworkEvent::NewLink`].
+     */
+    LinkChanged(InterfaceIndex, Flags),
+
     /** An interface has a new address; note that each interface can have several addresses.
+     *
+     * The fields are the interface, the (local) address, its prefix
+     * length (e.g. 24 for a netmask of 255.255.255.0), and its
+     * broadcast or peer address where there is one.
      */
-    NewAddr(InterfaceIndex, IpAddress, u8),
+    NewAddr(InterfaceIndex, IpAddress, u8, AddrInfo),
 
-    /** A previously-active address has been deactivated. */
+    /** A previously-active address has been deactivated.
+     *
+     * The fields are the first three of [`NetworkEvent::NewAddr`], so
+     * that a consumer tracking several addresses per interface can
+     * tell which one has gone.
+     */
     DelAddr(InterfaceIndex, IpAddress, u8),
+
+    /** All interfaces and addresses present at the start have been announced.
+     *
+     * Only the streams from `get_interfaces_async` generate this, and
+     * only once, after the events describing the initial state. All
+     * later events are live changes, so a consumer wanting to collect
+     * the current state before acting on changes, can wait for this.
+     */
+    EnumerationComplete,
+
+    /** Some changes were lost, because too many happened too quickly.
+     *
+     * Whatever the consumer knew may now be stale, so should be
+     * discarded: the stream goes on to announce the current state
+     * afresh, just as at the start -- every interface and address, as
+     * [`NetworkEvent::NewLink`] and [`NetworkEvent::NewAddr`], then
+     * [`NetworkEvent::EnumerationComplete`] -- and then live changes
+     * as before.
+     */
+    Overflow,
+}
+
+/// Undo Linux aliasing: "eth0:1" is "eth0" really
+#[cfg(all(any(feature = "sync", feature = "async"), unix))]
+pub(crate) fn unalias(name: &str) -> &str {
+    name.split_once(':').map_or(name, |(prefix, _alias)| prefix)
+}
+
+/// The interfaces already announced on a stream, so that later
+/// reports about them can be told apart from arrivals
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Default, Debug)]
+pub(crate) struct KnownLinks(
+    alloc::collections::BTreeMap<InterfaceIndex, Flags>,
+);
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl KnownLinks {
+    /// Turn a freshly-reported event into the one to pass on
+    ///
+    /// A NewLink for a known interface becomes LinkChanged if its
+    /// flags differ, and is dropped if they don't (the kernel reports
+    /// links again for all sorts of reasons).
+    pub(crate) fn filter(
+        &mut self,
+        event: NetworkEvent,
+    ) -> Option<NetworkEvent> {
+        match event {
+            NetworkEvent::NewLink(ix, _, flags, _) => {
+                match self.0.insert(ix, flags) {
+                    None => Some(event),
+                    Some(old) if old == flags => None,
+                    Some(_) => Some(NetworkEvent::LinkChanged(ix, flags)),
+                }
+            }
+            NetworkEvent::DelLink(ix) => {
+                self.0.remove(&ix);
+                Some(event)
+            }
+            _ => Some(event),
+        }
+    }
+
+    /// Whether interface `ix` has been announced (and not deleted)
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    pub(crate) fn contains(&self, ix: InterfaceIndex) -> bool {
+        self.0.contains_key(&ix)
+    }
 }
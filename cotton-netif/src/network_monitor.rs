@@ -0,0 +1,539 @@
+use crate::network_event::{
+    AddrInfo, Flags, InterfaceIndex, LinkInfo, NetworkEvent,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::net::IpAddr;
+
+/// The type of the name filter passed to [`NetworkMonitor::name_filter`]
+type NameFilter = Box<dyn Fn(&str) -> bool + Send>;
+
+/** A filtered stream of network events, for only some interfaces
+
+Most applications only care about some of the system's interfaces:
+perhaps only multicast-capable ones, or not the loopback interface,
+or not the `veth*` interfaces created for containers. A
+`NetworkMonitor` filters the stream from
+[`get_interfaces_async`](crate::get_interfaces_async) (or any other
+stream of [`NetworkEvent`]) so that only interfaces which match all
+its criteria, and their addresses, are reported.
+
+Simply calling `filter()` on the stream isn't enough, because
+interfaces change: an interface which didn't match (say, because it
+wasn't [`Flags::RUNNING`]) might start to, or vice versa. A
+`NetworkMonitor` remembers every interface and address, so an
+interface which starts to match is announced with a
+[`NetworkEvent::NewLink`], followed by a [`NetworkEvent::NewAddr`] for
+each of its addresses; one which stops matching has its addresses and
+itself removed, with [`NetworkEvent::DelAddr`] and
+[`NetworkEvent::DelLink`]. Changes to an interface which matches both
+before and after, are passed on as [`NetworkEvent::LinkChanged`].
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+let mut s = NetworkMonitor::new()
+    .include_loopback(false)
+    .require_flags(Flags::MULTICAST | Flags::RUNNING)
+    .name_filter(|name| !name.starts_with("veth"))
+    .get_interfaces_async()?;
+
+while let Some(e) = s.next().await {
+    println!("{:?}", e);
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+# });
+# Ok::<(), std::io::Error>(())
+```
+ */
+pub struct NetworkMonitor {
+    include_loopback: bool,
+    required_flags: Flags,
+    name_filter: Option<NameFilter>,
+}
+
+impl Default for NetworkMonitor {
+    fn default() -> Self {
+        Self {
+            include_loopback: true,
+            required_flags: Flags::empty(),
+            name_filter: None,
+        }
+    }
+}
+
+impl NetworkMonitor {
+    /// Create a monitor which, so far, reports all interfaces
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to report loopback interfaces (the default is to)
+    #[must_use]
+    pub fn include_loopback(mut self, include: bool) -> Self {
+        self.include_loopback = include;
+        self
+    }
+
+    /// Report only interfaces which have all of these flags
+    #[must_use]
+    pub fn require_flags(mut self, flags: Flags) -> Self {
+        self.required_flags = flags;
+        self
+    }
+
+    /// Report only interfaces whose names satisfy this predicate
+    #[must_use]
+    pub fn name_filter(
+        mut self,
+        f: impl Fn(&str) -> bool + Send + 'static,
+    ) -> Self {
+        self.name_filter = Some(Box::new(f));
+        self
+    }
+
+    /// Filter the stream from [`get_interfaces_async`](crate::get_interfaces_async)
+    ///
+    /// # Errors
+    ///
+    /// As for `get_interfaces_async`.
+    #[cfg(any(unix, windows))]
+    pub fn get_interfaces_async(
+        self,
+    ) -> Result<impl Stream<Item = Result<NetworkEvent, Error>>, Error> {
+        Ok(self.filter(crate::get_interfaces_async()?))
+    }
+
+    /// Filter any stream of network events
+    ///
+    /// Errors in the stream are passed on unchanged.
+    pub fn filter(
+        self,
+        s: impl Stream<Item = Result<NetworkEvent, Error>>,
+    ) -> impl Stream<Item = Result<NetworkEvent, Error>> {
+        let mut state = Filter {
+            monitor: self,
+            links: BTreeMap::new(),
+        };
+        s.flat_map(move |e| {
+            stream::iter(match e {
+                Ok(e) => state.on_event(e).into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            })
+        })
+    }
+
+    fn matches(&self, name: &str, flags: Flags) -> bool {
+        (self.include_loopback || !flags.contains(Flags::LOOPBACK))
+            && flags.contains(self.required_flags)
+            && self.name_filter.as_ref().map_or(true, |f| f(name))
+    }
+}
+
+/// Everything known about one interface, whether reported or not
+struct Link {
+    name: String,
+    flags: Flags,
+    info: LinkInfo,
+    addrs: Vec<(IpAddr, u8, AddrInfo)>,
+    reported: bool,
+}
+
+impl Link {
+    fn new_link(&self, ix: InterfaceIndex) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            ix,
+            self.name.clone(),
+            self.flags,
+            self.info.clone(),
+        )
+    }
+}
+
+struct Filter {
+    monitor: NetworkMonitor,
+    links: BTreeMap<InterfaceIndex, Link>,
+}
+
+impl Filter {
+    /// Turn one event from the underlying stream, into those to report
+    fn on_event(&mut self, e: NetworkEvent) -> Vec<NetworkEvent> {
+        match e {
+            NetworkEvent::NewLink(ix, name, flags, info) => {
+                let reported = self.monitor.matches(&name, flags);
+                let link = Link {
+                    name,
+                    flags,
+                    info,
+                    addrs: Vec::new(),
+                    reported,
+                };
+                let events = if reported {
+                    vec![link.new_link(ix)]
+                } else {
+                    Vec::new()
+                };
+                self.links.insert(ix, link);
+                events
+            }
+            NetworkEvent::LinkChanged(ix, flags) => {
+                let Some(link) = self.links.get_mut(&ix) else {
+                    return Vec::new();
+                };
+                link.flags = flags;
+                let now = self.monitor.matches(&link.name, flags);
+                let events = match (link.reported, now) {
+                    (false, true) => core::iter::once(link.new_link(ix))
+                        .chain(link.addrs.iter().map(|(ip, prefix, info)| {
+                            NetworkEvent::NewAddr(ix, *ip, *prefix, *info)
+                        }))
+                        .collect(),
+                    (true, false) => link
+                        .addrs
+                        .iter()
+                        .map(|(ip, prefix, _)| {
+                            NetworkEvent::DelAddr(ix, *ip, *prefix)
+                        })
+                        .chain(core::iter::once(NetworkEvent::DelLink(ix)))
+                        .collect(),
+                    (true, true) => vec![e],
+                    (false, false) => Vec::new(),
+                };
+                link.reported = now;
+                events
+            }
+            NetworkEvent::DelLink(ix) => match self.links.remove(&ix) {
+                Some(link) if link.reported => vec![e],
+                _ => Vec::new(),
+            },
+            NetworkEvent::NewAddr(ix, ip, prefix, info) => {
+                let Some(link) = self.links.get_mut(&ix) else {
+                    return Vec::new();
+                };
+                link.addrs.retain(|a| (a.0, a.1) != (ip, prefix));
+                link.addrs.push((ip, prefix, info));
+                if link.reported {
+                    vec![e]
+                } else {
+                    Vec::new()
+                }
+            }
+            NetworkEvent::DelAddr(ix, ip, prefix) => {
+                let Some(link) = self.links.get_mut(&ix) else {
+                    return Vec::new();
+                };
+                link.addrs.retain(|a| (a.0, a.1) != (ip, prefix));
+                if link.reported {
+                    vec![e]
+                } else {
+                    Vec::new()
+                }
+            }
+            NetworkEvent::Overflow => {
+                // Everything is about to be announced afresh
+                self.links.clear();
+                vec![e]
+            }
+            NetworkEvent::EnumerationComplete => vec![e],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn new_link(i: u32, name: &str, flags: Flags) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            name.to_string(),
+            flags,
+            LinkInfo::default(),
+        )
+    }
+
+    const LAN: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+    const LAN_2: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+
+    fn filter(monitor: NetworkMonitor) -> Filter {
+        Filter {
+            monitor,
+            links: BTreeMap::new(),
+        }
+    }
+
+    fn running() -> Filter {
+        filter(
+            NetworkMonitor::new()
+                .require_flags(Flags::UP | Flags::RUNNING | Flags::MULTICAST),
+        )
+    }
+
+    const UP: Flags = Flags::UP;
+    const DOWN: Flags = Flags::MULTICAST;
+
+    fn running_flags() -> Flags {
+        Flags::UP | Flags::RUNNING | Flags::MULTICAST
+    }
+
+    #[test]
+    fn default_reports_everything() {
+        let mut f = filter(NetworkMonitor::new());
+        let events = [
+            new_link(1, "lo", Flags::LOOPBACK | UP),
+            NetworkEvent::NewAddr(make_index(1), LAN, 8, AddrInfo::default()),
+            NetworkEvent::EnumerationComplete,
+        ];
+        for e in events {
+            assert_eq!(f.on_event(e.clone()), vec![e]);
+        }
+    }
+
+    #[test]
+    fn exclude_loopback() {
+        let mut f = filter(NetworkMonitor::new().include_loopback(false));
+        assert_eq!(
+            f.on_event(new_link(1, "lo", Flags::LOOPBACK | UP)),
+            vec![]
+        );
+        assert_eq!(
+            f.on_event(NetworkEvent::NewAddr(
+                make_index(1),
+                LAN,
+                8,
+                AddrInfo::default()
+            )),
+            vec![]
+        );
+        assert_eq!(f.on_event(NetworkEvent::DelLink(make_index(1))), vec![]);
+        assert_eq!(
+            f.on_event(new_link(2, "eth0", UP)),
+            vec![new_link(2, "eth0", UP)]
+        );
+    }
+
+    #[test]
+    fn name_filter() {
+        let mut f = filter(
+            NetworkMonitor::new().name_filter(|n| !n.starts_with("veth")),
+        );
+        assert_eq!(f.on_event(new_link(5, "veth1234", UP)), vec![]);
+        assert_eq!(
+            f.on_event(new_link(2, "eth0", UP)),
+            vec![new_link(2, "eth0", UP)]
+        );
+    }
+
+    #[test]
+    fn backfill_when_starting_to_match() {
+        let mut f = running();
+        assert_eq!(f.on_event(new_link(2, "eth0", DOWN)), vec![]);
+        assert_eq!(
+            f.on_event(NetworkEvent::NewAddr(
+                make_index(2),
+                LAN,
+                24,
+                AddrInfo::default()
+            )),
+            vec![]
+        );
+        assert_eq!(
+            f.on_event(NetworkEvent::NewAddr(
+                make_index(2),
+                LAN_2,
+                24,
+                AddrInfo::default()
+            )),
+            vec![]
+        );
+        assert_eq!(
+            f.on_event(NetworkEvent::DelAddr(make_index(2), LAN_2, 24)),
+            vec![]
+        );
+
+        // Cable plugged in
+        assert_eq!(
+            f.on_event(NetworkEvent::LinkChanged(
+                make_index(2),
+                running_flags()
+            )),
+            vec![
+                new_link(2, "eth0", running_flags()),
+                NetworkEvent::NewAddr(
+                    make_index(2),
+                    LAN,
+                    24,
+                    AddrInfo::default()
+                ),
+            ]
+        );
+
+        // Now reported as usual
+        assert_eq!(
+            f.on_event(NetworkEvent::NewAddr(
+                make_index(2),
+                LAN_2,
+                24,
+                AddrInfo::default()
+            )),
+            vec![NetworkEvent::NewAddr(
+                make_index(2),
+                LAN_2,
+                24,
+                AddrInfo::default()
+            )]
+        );
+    }
+
+    #[test]
+    fn removal_when_ceasing_to_match() {
+        let mut f = running();
+        f.on_event(new_link(2, "eth0", running_flags()));
+        f.on_event(NetworkEvent::NewAddr(
+            make_index(2),
+            LAN,
+            24,
+            AddrInfo::default(),
+        ));
+
+        // Cable unplugged
+        assert_eq!(
+            f.on_event(NetworkEvent::LinkChanged(make_index(2), DOWN)),
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::DelLink(make_index(2)),
+            ]
+        );
+
+        // The address is still tracked, but not reported
+        assert_eq!(
+            f.on_event(NetworkEvent::DelAddr(make_index(2), LAN, 24)),
+            vec![]
+        );
+        assert_eq!(f.on_event(NetworkEvent::DelLink(make_index(2))), vec![]);
+        assert!(f.links.is_empty());
+    }
+
+    #[test]
+    fn scripted_sequence() {
+        let mut f = running();
+        let ix = make_index(3);
+        let script = [
+            (new_link(3, "wlan0", DOWN), vec![]),
+            (
+                NetworkEvent::NewAddr(ix, LAN, 24, AddrInfo::default()),
+                vec![],
+            ),
+            (NetworkEvent::LinkChanged(ix, UP | DOWN), vec![]),
+            (
+                NetworkEvent::LinkChanged(ix, running_flags()),
+                vec![
+                    new_link(3, "wlan0", running_flags()),
+                    NetworkEvent::NewAddr(ix, LAN, 24, AddrInfo::default()),
+                ],
+            ),
+            (
+                NetworkEvent::LinkChanged(
+                    ix,
+                    running_flags() | Flags::BROADCAST,
+                ),
+                vec![NetworkEvent::LinkChanged(
+                    ix,
+                    running_flags() | Flags::BROADCAST,
+                )],
+            ),
+            (
+                NetworkEvent::LinkChanged(ix, DOWN),
+                vec![
+                    NetworkEvent::DelAddr(ix, LAN, 24),
+                    NetworkEvent::DelLink(ix),
+                ],
+            ),
+            (NetworkEvent::DelLink(ix), vec![]),
+        ];
+        for (event, expected) in script {
+            assert_eq!(f.on_event(event), expected);
+        }
+    }
+
+    #[test]
+    fn unknown_interface_ignored() {
+        let mut f = running();
+        let ix = make_index(9);
+        assert_eq!(
+            f.on_event(NetworkEvent::NewAddr(
+                ix,
+                LAN,
+                24,
+                AddrInfo::default()
+            )),
+            vec![]
+        );
+        assert_eq!(
+            f.on_event(NetworkEvent::LinkChanged(ix, running_flags())),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn overflow_forgets() {
+        let mut f = running();
+        f.on_event(new_link(2, "eth0", running_flags()));
+        assert_eq!(
+            f.on_event(NetworkEvent::Overflow),
+            vec![NetworkEvent::Overflow]
+        );
+        assert!(f.links.is_empty());
+    }
+
+    #[tokio::test]
+    async fn filter_stream() {
+        let events = vec![
+            Ok(new_link(1, "lo", Flags::LOOPBACK | running_flags())),
+            Ok(new_link(2, "eth0", DOWN)),
+            Err(Error::from(std::io::ErrorKind::Other)),
+            Ok(NetworkEvent::NewAddr(
+                make_index(2),
+                LAN,
+                24,
+                AddrInfo::default(),
+            )),
+            Ok(NetworkEvent::EnumerationComplete),
+            Ok(NetworkEvent::LinkChanged(make_index(2), running_flags())),
+        ];
+        let out = NetworkMonitor::new()
+            .include_loopback(false)
+            .require_flags(running_flags())
+            .filter(stream::iter(events))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(out.len(), 4);
+        assert!(out[0].is_err());
+        assert_eq!(
+            out[1..]
+                .iter()
+                .map(|e| e.as_ref().unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                &NetworkEvent::EnumerationComplete,
+                &new_link(2, "eth0", running_flags()),
+                &NetworkEvent::NewAddr(
+                    make_index(2),
+                    LAN,
+                    24,
+                    AddrInfo::default()
+                ),
+            ]
+        );
+    }
+}
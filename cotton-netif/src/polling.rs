@@ -0,0 +1,554 @@
+use crate::network_event::{
+    AddrInfo, Flags, InterfaceIndex, KnownLinks, LinkInfo, NetworkEvent,
+};
+use async_stream::stream;
+use futures_util::future::{self, Either};
+use futures_util::stream::Stream;
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The type of the function taking a static listing
+type SnapshotFn = fn() -> Result<Vec<NetworkEvent>, Error>;
+
+/// How often the fallback `get_interfaces_async` polls
+#[cfg(not(any(
+    target_os = "linux",
+    windows,
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Everything known about the interfaces at one moment, by name
+///
+/// Interfaces are identified by name, which is what `getifaddrs`
+/// itself reports (the indexes are looked up afterwards), so that a
+/// listing which comes back in a different order, or with the indexes
+/// looked up differently, isn't mistaken for a change. An interface
+/// whose index really has changed is reported as removed and added
+/// again.
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Snapshot {
+    links: BTreeMap<String, (InterfaceIndex, Flags, LinkInfo)>,
+    addrs: BTreeMap<(String, IpAddr, u8), (InterfaceIndex, AddrInfo)>,
+}
+
+impl Snapshot {
+    fn new(events: impl IntoIterator<Item = NetworkEvent>) -> Self {
+        let mut s = Self::default();
+        let mut names = BTreeMap::new();
+        for e in events {
+            match e {
+                NetworkEvent::NewLink(ix, name, flags, info) => {
+                    names.insert(ix, name.clone());
+                    s.links.insert(name, (ix, flags, info));
+                }
+                NetworkEvent::NewAddr(ix, ip, prefix, info) => {
+                    if let Some(name) = names.get(&ix) {
+                        s.addrs.insert((name.clone(), ip, prefix), (ix, info));
+                    }
+                }
+                _ => {}
+            }
+        }
+        s
+    }
+
+    /// Whether the interface called `name` is in both snapshots, with
+    /// the same index
+    fn same_index(&self, new: &Snapshot, name: &str) -> bool {
+        match (self.links.get(name), new.links.get(name)) {
+            (Some(old), Some(new)) => old.0 == new.0,
+            _ => false,
+        }
+    }
+
+    /// Replace the snapshot with `new`, returning the events which
+    /// describe the difference
+    ///
+    /// As for Windows's snapshots, departures come before arrivals,
+    /// and an interface or address which has changed is announced
+    /// again (to be turned into a LinkChanged, or dropped, by
+    /// KnownLinks). An interface whose index has changed is removed,
+    /// with its addresses, and added afresh.
+    fn update(&mut self, new: Snapshot) -> Vec<NetworkEvent> {
+        let mut events = Vec::new();
+        for ((name, ip, prefix), (ix, _)) in &self.addrs {
+            if !new.addrs.contains_key(&(name.clone(), *ip, *prefix))
+                || !self.same_index(&new, name)
+            {
+                events.push(NetworkEvent::DelAddr(*ix, *ip, *prefix));
+            }
+        }
+        for (name, (ix, _, _)) in &self.links {
+            if !self.same_index(&new, name) {
+                events.push(NetworkEvent::DelLink(*ix));
+            }
+        }
+        for (name, link) in &new.links {
+            if self.links.get(name) != Some(link) {
+                let (ix, flags, info) = link;
+                events.push(NetworkEvent::NewLink(
+                    *ix,
+                    name.clone(),
+                    *flags,
+                    info.clone(),
+                ));
+            }
+        }
+        for ((name, ip, prefix), addr) in &new.addrs {
+            let key = (name.clone(), *ip, *prefix);
+            if self.addrs.get(&key) != Some(addr) {
+                let (ix, info) = addr;
+                events.push(NetworkEvent::NewAddr(*ix, *ip, *prefix, *info));
+            }
+        }
+        *self = new;
+        events
+    }
+}
+
+/** A handle for changing how often a polling stream polls
+
+Returned by [`network_interfaces_poll`]. Dropping it leaves the stream
+polling at the interval last set.
+ */
+#[derive(Clone, Debug)]
+pub struct PollInterval(Arc<watch::Sender<Duration>>);
+
+impl PollInterval {
+    /// Poll every `interval` from now on
+    ///
+    /// This takes effect straight away: the next listing is taken
+    /// `interval` after this call, not after the previous interval has
+    /// run out.
+    pub fn set(&self, interval: Duration) {
+        self.0.send_replace(interval);
+    }
+
+    /// The current interval
+    #[must_use]
+    pub fn get(&self) -> Duration {
+        *self.0.borrow()
+    }
+}
+
+/// Wait, using whichever runtime is configured
+pub(crate) async fn sleep(interval: Duration) {
+    #[cfg(not(feature = "async-io"))]
+    tokio::time::sleep(interval).await;
+
+    #[cfg(feature = "async-io")]
+    async_io::Timer::after(interval).await;
+}
+
+/** Obtain the current list of network interfaces and a stream of future
+events, by polling
+
+This works wherever [`get_interfaces`](crate::get_interfaces) does,
+even where the system's change notifications aren't available (for
+instance, inside a sandbox which forbids netlink sockets). Every
+`interval`, it takes a fresh listing, compares it with the previous
+one, and reports the differences, so the stream looks just like that
+from [`get_interfaces_async`](crate::get_interfaces_async): the
+interfaces and addresses present at the start, then a
+[`NetworkEvent::EnumerationComplete`], then changes, with a change in
+a known interface's flags reported as [`NetworkEvent::LinkChanged`].
+
+Changes which come and go between two listings aren't seen at all,
+and the others are reported up to `interval` late; the returned
+[`PollInterval`] can be used to poll more or less often as the
+application's needs change.
+
+```rust
+# use cotton_netif::*;
+# use futures_util::StreamExt;
+# use std::time::Duration;
+# #[cfg(not(miri))]
+# tokio_test::block_on(async {
+let (interval, mut s) = network_interfaces_poll(Duration::from_secs(10));
+
+while let Some(e) = s.next().await {
+    if let NetworkEvent::EnumerationComplete = e? {
+        // Now settled: poll less often
+        interval.set(Duration::from_secs(60));
+    }
+#   break;
+}
+# Ok::<(), std::io::Error>(())
+# });
+```
+
+Errors in obtaining a listing are passed on as stream items; the
+stream continues after them.
+ */
+pub fn network_interfaces_poll(
+    interval: Duration,
+) -> (
+    PollInterval,
+    impl Stream<Item = Result<NetworkEvent, Error>>,
+) {
+    network_interfaces_poll_inner(interval, snapshot)
+}
+
+fn snapshot() -> Result<Vec<NetworkEvent>, Error> {
+    #[cfg(unix)]
+    return crate::getifaddrs::get_interfaces().map(Iterator::collect);
+
+    #[cfg(windows)]
+    return crate::getadaptersaddresses::get_interfaces()
+        .map(Iterator::collect);
+}
+
+fn network_interfaces_poll_inner(
+    interval: Duration,
+    snapshot: SnapshotFn,
+) -> (
+    PollInterval,
+    impl Stream<Item = Result<NetworkEvent, Error>>,
+) {
+    let (tx, mut rx) = watch::channel(interval);
+
+    let s = Box::pin(stream! {
+        let mut previous = Snapshot::default();
+        let mut known = KnownLinks::default();
+        let mut enumerated = false;
+        loop {
+            match snapshot() {
+                Ok(events) => {
+                    for event in previous
+                        .update(Snapshot::new(events))
+                        .into_iter()
+                        .filter_map(|e| known.filter(e))
+                    {
+                        yield Ok(event);
+                    }
+                    if !enumerated {
+                        enumerated = true;
+                        yield Ok(NetworkEvent::EnumerationComplete);
+                    }
+                }
+                Err(e) => yield Err(e),
+            }
+
+            // Sleep, starting again whenever the interval is changed
+            loop {
+                let interval = *rx.borrow_and_update();
+                let changed = async {
+                    if rx.changed().await.is_err() {
+                        // The PollInterval has gone, so it never will
+                        future::pending::<()>().await;
+                    }
+                };
+                let slept = core::pin::pin!(sleep(interval));
+                let changed = core::pin::pin!(changed);
+                if let Either::Left(_) = future::select(slept, changed).await
+                {
+                    break;
+                }
+            }
+        }
+    });
+    (PollInterval(Arc::new(tx)), s)
+}
+
+/** Obtain the current list of network interfaces and a stream of future
+events
+
+This is the implementation of `get_interfaces_async` for platforms
+with no native change notifications supported yet: it is
+[`network_interfaces_poll`], polling every five seconds. See the Linux
+version for examples, which work unchanged here.
+
+# Errors
+
+None at present, but the signature matches the other platforms'.
+ */
+#[cfg(not(any(
+    target_os = "linux",
+    windows,
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+pub fn get_interfaces_async(
+) -> Result<impl Stream<Item = Result<NetworkEvent, Error>>, Error> {
+    Ok(network_interfaces_poll(DEFAULT_INTERVAL).1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::net::Ipv4Addr;
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn link(i: u32, name: &str, flags: Flags) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            name.to_string(),
+            flags,
+            LinkInfo::default(),
+        )
+    }
+
+    fn addr(i: u32, ip: IpAddr, prefix: u8) -> NetworkEvent {
+        NetworkEvent::NewAddr(make_index(i), ip, prefix, AddrInfo::default())
+    }
+
+    const LAN: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+    const LAN_2: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+
+    fn up() -> Flags {
+        Flags::UP | Flags::RUNNING | Flags::MULTICAST
+    }
+
+    /// Feed one snapshot after another through, as the stream does
+    fn diffs(snapshots: Vec<Vec<NetworkEvent>>) -> Vec<Vec<NetworkEvent>> {
+        let mut previous = Snapshot::default();
+        let mut known = KnownLinks::default();
+        snapshots
+            .into_iter()
+            .map(|events| {
+                previous
+                    .update(Snapshot::new(events))
+                    .into_iter()
+                    .filter_map(|e| known.filter(e))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_snapshot_announced() {
+        let first = vec![link(2, "eth0", up()), addr(2, LAN, 24)];
+        assert_eq!(diffs(vec![first.clone()]), vec![first]);
+    }
+
+    #[test]
+    fn unchanged() {
+        let first = vec![link(2, "eth0", up()), addr(2, LAN, 24)];
+        assert_eq!(
+            diffs(vec![first.clone(), first.clone(), first.clone()]),
+            vec![first, vec![], vec![]]
+        );
+    }
+
+    #[test]
+    fn addr_added_and_removed() {
+        assert_eq!(
+            diffs(vec![
+                vec![link(2, "eth0", up()), addr(2, LAN, 24)],
+                vec![link(2, "eth0", up()), addr(2, LAN_2, 24)],
+            ])[1],
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                addr(2, LAN_2, 24),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_change_is_new_addr() {
+        assert_eq!(
+            diffs(vec![
+                vec![link(2, "eth0", up()), addr(2, LAN, 24)],
+                vec![link(2, "eth0", up()), addr(2, LAN, 16)],
+            ])[1],
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                addr(2, LAN, 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_change() {
+        assert_eq!(
+            diffs(vec![
+                vec![link(2, "eth0", up()), addr(2, LAN, 24)],
+                vec![link(2, "eth0", Flags::UP), addr(2, LAN, 24)],
+            ])[1],
+            vec![NetworkEvent::LinkChanged(make_index(2), Flags::UP)]
+        );
+    }
+
+    #[test]
+    fn link_removed_with_addrs() {
+        assert_eq!(
+            diffs(vec![
+                vec![
+                    link(1, "lo", Flags::LOOPBACK),
+                    link(2, "eth0", up()),
+                    addr(2, LAN, 24),
+                ],
+                vec![link(1, "lo", Flags::LOOPBACK)],
+            ])[1],
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::DelLink(make_index(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn index_reorder_is_no_churn() {
+        // The listing comes back in a different order, but nothing has
+        // actually changed
+        assert_eq!(
+            diffs(vec![
+                vec![
+                    link(1, "lo", Flags::LOOPBACK),
+                    link(2, "eth0", up()),
+                    addr(1, LAN_2, 8),
+                    addr(2, LAN, 24),
+                ],
+                vec![
+                    link(2, "eth0", up()),
+                    link(1, "lo", Flags::LOOPBACK),
+                    addr(2, LAN, 24),
+                    addr(1, LAN_2, 8),
+                ],
+            ])[1],
+            vec![]
+        );
+    }
+
+    #[test]
+    fn index_changed() {
+        assert_eq!(
+            diffs(vec![
+                vec![link(2, "eth0", up()), addr(2, LAN, 24)],
+                vec![link(5, "eth0", up()), addr(5, LAN, 24)],
+            ])[1],
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                NetworkEvent::DelLink(make_index(2)),
+                link(5, "eth0", up()),
+                addr(5, LAN, 24),
+            ]
+        );
+    }
+
+    #[test]
+    fn addr_moved_between_links() {
+        assert_eq!(
+            diffs(vec![
+                vec![
+                    link(2, "eth0", up()),
+                    link(3, "eth1", up()),
+                    addr(2, LAN, 24),
+                ],
+                vec![
+                    link(2, "eth0", up()),
+                    link(3, "eth1", up()),
+                    addr(3, LAN, 24),
+                ],
+            ])[1],
+            vec![
+                NetworkEvent::DelAddr(make_index(2), LAN, 24),
+                addr(3, LAN, 24),
+            ]
+        );
+    }
+
+    #[test]
+    fn addr_without_link_ignored() {
+        assert_eq!(diffs(vec![vec![addr(2, LAN, 24)]]), vec![vec![]]);
+    }
+
+    thread_local! {
+        static SNAPSHOTS: RefCell<VecDeque<Result<Vec<NetworkEvent>, Error>>> =
+            const { RefCell::new(VecDeque::new()) };
+    }
+
+    /// Hand out the scripted snapshots, then keep repeating the last
+    fn scripted() -> Result<Vec<NetworkEvent>, Error> {
+        SNAPSHOTS.with(|s| {
+            let mut s = s.borrow_mut();
+            if s.len() > 1 {
+                s.pop_front().unwrap()
+            } else {
+                match s.front().unwrap() {
+                    Ok(events) => Ok(events.clone()),
+                    Err(e) => Err(Error::from(e.kind())),
+                }
+            }
+        })
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[cfg_attr(miri, ignore)]
+    async fn stream_of_changes() {
+        SNAPSHOTS.with(|s| {
+            *s.borrow_mut() = VecDeque::from([
+                Ok(vec![link(2, "eth0", up())]),
+                Err(Error::from(std::io::ErrorKind::Other)),
+                Ok(vec![link(2, "eth0", up()), addr(2, LAN, 24)]),
+            ]);
+        });
+        let (interval, mut s) =
+            network_interfaces_poll_inner(Duration::from_millis(1), scripted);
+        assert_eq!(interval.get(), Duration::from_millis(1));
+        assert_eq!(s.next().await.unwrap().unwrap(), link(2, "eth0", up()));
+        assert_eq!(
+            s.next().await.unwrap().unwrap(),
+            NetworkEvent::EnumerationComplete
+        );
+        assert_eq!(
+            s.next().await.unwrap().unwrap_err().kind(),
+            std::io::ErrorKind::Other
+        );
+        assert_eq!(s.next().await.unwrap().unwrap(), addr(2, LAN, 24));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[cfg_attr(miri, ignore)]
+    async fn interval_change_takes_effect() {
+        SNAPSHOTS.with(|s| {
+            *s.borrow_mut() = VecDeque::from([
+                Ok(vec![link(2, "eth0", up())]),
+                Ok(vec![link(2, "eth0", up()), addr(2, LAN, 24)]),
+            ]);
+        });
+        let (interval, mut s) =
+            network_interfaces_poll_inner(Duration::from_secs(3600), scripted);
+        assert_eq!(s.next().await.unwrap().unwrap(), link(2, "eth0", up()));
+        assert_eq!(
+            s.next().await.unwrap().unwrap(),
+            NetworkEvent::EnumerationComplete
+        );
+
+        // Without the change, this would wait for an hour
+        interval.set(Duration::from_millis(1));
+        drop(interval);
+        assert_eq!(s.next().await.unwrap().unwrap(), addr(2, LAN, 24));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn zzz_poll_enumerates() {
+        let (_interval, mut s) =
+            network_interfaces_poll(Duration::from_millis(10));
+        let mut links = 0;
+        while let Some(e) = s.next().await {
+            match e.unwrap() {
+                NetworkEvent::NewLink(..) => links += 1,
+                NetworkEvent::EnumerationComplete => break,
+                _ => {}
+            }
+        }
+        // At least the loopback interface
+        assert!(links > 0);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::network_event::InterfaceIndex;
+use core::net::IpAddr as IpAddress;
+
+/// A route in the system's main routing table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Route {
+    /// The destination network
+    ///
+    /// For a default route, this is the unspecified address of the
+    /// route's family (`0.0.0.0` or `::`), and `prefix_len` is zero.
+    pub destination: IpAddress,
+
+    /// The prefix length of the destination network (0 for a default
+    /// route, 32 or 128 for a route to a single host)
+    pub prefix_len: u8,
+
+    /// The next hop, or None if the destination is directly attached
+    pub gateway: Option<IpAddress>,
+
+    /// The interface the route goes out of, if there's only one (a
+    /// multipath route has several, and none is reported here)
+    pub interface: Option<InterfaceIndex>,
+
+    /// The route's priority: where several routes match, the one with
+    /// the lowest metric is used
+    pub metric: u32,
+}
+
+impl Route {
+    /// Whether this is a default route (e.g. `0.0.0.0/0`)
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        self.prefix_len == 0
+    }
+}
+
+/** Event when a route is added, changed, or removed
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RouteEvent {
+    /** A route has been added (or has changed).
+     *
+     * Routes are identified by their destination, prefix length, and
+     * metric; a NewRoute for a route already announced, replaces it
+     * (e.g. its gateway has changed).
+     */
+    NewRoute(Route),
+
+    /** A previously-announced route has been removed. */
+    DelRoute(Route),
+
+    /** All routes present at the start have been announced.
+     *
+     * As for [`crate::NetworkEvent::EnumerationComplete`].
+     */
+    EnumerationComplete,
+
+    /** Some changes were lost, because too many happened too quickly.
+     *
+     * As for [`crate::NetworkEvent::Overflow`]: every route is then
+     * announced afresh, followed by [`RouteEvent::EnumerationComplete`].
+     */
+    Overflow,
+}
@@ -0,0 +1,417 @@
+use crate::getadaptersaddresses::get_interfaces;
+use crate::network_event::{
+    AddrInfo, Flags, InterfaceIndex, KnownLinks, LinkInfo, NetworkEvent,
+};
+use async_stream::stream;
+use futures_util::stream::Stream;
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+use windows_sys::Win32::Foundation::{HANDLE, NO_ERROR};
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    CancelMibChangeNotify2, NotifyIpInterfaceChange,
+    NotifyUnicastIpAddressChange, MIB_IPINTERFACE_ROW, MIB_NOTIFICATION_TYPE,
+    MIB_UNICASTIPADDRESS_ROW,
+};
+use windows_sys::Win32::Networking::WinSock::AF_UNSPEC;
+
+/// What the callbacks send: just "something changed"
+type Sender = mpsc::UnboundedSender<()>;
+
+/// Everything known about the interfaces at one moment
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Snapshot {
+    links: BTreeMap<InterfaceIndex, (String, Flags, LinkInfo)>,
+    addrs: BTreeMap<(InterfaceIndex, IpAddr, u8), AddrInfo>,
+}
+
+impl Snapshot {
+    fn new(events: impl Iterator<Item = NetworkEvent>) -> Self {
+        let mut s = Self::default();
+        for e in events {
+            match e {
+                NetworkEvent::NewLink(ix, name, flags, info) => {
+                    s.links.insert(ix, (name, flags, info));
+                }
+                NetworkEvent::NewAddr(ix, ip, prefix, info) => {
+                    s.addrs.insert((ix, ip, prefix), info);
+                }
+                _ => {}
+            }
+        }
+        s
+    }
+
+    /// Replace the snapshot with `new`, returning the events which
+    /// describe the difference
+    ///
+    /// Departures come before arrivals, and within those, addresses
+    /// are removed before their interfaces, and interfaces added
+    /// before their addresses. An interface whose name, flags, or
+    /// link info have changed is announced again, as netlink does;
+    /// so is an address whose AddrInfo has changed (e.g. on passing
+    /// duplicate address detection).
+    fn update(&mut self, new: Snapshot) -> Vec<NetworkEvent> {
+        let mut events = Vec::new();
+        for (ix, ip, prefix) in self.addrs.keys() {
+            if !new.addrs.contains_key(&(*ix, *ip, *prefix)) {
+                events.push(NetworkEvent::DelAddr(*ix, *ip, *prefix));
+            }
+        }
+        for ix in self.links.keys() {
+            if !new.links.contains_key(ix) {
+                events.push(NetworkEvent::DelLink(*ix));
+            }
+        }
+        for (ix, link) in &new.links {
+            if self.links.get(ix) != Some(link) {
+                events.push(NetworkEvent::NewLink(
+                    *ix,
+                    link.0.clone(),
+                    link.1,
+                    link.2.clone(),
+                ));
+            }
+        }
+        for (key, info) in &new.addrs {
+            if self.addrs.get(key) != Some(info) {
+                let (ix, ip, prefix) = *key;
+                events.push(NetworkEvent::NewAddr(ix, ip, prefix, *info));
+            }
+        }
+        *self = new;
+        events
+    }
+}
+
+unsafe extern "system" fn interface_changed(
+    context: *const core::ffi::c_void,
+    _row: *const MIB_IPINTERFACE_ROW,
+    _notification_type: MIB_NOTIFICATION_TYPE,
+) {
+    // SAFETY: context is the Sender owned by the Registration, which
+    // outlives the notification
+    let _ = (*(context as *const Sender)).send(());
+}
+
+unsafe extern "system" fn address_changed(
+    context: *const core::ffi::c_void,
+    _row: *const MIB_UNICASTIPADDRESS_ROW,
+    _notification_type: MIB_NOTIFICATION_TYPE,
+) {
+    // SAFETY: as for interface_changed
+    let _ = (*(context as *const Sender)).send(());
+}
+
+/// The two change notifications, cancelled on drop
+struct Registration {
+    handles: Vec<HANDLE>,
+
+    // Boxed so that its address, as passed to the callbacks, is stable
+    sender: Box<Sender>,
+}
+
+impl Registration {
+    fn new(sender: Sender) -> Result<Self, Error> {
+        let mut r = Self {
+            handles: Vec::new(),
+            sender: Box::new(sender),
+        };
+        let context = &*r.sender as *const Sender as *const core::ffi::c_void;
+        let mut handle: HANDLE = 0;
+
+        // SAFETY: the callbacks only use the context, which lives as
+        // long as the registration does
+        let rc = unsafe {
+            NotifyIpInterfaceChange(
+                AF_UNSPEC,
+                Some(interface_changed),
+                context,
+                0,
+                &mut handle,
+            )
+        };
+        if rc != NO_ERROR {
+            return Err(Error::from_raw_os_error(rc as i32));
+        }
+        r.handles.push(handle);
+
+        // SAFETY: as above
+        let rc = unsafe {
+            NotifyUnicastIpAddressChange(
+                AF_UNSPEC,
+                Some(address_changed),
+                context,
+                0,
+                &mut handle,
+            )
+        };
+        if rc != NO_ERROR {
+            // Dropping `r` cancels the first notification
+            return Err(Error::from_raw_os_error(rc as i32));
+        }
+        r.handles.push(handle);
+        Ok(r)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        for handle in self.handles.drain(..) {
+            // SAFETY: the handle came from Notify*Change. This waits
+            // for any callbacks in progress, so once it has returned
+            // for both handles, the Sender is no longer used.
+            unsafe { CancelMibChangeNotify2(handle) };
+        }
+    }
+}
+
+/** Obtain the current list of network interfaces and a stream of future events
+
+This is the Windows implementation of `get_interfaces_async`, using
+the IP Helper API's `NotifyIpInterfaceChange` and
+`NotifyUnicastIpAddressChange`; see the Linux version for examples,
+which work unchanged here. As there, all interfaces and addresses
+already present are first announced as if newly-added, and
+[`NetworkEvent::NewLink`] events always precede the
+[`NetworkEvent::NewAddr`] events for that interface; then a
+[`NetworkEvent::EnumerationComplete`] marks the end of the first
+listing.
+
+The notifications themselves say little, so each one prompts a fresh
+listing (as by [`get_interfaces`](crate::get_interfaces)) which is
+compared with the previous one. Interface names and flags are as
+reported by that listing. A change in a known interface's flags
+generates a [`NetworkEvent::LinkChanged`]; other changes to it (for
+instance, of its name) are not reported.
+
+# Errors
+
+Returns Err if the change notifications can't be registered. Errors
+in obtaining a listing are passed on as stream items; the stream
+continues after them.
+
+ */
+pub fn get_interfaces_async(
+) -> Result<impl Stream<Item = Result<NetworkEvent, Error>>, Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    // Register before taking the first listing, so that no change can
+    // fall between the two
+    let registration = Registration::new(tx)?;
+
+    Ok(Box::pin(stream! {
+        let _registration = registration;
+        let mut snapshot = Snapshot::default();
+        let mut known = KnownLinks::default();
+        let mut enumerated = false;
+        loop {
+            match get_interfaces() {
+                Ok(events) => {
+                    for event in snapshot
+                        .update(Snapshot::new(events))
+                        .into_iter()
+                        .filter_map(|e| known.filter(e))
+                    {
+                        yield Ok(event);
+                    }
+                    if !enumerated {
+                        enumerated = true;
+                        yield Ok(NetworkEvent::EnumerationComplete);
+                    }
+                }
+                Err(e) => yield Err(e),
+            }
+
+            if rx.recv().await.is_none() {
+                break;
+            }
+
+            // One fresh listing covers any number of notifications
+            while rx.try_recv().is_ok() {}
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_event::AddressFlags;
+    use futures_util::StreamExt;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn make_index(i: u32) -> InterfaceIndex {
+        InterfaceIndex(core::num::NonZeroU32::new(i).unwrap())
+    }
+
+    fn link(i: u32, name: &str, flags: Flags) -> NetworkEvent {
+        NetworkEvent::NewLink(
+            make_index(i),
+            name.to_string(),
+            flags,
+            LinkInfo::default(),
+        )
+    }
+
+    fn addr(i: u32, ip: IpAddr, prefix: u8) -> NetworkEvent {
+        NetworkEvent::NewAddr(make_index(i), ip, prefix, AddrInfo::default())
+    }
+
+    const LAN: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
+    const LINK_LOCAL: IpAddr =
+        IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+
+    #[test]
+    fn initial_listing() {
+        let mut s = Snapshot::default();
+        let events = s.update(Snapshot::new(
+            vec![
+                link(1, "Ethernet", Flags::UP),
+                addr(1, LAN, 24),
+                link(2, "Wi-Fi", Flags::UP),
+                addr(1, LINK_LOCAL, 64),
+            ]
+            .into_iter(),
+        ));
+        assert_eq!(
+            events,
+            vec![
+                link(1, "Ethernet", Flags::UP),
+                link(2, "Wi-Fi", Flags::UP),
+                addr(1, LAN, 24),
+                addr(1, LINK_LOCAL, 64),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_change() {
+        let listing =
+            || vec![link(1, "Ethernet", Flags::UP), addr(1, LAN, 24)];
+        let mut s = Snapshot::new(listing().into_iter());
+        let events = s.update(Snapshot::new(listing().into_iter()));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn address_info_change() {
+        let tentative = NetworkEvent::NewAddr(
+            make_index(1),
+            LINK_LOCAL,
+            64,
+            AddrInfo {
+                flags: AddressFlags::TENTATIVE,
+                ..AddrInfo::default()
+            },
+        );
+        let mut s = Snapshot::new(
+            vec![link(1, "Ethernet", Flags::UP), tentative].into_iter(),
+        );
+        let events = s.update(Snapshot::new(
+            vec![link(1, "Ethernet", Flags::UP), addr(1, LINK_LOCAL, 64)]
+                .into_iter(),
+        ));
+        assert_eq!(events, vec![addr(1, LINK_LOCAL, 64)]);
+    }
+
+    #[test]
+    fn flags_change() {
+        let mut s = Snapshot::new(
+            vec![link(1, "Ethernet", Flags::UP), addr(1, LAN, 24)].into_iter(),
+        );
+        let events = s.update(Snapshot::new(
+            vec![
+                link(1, "Ethernet", Flags::UP | Flags::RUNNING),
+                addr(1, LAN, 24),
+            ]
+            .into_iter(),
+        ));
+        assert_eq!(
+            events,
+            vec![link(1, "Ethernet", Flags::UP | Flags::RUNNING)]
+        );
+    }
+
+    #[test]
+    fn address_change() {
+        let mut s = Snapshot::new(
+            vec![link(1, "Ethernet", Flags::UP), addr(1, LAN, 24)].into_iter(),
+        );
+        let events = s.update(Snapshot::new(
+            vec![link(1, "Ethernet", Flags::UP), addr(1, LAN, 16)].into_iter(),
+        ));
+        assert_eq!(
+            events,
+            vec![
+                NetworkEvent::DelAddr(make_index(1), LAN, 24),
+                addr(1, LAN, 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn interface_removed() {
+        let mut s = Snapshot::new(
+            vec![
+                link(1, "Ethernet", Flags::UP),
+                addr(1, LAN, 24),
+                link(2, "Wi-Fi", Flags::UP),
+            ]
+            .into_iter(),
+        );
+        let events = s.update(Snapshot::new(
+            vec![link(2, "Wi-Fi", Flags::UP)].into_iter(),
+        ));
+        assert_eq!(
+            events,
+            vec![
+                NetworkEvent::DelAddr(make_index(1), LAN, 24),
+                NetworkEvent::DelLink(make_index(1)),
+            ]
+        );
+        assert_eq!(
+            s,
+            Snapshot::new(vec![link(2, "Wi-Fi", Flags::UP)].into_iter())
+        );
+    }
+
+    #[test]
+    fn link_changed() {
+        let mut s = Snapshot::default();
+        let mut known = KnownLinks::default();
+        let mut script = |listing: Vec<NetworkEvent>| {
+            s.update(Snapshot::new(listing.into_iter()))
+                .into_iter()
+                .filter_map(|e| known.filter(e))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            script(vec![link(1, "Ethernet", Flags::MULTICAST)]),
+            vec![link(1, "Ethernet", Flags::MULTICAST)]
+        );
+        assert_eq!(
+            script(vec![link(1, "Ethernet", Flags::UP | Flags::MULTICAST)]),
+            vec![NetworkEvent::LinkChanged(
+                make_index(1),
+                Flags::UP | Flags::MULTICAST
+            )]
+        );
+        assert_eq!(
+            script(vec![link(1, "Ethernet 2", Flags::UP | Flags::MULTICAST)]),
+            vec![]
+        );
+        assert_eq!(
+            script(vec![link(1, "Ethernet 2", Flags::MULTICAST)]),
+            vec![NetworkEvent::LinkChanged(make_index(1), Flags::MULTICAST)]
+        );
+        assert_eq!(script(vec![]), vec![NetworkEvent::DelLink(make_index(1))]);
+    }
+
+    #[tokio::test]
+    async fn zzz_instantiate() {
+        let mut s = get_interfaces_async().unwrap();
+        let first = s.next().await;
+        assert!(matches!(first, Some(Ok(NetworkEvent::NewLink(..)))));
+    }
+}
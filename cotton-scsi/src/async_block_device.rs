@@ -12,6 +12,14 @@ pub struct DeviceInfo {
 
     /// The size of each block
     pub block_size: u32,
+
+    /// The size of each physical block: a multiple of `block_size`
+    ///
+    /// Writing a partial physical block might be slow (the device has
+    /// to do a read-modify-write internally), so layers above should
+    /// write in whole physical blocks where they can. If the device
+    /// doesn't report a physical block size, this is `block_size`.
+    pub physical_block_size: u32,
 }
 
 /// A generic, asynchronous, read/write block device
@@ -60,4 +68,14 @@ pub trait AsyncBlockDevice {
         count: u32,
         data: &[u8],
     ) -> impl Future<Output = Result<(), Self::E>>;
+
+    /// # Make sure all written blocks are stored on the device
+    ///
+    /// Devices may cache written data internally, and only actually
+    /// store it later. This call returns once the data from all
+    /// previous writes is stored persistently. The default
+    /// implementation, for devices with no such cache, does nothing.
+    fn flush(&mut self) -> impl Future<Output = Result<(), Self::E>> {
+        async { Ok(()) }
+    }
 }
@@ -0,0 +1,316 @@
+use super::scsi_device::{ScsiDevice, DEFAULT_MAX_TRANSFER_BYTES};
+use super::scsi_transport::{Error, ScsiTransport};
+
+/// The largest offset, or transfer, expressible in a 10-byte READ
+/// BUFFER or WRITE BUFFER command (24 bits)
+pub const MAX_BUFFER_OFFSET: usize = 0xFF_FFFF;
+
+fn be24(n: u32) -> [u8; 3] {
+    let b = n.to_be_bytes();
+    [b[1], b[2], b[3]]
+}
+
+/// The mode field of READ BUFFER
+/// Seagate SCSI Commands Reference Manual s3.26.1
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ReadBufferMode {
+    /// Read the contents of the buffer
+    Data = 0x02,
+    /// Read the buffer's capacity and offset alignment (see
+    /// [`ScsiDevice::read_buffer_descriptor()`])
+    Descriptor = 0x03,
+    /// Read back the echo buffer (as written by
+    /// [`WriteBufferMode::Echo`])
+    Echo = 0x0A,
+    /// Read the echo buffer's capacity
+    EchoDescriptor = 0x0B,
+}
+
+/// The mode field of WRITE BUFFER
+/// Seagate SCSI Commands Reference Manual s3.60.1
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum WriteBufferMode {
+    /// Write the contents of the buffer
+    Data = 0x02,
+    /// Download microcode in pieces; the device activates it once the
+    /// last piece arrives, but doesn't save it
+    DownloadMicrocodeOffsets = 0x06,
+    /// Download microcode in pieces; the device saves and activates it
+    /// once the last piece arrives
+    DownloadMicrocodeOffsetsSave = 0x07,
+    /// Download microcode in pieces, and save it, but don't activate
+    /// it until [`WriteBufferMode::ActivateDeferred`]
+    DownloadMicrocodeOffsetsDeferred = 0x0E,
+    /// Activate microcode downloaded with
+    /// [`WriteBufferMode::DownloadMicrocodeOffsetsDeferred`]
+    ActivateDeferred = 0x0F,
+    /// Write to the echo buffer, for testing the transport
+    Echo = 0x0A,
+}
+
+/// READ BUFFER (10)
+/// Seagate SCSI Commands Reference Manual s3.26
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ReadBuffer10 {
+    operation_code: u8,
+    mode: u8,
+    buffer_id: u8,
+    buffer_offset: [u8; 3],
+    allocation_length: [u8; 3],
+    control: u8,
+}
+
+impl ReadBuffer10 {
+    fn new(
+        mode: ReadBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        len: u32,
+    ) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x3C,
+            mode: mode as u8,
+            buffer_id,
+            buffer_offset: be24(offset),
+            allocation_length: be24(len),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReadBuffer10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReadBuffer10 {}
+
+/// WRITE BUFFER (10)
+/// Seagate SCSI Commands Reference Manual s3.60
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct WriteBuffer10 {
+    operation_code: u8,
+    mode: u8,
+    buffer_id: u8,
+    buffer_offset: [u8; 3],
+    parameter_list_length: [u8; 3],
+    control: u8,
+}
+
+impl WriteBuffer10 {
+    fn new(
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        len: u32,
+    ) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x3B,
+            mode: mode as u8,
+            buffer_id,
+            buffer_offset: be24(offset),
+            parameter_list_length: be24(len),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for WriteBuffer10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for WriteBuffer10 {}
+
+/// READ BUFFER descriptor
+/// Seagate SCSI Commands Reference Manual s3.26.5
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct BufferDescriptorReply {
+    offset_boundary: u8,
+    buffer_capacity: [u8; 3],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for BufferDescriptorReply {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for BufferDescriptorReply {}
+
+/// The size and alignment requirements of a device buffer
+///
+/// As returned by [`ScsiDevice::read_buffer_descriptor()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct BufferDescriptor {
+    /// Offsets into the buffer must be multiples of this, or `None` if
+    /// only offset zero is allowed
+    pub offset_alignment: Option<u32>,
+
+    /// The size of the buffer, in bytes
+    pub capacity: u32,
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Read from one of the device's buffers
+    ///
+    /// With [`ReadBufferMode::Data`], this reads part of the buffer
+    /// numbered `buffer_id`, starting `offset` bytes in (which may need
+    /// aligning, see [`ScsiDevice::read_buffer_descriptor()`]). What
+    /// the buffers contain is entirely device-specific: many USB-SATA
+    /// bridges, for instance, offer diagnostic dumps this way. Returns
+    /// the number of bytes read, at most [`MAX_BUFFER_OFFSET`].
+    pub async fn read_buffer(
+        &mut self,
+        mode: ReadBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<T::Error>> {
+        if offset as usize > MAX_BUFFER_OFFSET {
+            return Err(Error::ProtocolError);
+        }
+        let len = buf.len().min(MAX_BUFFER_OFFSET);
+        self.command_in(
+            ReadBuffer10::new(mode, buffer_id, offset, len as u32),
+            &mut buf[..len],
+        )
+        .await
+    }
+
+    /// Find out the capacity and alignment requirements of a buffer
+    ///
+    /// Uses READ BUFFER in [`ReadBufferMode::Descriptor`].
+    pub async fn read_buffer_descriptor(
+        &mut self,
+        buffer_id: u8,
+    ) -> Result<BufferDescriptor, Error<T::Error>> {
+        let reply: BufferDescriptorReply = self
+            .command_response(ReadBuffer10::new(
+                ReadBufferMode::Descriptor,
+                buffer_id,
+                0,
+                4,
+            ))
+            .await?;
+        let [a, b, c] = reply.buffer_capacity;
+        Ok(BufferDescriptor {
+            offset_alignment: match reply.offset_boundary {
+                0xFF => None,
+                n => 1u32.checked_shl(n as u32),
+            },
+            capacity: u32::from_be_bytes([0, a, b, c]),
+        })
+    }
+
+    /// Write to one of the device's buffers
+    ///
+    /// See [`WriteBufferMode`] for what the different modes do; the
+    /// microcode-download modes are best used via
+    /// [`ScsiDevice::download_microcode_overwriting_firmware()`], and
+    /// with great care. `data` can be at most [`MAX_BUFFER_OFFSET`]
+    /// bytes. Returns the number of bytes written.
+    pub async fn write_buffer(
+        &mut self,
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<usize, Error<T::Error>> {
+        if offset as usize > MAX_BUFFER_OFFSET
+            || data.len() > MAX_BUFFER_OFFSET
+        {
+            return Err(Error::ProtocolError);
+        }
+        let cmd =
+            WriteBuffer10::new(mode, buffer_id, offset, data.len() as u32);
+        if data.is_empty() {
+            self.command_no_data(cmd).await.map(|_| 0)
+        } else {
+            self.command_out(cmd, data).await
+        }
+    }
+
+    /// Replace the device's firmware with `image`. DANGEROUS.
+    ///
+    /// **If `image` isn't exactly the firmware that this particular
+    /// device expects -- right vendor, right model, right hardware
+    /// revision -- or if power is lost or the device is unplugged
+    /// during the update, the device may well be permanently
+    /// "bricked".** Nothing here can check that the image is suitable;
+    /// that's entirely the caller's responsibility. Don't call this
+    /// without being sure.
+    ///
+    /// The image is sent in pieces, with WRITE BUFFER in
+    /// [`WriteBufferMode::DownloadMicrocodeOffsetsDeferred`] mode, to
+    /// buffer `buffer_id` (usually 0). The pieces are as large as the
+    /// device and transport allow, and a multiple of the alignment
+    /// reported by [`ScsiDevice::read_buffer_descriptor()`], if the
+    /// device supports that (a device which only allows offset zero
+    /// gets the whole image in one piece). Once the whole image is saved, a final
+    /// WRITE BUFFER in [`WriteBufferMode::ActivateDeferred`] mode
+    /// switches to the new firmware; the device typically then resets,
+    /// so after a successful return, expect to have to find and probe
+    /// it afresh.
+    ///
+    /// Fails with `ProtocolError` if the image is empty, or too large
+    /// for the buffer or for the 24-bit offsets of WRITE BUFFER. If any
+    /// command fails, the update stops there, without activating.
+    pub async fn download_microcode_overwriting_firmware(
+        &mut self,
+        buffer_id: u8,
+        image: &[u8],
+    ) -> Result<(), Error<T::Error>> {
+        let mut chunk = self
+            .transport()
+            .max_transfer_bytes()
+            .unwrap_or(DEFAULT_MAX_TRANSFER_BYTES)
+            .min(MAX_BUFFER_OFFSET);
+        match self.read_buffer_descriptor(buffer_id).await {
+            Ok(d) => {
+                if image.len() > d.capacity as usize {
+                    return Err(Error::ProtocolError);
+                }
+                chunk = match d.offset_alignment {
+                    None => image.len(),
+                    Some(align) => chunk - chunk % align as usize,
+                };
+            }
+            // Not knowing the buffer's requirements isn't fatal
+            Err(Error::Scsi(_) | Error::CommandFailed) => {}
+            Err(e) => return Err(e),
+        }
+        if image.is_empty() || chunk == 0 || image.len() > MAX_BUFFER_OFFSET {
+            return Err(Error::ProtocolError);
+        }
+
+        for (i, piece) in image.chunks(chunk).enumerate() {
+            self.write_buffer(
+                WriteBufferMode::DownloadMicrocodeOffsetsDeferred,
+                buffer_id,
+                (i * chunk) as u32,
+                piece,
+            )
+            .await?;
+        }
+        self.write_buffer(WriteBufferMode::ActivateDeferred, buffer_id, 0, &[])
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/buffer.rs"]
+mod tests;
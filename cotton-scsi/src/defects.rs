@@ -0,0 +1,371 @@
+use super::scsi_device::{ScsiDevice, DEFAULT_MAX_TRANSFER_BYTES};
+use super::scsi_transport::{Error, ScsiTransport};
+
+/// The size of the READ DEFECT DATA (10) reply header
+const HEADER_SIZE_10: usize = 4;
+
+/// The size of the READ DEFECT DATA (12) reply header
+const HEADER_SIZE_12: usize = 8;
+
+/// READ DEFECT DATA (10)
+/// SCSI Block Commands (SBC-3), "READ DEFECT DATA (10) command"
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ReadDefectData10 {
+    operation_code: u8,
+    reserved: u8,
+    lists_and_format: u8,
+    reserved2: [u8; 4],
+    allocation_length_be: [u8; 2],
+    control: u8,
+}
+
+impl ReadDefectData10 {
+    fn new(lists: DefectLists, format: DefectFormat, len: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x37,
+            reserved: 0,
+            lists_and_format: lists as u8 | format as u8,
+            reserved2: [0; 4],
+            allocation_length_be: len.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReadDefectData10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReadDefectData10 {}
+
+/// READ DEFECT DATA (12)
+/// SCSI Block Commands (SBC-3), "READ DEFECT DATA (12) command"
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ReadDefectData12 {
+    operation_code: u8,
+    lists_and_format: u8,
+    address_descriptor_index_be: [u8; 4],
+    allocation_length_be: [u8; 4],
+    reserved: u8,
+    control: u8,
+}
+
+impl ReadDefectData12 {
+    fn new(lists: DefectLists, format: DefectFormat, len: u32) -> Self {
+        assert!(core::mem::size_of::<Self>() == 12);
+        Self {
+            operation_code: 0xB7,
+            lists_and_format: lists as u8 | format as u8,
+            address_descriptor_index_be: [0; 4],
+            allocation_length_be: len.to_be_bytes(),
+            reserved: 0,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReadDefectData12 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReadDefectData12 {}
+
+/// Which defect lists READ DEFECT DATA should return
+/// SCSI Block Commands (SBC-3), "READ DEFECT DATA (10) command"
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DefectLists {
+    /// Neither list: just the header, which gives the lists' length
+    HeaderOnly = 0x00,
+    /// The grown list (GLIST): defects found since manufacture
+    Grown = 0x08,
+    /// The primary list (PLIST): defects found at manufacture
+    Primary = 0x10,
+    /// Both lists, combined
+    Both = 0x18,
+}
+
+/// The format of the entries in a defect list
+/// SCSI Block Commands (SBC-3), "Address descriptor formats"
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DefectFormat {
+    /// 32-bit logical block addresses
+    ShortBlock = 0,
+    /// 64-bit logical block addresses
+    LongBlock = 3,
+    /// Cylinder, head, and distance from the index in bytes
+    BytesFromIndex = 4,
+    /// Cylinder, head, and physical sector
+    PhysicalSector = 5,
+    /// Vendor-specific entries (which can't be decoded here)
+    VendorSpecific = 6,
+}
+
+impl DefectFormat {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::ShortBlock),
+            3 => Some(Self::LongBlock),
+            4 => Some(Self::BytesFromIndex),
+            5 => Some(Self::PhysicalSector),
+            6 => Some(Self::VendorSpecific),
+            _ => None,
+        }
+    }
+
+    /// The size of each entry in this format, or `None` if unknown
+    fn entry_size(self) -> Option<usize> {
+        match self {
+            Self::ShortBlock => Some(4),
+            Self::LongBlock | Self::BytesFromIndex | Self::PhysicalSector => {
+                Some(8)
+            }
+            Self::VendorSpecific => None,
+        }
+    }
+}
+
+/// One entry in a defect list
+///
+/// SCSI Block Commands (SBC-3), "Address descriptor formats". Entries in
+/// [`DefectFormat::BytesFromIndex`] or [`DefectFormat::PhysicalSector`]
+/// format with all-ones in the third field mean that the whole track
+/// is defective.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Defect {
+    /// A defective logical block
+    Block(u64),
+    /// A defect at a distance in bytes from the index (start) of a track
+    BytesFromIndex {
+        /// Cylinder number (24 bits)
+        cylinder: u32,
+        /// Head number
+        head: u8,
+        /// Distance from the index, in bytes
+        bytes_from_index: u32,
+    },
+    /// A defective physical sector
+    PhysicalSector {
+        /// Cylinder number (24 bits)
+        cylinder: u32,
+        /// Head number
+        head: u8,
+        /// Sector number
+        sector: u32,
+    },
+}
+
+impl Defect {
+    /// Decode one defect-list entry in the given format
+    ///
+    /// Returns `None` if `bytes` is too short, or the format is
+    /// vendor-specific.
+    pub fn parse(format: DefectFormat, bytes: &[u8]) -> Option<Self> {
+        let size = format.entry_size()?;
+        let bytes = bytes.get(0..size)?;
+        let be32 = |b: &[u8]| u32::from_be_bytes(b.try_into().unwrap());
+        let cylinder = || be32(&bytes[0..4]) >> 8;
+        Some(match format {
+            DefectFormat::ShortBlock => Self::Block(be32(bytes) as u64),
+            DefectFormat::LongBlock => {
+                Self::Block(u64::from_be_bytes(bytes.try_into().unwrap()))
+            }
+            DefectFormat::BytesFromIndex => Self::BytesFromIndex {
+                cylinder: cylinder(),
+                head: bytes[3],
+                bytes_from_index: be32(&bytes[4..8]),
+            },
+            DefectFormat::PhysicalSector => Self::PhysicalSector {
+                cylinder: cylinder(),
+                head: bytes[3],
+                sector: be32(&bytes[4..8]),
+            },
+            DefectFormat::VendorSpecific => return None,
+        })
+    }
+}
+
+/// The reply to READ DEFECT DATA
+///
+/// As returned by [`ScsiDevice::read_defect_data()`]; the entries
+/// themselves are still in the caller's buffer, and are decoded by
+/// [`DefectData::iter()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct DefectData<'a> {
+    /// Whether the primary list is included
+    pub primary_valid: bool,
+
+    /// Whether the grown list is included
+    pub grown_valid: bool,
+
+    /// The format of the entries, or `None` if reserved or unknown
+    ///
+    /// This needn't be the format that was asked for: devices which
+    /// don't support that one, report in a format they do support.
+    pub format: Option<DefectFormat>,
+
+    /// The size of the whole list, in bytes, as reported by the device
+    ///
+    /// This can be larger than was returned, if the buffer was too
+    /// small; see [`DefectData::is_complete()`].
+    pub list_length: u32,
+
+    descriptors: &'a [u8],
+}
+
+impl<'a> DefectData<'a> {
+    fn parse(bytes: &'a [u8], list_length: u32, header: usize) -> Self {
+        let available = (bytes.len() - header).min(list_length as usize);
+        Self {
+            primary_valid: (bytes[1] & 0x10) != 0,
+            grown_valid: (bytes[1] & 0x08) != 0,
+            format: DefectFormat::from_code(bytes[1] & 7),
+            list_length,
+            descriptors: &bytes[header..header + available],
+        }
+    }
+
+    /// Decode a READ DEFECT DATA (10) reply
+    ///
+    /// SCSI Block Commands (SBC-3), "READ DEFECT DATA (10) parameter
+    /// data". Returns `None` if `bytes` is shorter than the header.
+    pub fn parse_10(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE_10 {
+            return None;
+        }
+        let list_length = u16::from_be_bytes([bytes[2], bytes[3]]);
+        Some(Self::parse(bytes, list_length as u32, HEADER_SIZE_10))
+    }
+
+    /// Decode a READ DEFECT DATA (12) reply
+    ///
+    /// SCSI Block Commands (SBC-3), "READ DEFECT DATA (12) parameter
+    /// data". Returns `None` if `bytes` is shorter than the header.
+    pub fn parse_12(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE_12 {
+            return None;
+        }
+        let list_length = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        Some(Self::parse(bytes, list_length, HEADER_SIZE_12))
+    }
+
+    /// The number of entries in the whole list, as reported by the
+    /// device, or `None` if the format is vendor-specific or unknown
+    ///
+    /// This is available even if the list was requested with
+    /// [`DefectLists::HeaderOnly`], or didn't fit in the buffer.
+    pub fn total(&self) -> Option<u32> {
+        let size = self.format?.entry_size()?;
+        Some(self.list_length / size as u32)
+    }
+
+    /// Whether the whole list fitted in the buffer
+    pub fn is_complete(&self) -> bool {
+        self.descriptors.len() == self.list_length as usize
+    }
+
+    /// Iterate over the entries which fitted in the buffer
+    ///
+    /// Yields nothing if the format is vendor-specific or unknown.
+    pub fn iter(&self) -> DefectIter<'a> {
+        DefectIter {
+            format: self.format,
+            bytes: self.descriptors,
+        }
+    }
+}
+
+/// Iterator over the entries in a defect list
+///
+/// See [`DefectData::iter()`].
+pub struct DefectIter<'a> {
+    format: Option<DefectFormat>,
+    bytes: &'a [u8],
+}
+
+impl Iterator for DefectIter<'_> {
+    type Item = Defect;
+
+    fn next(&mut self) -> Option<Defect> {
+        let format = self.format?;
+        let size = format.entry_size()?;
+        let entry = Defect::parse(format, self.bytes)?;
+        self.bytes = &self.bytes[size..];
+        Some(entry)
+    }
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Read the device's lists of defective sectors
+    ///
+    /// Mostly only spinning disks keep defect lists; the length of the
+    /// grown list (defects which have appeared since manufacture) is a
+    /// good guide to a disk's health. Flash devices, and most USB
+    /// bridges in front of them, fail with
+    /// [`ScsiError::InvalidCommandOperationCode`] or
+    /// [`ScsiError::IllegalRequest`].
+    ///
+    /// Uses READ DEFECT DATA (10) if `buf` is smaller than 64KiB, or
+    /// READ DEFECT DATA (12) otherwise (a grown list can be larger than
+    /// READ DEFECT DATA (10) can report). To find out how big a buffer
+    /// is needed, first ask for [`DefectLists::HeaderOnly`].
+    ///
+    /// Fails with `ProtocolError` if the reply is shorter than its
+    /// header.
+    ///
+    /// [`ScsiError::InvalidCommandOperationCode`]: crate::scsi_transport::ScsiError::InvalidCommandOperationCode
+    /// [`ScsiError::IllegalRequest`]: crate::scsi_transport::ScsiError::IllegalRequest
+    pub async fn read_defect_data<'b>(
+        &mut self,
+        lists: DefectLists,
+        format: DefectFormat,
+        buf: &'b mut [u8],
+    ) -> Result<DefectData<'b>, Error<T::Error>> {
+        let len = buf
+            .len()
+            .min(
+                self.transport()
+                    .max_transfer_bytes()
+                    .unwrap_or(DEFAULT_MAX_TRANSFER_BYTES),
+            )
+            .min(u32::MAX as usize);
+        let buf = &mut buf[..len];
+        if len <= u16::MAX as usize {
+            let sz = self
+                .command_in(
+                    ReadDefectData10::new(lists, format, len as u16),
+                    buf,
+                )
+                .await?
+                .min(len);
+            DefectData::parse_10(&buf[..sz]).ok_or(Error::ProtocolError)
+        } else {
+            let sz = self
+                .command_in(
+                    ReadDefectData12::new(lists, format, len as u32),
+                    buf,
+                )
+                .await?
+                .min(len);
+            DefectData::parse_12(&buf[..sz]).ok_or(Error::ProtocolError)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/defects.rs"]
+mod tests;
@@ -0,0 +1,302 @@
+use crate::async_block_device::AsyncBlockDevice;
+use crate::mbr::MAX_BLOCK_SIZE;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// The smallest header size allowed (UEFI 2.10 s5.3.2)
+const MIN_HEADER_SIZE: usize = 92;
+
+/// The part of each partition entry which is defined by the spec; any
+/// further bytes of larger entries are reserved
+const ENTRY_SIZE: usize = 128;
+
+/// The number of UTF-16 code units in a partition name
+pub const NAME_LENGTH: usize = 36;
+
+/// One partition found in a GPT partition table
+///
+/// GUIDs are as stored on disk: that is, in the mixed-endian layout in
+/// which the first three fields are little-endian.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct GptEntry {
+    /// The partition type, e.g. EFI System Partition or Linux filesystem
+    pub type_guid: [u8; 16],
+
+    /// A GUID unique to this partition
+    pub unique_guid: [u8; 16],
+
+    /// The first block of the partition
+    pub first_lba: u64,
+
+    /// The last block of the partition (inclusive)
+    pub last_lba: u64,
+
+    /// Partition attribute flags, e.g. bit 0 "required partition"
+    pub attributes: u64,
+
+    /// The partition's name, as UTF-16 code units, zero-padded
+    ///
+    /// See [`GptEntry::name()`] for a more usable form.
+    pub name_utf16: [u16; NAME_LENGTH],
+}
+
+impl Default for GptEntry {
+    fn default() -> Self {
+        Self {
+            type_guid: [0; 16],
+            unique_guid: [0; 16],
+            first_lba: 0,
+            last_lba: 0,
+            attributes: 0,
+            name_utf16: [0; NAME_LENGTH],
+        }
+    }
+}
+
+impl GptEntry {
+    /// The length of the partition, in blocks
+    pub fn blocks(&self) -> u64 {
+        self.last_lba
+            .saturating_add(1)
+            .saturating_sub(self.first_lba)
+    }
+
+    /// Decode the partition's name into `buf`, as UTF-8
+    ///
+    /// Invalid UTF-16 is replaced by U+FFFD. If `buf` is too small, the
+    /// name is truncated (at a character boundary); 108 bytes is always
+    /// enough.
+    pub fn name<'a>(&self, buf: &'a mut [u8]) -> &'a str {
+        let units = self.name_utf16.iter().copied().take_while(|u| *u != 0);
+        let mut n = 0;
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            if n + c.len_utf8() > buf.len() {
+                break;
+            }
+            c.encode_utf8(&mut buf[n..]);
+            n += c.len_utf8();
+        }
+        // Only whole characters were written
+        core::str::from_utf8(&buf[..n]).unwrap_or("")
+    }
+
+    fn parse(e: &[u8; ENTRY_SIZE]) -> Self {
+        let mut entry = Self {
+            type_guid: e[0..16].try_into().unwrap_or_default(),
+            unique_guid: e[16..32].try_into().unwrap_or_default(),
+            first_lba: le64(&e[32..40]),
+            last_lba: le64(&e[40..48]),
+            attributes: le64(&e[48..56]),
+            ..Self::default()
+        };
+        for (unit, b) in
+            entry.name_utf16.iter_mut().zip(e[56..128].chunks_exact(2))
+        {
+            *unit = u16::from_le_bytes([b[0], b[1]]);
+        }
+        entry
+    }
+}
+
+/// Errors which can arise while reading a GPT partition table
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum GptError<E> {
+    /// Neither header has the "EFI PART" signature
+    NoPartitionTable,
+
+    /// A header has an impossible size, location or entry size
+    BadHeader,
+
+    /// The CRC32 of a header, or of its partition entries, is wrong
+    BadChecksum,
+
+    /// The device's block size is smaller than 512 or larger than
+    /// [`MAX_BLOCK_SIZE`]
+    UnsupportedBlockSize(u32),
+
+    /// The underlying block device reported an error
+    Device(E),
+}
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le64(b: &[u8]) -> u64 {
+    (le32(&b[0..4]) as u64) | ((le32(&b[4..8]) as u64) << 32)
+}
+
+/// Continue a CRC32 (IEEE 802.3, as used by GPT) over more data
+///
+/// Start with `crc = 0`. Table-less, so small but not fast; partition
+/// tables are only read once.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// The fields of a GPT header which locate the partition entries
+struct Header {
+    entries_lba: u64,
+    num_entries: u32,
+    entry_size: u32,
+    entries_crc: u32,
+}
+
+/// Check the header in `sector`, which was read from block `lba` of a
+/// device with `blocks` blocks
+fn parse_header<E>(
+    sector: &[u8],
+    lba: u64,
+    blocks: u64,
+) -> Result<Header, GptError<E>> {
+    if &sector[0..8] != SIGNATURE {
+        return Err(GptError::NoPartitionTable);
+    }
+    let size = le32(&sector[12..16]) as usize;
+    if !(MIN_HEADER_SIZE..=sector.len()).contains(&size) {
+        return Err(GptError::BadHeader);
+    }
+    // The CRC is calculated with the CRC field itself zeroed
+    let crc = crc32_update(0, &sector[0..16]);
+    let crc = crc32_update(crc, &[0; 4]);
+    let crc = crc32_update(crc, &sector[20..size]);
+    if crc != le32(&sector[16..20]) {
+        return Err(GptError::BadChecksum);
+    }
+    let header = Header {
+        entries_lba: le64(&sector[72..80]),
+        num_entries: le32(&sector[80..84]),
+        entry_size: le32(&sector[84..88]),
+        entries_crc: le32(&sector[88..92]),
+    };
+    // Entry sizes must be 128 * 2^n
+    if le64(&sector[24..32]) != lba
+        || header.entry_size < ENTRY_SIZE as u32
+        || !header.entry_size.is_power_of_two()
+    {
+        return Err(GptError::BadHeader);
+    }
+    let bytes = header.num_entries as u64 * header.entry_size as u64;
+    let end = bytes
+        .div_ceil(sector.len() as u64)
+        .checked_add(header.entries_lba);
+    match end {
+        Some(end) if header.entries_lba > 0 && end <= blocks => Ok(header),
+        _ => Err(GptError::BadHeader),
+    }
+}
+
+/// Read, using the header at block `lba`
+async fn read_table<D: AsyncBlockDevice, const N: usize>(
+    dev: &mut D,
+    sector: &mut [u8],
+    lba: u64,
+    blocks: u64,
+    out: &mut [GptEntry; N],
+) -> Result<usize, GptError<D::E>> {
+    dev.read_blocks(lba, 1, sector)
+        .await
+        .map_err(GptError::Device)?;
+    let header = parse_header(sector, lba, blocks)?;
+
+    let entry_size = header.entry_size as u64;
+    let total = header.num_entries as u64 * entry_size;
+    let mut entry = [0u8; ENTRY_SIZE];
+    let mut crc = 0;
+    let mut n = 0;
+    let mut pos = 0u64;
+    let mut lba = header.entries_lba;
+    while pos < total {
+        dev.read_blocks(lba, 1, sector)
+            .await
+            .map_err(GptError::Device)?;
+        let chunk = &sector[..(total - pos).min(sector.len() as u64) as usize];
+        crc = crc32_update(crc, chunk);
+
+        // Gather the defined part of each entry; skip the rest
+        let mut i = 0;
+        while i < chunk.len() {
+            let offset = ((pos + i as u64) % entry_size) as usize;
+            let left = chunk.len() - i;
+            if offset < ENTRY_SIZE {
+                let take = (ENTRY_SIZE - offset).min(left);
+                entry[offset..offset + take]
+                    .copy_from_slice(&chunk[i..i + take]);
+                i += take;
+                if offset + take == ENTRY_SIZE
+                    && entry[0..16] != [0; 16]
+                    && n < N
+                {
+                    out[n] = GptEntry::parse(&entry);
+                    n += 1;
+                }
+            } else {
+                i += (entry_size as usize - offset).min(left);
+            }
+        }
+        pos += chunk.len() as u64;
+        lba += 1;
+    }
+
+    if crc != header.entries_crc {
+        return Err(GptError::BadChecksum);
+    }
+    Ok(n)
+}
+
+/// Read the GPT partition table of a block device
+///
+/// Stores the partitions found in `out`, in the order of their entries
+/// in the table, and returns how many there were. Unused entries are
+/// skipped. If there are more than `N` partitions, only the first `N`
+/// are returned (the whole table is still read, to check its CRC).
+///
+/// The primary header, in block 1, is used if it's valid; if not, the
+/// backup header in the last block is tried instead. If that's also
+/// invalid, the error returned is the one found with the primary
+/// header. Following an error, the contents of `out` are unspecified.
+///
+/// Disks with a GPT usually also have a "protective" MBR, for which
+/// [`mbr::read_partitions()`](crate::mbr::read_partitions) returns a
+/// single entry of type [`mbr::PROTECTIVE`](crate::mbr::PROTECTIVE).
+pub async fn read_partitions<D: AsyncBlockDevice, const N: usize>(
+    dev: &mut D,
+    out: &mut [GptEntry; N],
+) -> Result<usize, GptError<D::E>> {
+    let info = dev.device_info().await.map_err(GptError::Device)?;
+    let bs = info.block_size as usize;
+    if !(512..=MAX_BLOCK_SIZE).contains(&bs) {
+        return Err(GptError::UnsupportedBlockSize(info.block_size));
+    }
+    let mut buf = [0u8; MAX_BLOCK_SIZE];
+    let sector = &mut buf[..bs];
+
+    let blocks = info.blocks;
+    match read_table(dev, sector, 1, blocks, out).await {
+        Err(GptError::Device(e)) => Err(GptError::Device(e)),
+        Err(e) if blocks > 2 => {
+            match read_table(dev, sector, blocks - 1, blocks, out).await {
+                Err(GptError::Device(e)) => Err(GptError::Device(e)),
+                Err(_) => Err(e),
+                rc => rc,
+            }
+        }
+        rc => rc,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/gpt.rs"]
+mod tests;
@@ -0,0 +1,400 @@
+use crate::scsi_transport::{DataPhase, Error, ScsiTransport, SenseData};
+use crate::testing::{fixed_sense, sense};
+use std::fs::File;
+use std::future::{ready, Future};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Where the emulated disk's contents live
+#[derive(Debug)]
+enum Storage {
+    Memory(Vec<u8>),
+    File(File),
+}
+
+/// An emulated SCSI disk, backed by an in-memory or on-disk image
+///
+/// Unlike [`FakeScsiTransport`](crate::testing::FakeScsiTransport),
+/// which replays a fixed script, this actually implements (a useful
+/// subset of) the SCSI block commands against its disk image: INQUIRY,
+/// TEST UNIT READY, READ CAPACITY(10) and (16), READ and WRITE (10)
+/// and (16), SYNCHRONIZE CACHE, MODE SENSE(6) and (10), and REQUEST SENSE.
+/// Errors, such as reads beyond the end of the disk, are reported with
+/// the same sense data that a real disk would report. So code layered
+/// on top of [`ScsiDevice`](crate::ScsiDevice) can be exercised
+/// end-to-end in tests, with no hardware or scripting.
+///
+/// Commands complete immediately, so its futures can be run with, for
+/// instance, `futures::FutureExt::now_or_never()`.
+///
+/// ```
+/// use cotton_scsi::image_transport::ImageTransport;
+/// use cotton_scsi::ScsiDevice;
+/// use futures::FutureExt;
+///
+/// let mut device = ScsiDevice::new(ImageTransport::new(512, 2048));
+/// let capacity = device.read_capacity().now_or_never().unwrap();
+/// assert_eq!(capacity.unwrap(), (2048, 512));
+/// ```
+#[derive(Debug)]
+pub struct ImageTransport {
+    storage: Storage,
+    block_size: u32,
+    blocks: u64,
+    sense: SenseData,
+    write_protected: bool,
+    physical_block_exponent: u8,
+    fail_every_nth_write: Option<u32>,
+    writes: u32,
+
+    /// The value to return from [`ScsiTransport::max_transfer_bytes()`]
+    pub max_transfer_bytes: Option<usize>,
+}
+
+impl ImageTransport {
+    /// Create a zero-filled, in-memory disk image
+    ///
+    /// The block size is usually 512 or 4096.
+    pub fn new(block_size: u32, blocks: u64) -> Self {
+        Self::from_vec(
+            vec![0; block_size as usize * blocks as usize],
+            block_size,
+        )
+    }
+
+    /// Use an existing in-memory disk image
+    ///
+    /// Any partial block at the end of `data` is ignored.
+    pub fn from_vec(data: Vec<u8>, block_size: u32) -> Self {
+        let blocks = (data.len() / block_size as usize) as u64;
+        Self::with_storage(Storage::Memory(data), block_size, blocks)
+    }
+
+    /// Use a file as the disk image
+    ///
+    /// The file should be opened for both reading and writing, unless
+    /// [`ImageTransport::set_write_protected()`] is also used. Any
+    /// partial block at the end of the file is ignored.
+    pub fn from_file(file: File, block_size: u32) -> std::io::Result<Self> {
+        let blocks = file.metadata()?.len() / block_size as u64;
+        Ok(Self::with_storage(Storage::File(file), block_size, blocks))
+    }
+
+    fn with_storage(storage: Storage, block_size: u32, blocks: u64) -> Self {
+        assert!(block_size > 0);
+        Self {
+            storage,
+            block_size,
+            blocks,
+            sense: SenseData::default(),
+            write_protected: false,
+            physical_block_exponent: 0,
+            fail_every_nth_write: None,
+            writes: 0,
+            max_transfer_bytes: None,
+        }
+    }
+
+    /// The in-memory disk image, if that's what's being used
+    pub fn data(&self) -> Option<&[u8]> {
+        match &self.storage {
+            Storage::Memory(v) => Some(v),
+            Storage::File(_) => None,
+        }
+    }
+
+    /// Make all writes fail, with a DATA PROTECT error
+    pub fn set_write_protected(&mut self, write_protected: bool) {
+        self.write_protected = write_protected;
+    }
+
+    /// Report physical blocks of 2^`exponent` logical blocks
+    ///
+    /// This is what READ CAPACITY (16) tells the host; e.g. 3, with
+    /// 512-byte blocks, makes a "512e" drive with 4096-byte physical
+    /// blocks. The image itself is unaffected.
+    pub fn set_physical_block_exponent(&mut self, exponent: u8) {
+        self.physical_block_exponent = exponent & 0x0F;
+    }
+
+    /// Fault injection: fail every nth write command with a medium error
+    ///
+    /// The failing LBA is reported in the sense data's information
+    /// field. `None` (the default) means writes don't fail.
+    pub fn fail_every_nth_write(&mut self, n: Option<u32>) {
+        self.fail_every_nth_write = n;
+        self.writes = 0;
+    }
+
+    /// Record sense data and report failure
+    fn check_condition(&mut self, sense_data: SenseData) -> Error<()> {
+        self.sense = sense_data;
+        Error::CommandFailed
+    }
+
+    fn illegal_request(&mut self, asc: u8) -> Error<()> {
+        self.check_condition(sense(5, asc, 0))
+    }
+
+    fn reply(buf: &mut [u8], allocation_length: usize, data: &[u8]) -> usize {
+        let n = data.len().min(allocation_length).min(buf.len());
+        buf[0..n].copy_from_slice(&data[0..n]);
+        n
+    }
+
+    fn inquiry(
+        &mut self,
+        cmd: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let allocation_length = u16::from_be_bytes([cmd[3], cmd[4]]) as usize;
+        if (cmd[1] & 1) != 0 {
+            // Vital product data: only the list of supported pages
+            if cmd[2] != 0 {
+                return Err(self.illegal_request(0x24));
+            }
+            return Ok(Self::reply(buf, allocation_length, &[0, 0, 0, 1, 0]));
+        }
+        let mut data = [0u8; 36];
+        data[2] = 6; // SPC-4
+        data[3] = 2; // response data format
+        data[4] = 31; // additional length
+        data[8..16].copy_from_slice(b"cotton  ");
+        data[16..32].copy_from_slice(b"ImageTransport  ");
+        data[32..36].copy_from_slice(b"0.1 ");
+        Ok(Self::reply(buf, allocation_length, &data))
+    }
+
+    fn read_capacity_10(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let last =
+            u32::try_from(self.blocks.saturating_sub(1)).unwrap_or(u32::MAX);
+        let mut data = [0u8; 8];
+        data[0..4].copy_from_slice(&last.to_be_bytes());
+        data[4..8].copy_from_slice(&self.block_size.to_be_bytes());
+        Ok(Self::reply(buf, 8, &data))
+    }
+
+    fn read_capacity_16(
+        &mut self,
+        cmd: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let allocation_length = be_u32(&cmd[10..14]) as usize;
+        let mut data = [0u8; 32];
+        data[0..8]
+            .copy_from_slice(&self.blocks.saturating_sub(1).to_be_bytes());
+        data[8..12].copy_from_slice(&self.block_size.to_be_bytes());
+        data[13] = self.physical_block_exponent;
+        Ok(Self::reply(buf, allocation_length, &data))
+    }
+
+    fn mode_sense_6(
+        &mut self,
+        cmd: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let data = [3, 0, if self.write_protected { 0x80 } else { 0 }, 0];
+        Ok(Self::reply(buf, cmd[4] as usize, &data))
+    }
+
+    fn mode_sense_10(
+        &mut self,
+        cmd: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let allocation_length = u16::from_be_bytes([cmd[7], cmd[8]]) as usize;
+        let wp = if self.write_protected { 0x80 } else { 0 };
+        let data = [0, 6, 0, wp, 0, 0, 0, 0];
+        Ok(Self::reply(buf, allocation_length, &data))
+    }
+
+    fn request_sense(
+        &mut self,
+        cmd: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let data = fixed_sense(&self.sense);
+        self.sense = SenseData::default();
+        Ok(Self::reply(buf, cmd[4] as usize, &data))
+    }
+
+    /// Check the range of a read or write, returning the byte offset and
+    /// length
+    fn range(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf_len: usize,
+    ) -> Result<(u64, usize), Error<()>> {
+        match lba.checked_add(count as u64) {
+            Some(end) if end <= self.blocks => {}
+            _ => return Err(self.check_condition(sense(5, 0x21, 0))),
+        }
+        let len = count as usize * self.block_size as usize;
+        if buf_len < len {
+            return Err(Error::ProtocolError);
+        }
+        Ok((lba * self.block_size as u64, len))
+    }
+
+    fn read(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let (offset, len) = self.range(lba, count, buf.len())?;
+        let buf = &mut buf[0..len];
+        match &mut self.storage {
+            Storage::Memory(v) => {
+                buf.copy_from_slice(
+                    &v[offset as usize..offset as usize + len],
+                );
+            }
+            Storage::File(f) => {
+                if f.seek(SeekFrom::Start(offset))
+                    .and_then(|_| f.read_exact(buf))
+                    .is_err()
+                {
+                    return Err(self.check_condition(SenseData {
+                        information: Some(lba),
+                        ..sense(3, 0x11, 0)
+                    }));
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn write(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf: &[u8],
+    ) -> Result<usize, Error<()>> {
+        if self.write_protected {
+            return Err(self.check_condition(sense(7, 0x27, 0)));
+        }
+        let (offset, len) = self.range(lba, count, buf.len())?;
+        if let Some(n) = self.fail_every_nth_write {
+            self.writes += 1;
+            if self.writes >= n {
+                self.writes = 0;
+                return Err(self.check_condition(SenseData {
+                    information: Some(lba),
+                    ..sense(3, 0x0C, 0)
+                }));
+            }
+        }
+        let buf = &buf[0..len];
+        match &mut self.storage {
+            Storage::Memory(v) => {
+                v[offset as usize..offset as usize + len].copy_from_slice(buf);
+            }
+            Storage::File(f) => {
+                if f.seek(SeekFrom::Start(offset))
+                    .and_then(|_| f.write_all(buf))
+                    .is_err()
+                {
+                    return Err(self.check_condition(SenseData {
+                        information: Some(lba),
+                        ..sense(3, 0x0C, 0)
+                    }));
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn synchronize_cache(&mut self) -> Result<usize, Error<()>> {
+        if let Storage::File(f) = &mut self.storage {
+            if f.flush().and_then(|_| f.sync_data()).is_err() {
+                return Err(self.check_condition(sense(3, 0x0C, 0)));
+            }
+        }
+        Ok(0)
+    }
+
+    fn execute(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+    ) -> Result<usize, Error<()>> {
+        let Some(&opcode) = cmd.first() else {
+            return Err(Error::ProtocolError);
+        };
+        let expected_len = match opcode {
+            0..=0x1F => 6,
+            0x20..=0x5F => 10,
+            0x80..=0x9F => 16,
+            _ => return Err(self.illegal_request(0x20)),
+        };
+        if cmd.len() < expected_len {
+            return Err(Error::ProtocolError);
+        }
+        match (opcode, data) {
+            (0x00, DataPhase::None) => Ok(0),
+            (0x03, DataPhase::In(buf)) => self.request_sense(cmd, buf),
+            (0x12, DataPhase::In(buf)) => self.inquiry(cmd, buf),
+            (0x1A, DataPhase::In(buf)) => self.mode_sense_6(cmd, buf),
+            (0x25, DataPhase::In(buf)) => self.read_capacity_10(buf),
+            (0x5A, DataPhase::In(buf)) => self.mode_sense_10(cmd, buf),
+            (0x28, DataPhase::In(buf)) => {
+                let count = u16::from_be_bytes([cmd[7], cmd[8]]) as u32;
+                self.read(be_u32(&cmd[2..6]) as u64, count, buf)
+            }
+            (0x2A, DataPhase::Out(buf)) => {
+                let count = u16::from_be_bytes([cmd[7], cmd[8]]) as u32;
+                self.write(be_u32(&cmd[2..6]) as u64, count, buf)
+            }
+            (0x35 | 0x91, DataPhase::None) => self.synchronize_cache(),
+            (0x88, DataPhase::In(buf)) => {
+                self.read(be_u64(&cmd[2..10]), be_u32(&cmd[10..14]), buf)
+            }
+            (0x8A, DataPhase::Out(buf)) => {
+                self.write(be_u64(&cmd[2..10]), be_u32(&cmd[10..14]), buf)
+            }
+            (0x9E, DataPhase::In(buf)) if (cmd[1] & 0x1F) == 0x10 => {
+                self.read_capacity_16(cmd, buf)
+            }
+            (
+                0x00 | 0x03 | 0x12 | 0x1A | 0x25 | 0x28 | 0x2A | 0x35 | 0x5A
+                | 0x88 | 0x8A | 0x91,
+                _,
+            ) => Err(Error::ProtocolError),
+            _ => Err(self.illegal_request(0x20)),
+        }
+    }
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn be_u64(b: &[u8]) -> u64 {
+    let mut a = [0u8; 8];
+    a.copy_from_slice(&b[0..8]);
+    u64::from_be_bytes(a)
+}
+
+impl ScsiTransport for ImageTransport {
+    type Error = ();
+
+    fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+    ) -> impl Future<Output = Result<usize, Error<()>>> {
+        ready(self.execute(cmd, data))
+    }
+
+    fn max_transfer_bytes(&self) -> Option<usize> {
+        self.max_transfer_bytes
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/image_transport.rs"]
+mod tests;
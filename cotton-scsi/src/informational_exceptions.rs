@@ -0,0 +1,276 @@
+use super::scsi_device::{PageControl, ScsiDevice, MODE_PAGE_BUFFER_SIZE};
+use super::scsi_transport::{Error, ScsiError, ScsiTransport};
+
+/// The size of the Informational Exceptions Control mode page
+const PAGE_SIZE: usize = 12;
+
+/// How the device reports informational exceptions (MRIE)
+/// SCSI Primary Commands (SPC-4), "Informational Exceptions Control
+/// mode page"
+///
+/// In each case the exception is reported with ASC 5Dh, which is
+/// mapped onto [`ScsiError::FailurePredicted`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ReportingMethod {
+    /// Not reported at all
+    NoReporting,
+    /// Reported as a Unit Attention, failing the next command
+    UnitAttention,
+    /// Reported as a Recovered Error, on the next command which would
+    /// otherwise have succeeded -- if the device reports recovered
+    /// errors at all (see the Read-Write Error Recovery mode page)
+    ConditionalRecoveredError,
+    /// Reported as a Recovered Error, on the next command which would
+    /// otherwise have succeeded
+    UnconditionalRecoveredError,
+    /// Reported with sense key "no sense", on the next command which
+    /// would otherwise have succeeded
+    NoSense,
+    /// Reported only in reply to REQUEST SENSE; see
+    /// [`ScsiDevice::check_informational_exception()`]
+    OnRequest,
+    /// Obsolete, reserved, or vendor-specific
+    Other(u8),
+}
+
+impl ReportingMethod {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0 => Self::NoReporting,
+            2 => Self::UnitAttention,
+            3 => Self::ConditionalRecoveredError,
+            4 => Self::UnconditionalRecoveredError,
+            5 => Self::NoSense,
+            6 => Self::OnRequest,
+            n => Self::Other(n),
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Self::NoReporting => 0,
+            Self::UnitAttention => 2,
+            Self::ConditionalRecoveredError => 3,
+            Self::UnconditionalRecoveredError => 4,
+            Self::NoSense => 5,
+            Self::OnRequest => 6,
+            Self::Other(n) => n & 0xF,
+        }
+    }
+}
+
+/// The Informational Exceptions Control mode page (0x1C)
+///
+/// SCSI Primary Commands (SPC-4), "Informational Exceptions Control
+/// mode page". "Informational exceptions" are the SCSI face of
+/// failure prediction, or SMART: this page controls whether the
+/// device reports them, and how. Fields of the page which aren't
+/// represented here are written back as they were read.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct InformationalExceptionsPage {
+    /// Whether the device can save the page, so that it survives a
+    /// power cycle (see
+    /// [`ScsiDevice::set_informational_exceptions_page()`])
+    pub saveable: bool,
+    /// Whether the device may skip failure prediction if it would
+    /// slow down commands (PERF)
+    pub performance: bool,
+    /// Whether warnings, as well as failure predictions, are reported
+    /// (EWASC)
+    pub enable_warnings: bool,
+    /// Whether failure prediction is *disabled* (DEXCPT)
+    pub disable_exceptions: bool,
+    /// Whether the device reports a fake failure prediction, with ASCQ
+    /// 0xFF, to test the reporting path (TEST)
+    pub test: bool,
+    /// Whether exceptions are logged, in the Informational Exceptions
+    /// log page (LOGERR)
+    pub log_errors: bool,
+    /// How exceptions are reported (MRIE)
+    pub reporting_method: ReportingMethod,
+    /// How often the device checks for exceptions, in units of 100ms
+    /// (zero means vendor-specific)
+    pub interval_timer_100ms: u32,
+    /// How many times an exception is reported (zero means no limit)
+    pub report_count: u32,
+
+    /// The page as read, for the fields not represented above
+    raw: [u8; PAGE_SIZE],
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[0..4].try_into().unwrap())
+}
+
+impl InformationalExceptionsPage {
+    /// The mode page code for the Informational Exceptions Control page
+    pub const PAGE_CODE: u8 = 0x1C;
+
+    /// Decode the page, starting at its page-code byte
+    ///
+    /// Returns `None` if `bytes` is too short, or isn't this page.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 2 || (bytes[0] & 0x3F) != Self::PAGE_CODE {
+            return None;
+        }
+        let bytes = bytes.get(0..PAGE_SIZE)?;
+        if 2 + (bytes[1] as usize) < PAGE_SIZE {
+            return None;
+        }
+        let mut raw = [0u8; PAGE_SIZE];
+        raw.copy_from_slice(bytes);
+        raw[1] = (PAGE_SIZE - 2) as u8;
+        Some(Self {
+            saveable: (raw[0] & 0x80) != 0,
+            performance: (raw[2] & 0x80) != 0,
+            enable_warnings: (raw[2] & 0x10) != 0,
+            disable_exceptions: (raw[2] & 0x08) != 0,
+            test: (raw[2] & 0x04) != 0,
+            log_errors: (raw[2] & 0x01) != 0,
+            reporting_method: ReportingMethod::from_code(raw[3] & 0xF),
+            interval_timer_100ms: be32(&raw[4..]),
+            report_count: be32(&raw[8..]),
+            raw,
+        })
+    }
+
+    /// Encode the page, for MODE SELECT
+    ///
+    /// The PS bit is cleared, as MODE SELECT requires.
+    pub fn to_bytes(&self) -> [u8; PAGE_SIZE] {
+        let mut bytes = self.raw;
+        bytes[0] &= 0x3F;
+        for (set, bit) in [
+            (self.performance, 0x80),
+            (self.enable_warnings, 0x10),
+            (self.disable_exceptions, 0x08),
+            (self.test, 0x04),
+            (self.log_errors, 0x01),
+        ] {
+            if set {
+                bytes[2] |= bit;
+            } else {
+                bytes[2] &= !bit;
+            }
+        }
+        bytes[3] = (bytes[3] & 0xF0) | self.reporting_method.code();
+        bytes[4..8].copy_from_slice(&self.interval_timer_100ms.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.report_count.to_be_bytes());
+        bytes
+    }
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Read the Informational Exceptions Control mode page
+    ///
+    /// Uses MODE SENSE(10). Fails with `ProtocolError` if the reply
+    /// doesn't contain the page; devices without failure prediction
+    /// typically fail with `Error::Scsi(ScsiError::InvalidFieldInCDB)`
+    /// instead.
+    pub async fn informational_exceptions_page(
+        &mut self,
+        control: PageControl,
+    ) -> Result<InformationalExceptionsPage, Error<T::Error>> {
+        let mut buf = [0u8; MODE_PAGE_BUFFER_SIZE];
+        let page = self
+            .mode_page(
+                InformationalExceptionsPage::PAGE_CODE,
+                control,
+                &mut buf,
+            )
+            .await?;
+        InformationalExceptionsPage::parse(page).ok_or(Error::ProtocolError)
+    }
+
+    /// Write the Informational Exceptions Control mode page
+    ///
+    /// Uses MODE SELECT(10). If `save` is false, the new values last
+    /// only until the device is next power-cycled or reset, when the
+    /// saved values are restored. If `save` is true, the values become
+    /// the saved values too, and so persist -- but the device rejects
+    /// that if the page isn't
+    /// [`InformationalExceptionsPage::saveable`].
+    pub async fn set_informational_exceptions_page(
+        &mut self,
+        page: &InformationalExceptionsPage,
+        save: bool,
+    ) -> Result<(), Error<T::Error>> {
+        self.set_mode_page(&page.to_bytes(), save).await
+    }
+
+    /// Turn on failure prediction, reported only on request
+    ///
+    /// Clears DEXCPT and TEST, and sets MRIE to
+    /// [`ReportingMethod::OnRequest`], so that exceptions don't
+    /// disturb normal commands and are found by
+    /// [`ScsiDevice::check_informational_exception()`] instead. The
+    /// other fields of the page are unaffected. See
+    /// [`ScsiDevice::set_informational_exceptions_page()`] for the
+    /// meaning of `save`.
+    pub async fn enable_failure_prediction(
+        &mut self,
+        save: bool,
+    ) -> Result<(), Error<T::Error>> {
+        let mut page = self
+            .informational_exceptions_page(PageControl::Current)
+            .await?;
+        page.disable_exceptions = false;
+        page.test = false;
+        page.reporting_method = ReportingMethod::OnRequest;
+        self.set_informational_exceptions_page(&page, save).await
+    }
+
+    /// Ask the device whether it predicts its own failure
+    ///
+    /// Issues REQUEST SENSE, which is where the device reports an
+    /// informational exception if it's set to
+    /// [`ReportingMethod::OnRequest`]; if that shows nothing, issues
+    /// TEST UNIT READY, which picks up exceptions reported with the
+    /// other methods. Returns `Ok(())` if neither command reports
+    /// anything, or `Err(Error::Scsi(ScsiError::FailurePredicted(ascq)))`
+    /// if a failure is predicted; other errors, such as the device not
+    /// being ready, are returned too.
+    ///
+    /// Devices check for failures only now and then (see
+    /// [`InformationalExceptionsPage::interval_timer_100ms`]), so there's
+    /// no point calling this very often:
+    ///
+    /// ```no_run
+    /// # use cotton_scsi::{Error, ScsiDevice, ScsiTransport};
+    /// # use cotton_scsi::scsi_transport::ScsiError;
+    /// # async fn foo<T: ScsiTransport>(
+    /// #     mut device: ScsiDevice<T>,
+    /// #     sleep_secs: impl Fn(u32) -> core::future::Ready<()>,
+    /// # ) -> Result<(), Error<T::Error>> {
+    /// device.enable_failure_prediction(false).await?;
+    /// loop {
+    ///     match device.check_informational_exception().await {
+    ///         Ok(()) => {}
+    ///         Err(Error::Scsi(ScsiError::FailurePredicted(ascq))) => {
+    ///             println!("Disk predicts its own failure ({ascq:#x})");
+    ///             println!("Back up its contents now!");
+    ///         }
+    ///         Err(e) => return Err(e),
+    ///     }
+    ///     sleep_secs(3600).await;
+    /// }
+    /// # }
+    /// ```
+    pub async fn check_informational_exception(
+        &mut self,
+    ) -> Result<(), Error<T::Error>> {
+        let sense = self.unsolicited_sense().await?;
+        if sense.asc == 0x5D {
+            return Err(Error::Scsi(ScsiError::FailurePredicted(sense.ascq)));
+        }
+        self.test_unit_ready().await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/informational_exceptions.rs"]
+mod tests;
@@ -9,14 +9,23 @@ mod debug;
 
 /// A generic SCSI device
 pub mod scsi_device;
-pub use scsi_device::{PeripheralType, ScsiDevice};
+pub use scsi_device::{
+    AccessFlags, DeviceCapabilities, EjectSummary, InquiryData, MediaState,
+    PageControl, PeripheralQualifier, PeripheralType, ProbeHints, ScsiDevice,
+    ScsiStats, SelfTest, SpcVersion,
+};
 
 /// An abstract communication channel with a SCSI device
 ///
 /// Usually, these days, not actual SCSI hardware, but instead SCSI
 /// tunnelled over something else (USB, ATAPI).
 pub mod scsi_transport;
-pub use scsi_transport::{Error, ScsiTransport};
+pub use scsi_transport::{
+    Error, ScsiQuirks, ScsiStatus, ScsiTransport, SenseData, TransferError,
+};
+
+/// Per-command timeouts for SCSI transports
+pub mod timeout;
 
 /// A generic asynchronous block device with a "read/write blocks" interface
 pub mod async_block_device;
@@ -25,3 +34,43 @@ pub use async_block_device::{AsyncBlockDevice, DeviceInfo};
 /// Implementing AsyncBlockDevice in terms of ScsiDevice
 pub mod scsi_block_device;
 pub use scsi_block_device::ScsiBlockDevice;
+
+/// Reading MBR (PC-style) partition tables
+pub mod mbr;
+
+/// Reading GPT (GUID Partition Table) partition tables
+pub mod gpt;
+
+/// Multimedia (MMC) commands for optical drives: CD, DVD, etc.
+pub mod optical;
+
+/// READ BUFFER and WRITE BUFFER: device diagnostics and firmware updates
+pub mod buffer;
+
+/// Zoned block devices (ZBC): REPORT ZONES and zone management
+pub mod zoned;
+
+/// READ DEFECT DATA: a disk's lists of defective sectors
+pub mod defects;
+
+/// The Power Condition mode page: idle and spin-down timers
+pub mod power_condition;
+
+/// Failure prediction (SMART): the Informational Exceptions Control page
+pub mod informational_exceptions;
+
+/// Byte-addressed access to block devices, using `embedded-storage-async`
+#[cfg(feature = "embedded-storage")]
+pub mod storage;
+
+/// A scripted fake ScsiTransport, for writing unit tests
+#[cfg(feature = "std")]
+pub mod testing;
+
+/// An emulated SCSI disk, for end-to-end testing without hardware
+#[cfg(feature = "std")]
+pub mod image_transport;
+
+/// A ScsiTransport for Linux SCSI generic (/dev/sg*) devices
+#[cfg(all(feature = "sg", target_os = "linux"))]
+pub mod sg;
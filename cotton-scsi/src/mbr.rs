@@ -0,0 +1,190 @@
+use crate::async_block_device::AsyncBlockDevice;
+
+/// The largest block size supported when reading partition tables
+pub const MAX_BLOCK_SIZE: usize = 4096;
+
+/// The partition type of a protective MBR's single entry
+///
+/// A disk partitioned with GPT has an MBR containing just one partition,
+/// of this type, covering the whole disk -- to stop MBR-only tools from
+/// considering the disk unpartitioned. An entry of this type means that
+/// the GPT, not the MBR, should be consulted.
+pub const PROTECTIVE: u8 = 0xEE;
+
+/// Extended partitions are followed through at most this many EBRs
+///
+/// This bounds the work done on a corrupt (e.g. circular) chain.
+pub const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const TABLE_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+
+/// One partition found in an MBR partition table
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// The partition type, e.g. 0x0C for FAT32 or 0x83 for Linux
+    pub partition_type: u8,
+
+    /// Whether the partition is marked "active" (bootable)
+    pub bootable: bool,
+
+    /// Whether this is a logical partition, inside an extended partition
+    pub logical: bool,
+
+    /// The first block of the partition, counted from the start of the disk
+    pub start: u64,
+
+    /// The length of the partition, in blocks
+    pub blocks: u64,
+}
+
+impl PartitionEntry {
+    /// Is this the entry of a protective MBR, meaning the disk uses GPT?
+    pub fn is_protective(&self) -> bool {
+        self.partition_type == PROTECTIVE
+    }
+
+    /// Is this an extended partition: a container of logical partitions?
+    pub fn is_extended(&self) -> bool {
+        matches!(self.partition_type, 0x05 | 0x0F | 0x85)
+    }
+}
+
+/// Errors which can arise while reading an MBR partition table
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MbrError<E> {
+    /// Block 0 doesn't end with the 0x55AA signature of an MBR
+    NoPartitionTable,
+
+    /// The device's block size is smaller than 512 or larger than
+    /// [`MAX_BLOCK_SIZE`]
+    UnsupportedBlockSize(u32),
+
+    /// The underlying block device reported an error
+    Device(E),
+}
+
+/// Decode the four entries of the partition table in an MBR or EBR
+///
+/// Returns `None` if the signature is missing. Empty entries have a
+/// partition type of zero.
+fn parse_table(sector: &[u8]) -> Option<[PartitionEntry; 4]> {
+    if sector.get(510..512) != Some(&SIGNATURE) {
+        return None;
+    }
+    let mut entries = [PartitionEntry::default(); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let e = &sector[TABLE_OFFSET + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        let start = u32::from_le_bytes([e[8], e[9], e[10], e[11]]);
+        let blocks = u32::from_le_bytes([e[12], e[13], e[14], e[15]]);
+        *entry = PartitionEntry {
+            partition_type: e[4],
+            bootable: (e[0] & 0x80) != 0,
+            logical: false,
+            start: start as u64,
+            blocks: blocks as u64,
+        };
+    }
+    Some(entries)
+}
+
+/// Store `entry` in the next free slot, returning false if there is none
+fn push(
+    out: &mut [PartitionEntry],
+    n: &mut usize,
+    entry: PartitionEntry,
+) -> bool {
+    if let Some(slot) = out.get_mut(*n) {
+        *slot = entry;
+        *n += 1;
+        true
+    } else {
+        false
+    }
+}
+
+/// Read the MBR partition table of a block device
+///
+/// Stores the partitions found in `out`, and returns how many there
+/// were. Primary partitions come first, in the order of their table
+/// entries, followed by any logical partitions found by following the
+/// chain of EBRs (extended boot records) in an extended partition;
+/// extended partitions themselves aren't listed, and nor are empty
+/// entries. If there are more than `N` partitions, only the first `N`
+/// are returned.
+///
+/// A disk partitioned with GPT returns a single entry for which
+/// [`PartitionEntry::is_protective()`] is true.
+///
+/// A broken EBR chain -- a missing signature, a read error, or a chain
+/// longer than [`MAX_LOGICAL_PARTITIONS`] -- ends the list of logical
+/// partitions, but isn't itself an error.
+pub async fn read_partitions<D: AsyncBlockDevice, const N: usize>(
+    dev: &mut D,
+    out: &mut [PartitionEntry; N],
+) -> Result<usize, MbrError<D::E>> {
+    let info = dev.device_info().await.map_err(MbrError::Device)?;
+    let bs = info.block_size as usize;
+    if !(512..=MAX_BLOCK_SIZE).contains(&bs) {
+        return Err(MbrError::UnsupportedBlockSize(info.block_size));
+    }
+    let mut buf = [0u8; MAX_BLOCK_SIZE];
+    let sector = &mut buf[..bs];
+
+    dev.read_blocks(0, 1, sector)
+        .await
+        .map_err(MbrError::Device)?;
+    let primaries = parse_table(sector).ok_or(MbrError::NoPartitionTable)?;
+
+    let mut n = 0;
+    let mut extended = None;
+    for entry in primaries {
+        if entry.partition_type == 0 || entry.blocks == 0 {
+            continue;
+        }
+        if entry.is_extended() {
+            extended = extended.or(Some(entry.start));
+        } else if !push(out, &mut n, entry) {
+            return Ok(n);
+        }
+    }
+
+    let Some(base) = extended else {
+        return Ok(n);
+    };
+    let mut ebr = base;
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        if dev.read_blocks(ebr, 1, sector).await.is_err() {
+            break;
+        }
+        let Some([logical, next, ..]) = parse_table(sector) else {
+            break;
+        };
+        if logical.partition_type != 0 && logical.blocks != 0 {
+            let entry = PartitionEntry {
+                logical: true,
+                // Relative to this EBR
+                start: ebr + logical.start,
+                ..logical
+            };
+            if !push(out, &mut n, entry) {
+                break;
+            }
+        }
+        // The next EBR is relative to the start of the extended partition
+        if !next.is_extended() || next.start == 0 {
+            break;
+        }
+        ebr = base + next.start;
+    }
+    Ok(n)
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/mbr.rs"]
+mod tests;
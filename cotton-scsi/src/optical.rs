@@ -0,0 +1,283 @@
+use super::scsi_device::ScsiDevice;
+use super::scsi_transport::{Error, ScsiTransport};
+
+/// READ TOC/PMA/ATIP
+/// SCSI Multimedia Commands (MMC-6) s6.38
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ReadToc {
+    operation_code: u8,
+    msf: u8,
+    format: u8,
+    reserved: [u8; 3],
+    track_number: u8,
+    allocation_length_be: [u8; 2],
+    control: u8,
+}
+
+impl ReadToc {
+    fn new(first_track: u8, len: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x43,
+            msf: 0,    // LBA addressing, not minute/second/frame
+            format: 0, // formatted TOC
+            reserved: [0; 3],
+            track_number: first_track,
+            allocation_length_be: len.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReadToc {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReadToc {}
+
+/// GET EVENT STATUS NOTIFICATION
+/// SCSI Multimedia Commands (MMC-6) s6.7
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct GetEventStatusNotification {
+    operation_code: u8,
+    polled: u8,
+    reserved: [u8; 2],
+    notification_class_request: u8,
+    reserved5: [u8; 2],
+    allocation_length_be: [u8; 2],
+    control: u8,
+}
+
+impl GetEventStatusNotification {
+    fn new(class_request: u8, len: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x4A,
+            polled: 1,
+            reserved: [0; 2],
+            notification_class_request: class_request,
+            reserved5: [0; 2],
+            allocation_length_be: len.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for GetEventStatusNotification {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for GetEventStatusNotification {}
+
+/// Event header plus a single Media Class event descriptor
+/// MMC-6 s6.7.2.1, s6.7.2.5
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct MediaEventReply {
+    event_data_length: [u8; 2],
+    notification_class: u8,
+    supported_event_classes: u8,
+    event_code: u8,
+    media_status: u8,
+    start_slot: u8,
+    end_slot: u8,
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for MediaEventReply {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for MediaEventReply {}
+
+/// Notification class bit (and class number) for media events
+const MEDIA_CLASS: u8 = 4;
+
+/// The number of the special "lead-out" track in the TOC
+///
+/// Its start address marks the end of the last real track.
+pub const LEAD_OUT_TRACK: u8 = 0xAA;
+
+/// One track descriptor from the table of contents of a disc
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct TocEntry {
+    /// Track number, 1-99, or [`LEAD_OUT_TRACK`]
+    pub track_number: u8,
+    /// The type of information in the Q sub-channel (usually 1)
+    pub adr: u8,
+    /// The track's CONTROL nibble (see [`TocEntry::is_data()`])
+    pub control: u8,
+    /// The first logical block of the track
+    pub start_lba: u32,
+}
+
+impl TocEntry {
+    /// Is this a data track (as opposed to an audio track)?
+    pub fn is_data(&self) -> bool {
+        (self.control & 4) != 0
+    }
+}
+
+/// Table of contents of a disc, as returned by [`ScsiDevice::read_toc()`]
+///
+/// The track descriptors remain in the caller's buffer, and are decoded
+/// on demand by [`Toc::tracks()`].
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Toc<'a> {
+    /// The lowest-numbered track on the disc
+    pub first_track: u8,
+    /// The highest-numbered track on the disc (not counting the lead-out)
+    pub last_track: u8,
+    descriptors: &'a [u8],
+}
+
+impl<'a> Toc<'a> {
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < 4 {
+            return None;
+        }
+        // The data length excludes the length field itself
+        let len = u16::from_be_bytes([buf[0], buf[1]]) as usize + 2;
+        let end = len.min(buf.len());
+        Some(Self {
+            first_track: buf[2],
+            last_track: buf[3],
+            descriptors: &buf[4..end.max(4)],
+        })
+    }
+
+    /// Iterate over the track descriptors, including the lead-out
+    ///
+    /// If the buffer passed to [`ScsiDevice::read_toc()`] was too
+    /// small for the whole table, only those tracks which fitted are
+    /// returned.
+    pub fn tracks(&self) -> impl Iterator<Item = TocEntry> + 'a {
+        self.descriptors.chunks_exact(8).map(|d| TocEntry {
+            track_number: d[2],
+            adr: d[1] >> 4,
+            control: d[1] & 0xF,
+            start_lba: u32::from_be_bytes([d[4], d[5], d[6], d[7]]),
+        })
+    }
+}
+
+/// The kind of media event reported by GET EVENT STATUS NOTIFICATION
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum MediaEventCode {
+    /// Nothing has happened since the last poll
+    #[default]
+    NoChange,
+    /// The user has pressed the eject button
+    EjectRequest,
+    /// A disc has been inserted
+    NewMedia,
+    /// The disc has been removed
+    MediaRemoval,
+    /// The disc has been changed (removed and a new one inserted)
+    MediaChanged,
+    /// A background format has finished
+    BackgroundFormatCompleted,
+    /// A background format has been restarted
+    BackgroundFormatRestarted,
+    /// An event code not defined by MMC-6
+    Reserved(u8),
+}
+
+impl From<u8> for MediaEventCode {
+    fn from(code: u8) -> Self {
+        match code & 0xF {
+            0 => Self::NoChange,
+            1 => Self::EjectRequest,
+            2 => Self::NewMedia,
+            3 => Self::MediaRemoval,
+            4 => Self::MediaChanged,
+            5 => Self::BackgroundFormatCompleted,
+            6 => Self::BackgroundFormatRestarted,
+            n => Self::Reserved(n),
+        }
+    }
+}
+
+/// A media event, as returned by [`ScsiDevice::get_media_event()`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct MediaEvent {
+    /// What, if anything, has happened since the last poll
+    pub code: MediaEventCode,
+    /// Whether there is currently a disc in the drive
+    pub media_present: bool,
+    /// Whether the drive tray or door is currently open
+    pub door_open: bool,
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Read the table of contents of a CD or DVD
+    ///
+    /// The supplied buffer receives the raw TOC, which needs 4 bytes
+    /// for the header plus 8 bytes for each track (including the
+    /// lead-out); 804 bytes suffices for any disc. Track start
+    /// addresses are returned as logical block addresses (LBAs)
+    /// rather than minute/second/frame.
+    ///
+    /// Data tracks can then be read with
+    /// [`ScsiDevice::read_10()`], starting at the track's
+    /// `start_lba`. Note that on optical media a logical block is
+    /// always 2048 bytes, as reported by
+    /// [`ScsiDevice::read_capacity_10()`], and buffers must be sized
+    /// accordingly. Attempting to read audio tracks in this way fails
+    /// with an `IllegalRequest` error.
+    pub async fn read_toc<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+    ) -> Result<Toc<'a>, Error<T::Error>> {
+        let len = buf.len().min(u16::MAX as usize);
+        let sz = self
+            .command_in(ReadToc::new(1, len as u16), &mut buf[0..len])
+            .await?;
+        Toc::parse(&buf[0..sz.min(len)]).ok_or(Error::ProtocolError)
+    }
+
+    /// Poll for media events (disc insertion, removal, eject-button)
+    ///
+    /// Uses the "polled" form of GET EVENT STATUS NOTIFICATION, which
+    /// all MMC (optical) devices support. Events are consumed by being
+    /// reported, so each one is returned only once; in between
+    /// events, `MediaEventCode::NoChange` is returned along with the
+    /// current state of the drive.
+    pub async fn get_media_event(
+        &mut self,
+    ) -> Result<MediaEvent, Error<T::Error>> {
+        let reply: MediaEventReply = self
+            .command_response(GetEventStatusNotification::new(
+                1 << MEDIA_CLASS,
+                8,
+            ))
+            .await?;
+        // NEA ("no event available") means the device doesn't
+        // support the media class after all
+        if (reply.notification_class & 0x80) != 0
+            || (reply.notification_class & 7) != MEDIA_CLASS
+        {
+            return Err(Error::ProtocolError);
+        }
+        Ok(MediaEvent {
+            code: reply.event_code.into(),
+            media_present: (reply.media_status & 2) != 0,
+            door_open: (reply.media_status & 1) != 0,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/optical.rs"]
+mod tests;
@@ -0,0 +1,229 @@
+use super::scsi_device::{PageControl, ScsiDevice, MODE_PAGE_BUFFER_SIZE};
+use super::scsi_transport::{Error, ScsiTransport};
+
+/// The size of the Power Condition mode page (SPC-4 onwards)
+const PAGE_SIZE: usize = 40;
+
+/// The size of the older (SPC-3) Power Condition mode page
+const SHORT_PAGE_SIZE: usize = 12;
+
+/// One of the timers in the Power Condition mode page
+///
+/// The timer counts, in units of 100 milliseconds, how long the device
+/// must have been idle before it enters the corresponding power
+/// condition.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct PowerConditionTimer {
+    /// Whether the device enters this power condition at all
+    pub enabled: bool,
+    /// The idle time before entering it, in units of 100ms
+    pub timer_100ms: u32,
+}
+
+impl PowerConditionTimer {
+    /// Enable the timer, with a timeout in seconds
+    ///
+    /// Timeouts too long to represent are limited to the longest
+    /// possible (about 13.6 years).
+    pub fn set_seconds(&mut self, seconds: u32) {
+        self.enabled = true;
+        self.timer_100ms = seconds.saturating_mul(10);
+    }
+}
+
+/// The Power Condition mode page (0x1A)
+///
+/// SCSI Primary Commands (SPC-4), "Power Condition mode page". The
+/// device enters each enabled power condition once it's been idle for
+/// that condition's timer: the Idle conditions save power by
+/// (progressively) parking heads and slowing electronics, the Standby
+/// conditions by spinning the disk down.
+///
+/// Older (SPC-3) devices have a shorter page, with just the
+/// [`PowerConditionPage::idle_a`] and [`PowerConditionPage::standby_z`]
+/// timers (called just "Idle" and "Standby" there); for those the
+/// other timers read as disabled, and changes to them are ignored.
+/// Fields of the page which aren't represented here are written back
+/// as they were read.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PowerConditionPage {
+    /// Whether the device can save the page, so that it survives a
+    /// power cycle (see [`ScsiDevice::set_power_condition_page()`])
+    pub saveable: bool,
+    /// Idle_a: the shallowest power saving
+    pub idle_a: PowerConditionTimer,
+    /// Idle_b: deeper power saving
+    pub idle_b: PowerConditionTimer,
+    /// Idle_c: deeper still
+    pub idle_c: PowerConditionTimer,
+    /// Standby_y: spun down, but quicker to recover than Standby_z
+    pub standby_y: PowerConditionTimer,
+    /// Standby_z: spun down
+    pub standby_z: PowerConditionTimer,
+
+    /// The page as read, for the fields not represented above
+    raw: [u8; PAGE_SIZE],
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[0..4].try_into().unwrap())
+}
+
+impl PowerConditionPage {
+    /// The mode page code for the Power Condition page
+    pub const PAGE_CODE: u8 = 0x1A;
+
+    /// Decode the page, starting at its page-code byte
+    ///
+    /// Returns `None` if `bytes` is too short, or isn't this page.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 2 || (bytes[0] & 0x3F) != Self::PAGE_CODE {
+            return None;
+        }
+        let len = (2 + bytes[1] as usize).min(PAGE_SIZE);
+        if len < SHORT_PAGE_SIZE || bytes.len() < len {
+            return None;
+        }
+        let mut raw = [0u8; PAGE_SIZE];
+        raw[..len].copy_from_slice(&bytes[..len]);
+        raw[1] = (len - 2) as u8; // in case it's a longer, later, version
+        let timer = |bit: u8, byte: usize, at: usize| PowerConditionTimer {
+            enabled: (raw[byte] & bit) != 0,
+            timer_100ms: be32(&raw[at..]),
+        };
+        Some(Self {
+            saveable: (raw[0] & 0x80) != 0,
+            idle_a: timer(0x02, 3, 4),
+            standby_z: timer(0x01, 3, 8),
+            idle_b: timer(0x04, 3, 12),
+            idle_c: timer(0x08, 3, 16),
+            standby_y: timer(0x01, 2, 20),
+            raw,
+        })
+    }
+
+    /// The size of the page, in bytes, including its two-byte header
+    pub fn size(&self) -> usize {
+        2 + self.raw[1] as usize
+    }
+
+    /// Whether this is the older, short, page (see above)
+    pub fn is_short(&self) -> bool {
+        self.size() < PAGE_SIZE
+    }
+
+    /// Encode the page, for MODE SELECT
+    ///
+    /// Only the first [`PowerConditionPage::size()`] bytes are
+    /// meaningful. The PS bit is cleared, as MODE SELECT requires.
+    pub fn to_bytes(&self) -> [u8; PAGE_SIZE] {
+        let mut bytes = self.raw;
+        bytes[0] &= 0x3F;
+        let mut timer = |t: &PowerConditionTimer, bit: u8, byte, at: usize| {
+            if t.enabled {
+                bytes[byte] |= bit;
+            } else {
+                bytes[byte] &= !bit;
+            }
+            bytes[at..at + 4].copy_from_slice(&t.timer_100ms.to_be_bytes());
+        };
+        timer(&self.idle_a, 0x02, 3, 4);
+        timer(&self.standby_z, 0x01, 3, 8);
+        if !self.is_short() {
+            timer(&self.idle_b, 0x04, 3, 12);
+            timer(&self.idle_c, 0x08, 3, 16);
+            timer(&self.standby_y, 0x01, 2, 20);
+        }
+        bytes
+    }
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Read the Power Condition mode page
+    ///
+    /// Uses MODE SENSE(10). Fails with `ProtocolError` if the reply
+    /// doesn't contain the page; devices without power management
+    /// typically fail with `Error::Scsi(ScsiError::InvalidFieldInCDB)`
+    /// instead.
+    pub async fn power_condition_page(
+        &mut self,
+        control: PageControl,
+    ) -> Result<PowerConditionPage, Error<T::Error>> {
+        let mut buf = [0u8; MODE_PAGE_BUFFER_SIZE];
+        let page = self
+            .mode_page(PowerConditionPage::PAGE_CODE, control, &mut buf)
+            .await?;
+        PowerConditionPage::parse(page).ok_or(Error::ProtocolError)
+    }
+
+    /// Write the Power Condition mode page
+    ///
+    /// Uses MODE SELECT(10). If `save` is false, the new values last
+    /// only until the device is next power-cycled or reset, when the
+    /// saved values are restored (and external enclosures are often
+    /// power-cycled along with the host). If `save` is true, the values
+    /// become the saved values too, and so persist -- but the device
+    /// rejects that if the page isn't
+    /// [`PowerConditionPage::saveable`].
+    ///
+    /// Usually `page` is the result of
+    /// [`ScsiDevice::power_condition_page()`] with
+    /// [`PageControl::Current`], modified.
+    pub async fn set_power_condition_page(
+        &mut self,
+        page: &PowerConditionPage,
+        save: bool,
+    ) -> Result<(), Error<T::Error>> {
+        self.set_mode_page(&page.to_bytes()[..page.size()], save)
+            .await
+    }
+
+    /// Make a disk spin down after `seconds` of inactivity
+    ///
+    /// Enables the Standby_z condition, with its timer set to
+    /// `seconds` (the page's timers count in units of 100ms), by
+    /// reading, modifying, and writing back the Power Condition mode
+    /// page; other timers are unaffected. See
+    /// [`ScsiDevice::set_power_condition_page()`] for the meaning of
+    /// `save`.
+    pub async fn set_standby_timer(
+        &mut self,
+        seconds: u32,
+        save: bool,
+    ) -> Result<(), Error<T::Error>> {
+        let mut page = self.power_condition_page(PageControl::Current).await?;
+        page.standby_z.set_seconds(seconds);
+        self.set_power_condition_page(&page, save).await
+    }
+
+    /// Stop the device entering any power-saving condition by itself
+    ///
+    /// Disables all the timers in the Power Condition mode page (their
+    /// values are kept, for if they're re-enabled). See
+    /// [`ScsiDevice::set_power_condition_page()`] for the meaning of
+    /// `save`.
+    pub async fn disable_power_management(
+        &mut self,
+        save: bool,
+    ) -> Result<(), Error<T::Error>> {
+        let mut page = self.power_condition_page(PageControl::Current).await?;
+        for timer in [
+            &mut page.idle_a,
+            &mut page.idle_b,
+            &mut page.idle_c,
+            &mut page.standby_y,
+            &mut page.standby_z,
+        ] {
+            timer.enabled = false;
+        }
+        self.set_power_condition_page(&page, save).await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/power_condition.rs"]
+mod tests;
@@ -1,20 +1,115 @@
 use super::async_block_device::{AsyncBlockDevice, DeviceInfo};
 use super::debug;
-use super::scsi_device::ScsiDevice;
+use super::scsi_device::{MediaState, ScsiDevice};
 use super::scsi_transport::{Error, ScsiError, ScsiTransport};
 
 /// Implementing [`AsyncBlockDevice`] in terms of [`ScsiDevice`]
+///
+/// The device's size and block size are found out just once: from
+/// [`ScsiDevice::capabilities()`] if [`ScsiDevice::probe()`] has
+/// already been called, or otherwise by READ CAPACITY on first use
+/// (which doesn't find out the physical block size).
+/// They're then used to check the arguments of every read and write
+/// before any command is issued. Similarly, once
+/// [`ScsiBlockDevice::read_only()`] has found that the medium is
+/// write-protected, writes fail straight away.
 pub struct ScsiBlockDevice<T: ScsiTransport> {
     /// The underlying SCSI block device
     ///
     /// Made "pub" so that additional SCSI commands can be issued if need be.
     pub scsi: ScsiDevice<T>,
+    info: Option<DeviceInfo>,
+    read_only: Option<bool>,
 }
 
 impl<T: ScsiTransport> ScsiBlockDevice<T> {
     /// Construct a new block device from a generic SCSI device
     pub fn new(scsi: ScsiDevice<T>) -> Self {
-        Self { scsi }
+        Self {
+            scsi,
+            info: None,
+            read_only: None,
+        }
+    }
+
+    async fn info(&mut self) -> Result<DeviceInfo, Error<T::Error>> {
+        if let Some(info) = self.info {
+            return Ok(info);
+        }
+        let info = if let Some(caps) = self.scsi.capabilities() {
+            DeviceInfo {
+                blocks: caps.blocks,
+                block_size: caps.block_size,
+                physical_block_size: caps.physical_block_size,
+            }
+        } else {
+            let (blocks, block_size) = self.scsi.read_capacity().await?;
+            DeviceInfo {
+                blocks,
+                block_size,
+                physical_block_size: block_size,
+            }
+        };
+        self.info = Some(info);
+        Ok(info)
+    }
+
+    /// Is the medium write-protected?
+    ///
+    /// Taken from [`ScsiDevice::capabilities()`] if
+    /// [`ScsiDevice::probe()`] has already been called, or otherwise
+    /// found out (just once) using
+    /// [`ScsiDevice::is_write_protected()`]. A device which doesn't
+    /// report write-protection is assumed to be writable.
+    pub async fn read_only(&mut self) -> Result<bool, Error<T::Error>> {
+        if let Some(read_only) = self.read_only {
+            return Ok(read_only);
+        }
+        let wp = match self.scsi.capabilities() {
+            Some(caps) => caps.write_protected,
+            None => self.scsi.is_write_protected().await?,
+        };
+        let read_only = wp.unwrap_or(false);
+        self.read_only = Some(read_only);
+        Ok(read_only)
+    }
+
+    /// Check the arguments of a read or write, returning the number of
+    /// bytes to transfer
+    async fn check(
+        &mut self,
+        offset: u64,
+        count: u32,
+        len: usize,
+    ) -> Result<usize, Error<T::Error>> {
+        let info = self.info().await?;
+        match offset.checked_add(count as u64) {
+            Some(end) if end <= info.blocks => {}
+            _ => {
+                return Err(Error::Scsi(
+                    ScsiError::LogicalBlockAddressOutOfRange,
+                ))
+            }
+        }
+        (count as usize)
+            .checked_mul(info.block_size as usize)
+            .filter(|n| *n <= len)
+            .ok_or(Error::BadBufferSize)
+    }
+
+    /// Check whether the medium is present, and whether it's changed
+    ///
+    /// See [`ScsiDevice::check_media()`]. After a change, the device's
+    /// size is found out again on next use.
+    pub async fn check_media(
+        &mut self,
+    ) -> Result<MediaState, Error<T::Error>> {
+        let state = self.scsi.check_media().await?;
+        if state != MediaState::Ready {
+            self.info = None;
+            self.read_only = None;
+        }
+        Ok(state)
     }
 
     /// For testing: query supported SCSI commands on this device
@@ -52,16 +147,7 @@ impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
     type E = Error<T::Error>;
 
     async fn device_info(&mut self) -> Result<DeviceInfo, Self::E> {
-        let (blocks, block_size) = {
-            let capacity10 = self.scsi.read_capacity_10().await?;
-            if capacity10.0 != 0xFFFF_FFFF {
-                (capacity10.0 as u64, capacity10.1)
-            } else {
-                self.scsi.read_capacity_16().await?
-            }
-        };
-
-        Ok(DeviceInfo { blocks, block_size })
+        self.info().await
     }
 
     async fn read_blocks(
@@ -70,16 +156,11 @@ impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
         count: u32,
         data: &mut [u8],
     ) -> Result<(), Self::E> {
-        let end = offset
-            .checked_add(count as u64)
-            .ok_or(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))?;
-        let sz = if end < u32::MAX as u64 && count < u16::MAX as u32 {
-            self.scsi.read_10(offset as u32, count as u16, data).await?
-        } else {
-            self.scsi.read_16(offset, count, data).await?
-        };
-        if sz < data.len() {
-            return Err(Error::ProtocolError);
+        let len = self.check(offset, count, data.len()).await?;
+        if len > 0 {
+            self.scsi
+                .read_blocks(offset, count, &mut data[..len])
+                .await?;
         }
         Ok(())
     }
@@ -90,18 +171,26 @@ impl<T: ScsiTransport> AsyncBlockDevice for ScsiBlockDevice<T> {
         count: u32,
         data: &[u8],
     ) -> Result<(), Self::E> {
-        let end = offset
-            .checked_add(count as u64)
-            .ok_or(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))?;
-        if end < u32::MAX as u64 && count < u16::MAX as u32 {
-            self.scsi
-                .write_10(offset as u32, count as u16, data)
-                .await?;
-        } else {
-            self.scsi.write_16(offset, count, data).await?;
+        if self.read_only == Some(true) {
+            return Err(Error::Scsi(ScsiError::WriteProtected));
+        }
+        let len = self.check(offset, count, data.len()).await?;
+        if len > 0 {
+            self.scsi.write_blocks(offset, count, &data[..len]).await?;
         }
         Ok(())
     }
+
+    /// Issues SYNCHRONIZE CACHE
+    ///
+    /// Devices which don't support that command are assumed not to
+    /// have a write cache, so there's nothing to flush.
+    async fn flush(&mut self) -> Result<(), Self::E> {
+        match self.scsi.synchronize_cache().await {
+            Err(Error::Scsi(ScsiError::InvalidCommandOperationCode)) => Ok(()),
+            rc => rc,
+        }
+    }
 }
 
 #[cfg(all(test, feature = "std"))]
@@ -1,5 +1,106 @@
 use super::debug;
-use super::scsi_transport::{DataPhase, Error, ScsiError, ScsiTransport};
+use super::scsi_transport::{
+    DataPhase, Error, ReadBuffer, ScsiError, ScsiQuirks, ScsiStatus,
+    ScsiTransport, SenseData, TransferError,
+};
+use super::timeout::Timeouts;
+use core::mem::MaybeUninit;
+
+/// Largest single transfer made by [`ScsiDevice::read_blocks()`] and
+/// [`ScsiDevice::write_blocks()`] if neither device nor transport says
+///
+/// Many USB mass-storage bridges fail on transfers much bigger than
+/// this (Linux limits them to 120KB by default).
+pub(crate) const DEFAULT_MAX_TRANSFER_BYTES: usize = 64 * 1024;
+
+/// Room for a MODE SENSE(10) reply: the header, any block descriptors
+/// the device sends despite being asked not to, and one mode page
+pub(crate) const MODE_PAGE_BUFFER_SIZE: usize = 128;
+
+/// How many times a command failing with [`Error::Busy`] is reissued,
+/// unless changed with [`ScsiDevice::set_busy_retries()`]
+const DEFAULT_BUSY_RETRIES: u8 = 3;
+
+/// How long to wait before first reissuing a command which failed with
+/// [`Error::Busy`]; the wait doubles with each further retry
+const BUSY_RETRY_DELAY_MS: u32 = 10;
+
+/// Can a transfer of `count` blocks at `lba` be done with a 10-byte CDB?
+fn fits_10(lba: u64, count: u32) -> bool {
+    lba.checked_add(count as u64)
+        .is_some_and(|end| end < u32::MAX as u64)
+        && count <= u16::MAX as u32
+}
+
+/// The data phase of a command issued by a [`ScsiDevice`]: either an
+/// ordinary one, or a data-in phase into memory which needn't be
+/// initialised (see [`ScsiTransport::command_in_uninit()`])
+enum Payload<'a, 'b> {
+    Phase(DataPhase<'a>),
+    Uninit(&'a mut ReadBuffer<'b>),
+}
+
+impl<'b> Payload<'_, 'b> {
+    /// The same payload, borrowed again, so that a command can be
+    /// reissued
+    fn reborrow(&mut self) -> Payload<'_, 'b> {
+        match self {
+            Payload::Phase(data) => Payload::Phase(data.reborrow()),
+            Payload::Uninit(buf) => Payload::Uninit(buf),
+        }
+    }
+
+    fn is_in(&self) -> bool {
+        matches!(self, Payload::Phase(DataPhase::In(_)) | Payload::Uninit(_))
+    }
+}
+
+impl<'a> From<DataPhase<'a>> for Payload<'a, '_> {
+    fn from(data: DataPhase<'a>) -> Self {
+        Payload::Phase(data)
+    }
+}
+
+/// Per-command cache hints for READ and WRITE
+///
+/// Seagate SCSI Commands Reference Manual s3.16 (READ (10)) and s3.60
+/// (WRITE (10)). Passed to [`ScsiDevice::read_10_with_flags()`] and
+/// friends; the default, as used by [`ScsiDevice::read_10()`] and
+/// friends, is neither.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct AccessFlags {
+    /// Force Unit Access (FUA): a write completes only once the data is
+    /// on the medium, and a read is satisfied from the medium rather
+    /// than the cache -- for a single command, without turning off the
+    /// whole write cache
+    pub force_unit_access: bool,
+    /// Disable Page Out (DPO): the data isn't worth caching, so
+    /// shouldn't displace anything else from the cache -- for instance,
+    /// during a large streaming read
+    pub disable_page_out: bool,
+}
+
+impl AccessFlags {
+    /// Force Unit Access only
+    pub const FUA: Self = Self {
+        force_unit_access: true,
+        disable_page_out: false,
+    };
+
+    /// Disable Page Out only
+    pub const DPO: Self = Self {
+        force_unit_access: false,
+        disable_page_out: true,
+    };
+
+    /// The flags as they appear in byte 1 of the command block
+    fn bits(self) -> u8 {
+        (if self.disable_page_out { 0x10 } else { 0 })
+            | (if self.force_unit_access { 0x08 } else { 0 })
+    }
+}
 
 /// READ (10)
 /// Seagate SCSI Commands Reference Manual s3.16
@@ -17,11 +118,11 @@ struct Read10 {
 }
 
 impl Read10 {
-    fn new(lba: u32, count: u16) -> Self {
+    fn new(lba: u32, count: u16, flags: AccessFlags) -> Self {
         assert!(core::mem::size_of::<Self>() == 10);
         Self {
             operation_code: 0x28,
-            flags: 0,
+            flags: flags.bits(),
             lba_be: lba.to_be_bytes(),
             transfer_length_be: count.to_be_bytes(),
             group: 0,
@@ -51,11 +152,11 @@ struct Read16 {
 }
 
 impl Read16 {
-    fn new(lba: u64, count: u32) -> Self {
+    fn new(lba: u64, count: u32, flags: AccessFlags) -> Self {
         assert!(core::mem::size_of::<Self>() == 16);
         Self {
             operation_code: 0x88,
-            flags: 0,
+            flags: flags.bits(),
             lba_be: lba.to_be_bytes(),
             transfer_length_be: count.to_be_bytes(),
             group: 0,
@@ -85,11 +186,11 @@ struct Write10 {
 }
 
 impl Write10 {
-    fn new(lba: u32, count: u16) -> Self {
+    fn new(lba: u32, count: u16, flags: AccessFlags) -> Self {
         assert!(core::mem::size_of::<Self>() == 10);
         Self {
             operation_code: 0x2A,
-            flags: 0,
+            flags: flags.bits(),
             lba_be: lba.to_be_bytes(),
             transfer_length_be: count.to_be_bytes(),
             group: 0,
@@ -119,11 +220,11 @@ struct Write16 {
 }
 
 impl Write16 {
-    fn new(lba: u64, count: u32) -> Self {
+    fn new(lba: u64, count: u32, flags: AccessFlags) -> Self {
         assert!(core::mem::size_of::<Self>() == 16);
         Self {
             operation_code: 0x8A,
-            flags: 0,
+            flags: flags.bits(),
             lba_be: lba.to_be_bytes(),
             transfer_length_be: count.to_be_bytes(),
             group: 0,
@@ -137,6 +238,110 @@ unsafe impl bytemuck::Zeroable for Write16 {}
 // SAFETY: no padding, no disallowed bit patterns
 unsafe impl bytemuck::Pod for Write16 {}
 
+/// PRE-FETCH (10)
+/// Seagate SCSI Commands Reference Manual s3.19
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct PreFetch10 {
+    operation_code: u8,
+    flags: u8,
+    lba_be: [u8; 4],
+    group: u8,
+    prefetch_length_be: [u8; 2],
+    control: u8,
+}
+
+impl PreFetch10 {
+    fn new(lba: u32, count: u16, immediate: bool) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x34,
+            flags: if immediate { 2 } else { 0 },
+            lba_be: lba.to_be_bytes(),
+            group: 0,
+            prefetch_length_be: count.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for PreFetch10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for PreFetch10 {}
+
+/// PRE-FETCH (16)
+/// Seagate SCSI Commands Reference Manual s3.20
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct PreFetch16 {
+    operation_code: u8,
+    flags: u8,
+    lba_be: [u8; 8],
+    prefetch_length_be: [u8; 4],
+    group: u8,
+    control: u8,
+}
+
+impl PreFetch16 {
+    fn new(lba: u64, count: u32, immediate: bool) -> Self {
+        assert!(core::mem::size_of::<Self>() == 16);
+        Self {
+            operation_code: 0x90,
+            flags: if immediate { 2 } else { 0 },
+            lba_be: lba.to_be_bytes(),
+            prefetch_length_be: count.to_be_bytes(),
+            group: 0,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for PreFetch16 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for PreFetch16 {}
+
+/// COMPARE AND WRITE
+/// Seagate SCSI Commands Reference Manual s3.2
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct CompareAndWrite {
+    operation_code: u8,
+    flags: u8,
+    lba_be: [u8; 8],
+    reserved: [u8; 3],
+    number_of_blocks: u8,
+    group: u8,
+    control: u8,
+}
+
+impl CompareAndWrite {
+    fn new(lba: u64, count: u8) -> Self {
+        assert!(core::mem::size_of::<Self>() == 16);
+        Self {
+            operation_code: 0x89,
+            flags: 0,
+            lba_be: lba.to_be_bytes(),
+            reserved: [0; 3],
+            number_of_blocks: count,
+            group: 0,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for CompareAndWrite {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for CompareAndWrite {}
+
 /// READ CAPACITY (10)
 /// Seagate SCSI Commands Reference Manual s3.23.2
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -234,6 +439,63 @@ unsafe impl bytemuck::Zeroable for ReadCapacity16Reply {}
 // SAFETY: no padding, no disallowed bit patterns
 unsafe impl bytemuck::Pod for ReadCapacity16Reply {}
 
+/// The full results of READ CAPACITY(16)
+///
+/// Seagate SCSI Commands Reference Manual s3.23.2
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct ReadCapacity16Data {
+    /// The address of the last block (one less than the number of blocks)
+    pub last_lba: u64,
+    /// The size of each (logical) block in bytes
+    pub block_size: u32,
+    /// The size of each physical block in bytes
+    ///
+    /// "512e" drives have 512-byte logical blocks, but 4096-byte
+    /// physical blocks; writes which don't cover whole physical blocks
+    /// need a read-modify-write inside the drive, which is slow.
+    pub physical_block_size: u32,
+    /// The first logical block which starts a physical block
+    ///
+    /// Usually 0, but drives which emulate old-style (sector 63)
+    /// partition alignment report 1 (or 7).
+    pub lowest_aligned_lba: u16,
+    /// The protection information type (1, 2, or 3), if protection
+    /// is enabled
+    pub protection_type: Option<u8>,
+    /// There are 2^this protection information intervals per block
+    pub protection_interval_exponent: u8,
+    /// Whether the device is thin-provisioned ("LBPME")
+    pub thin_provisioned: bool,
+    /// Whether unmapped blocks read as zero ("LBPRZ")
+    pub unmapped_reads_zero: bool,
+}
+
+impl ReadCapacity16Data {
+    fn from_reply(reply: &ReadCapacity16Reply) -> Self {
+        let block_size = u32::from_be_bytes(reply.block_size);
+        let exponent = reply.flags[1] & 0xF;
+        let aligned = u16::from_be_bytes(reply.lowest_aligned_lba);
+        Self {
+            last_lba: u64::from_be_bytes(reply.lba),
+            block_size,
+            physical_block_size: block_size
+                .checked_mul(1 << exponent)
+                .unwrap_or(block_size),
+            lowest_aligned_lba: aligned & 0x3FFF,
+            protection_type: if (reply.flags[0] & 1) != 0 {
+                Some(((reply.flags[0] >> 1) & 7) + 1)
+            } else {
+                None
+            },
+            protection_interval_exponent: reply.flags[1] >> 4,
+            thin_provisioned: (aligned & 0x8000) != 0,
+            unmapped_reads_zero: (aligned & 0x4000) != 0,
+        }
+    }
+}
+
 /// TEST UNIT READY
 /// Seagate SCSI Commands Reference Manual s3.53
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -262,180 +524,593 @@ unsafe impl bytemuck::Zeroable for TestUnitReady {}
 // SAFETY: no padding, no disallowed bit patterns
 unsafe impl bytemuck::Pod for TestUnitReady {}
 
-/// REQUEST SENSE
-/// Seagate SCSI Commands Reference Manual s3.37
+/// SYNCHRONIZE CACHE (10)
+/// Seagate SCSI Commands Reference Manual s3.51
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Copy, Clone)]
 #[repr(C)]
-struct RequestSense {
+struct SynchronizeCache10 {
     operation_code: u8,
-    desc: u8,
-    reserved: [u8; 2],
-    allocation_length: u8,
+    flags: u8,
+    lba_be: [u8; 4],
+    group_number: u8,
+    number_of_blocks_be: [u8; 2],
     control: u8,
 }
 
-impl RequestSense {
+impl SynchronizeCache10 {
     fn new() -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x35,
+            flags: 0,
+            lba_be: [0; 4],
+            group_number: 0,
+            number_of_blocks_be: [0; 2], // i.e., the whole medium
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for SynchronizeCache10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for SynchronizeCache10 {}
+
+/// START STOP UNIT
+/// Seagate SCSI Commands Reference Manual s3.49
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct StartStopUnit {
+    operation_code: u8,
+    immed: u8,
+    reserved: u8,
+    power_condition_modifier: u8,
+    flags: u8,
+    control: u8,
+}
+
+impl StartStopUnit {
+    fn new(start: bool, load_eject: bool) -> Self {
         assert!(core::mem::size_of::<Self>() == 6);
         Self {
-            operation_code: 3,
-            desc: 0,
-            reserved: [0; 2],
-            allocation_length: 18,
+            operation_code: 0x1B,
+            immed: 0,
+            reserved: 0,
+            power_condition_modifier: 0,
+            flags: ((load_eject as u8) << 1) | (start as u8),
             control: 0,
         }
     }
 }
 
 // SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for RequestSense {}
+unsafe impl bytemuck::Zeroable for StartStopUnit {}
 // SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for RequestSense {}
+unsafe impl bytemuck::Pod for StartStopUnit {}
 
+/// PREVENT ALLOW MEDIUM REMOVAL
+/// Seagate SCSI Commands Reference Manual s3.21
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone)]
 #[repr(C)]
-struct RequestSenseReply {
-    response_code: u8,
-    reserved1: u8,
-    sense_key: u8,
-    information: [u8; 4],
-    additional_length: u8,
-    command_specific_information: [u8; 4],
-    additional_sense_code: u8,
-    additional_sense_code_qualifier: u8,
-    fru_code: u8,
-    sense_key_specific: [u8; 3],
+struct PreventAllowMediumRemoval {
+    operation_code: u8,
+    reserved: [u8; 3],
+    prevent: u8,
+    control: u8,
+}
+
+impl PreventAllowMediumRemoval {
+    fn new(prevent: bool) -> Self {
+        assert!(core::mem::size_of::<Self>() == 6);
+        Self {
+            operation_code: 0x1E,
+            reserved: [0; 3],
+            prevent: prevent as u8,
+            control: 0,
+        }
+    }
 }
 
 // SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for RequestSenseReply {}
+unsafe impl bytemuck::Zeroable for PreventAllowMediumRemoval {}
 // SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for RequestSenseReply {}
+unsafe impl bytemuck::Pod for PreventAllowMediumRemoval {}
 
-/// REPORT SUPPORTED OPERATION CODES
-/// Seagate SCSI Commands Reference Manual s3.34
+/// FORMAT UNIT
+/// Seagate SCSI Commands Reference Manual s3.3
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Copy, Clone)]
 #[repr(C)]
-struct ReportSupportedOperationCodes {
+struct FormatUnit {
     operation_code: u8,
-    service_action: u8,
-    reporting_options: u8,
-    requested_opcode: u8,
-    requested_service_action_be: [u8; 2],
-    allocation_length_be: [u8; 4],
-    reserved: u8,
+    flags: u8,
+    vendor_specific: u8,
+    obsolete: [u8; 2],
     control: u8,
 }
 
-impl ReportSupportedOperationCodes {
-    fn new(opcode: u8, service_action: Option<u16>) -> Self {
-        assert!(core::mem::size_of::<Self>() == 12);
+impl FormatUnit {
+    fn new() -> Self {
+        assert!(core::mem::size_of::<Self>() == 6);
         Self {
-            operation_code: 0xA3,
-            service_action: 0x0C,
-            reporting_options: 3,
-            requested_opcode: opcode,
-            requested_service_action_be: service_action
-                .unwrap_or_default()
-                .to_be_bytes(),
-            allocation_length_be: [0, 0, 0, 4],
-            reserved: 0,
+            operation_code: 0x04,
+            flags: 0x10, // FMTDATA: a parameter list follows
+            vendor_specific: 0,
+            obsolete: [0; 2],
             control: 0,
         }
     }
 }
 
 // SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for ReportSupportedOperationCodes {}
+unsafe impl bytemuck::Zeroable for FormatUnit {}
 // SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for ReportSupportedOperationCodes {}
+unsafe impl bytemuck::Pod for FormatUnit {}
 
-/// The "One-Command" reply format for ReportSupportedOperationCodes
-/// Seagate SCSI Commands Reference Mnaual s3.34.3
+/// FORMAT UNIT short parameter list header
+/// Seagate SCSI Commands Reference Manual s3.3.3
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone)]
 #[repr(C)]
-pub(crate) struct ReportSupportedOperationCodesReply {
-    pub reserved: u8,
-    pub support: u8,
-    pub cdb_size: [u8; 2],
+struct ShortParameterListHeader {
+    protection_field_usage: u8,
+    flags: u8,
+    defect_list_length: [u8; 2],
+}
+
+impl ShortParameterListHeader {
+    fn new() -> Self {
+        assert!(core::mem::size_of::<Self>() == 4);
+        Self {
+            protection_field_usage: 0,
+            flags: 0x02, // IMMED: report status before formatting
+            defect_list_length: [0; 2],
+        }
+    }
 }
 
 // SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for ReportSupportedOperationCodesReply {}
+unsafe impl bytemuck::Zeroable for ShortParameterListHeader {}
 // SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for ReportSupportedOperationCodesReply {}
+unsafe impl bytemuck::Pod for ShortParameterListHeader {}
 
-/// INQUIRY
-/// Seagate SCSI Commands Reference Manual s3.6
+/// SEND DIAGNOSTIC
+/// SCSI Primary Commands (SPC-4), "SEND DIAGNOSTIC command"
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Copy, Clone)]
 #[repr(C)]
-struct Inquiry {
+struct SendDiagnostic {
     operation_code: u8,
-    evpd: u8,
-    page_code: u8,
-    allocation_length_be: [u8; 2],
+    flags: u8,
+    reserved: u8,
+    parameter_list_length: [u8; 2],
     control: u8,
 }
 
-impl Inquiry {
-    fn new(evpd: Option<u8>, len: u16) -> Self {
+impl SendDiagnostic {
+    fn new(test: SelfTest) -> Self {
         assert!(core::mem::size_of::<Self>() == 6);
         Self {
-            operation_code: 0x12,
-            evpd: evpd.is_some() as u8,
-            page_code: evpd.unwrap_or_default(),
-            allocation_length_be: len.to_be_bytes(),
+            operation_code: 0x1D,
+            flags: test.code() << 5,
+            reserved: 0,
+            parameter_list_length: [0; 2],
             control: 0,
         }
     }
 }
 
 // SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for Inquiry {}
+unsafe impl bytemuck::Zeroable for SendDiagnostic {}
 // SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for Inquiry {}
+unsafe impl bytemuck::Pod for SendDiagnostic {}
 
-/// Standard INQUIRY data
-/// Seagate SCSI Commands Reference Manual s3.6.2
-///
-/// This is the compulsory leading 36 bytes; the actual data might be
-/// larger (but the device truncates it, and tells us that it's done
-/// so via the "residue" field of the command status wrapper).
+/// REASSIGN BLOCKS
+/// Seagate SCSI Commands Reference Manual s3.32
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone)]
 #[repr(C)]
-struct StandardInquiryData {
-    peripheral_device_type: u8,
-    removable: u8,
-    version: u8,
-    data_format: u8,
-    additional_length: u8,
-    flags: [u8; 3],
-    vendor_id: [u8; 8],
-    product_id: [u8; 16],
-    product_revision: [u8; 4],
+struct ReassignBlocks {
+    operation_code: u8,
+    flags: u8,
+    reserved: [u8; 3],
+    control: u8,
+}
+
+impl ReassignBlocks {
+    fn new(long: bool) -> Self {
+        assert!(core::mem::size_of::<Self>() == 6);
+        Self {
+            operation_code: 0x07,
+            // LONGLBA and LONGLIST
+            flags: if long { 3 } else { 0 },
+            reserved: [0; 3],
+            control: 0,
+        }
+    }
 }
 
 // SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for StandardInquiryData {}
+unsafe impl bytemuck::Zeroable for ReassignBlocks {}
 // SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for StandardInquiryData {}
+unsafe impl bytemuck::Pod for ReassignBlocks {}
 
-/// Inquiry Block Limits page
-/// Seagate SCSI Commands Reference Manual s5.4.5
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+/// The most blocks reassigned by a single REASSIGN BLOCKS command
+///
+/// Longer lists are sent as several commands.
+pub const REASSIGN_BATCH: usize = 32;
+
+/// Encode a REASSIGN BLOCKS parameter list into `buf`
+///
+/// Each LBA is 4 bytes, or 8 if `long`, and so is the list-length
+/// field of the header. Returns the length of the parameter list.
+fn encode_reassign(
+    buf: &mut [u8],
+    lbas: impl ExactSizeIterator<Item = u64>,
+    long: bool,
+) -> usize {
+    let size = if long { 8 } else { 4 };
+    let len = lbas.len() * size;
+    if long {
+        buf[0..4].copy_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf[0..2].fill(0);
+        buf[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+    }
+    for (lba, dest) in lbas.zip(buf[4..].chunks_exact_mut(size)) {
+        dest.copy_from_slice(&lba.to_be_bytes()[8 - size..]);
+    }
+    4 + len
+}
+
+/// Room for fixed-format sense data (18 bytes), or for descriptor-format
+/// sense data with a handful of descriptors
+pub(crate) const SENSE_BUFFER_SIZE: usize = 64;
+
+/// REQUEST SENSE
+/// Seagate SCSI Commands Reference Manual s3.37
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct RequestSense {
+    operation_code: u8,
+    desc: u8,
+    reserved: [u8; 2],
+    allocation_length: u8,
+    control: u8,
+}
+
+impl RequestSense {
+    fn new() -> Self {
+        assert!(core::mem::size_of::<Self>() == 6);
+        Self {
+            operation_code: 3,
+            desc: 0,
+            reserved: [0; 2],
+            allocation_length: SENSE_BUFFER_SIZE as u8,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for RequestSense {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for RequestSense {}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub(crate) struct RequestSenseReply {
+    pub response_code: u8,
+    pub reserved1: u8,
+    pub sense_key: u8,
+    pub information: [u8; 4],
+    pub additional_length: u8,
+    pub command_specific_information: [u8; 4],
+    pub additional_sense_code: u8,
+    pub additional_sense_code_qualifier: u8,
+    pub fru_code: u8,
+    pub sense_key_specific: [u8; 3],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for RequestSenseReply {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for RequestSenseReply {}
+
+/// REPORT SUPPORTED OPERATION CODES
+/// Seagate SCSI Commands Reference Manual s3.34
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ReportSupportedOperationCodes {
+    operation_code: u8,
+    service_action: u8,
+    reporting_options: u8,
+    requested_opcode: u8,
+    requested_service_action_be: [u8; 2],
+    allocation_length_be: [u8; 4],
+    reserved: u8,
+    control: u8,
+}
+
+impl ReportSupportedOperationCodes {
+    fn new(opcode: u8, service_action: Option<u16>) -> Self {
+        assert!(core::mem::size_of::<Self>() == 12);
+        Self {
+            operation_code: 0xA3,
+            service_action: 0x0C,
+            reporting_options: 3,
+            requested_opcode: opcode,
+            requested_service_action_be: service_action
+                .unwrap_or_default()
+                .to_be_bytes(),
+            allocation_length_be: [0, 0, 0, 4],
+            reserved: 0,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReportSupportedOperationCodes {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReportSupportedOperationCodes {}
+
+/// The "One-Command" reply format for ReportSupportedOperationCodes
+/// Seagate SCSI Commands Reference Mnaual s3.34.3
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub(crate) struct ReportSupportedOperationCodesReply {
+    pub reserved: u8,
+    pub support: u8,
+    pub cdb_size: [u8; 2],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReportSupportedOperationCodesReply {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReportSupportedOperationCodesReply {}
+
+/// MODE SENSE (6)
+/// Seagate SCSI Commands Reference Manual s3.11
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ModeSense6 {
+    operation_code: u8,
+    dbd: u8,
+    page_code: u8,
+    subpage_code: u8,
+    allocation_length: u8,
+    control: u8,
+}
+
+impl ModeSense6 {
+    fn new(page_code: u8, len: u8) -> Self {
+        assert!(core::mem::size_of::<Self>() == 6);
+        Self {
+            operation_code: 0x1A,
+            dbd: 0x08, // no block descriptors please
+            page_code,
+            subpage_code: 0,
+            allocation_length: len,
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ModeSense6 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ModeSense6 {}
+
+/// Which set of a mode page's values to read
+/// Seagate SCSI Commands Reference Manual s3.11
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PageControl {
+    /// The values in effect now
+    Current = 0,
+    /// Not values as such, but a mask of which bits can be changed
+    Changeable = 1,
+    /// The manufacturer's defaults
+    Default = 2,
+    /// The values which will be in effect after the next power cycle
+    /// or reset
+    Saved = 3,
+}
+
+/// Mode parameter header, 6-byte-CDB version
+/// Seagate SCSI Commands Reference Manual s5.3.3
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub(crate) struct ModeParameterHeader6 {
+    pub mode_data_length: u8,
+    pub medium_type: u8,
+    pub device_specific_parameter: u8,
+    pub block_descriptor_length: u8,
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ModeParameterHeader6 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ModeParameterHeader6 {}
+
+/// MODE SENSE (10)
+/// Seagate SCSI Commands Reference Manual s3.12
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ModeSense10 {
+    operation_code: u8,
+    dbd: u8,
+    page_code: u8,
+    subpage_code: u8,
+    reserved: [u8; 3],
+    allocation_length_be: [u8; 2],
+    control: u8,
+}
+
+impl ModeSense10 {
+    fn new(page_code: u8, len: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x5A,
+            dbd: 0x08, // no block descriptors please
+            page_code,
+            subpage_code: 0,
+            reserved: [0; 3],
+            allocation_length_be: len.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ModeSense10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ModeSense10 {}
+
+/// Mode parameter header, 10-byte-CDB version
+/// Seagate SCSI Commands Reference Manual s5.3.3
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub(crate) struct ModeParameterHeader10 {
+    pub mode_data_length: [u8; 2],
+    pub medium_type: u8,
+    pub device_specific_parameter: u8,
+    pub long_lba: u8,
+    pub reserved: u8,
+    pub block_descriptor_length: [u8; 2],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ModeParameterHeader10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ModeParameterHeader10 {}
+
+/// MODE SELECT (10)
+/// Seagate SCSI Commands Reference Manual s3.10
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ModeSelect10 {
+    operation_code: u8,
+    pf_sp: u8,
+    reserved: [u8; 5],
+    parameter_list_length_be: [u8; 2],
+    control: u8,
+}
+
+impl ModeSelect10 {
+    fn new(save: bool, len: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 10);
+        Self {
+            operation_code: 0x55,
+            pf_sp: 0x10 | save as u8, // PF=1: pages in standard format
+            reserved: [0; 5],
+            parameter_list_length_be: len.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ModeSelect10 {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ModeSelect10 {}
+
+/// INQUIRY
+/// Seagate SCSI Commands Reference Manual s3.6
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct Inquiry {
+    operation_code: u8,
+    evpd: u8,
+    page_code: u8,
+    allocation_length_be: [u8; 2],
+    control: u8,
+}
+
+impl Inquiry {
+    fn new(evpd: Option<u8>, len: u16) -> Self {
+        assert!(core::mem::size_of::<Self>() == 6);
+        Self {
+            operation_code: 0x12,
+            evpd: evpd.is_some() as u8,
+            page_code: evpd.unwrap_or_default(),
+            allocation_length_be: len.to_be_bytes(),
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for Inquiry {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for Inquiry {}
+
+/// Standard INQUIRY data
+/// Seagate SCSI Commands Reference Manual s3.6.2
+///
+/// This is the compulsory leading 36 bytes; the actual data might be
+/// larger (but the device truncates it, and tells us that it's done
+/// so via the "residue" field of the command status wrapper).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct StandardInquiryData {
+    peripheral_device_type: u8,
+    removable: u8,
+    version: u8,
+    data_format: u8,
+    additional_length: u8,
+    flags: [u8; 3],
+    vendor_id: [u8; 8],
+    product_id: [u8; 16],
+    product_revision: [u8; 4],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for StandardInquiryData {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for StandardInquiryData {}
+
+/// Inquiry Block Limits page
+/// Seagate SCSI Commands Reference Manual s5.4.5
+///
+/// The fields are big-endian on the wire; use the accessor methods to
+/// read them. Most limits use zero to mean "not reported", and the
+/// accessors return `None` in that case.
 #[derive(Copy, Clone, Default, PartialEq, Eq)]
 #[repr(C)]
 pub struct BlockLimitsPage {
@@ -448,25 +1123,321 @@ pub struct BlockLimitsPage {
     maximum_transfer_length: [u8; 4],
     optimal_transfer_length: [u8; 4], // 16
 
-    maximum_prefetch_length: [u8; 4],
-    maximum_unmap_lba_count: [u8; 4],
-    maximum_unmap_block_descriptor_count: [u8; 4],
-    optimal_unmap_granularity: [u8; 4], // 32
+    maximum_prefetch_length: [u8; 4],
+    maximum_unmap_lba_count: [u8; 4],
+    maximum_unmap_block_descriptor_count: [u8; 4],
+    optimal_unmap_granularity: [u8; 4], // 32
+
+    unmap_granularity_alignemnt: [u8; 4],
+    maximum_write_same_length: [u8; 8],
+    maximum_atomic_transfer_length: [u8; 4], // 48
+
+    atomic_alignment: [u8; 4],
+    atomic_transfer_length_granularity: [u8; 4],
+    maximum_atomic_transfer_length_with_atomic_boundary: [u8; 4],
+    maximum_atomic_boundary_size: [u8; 4],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for BlockLimitsPage {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for BlockLimitsPage {}
+
+fn nonzero_u32(field: [u8; 4]) -> Option<u32> {
+    Some(u32::from_be_bytes(field)).filter(|n| *n != 0)
+}
+
+impl BlockLimitsPage {
+    /// The page code of this VPD page
+    pub const PAGE_CODE: u8 = 0xB0;
+
+    /// The smallest plausible page length: that of early versions of
+    /// the page, which stopped after the optimal transfer length
+    const MIN_PAGE_LENGTH: u16 = 0x0C;
+
+    /// The length of the page, not counting the 4-byte header
+    pub fn page_length(&self) -> u16 {
+        u16::from_be_bytes(self.page_length)
+    }
+
+    /// The largest number of blocks in one READ or WRITE, if limited
+    pub fn maximum_transfer_length_blocks(&self) -> Option<u32> {
+        nonzero_u32(self.maximum_transfer_length)
+    }
+
+    /// The preferred granularity of transfers, in blocks
+    pub fn optimal_transfer_length_granularity(&self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.optimal_transfer_length_granularity))
+            .filter(|n| *n != 0)
+    }
+
+    /// The preferred largest number of blocks in one READ or WRITE
+    pub fn optimal_transfer_length(&self) -> Option<u32> {
+        nonzero_u32(self.optimal_transfer_length)
+    }
+
+    /// The largest number of blocks in one COMPARE AND WRITE, or `None`
+    /// if COMPARE AND WRITE isn't supported
+    pub fn maximum_compare_and_write_length(&self) -> Option<u8> {
+        Some(self.max_compare_and_write).filter(|n| *n != 0)
+    }
+
+    /// The largest number of blocks in one PRE-FETCH
+    pub fn maximum_prefetch_length(&self) -> Option<u32> {
+        nonzero_u32(self.maximum_prefetch_length)
+    }
+
+    /// The largest number of blocks in one UNMAP, or `None` if UNMAP
+    /// isn't supported (`Some(u32::MAX)` means no limit)
+    pub fn maximum_unmap_lba_count(&self) -> Option<u32> {
+        nonzero_u32(self.maximum_unmap_lba_count)
+    }
+
+    /// The largest number of descriptors in one UNMAP, or `None` if
+    /// UNMAP isn't supported (`Some(u32::MAX)` means no limit)
+    pub fn maximum_unmap_block_descriptor_count(&self) -> Option<u32> {
+        nonzero_u32(self.maximum_unmap_block_descriptor_count)
+    }
+
+    /// The preferred granularity of UNMAP, in blocks
+    pub fn unmap_granularity(&self) -> Option<u32> {
+        nonzero_u32(self.optimal_unmap_granularity)
+    }
+
+    /// The first block at which the unmap granularity applies, if valid
+    pub fn unmap_granularity_alignment(&self) -> Option<u32> {
+        let n = u32::from_be_bytes(self.unmap_granularity_alignemnt);
+        // The top bit is UGAVALID
+        if (n & 0x8000_0000) != 0 {
+            Some(n & 0x7FFF_FFFF)
+        } else {
+            None
+        }
+    }
+
+    /// The largest number of blocks in one WRITE SAME
+    pub fn maximum_write_same_length(&self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.maximum_write_same_length))
+            .filter(|n| *n != 0)
+    }
+
+    /// The largest number of blocks in one atomic write, if supported
+    pub fn maximum_atomic_transfer_length(&self) -> Option<u32> {
+        nonzero_u32(self.maximum_atomic_transfer_length)
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for BlockLimitsPage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BlockLimitsPage")
+            .field(
+                "maximum_transfer_length",
+                &self.maximum_transfer_length_blocks(),
+            )
+            .field(
+                "optimal_transfer_length_granularity",
+                &self.optimal_transfer_length_granularity(),
+            )
+            .field("optimal_transfer_length", &self.optimal_transfer_length())
+            .field("maximum_prefetch_length", &self.maximum_prefetch_length())
+            .field("maximum_unmap_lba_count", &self.maximum_unmap_lba_count())
+            .field(
+                "maximum_unmap_block_descriptor_count",
+                &self.maximum_unmap_block_descriptor_count(),
+            )
+            .field("unmap_granularity", &self.unmap_granularity())
+            .field(
+                "unmap_granularity_alignment",
+                &self.unmap_granularity_alignment(),
+            )
+            .field(
+                "maximum_write_same_length",
+                &self.maximum_write_same_length(),
+            )
+            .field(
+                "maximum_atomic_transfer_length",
+                &self.maximum_atomic_transfer_length(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for BlockLimitsPage {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "BlockLimitsPage {{ maximum_transfer_length: {}, \
+             optimal_transfer_length_granularity: {}, \
+             optimal_transfer_length: {}, maximum_prefetch_length: {}, \
+             maximum_unmap_lba_count: {}, \
+             maximum_unmap_block_descriptor_count: {}, \
+             unmap_granularity: {}, unmap_granularity_alignment: {}, \
+             maximum_write_same_length: {}, \
+             maximum_atomic_transfer_length: {} }}",
+            self.maximum_transfer_length_blocks(),
+            self.optimal_transfer_length_granularity(),
+            self.optimal_transfer_length(),
+            self.maximum_prefetch_length(),
+            self.maximum_unmap_lba_count(),
+            self.maximum_unmap_block_descriptor_count(),
+            self.unmap_granularity(),
+            self.unmap_granularity_alignment(),
+            self.maximum_write_same_length(),
+            self.maximum_atomic_transfer_length(),
+        )
+    }
+}
+
+/// Inquiry Block Device Characteristics page
+/// SBC-4 s6.6.2
+///
+/// Use the accessor methods to read the fields. Many devices, and many
+/// USB bridges, don't fill this page in (or return all zeroes), in
+/// which case the accessors return `None`.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct BlockDeviceCharacteristics {
+    peripheral_device_type: u8,
+    page_code: u8,
+    page_length: [u8; 2],
+    medium_rotation_rate: [u8; 2],
+    product_type: u8,
+    form_factor: u8,
+    zoned: u8,
+    reserved: [u8; 32],
+    reserved2: [u8; 23],
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for BlockDeviceCharacteristics {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for BlockDeviceCharacteristics {}
+
+/// The nominal form factor of a disk
+///
+/// As reported by [`BlockDeviceCharacteristics::nominal_form_factor()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum FormFactor {
+    /// 5.25 inch
+    FiveAndAQuarterInch,
+    /// 3.5 inch
+    ThreeAndAHalfInch,
+    /// 2.5 inch
+    TwoAndAHalfInch,
+    /// 1.8 inch
+    OnePointEightInch,
+    /// Smaller than 1.8 inch (including M.2 and mSATA)
+    SmallerThanOnePointEightInch,
+}
+
+/// The zoned block capabilities of a device
+///
+/// As reported by [`BlockDeviceCharacteristics::zoned()`], or, including
+/// host-managed devices, by
+/// [`ScsiDevice::zone_model()`](crate::scsi_device::ScsiDevice::zone_model).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Zoned {
+    /// Host-aware: random writes work, but sequential ones within each
+    /// zone are faster
+    HostAware,
+    /// Device-managed: the device hides its zones, at some cost in
+    /// random-write performance ("drive-managed SMR")
+    DeviceManaged,
+    /// Host-managed: writes within each zone must be sequential, at
+    /// its write pointer, or they fail (ZBC). Never reported in the
+    /// Block Device Characteristics page, as these devices have their
+    /// own [`PeripheralType`].
+    HostManaged,
+}
+
+impl BlockDeviceCharacteristics {
+    /// The page code of this VPD page
+    pub const PAGE_CODE: u8 = 0xB1;
 
-    unmap_granularity_alignemnt: [u8; 4],
-    maximum_write_same_length: [u8; 8],
-    maximum_atomic_transfer_length: [u8; 4], // 48
+    /// The smallest plausible page length: enough for the rotation rate
+    const MIN_PAGE_LENGTH: u16 = 2;
 
-    atomic_alignment: [u8; 4],
-    atomic_transfer_length_granularity: [u8; 4],
-    maximum_atomic_transfer_length_with_atomic_boundary: [u8; 4],
-    maximum_atomic_boundary_size: [u8; 4],
+    /// The length of the page, not counting the 4-byte header
+    pub fn page_length(&self) -> u16 {
+        u16::from_be_bytes(self.page_length)
+    }
+
+    /// The rotation rate of the medium, in RPM, or `Some(1)` if it
+    /// doesn't rotate (see [`BlockDeviceCharacteristics::is_solid_state()`])
+    pub fn medium_rotation_rate(&self) -> Option<u16> {
+        match u16::from_be_bytes(self.medium_rotation_rate) {
+            0 | 0xFFFF => None,
+            n => Some(n),
+        }
+    }
+
+    /// Does the device (reportedly) have a non-rotating medium?
+    ///
+    /// That is, is it flash memory or similar, rather than a spinning
+    /// disk? False if the device doesn't say.
+    pub fn is_solid_state(&self) -> bool {
+        self.medium_rotation_rate() == Some(1)
+    }
+
+    /// The product type, e.g. 1 for CFast or 9 for MMC/eMMC
+    pub fn product_type(&self) -> Option<u8> {
+        Some(self.product_type).filter(|n| *n != 0)
+    }
+
+    /// The nominal form factor
+    pub fn nominal_form_factor(&self) -> Option<FormFactor> {
+        match self.form_factor & 0xF {
+            1 => Some(FormFactor::FiveAndAQuarterInch),
+            2 => Some(FormFactor::ThreeAndAHalfInch),
+            3 => Some(FormFactor::TwoAndAHalfInch),
+            4 => Some(FormFactor::OnePointEightInch),
+            5 => Some(FormFactor::SmallerThanOnePointEightInch),
+            _ => None,
+        }
+    }
+
+    /// The zoned block capabilities, if any
+    pub fn zoned(&self) -> Option<Zoned> {
+        match (self.zoned >> 4) & 3 {
+            1 => Some(Zoned::HostAware),
+            2 => Some(Zoned::DeviceManaged),
+            _ => None,
+        }
+    }
 }
 
-// SAFETY: all fields zeroable
-unsafe impl bytemuck::Zeroable for BlockLimitsPage {}
-// SAFETY: no padding, no disallowed bit patterns
-unsafe impl bytemuck::Pod for BlockLimitsPage {}
+#[cfg(feature = "std")]
+impl core::fmt::Debug for BlockDeviceCharacteristics {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BlockDeviceCharacteristics")
+            .field("medium_rotation_rate", &self.medium_rotation_rate())
+            .field("product_type", &self.product_type())
+            .field("nominal_form_factor", &self.nominal_form_factor())
+            .field("zoned", &self.zoned())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for BlockDeviceCharacteristics {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "BlockDeviceCharacteristics {{ medium_rotation_rate: {}, \
+             product_type: {}, nominal_form_factor: {}, zoned: {} }}",
+            self.medium_rotation_rate(),
+            self.product_type(),
+            self.nominal_form_factor(),
+            self.zoned(),
+        )
+    }
+}
 
 /// SCSI "Peripheral Type" (general device type)
 ///
@@ -516,7 +1487,9 @@ pub enum PeripheralType {
     /// Automation for mounting/unmounting media
     Automation = 0x12,
     Reserved13 = 0x13,
-    Reserved14 = 0x14,
+    /// Zoned disks which need the host to do their bidding (ZBC), such
+    /// as host-managed SMR drives
+    HostManagedZoned = 0x14,
     Reserved15 = 0x15,
     Reserved16 = 0x16,
     Reserved17 = 0x17,
@@ -531,6 +1504,110 @@ pub enum PeripheralType {
     Other = 0x1F,
 }
 
+impl PeripheralType {
+    /// The peripheral type in the low five bits of `byte`
+    fn from_bits(byte: u8) -> Self {
+        const TYPES: [PeripheralType; 32] = [
+            PeripheralType::Disk,
+            PeripheralType::Sequential,
+            PeripheralType::Printer,
+            PeripheralType::Processor,
+            PeripheralType::WriteOnce,
+            PeripheralType::Optical,
+            PeripheralType::Scanner,
+            PeripheralType::OpticalMemory,
+            PeripheralType::Changer,
+            PeripheralType::Communications,
+            PeripheralType::Obsolete10,
+            PeripheralType::Obsolete11,
+            PeripheralType::StorageArray,
+            PeripheralType::EnclosureServices,
+            PeripheralType::SimplifiedDirect,
+            PeripheralType::OpticalCardReader,
+            PeripheralType::BridgeController,
+            PeripheralType::ObjectStorage,
+            PeripheralType::Automation,
+            PeripheralType::Reserved13,
+            PeripheralType::HostManagedZoned,
+            PeripheralType::Reserved15,
+            PeripheralType::Reserved16,
+            PeripheralType::Reserved17,
+            PeripheralType::Reserved18,
+            PeripheralType::Reserved19,
+            PeripheralType::Reserved1A,
+            PeripheralType::Reserved1B,
+            PeripheralType::Reserved1C,
+            PeripheralType::Reserved1D,
+            PeripheralType::WellKnownUnit,
+            PeripheralType::Other,
+        ];
+        TYPES[(byte & 0x1F) as usize]
+    }
+}
+
+/// Whether a device is actually present at a LUN
+///
+/// The "peripheral qualifier" from INQUIRY: see
+/// [`InquiryData::peripheral_qualifier`]. Multi-slot card readers, for
+/// instance, typically report empty slots as `NotCapable`.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum PeripheralQualifier {
+    /// A device of the reported type is connected to this LUN
+    #[default]
+    Connected,
+    /// A device of the reported type could be connected to this LUN,
+    /// but isn't at the moment
+    NotConnected,
+    /// The device can't support any device at this LUN; the peripheral
+    /// type is meaningless
+    NotCapable,
+    /// Reserved or vendor-specific qualifier
+    Other(u8),
+}
+
+/// The version of the SCSI standard which a device claims to follow
+///
+/// As reported by INQUIRY: see [`InquiryData::version`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum SpcVersion {
+    /// No particular standard (common with USB bridges)
+    #[default]
+    None,
+    /// SCSI-2 (or, from older devices, SCSI-1)
+    Scsi2,
+    /// SPC (SCSI-3)
+    Spc,
+    /// SPC-2
+    Spc2,
+    /// SPC-3
+    Spc3,
+    /// SPC-4
+    Spc4,
+    /// SPC-5
+    Spc5,
+    /// Obsolete or reserved version code
+    Other(u8),
+}
+
+impl SpcVersion {
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => SpcVersion::None,
+            1 | 2 => SpcVersion::Scsi2,
+            3 => SpcVersion::Spc,
+            4 => SpcVersion::Spc2,
+            5 => SpcVersion::Spc3,
+            6 => SpcVersion::Spc4,
+            7 => SpcVersion::Spc5,
+            n => SpcVersion::Other(n),
+        }
+    }
+}
+
 /// Information obtained from INQUIRY command
 ///
 /// i.e., returned from [ScsiDevice::inquiry]
@@ -539,126 +1616,832 @@ pub enum PeripheralType {
 #[derive(Copy, Clone, Default, PartialEq, Eq)]
 pub struct InquiryData {
     /// The general type of the attached SCSI device
+    ///
+    /// Only meaningful if `peripheral_qualifier` is
+    /// [`PeripheralQualifier::Connected`] (or `NotConnected`).
     pub peripheral_type: PeripheralType,
+    /// Whether there's really a device at this LUN
+    pub peripheral_qualifier: PeripheralQualifier,
+    /// Whether the SCSI device supports removable media
+    ///
+    /// Disks usually no; CD-ROMs usually yes.
+    pub is_removable: bool,
+    /// Vendor identification, ASCII, space-padded
+    pub vendor_id: [u8; 8],
+    /// Product identification, ASCII, space-padded
+    pub product_id: [u8; 16],
+    /// Product revision level, ASCII, space-padded
+    pub product_revision: [u8; 4],
+    /// The version of the SCSI standard claimed
+    pub version: SpcVersion,
+    /// The format of the INQUIRY data itself: 2 for all modern devices
+    pub response_data_format: u8,
+    /// Whether the device supports command queueing (CmdQue)
+    pub command_queueing: bool,
+}
+
+/// Convert a space-padded ASCII field into a string
+fn ascii_field(field: &[u8]) -> &str {
+    core::str::from_utf8(field)
+        .unwrap_or_default()
+        .trim_end_matches([' ', '\0'])
+}
+
+impl InquiryData {
+    /// Is there actually a device at this LUN?
+    ///
+    /// Code enumerating the LUNs of a multi-LUN device should skip
+    /// those where this is false.
+    pub fn is_connected(&self) -> bool {
+        self.peripheral_qualifier == PeripheralQualifier::Connected
+    }
+
+    /// The vendor identification, with any padding removed
+    pub fn vendor(&self) -> &str {
+        ascii_field(&self.vendor_id)
+    }
+
+    /// The product identification, with any padding removed
+    pub fn product(&self) -> &str {
+        ascii_field(&self.product_id)
+    }
+
+    /// The product revision level, with any padding removed
+    pub fn revision(&self) -> &str {
+        ascii_field(&self.product_revision)
+    }
+}
+
+/// Everything worth knowing about a device before using it
+///
+/// As determined by [`ScsiDevice::probe()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// The results of the INQUIRY command: type, vendor, product
+    pub inquiry: InquiryData,
+    /// The total number of blocks on the device
+    pub blocks: u64,
+    /// The size of each block in bytes
+    pub block_size: u32,
+    /// The size of each physical block in bytes
+    ///
+    /// Writes are best done in whole physical blocks. If the device
+    /// doesn't report this, it's the same as `block_size`.
+    pub physical_block_size: u32,
+    /// Whether the device is known to support the 16-byte
+    /// READ/WRITE/READ CAPACITY commands
+    pub supports_16: bool,
+    /// The maximum number of blocks in one transfer, if the device
+    /// reports it
+    pub max_transfer_blocks: Option<u32>,
+    /// Whether the medium is write-protected, if the device reports it
+    pub write_protected: Option<bool>,
+}
+
+impl DeviceCapabilities {
     /// Whether the SCSI device supports removable media
+    pub fn is_removable(&self) -> bool {
+        self.inquiry.is_removable
+    }
+
+    /// The vendor identification, with any padding removed
+    pub fn vendor(&self) -> &str {
+        self.inquiry.vendor()
+    }
+
+    /// The product identification, with any padding removed
+    pub fn product(&self) -> &str {
+        self.inquiry.product()
+    }
+}
+
+/// What an earlier [`ScsiDevice::probe()`] found out about a device's
+/// optional commands
+///
+/// Finding this out costs several commands, some of which (REPORT
+/// SUPPORTED OPERATION CODES, INQUIRY for vital product data) upset
+/// certain devices; an application which sees the same devices again
+/// and again can store these hints, and pass them back with
+/// [`ScsiDevice::preload()`] to skip those steps next time.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct ProbeHints {
+    /// See [`DeviceCapabilities::supports_16`]
+    pub supports_16: bool,
+    /// The maximum number of blocks in one transfer, from the Block
+    /// Limits Page, if the device reports it
+    pub max_transfer_blocks: Option<u32>,
+}
+
+/// How long [`ScsiDevice::wait_until_ready()`] waits between polls
+pub const READY_POLL_INTERVAL_MS: usize = 100;
+
+/// How long [`ScsiDevice::wait_for_media_change()`] waits between polls
+pub const MEDIA_POLL_INTERVAL_MS: usize = 500;
+
+/// How long [`ScsiDevice::format_unit_and_wait()`] and
+/// [`ScsiDevice::self_test()`] wait between polls
+pub const PROGRESS_POLL_INTERVAL_MS: usize = 1000;
+
+/// Which of a device's own self-tests to run, with
+/// [`ScsiDevice::start_self_test()`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SelfTest {
+    /// The short self-test: typically a couple of minutes
+    Short,
+    /// The extended self-test, which usually reads the whole medium:
+    /// typically hours
+    Extended,
+}
+
+impl SelfTest {
+    /// The SELF-TEST CODE for running this test in the background
+    fn code(self) -> u8 {
+        match self {
+            Self::Short => 1,
+            Self::Extended => 2,
+        }
+    }
+}
+
+/// The state of a device's removable medium, as found by
+/// [`ScsiDevice::check_media()`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MediaState {
+    /// The medium is present and ready; as far as the device knows, it's
+    /// the same medium as before
+    Ready,
+    /// A medium is present, but it might be a different one: the
+    /// device should be re-probed, and any filesystem re-mounted
+    Changed,
+    /// There's no medium in the drive
+    NotPresent,
+    /// A medium is present, but isn't ready yet (for instance, it's
+    /// still spinning up)
+    NotReady,
+}
+
+/// How many times [`ScsiDevice::probe()`] asks if the device is ready
+const PROBE_READY_ATTEMPTS: u32 = 10;
+
+/// Decode sense data in either fixed or descriptor format
+///
+/// Seagate SCSI Commands Reference Manual s2.4. Response codes 0x72 and
+/// 0x73 are descriptor format, anything else is treated as fixed format.
+/// Returns `None` if the data is too short to be either.
+pub(crate) fn parse_sense(buf: &[u8]) -> Option<SenseData> {
+    match buf.first()? & 0x7F {
+        0x72 | 0x73 => {
+            if buf.len() < 8 {
+                return None;
+            }
+            let mut information = None;
+            let mut sense_key_specific = None;
+            let end = (buf[7] as usize + 8).min(buf.len());
+            let mut descriptors = &buf[8..end];
+            while descriptors.len() >= 2 {
+                let len = descriptors[1] as usize + 2;
+                let Some(descriptor) = descriptors.get(0..len) else {
+                    break; // truncated
+                };
+                // Information descriptor, with VALID bit set
+                if descriptor[0] == 0
+                    && len >= 12
+                    && (descriptor[2] & 0x80) != 0
+                {
+                    let mut lba = [0u8; 8];
+                    lba.copy_from_slice(&descriptor[4..12]);
+                    information = Some(u64::from_be_bytes(lba));
+                }
+                // Sense key specific descriptor, with SKSV bit set
+                if descriptor[0] == 2
+                    && len >= 7
+                    && (descriptor[4] & 0x80) != 0
+                {
+                    sense_key_specific = Some([
+                        descriptor[4] & 0x7F,
+                        descriptor[5],
+                        descriptor[6],
+                    ]);
+                }
+                descriptors = &descriptors[len..];
+            }
+            Some(SenseData {
+                key: buf[1] & 0xF,
+                asc: buf[2],
+                ascq: buf[3],
+                information,
+                sense_key_specific,
+            })
+        }
+        _ => {
+            let reply = bytemuck::try_from_bytes::<RequestSenseReply>(
+                buf.get(0..core::mem::size_of::<RequestSenseReply>())?,
+            )
+            .ok()?;
+            Some(SenseData {
+                key: reply.sense_key & 0xF,
+                asc: reply.additional_sense_code,
+                ascq: reply.additional_sense_code_qualifier,
+                information: if (reply.response_code & 0x80) != 0 {
+                    Some(u32::from_be_bytes(reply.information) as u64)
+                } else {
+                    None
+                },
+                sense_key_specific: if reply.additional_length >= 10
+                    && (reply.sense_key_specific[0] & 0x80) != 0
+                {
+                    let mut sks = reply.sense_key_specific;
+                    sks[0] &= 0x7F;
+                    Some(sks)
+                } else {
+                    None
+                },
+            })
+        }
+    }
+}
+
+/// Does the sense data say that a long-running operation is under way?
+///
+/// Either there's a progress indication, or (for devices which don't
+/// give one) ASC 04h, "logical unit not ready", with a qualifier which
+/// means an operation is in progress.
+fn is_in_progress(sense: &SenseData) -> bool {
+    sense.progress().is_some()
+        || (sense.asc == 4 && matches!(sense.ascq, 4 | 7 | 9 | 0x1B))
+}
+
+/// Map sense data onto the errors in [`ScsiError`], if any applies
+///
+/// The most specific match wins: sense key plus ASC plus ASCQ, then
+/// sense key plus ASC, then sense key alone. The exception is failure
+/// prediction, which is recognised by its ASC whatever the sense key.
+fn upgrade_sense(sense: &SenseData) -> Option<ScsiError> {
+    const ERRORS3: &[(u8, u8, u8, ScsiError)] = &[
+        (2, 4, 1, ScsiError::BecomingReady),
+        (2, 4, 2, ScsiError::StartUnitRequired),
+        (2, 4, 3, ScsiError::ManualInterventionRequired),
+        (2, 4, 4, ScsiError::FormatInProgress),
+        (2, 4, 9, ScsiError::SelfTestInProgress),
+        (2, 4, 0x22, ScsiError::PowerCycleRequired),
+        (1, 0x0B, 0x01, ScsiError::Overheat),
+        (1, 0x0B, 0x02, ScsiError::EnclosureDegraded),
+        (1, 0x0B, 0x05, ScsiError::SelfTestFailed),
+        (3, 0x0C, 0x00, ScsiError::WriteError),
+        (3, 0x0C, 0x02, ScsiError::WriteReallocationFailed),
+        (1, 0x11, 0x00, ScsiError::UnrecoveredReadError),
+        (1, 0x11, 0x01, ScsiError::ReadRetriesExhausted),
+        (1, 0x11, 0x02, ScsiError::ReadErrorTooLong),
+        (3, 0x11, 0x04, ScsiError::ReadReallocationFailed),
+        (3, 0x14, 0x00, ScsiError::LogicalBlockNotFound),
+        (3, 0x14, 0x01, ScsiError::RecordNotFound),
+        (5, 0x21, 0x04, ScsiError::UnalignedWrite),
+        (5, 0x26, 0x00, ScsiError::InvalidFieldInParameterList),
+        (5, 0x26, 0x01, ScsiError::ParameterNotSupported),
+        (5, 0x26, 0x02, ScsiError::ParameterValueInvalid),
+        (4, 0x3E, 0x03, ScsiError::LogicalUnitSelfTestFailed),
+        (4, 0x42, 0x00, ScsiError::SelfTestFailed),
+    ];
+    const ERRORS2: &[(u8, u8, ScsiError)] = &[
+        (3, 0x14, ScsiError::PositioningError),
+        (5, 0x1A, ScsiError::ParameterListLengthError),
+        (0xE, 0x1D, ScsiError::MiscompareDuringVerify),
+        (5, 0x20, ScsiError::InvalidCommandOperationCode),
+        (0xD, 0x21, ScsiError::LogicalBlockAddressOutOfRange),
+        (5, 0x21, ScsiError::LogicalBlockAddressOutOfRange),
+        (5, 0x24, ScsiError::InvalidFieldInCDB),
+        (5, 0x25, ScsiError::LogicalUnitNotSupported),
+        (2, 0x3A, ScsiError::MediaNotPresent),
+        (5, 0x55, ScsiError::InsufficientResources),
+        (6, 0x28, ScsiError::MediaChanged),
+        (6, 0x29, ScsiError::ResetOccurred),
+        (6, 0x3A, ScsiError::MediaNotPresent),
+        (7, 0x27, ScsiError::WriteProtected),
+    ];
+    const ERRORS1: &[(u8, ScsiError)] = &[
+        (2, ScsiError::NotReady),
+        (3, ScsiError::MediumError),
+        (4, ScsiError::HardwareError),
+        (5, ScsiError::IllegalRequest),
+        (6, ScsiError::UnitAttention),
+        (7, ScsiError::DataProtect),
+        (8, ScsiError::BlankCheck),
+        (9, ScsiError::VendorSpecific),
+        (10, ScsiError::CopyAborted),
+        (11, ScsiError::Aborted),
+        (13, ScsiError::VolumeOverflow),
+        (14, ScsiError::Miscompare),
+    ];
+
+    // Failure prediction (5Dh) can come with any of several sense
+    // keys, depending on the MRIE setting
+    if sense.asc == 0x5D {
+        return Some(ScsiError::FailurePredicted(sense.ascq));
+    }
+    for i in ERRORS3 {
+        if sense.key == i.0 && sense.asc == i.1 && sense.ascq == i.2 {
+            return Some(i.3);
+        }
+    }
+    for i in ERRORS2 {
+        if sense.key == i.0 && sense.asc == i.1 {
+            return Some(i.2);
+        }
+    }
+    for i in ERRORS1 {
+        if sense.key == i.0 {
+            return Some(i.1);
+        }
+    }
+    None
+}
+
+/// Does this error just mean that the device doesn't support a command?
+fn is_unsupported<T: PartialEq + Eq>(e: &Error<T>) -> bool {
+    matches!(
+        e,
+        Error::CommandFailed
+            | Error::ProtocolError
+            | Error::Scsi(
+                ScsiError::InvalidCommandOperationCode
+                    | ScsiError::InvalidFieldInCDB
+                    | ScsiError::IllegalRequest
+            )
+    )
+}
+
+/// One completed command, as reported to a [`Tracer`]
+///
+/// See [`ScsiDevice::set_tracer()`].
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct TraceEvent<'a, E: PartialEq + Eq> {
+    /// The command block, as sent to the transport
+    pub cdb: &'a [u8],
+
+    /// The number of bytes transferred, or the error (after any
+    /// upgrading using REQUEST SENSE)
+    pub result: Result<usize, &'a Error<E>>,
+
+    /// If the command failed, the sense data which REQUEST SENSE
+    /// returned about it (if any)
+    pub sense: Option<&'a SenseData>,
+}
+
+/// A function which is told about each command issued by a [`ScsiDevice`]
+pub type Tracer<E> = fn(&TraceEvent<'_, E>);
+
+/// Which steps of [`ScsiDevice::eject()`] the device carried out
+///
+/// A step which is `false` was rejected as unsupported: common, with
+/// USB bridges, for all but the last step.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct EjectSummary {
+    /// SYNCHRONIZE CACHE succeeded
+    pub cache_flushed: bool,
+    /// PREVENT ALLOW MEDIUM REMOVAL (allow) succeeded
+    pub removal_allowed: bool,
+    /// START STOP UNIT (stop and eject) succeeded
+    pub stopped: bool,
+}
+
+/// What to do when a command fails with a Unit Attention condition
+///
+/// See [`ScsiDevice::set_unit_attention_policy()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum UnitAttentionPolicy {
+    /// Report every Unit Attention as an error (the default)
+    #[default]
+    Surface,
+    /// Reissue a command which failed because of a benign Unit
+    /// Attention, up to `max` times
+    Retry {
+        /// The most times to reissue any one command
+        max: u8,
+    },
+}
+
+/// Is this sense data a Unit Attention which can safely be retried?
+///
+/// Only "power on, reset, or bus device reset occurred" (29h) is: in
+/// particular, "not ready to ready change, medium may have changed"
+/// (28h) isn't, as it means that the medium can't be assumed to be
+/// the same one as before.
+fn is_benign_unit_attention(sense: &SenseData) -> bool {
+    sense.key == 6 && sense.asc == 0x29
+}
+
+/// Counts of what a [`ScsiDevice`] has been doing
+///
+/// As returned by [`ScsiDevice::stats()`]. All the counts wrap around
+/// on overflow, and all start from zero when the device is created or
+/// [`ScsiDevice::reset_stats()`] is called.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct ScsiStats {
+    /// The number of commands issued, including REQUEST SENSE and any
+    /// retries
+    pub commands: u32,
+
+    /// The number of bytes transferred from the device, by all commands
+    /// (not just READ)
+    pub bytes_read: u64,
+
+    /// The number of bytes transferred to the device, by all commands
+    /// (not just WRITE)
+    pub bytes_written: u64,
+
+    /// The number of commands which failed, for whatever reason
+    pub failures: u32,
+
+    /// The number of failures whose REQUEST SENSE returned each sense
+    /// key: for instance, `failures_by_sense_key[3]` counts medium
+    /// errors
+    pub failures_by_sense_key: [u32; 16],
+
+    /// The number of commands reissued under the
+    /// [`UnitAttentionPolicy`], or because the device was busy
+    pub retries: u32,
+
+    /// The most recent sense data: (sense key, ASC, ASCQ)
+    pub last_sense: Option<(u8, u8, u8)>,
+
+    /// The value of `commands` when `last_sense` was recorded, so that
+    /// a poller can tell whether it's new
+    pub last_sense_sequence: u32,
+}
+
+/// A generic SCSI device, attached over a particular transport
+///
+/// The first commands issued to a newly-discovered device are
+/// typically [`ScsiDevice::test_unit_ready()`] followed by, if it's
+/// ready, [`ScsiDevice::inquiry()`].
+///
+/// # Command support across test devices
+///
+/// {R,W,RC}(10) = READ(10), WRITE(10), READ_CAPACITY(10)
+///
+/// {R,W,RC}(16) = READ(16), WRITE(16), READ_CAPACITY(16)
+///
+/// BLP = INQUIRY, Vital Product Data, Basic Limits Page
+///
+/// RSOC = REPORT SUPPORTED OPERATION CODES
+///
+/// | Test device  | {R,W,RC}(10) | {R,W,RC}(16) |  BLP  |  RSOC  |
+/// | ---          | :---:        | :---:        | :---: | :---:  |
+/// | Black (4G)   |    Y         | Y | - | - |
+/// | Green (16G)  |    Y         | Y | - | - |
+/// | Handbag (8G) |    Y         | - | - | - |
+/// | Poker (1G)   |    Y         | - | - | - |
+/// | Kingston (120G)[^1] | Y     | Y | - | - |
+/// | Sandisk (120G)[^2] | Y      | Y | - | - |
+/// | WD (500G)[^3] |   Y         | Y | - | - |
+///
+/// [^1]: M.2 SSD via JMicron 20337
+/// [^2]: mSATA SSD via JMicron 20337
+/// [^3]: SATA winchester via JMicron 20337
+pub struct ScsiDevice<T: ScsiTransport> {
+    transport: T,
+    max_transfer_blocks: Option<u32>,
+    max_compare_and_write: Option<u8>,
+    supports_16: Option<bool>,
+    pub(crate) capabilities: Option<DeviceCapabilities>,
+    last_sense: Option<SenseData>,
+    capacity_16: Option<ReadCapacity16Data>,
+    block_size: Option<u32>,
+    timeouts: Timeouts,
+    tracer: Option<Tracer<T::Error>>,
+    unit_attention_policy: UnitAttentionPolicy,
+    busy_retries: u8,
+    last_status: Option<ScsiStatus>,
+    stats: ScsiStats,
+    quirks: ScsiQuirks,
+    preloaded: bool,
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Create a new device, from the given transport
+    ///
+    /// The device starts off with whatever quirks the transport
+    /// reports, see [`ScsiTransport::quirks()`].
+    pub fn new(transport: T) -> Self {
+        let quirks = transport.quirks();
+        Self {
+            transport,
+            max_transfer_blocks: None,
+            max_compare_and_write: None,
+            supports_16: None,
+            capabilities: None,
+            last_sense: None,
+            capacity_16: None,
+            block_size: None,
+            timeouts: Timeouts::DEFAULT,
+            tracer: None,
+            unit_attention_policy: UnitAttentionPolicy::Surface,
+            busy_retries: DEFAULT_BUSY_RETRIES,
+            last_status: None,
+            stats: ScsiStats::default(),
+            quirks,
+            preloaded: false,
+        }
+    }
+
+    /// The workarounds in force for this device
+    pub fn quirks(&self) -> ScsiQuirks {
+        self.quirks
+    }
+
+    /// Change the workarounds in force for this device
+    ///
+    /// With [`ScsiQuirks::no_vpd`] or [`ScsiQuirks::no_rsoc`], the
+    /// corresponding commands aren't issued at all, but fail straight
+    /// away as if the device had rejected them; [`ScsiDevice::probe()`]
+    /// then skips them.
+    pub fn set_quirks(&mut self, quirks: ScsiQuirks) {
+        self.quirks = quirks;
+    }
+
+    /// Tell the device what an earlier probe found out
+    ///
+    /// [`ScsiDevice::probe()`] then skips REPORT SUPPORTED OPERATION
+    /// CODES (and the trial READ CAPACITY(16)) and the Block Limits
+    /// Page, and uses these answers instead. See [`ProbeHints`].
+    pub fn preload(&mut self, hints: ProbeHints) {
+        self.supports_16 = Some(hints.supports_16);
+        self.max_transfer_blocks = hints.max_transfer_blocks;
+        self.preloaded = true;
+    }
+
+    /// What the most recent successful [`ScsiDevice::probe()`] found
+    /// out, in a form suitable for [`ScsiDevice::preload()`]
+    pub fn probe_hints(&self) -> Option<ProbeHints> {
+        self.capabilities.map(|c| ProbeHints {
+            supports_16: c.supports_16,
+            max_transfer_blocks: self.max_transfer_blocks,
+        })
+    }
+
+    /// The underlying transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// The underlying transport, mutably
+    ///
+    /// Issuing commands directly through the transport bypasses any
+    /// state cached by the `ScsiDevice`, such as
+    /// [`ScsiDevice::capabilities()`].
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Stop using this device, and return the underlying transport
+    ///
+    /// For instance, to perform transport-level recovery (such as USB
+    /// mass-storage reset recovery) before constructing a new
+    /// `ScsiDevice`.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// The timeouts passed to the transport with each command
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// Change the timeouts passed to the transport with each command
+    ///
+    /// These only have an effect if the transport enforces them, as
+    /// [`TimedTransport`](crate::timeout::TimedTransport) does. A
+    /// timed-out command fails with [`Error::Timeout`], and isn't
+    /// followed by REQUEST SENSE.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    async fn transport_command(
+        &mut self,
+        cmd: &[u8],
+        data: Payload<'_, '_>,
+    ) -> Result<usize, Error<T::Error>> {
+        self.stats.commands = self.stats.commands.wrapping_add(1);
+        let timeout_ms = self.timeouts.for_command(cmd);
+        match data {
+            Payload::Phase(data) => {
+                self.transport
+                    .command_with_timeout(cmd, data, timeout_ms)
+                    .await
+            }
+            Payload::Uninit(buf) => {
+                // Any data from an earlier attempt is stale
+                buf.clear();
+                self.transport.command_in_uninit(cmd, buf, timeout_ms).await
+            }
+        }
+    }
+
+    /// Report each command, and its outcome, to `tracer`
+    ///
+    /// For debugging (or logging, or counting retries): `tracer` is
+    /// called after every command issued via this device has completed.
+    /// If a command failed, the REQUEST SENSE issued to find out why
+    /// isn't reported separately; instead, the resulting sense data is
+    /// included in the failed command's [`TraceEvent`]. `None` (the
+    /// default) turns tracing off again.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer<T::Error>>) {
+        self.tracer = tracer;
+    }
+
+    /// Choose whether to retry commands which fail with a benign Unit
+    /// Attention condition
+    ///
+    /// After power-on or reset, many devices fail the next command
+    /// with Unit Attention, "power on, reset, or bus device reset
+    /// occurred" ([`ScsiError::ResetOccurred`]); the command itself wasn't at fault, and would succeed
+    /// if reissued. With [`UnitAttentionPolicy::Retry`], it is
+    /// reissued, without the caller seeing the error. Other Unit
+    /// Attention conditions, notably [`ScsiError::MediaChanged`],
+    /// are always reported, whatever the policy.
+    pub fn set_unit_attention_policy(&mut self, policy: UnitAttentionPolicy) {
+        self.unit_attention_policy = policy;
+    }
+
+    /// The current Unit Attention policy
+    pub fn unit_attention_policy(&self) -> UnitAttentionPolicy {
+        self.unit_attention_policy
+    }
+
+    /// Choose how many times to reissue a command which fails with
+    /// [`Error::Busy`]
     ///
-    /// Disks usually no; CD-ROMs usually yes.
-    pub is_removable: bool,
-}
+    /// A device which replies with status BUSY (or TASK SET FULL)
+    /// hasn't carried out the command, but may well do so if it's sent
+    /// again a little later; it's reissued up to this many times, after
+    /// waiting 10ms, then 20ms, 40ms and so on, before the error is
+    /// reported. Zero turns retrying off; the default is 3.
+    ///
+    /// Waiting needs the transport's help (see
+    /// [`ScsiTransport::delay_ms()`]), which, for instance,
+    /// [`TimedTransport`](crate::timeout::TimedTransport) and
+    /// `SgTransport` provide. With a transport that can't wait, the
+    /// command is still reissued this many times, but straight away --
+    /// so wrap such a transport in a `TimedTransport` if the device is
+    /// likely to stay busy for long.
+    pub fn set_busy_retries(&mut self, retries: u8) {
+        self.busy_retries = retries;
+    }
 
-/// A generic SCSI device, attached over a particular transport
-///
-/// The first commands issued to a newly-discovered device are
-/// typically [`ScsiDevice::test_unit_ready()`] followed by, if it's
-/// ready, [`ScsiDevice::inquiry()`].
-///
-/// # Command support across test devices
-///
-/// {R,W,RC}(10) = READ(10), WRITE(10), READ_CAPACITY(10)
-///
-/// {R,W,RC}(16) = READ(16), WRITE(16), READ_CAPACITY(16)
-///
-/// BLP = INQUIRY, Vital Product Data, Basic Limits Page
-///
-/// RSOC = REPORT SUPPORTED OPERATION CODES
-///
-/// | Test device  | {R,W,RC}(10) | {R,W,RC}(16) |  BLP  |  RSOC  |
-/// | ---          | :---:        | :---:        | :---: | :---:  |
-/// | Black (4G)   |    Y         | Y | - | - |
-/// | Green (16G)  |    Y         | Y | - | - |
-/// | Handbag (8G) |    Y         | - | - | - |
-/// | Poker (1G)   |    Y         | - | - | - |
-/// | Kingston (120G)[^1] | Y     | Y | - | - |
-/// | Sandisk (120G)[^2] | Y      | Y | - | - |
-/// | WD (500G)[^3] |   Y         | Y | - | - |
-///
-/// [^1]: M.2 SSD via JMicron 20337
-/// [^2]: mSATA SSD via JMicron 20337
-/// [^3]: SATA winchester via JMicron 20337
-pub struct ScsiDevice<T: ScsiTransport> {
-    transport: T,
-}
+    /// How many times a command which fails with [`Error::Busy`] is
+    /// reissued
+    pub fn busy_retries(&self) -> u8 {
+        self.busy_retries
+    }
 
-impl<T: ScsiTransport> ScsiDevice<T> {
-    /// Create a new device, from the given transport
-    pub fn new(transport: T) -> Self {
-        Self { transport }
+    /// The status with which the most recent command completed, if
+    /// known
+    ///
+    /// As reported by [`ScsiTransport::last_status()`] or, if the
+    /// transport can't see the status byte, as implied by the
+    /// command's result (see [`ScsiStatus::from_result()`]). The
+    /// REQUEST SENSE issued after a failure doesn't count.
+    pub fn last_status(&self) -> Option<ScsiStatus> {
+        self.last_status
+    }
+
+    /// A snapshot of the device's command counters
+    pub fn stats(&self) -> ScsiStats {
+        self.stats
+    }
+
+    /// Set all the command counters back to zero
+    pub fn reset_stats(&mut self) {
+        self.stats = ScsiStats::default();
     }
 
     async fn try_upgrade_error(
         &mut self,
         e: Error<T::Error>,
     ) -> Error<T::Error> {
-        if e == Error::CommandFailed {
-            if let Ok(r) = self.request_sense().await {
-                const ERRORS3: &[(u8, u8, u8, ScsiError)] = &[
-                    (2, 4, 1, ScsiError::BecomingReady),
-                    (2, 4, 2, ScsiError::StartUnitRequired),
-                    (2, 4, 3, ScsiError::ManualInterventionRequired),
-                    (2, 4, 4, ScsiError::FormatInProgress),
-                    (2, 4, 9, ScsiError::SelfTestInProgress),
-                    (2, 4, 0x22, ScsiError::PowerCycleRequired),
-                    (1, 0x0B, 0x01, ScsiError::Overheat),
-                    (1, 0x0B, 0x02, ScsiError::EnclosureDegraded),
-                    (3, 0x0C, 0x00, ScsiError::WriteError),
-                    (3, 0x0C, 0x02, ScsiError::WriteReallocationFailed),
-                    (1, 0x11, 0x00, ScsiError::UnrecoveredReadError),
-                    (1, 0x11, 0x01, ScsiError::ReadRetriesExhausted),
-                    (1, 0x11, 0x02, ScsiError::ReadErrorTooLong),
-                    (3, 0x11, 0x04, ScsiError::ReadReallocationFailed),
-                    (3, 0x14, 0x00, ScsiError::LogicalBlockNotFound),
-                    (3, 0x14, 0x01, ScsiError::RecordNotFound),
-                    (5, 0x26, 0x00, ScsiError::InvalidFieldInParameterList),
-                    (5, 0x26, 0x01, ScsiError::ParameterNotSupported),
-                    (5, 0x26, 0x02, ScsiError::ParameterValueInvalid),
-                    (4, 0x3E, 0x03, ScsiError::LogicalUnitSelfTestFailed),
-                    (4, 0x42, 0x00, ScsiError::SelfTestFailed),
-                ];
-                const ERRORS2: &[(u8, u8, ScsiError)] = &[
-                    (3, 0x14, ScsiError::PositioningError),
-                    (5, 0x1A, ScsiError::ParameterListLengthError),
-                    (0xE, 0x1D, ScsiError::MiscompareDuringVerify),
-                    (5, 0x20, ScsiError::InvalidCommandOperationCode),
-                    (0xD, 0x21, ScsiError::LogicalBlockAddressOutOfRange),
-                    (5, 0x24, ScsiError::InvalidFieldInCDB),
-                    (5, 0x25, ScsiError::LogicalUnitNotSupported),
-                ];
-                const ERRORS1: &[(u8, ScsiError)] = &[
-                    (2, ScsiError::NotReady),
-                    (3, ScsiError::MediumError),
-                    (4, ScsiError::HardwareError),
-                    (5, ScsiError::IllegalRequest),
-                    (6, ScsiError::UnitAttention),
-                    (7, ScsiError::DataProtect),
-                    (8, ScsiError::BlankCheck),
-                    (9, ScsiError::VendorSpecific),
-                    (10, ScsiError::CopyAborted),
-                    (11, ScsiError::Aborted),
-                    (13, ScsiError::VolumeOverflow),
-                    (14, ScsiError::Miscompare),
-                ];
-
-                for i in ERRORS3 {
-                    if r.sense_key == i.0
-                        && r.additional_sense_code == i.1
-                        && r.additional_sense_code_qualifier == i.2
-                    {
-                        return Error::Scsi(i.3);
-                    }
+        // Only CHECK CONDITION comes with sense data; if the transport
+        // says the status was something else (RESERVATION CONFLICT,
+        // say), REQUEST SENSE would tell us nothing
+        let sense_expected = matches!(
+            self.last_status,
+            None | Some(ScsiStatus::CheckCondition)
+        );
+        if e == Error::CommandFailed && sense_expected {
+            if let Ok(sense) = self.request_sense().await {
+                let stats = &mut self.stats;
+                let n =
+                    &mut stats.failures_by_sense_key[sense.key as usize & 0xF];
+                *n = n.wrapping_add(1);
+                stats.last_sense = Some((sense.key, sense.asc, sense.ascq));
+                stats.last_sense_sequence = stats.commands;
+                if let Some(err) = upgrade_sense(&sense) {
+                    return Error::Scsi(err);
                 }
-                for i in ERRORS2 {
-                    if r.sense_key == i.0 && r.additional_sense_code == i.1 {
-                        return Error::Scsi(i.2);
-                    }
+            }
+        }
+        e
+    }
+
+    /// Issue one command, retrying it according to the Unit Attention
+    /// policy, and (after a delay, if possible) if the device is busy
+    async fn execute(
+        &mut self,
+        cmd: &[u8],
+        mut data: Payload<'_, '_>,
+    ) -> Result<usize, Error<T::Error>> {
+        let max = match self.unit_attention_policy {
+            UnitAttentionPolicy::Surface => 0,
+            UnitAttentionPolicy::Retry { max } => max,
+        };
+        let mut retries = 0;
+        let mut busy_retries = 0;
+        loop {
+            let rc = self.execute_once(cmd, data.reborrow()).await;
+            if retries < max
+                && rc == Err(Error::Scsi(ScsiError::ResetOccurred))
+                && self
+                    .last_sense
+                    .as_ref()
+                    .is_some_and(is_benign_unit_attention)
+            {
+                retries += 1;
+                self.stats.retries = self.stats.retries.wrapping_add(1);
+                continue;
+            }
+            if busy_retries < self.busy_retries && rc == Err(Error::Busy) {
+                let ms = BUSY_RETRY_DELAY_MS << busy_retries.min(16);
+                if let Some(delay) = self.transport.delay_ms(ms) {
+                    delay.await;
                 }
-                for i in ERRORS1 {
-                    if r.sense_key == i.0 {
-                        return Error::Scsi(i.1);
-                    }
+                busy_retries += 1;
+                self.stats.retries = self.stats.retries.wrapping_add(1);
+                continue;
+            }
+            return rc;
+        }
+    }
+
+    /// Issue one command, upgrading any failure using REQUEST SENSE,
+    /// and report it to the tracer
+    async fn execute_once(
+        &mut self,
+        cmd: &[u8],
+        data: Payload<'_, '_>,
+    ) -> Result<usize, Error<T::Error>> {
+        let is_in = data.is_in();
+        let rc = self.transport_command(cmd, data).await;
+        self.last_status = self
+            .transport
+            .last_status()
+            .or_else(|| ScsiStatus::from_result(&rc));
+        let (rc, sense) = match rc {
+            Ok(n) => {
+                if is_in {
+                    self.stats.bytes_read =
+                        self.stats.bytes_read.wrapping_add(n as u64);
+                } else {
+                    self.stats.bytes_written =
+                        self.stats.bytes_written.wrapping_add(n as u64);
+                }
+                (Ok(n), None)
+            }
+            Err(Error::ConditionMet) => (Err(Error::ConditionMet), None),
+            Err(e) => {
+                self.stats.failures = self.stats.failures.wrapping_add(1);
+                // Find out whether *this* failure produced sense data,
+                // without losing any earlier sense if it didn't
+                let earlier = self.last_sense.take();
+                let e = self.try_upgrade_error(e).await;
+                let sense = self.last_sense;
+                if sense.is_none() {
+                    self.last_sense = earlier;
                 }
+                (Err(e), sense)
             }
+        };
+        if let Some(tracer) = self.tracer {
+            tracer(&TraceEvent {
+                cdb: cmd,
+                result: rc.as_ref().copied(),
+                sense: sense.as_ref(),
+            });
         }
-        e
+        rc
     }
 
     /// Send a generic SCSI command and await a reply
@@ -666,6 +2449,10 @@ impl<T: ScsiTransport> ScsiDevice<T> {
     /// Only one command is ever "in-flight" at once, so even though this
     /// function is asynchronous, nothing else happens until the reply is
     /// returned.
+    ///
+    /// The reply must fill the whole of `R`; a shorter one is a
+    /// `ProtocolError`. For replies which the device is allowed to
+    /// truncate, use [`ScsiDevice::command_response_partial()`].
     pub async fn command_response<
         C: bytemuck::Pod,
         R: bytemuck::NoUninit + bytemuck::AnyBitPattern + Default,
@@ -673,27 +2460,122 @@ impl<T: ScsiTransport> ScsiDevice<T> {
         &mut self,
         cmd: C,
     ) -> Result<R, Error<T::Error>> {
-        let mut r = R::default();
-        let rc = self
-            .transport
-            .command(
-                bytemuck::bytes_of(&cmd),
-                DataPhase::In(bytemuck::bytes_of_mut(&mut r)),
-            )
-            .await;
-        match rc {
-            Err(e) => Err(self.try_upgrade_error(e).await),
-            Ok(sz) => {
-                if sz < core::mem::size_of::<R>() {
-                    Err(Error::ProtocolError)
-                } else {
-                    Ok(r)
-                }
-            }
+        let (r, sz) = self.command_response_partial(cmd).await?;
+        if sz < core::mem::size_of::<R>() {
+            Err(Error::ProtocolError)
+        } else {
+            Ok(r)
         }
     }
 
+    /// Send a generic SCSI command and await a possibly-short reply
+    ///
+    /// Devices may legitimately return less data than was asked for
+    /// (for instance, INQUIRY data or vital product data pages shorter
+    /// than the structure used to receive them). Here, that isn't an
+    /// error: any part of `R` not covered by the reply is zero-filled,
+    /// and the number of bytes actually received is returned alongside
+    /// the reply.
+    pub async fn command_response_partial<
+        C: bytemuck::Pod,
+        R: bytemuck::NoUninit + bytemuck::AnyBitPattern + Default,
+    >(
+        &mut self,
+        cmd: C,
+    ) -> Result<(R, usize), Error<T::Error>> {
+        let mut r = R::default();
+        let bytes = bytemuck::bytes_of_mut(&mut r);
+        let sz = self.command_in(cmd, bytes).await?.min(bytes.len());
+        bytes[sz..].fill(0);
+        Ok((r, sz))
+    }
+
+    /// Send a generic SCSI command with a variable-length reply
+    ///
+    /// Unlike [`ScsiDevice::command_response()`], the reply is left
+    /// as raw bytes in `buf`, and the number of bytes actually
+    /// transferred is returned.
+    pub async fn command_in<C: bytemuck::Pod>(
+        &mut self,
+        cmd: C,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<T::Error>> {
+        self.execute(bytemuck::bytes_of(&cmd), DataPhase::In(buf).into())
+            .await
+    }
+
+    /// Send a generic SCSI command with a variable-length reply,
+    /// received into a buffer which needn't be initialised
+    ///
+    /// As [`ScsiDevice::command_in()`], but saves zeroing `buf`
+    /// beforehand, if the transport can receive straight into
+    /// uninitialised memory (see
+    /// [`ScsiTransport::command_in_uninit()`]). Returns the part of
+    /// `buf` which was transferred, as initialised bytes.
+    pub async fn command_in_uninit<'b, C: bytemuck::Pod>(
+        &mut self,
+        cmd: C,
+        buf: &'b mut [MaybeUninit<u8>],
+    ) -> Result<&'b mut [u8], Error<T::Error>> {
+        let mut buf = ReadBuffer::new(buf);
+        self.execute(bytemuck::bytes_of(&cmd), Payload::Uninit(&mut buf))
+            .await?;
+        Ok(buf.into_filled())
+    }
+
+    /// Send a generic SCSI command along with some parameter data
+    ///
+    /// For commands such as MODE SELECT or WRITE(10) that send data to
+    /// the device. Returns the number of bytes actually transferred.
+    pub async fn command_out<C: bytemuck::Pod>(
+        &mut self,
+        cmd: C,
+        data: &[u8],
+    ) -> Result<usize, Error<T::Error>> {
+        self.execute(bytemuck::bytes_of(&cmd), DataPhase::Out(data).into())
+            .await
+    }
+
+    /// Send an arbitrary command block, such as a vendor-specific one
+    ///
+    /// Unlike issuing it directly with [`ScsiDevice::transport_mut()`],
+    /// this treats the command like any other: a failure is followed by
+    /// REQUEST SENSE, and upgraded to an [`Error::Scsi`] if possible
+    /// (with the sense data available from
+    /// [`ScsiDevice::last_sense()`]); the command is retried according
+    /// to the Unit Attention and busy policies; and it's reported to the
+    /// tracer and counted in the statistics. Returns the number of bytes
+    /// transferred in the data phase.
+    ///
+    /// State cached by the `ScsiDevice`, such as
+    /// [`ScsiDevice::capabilities()`], isn't updated, whatever the
+    /// command does.
+    pub async fn raw_command(
+        &mut self,
+        cdb: &[u8],
+        data: DataPhase<'_>,
+    ) -> Result<usize, Error<T::Error>> {
+        self.execute(cdb, data.into()).await
+    }
+
+    /// Send a generic SCSI command which has no data phase
+    ///
+    /// For commands such as TEST UNIT READY, whose only result is
+    /// success or failure.
+    pub async fn command_no_data<C: bytemuck::Pod>(
+        &mut self,
+        cmd: C,
+    ) -> Result<(), Error<T::Error>> {
+        self.execute(bytemuck::bytes_of(&cmd), DataPhase::None.into())
+            .await
+            .map(|_| ())
+    }
+
     /// Read capacity (32-bit LBA version, supports <2TB only)
+    ///
+    /// For optical drives (`PeripheralType::Optical`), this reports
+    /// the size of the *recorded* area of the disc in 2048-byte
+    /// sectors; see [`crate::optical`].
     pub async fn read_capacity_10(
         &mut self,
     ) -> Result<(u32, u32), Error<T::Error>> {
@@ -701,67 +2583,591 @@ impl<T: ScsiTransport> ScsiDevice<T> {
             self.command_response(ReadCapacity10::new()).await?;
         let blocks = u32::from_be_bytes(reply.lba);
         let block_size = u32::from_be_bytes(reply.block_size);
+        self.note_block_size(block_size);
         Ok((blocks, block_size))
     }
 
     /// Read capacity (64-bit LBA version, supports >2TB)
     ///
-    /// Not universally supported.
-    pub async fn read_capacity_16(
+    /// Not universally supported.
+    pub async fn read_capacity_16(
+        &mut self,
+    ) -> Result<(u64, u32), Error<T::Error>> {
+        let data = self.read_capacity_16_full().await?;
+        Ok((data.last_lba, data.block_size))
+    }
+
+    /// Read capacity (64-bit LBA version), returning everything reported
+    ///
+    /// Including the physical block size and the alignment, which
+    /// [`ScsiDevice::read_capacity_16()`] omits.
+    pub async fn read_capacity_16_full(
+        &mut self,
+    ) -> Result<ReadCapacity16Data, Error<T::Error>> {
+        let reply: ReadCapacity16Reply =
+            match self.command_response(ReadCapacity16::new()).await {
+                Ok(r) => r,
+                Err(e) => {
+                    if e == Error::Scsi(ScsiError::InvalidCommandOperationCode)
+                    {
+                        self.supports_16 = Some(false);
+                    }
+                    return Err(e);
+                }
+            };
+        self.supports_16 = Some(true);
+        let data = ReadCapacity16Data::from_reply(&reply);
+        self.capacity_16 = Some(data);
+        self.note_block_size(data.block_size);
+        Ok(data)
+    }
+
+    /// Read capacity, using whichever command is appropriate
+    ///
+    /// Returns the number of blocks and the block size. Note that,
+    /// unlike [`ScsiDevice::read_capacity_10()`] and
+    /// [`ScsiDevice::read_capacity_16()`], which return the address of
+    /// the *last* block, this returns the total *number* of blocks.
+    ///
+    /// READ CAPACITY(10) is issued first, as all devices support it.
+    /// Only if the device reports that it's too large to describe in
+    /// 32 bits (i.e., larger than 2TB with 512-byte blocks) is READ
+    /// CAPACITY(16) issued; devices that large are required to support
+    /// the 16-byte command family. Smaller devices, many of which don't
+    /// support the 16-byte commands at all, are never sent one.
+    ///
+    /// Whether the 16-byte commands are supported, if this is found
+    /// out, is remembered and used by [`ScsiDevice::read_blocks()`] and
+    /// [`ScsiDevice::write_blocks()`].
+    pub async fn read_capacity(
+        &mut self,
+    ) -> Result<(u64, u32), Error<T::Error>> {
+        let (last_block, block_size) = self.read_capacity_10().await?;
+        if last_block != 0xFFFF_FFFF {
+            return Ok((last_block as u64 + 1, block_size));
+        }
+        let (last_block, block_size) = self.read_capacity_16().await?;
+        let blocks = last_block.checked_add(1).ok_or(Error::ProtocolError)?;
+        Ok((blocks, block_size))
+    }
+
+    /// Not much supports this one
+    pub async fn report_supported_operation_codes(
+        &mut self,
+        opcode: u8,
+        service_action: Option<u16>,
+    ) -> Result<bool, Error<T::Error>> {
+        if self.quirks.no_rsoc {
+            return Err(Error::Scsi(ScsiError::InvalidCommandOperationCode));
+        }
+        let reply: ReportSupportedOperationCodesReply = self
+            .command_response(ReportSupportedOperationCodes::new(
+                opcode,
+                service_action,
+            ))
+            .await?;
+        Ok((reply.support & 7) == 3)
+    }
+
+    /// Write any cached data out to the medium
+    ///
+    /// Issues SYNCHRONIZE CACHE(10) covering the whole medium, which
+    /// returns once all previously-written data is in non-volatile
+    /// storage. Devices without a write cache may not support this
+    /// command, and fail it with `InvalidCommandOperationCode`.
+    pub async fn synchronize_cache(&mut self) -> Result<(), Error<T::Error>> {
+        self.command_no_data(SynchronizeCache10::new()).await
+    }
+
+    /// Replace bad blocks with spare ones
+    ///
+    /// Issues REASSIGN BLOCKS, which tells the device to stop using
+    /// the blocks at the given addresses and use spares from its
+    /// reserved area instead -- as it would itself, with automatic
+    /// reallocation enabled, on a failed write. Whatever the blocks
+    /// contained is lost: they then read back as undefined data, so
+    /// should be rewritten. Supported by hard drives, but rarely by
+    /// flash drives.
+    ///
+    /// The blocks to reassign are usually found from the sense data of
+    /// a failed read; for instance, to recover from an unreadable
+    /// block:
+    ///
+    /// ```
+    /// # use cotton_scsi::testing::{self, FakeScsiTransport};
+    /// # use cotton_scsi::{Error, ScsiDevice, SenseData};
+    /// # use cotton_scsi::scsi_transport::ScsiError;
+    /// # use futures::FutureExt;
+    /// # let mut fake = FakeScsiTransport::new();
+    /// # fake.expect_check_condition(
+    /// #     &[0x28, 0, 0, 0, 0, 40, 0, 0, 8, 0],
+    /// #     SenseData { information: Some(43), ..testing::sense(3, 0x11, 0) },
+    /// # )
+    /// # .expect_out(&[7, 0, 0, 0, 0, 0], &[0, 0, 0, 4, 0, 0, 0, 43])
+    /// # .expect_out(&[0x2A, 0, 0, 0, 0, 43, 0, 0, 1, 0], &[0; 512]);
+    /// # let mut device = ScsiDevice::new(fake);
+    /// # async {
+    /// let mut buf = [0u8; 512 * 8];
+    /// let rc = device.read_10(40, 8, &mut buf).await;
+    /// if let Err(Error::Scsi(
+    ///     ScsiError::MediumError | ScsiError::UnrecoveredReadError,
+    /// )) = rc
+    /// {
+    ///     // The sense data identifies the block which failed
+    ///     if let Some(lba) = device.last_sense().and_then(|s| s.information)
+    ///     {
+    ///         device.reassign_blocks(&[lba as u32]).await?;
+    ///         // The contents are gone: restore them from a backup, if
+    ///         // there is one, or else at least make them readable
+    ///         device.write_10(lba as u32, 1, &[0u8; 512]).await?;
+    ///     }
+    /// }
+    /// # Ok::<(), Error<()>>(())
+    /// # }.now_or_never().unwrap().unwrap();
+    /// ```
+    ///
+    /// See also [`ScsiDevice::reassign_blocks_long()`], for LBAs which
+    /// don't fit in 32 bits.
+    pub async fn reassign_blocks(
+        &mut self,
+        lbas: &[u32],
+    ) -> Result<(), Error<T::Error>> {
+        for batch in lbas.chunks(REASSIGN_BATCH) {
+            let mut buf = [0u8; 4 + 4 * REASSIGN_BATCH];
+            let iter = batch.iter().map(|lba| *lba as u64);
+            let len = encode_reassign(&mut buf, iter, false);
+            self.command_out(ReassignBlocks::new(false), &buf[..len])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Replace bad blocks with spare ones (64-bit LBA version)
+    ///
+    /// As [`ScsiDevice::reassign_blocks()`], but using the LONGLBA and
+    /// LONGLIST forms of the parameter list, with 8-byte addresses.
+    pub async fn reassign_blocks_long(
+        &mut self,
+        lbas: &[u64],
+    ) -> Result<(), Error<T::Error>> {
+        for batch in lbas.chunks(REASSIGN_BATCH) {
+            let mut buf = [0u8; 4 + 8 * REASSIGN_BATCH];
+            let len = encode_reassign(&mut buf, batch.iter().copied(), true);
+            self.command_out(ReassignBlocks::new(true), &buf[..len])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Is the device "ready"?
+    ///
+    /// For instance, hard drives might take a while to spin up to operating
+    /// speed.
+    pub async fn test_unit_ready(&mut self) -> Result<(), Error<T::Error>> {
+        self.command_no_data(TestUnitReady::new()).await
+    }
+
+    /// Wait for the device to become ready, starting it if need be
+    ///
+    /// Polls TEST UNIT READY up to `max_polls` times, waiting
+    /// [`READY_POLL_INTERVAL_MS`] between attempts, for as long as the
+    /// device reports that it is not ready *yet*. If the device reports
+    /// that it needs a START STOP UNIT command before it can become
+    /// ready -- as external hard drives which have spun down often do
+    /// -- then one is sent.
+    ///
+    /// You need to supply an implementation of the "delay" function
+    /// which, given a parameter in milliseconds, returns a Future that
+    /// waits for that long before coming ready -- the same function
+    /// as used by `cotton_usb_host::usb_bus::UsbBus::device_events()`.
+    ///
+    /// If the device still isn't ready after `max_polls` attempts, the
+    /// error from the last attempt is returned. Errors that mean the
+    /// device is never going to become ready by itself (such as
+    /// `ScsiError::MediaNotPresent`) are returned straight away.
+    pub async fn wait_until_ready<
+        D: core::future::Future<Output = ()>,
+        F: FnMut(usize) -> D,
+    >(
+        &mut self,
+        mut delay_ms: F,
+        max_polls: u32,
+    ) -> Result<(), Error<T::Error>> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let e = match self.test_unit_ready().await {
+                Ok(()) => return Ok(()),
+                Err(Error::Scsi(ScsiError::StartUnitRequired)) => {
+                    match self.start_stop_unit(true, false).await {
+                        Ok(()) => Error::Scsi(ScsiError::StartUnitRequired),
+                        Err(e) => e,
+                    }
+                }
+                Err(
+                    e @ Error::Scsi(
+                        ScsiError::BecomingReady
+                        | ScsiError::NotReady
+                        | ScsiError::UnitAttention
+                        | ScsiError::ResetOccurred
+                        | ScsiError::MediaChanged,
+                    ),
+                ) => e,
+                Err(e) => return Err(e),
+            };
+            if attempt >= max_polls {
+                return Err(e);
+            }
+            delay_ms(READY_POLL_INTERVAL_MS).await;
+        }
+    }
+
+    /// Check whether the medium is present, and whether it's changed
+    ///
+    /// Issues TEST UNIT READY, and interprets the result. Card readers
+    /// and optical drives report the insertion of a new medium as a
+    /// Unit Attention condition, just once, on the first command
+    /// afterwards -- so if anything else is also issuing commands to
+    /// the device, this might not be the call that sees it.
+    ///
+    /// On [`MediaState::Changed`] or [`MediaState::NotPresent`] the
+    /// results of [`ScsiDevice::probe()`] are discarded, as they might
+    /// no longer apply.
+    pub async fn check_media(
+        &mut self,
+    ) -> Result<MediaState, Error<T::Error>> {
+        let state = match self.test_unit_ready().await {
+            Ok(()) => MediaState::Ready,
+            // Other Unit Attentions, such as "power on or reset", could
+            // also have been a change of medium
+            Err(Error::Scsi(
+                ScsiError::MediaChanged
+                | ScsiError::ResetOccurred
+                | ScsiError::UnitAttention,
+            )) => MediaState::Changed,
+            Err(Error::Scsi(ScsiError::MediaNotPresent)) => {
+                MediaState::NotPresent
+            }
+            Err(Error::Scsi(
+                ScsiError::BecomingReady
+                | ScsiError::StartUnitRequired
+                | ScsiError::NotReady,
+            )) => MediaState::NotReady,
+            Err(e) => return Err(e),
+        };
+        if matches!(state, MediaState::Changed | MediaState::NotPresent) {
+            self.capabilities = None;
+        }
+        Ok(state)
+    }
+
+    /// Wait until a medium is inserted, removed, or changed
+    ///
+    /// Polls [`ScsiDevice::check_media()`] every
+    /// [`MEDIA_POLL_INTERVAL_MS`], using the supplied `delay_ms`
+    /// function (as for [`ScsiDevice::wait_until_ready()`]), until the
+    /// state settles on something different from `last` -- the state
+    /// as it was last known. A newly-inserted medium is always reported
+    /// as [`MediaState::Changed`], and `NotReady` is never returned:
+    /// polling continues until the medium is ready, or gone again.
+    ///
+    /// Only devices with removable media are worth polling. If the
+    /// device (according to INQUIRY) doesn't have removable media,
+    /// `Ok(None)` is returned straight away.
+    pub async fn wait_for_media_change<
+        D: core::future::Future<Output = ()>,
+        F: FnMut(usize) -> D,
+    >(
+        &mut self,
+        last: MediaState,
+        mut delay_ms: F,
+    ) -> Result<Option<MediaState>, Error<T::Error>> {
+        let removable = match &self.capabilities {
+            Some(caps) => caps.is_removable(),
+            None => self.inquiry().await?.is_removable,
+        };
+        if !removable {
+            return Ok(None);
+        }
+        loop {
+            let state = self.check_media().await?;
+            let settled = match (last, state) {
+                (_, MediaState::NotReady) => None,
+                (_, MediaState::Changed) => Some(MediaState::Changed),
+                (MediaState::NotPresent, MediaState::Ready) => {
+                    Some(MediaState::Changed)
+                }
+                (MediaState::NotReady, MediaState::Ready) => {
+                    Some(MediaState::Ready)
+                }
+                (MediaState::NotPresent, MediaState::NotPresent) => None,
+                (_, MediaState::NotPresent) => Some(MediaState::NotPresent),
+                (_, MediaState::Ready) => None,
+            };
+            if settled.is_some() {
+                return Ok(settled);
+            }
+            delay_ms(MEDIA_POLL_INTERVAL_MS).await;
+        }
+    }
+
+    /// Low-level format the whole medium. DESTROYS ALL DATA.
+    ///
+    /// Issues FORMAT UNIT, with no defect lists (so the device's
+    /// existing defect list is kept), and with the IMMED bit set: the
+    /// command completes as soon as the format has started, rather than
+    /// when it finishes (which, on large media, can take minutes or
+    /// hours). In the meantime, most other commands fail with
+    /// [`ScsiError::FormatInProgress`]; use
+    /// [`ScsiDevice::format_progress()`] to find out when it's done.
+    pub async fn format_unit(&mut self) -> Result<(), Error<T::Error>> {
+        self.command_out(
+            FormatUnit::new(),
+            bytemuck::bytes_of(&ShortParameterListHeader::new()),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// How far a format started by [`ScsiDevice::format_unit()`] has got
+    ///
+    /// Returns the percentage complete, or `None` if no format is in
+    /// progress (for instance, because it's finished). Uses REQUEST
+    /// SENSE, which (unlike most commands) succeeds during a format,
+    /// reporting the progress in its sense-key-specific field.
+    pub async fn format_progress(
+        &mut self,
+    ) -> Result<Option<u8>, Error<T::Error>> {
+        let sense = self.unsolicited_sense().await?;
+        if upgrade_sense(&sense) != Some(ScsiError::FormatInProgress) {
+            return Ok(None);
+        }
+        let progress = sense.progress().unwrap_or(0) as u32;
+        Ok(Some((progress * 100 / 65536) as u8))
+    }
+
+    /// Low-level format the whole medium, and wait for it to finish.
+    /// DESTROYS ALL DATA.
+    ///
+    /// Issues FORMAT UNIT as [`ScsiDevice::format_unit()`] does, then
+    /// waits for it with [`ScsiDevice::wait_for_completion()`], polling
+    /// every [`PROGRESS_POLL_INTERVAL_MS`] for up to `timeout_ms`.
+    pub async fn format_unit_and_wait<
+        D: core::future::Future<Output = ()>,
+        F: FnMut(usize) -> D,
+    >(
+        &mut self,
+        delay_ms: F,
+        timeout_ms: usize,
+    ) -> Result<(), Error<T::Error>> {
+        self.format_unit().await?;
+        self.wait_for_completion(
+            delay_ms,
+            PROGRESS_POLL_INTERVAL_MS,
+            timeout_ms,
+        )
+        .await
+    }
+
+    /// Start one of the device's self-tests, in the background
+    ///
+    /// Issues SEND DIAGNOSTIC, which completes as soon as the test has
+    /// started. While it runs, the device carries on executing other
+    /// commands (though they might abort the test); use
+    /// [`ScsiDevice::operation_progress()`] to follow it. The outcome
+    /// isn't reported as sense data, but recorded in the device's
+    /// Self-Test Results log page.
+    pub async fn start_self_test(
+        &mut self,
+        test: SelfTest,
+    ) -> Result<(), Error<T::Error>> {
+        self.command_no_data(SendDiagnostic::new(test)).await
+    }
+
+    /// Run one of the device's self-tests, and wait for it to finish
+    ///
+    /// Starts the test with [`ScsiDevice::start_self_test()`], then
+    /// waits for it with [`ScsiDevice::wait_for_completion()`], polling
+    /// every [`PROGRESS_POLL_INTERVAL_MS`] for up to `timeout_ms`.
+    pub async fn self_test<
+        D: core::future::Future<Output = ()>,
+        F: FnMut(usize) -> D,
+    >(
+        &mut self,
+        test: SelfTest,
+        delay_ms: F,
+        timeout_ms: usize,
+    ) -> Result<(), Error<T::Error>> {
+        self.start_self_test(test).await?;
+        self.wait_for_completion(
+            delay_ms,
+            PROGRESS_POLL_INTERVAL_MS,
+            timeout_ms,
+        )
+        .await
+    }
+
+    /// How far a long-running operation has got
+    ///
+    /// Issues REQUEST SENSE, which during FORMAT UNIT, SANITIZE, or a
+    /// background self-test reports a progress indication (see
+    /// [`SenseData::progress()`]). Returns that progress, as a fraction
+    /// of 65536, or `None` if the device reports none -- usually because
+    /// no such operation is in progress, though a few devices don't
+    /// report progress at all.
+    pub async fn operation_progress(
+        &mut self,
+    ) -> Result<Option<u16>, Error<T::Error>> {
+        Ok(self.unsolicited_sense().await?.progress())
+    }
+
+    /// Wait for a long-running operation to finish
+    ///
+    /// Polls REQUEST SENSE every `poll_interval_ms`, using the supplied
+    /// `delay_ms` function (as for [`ScsiDevice::wait_until_ready()`]),
+    /// for as long as the device reports an operation (FORMAT UNIT,
+    /// SANITIZE, a self-test) in progress. Returns `Ok(())` once the
+    /// device reports nothing amiss, or the error it reports instead
+    /// (kept, too, as [`ScsiDevice::last_sense()`]); or
+    /// `Err(Error::Timeout)` if the operation is still going after
+    /// `timeout_ms`.
+    pub async fn wait_for_completion<
+        D: core::future::Future<Output = ()>,
+        F: FnMut(usize) -> D,
+    >(
+        &mut self,
+        mut delay_ms: F,
+        poll_interval_ms: usize,
+        timeout_ms: usize,
+    ) -> Result<(), Error<T::Error>> {
+        let mut waited = 0usize;
+        loop {
+            let sense = self.unsolicited_sense().await?;
+            if !is_in_progress(&sense) {
+                // "No sense", or "recovered error"
+                if sense.key <= 1 {
+                    return Ok(());
+                }
+                self.last_sense = Some(sense);
+                return Err(upgrade_sense(&sense)
+                    .map_or(Error::CommandFailed, Error::Scsi));
+            }
+            if waited >= timeout_ms {
+                return Err(Error::Timeout);
+            }
+            delay_ms(poll_interval_ms).await;
+            waited = waited.saturating_add(poll_interval_ms);
+        }
+    }
+
+    /// Start or stop the device's medium (e.g. spin a disk up or down)
+    ///
+    /// If `load_eject` is set, stopping the device also ejects
+    /// removable media, and starting it loads the media (e.g.,
+    /// closes a CD-ROM tray).
+    pub async fn start_stop_unit(
         &mut self,
-    ) -> Result<(u64, u32), Error<T::Error>> {
-        let reply: ReadCapacity16Reply =
-            self.command_response(ReadCapacity16::new()).await?;
-        let blocks = u64::from_be_bytes(reply.lba);
-        let block_size = u32::from_be_bytes(reply.block_size);
-        Ok((blocks, block_size))
+        start: bool,
+        load_eject: bool,
+    ) -> Result<(), Error<T::Error>> {
+        self.command_no_data(StartStopUnit::new(start, load_eject))
+            .await
     }
 
-    /// Not much supports this one
-    pub async fn report_supported_operation_codes(
+    /// Stop the user removing the medium (or allow them to again)
+    ///
+    /// For drives with a lockable tray or slot. `true` prevents the
+    /// medium being removed, `false` allows it. Many USB devices don't
+    /// support this command.
+    pub async fn prevent_medium_removal(
         &mut self,
-        opcode: u8,
-        service_action: Option<u16>,
-    ) -> Result<bool, Error<T::Error>> {
-        let reply: ReportSupportedOperationCodesReply = self
-            .command_response(ReportSupportedOperationCodes::new(
-                opcode,
-                service_action,
-            ))
-            .await?;
-        Ok((reply.support & 7) == 3)
+        prevent: bool,
+    ) -> Result<(), Error<T::Error>> {
+        self.command_no_data(PreventAllowMediumRemoval::new(prevent))
+            .await
     }
 
-    /// Is the device "ready"?
+    /// Get the device ready to be unplugged, or its medium removed
     ///
-    /// For instance, hard drives might take a while to spin up to operating
-    /// speed.
-    pub async fn test_unit_ready(&mut self) -> Result<(), Error<T::Error>> {
-        let cmd = TestUnitReady::new();
-        let rc = self
-            .transport
-            .command(bytemuck::bytes_of(&cmd), DataPhase::None)
-            .await;
-        match rc {
-            Err(e) => Err(self.try_upgrade_error(e).await),
-            Ok(_) => Ok(()),
-        }
+    /// Writes out any cached data with
+    /// [`ScsiDevice::synchronize_cache()`], allows medium removal with
+    /// [`ScsiDevice::prevent_medium_removal()`], then stops the device
+    /// and ejects the medium with [`ScsiDevice::start_stop_unit()`] --
+    /// the sequence which operating systems use for "safely remove".
+    /// Skipping it, and just unplugging, risks losing data which was
+    /// still in the device's cache.
+    ///
+    /// Steps which the device rejects as unsupported are skipped (see
+    /// the returned [`EjectSummary`]); any other failure stops the
+    /// sequence, and is returned, rather than ejecting a medium whose
+    /// data might not have been written.
+    ///
+    /// Afterwards, treat the device as gone: any further commands will
+    /// (and should) fail, until the medium is reinserted or the device
+    /// re-plugged. The results of [`ScsiDevice::probe()`] are
+    /// discarded.
+    pub async fn eject(&mut self) -> Result<EjectSummary, Error<T::Error>> {
+        let supported = |rc: Result<(), Error<T::Error>>| match rc {
+            Ok(()) => Ok(true),
+            Err(e) if is_unsupported(&e) => Ok(false),
+            Err(e) => Err(e),
+        };
+        let cache_flushed = supported(self.synchronize_cache().await)?;
+        let removal_allowed =
+            supported(self.prevent_medium_removal(false).await)?;
+        let stopped = supported(self.start_stop_unit(false, true).await)?;
+        self.capabilities = None;
+        Ok(EjectSummary {
+            cache_flushed,
+            removal_allowed,
+            stopped,
+        })
     }
 
-    async fn request_sense(
+    /// Issue REQUEST SENSE other than after a failed command
+    ///
+    /// The result isn't kept as [`ScsiDevice::last_sense()`], as no
+    /// command failed.
+    pub(crate) async fn unsolicited_sense(
         &mut self,
-    ) -> Result<RequestSenseReply, Error<T::Error>> {
+    ) -> Result<SenseData, Error<T::Error>> {
+        let earlier = self.last_sense;
+        let rc = self.request_sense().await;
+        self.last_sense = earlier;
+        rc
+    }
+
+    async fn request_sense(&mut self) -> Result<SenseData, Error<T::Error>> {
         // Can't use command_response, because we're used BY command_response
         let cmd = RequestSense::new();
-        let mut buf = [0u8; 18];
+        let mut buf = [0u8; SENSE_BUFFER_SIZE];
         let sz = self
-            .transport
-            .command(bytemuck::bytes_of(&cmd), DataPhase::In(&mut buf))
+            .transport_command(
+                bytemuck::bytes_of(&cmd),
+                DataPhase::In(&mut buf).into(),
+            )
             .await?;
-        let reply = bytemuck::try_from_bytes::<RequestSenseReply>(&buf[0..sz])
-            .map_err(|_| Error::ProtocolError)?;
-        debug::println!("{:?}", *reply);
-        Ok(*reply)
+        let sense = parse_sense(&buf[0..sz.min(buf.len())])
+            .ok_or(Error::ProtocolError)?;
+        debug::println!("{:?}", sense);
+        self.last_sense = Some(sense);
+        Ok(sense)
+    }
+
+    /// The sense data from the most recent failed command
+    ///
+    /// Whenever a command fails, REQUEST SENSE is issued to find out
+    /// why, and the result mapped onto [`ScsiError`] if possible; this
+    /// returns the underlying sense data in full, including the
+    /// additional sense code (ASC) and qualifier (ASCQ) -- which might
+    /// be vendor-specific and not mapped at all -- and, for some
+    /// errors, the "information" field (which for medium errors is the
+    /// address of the failing block).
+    ///
+    /// Returns `None` if no command has yet failed, or if the device
+    /// couldn't report sense data.
+    pub fn last_sense(&self) -> Option<SenseData> {
+        self.last_sense
     }
 
     /// Send a SCSI INQUIRY command and wait for a reply
@@ -770,15 +3176,31 @@ impl<T: ScsiTransport> ScsiDevice<T> {
     /// newly-detected device, as it determines whether the device is a disk,
     /// a CD-ROM drive, or something more exotic.
     pub async fn inquiry(&mut self) -> Result<InquiryData, Error<T::Error>> {
-        let reply: StandardInquiryData =
-            self.command_response(Inquiry::new(None, 36)).await?;
+        let (reply, sz): (StandardInquiryData, _) = self
+            .command_response_partial(Inquiry::new(None, 36))
+            .await?;
+        // Older devices might not supply the identification strings,
+        // but everything has the peripheral type and removable flag
+        if sz < 2 {
+            return Err(Error::ProtocolError);
+        }
         let data = InquiryData {
-            peripheral_type: unsafe {
-                core::mem::transmute::<u8, PeripheralType>(
-                    reply.peripheral_device_type & 0x1F,
-                )
+            peripheral_type: PeripheralType::from_bits(
+                reply.peripheral_device_type,
+            ),
+            peripheral_qualifier: match reply.peripheral_device_type >> 5 {
+                0 => PeripheralQualifier::Connected,
+                1 => PeripheralQualifier::NotConnected,
+                3 => PeripheralQualifier::NotCapable,
+                n => PeripheralQualifier::Other(n),
             },
             is_removable: (reply.removable & 0x80) != 0,
+            vendor_id: reply.vendor_id,
+            product_id: reply.product_id,
+            product_revision: reply.product_revision,
+            version: SpcVersion::from_byte(reply.version),
+            response_data_format: reply.data_format & 0x0F,
+            command_queueing: (reply.flags[2] & 0x02) != 0,
         };
         /*
         debug::println!("actual len {}", reply.additional_length + 4);
@@ -819,6 +3241,15 @@ impl<T: ScsiTransport> ScsiDevice<T> {
     }
     */
 
+    /// Fail, as the device would, if VPD pages are off-limits
+    fn check_vpd(&self) -> Result<(), Error<T::Error>> {
+        if self.quirks.no_vpd {
+            Err(Error::Scsi(ScsiError::InvalidFieldInCDB))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Return Vital Product Data, Block Limits Page
     ///
     /// Which is meant to contain important information like maximum write
@@ -826,94 +3257,808 @@ impl<T: ScsiTransport> ScsiDevice<T> {
     pub async fn block_limits_page(
         &mut self,
     ) -> Result<BlockLimitsPage, Error<T::Error>> {
+        self.check_vpd()?;
         let cmd = Inquiry::new(Some(0xB0), 64);
         assert!(core::mem::size_of::<BlockLimitsPage>() == 64);
-        let page = self.command_response(cmd).await?;
+        let (mut page, sz): (BlockLimitsPage, _) =
+            self.command_response_partial(cmd).await?;
+        if sz < 4
+            || page.page_code != BlockLimitsPage::PAGE_CODE
+            || page.page_length() < BlockLimitsPage::MIN_PAGE_LENGTH
+        {
+            return Err(Error::ProtocolError);
+        }
+        // Early versions of the page are only 16 bytes long; anything
+        // beyond the stated length is ignored, as if it were zero,
+        // meaning "not reported"
+        let len = 4 + page.page_length() as usize;
+        if let Some(tail) = bytemuck::bytes_of_mut(&mut page).get_mut(len..) {
+            tail.fill(0);
+        }
+        if let Some(max) = page.maximum_transfer_length_blocks() {
+            self.max_transfer_blocks = Some(max);
+        }
+        self.max_compare_and_write =
+            Some(page.maximum_compare_and_write_length().unwrap_or(0));
+        Ok(page)
+    }
+
+    /// Return Vital Product Data, Block Device Characteristics page
+    ///
+    /// Which tells, among other things, whether the device is a
+    /// spinning disk or solid-state, and so whether it's worth
+    /// spinning it down when idle, or issuing UNMAP for unused blocks.
+    /// Devices which don't support the page at all typically fail with
+    /// `InvalidFieldInCDB`.
+    pub async fn block_device_characteristics_page(
+        &mut self,
+    ) -> Result<BlockDeviceCharacteristics, Error<T::Error>> {
+        self.check_vpd()?;
+        let cmd =
+            Inquiry::new(Some(BlockDeviceCharacteristics::PAGE_CODE), 64);
+        assert!(core::mem::size_of::<BlockDeviceCharacteristics>() == 64);
+        let (mut page, sz): (BlockDeviceCharacteristics, _) =
+            self.command_response_partial(cmd).await?;
+        if sz < 4
+            || page.page_code != BlockDeviceCharacteristics::PAGE_CODE
+            || page.page_length() < BlockDeviceCharacteristics::MIN_PAGE_LENGTH
+        {
+            return Err(Error::ProtocolError);
+        }
+        let len = 4 + page.page_length() as usize;
+        if let Some(tail) = bytemuck::bytes_of_mut(&mut page).get_mut(len..) {
+            tail.fill(0);
+        }
         Ok(page)
     }
 
+    /// Ask the device whether its medium is write-protected
+    ///
+    /// Uses the header of the MODE SENSE(6) reply.
+    async fn write_protected_6(&mut self) -> Result<bool, Error<T::Error>> {
+        let header: ModeParameterHeader6 = self
+            .command_response(ModeSense6::new(
+                0x3F, // all pages
+                core::mem::size_of::<ModeParameterHeader6>() as u8,
+            ))
+            .await?;
+        Ok((header.device_specific_parameter & 0x80) != 0)
+    }
+
+    /// Ask the device whether its medium is write-protected
+    ///
+    /// Uses the header of the MODE SENSE(10) reply.
+    async fn write_protected_10(&mut self) -> Result<bool, Error<T::Error>> {
+        let header: ModeParameterHeader10 = self
+            .command_response(ModeSense10::new(
+                0x3F, // all pages
+                core::mem::size_of::<ModeParameterHeader10>() as u16,
+            ))
+            .await?;
+        Ok((header.device_specific_parameter & 0x80) != 0)
+    }
+
+    /// Is the medium write-protected?
+    ///
+    /// SD cards with the "lock" switch set, and some secure USB drives,
+    /// reject all writes with `ScsiError::WriteProtected`; this finds that
+    /// out in advance, from the WP bit in the MODE SENSE header. MODE
+    /// SENSE(6) is tried first, then MODE SENSE(10). If the device
+    /// supports neither, `Ok(None)` is returned: it's unknown whether
+    /// writes will succeed.
+    pub async fn is_write_protected(
+        &mut self,
+    ) -> Result<Option<bool>, Error<T::Error>> {
+        match self.write_protected_6().await {
+            Ok(wp) => return Ok(Some(wp)),
+            Err(e) if !is_unsupported(&e) => return Err(e),
+            Err(_) => {}
+        }
+        match self.write_protected_10().await {
+            Ok(wp) => Ok(Some(wp)),
+            Err(e) if !is_unsupported(&e) => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Read one mode page, using MODE SENSE(10)
+    ///
+    /// Returns the page itself, from its page-code byte onwards (that
+    /// is, skipping the header and any block descriptors), within
+    /// `buf`. Fails with `ProtocolError` if the reply is too short to
+    /// contain the page header.
+    pub(crate) async fn mode_page<'b>(
+        &mut self,
+        page_code: u8,
+        control: PageControl,
+        buf: &'b mut [u8; MODE_PAGE_BUFFER_SIZE],
+    ) -> Result<&'b [u8], Error<T::Error>> {
+        const HEADER_SIZE: usize =
+            core::mem::size_of::<ModeParameterHeader10>();
+        let sz = self
+            .command_in(
+                ModeSense10::new(
+                    ((control as u8) << 6) | page_code,
+                    MODE_PAGE_BUFFER_SIZE as u16,
+                ),
+                buf,
+            )
+            .await?
+            .min(MODE_PAGE_BUFFER_SIZE);
+        if sz < HEADER_SIZE {
+            return Err(Error::ProtocolError);
+        }
+        let descriptors = u16::from_be_bytes([buf[6], buf[7]]) as usize;
+        match buf[..sz].get(HEADER_SIZE + descriptors..) {
+            Some(page) if page.len() >= 2 => Ok(page),
+            _ => Err(Error::ProtocolError),
+        }
+    }
+
+    /// Write one mode page, using MODE SELECT(10)
+    ///
+    /// `page` starts at its page-code byte, whose PS bit must be clear.
+    /// If `save` is true, the device saves the page, so that it
+    /// survives a power cycle.
+    pub(crate) async fn set_mode_page(
+        &mut self,
+        page: &[u8],
+        save: bool,
+    ) -> Result<(), Error<T::Error>> {
+        const HEADER_SIZE: usize =
+            core::mem::size_of::<ModeParameterHeader10>();
+        let len = HEADER_SIZE + page.len();
+        let mut data = [0u8; MODE_PAGE_BUFFER_SIZE];
+        // The header is all zero: "mode data length" is reserved in
+        // MODE SELECT, and there are no block descriptors
+        data.get_mut(HEADER_SIZE..len)
+            .ok_or(Error::ProtocolError)?
+            .copy_from_slice(page);
+        self.command_out(ModeSelect10::new(save, len as u16), &data[..len])
+            .await?;
+        Ok(())
+    }
+
+    /// Find out the device's type, size, and which commands it supports
+    ///
+    /// This performs the standard discovery sequence -- INQUIRY, TEST
+    /// UNIT READY (repeated while the device reports that it's becoming
+    /// ready), READ CAPACITY, and then optionally REPORT SUPPORTED
+    /// OPERATION CODES (or, failing that, a trial READ CAPACITY(16)),
+    /// INQUIRY for the Block Limits Page, and MODE SENSE. Failure of
+    /// any of the optional steps is not a failure of the probe, it just
+    /// means that less is known about the device; see the table
+    /// in the [`ScsiDevice`] documentation for how much variation
+    /// there is between devices. After [`ScsiDevice::preload()`], the
+    /// RSOC and Block Limits steps are skipped.
+    ///
+    /// The results are also cached, see [`ScsiDevice::capabilities()`],
+    /// and are used by later operations such as
+    /// [`ScsiDevice::read_blocks()`].
+    ///
+    /// The readiness check here doesn't wait between attempts, so
+    /// devices which take a long time to become ready -- spun-down
+    /// hard drives, for instance -- need a call to
+    /// [`ScsiDevice::wait_until_ready()`] first.
+    pub async fn probe(
+        &mut self,
+    ) -> Result<DeviceCapabilities, Error<T::Error>> {
+        let inquiry = self.inquiry().await?;
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.test_unit_ready().await {
+                Ok(()) => break,
+                Err(Error::Scsi(
+                    ScsiError::BecomingReady
+                    | ScsiError::UnitAttention
+                    | ScsiError::ResetOccurred
+                    | ScsiError::MediaChanged,
+                )) if attempts < PROBE_READY_ATTEMPTS => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.capacity_16 = None;
+        let (blocks, block_size) = self.read_capacity().await?;
+
+        if self.supports_16.is_none() {
+            match self.report_supported_operation_codes(0x88, None).await {
+                Ok(b) => self.supports_16 = Some(b),
+                Err(_) => {
+                    if self.read_capacity_16().await.is_err() {
+                        self.supports_16 = Some(false);
+                    }
+                }
+            }
+        }
+
+        // The physical block size is only reported by READ CAPACITY(16),
+        // which has often already been issued by now
+        if self.supports_16 == Some(true) && self.capacity_16.is_none() {
+            let _ = self.read_capacity_16_full().await;
+        }
+        let physical_block_size = self
+            .capacity_16
+            .filter(|c| c.block_size == block_size)
+            .map_or(block_size, |c| c.physical_block_size);
+
+        // Not much supports the Block Limits Page, but if it does, it
+        // sets self.max_transfer_blocks
+        if !self.quirks.no_vpd && !self.preloaded {
+            let _ = self.block_limits_page().await;
+        }
+
+        let write_protected = self.is_write_protected().await.ok().flatten();
+
+        let caps = DeviceCapabilities {
+            inquiry,
+            blocks,
+            block_size,
+            physical_block_size,
+            supports_16: self.supports_16 == Some(true),
+            max_transfer_blocks: self.max_transfer_blocks(),
+            write_protected,
+        };
+        self.capabilities = Some(caps);
+        Ok(caps)
+    }
+
+    /// The results of the most recent successful [`ScsiDevice::probe()`]
+    pub fn capabilities(&self) -> Option<&DeviceCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// The device's own transfer limit, from the Block Limits Page or
+    /// from its quirks
+    fn max_transfer_blocks(&self) -> Option<u32> {
+        [self.max_transfer_blocks, self.quirks.max_transfer_blocks]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// The largest number of blocks to transfer in one command
+    fn transfer_limit(&self, block_size: usize) -> u32 {
+        let transport = self
+            .transport
+            .max_transfer_bytes()
+            .map(|n| (n / block_size).min(u32::MAX as usize) as u32);
+        [self.max_transfer_blocks(), transport]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or((DEFAULT_MAX_TRANSFER_BYTES / block_size) as u32)
+            .clamp(1, u16::MAX as u32)
+    }
+
+    /// Check the arguments to read_blocks/write_blocks, returning the
+    /// block size
+    ///
+    /// If the block size isn't known (see
+    /// [`ScsiDevice::set_block_size()`]), it's taken to be whatever
+    /// evenly divides `len` into `count` blocks.
+    fn check_transfer(
+        &self,
+        lba: u64,
+        count: u32,
+        len: usize,
+    ) -> Result<usize, TransferError<T::Error>> {
+        let fail = |error| TransferError {
+            blocks_done: 0,
+            error,
+        };
+        lba.checked_add(count as u64).ok_or(fail(Error::Scsi(
+            ScsiError::LogicalBlockAddressOutOfRange,
+        )))?;
+        self.check_buffer(count, len, true).map_err(fail)?;
+        match self.block_size {
+            Some(block_size) => Ok(block_size as usize),
+            None if len != 0 && len % (count as usize) == 0 => {
+                Ok(len / (count as usize))
+            }
+            None => Err(fail(Error::BadBufferSize)),
+        }
+    }
+
+    /// Read sector(s), issuing as many commands as necessary
+    ///
+    /// Large reads are split up into chunks no bigger than the
+    /// smallest of: the maximum transfer length given in the Block
+    /// Limits Page (if [`ScsiDevice::block_limits_page()`] has been
+    /// called and succeeded), [`ScsiQuirks::max_transfer_blocks`], the
+    /// limit reported by [`ScsiTransport::max_transfer_bytes()`], or,
+    /// if none of those is known, a conservative default which all
+    /// known devices accept.
+    /// READ(10) is used wherever possible, and READ(16) only for the
+    /// parts of the transfer beyond the 32-bit LBA range (and not at
+    /// all if the device is known not to support it, see
+    /// [`ScsiDevice::read_capacity()`]).
+    ///
+    /// The buffer length must be exactly `count` times the device's
+    /// block size, or the call fails with [`Error::BadBufferSize`]
+    /// without issuing a command.
+    ///
+    /// If a command fails part-way, the returned error includes the
+    /// number of blocks successfully read before the failure.
+    pub async fn read_blocks(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf: &mut [u8],
+    ) -> Result<(), TransferError<T::Error>> {
+        self.read_blocks_into(lba, count, &mut ReadBuffer::from(buf))
+            .await
+    }
+
+    /// Read sector(s) into a buffer which needn't be initialised
+    ///
+    /// As [`ScsiDevice::read_blocks()`], but saves zeroing `buf`
+    /// beforehand, if the transport can receive straight into
+    /// uninitialised memory (see
+    /// [`ScsiTransport::command_in_uninit()`]). On success, all of
+    /// `buf` has been filled, and is returned as initialised bytes.
+    pub async fn read_blocks_uninit<'b>(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf: &'b mut [MaybeUninit<u8>],
+    ) -> Result<&'b mut [u8], TransferError<T::Error>> {
+        let mut buf = ReadBuffer::new(buf);
+        self.read_blocks_into(lba, count, &mut buf).await?;
+        Ok(buf.into_filled())
+    }
+
+    async fn read_blocks_into(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf: &mut ReadBuffer<'_>,
+    ) -> Result<(), TransferError<T::Error>> {
+        if count == 0 {
+            return Ok(());
+        }
+        let block_size = self.check_transfer(lba, count, buf.capacity())?;
+        let chunk = self.transfer_limit(block_size);
+        let mut done = 0;
+        while done < count {
+            let n = chunk.min(count - done);
+            let start = lba + done as u64;
+            let bytes = n as usize * block_size;
+            let mut data = buf.unfilled(bytes);
+            let rc = if fits_10(start, n) {
+                self.read_into(
+                    Read10::new(
+                        start as u32,
+                        n as u16,
+                        AccessFlags::default(),
+                    ),
+                    n,
+                    &mut data,
+                )
+                .await
+            } else if self.supports_16 == Some(false) {
+                Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+            } else {
+                self.read_into(
+                    Read16::new(start, n, AccessFlags::default()),
+                    n,
+                    &mut data,
+                )
+                .await
+            };
+            let filled = data.len();
+            // SAFETY: `data` covers the start of buf's unfilled part,
+            // and its filled part is initialised (ReadBuffer's
+            // invariant)
+            unsafe { buf.assume_filled(filled) };
+            match rc {
+                Ok(sz) if sz >= bytes => done += n,
+                Ok(sz) => {
+                    return Err(TransferError {
+                        blocks_done: done + (sz / block_size) as u32,
+                        error: Error::ProtocolError,
+                    })
+                }
+                Err(error) => {
+                    return Err(TransferError {
+                        blocks_done: done,
+                        error,
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write sector(s), issuing as many commands as necessary
+    ///
+    /// Large writes are split up in the same way as for
+    /// [`ScsiDevice::read_blocks()`].
+    ///
+    /// The buffer length must be exactly `count` times the device's
+    /// block size, or the call fails with [`Error::BadBufferSize`]
+    /// without issuing a command.
+    ///
+    /// If a command fails part-way, the returned error includes the
+    /// number of blocks successfully written before the failure.
+    pub async fn write_blocks(
+        &mut self,
+        lba: u64,
+        count: u32,
+        buf: &[u8],
+    ) -> Result<(), TransferError<T::Error>> {
+        if count == 0 {
+            return Ok(());
+        }
+        let block_size = self.check_transfer(lba, count, buf.len())?;
+        let chunk = self.transfer_limit(block_size);
+        let mut done = 0;
+        while done < count {
+            let n = chunk.min(count - done);
+            let start = lba + done as u64;
+            let bytes = n as usize * block_size;
+            let offset = done as usize * block_size;
+            let data = &buf[offset..(offset + bytes)];
+            let rc = if fits_10(start, n) {
+                self.write_10(start as u32, n as u16, data).await
+            } else if self.supports_16 == Some(false) {
+                Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+            } else {
+                self.write_16(start, n, data).await
+            };
+            if let Err(error) = rc {
+                return Err(TransferError {
+                    blocks_done: done,
+                    error,
+                });
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// The block size used to check the buffers passed to
+    /// [`ScsiDevice::read_10()`] and friends, if known
+    pub fn block_size(&self) -> Option<u32> {
+        self.block_size
+    }
+
+    /// Set the block size used to check the buffers passed to
+    /// [`ScsiDevice::read_10()`] and friends
+    ///
+    /// This is set automatically by [`ScsiDevice::read_capacity()`] (and
+    /// so by [`ScsiDevice::probe()`]), so only needs calling if those
+    /// aren't used. Until the block size is known, buffers can't be
+    /// checked; a wrongly-sized buffer then typically shows up as a
+    /// short transfer, or as a transport error. A block size of zero
+    /// is ignored.
+    pub fn set_block_size(&mut self, block_size: u32) {
+        self.note_block_size(block_size);
+    }
+
+    fn note_block_size(&mut self, block_size: u32) {
+        if block_size != 0 {
+            self.block_size = Some(block_size);
+        }
+    }
+
+    /// Check that a buffer of `len` bytes suits a transfer of `count`
+    /// blocks: at least that long for reads, exactly that long for
+    /// writes
+    fn check_buffer(
+        &self,
+        count: u32,
+        len: usize,
+        exact: bool,
+    ) -> Result<(), Error<T::Error>> {
+        if count == 0 {
+            return Err(Error::BadBufferSize);
+        }
+        if let Some(block_size) = self.block_size {
+            let needed = (count as usize)
+                .checked_mul(block_size as usize)
+                .ok_or(Error::BadBufferSize)?;
+            if len < needed || (exact && len != needed) {
+                return Err(Error::BadBufferSize);
+            }
+        }
+        Ok(())
+    }
+
     /// Read sector(s), 32-bit LBA version
     ///
     /// All disk devices are required to support this, but on large
     /// devices it is only capable of reading within the first 2TB.
+    ///
+    /// Fails with [`Error::BadBufferSize`], without issuing a command,
+    /// if `count` is zero, or if the block size is known (see
+    /// [`ScsiDevice::set_block_size()`]) and `buf` is too small.
     pub async fn read_10(
         &mut self,
         start_block: u32,
         count: u16,
         buf: &mut [u8],
     ) -> Result<usize, Error<T::Error>> {
-        let cmd = Read10::new(start_block, count);
-        let rc = self
-            .transport
-            .command(bytemuck::bytes_of(&cmd), DataPhase::In(buf))
-            .await;
-        if let Err(e) = rc {
-            return Err(self.try_upgrade_error(e).await);
-        }
-        rc
+        self.read_10_with_flags(
+            start_block,
+            count,
+            AccessFlags::default(),
+            buf,
+        )
+        .await
+    }
+
+    /// Read sector(s), 32-bit LBA version, with cache hints
+    ///
+    /// As [`ScsiDevice::read_10()`], but with the FUA and DPO bits set
+    /// according to `flags`.
+    pub async fn read_10_with_flags(
+        &mut self,
+        start_block: u32,
+        count: u16,
+        flags: AccessFlags,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<T::Error>> {
+        self.check_buffer(count as u32, buf.len(), false)?;
+        self.command_in(Read10::new(start_block, count, flags), buf)
+            .await
     }
 
     /// Read sector(s), 64-bit LBA version
     ///
     /// Not universally supported (but should be supported on all devices
-    /// where it's needed, i.e. devices >2TB).
+    /// where it's needed, i.e. devices >2TB). Checks `buf` in the same
+    /// way as [`ScsiDevice::read_10()`].
     pub async fn read_16(
         &mut self,
         start_block: u64,
         count: u32,
         buf: &mut [u8],
     ) -> Result<usize, Error<T::Error>> {
-        let cmd = Read16::new(start_block, count);
-        let rc = self
-            .transport
-            .command(bytemuck::bytes_of(&cmd), DataPhase::In(buf))
-            .await;
-        if let Err(e) = rc {
-            return Err(self.try_upgrade_error(e).await);
-        }
-        rc
+        self.read_16_with_flags(
+            start_block,
+            count,
+            AccessFlags::default(),
+            buf,
+        )
+        .await
+    }
+
+    /// Read sector(s), 64-bit LBA version, with cache hints
+    ///
+    /// As [`ScsiDevice::read_16()`], but with the FUA and DPO bits set
+    /// according to `flags`.
+    pub async fn read_16_with_flags(
+        &mut self,
+        start_block: u64,
+        count: u32,
+        flags: AccessFlags,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<T::Error>> {
+        self.check_buffer(count, buf.len(), false)?;
+        self.command_in(Read16::new(start_block, count, flags), buf)
+            .await
+    }
+
+    /// Issue a READ(10) or READ(16) for `count` blocks, receiving
+    /// into a buffer which needn't be initialised
+    async fn read_into<C: bytemuck::Pod>(
+        &mut self,
+        cmd: C,
+        count: u32,
+        buf: &mut ReadBuffer<'_>,
+    ) -> Result<usize, Error<T::Error>> {
+        self.check_buffer(count, buf.remaining(), false)?;
+        self.execute(bytemuck::bytes_of(&cmd), Payload::Uninit(buf))
+            .await
     }
 
     /// Write sector(s), 32-bit LBA version
     ///
     /// All disk devices are required to support this, but on large
     /// devices it is only capable of writing within the first 2TB.
+    ///
+    /// Fails with [`Error::BadBufferSize`], without issuing a command,
+    /// if `count` is zero, or if the block size is known (see
+    /// [`ScsiDevice::set_block_size()`]) and `buf` isn't exactly `count`
+    /// blocks long.
     pub async fn write_10(
         &mut self,
         start_block: u32,
         count: u16,
         buf: &[u8],
     ) -> Result<usize, Error<T::Error>> {
-        let cmd = Write10::new(start_block, count);
-        let rc = self
-            .transport
-            .command(bytemuck::bytes_of(&cmd), DataPhase::Out(buf))
-            .await;
-        if let Err(e) = rc {
-            return Err(self.try_upgrade_error(e).await);
+        self.write_10_with_flags(
+            start_block,
+            count,
+            AccessFlags::default(),
+            buf,
+        )
+        .await
+    }
+
+    /// Write sector(s), 32-bit LBA version, with cache hints
+    ///
+    /// As [`ScsiDevice::write_10()`], but with the FUA and DPO bits set
+    /// according to `flags`: with [`AccessFlags::FUA`], the command
+    /// doesn't complete until the data is on the medium, even if the
+    /// device's write cache is enabled.
+    pub async fn write_10_with_flags(
+        &mut self,
+        start_block: u32,
+        count: u16,
+        flags: AccessFlags,
+        buf: &[u8],
+    ) -> Result<usize, Error<T::Error>> {
+        self.check_buffer(count as u32, buf.len(), true)?;
+        self.command_out(Write10::new(start_block, count, flags), buf)
+            .await
+    }
+
+    /// Ask the device to read sector(s) into its cache, 32-bit LBA version
+    ///
+    /// This is only a hint, to speed up a later read of the same
+    /// sectors. With `immediate`, the device replies as soon as it's
+    /// checked the command, rather than once the sectors are in cache.
+    ///
+    /// Returns `true` if the device replied with CONDITION MET status,
+    /// meaning that all the sectors will fit in its cache, or `false`
+    /// if it replied with GOOD status, meaning that they won't. Not
+    /// all transports can tell the difference (USB mass-storage
+    /// can't), in which case the answer is always `false`.
+    pub async fn pre_fetch_10(
+        &mut self,
+        lba: u32,
+        count: u16,
+        immediate: bool,
+    ) -> Result<bool, Error<T::Error>> {
+        Self::condition_met(
+            self.command_no_data(PreFetch10::new(lba, count, immediate))
+                .await,
+        )
+    }
+
+    /// Ask the device to read sector(s) into its cache, 64-bit LBA version
+    ///
+    /// See [`ScsiDevice::pre_fetch_10()`].
+    pub async fn pre_fetch_16(
+        &mut self,
+        lba: u64,
+        count: u32,
+        immediate: bool,
+    ) -> Result<bool, Error<T::Error>> {
+        Self::condition_met(
+            self.command_no_data(PreFetch16::new(lba, count, immediate))
+                .await,
+        )
+    }
+
+    /// Ask the device to read sector(s) into its cache, returning as
+    /// soon as the command has been accepted
+    ///
+    /// Issues PRE-FETCH (10) or PRE-FETCH (16) as required, in the
+    /// same way as [`ScsiDevice::read_blocks()`]; a sequential reader
+    /// can use this to hint the next extent while still processing the
+    /// current one. For the meaning of the result, see
+    /// [`ScsiDevice::pre_fetch_10()`].
+    pub async fn pre_fetch(
+        &mut self,
+        lba: u64,
+        count: u32,
+    ) -> Result<bool, Error<T::Error>> {
+        if lba.checked_add(count as u64).is_none() {
+            Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+        } else if fits_10(lba, count) {
+            self.pre_fetch_10(lba as u32, count as u16, true).await
+        } else if self.supports_16 == Some(false) {
+            Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+        } else {
+            self.pre_fetch_16(lba, count, true).await
+        }
+    }
+
+    fn condition_met(
+        rc: Result<(), Error<T::Error>>,
+    ) -> Result<bool, Error<T::Error>> {
+        match rc {
+            Ok(_) => Ok(false),
+            Err(Error::ConditionMet) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Atomically compare sector(s) with `verify`, and if they match,
+    /// replace them with `write`
+    ///
+    /// This is a "test-and-set" at the level of blocks: handy for
+    /// locking, when several hosts share the same storage. If the
+    /// blocks' contents don't match `verify`, nothing is written, and
+    /// the command fails with [`Error::Miscompare`], giving the offset
+    /// in `verify` of the first difference (if the device reported it).
+    ///
+    /// Both `verify` and `write` must cover all `count` blocks. The
+    /// command sends them to the device together, as a single buffer;
+    /// to avoid allocating one, the caller supplies `scratch`, which
+    /// must be at least twice the length of `verify`. Fails with
+    /// `ProtocolError`, without issuing any command, if the buffers
+    /// are the wrong size -- or if [`ScsiDevice::block_limits_page()`]
+    /// has been read, and has said that COMPARE AND WRITE isn't
+    /// supported, or is limited to fewer blocks than `count`. Many
+    /// devices don't support it in any case, and fail with
+    /// `InvalidCommandOperationCode`.
+    pub async fn compare_and_write(
+        &mut self,
+        lba: u64,
+        count: u8,
+        verify: &[u8],
+        write: &[u8],
+        scratch: &mut [u8],
+    ) -> Result<(), Error<T::Error>> {
+        let len = verify.len();
+        if count == 0
+            || len == 0
+            || len % count as usize != 0
+            || write.len() != len
+            || scratch.len() < 2 * len
+            || self.max_compare_and_write.is_some_and(|max| count > max)
+        {
+            return Err(Error::ProtocolError);
+        }
+        scratch[..len].copy_from_slice(verify);
+        scratch[len..2 * len].copy_from_slice(write);
+        match self
+            .command_out(CompareAndWrite::new(lba, count), &scratch[..2 * len])
+            .await
+        {
+            Ok(_) => Ok(()),
+            // The information field is the offset of the first mismatch
+            Err(Error::Scsi(
+                ScsiError::Miscompare | ScsiError::MiscompareDuringVerify,
+            )) => Err(Error::Miscompare(
+                self.last_sense
+                    .and_then(|s| s.information)
+                    .map(|offset| offset as u32),
+            )),
+            Err(e) => Err(e),
         }
-        rc
     }
 
     /// Write sector(s), 64-bit LBA version
     ///
     /// Not universally supported (but should be supported on all devices
-    /// where it's needed, i.e. devices >2TB).
+    /// where it's needed, i.e. devices >2TB). Checks `buf` in the same
+    /// way as [`ScsiDevice::write_10()`].
     pub async fn write_16(
         &mut self,
         start_block: u64,
         count: u32,
         buf: &[u8],
     ) -> Result<usize, Error<T::Error>> {
-        let cmd = Write16::new(start_block, count);
-        let rc = self
-            .transport
-            .command(bytemuck::bytes_of(&cmd), DataPhase::Out(buf))
-            .await;
-        if let Err(e) = rc {
-            return Err(self.try_upgrade_error(e).await);
-        }
-        rc
+        self.write_16_with_flags(
+            start_block,
+            count,
+            AccessFlags::default(),
+            buf,
+        )
+        .await
+    }
+
+    /// Write sector(s), 64-bit LBA version, with cache hints
+    ///
+    /// As [`ScsiDevice::write_16()`], but with the FUA and DPO bits set
+    /// according to `flags`, as for [`ScsiDevice::write_10_with_flags()`].
+    pub async fn write_16_with_flags(
+        &mut self,
+        start_block: u64,
+        count: u32,
+        flags: AccessFlags,
+        buf: &[u8],
+    ) -> Result<usize, Error<T::Error>> {
+        self.check_buffer(count, buf.len(), true)?;
+        self.command_out(Write16::new(start_block, count, flags), buf)
+            .await
     }
 }
 
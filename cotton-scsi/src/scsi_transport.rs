@@ -1,4 +1,5 @@
 use core::future::Future;
+use core::mem::MaybeUninit;
 
 /// The data phase of a SCSI transaction: in, out, or none
 ///
@@ -17,6 +18,239 @@ pub enum DataPhase<'a> {
     None,
 }
 
+impl DataPhase<'_> {
+    /// The same data phase, borrowed again, so that a command can be
+    /// reissued
+    pub(crate) fn reborrow(&mut self) -> DataPhase<'_> {
+        match self {
+            DataPhase::In(buf) => DataPhase::In(buf),
+            DataPhase::Out(buf) => DataPhase::Out(buf),
+            DataPhase::None => DataPhase::None,
+        }
+    }
+}
+
+/// A buffer for received data, which needn't be initialised beforehand
+///
+/// Zeroing a large buffer before every read, only for the transport
+/// to overwrite it straight away, wastes time on a small
+/// microcontroller. A `ReadBuffer` wraps a buffer of
+/// [`MaybeUninit<u8>`], and keeps track of how much of it has been
+/// filled with received data, and how much of it is known to be
+/// initialised (like the standard library's `BorrowedBuf`). Its
+/// invariant is `filled <= init <= capacity`, where the first `init`
+/// bytes really are initialised.
+///
+/// Safe code can only grow the filled part over bytes which are known
+/// to be initialised, so a transport which misreports how much it
+/// received can't expose uninitialised memory to the caller: that
+/// needs one of the `unsafe` methods, whose safety requirements say
+/// exactly what must have been written.
+///
+/// As with [`DataPhase`], USB has the same thing, but this is
+/// duplicated here so as not to introduce a dependency.
+pub struct ReadBuffer<'a> {
+    buf: &'a mut [MaybeUninit<u8>],
+    filled: usize,
+    init: usize,
+}
+
+impl<'a> ReadBuffer<'a> {
+    /// Wrap a buffer, none of which is (as far as we know) initialised
+    pub fn new(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self {
+            buf,
+            filled: 0,
+            init: 0,
+        }
+    }
+
+    /// The total size of the buffer
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The number of bytes filled so far
+    pub fn len(&self) -> usize {
+        self.filled
+    }
+
+    /// Has nothing been filled yet?
+    pub fn is_empty(&self) -> bool {
+        self.filled == 0
+    }
+
+    /// The number of bytes still to be filled
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.filled
+    }
+
+    /// The number of bytes, from the start of the buffer, known to be
+    /// initialised (never less than [`ReadBuffer::len()`])
+    pub fn init_len(&self) -> usize {
+        self.init
+    }
+
+    /// The bytes filled so far
+    pub fn filled(&self) -> &[u8] {
+        // SAFETY: filled <= init, and the first `init` bytes are
+        // initialised; MaybeUninit<u8> has the same layout as u8
+        unsafe {
+            core::slice::from_raw_parts(self.buf.as_ptr().cast(), self.filled)
+        }
+    }
+
+    /// The bytes filled so far (mutable)
+    pub fn filled_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for filled()
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.buf.as_mut_ptr().cast(),
+                self.filled,
+            )
+        }
+    }
+
+    /// Give up the buffer, returning the bytes filled so far
+    pub fn into_filled(self) -> &'a mut [u8] {
+        // SAFETY: as for filled(); the lifetime is that of the
+        // original buffer, which `self` no longer borrows
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.buf.as_mut_ptr().cast(),
+                self.filled,
+            )
+        }
+    }
+
+    /// Forget the bytes filled so far (they stay initialised)
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+
+    /// The unfilled part of the buffer, zeroed where necessary
+    ///
+    /// This is the slow-but-safe way to fill the buffer: receive into
+    /// the returned slice, then call [`ReadBuffer::add_filled()`].
+    /// Only the bytes not already known to be initialised are zeroed.
+    pub fn unfilled_zeroed(&mut self) -> &mut [u8] {
+        for b in &mut self.buf[self.init..] {
+            b.write(0);
+        }
+        self.init = self.buf.len();
+        let unfilled = &mut self.buf[self.filled..];
+        // SAFETY: the whole buffer has now been initialised
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                unfilled.as_mut_ptr().cast(),
+                unfilled.len(),
+            )
+        }
+    }
+
+    /// Mark the next `n` bytes as filled
+    ///
+    /// # Panics
+    ///
+    /// If those bytes aren't known to be initialised: for instance,
+    /// if `n` is bigger than the slice returned by
+    /// [`ReadBuffer::unfilled_zeroed()`].
+    pub fn add_filled(&mut self, n: usize) {
+        assert!(n <= self.init - self.filled);
+        self.filled += n;
+    }
+
+    /// Copy `data` onto the end of the filled part
+    ///
+    /// # Panics
+    ///
+    /// If there isn't room for it.
+    pub fn push_slice(&mut self, data: &[u8]) {
+        let dest = &mut self.buf[self.filled..(self.filled + data.len())];
+        for (d, s) in dest.iter_mut().zip(data) {
+            d.write(*s);
+        }
+        self.filled += data.len();
+        self.init = self.init.max(self.filled);
+    }
+
+    /// Another `ReadBuffer`, over at most `max` bytes of this one's
+    /// unfilled part
+    ///
+    /// This is for splitting up a large read into pieces: fill the
+    /// returned buffer, then pass its length to
+    /// [`ReadBuffer::assume_filled()`] on this one. (What this one
+    /// knows about which of those bytes are initialised is passed on
+    /// to the new one, but can't be passed back.)
+    pub fn unfilled(&mut self, max: usize) -> ReadBuffer<'_> {
+        let max = max.min(self.remaining());
+        ReadBuffer {
+            init: (self.init - self.filled).min(max),
+            buf: &mut self.buf[self.filled..(self.filled + max)],
+            filled: 0,
+        }
+    }
+
+    /// The unfilled part of the buffer, for receiving into directly
+    ///
+    /// # Safety
+    ///
+    /// Some of the returned bytes may be initialised, and the caller
+    /// mustn't de-initialise them (for instance, by writing
+    /// `MaybeUninit::uninit()` into them).
+    pub unsafe fn unfilled_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        &mut self.buf[self.filled..]
+    }
+
+    /// Mark the next `n` bytes as filled, without checking that
+    /// they're initialised
+    ///
+    /// # Safety
+    ///
+    /// The first `n` bytes of the unfilled part must have been
+    /// initialised (for instance, via [`ReadBuffer::unfilled_mut()`]).
+    ///
+    /// # Panics
+    ///
+    /// If there are fewer than `n` bytes remaining.
+    pub unsafe fn assume_filled(&mut self, n: usize) {
+        assert!(n <= self.remaining());
+        self.filled += n;
+        self.init = self.init.max(self.filled);
+    }
+
+    /// Mark the first `n` bytes as initialised, even though they
+    /// haven't been filled
+    ///
+    /// # Safety
+    ///
+    /// The first `n` bytes of the buffer must have been initialised.
+    pub unsafe fn set_init(&mut self, n: usize) {
+        self.init = self.init.max(n.min(self.buf.len()));
+    }
+}
+
+impl<'a> From<&'a mut [u8]> for ReadBuffer<'a> {
+    /// Wrap a buffer which is already initialised (so never needs
+    /// zeroing)
+    fn from(buf: &'a mut [u8]) -> Self {
+        let len = buf.len();
+        // SAFETY: MaybeUninit<u8> has the same layout as u8. Treating
+        // initialised bytes as maybe-uninitialised is always allowed,
+        // and the caller gets its buffer back (when the borrow ends)
+        // still initialised, because the only way to write
+        // uninitialised bytes into a ReadBuffer is through
+        // unfilled_mut(), whose safety requirements forbid it.
+        let buf =
+            unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        Self {
+            buf,
+            filled: 0,
+            init: len,
+        }
+    }
+}
+
 /// An abstract SCSI communications channel to a single device
 ///
 /// An actual SCSI bus would implement one `ScsiTransport` for each
@@ -46,11 +280,230 @@ pub trait ScsiTransport {
     /// [`ScsiDevice::command_response()`](crate::scsi_device::ScsiDevice::command_response)
     /// *does* issue REQUEST SENSE, so it can report the wider range
     /// of errors seen in [`ScsiError`]).
+    ///
+    /// A command which succeeds with status CONDITION MET, rather than
+    /// GOOD, should be reported as [`Error::ConditionMet`], if the
+    /// transport can distinguish the two.
     fn command(
         &mut self,
         cmd: &[u8],
         data: DataPhase,
     ) -> impl Future<Output = Result<usize, Error<Self::Error>>>;
+
+    /// Execute one SCSI command, to be abandoned if it takes too long
+    ///
+    /// As [`ScsiTransport::command()`], but with a hint of how long, in
+    /// milliseconds, the command might reasonably take: the caller (a
+    /// [`ScsiDevice`](crate::scsi_device::ScsiDevice)) picks this
+    /// according to the type of command, see
+    /// [`Timeouts`](crate::timeout::Timeouts). Transports which can
+    /// enforce it should fail with [`Error::Timeout`] if the hint is
+    /// exceeded.
+    ///
+    /// The default implementation ignores the hint, so waits for ever;
+    /// [`TimedTransport`](crate::timeout::TimedTransport) adds timeouts
+    /// to any transport.
+    fn command_with_timeout(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+        timeout_ms: u32,
+    ) -> impl Future<Output = Result<usize, Error<Self::Error>>> {
+        let _ = timeout_ms;
+        self.command(cmd, data)
+    }
+
+    /// Execute one SCSI command with a data-in phase, receiving into
+    /// memory which needn't be initialised
+    ///
+    /// As [`ScsiTransport::command_with_timeout()`] with
+    /// [`DataPhase::In`], but the transfer size is `data.remaining()`,
+    /// and the data received is added to the filled part of `data`.
+    /// Returns the number of bytes received, which, if the command
+    /// succeeds, is the amount by which `data.len()` grew.
+    ///
+    /// The default implementation zeroes the buffer (where necessary)
+    /// and calls [`ScsiTransport::command_with_timeout()`]; transports
+    /// which can receive straight into uninitialised memory should
+    /// override it.
+    fn command_in_uninit(
+        &mut self,
+        cmd: &[u8],
+        data: &mut ReadBuffer<'_>,
+        timeout_ms: u32,
+    ) -> impl Future<Output = Result<usize, Error<Self::Error>>> {
+        async move {
+            let buf = data.unfilled_zeroed();
+            let len = buf.len();
+            let n = self
+                .command_with_timeout(cmd, DataPhase::In(buf), timeout_ms)
+                .await?
+                .min(len);
+            data.add_filled(n);
+            Ok(n)
+        }
+    }
+
+    /// The status byte of the most recent command, if known
+    ///
+    /// The result of [`ScsiTransport::command()`] folds the status
+    /// into success or one of a few errors; transports which see the
+    /// actual status byte (such as Linux SG_IO) can report it here,
+    /// to tell apart, for instance, RESERVATION CONFLICT from CHECK
+    /// CONDITION. The default implementation returns `None`, in which
+    /// case [`ScsiStatus::from_result()`] gives the best guess.
+    fn last_status(&self) -> Option<ScsiStatus> {
+        None
+    }
+
+    /// The largest data transfer, in bytes, that this transport can
+    /// carry in a single command, if it has such a limit
+    ///
+    /// Used by
+    /// [`ScsiDevice::read_blocks()`](crate::scsi_device::ScsiDevice::read_blocks)
+    /// and
+    /// [`ScsiDevice::write_blocks()`](crate::scsi_device::ScsiDevice::write_blocks)
+    /// to split up large transfers. The default implementation returns
+    /// `None`, meaning that the transport knows of no particular limit.
+    fn max_transfer_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// Workarounds needed by the device at the other end, if known
+    ///
+    /// Picked up by
+    /// [`ScsiDevice::new()`](crate::scsi_device::ScsiDevice::new). USB
+    /// mass-storage transports, for instance, look the device up by
+    /// VID and PID. The default implementation returns no quirks.
+    fn quirks(&self) -> ScsiQuirks {
+        ScsiQuirks::default()
+    }
+
+    /// Wait for `ms` milliseconds, if the transport has a way to
+    ///
+    /// Used by [`ScsiDevice`](crate::scsi_device::ScsiDevice) to back
+    /// off before reissuing a command which failed with
+    /// [`Error::Busy`]. The default implementation returns `None`,
+    /// meaning that the transport can't wait, so such commands are
+    /// reissued straight away;
+    /// [`TimedTransport`](crate::timeout::TimedTransport) waits using
+    /// its caller-supplied delay function.
+    fn delay_ms(&mut self, ms: u32) -> Option<impl Future<Output = ()>> {
+        let _ = ms;
+        None::<core::future::Ready<()>>
+    }
+}
+
+/// Workarounds for SCSI devices which misbehave
+///
+/// See [`ScsiTransport::quirks()`] and
+/// [`ScsiDevice::set_quirks()`](crate::scsi_device::ScsiDevice::set_quirks).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct ScsiQuirks {
+    /// Never ask for vital product data pages (INQUIRY with the EVPD
+    /// bit set), which some devices hang on
+    pub no_vpd: bool,
+    /// Never issue REPORT SUPPORTED OPERATION CODES, which some devices
+    /// stall rather than reject
+    pub no_rsoc: bool,
+    /// Never transfer more than this many blocks in one command
+    pub max_transfer_blocks: Option<u32>,
+}
+
+/// The status byte with which a device completes a SCSI command
+///
+/// See SAM-5 section 5.3. Not every transport can see this: USB
+/// Bulk-Only Transport, for instance, only distinguishes "passed"
+/// from "failed". See [`ScsiTransport::last_status()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ScsiStatus {
+    /// The command succeeded
+    Good,
+    /// The command failed, and sense data says why
+    CheckCondition,
+    /// The command succeeded, and its condition was met (PRE-FETCH)
+    ConditionMet,
+    /// The device is busy, and the command may succeed if reissued
+    Busy,
+    /// Another initiator has reserved the logical unit
+    ReservationConflict,
+    /// The device's queue of commands is full: treated like Busy
+    TaskSetFull,
+    /// An auto contingent allegiance condition exists
+    AcaActive,
+    /// The command was aborted by another initiator
+    TaskAborted,
+    /// A reserved or obsolete status code
+    Other(u8),
+}
+
+impl ScsiStatus {
+    /// Decode a status byte
+    pub fn from_byte(b: u8) -> Self {
+        match b {
+            0x00 => ScsiStatus::Good,
+            0x02 => ScsiStatus::CheckCondition,
+            0x04 => ScsiStatus::ConditionMet,
+            0x08 => ScsiStatus::Busy,
+            0x18 => ScsiStatus::ReservationConflict,
+            0x28 => ScsiStatus::TaskSetFull,
+            0x30 => ScsiStatus::AcaActive,
+            0x40 => ScsiStatus::TaskAborted,
+            _ => ScsiStatus::Other(b),
+        }
+    }
+
+    /// Encode as a status byte
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ScsiStatus::Good => 0x00,
+            ScsiStatus::CheckCondition => 0x02,
+            ScsiStatus::ConditionMet => 0x04,
+            ScsiStatus::Busy => 0x08,
+            ScsiStatus::ReservationConflict => 0x18,
+            ScsiStatus::TaskSetFull => 0x28,
+            ScsiStatus::AcaActive => 0x30,
+            ScsiStatus::TaskAborted => 0x40,
+            ScsiStatus::Other(b) => b,
+        }
+    }
+
+    /// The status implied by the result of
+    /// [`ScsiTransport::command()`], for transports which don't report
+    /// it directly
+    ///
+    /// Transport errors, timeouts and so on say nothing about the
+    /// status, so give `None`.
+    pub fn from_result<T: PartialEq + Eq>(
+        result: &Result<usize, Error<T>>,
+    ) -> Option<Self> {
+        match result {
+            Ok(_) => Some(ScsiStatus::Good),
+            Err(Error::CommandFailed) => Some(ScsiStatus::CheckCondition),
+            Err(Error::ConditionMet) => Some(ScsiStatus::ConditionMet),
+            Err(Error::Busy) => Some(ScsiStatus::Busy),
+            Err(_) => None,
+        }
+    }
+
+    /// The result with which [`ScsiTransport::command()`] should report
+    /// a command which completed with this status, having transferred
+    /// `transferred` bytes
+    pub fn to_result<T: PartialEq + Eq>(
+        self,
+        transferred: usize,
+    ) -> Result<usize, Error<T>> {
+        match self {
+            ScsiStatus::Good => Ok(transferred),
+            ScsiStatus::ConditionMet => Err(Error::ConditionMet),
+            ScsiStatus::Busy | ScsiStatus::TaskSetFull => Err(Error::Busy),
+            _ => Err(Error::CommandFailed),
+        }
+    }
 }
 
 /// Errors which can arise during a SCSI command
@@ -72,6 +525,123 @@ pub enum Error<T: PartialEq + Eq> {
 
     /// The device experienced an error, as reported by REQUEST SENSE.
     Scsi(ScsiError),
+
+    /// The buffer passed to a read or write didn't match the number of
+    /// blocks requested (or no blocks were requested); nothing was sent
+    /// to the device.
+    BadBufferSize,
+
+    /// As an error from `ScsiDevice::compare_and_write`: the data on
+    /// the medium didn't match the verify data, so wasn't replaced.
+    /// Contains the offset, in bytes, of the first mismatch, if the
+    /// device reported it.
+    Miscompare(Option<u32>),
+
+    /// As an error from `ScsiTransport::command`: the command succeeded,
+    /// but the device returned status CONDITION MET rather than GOOD.
+    ///
+    /// Only PRE-FETCH ordinarily does this, and
+    /// `ScsiDevice::pre_fetch` treats it as success. Transports which
+    /// can't tell the two statuses apart just report success.
+    ConditionMet,
+
+    /// The command didn't complete in time.
+    ///
+    /// The state of the device is unknown (it may not be responding at
+    /// all), so unlike with `CommandFailed`, no REQUEST SENSE is issued.
+    Timeout,
+
+    /// As an error from `ScsiTransport::command`: the device replied
+    /// with status BUSY (or TASK SET FULL), so the command wasn't
+    /// carried out, but may succeed if reissued.
+    ///
+    /// There's no sense data, so no REQUEST SENSE is issued.
+    /// `ScsiDevice` reissues the command a few times (see
+    /// `ScsiDevice::set_busy_retries`) before reporting this, backing
+    /// off in between if the transport can wait (see
+    /// `ScsiTransport::delay_ms`).
+    Busy,
+}
+
+/// Errors which can arise during a multi-command block transfer
+///
+/// As returned by
+/// [`ScsiDevice::read_blocks()`](crate::scsi_device::ScsiDevice::read_blocks)
+/// and
+/// [`ScsiDevice::write_blocks()`](crate::scsi_device::ScsiDevice::write_blocks),
+/// which might have successfully transferred some of the blocks before
+/// encountering the error.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct TransferError<T: PartialEq + Eq> {
+    /// The number of blocks, from the start of the request, which were
+    /// transferred successfully
+    pub blocks_done: u32,
+
+    /// The error which ended the transfer
+    pub error: Error<T>,
+}
+
+impl<T: PartialEq + Eq> From<TransferError<T>> for Error<T> {
+    fn from(e: TransferError<T>) -> Self {
+        e.error
+    }
+}
+
+/// The reason for a command failure, as reported by REQUEST SENSE
+///
+/// See [`ScsiDevice::last_sense()`](crate::scsi_device::ScsiDevice::last_sense).
+/// The meanings of all the defined combinations of sense key, ASC and
+/// ASCQ are listed in Seagate SCSI Commands Reference Manual tables 28
+/// and 29.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct SenseData {
+    /// The sense key: the general category of the error
+    pub key: u8,
+    /// The additional sense code (ASC)
+    pub asc: u8,
+    /// The additional sense code qualifier (ASCQ)
+    pub ascq: u8,
+    /// The "information" field, if the device supplied one
+    ///
+    /// For read and write errors this is usually the address of the
+    /// block which failed.
+    pub information: Option<u64>,
+    /// The sense-key-specific field, if the device supplied one
+    ///
+    /// Present only if the device set its SKSV bit, which is cleared
+    /// here. What it means depends on the sense key: for "not ready"
+    /// and "no sense" it's a progress indication (see
+    /// [`SenseData::progress()`]), for "illegal request" it points at
+    /// the offending field.
+    pub sense_key_specific: Option<[u8; 3]>,
+}
+
+impl SenseData {
+    /// Decode sense data, as returned by REQUEST SENSE
+    ///
+    /// Both fixed and descriptor formats are understood. Returns `None`
+    /// if the data is too short to be either.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        crate::scsi_device::parse_sense(buf)
+    }
+
+    /// How far a long-running operation has got, if the device says
+    ///
+    /// During FORMAT UNIT, SANITIZE, or a background self-test, REQUEST
+    /// SENSE reports "not ready" or "no sense", with a progress
+    /// indication in the sense-key-specific field. This returns that
+    /// progress, as a fraction of 65536 (so 0x8000 is half-way), or
+    /// `None` if there isn't one.
+    pub fn progress(&self) -> Option<u16> {
+        match (self.key, self.sense_key_specific) {
+            (0 | 2, Some(sks)) => Some(u16::from_be_bytes([sks[1], sks[2]])),
+            _ => None,
+        }
+    }
 }
 
 /// Errors which can be returned over SCSI protocol from the SCSI device
@@ -120,6 +690,28 @@ pub enum ScsiError {
     /// Something is incorrect in the command block itself
     InvalidFieldInCDB,
     LogicalUnitNotSupported,
+    /// There is no medium (disc, card) in the drive
+    MediaNotPresent,
+    /// The medium may have been changed since the last command: any
+    /// cached information about it, such as its capacity, is stale
+    MediaChanged,
+    /// The device has been powered on or reset since the last command,
+    /// so any settings made (such as with MODE SELECT) have been lost
+    ResetOccurred,
+    /// The medium is write-protected
+    WriteProtected,
+    /// A write to a zoned device wasn't at the zone's write pointer
+    UnalignedWrite,
+    /// The device lacks the resources (such as memory) to carry out
+    /// the command just now
+    InsufficientResources,
+    /// The device predicts that it will fail soon (it's exceeded a
+    /// SMART-style "failure prediction threshold"); the value is the
+    /// ASCQ, which says which threshold, and 0xFF means it's only a
+    /// test (see [`InformationalExceptionsPage::test`])
+    ///
+    /// [`InformationalExceptionsPage::test`]: crate::informational_exceptions::InformationalExceptionsPage::test
+    FailurePredicted(u8),
 
     NotReady,
     MediumError,
@@ -137,3 +729,122 @@ pub enum ScsiError {
     VolumeOverflow,
     Miscompare,
 }
+
+impl core::fmt::Display for ScsiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let s = match self {
+            ScsiError::BecomingReady => "becoming ready",
+            ScsiError::StartUnitRequired => "START UNIT required",
+            ScsiError::ManualInterventionRequired => {
+                "manual intervention required"
+            }
+            ScsiError::FormatInProgress => "format in progress",
+            ScsiError::SelfTestInProgress => "self-test in progress",
+            ScsiError::PowerCycleRequired => "power cycle required",
+            ScsiError::Overheat => "overheated",
+            ScsiError::EnclosureDegraded => "enclosure degraded",
+            ScsiError::WriteError => "write error",
+            ScsiError::WriteReallocationFailed => "write reallocation failed",
+            ScsiError::UnrecoveredReadError => "unrecovered read error",
+            ScsiError::ReadRetriesExhausted => "read retries exhausted",
+            ScsiError::ReadErrorTooLong => "read error too long to correct",
+            ScsiError::ReadReallocationFailed => "read reallocation failed",
+            ScsiError::LogicalBlockNotFound => "block not found",
+            ScsiError::RecordNotFound => "record not found",
+            ScsiError::InvalidFieldInParameterList => {
+                "invalid field in parameter list"
+            }
+            ScsiError::ParameterNotSupported => "parameter not supported",
+            ScsiError::ParameterValueInvalid => "parameter value invalid",
+            ScsiError::LogicalUnitSelfTestFailed => "self-test failed",
+            ScsiError::SelfTestFailed => "diagnostic failed",
+            ScsiError::PositioningError => "positioning error",
+            ScsiError::ParameterListLengthError => {
+                "parameter list length error"
+            }
+            ScsiError::MiscompareDuringVerify => "miscompare during verify",
+            ScsiError::InvalidCommandOperationCode => "command not supported",
+            ScsiError::LogicalBlockAddressOutOfRange => "LBA out of range",
+            ScsiError::InvalidFieldInCDB => "invalid field in command",
+            ScsiError::LogicalUnitNotSupported => "logical unit not supported",
+            ScsiError::MediaNotPresent => "no medium",
+            ScsiError::MediaChanged => "medium may have changed",
+            ScsiError::ResetOccurred => "device was reset",
+            ScsiError::WriteProtected => "medium is write-protected",
+            ScsiError::UnalignedWrite => "write not at write pointer",
+            ScsiError::InsufficientResources => "insufficient resources",
+            ScsiError::FailurePredicted(ascq) => {
+                return write!(f, "failure predicted ({:#04x})", ascq);
+            }
+            ScsiError::NotReady => "not ready",
+            ScsiError::MediumError => "medium error",
+            ScsiError::HardwareError => "hardware error",
+            ScsiError::IllegalRequest => "illegal request",
+            ScsiError::UnitAttention => "unit attention",
+            ScsiError::DataProtect => "write protected",
+            ScsiError::BlankCheck => "blank medium",
+            ScsiError::VendorSpecific => "vendor-specific error",
+            ScsiError::CopyAborted => "copy aborted",
+            ScsiError::Aborted => "command aborted",
+            ScsiError::VolumeOverflow => "volume overflow",
+            ScsiError::Miscompare => "miscompare",
+        };
+        f.write_str(s)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ScsiError {}
+
+impl<T: PartialEq + Eq + core::fmt::Display> core::fmt::Display for Error<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::CommandFailed => f.write_str("command failed"),
+            Error::ProtocolError => f.write_str("SCSI protocol error"),
+            // The transport's own error is the `source()`
+            Error::Transport(_) => f.write_str("transport error"),
+            Error::Scsi(e) => e.fmt(f),
+            Error::BadBufferSize => f.write_str("wrong buffer size"),
+            Error::Miscompare(Some(n)) => write!(f, "miscompare at byte {n}"),
+            Error::Miscompare(None) => f.write_str("miscompare"),
+            Error::ConditionMet => f.write_str("condition met"),
+            Error::Timeout => f.write_str("command timed out"),
+            Error::Busy => f.write_str("device busy"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> std::error::Error for Error<T>
+where
+    T: PartialEq + Eq + std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<T: PartialEq + Eq + core::fmt::Display> core::fmt::Display
+    for TransferError<T>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "transfer failed after {} blocks", self.blocks_done)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> std::error::Error for TransferError<T>
+where
+    T: PartialEq + Eq + std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/scsi_transport.rs"]
+mod tests;
@@ -0,0 +1,289 @@
+use crate::scsi_transport::{DataPhase, Error, ScsiStatus, ScsiTransport};
+use std::fs::{File, OpenOptions};
+use std::future::{ready, Future};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::time::Duration;
+
+/// The SG_IO ioctl request number, from <scsi/sg.h>
+const SG_IO: libc::c_ulong = 0x2285;
+
+const SG_DXFER_NONE: libc::c_int = -1;
+const SG_DXFER_TO_DEV: libc::c_int = -2;
+const SG_DXFER_FROM_DEV: libc::c_int = -3;
+
+/// The "driver status" which just means that sense data is present
+const DRIVER_SENSE: u16 = 0x08;
+
+/// The "host status" for a command which timed out (and was aborted)
+const DID_TIME_OUT: u16 = 0x03;
+
+/// The largest sense buffer the kernel will return
+const SENSE_BUFFER_SIZE: usize = 64;
+
+/// The kernel's `struct sg_io_hdr`, from <scsi/sg.h>
+#[repr(C)]
+struct SgIoHdr {
+    interface_id: libc::c_int,
+    dxfer_direction: libc::c_int,
+    cmd_len: libc::c_uchar,
+    mx_sb_len: libc::c_uchar,
+    iovec_count: libc::c_ushort,
+    dxfer_len: libc::c_uint,
+    dxferp: *mut libc::c_void,
+    cmdp: *const libc::c_uchar,
+    sbp: *mut libc::c_uchar,
+    timeout: libc::c_uint,
+    flags: libc::c_uint,
+    pack_id: libc::c_int,
+    usr_ptr: *mut libc::c_void,
+    status: libc::c_uchar,
+    masked_status: libc::c_uchar,
+    msg_status: libc::c_uchar,
+    sb_len_wr: libc::c_uchar,
+    host_status: libc::c_ushort,
+    driver_status: libc::c_ushort,
+    resid: libc::c_int,
+    duration: libc::c_uint,
+    info: libc::c_uint,
+}
+
+/// Errors from the Linux SCSI generic driver itself
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SgError {
+    /// The SG_IO ioctl failed, with this `errno`
+    Io(i32),
+    /// The host adapter reported an error (a `DID_*` code)
+    Host(u16),
+    /// The low-level driver reported an error (a `DRIVER_*` code)
+    Driver(u16),
+}
+
+impl std::fmt::Display for SgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SgError::Io(errno) => write!(
+                f,
+                "SG_IO failed: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+            SgError::Host(n) => write!(f, "host adapter error {n:#x}"),
+            SgError::Driver(n) => write!(f, "driver error {n:#x}"),
+        }
+    }
+}
+
+impl std::error::Error for SgError {}
+
+/// A [`ScsiTransport`] using the Linux SCSI generic (`/dev/sg*`) driver
+///
+/// This lets the SCSI layer, and anything built upon it, be run on a
+/// desktop Linux system against real devices -- USB flash drives, SATA
+/// disks, optical drives -- or against the kernel's `scsi_debug`
+/// simulated device. Access to `/dev/sg*` usually requires membership
+/// of the `disk` group, or root.
+///
+/// Commands are issued with the SG_IO ioctl, which *blocks* the calling
+/// thread until the command completes; the futures returned by
+/// [`ScsiTransport::command()`] are always immediately ready. So this
+/// transport is meant for tools and testing, rather than for use
+/// inside an async executor that's also doing other things.
+///
+/// The kernel fetches sense data automatically when a command fails,
+/// after which the device itself no longer has it. So the sense data is
+/// retained, and used to answer the REQUEST SENSE which
+/// [`ScsiDevice`](crate::ScsiDevice) issues after a failure.
+#[derive(Debug)]
+pub struct SgTransport {
+    file: File,
+    timeout_ms: u32,
+    sense: Option<([u8; SENSE_BUFFER_SIZE], usize)>,
+    last_status: Option<ScsiStatus>,
+}
+
+impl SgTransport {
+    /// The default timeout for each command, in milliseconds
+    pub const DEFAULT_TIMEOUT_MS: u32 = 30_000;
+
+    /// Open a SCSI generic device node, such as `/dev/sg0`
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self::from_file(file))
+    }
+
+    /// Use an already-open SCSI generic device
+    pub fn from_file(file: File) -> Self {
+        Self {
+            file,
+            timeout_ms: Self::DEFAULT_TIMEOUT_MS,
+            sense: None,
+            last_status: None,
+        }
+    }
+
+    /// Set the timeout applied to each subsequent command
+    ///
+    /// This applies only to commands issued with
+    /// [`ScsiTransport::command()`];
+    /// [`ScsiTransport::command_with_timeout()`], which is what
+    /// [`ScsiDevice`](crate::ScsiDevice) uses, passes its own timeout
+    /// to the kernel instead (see
+    /// [`ScsiDevice::set_timeouts()`](crate::ScsiDevice::set_timeouts)).
+    pub fn set_timeout_ms(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Answer REQUEST SENSE from retained autosense data
+    fn stored_sense(
+        &mut self,
+        cmd: &[u8],
+        data: &mut DataPhase,
+    ) -> Option<usize> {
+        if cmd.first() != Some(&3) {
+            return None;
+        }
+        let (sense, len) = self.sense.take()?;
+        let DataPhase::In(buf) = data else {
+            return None;
+        };
+        let n = len.min(buf.len());
+        buf[0..n].copy_from_slice(&sense[0..n]);
+        Some(n)
+    }
+
+    fn sg_io(
+        &mut self,
+        cmd: &[u8],
+        mut data: DataPhase,
+        timeout_ms: u32,
+    ) -> Result<usize, Error<SgError>> {
+        if let Some(n) = self.stored_sense(cmd, &mut data) {
+            self.last_status = Some(ScsiStatus::Good);
+            return Ok(n);
+        }
+        self.sense = None;
+        self.last_status = None;
+
+        let (direction, dxferp, len) = match data {
+            DataPhase::In(buf) => (
+                SG_DXFER_FROM_DEV,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            ),
+            DataPhase::Out(buf) => (
+                SG_DXFER_TO_DEV,
+                // The kernel doesn't write to the buffer in this direction
+                buf.as_ptr() as *mut libc::c_void,
+                buf.len(),
+            ),
+            DataPhase::None => (SG_DXFER_NONE, core::ptr::null_mut(), 0),
+        };
+        let (Ok(cmd_len), Ok(dxfer_len)) =
+            (u8::try_from(cmd.len()), u32::try_from(len))
+        else {
+            return Err(Error::ProtocolError);
+        };
+
+        let mut sense = [0u8; SENSE_BUFFER_SIZE];
+        let mut hdr = SgIoHdr {
+            interface_id: b'S' as libc::c_int,
+            dxfer_direction: direction,
+            cmd_len,
+            mx_sb_len: SENSE_BUFFER_SIZE as u8,
+            iovec_count: 0,
+            dxfer_len,
+            dxferp,
+            cmdp: cmd.as_ptr(),
+            sbp: sense.as_mut_ptr(),
+            timeout: timeout_ms,
+            flags: 0,
+            pack_id: 0,
+            usr_ptr: core::ptr::null_mut(),
+            status: 0,
+            masked_status: 0,
+            msg_status: 0,
+            sb_len_wr: 0,
+            host_status: 0,
+            driver_status: 0,
+            resid: 0,
+            duration: 0,
+            info: 0,
+        };
+
+        // SAFETY: SG_IO is synchronous, so the command, data and sense
+        // buffers pointed to by hdr (whose lengths are given in hdr)
+        // all outlive the ioctl call, and the kernel keeps no
+        // reference to them afterwards. The data buffer is only written
+        // if it came from DataPhase::In, which was a &mut.
+        let rc = unsafe {
+            libc::ioctl(self.file.as_raw_fd(), SG_IO as _, &mut hdr)
+        };
+        if rc < 0 {
+            let errno =
+                std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+            return Err(Error::Transport(SgError::Io(errno)));
+        }
+
+        if hdr.host_status != 0 {
+            return Err(host_error(hdr.host_status));
+        }
+        if (hdr.driver_status & !DRIVER_SENSE) != 0 {
+            return Err(Error::Transport(SgError::Driver(hdr.driver_status)));
+        }
+        let status = ScsiStatus::from_byte(hdr.status & 0x7E);
+        self.last_status = Some(status);
+        if status == ScsiStatus::CheckCondition && hdr.sb_len_wr > 0 {
+            self.sense = Some((sense, hdr.sb_len_wr as usize));
+        }
+        let resid = usize::try_from(hdr.resid).unwrap_or(0);
+        status.to_result(len.saturating_sub(resid))
+    }
+}
+
+/// Interpret a non-zero host status
+///
+/// The kernel enforces the timeout itself, aborting the command and
+/// reporting DID_TIME_OUT, which is reported as [`Error::Timeout`].
+fn host_error(host_status: u16) -> Error<SgError> {
+    if host_status == DID_TIME_OUT {
+        Error::Timeout
+    } else {
+        Error::Transport(SgError::Host(host_status))
+    }
+}
+
+impl ScsiTransport for SgTransport {
+    type Error = SgError;
+
+    fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+    ) -> impl Future<Output = Result<usize, Error<SgError>>> {
+        ready(self.sg_io(cmd, data, self.timeout_ms))
+    }
+
+    fn command_with_timeout(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+        timeout_ms: u32,
+    ) -> impl Future<Output = Result<usize, Error<SgError>>> {
+        ready(self.sg_io(cmd, data, timeout_ms))
+    }
+
+    // SG_IO blocks the thread anyway, so waiting can too
+    fn delay_ms(&mut self, ms: u32) -> Option<impl Future<Output = ()>> {
+        Some(async move {
+            std::thread::sleep(Duration::from_millis(ms.into()));
+        })
+    }
+
+    fn last_status(&self) -> Option<ScsiStatus> {
+        self.last_status
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/sg.rs"]
+mod tests;
@@ -0,0 +1,315 @@
+use crate::async_block_device::{AsyncBlockDevice, DeviceInfo};
+use embedded_storage_async::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Errors which can arise from [`BlockStorage`]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum StorageError<E> {
+    /// The access extends beyond the end of the device (or beyond 4GiB,
+    /// the most that a `u32` offset can address)
+    OutOfBounds,
+
+    /// An erase didn't start or end on a multiple of `ERASE_SIZE`
+    NotAligned,
+
+    /// The device's block size doesn't fit the bounce buffer: the
+    /// buffer must be a whole number of blocks (and at least one)
+    UnsupportedBlockSize(u32),
+
+    /// The underlying block device reported an error
+    Device(E),
+}
+
+// Written out by hand, because the device's own errors needn't be Debug
+// (ScsiDevice's errors aren't, in no_std builds), but NorFlashError
+// requires that this one is.
+impl<E> core::fmt::Debug for StorageError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::OutOfBounds => f.write_str("OutOfBounds"),
+            Self::NotAligned => f.write_str("NotAligned"),
+            Self::UnsupportedBlockSize(n) => {
+                write!(f, "UnsupportedBlockSize({n})")
+            }
+            Self::Device(_) => f.write_str("Device(..)"),
+        }
+    }
+}
+
+impl<E> NorFlashError for StorageError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// Byte-addressed access to a block device, through `embedded-storage-async`
+///
+/// Adapts an [`AsyncBlockDevice`] -- usually a
+/// [`ScsiBlockDevice`](crate::ScsiBlockDevice) -- to the
+/// [`ReadNorFlash`] and [`NorFlash`] traits, so that crates written
+/// for those traits (key-value stores, logs, filesystems) can keep
+/// their data on, say, a USB flash drive.
+///
+/// Reads and writes may start and end anywhere. Whole blocks in the
+/// middle of an access are transferred directly to or from the
+/// caller's buffer; any partial block at the start or end goes via an
+/// internal "bounce buffer" of `N` bytes. So a write which doesn't
+/// cover a whole block is a read-modify-write of that block -- or, if
+/// the device reports a larger physical block size which fits in the
+/// bounce buffer, of the whole physical block, so that the device
+/// needn't do one internally as well. `N` must be a whole number of
+/// device blocks, and is also the `ERASE_SIZE`; the default suits the
+/// common 512-byte block size.
+///
+/// Unlike real NOR flash, a write fully replaces the previous
+/// contents, whether or not the area was first erased. Erasing sets
+/// the area to `0xFF`, as NOR flash does.
+///
+/// Offsets are `u32`, so only the first 4GiB of a larger device can be
+/// accessed in this way.
+pub struct BlockStorage<D: AsyncBlockDevice, const N: usize = 512> {
+    device: D,
+    block_size: usize,
+    per_physical: usize,
+    capacity: u64,
+    buffer: [u8; N],
+}
+
+impl<D: AsyncBlockDevice, const N: usize> BlockStorage<D, N> {
+    /// Wrap a block device, finding out its size and block size
+    ///
+    /// Fails with [`StorageError::UnsupportedBlockSize`] if `N` isn't
+    /// a whole number of the device's blocks.
+    pub async fn new(mut device: D) -> Result<Self, StorageError<D::E>> {
+        let DeviceInfo {
+            blocks,
+            block_size,
+            physical_block_size,
+        } = device.device_info().await.map_err(StorageError::Device)?;
+        let bs = block_size as usize;
+        if bs == 0 || N < bs || N % bs != 0 {
+            return Err(StorageError::UnsupportedBlockSize(block_size));
+        }
+        let physical = physical_block_size as usize;
+        let per_physical = if physical % bs == 0 && N % physical == 0 {
+            physical / bs
+        } else {
+            1
+        };
+        let capacity = blocks
+            .saturating_mul(block_size as u64)
+            .min(u32::MAX as u64 + 1);
+        Ok(Self {
+            device,
+            block_size: bs,
+            per_physical,
+            capacity,
+            buffer: [0u8; N],
+        })
+    }
+
+    /// Access the underlying block device
+    pub fn device(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Stop using this adaptor, and return the underlying block device
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Make sure that all writes so far are stored on the device
+    ///
+    /// See [`AsyncBlockDevice::flush()`].
+    pub async fn flush(&mut self) -> Result<(), StorageError<D::E>> {
+        self.device.flush().await.map_err(StorageError::Device)
+    }
+
+    fn check(
+        &self,
+        offset: u32,
+        len: usize,
+    ) -> Result<(), StorageError<D::E>> {
+        if offset as u64 + len as u64 > self.capacity {
+            Err(StorageError::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Split a byte position into block number and offset within block
+    fn locate(&self, pos: u64) -> (u64, usize) {
+        let bs = self.block_size as u64;
+        (pos / bs, (pos % bs) as usize)
+    }
+
+    /// The blocks to read-modify-write to change part of block `lba`
+    ///
+    /// That's the whole physical block containing it, if possible.
+    fn rmw_unit(&self, lba: u64) -> (u64, usize) {
+        let per_physical = self.per_physical as u64;
+        let first = lba - lba % per_physical;
+        if (first + per_physical) * self.block_size as u64 <= self.capacity {
+            (first, self.per_physical)
+        } else {
+            (lba, 1)
+        }
+    }
+
+    /// How many whole blocks, at most, can go straight to or from `len`
+    /// bytes of the caller's buffer
+    fn whole_blocks(&self, len: usize) -> u32 {
+        (len / self.block_size).min(u32::MAX as usize) as u32
+    }
+
+    async fn read_bytes(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), StorageError<D::E>> {
+        self.check(offset, bytes.len())?;
+        let mut pos = offset as u64;
+        let mut done = 0;
+        while done < bytes.len() {
+            let rest = &mut bytes[done..];
+            let (lba, skip) = self.locate(pos);
+            let count = self.whole_blocks(rest.len());
+            let n = if skip == 0 && count > 0 {
+                let n = count as usize * self.block_size;
+                self.device
+                    .read_blocks(lba, count, &mut rest[..n])
+                    .await
+                    .map_err(StorageError::Device)?;
+                n
+            } else {
+                let bs = self.block_size;
+                let blocks = (skip + rest.len()).div_ceil(bs).min(N / bs);
+                let buf = &mut self.buffer[..blocks * bs];
+                self.device
+                    .read_blocks(lba, blocks as u32, buf)
+                    .await
+                    .map_err(StorageError::Device)?;
+                let n = (buf.len() - skip).min(rest.len());
+                rest[..n].copy_from_slice(&buf[skip..skip + n]);
+                n
+            };
+            done += n;
+            pos += n as u64;
+        }
+        Ok(())
+    }
+
+    async fn write_bytes(
+        &mut self,
+        offset: u32,
+        bytes: &[u8],
+    ) -> Result<(), StorageError<D::E>> {
+        self.check(offset, bytes.len())?;
+        let mut pos = offset as u64;
+        let mut done = 0;
+        while done < bytes.len() {
+            let rest = &bytes[done..];
+            let (lba, skip) = self.locate(pos);
+            let count = self.whole_blocks(rest.len());
+            let n = if skip == 0 && count > 0 {
+                let n = count as usize * self.block_size;
+                self.device
+                    .write_blocks(lba, count, &rest[..n])
+                    .await
+                    .map_err(StorageError::Device)?;
+                n
+            } else {
+                // Partial block: read-modify-write
+                let (first, blocks) = self.rmw_unit(lba);
+                let skip = (pos - first * self.block_size as u64) as usize;
+                let buf = &mut self.buffer[..blocks * self.block_size];
+                self.device
+                    .read_blocks(first, blocks as u32, buf)
+                    .await
+                    .map_err(StorageError::Device)?;
+                let n = (buf.len() - skip).min(rest.len());
+                buf[skip..skip + n].copy_from_slice(&rest[..n]);
+                self.device
+                    .write_blocks(first, blocks as u32, buf)
+                    .await
+                    .map_err(StorageError::Device)?;
+                n
+            };
+            done += n;
+            pos += n as u64;
+        }
+        Ok(())
+    }
+
+    async fn erase_bytes(
+        &mut self,
+        from: u32,
+        to: u32,
+    ) -> Result<(), StorageError<D::E>> {
+        if from > to || to as u64 > self.capacity {
+            return Err(StorageError::OutOfBounds);
+        }
+        if from as usize % N != 0 || to as usize % N != 0 {
+            return Err(StorageError::NotAligned);
+        }
+        self.buffer.fill(0xFF);
+        let blocks = (N / self.block_size) as u32;
+        let mut lba = self.locate(from as u64).0;
+        let end = self.locate(to as u64).0;
+        while lba < end {
+            self.device
+                .write_blocks(lba, blocks, &self.buffer)
+                .await
+                .map_err(StorageError::Device)?;
+            lba += blocks as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<D: AsyncBlockDevice, const N: usize> ErrorType for BlockStorage<D, N> {
+    type Error = StorageError<D::E>;
+}
+
+impl<D: AsyncBlockDevice, const N: usize> ReadNorFlash for BlockStorage<D, N> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.read_bytes(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        usize::try_from(self.capacity).unwrap_or(usize::MAX)
+    }
+}
+
+impl<D: AsyncBlockDevice, const N: usize> NorFlash for BlockStorage<D, N> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = N;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase_bytes(from, to).await
+    }
+
+    async fn write(
+        &mut self,
+        offset: u32,
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.write_bytes(offset, bytes).await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/storage.rs"]
+mod tests;
@@ -0,0 +1,308 @@
+use crate::scsi_device::SENSE_BUFFER_SIZE;
+use crate::scsi_transport::{
+    DataPhase, Error, ScsiQuirks, ScsiStatus, ScsiTransport, SenseData,
+};
+use futures::future::Either;
+use std::collections::VecDeque;
+use std::future::{pending, ready, Future};
+
+/// What the fake device does with one command
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// Expect a data-in command, and reply with these bytes
+    In(Vec<u8>),
+    /// Expect a data-out command, carrying exactly these bytes
+    Out(Vec<u8>),
+    /// Expect a command with no data phase
+    NoData,
+    /// Expect a command (of any direction), and fail it
+    Fail(Error<()>),
+    /// Expect a command (of any direction), and never complete it
+    Pending,
+}
+
+#[derive(Debug)]
+struct Expectation {
+    cdb: Vec<u8>,
+    step: Step,
+}
+
+/// A scripted, in-memory [`ScsiTransport`], for writing unit tests
+///
+/// Each expected command is added to the script, in order, along with
+/// the fake device's response to it. As the code under test issues
+/// commands, each is checked against the next entry in the script; any
+/// discrepancy -- a different command block, the wrong data direction,
+/// or a command beyond the end of the script -- panics, showing both
+/// the expected and actual command block. When the transport is
+/// dropped, it also panics if any of the script was left unused.
+///
+/// All commands complete immediately (unless scripted with
+/// [`FakeScsiTransport::expect_pending()`]), so futures using this
+/// transport don't return `Poll::Pending`, and can be run to completion
+/// with, for instance, `futures::FutureExt::now_or_never()`.
+///
+/// ```
+/// use cotton_scsi::testing::{self, FakeScsiTransport};
+/// use cotton_scsi::{ScsiDevice, SenseData};
+/// use futures::FutureExt;
+///
+/// let mut fake = FakeScsiTransport::new();
+/// fake.expect_no_data(&[0, 0, 0, 0, 0, 0]) // TEST UNIT READY
+///     .expect_check_condition(
+///         &[0, 0, 0, 0, 0, 0],
+///         testing::sense(2, 0x3A, 0), // Medium not present
+///     );
+/// let mut device = ScsiDevice::new(fake);
+/// assert!(device.test_unit_ready().now_or_never().unwrap().is_ok());
+/// assert!(device.test_unit_ready().now_or_never().unwrap().is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct FakeScsiTransport {
+    script: VecDeque<Expectation>,
+    count: usize,
+
+    /// The value to return from [`ScsiTransport::max_transfer_bytes()`]
+    pub max_transfer_bytes: Option<usize>,
+
+    /// The value to return from [`ScsiTransport::quirks()`]
+    pub quirks: ScsiQuirks,
+
+    /// The value to return from [`ScsiTransport::last_status()`]
+    pub last_status: Option<ScsiStatus>,
+}
+
+impl FakeScsiTransport {
+    /// Create a new fake transport, with an empty script
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, cdb: &[u8], step: Step) -> &mut Self {
+        self.script.push_back(Expectation {
+            cdb: cdb.to_vec(),
+            step,
+        });
+        self
+    }
+
+    /// Expect a data-in command, and reply to it with `reply`
+    ///
+    /// If the initiator's buffer is smaller than `reply`, the reply is
+    /// truncated to fit (as a real device would do).
+    pub fn expect_in(&mut self, cdb: &[u8], reply: &[u8]) -> &mut Self {
+        self.push(cdb, Step::In(reply.to_vec()))
+    }
+
+    /// Expect a data-out command, whose data must match `data` exactly
+    pub fn expect_out(&mut self, cdb: &[u8], data: &[u8]) -> &mut Self {
+        self.push(cdb, Step::Out(data.to_vec()))
+    }
+
+    /// Expect a successful command with no data phase
+    pub fn expect_no_data(&mut self, cdb: &[u8]) -> &mut Self {
+        self.push(cdb, Step::NoData)
+    }
+
+    /// Expect a command, and fail it with the given (transport) error
+    ///
+    /// The data direction of the command isn't checked.
+    /// [`Error::CommandFailed`] causes
+    /// [`ScsiDevice`](crate::ScsiDevice) to issue REQUEST SENSE,
+    /// which must also be scripted; usually
+    /// [`FakeScsiTransport::expect_check_condition()`] is more
+    /// convenient for that case.
+    pub fn expect_error(&mut self, cdb: &[u8], error: Error<()>) -> &mut Self {
+        self.push(cdb, Step::Fail(error))
+    }
+
+    /// Expect a command, and never complete it
+    ///
+    /// The data direction of the command isn't checked. For testing
+    /// that the code under test itself pends while waiting for the
+    /// device.
+    pub fn expect_pending(&mut self, cdb: &[u8]) -> &mut Self {
+        self.push(cdb, Step::Pending)
+    }
+
+    /// Expect a command, fail it, and report `sense` data for it
+    ///
+    /// This scripts both the failing command and the REQUEST SENSE
+    /// which [`ScsiDevice`](crate::ScsiDevice) then issues.
+    pub fn expect_check_condition(
+        &mut self,
+        cdb: &[u8],
+        sense: SenseData,
+    ) -> &mut Self {
+        self.expect_error(cdb, Error::CommandFailed)
+            .expect_in(&request_sense_cdb(), &fixed_sense(&sense))
+    }
+
+    /// Has every command in the script been issued?
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    fn next(&mut self, cdb: &[u8]) -> Step {
+        let index = self.count;
+        self.count += 1;
+        let Some(expected) = self.script.pop_front() else {
+            panic!(
+                "FakeScsiTransport: unexpected command #{index} {}",
+                hex(cdb)
+            );
+        };
+        if expected.cdb != cdb {
+            panic!(
+                "FakeScsiTransport: command #{index} mismatch\n\
+                 expected: {}\n  actual: {}\n          {}",
+                hex(&expected.cdb),
+                hex(cdb),
+                diff_marker(&expected.cdb, cdb)
+            );
+        }
+        expected.step
+    }
+}
+
+impl Drop for FakeScsiTransport {
+    fn drop(&mut self) {
+        if !std::thread::panicking() && !self.script.is_empty() {
+            panic!(
+                "FakeScsiTransport: {} command(s) not issued, next {}",
+                self.script.len(),
+                hex(&self.script[0].cdb)
+            );
+        }
+    }
+}
+
+impl ScsiTransport for FakeScsiTransport {
+    type Error = ();
+
+    fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+    ) -> impl Future<Output = Result<usize, Error<()>>> {
+        let step = self.next(cmd);
+        if step == Step::Pending {
+            return Either::Right(pending());
+        }
+        Either::Left(ready(match (step, data) {
+            (Step::Fail(e), _) => Err(e),
+            (Step::In(reply), DataPhase::In(buf)) => {
+                let n = reply.len().min(buf.len());
+                buf[0..n].copy_from_slice(&reply[0..n]);
+                Ok(n)
+            }
+            (Step::Out(expected), DataPhase::Out(buf)) => {
+                assert_eq!(
+                    expected,
+                    buf,
+                    "FakeScsiTransport: data mismatch for {}",
+                    hex(cmd)
+                );
+                Ok(buf.len())
+            }
+            (Step::NoData, DataPhase::None) => Ok(0),
+            (step, data) => panic!(
+                "FakeScsiTransport: {} expected {step:?} got {data:?}",
+                hex(cmd)
+            ),
+        }))
+    }
+
+    fn last_status(&self) -> Option<ScsiStatus> {
+        self.last_status
+    }
+
+    fn max_transfer_bytes(&self) -> Option<usize> {
+        self.max_transfer_bytes
+    }
+
+    fn quirks(&self) -> ScsiQuirks {
+        self.quirks
+    }
+}
+
+/// The command block of the REQUEST SENSE issued by
+/// [`ScsiDevice`](crate::ScsiDevice) after a failure
+pub fn request_sense_cdb() -> [u8; 6] {
+    [3, 0, 0, 0, SENSE_BUFFER_SIZE as u8, 0]
+}
+
+/// Construct sense data with the given sense key, ASC and ASCQ
+pub fn sense(key: u8, asc: u8, ascq: u8) -> SenseData {
+    SenseData {
+        key,
+        asc,
+        ascq,
+        information: None,
+        sense_key_specific: None,
+    }
+}
+
+/// Encode sense data in fixed format, as a REQUEST SENSE reply
+///
+/// The information field, if any, is truncated to 32 bits; use
+/// [`descriptor_sense()`] for larger values.
+pub fn fixed_sense(sense: &SenseData) -> [u8; 18] {
+    let mut buf = [0u8; 18];
+    buf[0] = 0x70;
+    buf[2] = sense.key & 0xF;
+    if let Some(info) = sense.information {
+        buf[0] |= 0x80;
+        buf[3..7].copy_from_slice(&(info as u32).to_be_bytes());
+    }
+    buf[7] = 10;
+    buf[12] = sense.asc;
+    buf[13] = sense.ascq;
+    if let Some(sks) = sense.sense_key_specific {
+        buf[15..18].copy_from_slice(&sks);
+        buf[15] |= 0x80;
+    }
+    buf
+}
+
+/// Encode sense data in descriptor format, as a REQUEST SENSE reply
+///
+/// The information field, if any, is sent as an Information descriptor,
+/// and the sense-key-specific field as a Sense Key Specific descriptor.
+pub fn descriptor_sense(sense: &SenseData) -> Vec<u8> {
+    let mut buf =
+        vec![0x72, sense.key & 0xF, sense.asc, sense.ascq, 0, 0, 0, 0];
+    if let Some(info) = sense.information {
+        buf.extend_from_slice(&[0, 10, 0x80, 0]);
+        buf.extend_from_slice(&info.to_be_bytes());
+    }
+    if let Some(sks) = sense.sense_key_specific {
+        buf.extend_from_slice(&[2, 6, 0, 0, sks[0] | 0x80, sks[1], sks[2], 0]);
+    }
+    buf[7] = (buf.len() - 8) as u8;
+    buf
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let v: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!("[{}]", v.join(" "))
+}
+
+/// Underline the bytes which differ between two command blocks
+fn diff_marker(expected: &[u8], actual: &[u8]) -> String {
+    let n = expected.len().max(actual.len());
+    let marks: Vec<&str> = (0..n)
+        .map(|i| {
+            if expected.get(i) == actual.get(i) {
+                "  "
+            } else {
+                "^^"
+            }
+        })
+        .collect();
+    format!(" {}", marks.join(" "))
+}
+
+#[cfg(test)]
+#[path = "tests/testing.rs"]
+mod tests;
@@ -0,0 +1,154 @@
+use super::*;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+const DESCRIPTOR: [u8; 10] = [0x3C, 3, 0, 0, 0, 0, 0, 0, 4, 0];
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 3) as u8).collect()
+}
+
+#[test]
+fn test_read_buffer() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x3C, 2, 5, 0x01, 0x02, 0x03, 0, 0, 16, 0], &[1, 2, 3]);
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 16];
+    let rc = run(d.read_buffer(ReadBufferMode::Data, 5, 0x010203, &mut buf));
+    assert_eq!(rc, Ok(3));
+    assert_eq!(buf[0..3], [1, 2, 3]);
+}
+
+#[test]
+fn test_read_buffer_bad_offset() {
+    let mut d = ScsiDevice::new(FakeScsiTransport::new());
+    let mut buf = [0u8; 16];
+    let rc = run(d.read_buffer(ReadBufferMode::Echo, 0, 0x100_0000, &mut buf));
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_read_buffer_descriptor() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&DESCRIPTOR, &[9, 0x01, 0x00, 0x00])
+        .expect_in(&DESCRIPTOR, &[0xFF, 0, 0x10, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.read_buffer_descriptor(0)),
+        Ok(BufferDescriptor {
+            offset_alignment: Some(512),
+            capacity: 0x10000,
+        })
+    );
+    assert_eq!(
+        run(d.read_buffer_descriptor(0)),
+        Ok(BufferDescriptor {
+            offset_alignment: None,
+            capacity: 0x1000,
+        })
+    );
+}
+
+#[test]
+fn test_write_buffer() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&[0x3B, 0x0A, 0, 0, 0, 0, 0, 0, 4, 0], &[9, 8, 7, 6])
+        .expect_no_data(&[0x3B, 0x0F, 1, 0, 0, 0, 0, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    let rc = run(d.write_buffer(WriteBufferMode::Echo, 0, 0, &[9, 8, 7, 6]));
+    assert_eq!(rc, Ok(4));
+    let rc = run(d.write_buffer(WriteBufferMode::ActivateDeferred, 1, 0, &[]));
+    assert_eq!(rc, Ok(0));
+}
+
+#[test]
+fn test_download_microcode() {
+    let image = pattern(1200);
+    let mut fake = FakeScsiTransport::new();
+    fake.max_transfer_bytes = Some(1000);
+    fake.expect_in(&DESCRIPTOR, &[9, 0, 0x10, 0])
+        .expect_out(&[0x3B, 0x0E, 0, 0, 0, 0, 0, 2, 0, 0], &image[0..512])
+        .expect_out(&[0x3B, 0x0E, 0, 0, 2, 0, 0, 2, 0, 0], &image[512..1024])
+        .expect_out(&[0x3B, 0x0E, 0, 0, 4, 0, 0, 0, 176, 0], &image[1024..])
+        .expect_no_data(&[0x3B, 0x0F, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.download_microcode_overwriting_firmware(0, &image)),
+        Ok(())
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_download_microcode_no_descriptor() {
+    let image = pattern(100);
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&DESCRIPTOR, testing::sense(5, 0x24, 0))
+        .expect_out(&[0x3B, 0x0E, 0, 0, 0, 0, 0, 0, 100, 0], &image)
+        .expect_no_data(&[0x3B, 0x0F, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.download_microcode_overwriting_firmware(0, &image)),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_download_microcode_offset_zero_only() {
+    let image = pattern(300);
+    let mut fake = FakeScsiTransport::new();
+    fake.max_transfer_bytes = Some(256);
+    fake.expect_in(&DESCRIPTOR, &[0xFF, 0, 0x10, 0])
+        .expect_out(&[0x3B, 0x0E, 0, 0, 0, 0, 0, 1, 44, 0], &image)
+        .expect_no_data(&[0x3B, 0x0F, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.download_microcode_overwriting_firmware(0, &image)),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_download_microcode_too_large() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&DESCRIPTOR, &[9, 0, 0x02, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.download_microcode_overwriting_firmware(0, &pattern(1024))),
+        Err(Error::ProtocolError)
+    );
+}
+
+#[test]
+fn test_download_microcode_empty() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&DESCRIPTOR, &[9, 0, 0x02, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.download_microcode_overwriting_firmware(0, &[])),
+        Err(Error::ProtocolError)
+    );
+}
+
+#[test]
+fn test_download_microcode_fails() {
+    // A failure part-way through doesn't activate the partial image
+    let image = pattern(1024);
+    let mut fake = FakeScsiTransport::new();
+    fake.max_transfer_bytes = Some(512);
+    fake.expect_in(&DESCRIPTOR, &[9, 0, 0x10, 0])
+        .expect_out(&[0x3B, 0x0E, 0, 0, 0, 0, 0, 2, 0, 0], &image[0..512])
+        .expect_error(
+            &[0x3B, 0x0E, 0, 0, 2, 0, 0, 2, 0, 0],
+            Error::Transport(()),
+        );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.download_microcode_overwriting_firmware(0, &image)),
+        Err(Error::Transport(()))
+    );
+}
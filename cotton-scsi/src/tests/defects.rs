@@ -0,0 +1,260 @@
+use super::*;
+use crate::scsi_transport::ScsiError;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+/// Two defects in bytes-from-index format, and one whole-track defect
+const BFI_LIST: [u8; 24] = [
+    0x01, 0x23, 0x45, 2, 0x00, 0x00, 0x12, 0x34, // cyl 0x12345, head 2
+    0x00, 0x00, 0x07, 0, 0x00, 0x01, 0x00, 0x00, // cyl 7, head 0
+    0xAB, 0xCD, 0xEF, 5, 0xFF, 0xFF, 0xFF, 0xFF, // whole track
+];
+
+fn reply_10(byte1: u8, list: &[u8]) -> Vec<u8> {
+    let mut v = vec![0, byte1];
+    v.extend_from_slice(&(list.len() as u16).to_be_bytes());
+    v.extend_from_slice(list);
+    v
+}
+
+fn reply_12(byte1: u8, list: &[u8]) -> Vec<u8> {
+    let mut v = vec![0, byte1, 0, 0];
+    v.extend_from_slice(&(list.len() as u32).to_be_bytes());
+    v.extend_from_slice(list);
+    v
+}
+
+#[test]
+fn test_cdb_10() {
+    let cdb = ReadDefectData10::new(
+        DefectLists::Grown,
+        DefectFormat::BytesFromIndex,
+        0x1234,
+    );
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0x37, 0, 0x0C, 0, 0, 0, 0, 0x12, 0x34, 0]
+    );
+    let cdb =
+        ReadDefectData10::new(DefectLists::Both, DefectFormat::LongBlock, 4);
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0x37, 0, 0x1B, 0, 0, 0, 0, 0, 4, 0]
+    );
+}
+
+#[test]
+fn test_cdb_12() {
+    let cdb = ReadDefectData12::new(
+        DefectLists::Primary,
+        DefectFormat::PhysicalSector,
+        0x0102_0304,
+    );
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0xB7, 0x15, 0, 0, 0, 0, 1, 2, 3, 4, 0, 0]
+    );
+    let cdb = ReadDefectData12::new(
+        DefectLists::HeaderOnly,
+        DefectFormat::ShortBlock,
+        8,
+    );
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0xB7, 0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0]
+    );
+}
+
+#[test]
+fn test_parse_bytes_from_index() {
+    let reply = reply_10(0x0C, &BFI_LIST);
+    let data = DefectData::parse_10(&reply).unwrap();
+    assert!(!data.primary_valid);
+    assert!(data.grown_valid);
+    assert_eq!(data.format, Some(DefectFormat::BytesFromIndex));
+    assert_eq!(data.list_length, 24);
+    assert_eq!(data.total(), Some(3));
+    assert!(data.is_complete());
+    let defects = data.iter().collect::<Vec<_>>();
+    assert_eq!(
+        defects,
+        [
+            Defect::BytesFromIndex {
+                cylinder: 0x12345,
+                head: 2,
+                bytes_from_index: 0x1234,
+            },
+            Defect::BytesFromIndex {
+                cylinder: 7,
+                head: 0,
+                bytes_from_index: 0x10000,
+            },
+            Defect::BytesFromIndex {
+                cylinder: 0xABCDEF,
+                head: 5,
+                bytes_from_index: 0xFFFF_FFFF,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_physical_sector() {
+    let reply = reply_12(0x1D, &BFI_LIST[0..16]);
+    let data = DefectData::parse_12(&reply).unwrap();
+    assert!(data.primary_valid);
+    assert!(data.grown_valid);
+    assert_eq!(data.format, Some(DefectFormat::PhysicalSector));
+    assert_eq!(data.list_length, 16);
+    let defects = data.iter().collect::<Vec<_>>();
+    assert_eq!(
+        defects,
+        [
+            Defect::PhysicalSector {
+                cylinder: 0x12345,
+                head: 2,
+                sector: 0x1234,
+            },
+            Defect::PhysicalSector {
+                cylinder: 7,
+                head: 0,
+                sector: 0x10000,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_block_formats() {
+    let reply = reply_10(0x08, &[0, 0, 1, 0, 0xFF, 0xFF, 0xFF, 0xFE]);
+    let data = DefectData::parse_10(&reply).unwrap();
+    assert_eq!(data.format, Some(DefectFormat::ShortBlock));
+    assert_eq!(
+        data.iter().collect::<Vec<_>>(),
+        [Defect::Block(0x100), Defect::Block(0xFFFF_FFFE)]
+    );
+
+    let reply = reply_10(0x0B, &[0, 0, 0, 1, 0, 0, 0, 2]);
+    let data = DefectData::parse_10(&reply).unwrap();
+    assert_eq!(
+        data.iter().collect::<Vec<_>>(),
+        [Defect::Block(0x1_0000_0002)]
+    );
+}
+
+#[test]
+fn test_parse_truncated() {
+    // Device says 24 bytes of list, but only 20 arrived
+    let reply = reply_10(0x0C, &BFI_LIST);
+    let data = DefectData::parse_10(&reply[0..24]).unwrap();
+    assert_eq!(data.total(), Some(3));
+    assert!(!data.is_complete());
+    assert_eq!(data.iter().count(), 2);
+}
+
+#[test]
+fn test_parse_vendor_specific() {
+    let reply = reply_10(0x0E, &BFI_LIST);
+    let data = DefectData::parse_10(&reply).unwrap();
+    assert_eq!(data.format, Some(DefectFormat::VendorSpecific));
+    assert_eq!(data.total(), None);
+    assert_eq!(data.iter().next(), None);
+
+    let reply = reply_10(0x0F, &BFI_LIST);
+    let data = DefectData::parse_10(&reply).unwrap();
+    assert_eq!(data.format, None);
+    assert_eq!(data.iter().next(), None);
+}
+
+#[test]
+fn test_parse_short() {
+    assert_eq!(DefectData::parse_10(&[0, 0x0C, 0]), None);
+    assert_eq!(DefectData::parse_12(&[0, 0x0C, 0, 0, 0, 0, 0]), None);
+    assert!(DefectData::parse_12(&[0, 0x0C, 0, 0, 0, 0, 0, 0]).is_some());
+}
+
+#[test]
+fn test_read_defect_data() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &[0x37, 0, 0x0C, 0, 0, 0, 0, 0, 64, 0],
+        &reply_10(0x0C, &BFI_LIST),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 64];
+    let data = run(d.read_defect_data(
+        DefectLists::Grown,
+        DefectFormat::BytesFromIndex,
+        &mut buf,
+    ))
+    .unwrap();
+    assert_eq!(data.total(), Some(3));
+    assert_eq!(data.iter().count(), 3);
+}
+
+#[test]
+fn test_read_defect_data_12() {
+    let mut fake = FakeScsiTransport::new();
+    fake.max_transfer_bytes = Some(0x20000);
+    fake.expect_in(
+        &[0xB7, 0x15, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0],
+        &reply_12(0x15, &BFI_LIST[0..8]),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = vec![0u8; 0x10000];
+    let data = run(d.read_defect_data(
+        DefectLists::Primary,
+        DefectFormat::PhysicalSector,
+        &mut buf,
+    ))
+    .unwrap();
+    assert!(data.primary_valid);
+    assert!(!data.grown_valid);
+    assert_eq!(data.iter().count(), 1);
+}
+
+#[test]
+fn test_read_defect_data_short_reply() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x37, 0, 0x08, 0, 0, 0, 0, 0, 4, 0], &[0, 0x08]);
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 4];
+    let rc = run(d.read_defect_data(
+        DefectLists::Grown,
+        DefectFormat::ShortBlock,
+        &mut buf,
+    ));
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_read_defect_data_unsupported() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(
+        &[0x37, 0, 0x0C, 0, 0, 0, 0, 0, 16, 0],
+        testing::sense(5, 0x20, 0),
+    )
+    .expect_check_condition(
+        &[0x37, 0, 0x0C, 0, 0, 0, 0, 0, 16, 0],
+        testing::sense(5, 0, 0),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 16];
+    let rc = run(d.read_defect_data(
+        DefectLists::Grown,
+        DefectFormat::BytesFromIndex,
+        &mut buf,
+    ));
+    assert_eq!(rc, Err(Error::Scsi(ScsiError::InvalidCommandOperationCode)));
+    let rc = run(d.read_defect_data(
+        DefectLists::Grown,
+        DefectFormat::BytesFromIndex,
+        &mut buf,
+    ));
+    assert_eq!(rc, Err(Error::Scsi(ScsiError::IllegalRequest)));
+    assert!(d.transport().is_done());
+}
@@ -0,0 +1,358 @@
+use super::*;
+use crate::image_transport::ImageTransport;
+use crate::testing::FakeScsiTransport;
+use crate::{Error, ScsiBlockDevice, ScsiDevice};
+use futures::FutureExt;
+
+const BLOCKS: u64 = 200;
+const LINUX: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69,
+    0xD8, 0x47, 0x7D, 0xE4,
+];
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+fn device(image: Vec<u8>) -> ScsiBlockDevice<ImageTransport> {
+    ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::from_vec(image, 512)))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+struct Layout {
+    num_entries: u32,
+    entry_size: u32,
+}
+
+const STANDARD: Layout = Layout {
+    num_entries: 128,
+    entry_size: 128,
+};
+
+fn encode_entry(
+    layout: &Layout,
+    type_guid: [u8; 16],
+    first: u64,
+    last: u64,
+    name: &str,
+) -> Vec<u8> {
+    let mut e = vec![0u8; layout.entry_size as usize];
+    e[0..16].copy_from_slice(&type_guid);
+    e[16..32].copy_from_slice(&[first as u8; 16]);
+    e[32..40].copy_from_slice(&first.to_le_bytes());
+    e[40..48].copy_from_slice(&last.to_le_bytes());
+    e[48..56].copy_from_slice(&(1u64 << 60).to_le_bytes());
+    for (i, u) in name.encode_utf16().enumerate() {
+        e[56 + i * 2..58 + i * 2].copy_from_slice(&u.to_le_bytes());
+    }
+    // Reserved area of larger entries, which must be ignored
+    e[128..].fill(0x5A);
+    e
+}
+
+fn encode_header(
+    layout: &Layout,
+    my_lba: u64,
+    alternate: u64,
+    entries_lba: u64,
+    entries_crc: u32,
+) -> [u8; 512] {
+    let mut h = [0u8; 512];
+    h[0..8].copy_from_slice(b"EFI PART");
+    h[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+    h[12..16].copy_from_slice(&92u32.to_le_bytes());
+    h[24..32].copy_from_slice(&my_lba.to_le_bytes());
+    h[32..40].copy_from_slice(&alternate.to_le_bytes());
+    h[40..48].copy_from_slice(&34u64.to_le_bytes());
+    h[48..56].copy_from_slice(&(BLOCKS - 34).to_le_bytes());
+    h[56..72].copy_from_slice(&[0x77; 16]);
+    h[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    h[80..84].copy_from_slice(&layout.num_entries.to_le_bytes());
+    h[84..88].copy_from_slice(&layout.entry_size.to_le_bytes());
+    h[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&h[0..92]);
+    h[16..20].copy_from_slice(&crc.to_le_bytes());
+    h
+}
+
+/// A disk with primary and backup GPTs and three partitions
+fn gpt_image(layout: &Layout) -> Vec<u8> {
+    let mut entries =
+        vec![0u8; (layout.num_entries * layout.entry_size) as usize];
+    let parts = [
+        (LINUX, 34, 99, "root"),
+        ([0x11; 16], 100, 149, "Ünïcödé ☃"),
+        (LINUX, 150, 159, "012345678901234567890123456789012345"),
+    ];
+    for (i, (guid, first, last, name)) in parts.iter().enumerate() {
+        // Leave an unused entry in between
+        let slot = if i == 0 { 0 } else { i + 1 };
+        let e = encode_entry(layout, *guid, *first, *last, name);
+        entries[slot * e.len()..(slot + 1) * e.len()].copy_from_slice(&e);
+    }
+    let crc = crc32(&entries);
+    let array_blocks = entries.len().div_ceil(512) as u64;
+
+    let mut image = vec![0u8; 512 * BLOCKS as usize];
+    let backup_entries = BLOCKS - 1 - array_blocks;
+    image[512..1024].copy_from_slice(&encode_header(
+        layout,
+        1,
+        BLOCKS - 1,
+        2,
+        crc,
+    ));
+    image[1024..1024 + entries.len()].copy_from_slice(&entries);
+    let at = backup_entries as usize * 512;
+    image[at..at + entries.len()].copy_from_slice(&entries);
+    let at = (BLOCKS - 1) as usize * 512;
+    image[at..].copy_from_slice(&encode_header(
+        layout,
+        BLOCKS - 1,
+        1,
+        backup_entries,
+        crc,
+    ));
+    image
+}
+
+fn check_standard(out: &[GptEntry], n: usize) {
+    assert_eq!(n, 3);
+    assert_eq!(out[0].type_guid, LINUX);
+    assert_eq!(out[0].unique_guid, [34; 16]);
+    assert_eq!(out[0].first_lba, 34);
+    assert_eq!(out[0].last_lba, 99);
+    assert_eq!(out[0].blocks(), 66);
+    assert_eq!(out[0].attributes, 1 << 60);
+    let mut buf = [0u8; 108];
+    assert_eq!(out[0].name(&mut buf), "root");
+    assert_eq!(out[1].type_guid, [0x11; 16]);
+    assert_eq!(out[1].first_lba, 100);
+    assert_eq!(out[1].name(&mut buf), "Ünïcödé ☃");
+    assert_eq!(out[2].first_lba, 150);
+    assert_eq!(
+        out[2].name(&mut buf),
+        "012345678901234567890123456789012345"
+    );
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_primary() {
+    let mut d = device(gpt_image(&STANDARD));
+    let mut out = [GptEntry::default(); 8];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    check_standard(&out, n);
+}
+
+#[test]
+fn test_output_full() {
+    let mut d = device(gpt_image(&STANDARD));
+    let mut out = [GptEntry::default(); 2];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(2));
+    assert_eq!(out[1].first_lba, 100);
+}
+
+#[test]
+fn test_large_entries() {
+    let layout = Layout {
+        num_entries: 32,
+        entry_size: 256,
+    };
+    let mut d = device(gpt_image(&layout));
+    let mut out = [GptEntry::default(); 8];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    check_standard(&out, n);
+}
+
+#[test]
+fn test_entries_larger_than_block() {
+    let layout = Layout {
+        num_entries: 8,
+        entry_size: 1024,
+    };
+    let mut d = device(gpt_image(&layout));
+    let mut out = [GptEntry::default(); 8];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    check_standard(&out, n);
+}
+
+#[test]
+fn test_bad_entry_size() {
+    for entry_size in [0, 64, 192] {
+        let layout = Layout {
+            num_entries: 128,
+            entry_size,
+        };
+        let mut image = gpt_image(&STANDARD);
+        let h = encode_header(&layout, 1, BLOCKS - 1, 2, 0);
+        image[512..1024].copy_from_slice(&h);
+        let h = encode_header(&layout, BLOCKS - 1, 1, 2, 0);
+        image[512 * (BLOCKS as usize - 1)..].copy_from_slice(&h);
+        let mut d = device(image);
+        let mut out = [GptEntry::default(); 8];
+        assert_eq!(
+            run(read_partitions(&mut d, &mut out)),
+            Err(GptError::BadHeader)
+        );
+    }
+}
+
+#[test]
+fn test_corrupt_primary_header() {
+    let mut image = gpt_image(&STANDARD);
+    image[512 + 40] ^= 1;
+    // Also wreck the primary entries, to show they aren't used
+    image[1024..1024 + 512].fill(0xFF);
+    let mut d = device(image);
+    let mut out = [GptEntry::default(); 8];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    check_standard(&out, n);
+}
+
+#[test]
+fn test_corrupt_primary_entries() {
+    let mut image = gpt_image(&STANDARD);
+    // The unused second entry
+    image[1024 + 128] = 1;
+    let mut d = device(image);
+    let mut out = [GptEntry::default(); 8];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    check_standard(&out, n);
+}
+
+#[test]
+fn test_missing_primary() {
+    let mut image = gpt_image(&STANDARD);
+    image[512..1024].fill(0);
+    let mut d = device(image);
+    let mut out = [GptEntry::default(); 8];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    check_standard(&out, n);
+}
+
+#[test]
+fn test_both_corrupt() {
+    let mut image = gpt_image(&STANDARD);
+    image[512 + 40] ^= 1;
+    image[512 * (BLOCKS as usize - 1)] = 0;
+    let mut d = device(image);
+    let mut out = [GptEntry::default(); 8];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(GptError::BadChecksum)
+    );
+}
+
+#[test]
+fn test_no_gpt() {
+    let mut d = device(vec![0u8; 512 * 100]);
+    let mut out = [GptEntry::default(); 8];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(GptError::NoPartitionTable)
+    );
+}
+
+#[test]
+fn test_header_wrong_lba() {
+    // A valid header, but claiming to be somewhere else
+    let mut image = gpt_image(&STANDARD);
+    let crc = le32(&image[512 + 88..]);
+    let h = encode_header(&STANDARD, 5, BLOCKS - 1, 2, crc);
+    image[512..1024].copy_from_slice(&h);
+    image[512 * (BLOCKS as usize - 1)] = 0;
+    let mut d = device(image);
+    let mut out = [GptEntry::default(); 8];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(GptError::BadHeader)
+    );
+}
+
+#[test]
+fn test_entries_beyond_end() {
+    let mut image = gpt_image(&STANDARD);
+    let h = encode_header(&STANDARD, 1, BLOCKS - 1, BLOCKS - 10, 0);
+    image[512..1024].copy_from_slice(&h);
+    image[512 * (BLOCKS as usize - 1)] = 0;
+    let mut d = device(image);
+    let mut out = [GptEntry::default(); 8];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(GptError::BadHeader)
+    );
+}
+
+#[test]
+fn test_unsupported_block_size() {
+    let mut d =
+        ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(8192, 16)));
+    let mut out = [GptEntry::default(); 8];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(GptError::UnsupportedBlockSize(8192))
+    );
+}
+
+#[test]
+fn test_read_error() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[0, 0, 0, 99, 0, 0, 2, 0],
+    )
+    .expect_error(&[0x28, 0, 0, 0, 0, 1, 0, 0, 1, 0], Error::Transport(()));
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(fake));
+    let mut out = [GptEntry::default(); 8];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(GptError::Device(Error::Transport(())))
+    );
+}
+
+#[test]
+fn test_name_truncated() {
+    let mut e = GptEntry::default();
+    for (u, c) in e.name_utf16.iter_mut().zip("a☃b".encode_utf16()) {
+        *u = c;
+    }
+    let mut buf = [0u8; 3];
+    assert_eq!(e.name(&mut buf), "a");
+    let mut buf = [0u8; 4];
+    assert_eq!(e.name(&mut buf), "a☃");
+}
+
+#[test]
+fn test_name_invalid() {
+    let mut e = GptEntry::default();
+    e.name_utf16[0] = 0xD800;
+    e.name_utf16[1] = b'x' as u16;
+    let mut buf = [0u8; 8];
+    assert_eq!(e.name(&mut buf), "\u{FFFD}x");
+}
+
+#[test]
+fn test_blocks() {
+    let mut e = GptEntry {
+        first_lba: 34,
+        last_lba: 99,
+        ..GptEntry::default()
+    };
+    assert_eq!(e.blocks(), 66);
+    e.last_lba = 33;
+    assert_eq!(e.blocks(), 0);
+    // Found by fuzzing: used to overflow
+    e.first_lba = 0;
+    e.last_lba = u64::MAX;
+    assert_eq!(e.blocks(), u64::MAX);
+}
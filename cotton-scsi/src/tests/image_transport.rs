@@ -0,0 +1,207 @@
+use super::*;
+use crate::scsi_device::ScsiDevice;
+use crate::scsi_transport::{ScsiError, TransferError};
+use crate::PeripheralType;
+use futures::FutureExt;
+
+fn run<F: Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 512) as u8).collect()
+}
+
+#[test]
+fn test_probe() {
+    let mut d = ScsiDevice::new(ImageTransport::new(512, 10_000));
+    let caps = run(d.probe()).unwrap();
+    assert_eq!(caps.blocks, 10_000);
+    assert_eq!(caps.block_size, 512);
+    assert!(caps.supports_16);
+    assert_eq!(caps.write_protected, Some(false));
+    assert_eq!(caps.physical_block_size, 512);
+    assert_eq!(caps.vendor(), "cotton");
+    assert_eq!(caps.product(), "ImageTransport");
+    assert_eq!(caps.inquiry.peripheral_type, PeripheralType::Disk);
+    assert!(!caps.is_removable());
+}
+
+#[test]
+fn test_read_write() {
+    let mut d = ScsiDevice::new(ImageTransport::new(512, 1000));
+    let data = pattern(512 * 300);
+    run(d.write_blocks(100, 300, &data)).unwrap();
+    let mut buf = vec![0u8; 512 * 300];
+    run(d.read_blocks(100, 300, &mut buf)).unwrap();
+    assert_eq!(buf, data);
+
+    let image = d.transport().data().unwrap();
+    assert_eq!(image[0..512], [0u8; 512]);
+    assert_eq!(image[512 * 100..512 * 400], data);
+}
+
+#[test]
+fn test_read_write_16() {
+    let mut d = ScsiDevice::new(ImageTransport::new(4096, 8));
+    let data = pattern(4096 * 2);
+    assert_eq!(run(d.write_16(3, 2, &data)), Ok(4096 * 2));
+    let mut buf = vec![0u8; 4096 * 2];
+    assert_eq!(run(d.read_16(3, 2, &mut buf)), Ok(4096 * 2));
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn test_read_capacity_16() {
+    let mut d = ScsiDevice::new(ImageTransport::new(4096, 8));
+    assert_eq!(run(d.read_capacity_16()), Ok((7, 4096)));
+}
+
+#[test]
+fn test_physical_block_size() {
+    let mut t = ImageTransport::new(512, 64);
+    t.set_physical_block_exponent(3);
+    let mut d = ScsiDevice::new(t);
+    let caps = run(d.probe()).unwrap();
+    assert_eq!(caps.block_size, 512);
+    assert_eq!(caps.physical_block_size, 4096);
+}
+
+#[test]
+fn test_out_of_range() {
+    let mut d = ScsiDevice::new(ImageTransport::new(512, 100));
+    let mut buf = vec![0u8; 512 * 2];
+    assert_eq!(
+        run(d.read_10(99, 2, &mut buf)),
+        Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+    );
+    assert_eq!(
+        run(d.write_16(u64::MAX, 2, &buf)),
+        Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+    );
+    // Sense is cleared once reported
+    assert_eq!(run(d.test_unit_ready()), Ok(()));
+}
+
+#[test]
+fn test_unknown_command() {
+    let mut d = ScsiDevice::new(ImageTransport::new(512, 100));
+    assert_eq!(
+        run(d.command_no_data([0x04u8, 0, 0, 0, 0, 0])),
+        Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+    );
+    assert_eq!(
+        run(d.command_no_data([0xC0u8; 12])),
+        Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+    );
+}
+
+#[test]
+fn test_wrong_direction() {
+    let mut t = ImageTransport::new(512, 100);
+    let rc =
+        run(t.command(&[0x28, 0, 0, 0, 0, 0, 0, 0, 1, 0], DataPhase::None));
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_short_cdb() {
+    let mut t = ImageTransport::new(512, 100);
+    let rc = run(t.command(&[0x28, 0, 0], DataPhase::None));
+    assert_eq!(rc, Err(Error::ProtocolError));
+    let rc = run(t.command(&[], DataPhase::None));
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_buffer_too_small() {
+    let mut d = ScsiDevice::new(ImageTransport::new(512, 100));
+    let mut buf = [0u8; 100];
+    assert_eq!(run(d.read_10(0, 1, &mut buf)), Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_vpd_unsupported() {
+    let mut d = ScsiDevice::new(ImageTransport::new(512, 100));
+    assert_eq!(
+        run(d.block_limits_page()),
+        Err(Error::Scsi(ScsiError::InvalidFieldInCDB))
+    );
+}
+
+#[test]
+fn test_write_protected() {
+    let mut t = ImageTransport::new(512, 100);
+    t.set_write_protected(true);
+    let mut d = ScsiDevice::new(t);
+    let caps = run(d.probe()).unwrap();
+    assert_eq!(caps.write_protected, Some(true));
+    assert_eq!(
+        run(d.write_10(0, 1, &[0u8; 512])),
+        Err(Error::Scsi(ScsiError::WriteProtected))
+    );
+}
+
+#[test]
+fn test_fault_injection() {
+    let mut t = ImageTransport::new(512, 100);
+    t.fail_every_nth_write(Some(3));
+    t.max_transfer_bytes = Some(512);
+    let mut d = ScsiDevice::new(t);
+    let data = pattern(512 * 10);
+    // One block per command: the third command fails
+    let rc = run(d.write_blocks(20, 10, &data));
+    assert_eq!(
+        rc,
+        Err(TransferError {
+            blocks_done: 2,
+            error: Error::Scsi(ScsiError::WriteError),
+        })
+    );
+    assert_eq!(d.last_sense().unwrap().information, Some(22));
+    assert_eq!(run(d.write_10(0, 1, &data[0..512])), Ok(512));
+    assert_eq!(run(d.write_10(0, 1, &data[0..512])), Ok(512));
+    assert!(run(d.write_10(0, 1, &data[0..512])).is_err());
+}
+
+#[test]
+fn test_file() {
+    let path = std::env::temp_dir()
+        .join(format!("cotton-scsi-image-{}", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.set_len(512 * 64 + 100).unwrap();
+    let mut d = ScsiDevice::new(ImageTransport::from_file(file, 512).unwrap());
+    assert_eq!(run(d.read_capacity()), Ok((64, 512)));
+    assert!(d.transport().data().is_none());
+
+    let data = pattern(512 * 4);
+    run(d.write_blocks(60, 4, &data)).unwrap();
+    run(d.command_no_data([0x35u8, 0, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+    let mut buf = vec![0u8; 512 * 4];
+    run(d.read_blocks(60, 4, &mut buf)).unwrap();
+    assert_eq!(buf, data);
+    drop(d);
+
+    let contents = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(contents[512 * 60..512 * 64], data);
+}
+
+#[test]
+fn test_mode_sense_10() {
+    let mut t = ImageTransport::new(512, 100);
+    t.set_write_protected(true);
+    let mut buf = [0u8; 8];
+    let rc = run(t.command(
+        &[0x5A, 8, 0x3F, 0, 0, 0, 0, 0, 8, 0],
+        DataPhase::In(&mut buf),
+    ));
+    assert_eq!(rc, Ok(8));
+    assert_eq!(buf, [0, 6, 0, 0x80, 0, 0, 0, 0]);
+}
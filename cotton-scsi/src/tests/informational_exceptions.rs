@@ -0,0 +1,190 @@
+use super::*;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+const MODE_SENSE: [u8; 10] = [0x5A, 0x08, 0x1C, 0, 0, 0, 0, 0, 128, 0];
+
+const TEST_UNIT_READY: [u8; 6] = [0; 6];
+
+/// Saveable; DEXCPT set, MRIE 3; check hourly; report once
+const PAGE: [u8; 12] = [0x9C, 0x0A, 0x08, 0x03, 0, 0, 0x8C, 0xA0, 0, 0, 0, 1];
+
+fn mode_sense_reply(page: &[u8]) -> Vec<u8> {
+    let mut v = vec![0, 6 + page.len() as u8, 0, 0, 0, 0, 0, 0];
+    v.extend_from_slice(page);
+    v
+}
+
+fn mode_select_cdb(save: bool) -> [u8; 10] {
+    [0x55, 0x10 | save as u8, 0, 0, 0, 0, 0, 0, 20, 0]
+}
+
+fn mode_select_data(page: &[u8]) -> Vec<u8> {
+    let mut v = vec![0u8; 8];
+    v.extend_from_slice(page);
+    v
+}
+
+#[test]
+fn test_parse() {
+    let page = InformationalExceptionsPage::parse(&PAGE).unwrap();
+    assert!(page.saveable);
+    assert!(!page.performance);
+    assert!(!page.enable_warnings);
+    assert!(page.disable_exceptions);
+    assert!(!page.test);
+    assert!(!page.log_errors);
+    assert_eq!(
+        page.reporting_method,
+        ReportingMethod::ConditionalRecoveredError
+    );
+    assert_eq!(page.interval_timer_100ms, 36000);
+    assert_eq!(page.report_count, 1);
+}
+
+#[test]
+fn test_parse_bad() {
+    assert_eq!(InformationalExceptionsPage::parse(&PAGE[0..11]), None);
+    let mut short = PAGE;
+    short[1] = 6;
+    assert_eq!(InformationalExceptionsPage::parse(&short), None);
+    let mut other = PAGE;
+    other[0] = 0x1A;
+    assert_eq!(InformationalExceptionsPage::parse(&other), None);
+}
+
+#[test]
+fn test_round_trip() {
+    let mut expected = PAGE;
+    expected[0] = 0x1C; // PS cleared
+    let page = InformationalExceptionsPage::parse(&PAGE).unwrap();
+    assert_eq!(page.to_bytes(), expected);
+
+    let all = [0x1C, 0x0A, 0x95, 0x0F, 1, 2, 3, 4, 5, 6, 7, 8];
+    let page = InformationalExceptionsPage::parse(&all).unwrap();
+    assert!(page.performance);
+    assert!(page.enable_warnings);
+    assert!(page.test);
+    assert!(page.log_errors);
+    assert_eq!(page.reporting_method, ReportingMethod::Other(0xF));
+    assert_eq!(page.to_bytes(), all);
+}
+
+#[test]
+fn test_reporting_methods() {
+    for code in 0..16 {
+        let mut bytes = PAGE;
+        bytes[0] = 0x1C;
+        bytes[3] = code;
+        let page = InformationalExceptionsPage::parse(&bytes).unwrap();
+        assert_eq!(page.to_bytes(), bytes);
+    }
+}
+
+#[test]
+fn test_enable_failure_prediction() {
+    let mut page = PAGE;
+    page[2] = 0x0C; // TEST too
+    let expected = [0x1C, 0x0A, 0x00, 0x06, 0, 0, 0x8C, 0xA0, 0, 0, 0, 1];
+
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&MODE_SENSE, &mode_sense_reply(&page))
+        .expect_out(&mode_select_cdb(true), &mode_select_data(&expected));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.enable_failure_prediction(true)), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_informational_exceptions_page_saved() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &[0x5A, 0x08, 0xDC, 0, 0, 0, 0, 0, 128, 0],
+        &mode_sense_reply(&PAGE),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let page = run(d.informational_exceptions_page(PageControl::Saved));
+    assert_eq!(page, Ok(InformationalExceptionsPage::parse(&PAGE).unwrap()));
+}
+
+#[test]
+fn test_set_informational_exceptions_page() {
+    let mut page = InformationalExceptionsPage::parse(&PAGE).unwrap();
+    page.interval_timer_100ms = 600;
+    page.report_count = 0;
+    let expected = [0x1C, 0x0A, 0x08, 0x03, 0, 0, 0x02, 0x58, 0, 0, 0, 0];
+
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&mode_select_cdb(false), &mode_select_data(&expected));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.set_informational_exceptions_page(&page, false)),
+        Ok(())
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_check_on_request() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0x5D, 0x12)),
+    );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.check_informational_exception()),
+        Err(Error::Scsi(ScsiError::FailurePredicted(0x12)))
+    );
+    assert!(d.transport().is_done());
+    // It wasn't a failed command
+    assert_eq!(d.last_sense(), None);
+}
+
+#[test]
+fn test_check_unit_attention() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0, 0)),
+    )
+    .expect_check_condition(&TEST_UNIT_READY, testing::sense(6, 0x5D, 0xFF));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.check_informational_exception()),
+        Err(Error::Scsi(ScsiError::FailurePredicted(0xFF)))
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_check_nothing_to_report() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0, 0)),
+    )
+    .expect_no_data(&TEST_UNIT_READY);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.check_informational_exception()), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_check_not_ready() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0, 0)),
+    )
+    .expect_check_condition(&TEST_UNIT_READY, testing::sense(2, 0x3A, 0));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.check_informational_exception()),
+        Err(Error::Scsi(ScsiError::MediaNotPresent))
+    );
+}
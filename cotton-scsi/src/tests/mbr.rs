@@ -0,0 +1,233 @@
+use super::*;
+use crate::image_transport::ImageTransport;
+use crate::testing::FakeScsiTransport;
+use crate::{Error, ScsiBlockDevice, ScsiDevice};
+use futures::FutureExt;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+/// Write one partition table entry into the sector at `lba`
+fn set_entry(
+    image: &mut [u8],
+    lba: u64,
+    slot: usize,
+    status: u8,
+    partition_type: u8,
+    start: u32,
+    blocks: u32,
+) {
+    let sector = &mut image[lba as usize * 512..][..512];
+    let e = &mut sector[446 + slot * 16..][..16];
+    e[0] = status;
+    e[4] = partition_type;
+    e[8..12].copy_from_slice(&start.to_le_bytes());
+    e[12..16].copy_from_slice(&blocks.to_le_bytes());
+    sector[510] = 0x55;
+    sector[511] = 0xAA;
+}
+
+fn device(image: Vec<u8>) -> ScsiBlockDevice<ImageTransport> {
+    ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::from_vec(image, 512)))
+}
+
+const fn entry(
+    partition_type: u8,
+    logical: bool,
+    start: u64,
+    blocks: u64,
+) -> PartitionEntry {
+    PartitionEntry {
+        partition_type,
+        bootable: false,
+        logical,
+        start,
+        blocks,
+    }
+}
+
+/// Two primaries and an extended partition containing three logicals
+fn extended_image() -> Vec<u8> {
+    let mut image = vec![0u8; 512 * 1000];
+    set_entry(&mut image, 0, 0, 0x80, 0x0C, 1, 99);
+    set_entry(&mut image, 0, 1, 0, 0x83, 100, 100);
+    set_entry(&mut image, 0, 3, 0, 0x0F, 500, 500);
+    // EBR chain at 500, 600, 800; logicals start one block after each
+    set_entry(&mut image, 500, 0, 0, 0x07, 1, 49);
+    set_entry(&mut image, 500, 1, 0, 0x05, 100, 200);
+    set_entry(&mut image, 600, 0, 0, 0x83, 1, 199);
+    set_entry(&mut image, 600, 1, 0, 0x05, 300, 200);
+    set_entry(&mut image, 800, 0, 0, 0x82, 1, 199);
+    image
+}
+
+#[test]
+fn test_primary() {
+    let mut image = vec![0u8; 512 * 100];
+    set_entry(&mut image, 0, 0, 0x80, 0x0C, 2048, 10_000);
+    set_entry(&mut image, 0, 2, 0, 0x83, 12_048, 5_000);
+    let mut d = device(image);
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(2));
+    assert_eq!(
+        out[0],
+        PartitionEntry {
+            bootable: true,
+            ..entry(0x0C, false, 2048, 10_000)
+        }
+    );
+    assert_eq!(out[1], entry(0x83, false, 12_048, 5_000));
+    assert!(!out[0].is_protective());
+}
+
+#[test]
+fn test_extended_chain() {
+    let mut d = device(extended_image());
+    let mut out = [PartitionEntry::default(); 8];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(5));
+    assert_eq!(out[0].partition_type, 0x0C);
+    assert!(out[0].bootable);
+    assert_eq!(out[1], entry(0x83, false, 100, 100));
+    assert_eq!(out[2], entry(0x07, true, 501, 49));
+    assert_eq!(out[3], entry(0x83, true, 601, 199));
+    assert_eq!(out[4], entry(0x82, true, 801, 199));
+}
+
+#[test]
+fn test_output_full() {
+    let mut d = device(extended_image());
+    let mut out = [PartitionEntry::default(); 3];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(3));
+    assert_eq!(out[2], entry(0x07, true, 501, 49));
+
+    let mut out = [PartitionEntry::default(); 1];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(1));
+    assert_eq!(out[0].partition_type, 0x0C);
+}
+
+#[test]
+fn test_circular_chain() {
+    let mut image = vec![0u8; 512 * 100];
+    set_entry(&mut image, 0, 0, 0, 0x05, 10, 90);
+    set_entry(&mut image, 10, 0, 0, 0x83, 1, 9);
+    // Points back to itself
+    set_entry(&mut image, 10, 1, 0, 0x05, 0, 10);
+    let mut d = device(image.clone());
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(1));
+
+    // A loop not involving the first EBR: 10 -> 20 -> 20
+    set_entry(&mut image, 10, 1, 0, 0x05, 10, 10);
+    set_entry(&mut image, 20, 0, 0, 0x83, 1, 9);
+    set_entry(&mut image, 20, 1, 0, 0x05, 10, 10);
+    let mut d = device(image);
+    let mut out = [PartitionEntry::default(); 200];
+    let n = run(read_partitions(&mut d, &mut out)).unwrap();
+    assert_eq!(n, MAX_LOGICAL_PARTITIONS);
+    assert_eq!(out[0], entry(0x83, true, 11, 9));
+    assert_eq!(out[1], entry(0x83, true, 21, 9));
+    assert_eq!(out[n - 1], entry(0x83, true, 21, 9));
+}
+
+#[test]
+fn test_broken_chain() {
+    let mut image = extended_image();
+    // Remove the signature of the second EBR
+    image[600 * 512 + 510] = 0;
+    let mut d = device(image);
+    let mut out = [PartitionEntry::default(); 8];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(3));
+}
+
+#[test]
+fn test_chain_beyond_end() {
+    let mut image = vec![0u8; 512 * 100];
+    set_entry(&mut image, 0, 0, 0, 0x05, 50, 500);
+    set_entry(&mut image, 50, 0, 0, 0x83, 1, 9);
+    set_entry(&mut image, 50, 1, 0, 0x05, 1000, 10);
+    let mut d = device(image);
+    let mut out = [PartitionEntry::default(); 8];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(1));
+    assert_eq!(out[0], entry(0x83, true, 51, 9));
+}
+
+#[test]
+fn test_protective() {
+    let mut image = vec![0u8; 512 * 100];
+    set_entry(&mut image, 0, 0, 0, PROTECTIVE, 1, 99);
+    let mut d = device(image);
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(1));
+    assert!(out[0].is_protective());
+}
+
+#[test]
+fn test_no_signature() {
+    let mut d = device(vec![0u8; 512 * 100]);
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(MbrError::NoPartitionTable)
+    );
+}
+
+#[test]
+fn test_empty_entries_skipped() {
+    let mut image = vec![0u8; 512 * 100];
+    // Type but no length, and length but no type
+    set_entry(&mut image, 0, 0, 0, 0x83, 1, 0);
+    set_entry(&mut image, 0, 1, 0, 0, 1, 10);
+    set_entry(&mut image, 0, 3, 0, 0x83, 20, 10);
+    let mut d = device(image);
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(1));
+    assert_eq!(out[0], entry(0x83, false, 20, 10));
+}
+
+#[test]
+fn test_4k_blocks() {
+    let mut image = vec![0u8; 4096 * 16];
+    set_entry(&mut image, 0, 0, 0, 0x0C, 1, 15);
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(
+        ImageTransport::from_vec(image, 4096),
+    ));
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(run(read_partitions(&mut d, &mut out)), Ok(1));
+    assert_eq!(out[0], entry(0x0C, false, 1, 15));
+}
+
+#[test]
+fn test_unsupported_block_size() {
+    let mut d =
+        ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(256, 16)));
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(MbrError::UnsupportedBlockSize(256))
+    );
+}
+
+#[test]
+fn test_read_error() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[0, 0, 0, 99, 0, 0, 2, 0],
+    )
+    .expect_error(&[0x28, 0, 0, 0, 0, 0, 0, 0, 1, 0], Error::Transport(()));
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(fake));
+    let mut out = [PartitionEntry::default(); 4];
+    assert_eq!(
+        run(read_partitions(&mut d, &mut out)),
+        Err(MbrError::Device(Error::Transport(())))
+    );
+}
+
+#[test]
+fn test_is_extended() {
+    for t in [0x05, 0x0F, 0x85] {
+        assert!(entry(t, false, 0, 1).is_extended());
+    }
+    assert!(!entry(0x83, false, 0, 1).is_extended());
+}
@@ -0,0 +1,158 @@
+use super::*;
+use crate::scsi_device::tests::{do_test, ContextExtras};
+use crate::scsi_transport::ScsiError;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
+
+#[rustfmt::skip]
+const TOC: [u8; 28] = [
+    0, 26, 1, 2,
+    0, 0x14, 1, 0, 0, 0, 0, 0,
+    0, 0x10, 2, 0, 0, 0, 0x12, 0x34,
+    0, 0x14, 0xAA, 0, 0, 1, 0, 0,
+];
+
+const READ_TOC_100: [u8; 10] = [0x43, 0, 0, 0, 0, 0, 1, 0, 100, 0];
+const GET_MEDIA_EVENT: [u8; 10] = [0x4A, 1, 0, 0, 0x10, 0, 0, 0, 8, 0];
+
+fn device(
+    script: impl FnOnce(&mut FakeScsiTransport),
+) -> ScsiDevice<FakeScsiTransport> {
+    let mut fake = FakeScsiTransport::new();
+    script(&mut fake);
+    ScsiDevice::new(fake)
+}
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+#[test]
+fn test_read_toc() {
+    let mut d = device(|t| {
+        t.expect_in(&READ_TOC_100, &TOC);
+    });
+    let mut buf = [0u8; 100];
+    let toc = run(d.read_toc(&mut buf)).unwrap();
+    assert_eq!(toc.first_track, 1);
+    assert_eq!(toc.last_track, 2);
+    let tracks = toc.tracks().collect::<Vec<_>>();
+    assert_eq!(tracks.len(), 3);
+    assert_eq!(
+        tracks[0],
+        TocEntry {
+            track_number: 1,
+            adr: 1,
+            control: 4,
+            start_lba: 0,
+        }
+    );
+    assert!(tracks[0].is_data());
+    assert!(!tracks[1].is_data());
+    assert_eq!(tracks[1].start_lba, 0x1234);
+    assert_eq!(tracks[2].track_number, LEAD_OUT_TRACK);
+    assert_eq!(tracks[2].start_lba, 0x10000);
+}
+
+#[test]
+fn test_read_toc_truncated() {
+    let mut d = device(|t| {
+        t.expect_in(&[0x43, 0, 0, 0, 0, 0, 1, 0, 16, 0], &TOC);
+    });
+    let mut buf = [0u8; 16];
+    let toc = run(d.read_toc(&mut buf)).unwrap();
+    assert_eq!(toc.last_track, 2);
+    assert_eq!(toc.tracks().count(), 1);
+}
+
+#[test]
+fn test_read_toc_short() {
+    let mut d = device(|t| {
+        t.expect_in(&READ_TOC_100, &[0, 0]);
+    });
+    let mut buf = [0u8; 100];
+    assert_eq!(run(d.read_toc(&mut buf)), Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_read_toc_fails() {
+    let mut d = device(|t| {
+        t.expect_check_condition(&READ_TOC_100, testing::sense(4, 0, 0));
+    });
+    let mut buf = [0u8; 100];
+    assert_eq!(
+        run(d.read_toc(&mut buf)),
+        Err(Error::Scsi(ScsiError::HardwareError))
+    );
+}
+
+#[test]
+fn test_read_toc_no_medium() {
+    let mut d = device(|t| {
+        t.expect_check_condition(&READ_TOC_100, testing::sense(2, 0x3A, 1));
+    });
+    let mut buf = [0u8; 100];
+    assert_eq!(
+        run(d.read_toc(&mut buf)),
+        Err(Error::Scsi(ScsiError::MediaNotPresent))
+    );
+}
+
+#[test]
+fn test_read_toc_pends() {
+    do_test(
+        |t| {
+            t.expect_pending(&READ_TOC_100);
+        },
+        |mut f| {
+            let mut buf = [0u8; 100];
+            f.c.check_pends(f.d.read_toc(&mut buf));
+        },
+    );
+}
+
+#[test]
+fn test_get_media_event() {
+    let mut d = device(|t| {
+        t.expect_in(&GET_MEDIA_EVENT, &[0, 6, 4, 0x10, 2, 2, 0, 0]);
+    });
+    assert_eq!(
+        run(d.get_media_event()),
+        Ok(MediaEvent {
+            code: MediaEventCode::NewMedia,
+            media_present: true,
+            door_open: false,
+        })
+    );
+}
+
+#[test]
+fn test_get_media_event_no_event_available() {
+    let mut d = device(|t| {
+        t.expect_in(&GET_MEDIA_EVENT, &[0, 2, 0x80, 0, 0, 0, 0, 0]);
+    });
+    assert_eq!(run(d.get_media_event()), Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_get_media_event_fails() {
+    let mut d = device(|t| {
+        t.expect_check_condition(&GET_MEDIA_EVENT, testing::sense(4, 0, 0));
+    });
+    assert_eq!(
+        run(d.get_media_event()),
+        Err(Error::Scsi(ScsiError::HardwareError))
+    );
+}
+
+#[test]
+fn test_media_event_code() {
+    assert_eq!(MediaEventCode::from(0), MediaEventCode::NoChange);
+    assert_eq!(MediaEventCode::from(3), MediaEventCode::MediaRemoval);
+    assert_eq!(
+        MediaEventCode::from(6),
+        MediaEventCode::BackgroundFormatRestarted
+    );
+    assert_eq!(MediaEventCode::from(0xF4), MediaEventCode::MediaChanged);
+    assert_eq!(MediaEventCode::from(9), MediaEventCode::Reserved(9));
+}
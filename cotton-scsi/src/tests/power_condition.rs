@@ -0,0 +1,219 @@
+use super::*;
+use crate::scsi_transport::ScsiError;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+fn mode_sense_cdb(control: u8) -> [u8; 10] {
+    [0x5A, 0x08, (control << 6) | 0x1A, 0, 0, 0, 0, 0, 128, 0]
+}
+
+fn mode_select_cdb(save: bool, len: u8) -> [u8; 10] {
+    [0x55, 0x10 | save as u8, 0, 0, 0, 0, 0, 0, len, 0]
+}
+
+/// A page from an SPC-4 disk: Idle_a after 2s, Standby_z after 10min
+/// (disabled), Idle_b after 30s (disabled), and CCF bits set
+const PAGE: [u8; 40] = [
+    0x9A, 0x26, 0x40, 0x02, //
+    0, 0, 0, 20, // Idle_a
+    0, 0, 0x17, 0x70, // Standby_z
+    0, 0, 1, 0x2C, // Idle_b
+    0, 0, 0, 0, // Idle_c
+    0, 0, 0, 0, // Standby_y
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+    0x54,
+];
+
+/// An SPC-3 disk: Idle after 5s, Standby after 1min
+const SHORT_PAGE: [u8; 12] = [0x1A, 0x0A, 0, 0x03, 0, 0, 0, 50, 0, 0, 2, 0x58];
+
+fn mode_sense_reply(page: &[u8], descriptors: usize) -> Vec<u8> {
+    let len = 6 + descriptors + page.len();
+    let mut v = vec![0u8; 8 + descriptors];
+    v[0..2].copy_from_slice(&(len as u16).to_be_bytes());
+    v[6..8].copy_from_slice(&(descriptors as u16).to_be_bytes());
+    v.extend_from_slice(page);
+    v
+}
+
+fn mode_select_data(page: &[u8]) -> Vec<u8> {
+    let mut v = vec![0u8; 8];
+    v.extend_from_slice(page);
+    v
+}
+
+#[test]
+fn test_parse() {
+    let page = PowerConditionPage::parse(&PAGE).unwrap();
+    assert!(page.saveable);
+    assert!(!page.is_short());
+    assert_eq!(page.size(), 40);
+    assert_eq!(
+        page.idle_a,
+        PowerConditionTimer {
+            enabled: true,
+            timer_100ms: 20
+        }
+    );
+    assert_eq!(
+        page.standby_z,
+        PowerConditionTimer {
+            enabled: false,
+            timer_100ms: 6000
+        }
+    );
+    assert_eq!(
+        page.idle_b,
+        PowerConditionTimer {
+            enabled: false,
+            timer_100ms: 300
+        }
+    );
+    assert_eq!(page.idle_c, PowerConditionTimer::default());
+    assert_eq!(page.standby_y, PowerConditionTimer::default());
+}
+
+#[test]
+fn test_parse_short() {
+    let page = PowerConditionPage::parse(&SHORT_PAGE).unwrap();
+    assert!(!page.saveable);
+    assert!(page.is_short());
+    assert_eq!(page.size(), 12);
+    assert_eq!(page.idle_a.timer_100ms, 50);
+    assert!(page.idle_a.enabled);
+    assert_eq!(page.standby_z.timer_100ms, 600);
+    assert!(page.standby_z.enabled);
+    assert!(!page.idle_b.enabled);
+}
+
+#[test]
+fn test_parse_bad() {
+    assert_eq!(PowerConditionPage::parse(&[0x1A]), None);
+    assert_eq!(PowerConditionPage::parse(&PAGE[0..39]), None);
+    assert_eq!(
+        PowerConditionPage::parse(&[0x1A, 8, 0, 0, 0, 0, 0, 0, 0, 0]),
+        None
+    );
+    let mut caching = PAGE;
+    caching[0] = 0x08;
+    assert_eq!(PowerConditionPage::parse(&caching), None);
+}
+
+#[test]
+fn test_round_trip() {
+    let page = PowerConditionPage::parse(&PAGE).unwrap();
+    let mut expected = PAGE;
+    expected[0] = 0x1A; // PS cleared
+    assert_eq!(page.to_bytes(), expected);
+
+    let page = PowerConditionPage::parse(&SHORT_PAGE).unwrap();
+    assert_eq!(page.to_bytes()[0..12], SHORT_PAGE);
+}
+
+#[test]
+fn test_set_seconds() {
+    let mut t = PowerConditionTimer::default();
+    t.set_seconds(600);
+    assert_eq!(
+        t,
+        PowerConditionTimer {
+            enabled: true,
+            timer_100ms: 6000
+        }
+    );
+    t.set_seconds(u32::MAX);
+    assert_eq!(t.timer_100ms, u32::MAX);
+}
+
+#[test]
+fn test_power_condition_page() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&mode_sense_cdb(0), &mode_sense_reply(&PAGE, 0))
+        .expect_in(&mode_sense_cdb(3), &mode_sense_reply(&SHORT_PAGE, 8));
+    let mut d = ScsiDevice::new(fake);
+    let page = run(d.power_condition_page(PageControl::Current)).unwrap();
+    assert_eq!(page, PowerConditionPage::parse(&PAGE).unwrap());
+    let page = run(d.power_condition_page(PageControl::Saved)).unwrap();
+    assert_eq!(page, PowerConditionPage::parse(&SHORT_PAGE).unwrap());
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_power_condition_page_missing() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&mode_sense_cdb(0), &mode_sense_reply(&[], 0))
+        .expect_in(&mode_sense_cdb(0), &[0, 6, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.power_condition_page(PageControl::Current)),
+        Err(Error::ProtocolError)
+    );
+    assert_eq!(
+        run(d.power_condition_page(PageControl::Current)),
+        Err(Error::ProtocolError)
+    );
+}
+
+#[test]
+fn test_power_condition_page_unsupported() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(
+        &mode_sense_cdb(0),
+        testing::sense(5, 0x24, 0),
+    );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.power_condition_page(PageControl::Current)),
+        Err(Error::Scsi(ScsiError::InvalidFieldInCDB))
+    );
+}
+
+#[test]
+fn test_set_standby_timer() {
+    let mut expected = PAGE;
+    expected[0] = 0x1A;
+    expected[3] = 0x03; // Standby_z enabled
+    expected[8..12].copy_from_slice(&[0, 0, 0x04, 0xB0]); // 120s
+
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&mode_sense_cdb(0), &mode_sense_reply(&PAGE, 0))
+        .expect_out(&mode_select_cdb(true, 48), &mode_select_data(&expected));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.set_standby_timer(120, true)), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_set_standby_timer_short_page() {
+    let mut expected = SHORT_PAGE;
+    expected[8..12].copy_from_slice(&[0, 0, 0, 10]); // 1s
+
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&mode_sense_cdb(0), &mode_sense_reply(&SHORT_PAGE, 0))
+        .expect_out(&mode_select_cdb(false, 20), &mode_select_data(&expected));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.set_standby_timer(1, false)), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_disable_power_management() {
+    let mut page = PAGE;
+    page[2] = 0x41; // Standby_y enabled too
+    page[3] = 0x0F; // ...and everything else
+    let mut expected = page;
+    expected[0] = 0x1A;
+    expected[2] = 0x40; // PM_BG_PRECEDENCE untouched
+    expected[3] = 0;
+
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&mode_sense_cdb(0), &mode_sense_reply(&page, 0))
+        .expect_out(&mode_select_cdb(false, 48), &mode_select_data(&expected));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.disable_power_management(false)), Ok(()));
+    assert!(d.transport().is_done());
+}
@@ -1,38 +1,45 @@
 use super::*;
+use crate::image_transport::ImageTransport;
 use crate::scsi_device::tests::{
-    command_in_fails, command_in_pends, command_ok_with, command_out_fails,
-    command_out_ok, command_out_pends, ContextExtras, ExtraExpectations,
-    MockScsiTransport, MockScsiTransportInner, NoOpWaker,
+    overheat, read_10, read_16, rsoc, write_10, write_16, ContextExtras,
+    NoOpWaker, READ_CAPACITY_10, READ_CAPACITY_16, SYNC,
 };
 use crate::scsi_device::{
-    ReadCapacity10Reply, ReadCapacity16Reply,
+    DeviceCapabilities, ReadCapacity10Reply, ReadCapacity16Reply,
     ReportSupportedOperationCodesReply,
 };
+use crate::scsi_transport::ScsiError;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
 use std::sync::Arc;
 use std::task::Waker;
 
+/// A device whose size is already known, so no READ CAPACITY is needed
+const KNOWN: DeviceInfo = DeviceInfo {
+    blocks: u64::MAX,
+    block_size: 512,
+    physical_block_size: 512,
+};
+
 struct Fixture<'a> {
     c: &'a mut core::task::Context<'a>,
-    d: ScsiBlockDevice<MockScsiTransport>,
+    d: ScsiBlockDevice<FakeScsiTransport>,
 }
 
-fn do_test<
-    SetupFn: FnMut(&mut MockScsiTransportInner),
-    TestFn: FnMut(Fixture),
->(
+fn do_test<SetupFn: FnMut(&mut FakeScsiTransport), TestFn: FnMut(Fixture)>(
     mut setup: SetupFn,
     mut test: TestFn,
 ) {
     let w = Waker::from(Arc::new(NoOpWaker));
     let mut c = core::task::Context::from_waker(&w);
 
-    let mut hc = MockScsiTransport::new();
+    let mut fake = FakeScsiTransport::new();
 
-    setup(&mut hc.inner);
+    setup(&mut fake);
 
     let f = Fixture {
         c: &mut c,
-        d: ScsiBlockDevice::new(ScsiDevice::new(hc)),
+        d: ScsiBlockDevice::new(ScsiDevice::new(fake)),
     };
 
     test(f);
@@ -42,18 +49,35 @@ fn do_test<
 fn test_device_info() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_ok_with(ReadCapacity10Reply {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
                     lba: 0x1020304_u32.to_be_bytes(),
                     block_size: 512_u32.to_be_bytes(),
-                }));
+                }),
+            );
         },
         |mut f| {
             let info = f.c.check_ok(f.d.device_info());
             assert_eq!(info.block_size, 512);
-            assert_eq!(info.blocks, 0x1020304);
+            assert_eq!(info.blocks, 0x1020305);
+        },
+    );
+}
+
+#[test]
+fn test_device_info_cached() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.scsi.capabilities = Some(DeviceCapabilities {
+                blocks: 1000,
+                block_size: 4096,
+                ..Default::default()
+            });
+            let info = f.c.check_ok(f.d.device_info());
+            assert_eq!(info.block_size, 4096);
+            assert_eq!(info.blocks, 1000);
         },
     );
 }
@@ -62,11 +86,7 @@ fn test_device_info() {
 fn test_device_info_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&READ_CAPACITY_10, overheat());
         },
         |mut f| {
             f.c.check_fails(f.d.device_info());
@@ -78,10 +98,7 @@ fn test_device_info_fails() {
 fn test_device_info_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_in_pends);
+            t.expect_pending(&READ_CAPACITY_10);
         },
         |mut f| {
             f.c.check_pends(f.d.device_info());
@@ -93,28 +110,28 @@ fn test_device_info_pends() {
 fn test_device_info_large() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_ok_with(ReadCapacity10Reply {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
                     lba: 0xFFFF_FFFF_u32.to_be_bytes(),
                     block_size: 512_u32.to_be_bytes(),
-                }));
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x9e && c[1] == 0x10 && c[13] >= 32)
-                .returning(command_ok_with(ReadCapacity16Reply {
+                }),
+            )
+            .expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
                     lba: 0x102030405060708_u64.to_be_bytes(),
                     block_size: 4096_u32.to_be_bytes(),
                     flags: [0; 2],
                     lowest_aligned_lba: [0; 2],
                     reserved: [0; 16],
-                }));
+                }),
+            );
         },
         |mut f| {
             let info = f.c.check_ok(f.d.device_info());
             assert_eq!(info.block_size, 4096);
-            assert_eq!(info.blocks, 0x102030405060708);
+            assert_eq!(info.blocks, 0x102030405060709);
         },
     );
 }
@@ -123,18 +140,14 @@ fn test_device_info_large() {
 fn test_device_info_large_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_ok_with(ReadCapacity10Reply {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
                     lba: 0xFFFF_FFFF_u32.to_be_bytes(),
                     block_size: 512_u32.to_be_bytes(),
-                }));
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x9e && c[1] == 0x10 && c[13] >= 32)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+                }),
+            )
+            .expect_check_condition(&READ_CAPACITY_16, overheat());
         },
         |mut f| {
             f.c.check_fails(f.d.device_info());
@@ -146,17 +159,14 @@ fn test_device_info_large_fails() {
 fn test_device_info_large_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_ok_with(ReadCapacity10Reply {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
                     lba: 0xFFFF_FFFF_u32.to_be_bytes(),
                     block_size: 512_u32.to_be_bytes(),
-                }));
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x9e && c[1] == 0x10 && c[13] >= 32)
-                .returning(command_in_pends);
+                }),
+            )
+            .expect_pending(&READ_CAPACITY_16);
         },
         |mut f| {
             f.c.check_pends(f.d.device_info());
@@ -168,12 +178,10 @@ fn test_device_info_large_pends() {
 fn test_read_blocks() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x28)
-                .returning(command_ok_with([43u8; 512]));
+            t.expect_in(&read_10(0, 1), &[43u8; 512]);
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_ok(f.d.read_blocks(0, 1, &mut buf));
             assert_eq!(buf[0], 43);
@@ -185,13 +193,10 @@ fn test_read_blocks() {
 fn test_read_blocks_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x28)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&read_10(0, 1), overheat());
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_fails(f.d.read_blocks(0, 1, &mut buf));
         },
@@ -202,12 +207,10 @@ fn test_read_blocks_fails() {
 fn test_read_blocks_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x28)
-                .returning(command_in_pends);
+            t.expect_pending(&read_10(0, 1));
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_pends(f.d.read_blocks(0, 1, &mut buf));
         },
@@ -218,12 +221,10 @@ fn test_read_blocks_pends() {
 fn test_read_blocks_large() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x88)
-                .returning(command_ok_with([44u8; 512]));
+            t.expect_in(&read_16(0x1_0000_0000, 1), &[44u8; 512]);
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_ok(f.d.read_blocks(0x1_0000_0000, 1, &mut buf));
             assert_eq!(buf[0], 44);
@@ -235,13 +236,10 @@ fn test_read_blocks_large() {
 fn test_read_blocks_large_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x88)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&read_16(0x1_0000_0000, 1), overheat());
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_fails(f.d.read_blocks(0x1_0000_0000, 1, &mut buf));
         },
@@ -252,12 +250,10 @@ fn test_read_blocks_large_fails() {
 fn test_read_blocks_large_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x88)
-                .returning(command_in_pends);
+            t.expect_pending(&read_16(0x1_0000_0000, 1));
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_pends(f.d.read_blocks(0x1_0000_0000, 1, &mut buf));
         },
@@ -267,10 +263,9 @@ fn test_read_blocks_large_pends() {
 #[test]
 fn test_read_blocks_too_large() {
     do_test(
-        |t| {
-            t.expect_command_in().times(0);
-        },
+        |_| {},
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_fails_custom(
                 f.d.read_blocks(0xFFFF_FFFF_8000_0000, 0x8000_0000, &mut buf),
@@ -284,12 +279,10 @@ fn test_read_blocks_too_large() {
 fn test_read_blocks_short_read() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x28)
-                .returning(command_ok_with([43u8; 128]));
+            t.expect_in(&read_10(0, 1), &[43u8; 128]);
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let mut buf = [0u8; 512];
             f.c.check_fails_custom(
                 f.d.read_blocks(0, 1, &mut buf),
@@ -303,12 +296,10 @@ fn test_read_blocks_short_read() {
 fn test_write_blocks() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, d| c[0] == 0x2A && d[0] == 47)
-                .returning(command_out_ok);
+            t.expect_out(&write_10(0, 1), &[47u8; 512]);
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [47u8; 512];
             f.c.check_ok(f.d.write_blocks(0, 1, &buf));
         },
@@ -319,13 +310,10 @@ fn test_write_blocks() {
 fn test_write_blocks_fails() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, d| c[0] == 0x2A && d[0] == 47)
-                .returning(command_out_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&write_10(0, 1), overheat());
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [47u8; 512];
             f.c.check_fails(f.d.write_blocks(0, 1, &buf));
         },
@@ -336,12 +324,10 @@ fn test_write_blocks_fails() {
 fn test_write_blocks_pends() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, d| c[0] == 0x2A && d[0] == 47)
-                .returning(command_out_pends);
+            t.expect_pending(&write_10(0, 1));
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [47u8; 512];
             f.c.check_pends(f.d.write_blocks(0, 1, &buf));
         },
@@ -352,12 +338,10 @@ fn test_write_blocks_pends() {
 fn test_write_blocks_large() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, d| c[0] == 0x8A && d[0] == 47)
-                .returning(command_out_ok);
+            t.expect_out(&write_16(0x1_0000_0000, 1), &[47u8; 512]);
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [47u8; 512];
             f.c.check_ok(f.d.write_blocks(0x1_0000_0000, 1, &buf));
         },
@@ -368,13 +352,10 @@ fn test_write_blocks_large() {
 fn test_write_blocks_large_fails() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, d| c[0] == 0x8A && d[0] == 47)
-                .returning(command_out_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&write_16(0x1_0000_0000, 1), overheat());
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [47u8; 512];
             f.c.check_fails(f.d.write_blocks(0x1_0000_0000, 1, &buf));
         },
@@ -385,12 +366,10 @@ fn test_write_blocks_large_fails() {
 fn test_write_blocks_large_pends() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, d| c[0] == 0x8A && d[0] == 47)
-                .returning(command_out_pends);
+            t.expect_pending(&write_16(0x1_0000_0000, 1));
         },
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [47u8; 512];
             f.c.check_pends(f.d.write_blocks(0x1_0000_0000, 1, &buf));
         },
@@ -400,10 +379,9 @@ fn test_write_blocks_large_pends() {
 #[test]
 fn test_write_blocks_too_large() {
     do_test(
-        |t| {
-            t.expect_command_out().times(0);
-        },
+        |_| {},
         |mut f| {
+            f.d.info = Some(KNOWN);
             let buf = [0u8; 512];
             f.c.check_fails_custom(
                 f.d.write_blocks(0xFFFF_FFFF_8000_0000, 0x8000_0000, &buf),
@@ -417,16 +395,16 @@ fn test_write_blocks_too_large() {
 fn test_query_commands() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(10)
-                .withf(|c, _| c[0] == 0xA3)
-                .returning(command_ok_with(
-                    ReportSupportedOperationCodesReply {
-                        reserved: 0,
-                        support: 3,
-                        cdb_size: [0; 2],
-                    },
-                ));
+            let supported = ReportSupportedOperationCodesReply {
+                reserved: 0,
+                support: 3,
+                cdb_size: [0; 2],
+            };
+            for opcode in
+                [0x08, 0x28, 0xA8, 0x88, 0x0A, 0x2A, 0xAA, 0x8A, 0x9C, 0x8E]
+            {
+                t.expect_in(&rsoc(opcode), bytemuck::bytes_of(&supported));
+            }
         },
         |mut f| {
             f.c.check_ok(f.d.query_commands());
@@ -438,11 +416,7 @@ fn test_query_commands() {
 fn test_query_commands_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0xA3)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&rsoc(0x08), overheat());
         },
         |mut f| {
             f.c.check_fails(f.d.query_commands());
@@ -454,13 +428,258 @@ fn test_query_commands_fails() {
 fn test_query_commands_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0xA3)
-                .returning(command_in_pends);
+            t.expect_pending(&rsoc(0x08));
         },
         |mut f| {
             f.c.check_pends(f.d.query_commands());
         },
     );
 }
+
+#[test]
+fn test_device_info_once() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
+                    lba: 99_u32.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                }),
+            )
+            .expect_in(&read_10(0, 1), &[43u8; 512]);
+        },
+        |mut f| {
+            let mut buf = [0u8; 512];
+            f.c.check_ok(f.d.read_blocks(0, 1, &mut buf));
+            let info = f.c.check_ok(f.d.device_info());
+            assert_eq!(info.blocks, 100);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_buffer_too_small() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.info = Some(KNOWN);
+            let mut buf = [0u8; 1000];
+            f.c.check_fails_custom(
+                f.d.read_blocks(0, 2, &mut buf),
+                Error::BadBufferSize,
+            );
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_buffer_larger() {
+    do_test(
+        |t| {
+            t.expect_in(&read_10(0, 1), &[43u8; 1000]);
+        },
+        |mut f| {
+            f.d.info = Some(KNOWN);
+            let mut buf = [0u8; 1000];
+            f.c.check_ok(f.d.read_blocks(0, 1, &mut buf));
+            assert_eq!(buf[511], 43);
+            assert_eq!(buf[512], 0);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_none() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.info = Some(KNOWN);
+            f.c.check_ok(f.d.read_blocks(0, 0, &mut []));
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_beyond_end() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.info = Some(DeviceInfo {
+                blocks: 100,
+                ..KNOWN
+            });
+            let mut buf = [0u8; 1024];
+            f.c.check_fails_custom(
+                f.d.read_blocks(99, 2, &mut buf),
+                Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_buffer_too_small() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.info = Some(KNOWN);
+            let buf = [0u8; 511];
+            f.c.check_fails_custom(
+                f.d.write_blocks(0, 1, &buf),
+                Error::BadBufferSize,
+            );
+        },
+    );
+}
+
+#[test]
+fn test_flush() {
+    do_test(
+        |t| {
+            t.expect_no_data(&SYNC);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_flush_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&SYNC, overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_flush_unsupported() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&SYNC, testing::sense(5, 0x20, 0));
+        },
+        |mut f| {
+            f.c.check_ok(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_flush_pends() {
+    do_test(
+        |t| {
+            t.expect_pending(&SYNC);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.flush());
+        },
+    );
+}
+
+#[test]
+fn test_image_round_trip() {
+    let mut d =
+        ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(4096, 64)));
+    let data: Vec<u8> = (0..4096 * 3).map(|i| (i / 13) as u8).collect();
+    let mut buf = vec![0u8; 4096 * 3];
+    let info = d.device_info().now_or_never().unwrap().unwrap();
+    assert_eq!(info.block_size, 4096);
+    d.write_blocks(61, 3, &data)
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    d.flush().now_or_never().unwrap().unwrap();
+    d.read_blocks(61, 3, &mut buf)
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(
+        d.read_blocks(62, 3, &mut buf).now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+    );
+}
+
+#[test]
+fn test_check_media_rereads_capacity() {
+    let mut fake = crate::testing::FakeScsiTransport::new();
+    fake.expect_no_data(&[0; 6])
+        .expect_check_condition(&[0; 6], crate::testing::sense(6, 0x28, 0))
+        .expect_in(
+            &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 127, 0, 0, 2, 0],
+        );
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(fake));
+    d.info = Some(KNOWN);
+    assert_eq!(
+        d.check_media().now_or_never().unwrap(),
+        Ok(MediaState::Ready)
+    );
+    assert_eq!(d.info, Some(KNOWN));
+    // A new card, of a different size
+    assert_eq!(
+        d.check_media().now_or_never().unwrap(),
+        Ok(MediaState::Changed)
+    );
+    let info = d.device_info().now_or_never().unwrap().unwrap();
+    assert_eq!(info.blocks, 128);
+}
+
+#[test]
+fn test_read_only_probed() {
+    let mut t = ImageTransport::new(512, 64);
+    t.set_write_protected(true);
+    let mut scsi = ScsiDevice::new(t);
+    scsi.probe().now_or_never().unwrap().unwrap();
+    let mut d = ScsiBlockDevice::new(scsi);
+    assert_eq!(d.read_only().now_or_never().unwrap(), Ok(true));
+}
+
+#[test]
+fn test_read_only_asked_once() {
+    let mut fake = crate::testing::FakeScsiTransport::new();
+    fake.expect_in(&[0x1A, 8, 0x3F, 0, 4, 0], &[3, 0, 0x80, 0]);
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(fake));
+    d.info = Some(KNOWN);
+    assert_eq!(d.read_only().now_or_never().unwrap(), Ok(true));
+    assert_eq!(d.read_only().now_or_never().unwrap(), Ok(true));
+    // Fails without issuing a command
+    assert_eq!(
+        d.write_blocks(0, 1, &[0; 512]).now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::WriteProtected))
+    );
+}
+
+#[test]
+fn test_read_only_unknown() {
+    let mut fake = crate::testing::FakeScsiTransport::new();
+    fake.expect_check_condition(
+        &[0x1A, 8, 0x3F, 0, 4, 0],
+        crate::testing::sense(5, 0x20, 0),
+    )
+    .expect_check_condition(
+        &[0x5A, 8, 0x3F, 0, 0, 0, 0, 0, 8, 0],
+        crate::testing::sense(5, 0x20, 0),
+    );
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(fake));
+    assert_eq!(d.read_only().now_or_never().unwrap(), Ok(false));
+}
+
+#[test]
+fn test_read_only_forgotten_on_media_change() {
+    let mut fake = crate::testing::FakeScsiTransport::new();
+    fake.expect_check_condition(&[0; 6], crate::testing::sense(6, 0x28, 0))
+        .expect_in(&[0x1A, 8, 0x3F, 0, 4, 0], &[3, 0, 0, 0]);
+    let mut d = ScsiBlockDevice::new(ScsiDevice::new(fake));
+    d.read_only = Some(true);
+    assert_eq!(
+        d.check_media().now_or_never().unwrap(),
+        Ok(MediaState::Changed)
+    );
+    assert_eq!(d.read_only().now_or_never().unwrap(), Ok(false));
+}
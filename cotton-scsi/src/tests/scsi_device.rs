@@ -1,9 +1,15 @@
 use super::*;
+use crate::scsi_transport::SenseData;
+use crate::testing::{self, FakeScsiTransport};
+use crate::timeout::TimedTransport;
 use futures::future;
-use mockall::mock;
+use futures::FutureExt;
+use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::future::Future;
-use std::pin::{pin, Pin};
+use std::mem::MaybeUninit;
+use std::pin::pin;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::task::{Poll, Wake, Waker};
 
@@ -13,70 +19,15 @@ impl Wake for NoOpWaker {
     fn wake(self: Arc<Self>) {}
 }
 
-pub type MockError = Error<<MockScsiTransport as ScsiTransport>::Error>;
+pub type TestError = Error<<FakeScsiTransport as ScsiTransport>::Error>;
 
-mock! {
-    pub ScsiTransportInner {
-        pub fn command_in(
-            &mut self,
-            cmd: &[u8],
-            data: &mut [u8]
-        ) -> impl Future<Output = Result<usize, MockError>>;
-
-        pub fn command_out(
-            &mut self,
-            cmd: &[u8],
-            data: &[u8]
-        ) -> impl Future<Output = Result<usize, MockError>>;
-
-        pub fn command_nodata(
-            &mut self,
-            cmd: &[u8],
-        ) -> impl Future<Output = Result<usize, MockError>>;
-    }
-}
-
-pub struct MockScsiTransport {
-    pub inner: MockScsiTransportInner,
-}
-
-impl MockScsiTransport {
-    pub fn new() -> Self {
-        Self {
-            inner: MockScsiTransportInner::new(),
-        }
-    }
-}
-
-impl Debug for MockScsiTransport {
-    fn fmt(&self, _: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        Ok(())
-    }
-}
-
-impl ScsiTransport for MockScsiTransport {
-    type Error = ();
-
-    fn command(
-        &mut self,
-        cmd: &[u8],
-        data: DataPhase,
-    ) -> impl Future<Output = Result<usize, MockError>> {
-        match data {
-            DataPhase::In(data) => self.inner.command_in(cmd, data),
-            DataPhase::Out(data) => self.inner.command_out(cmd, data),
-            DataPhase::None => self.inner.command_nodata(cmd),
-        }
-    }
-}
-
-struct Fixture<'a> {
-    c: &'a mut core::task::Context<'a>,
-    d: ScsiDevice<MockScsiTransport>,
+pub struct Fixture<'a> {
+    pub c: &'a mut core::task::Context<'a>,
+    pub d: ScsiDevice<FakeScsiTransport>,
 }
 
-fn do_test<
-    SetupFn: FnMut(&mut MockScsiTransportInner),
+pub fn do_test<
+    SetupFn: FnMut(&mut FakeScsiTransport),
     TestFn: FnMut(Fixture),
 >(
     mut setup: SetupFn,
@@ -85,13 +36,13 @@ fn do_test<
     let w = Waker::from(Arc::new(NoOpWaker));
     let mut c = core::task::Context::from_waker(&w);
 
-    let mut hc = MockScsiTransport::new();
+    let mut fake = FakeScsiTransport::new();
 
-    setup(&mut hc.inner);
+    setup(&mut fake);
 
     let f = Fixture {
         c: &mut c,
-        d: ScsiDevice::new(hc),
+        d: ScsiDevice::new(fake),
     };
 
     test(f);
@@ -110,33 +61,15 @@ impl<T> PollExtras<T> for Poll<T> {
     }
 }
 
-pub trait ExtraExpectations {
-    fn expect_request_sense(&mut self);
-}
-
-impl ExtraExpectations for MockScsiTransportInner {
-    fn expect_request_sense(&mut self) {
-        self.expect_command_in()
-            .times(1)
-            .withf(|c, _| c[0] == 3)
-            .returning(command_ok_with(RequestSenseReply {
-                sense_key: 1,
-                additional_sense_code: 0xB,
-                additional_sense_code_qualifier: 1,
-                ..Default::default()
-            }));
-    }
-}
-
 pub trait ContextExtras {
-    fn check_ok<T, F: Future<Output = Result<T, MockError>>>(
+    fn check_ok<T, F: Future<Output = Result<T, TestError>>>(
         &mut self,
         fut: F,
     ) -> T;
 
     fn check_fails<
         T: Debug + PartialEq,
-        F: Future<Output = Result<T, MockError>>,
+        F: Future<Output = Result<T, TestError>>,
     >(
         &mut self,
         fut: F,
@@ -144,21 +77,21 @@ pub trait ContextExtras {
 
     fn check_fails_custom<
         T: Debug + PartialEq,
-        F: Future<Output = Result<T, MockError>>,
+        F: Future<Output = Result<T, TestError>>,
     >(
         &mut self,
         fut: F,
-        e: MockError,
+        e: TestError,
     );
 
-    fn check_pends<T, F: Future<Output = Result<T, MockError>>>(
+    fn check_pends<T, F: Future<Output = Result<T, TestError>>>(
         &mut self,
         fut: F,
     );
 }
 
 impl ContextExtras for core::task::Context<'_> {
-    fn check_ok<T, F: Future<Output = Result<T, MockError>>>(
+    fn check_ok<T, F: Future<Output = Result<T, TestError>>>(
         &mut self,
         fut: F,
     ) -> T {
@@ -169,7 +102,7 @@ impl ContextExtras for core::task::Context<'_> {
 
     fn check_fails<
         T: Debug + PartialEq,
-        F: Future<Output = Result<T, MockError>>,
+        F: Future<Output = Result<T, TestError>>,
     >(
         &mut self,
         fut: F,
@@ -181,18 +114,18 @@ impl ContextExtras for core::task::Context<'_> {
 
     fn check_fails_custom<
         T: Debug + PartialEq,
-        F: Future<Output = Result<T, MockError>>,
+        F: Future<Output = Result<T, TestError>>,
     >(
         &mut self,
         fut: F,
-        e: MockError,
+        e: TestError,
     ) {
         let fut = pin!(fut);
         let result = fut.poll(self).to_option().unwrap();
         assert_eq!(result.unwrap_err(), e);
     }
 
-    fn check_pends<T, F: Future<Output = Result<T, MockError>>>(
+    fn check_pends<T, F: Future<Output = Result<T, TestError>>>(
         &mut self,
         fut: F,
     ) {
@@ -204,84 +137,60 @@ impl ContextExtras for core::task::Context<'_> {
     }
 }
 
-fn command_nodata_ok(
-    _: &[u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::ready(Ok(0)))
-}
-
-fn command_nodata_fails(
-    _: &[u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::ready(Err(Error::CommandFailed)))
-}
-
-fn command_nodata_pends(
-    _: &[u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::pending())
+/// The sense data which `check_fails` expects
+pub fn overheat() -> SenseData {
+    testing::sense(1, 0xB, 1)
 }
 
-#[rustfmt::skip]
-pub fn command_ok_with<T: bytemuck::NoUninit>(
-    reply: T,
-) -> impl FnMut(
-    &[u8],
-    &mut [u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    move |_, d| {
-        let size = core::mem::size_of::<T>();
-        d[0..size].copy_from_slice(bytemuck::bytes_of(&reply));
-        Box::pin(future::ready(Ok(size)))
-    }
-}
+pub const READ_CAPACITY_10: [u8; 10] = [0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+pub const READ_CAPACITY_16: [u8; 16] =
+    [0x9E, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0];
+pub const START_UNIT: [u8; 6] = [0x1B, 0, 0, 0, 1, 0];
+pub const BLOCK_LIMITS: [u8; 6] = [0x12, 1, 0xB0, 0, 64, 0];
 
-pub fn command_in_pends(
-    _: &[u8],
-    _: &mut [u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::pending())
+/// REPORT SUPPORTED OPERATION CODES, for one operation code
+pub fn rsoc(opcode: u8) -> [u8; 12] {
+    [0xA3, 0xC, 3, opcode, 0, 0, 0, 0, 0, 4, 0, 0]
 }
 
-pub fn command_in_fails(
-    _: &[u8],
-    _: &mut [u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::ready(Err(Error::CommandFailed)))
+pub fn read_10(lba: u32, count: u16) -> [u8; 10] {
+    let mut cdb = [0x28, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    cdb[2..6].copy_from_slice(&lba.to_be_bytes());
+    cdb[7..9].copy_from_slice(&count.to_be_bytes());
+    cdb
 }
 
-pub fn command_out_ok(
-    _: &[u8],
-    d: &[u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::ready(Ok(d.len())))
+pub fn write_10(lba: u32, count: u16) -> [u8; 10] {
+    let mut cdb = read_10(lba, count);
+    cdb[0] = 0x2A;
+    cdb
 }
 
-pub fn command_out_fails(
-    _: &[u8],
-    _: &[u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::ready(Err(Error::CommandFailed)))
+pub fn read_16(lba: u64, count: u32) -> [u8; 16] {
+    let mut cdb = [0u8; 16];
+    cdb[0] = 0x88;
+    cdb[2..10].copy_from_slice(&lba.to_be_bytes());
+    cdb[10..14].copy_from_slice(&count.to_be_bytes());
+    cdb
 }
 
-pub fn command_out_pends(
-    _: &[u8],
-    _: &[u8],
-) -> Pin<Box<dyn Future<Output = Result<usize, MockError>>>> {
-    Box::pin(future::pending())
+pub fn write_16(lba: u64, count: u32) -> [u8; 16] {
+    let mut cdb = read_16(lba, count);
+    cdb[0] = 0x8A;
+    cdb
 }
 
 #[test]
 fn test_read_capacity_10() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_ok_with(ReadCapacity10Reply {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
                     lba: 0x1020304_u32.to_be_bytes(),
                     block_size: 512_u32.to_be_bytes(),
-                }));
+                }),
+            );
         },
         |mut f| {
             let (count, size) = f.c.check_ok(f.d.read_capacity_10());
@@ -295,10 +204,7 @@ fn test_read_capacity_10() {
 fn test_read_capacity_10_wrong_size() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_ok_with([0u8; 6]));
+            t.expect_in(&READ_CAPACITY_10, &[0u8; 6]);
         },
         |mut f| {
             f.c.check_fails_custom(
@@ -313,11 +219,7 @@ fn test_read_capacity_10_wrong_size() {
 fn test_read_capacity_10_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&READ_CAPACITY_10, overheat());
         },
         |mut f| {
             f.c.check_fails(f.d.read_capacity_10());
@@ -329,10 +231,7 @@ fn test_read_capacity_10_fails() {
 fn test_read_capacity_10_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_in_pends);
+            t.expect_pending(&READ_CAPACITY_10);
         },
         |mut f| {
             f.c.check_pends(f.d.read_capacity_10());
@@ -344,14 +243,8 @@ fn test_read_capacity_10_pends() {
 fn test_read_capacity_10_error_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x25)
-                .returning(command_in_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_pends);
+            t.expect_error(&READ_CAPACITY_10, Error::CommandFailed)
+                .expect_pending(&testing::request_sense_cdb());
         },
         |mut f| {
             f.c.check_pends(f.d.read_capacity_10());
@@ -363,16 +256,16 @@ fn test_read_capacity_10_error_pends() {
 fn test_read_capacity_16() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x9e && c[1] == 0x10 && c[13] >= 32)
-                .returning(command_ok_with(ReadCapacity16Reply {
+            t.expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
                     lba: 0x102030405060708_u64.to_be_bytes(),
                     block_size: 4096_u32.to_be_bytes(),
                     flags: [0; 2],
                     lowest_aligned_lba: [0; 2],
                     reserved: [0; 16],
-                }));
+                }),
+            );
         },
         |mut f| {
             let (count, size) = f.c.check_ok(f.d.read_capacity_16());
@@ -382,15 +275,70 @@ fn test_read_capacity_16() {
     );
 }
 
+#[test]
+fn test_read_capacity_16_full() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
+                    lba: 999_u64.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                    flags: [0, 3],
+                    lowest_aligned_lba: [0xC0, 1],
+                    reserved: [0; 16],
+                }),
+            );
+        },
+        |mut f| {
+            let data = f.c.check_ok(f.d.read_capacity_16_full());
+            assert_eq!(
+                data,
+                ReadCapacity16Data {
+                    last_lba: 999,
+                    block_size: 512,
+                    physical_block_size: 4096,
+                    lowest_aligned_lba: 1,
+                    protection_type: None,
+                    protection_interval_exponent: 0,
+                    thin_provisioned: true,
+                    unmapped_reads_zero: true,
+                }
+            );
+        },
+    );
+}
+
+#[test]
+fn test_read_capacity_16_protection() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
+                    lba: 7_u64.to_be_bytes(),
+                    block_size: 4096_u32.to_be_bytes(),
+                    flags: [0x03, 0x30],
+                    lowest_aligned_lba: [0; 2],
+                    reserved: [0; 16],
+                }),
+            );
+        },
+        |mut f| {
+            let data = f.c.check_ok(f.d.read_capacity_16_full());
+            assert_eq!(data.physical_block_size, 4096);
+            assert_eq!(data.protection_type, Some(2));
+            assert_eq!(data.protection_interval_exponent, 3);
+            assert!(!data.thin_provisioned);
+        },
+    );
+}
+
 #[test]
 fn test_read_capacity_16_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x9E)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&READ_CAPACITY_16, overheat());
         },
         |mut f| {
             f.c.check_fails(f.d.read_capacity_16());
@@ -402,10 +350,7 @@ fn test_read_capacity_16_fails() {
 fn test_read_capacity_16_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x9E)
-                .returning(command_in_pends);
+            t.expect_pending(&READ_CAPACITY_16);
         },
         |mut f| {
             f.c.check_pends(f.d.read_capacity_16());
@@ -413,14 +358,125 @@ fn test_read_capacity_16_pends() {
     );
 }
 
+#[test]
+fn test_read_capacity() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
+                    lba: 0x1020304_u32.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                }),
+            );
+        },
+        |mut f| {
+            let (count, size) = f.c.check_ok(f.d.read_capacity());
+            assert_eq!(size, 512);
+            assert_eq!(count, 0x1020305);
+            assert_eq!(f.d.supports_16, None);
+        },
+    );
+}
+
+#[test]
+fn test_read_capacity_saturated() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
+                    lba: 0xFFFF_FFFF_u32.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                }),
+            )
+            .expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
+                    lba: 0x1_2345_6789_u64.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                    ..Default::default()
+                }),
+            );
+        },
+        |mut f| {
+            let (count, size) = f.c.check_ok(f.d.read_capacity());
+            assert_eq!(size, 512);
+            assert_eq!(count, 0x1_2345_678A);
+            assert_eq!(f.d.supports_16, Some(true));
+        },
+    );
+}
+
+#[test]
+fn test_read_capacity_saturated_fails() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
+                    lba: 0xFFFF_FFFF_u32.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                }),
+            )
+            .expect_check_condition(
+                &READ_CAPACITY_16,
+                testing::sense(5, 0x20, 0),
+            );
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.d.read_capacity(),
+                Error::Scsi(ScsiError::InvalidCommandOperationCode),
+            );
+            assert_eq!(f.d.supports_16, Some(false));
+        },
+    );
+}
+
+#[test]
+fn test_read_capacity_saturated_overflow() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
+                    lba: 0xFFFF_FFFF_u32.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                }),
+            )
+            .expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
+                    lba: u64::MAX.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                    ..Default::default()
+                }),
+            );
+        },
+        |mut f| {
+            f.c.check_fails_custom(f.d.read_capacity(), Error::ProtocolError);
+        },
+    );
+}
+
+#[test]
+fn test_read_capacity_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&READ_CAPACITY_10, overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.read_capacity());
+        },
+    );
+}
+
 #[test]
 fn test_unit_ready() {
     do_test(
         |t| {
-            t.expect_command_nodata()
-                .times(1)
-                .withf(|c| c[0] == 0)
-                .returning(command_nodata_ok);
+            t.expect_no_data(&TUR);
         },
         |mut f| {
             f.c.check_ok(f.d.test_unit_ready());
@@ -432,11 +488,7 @@ fn test_unit_ready() {
 fn test_unit_ready_fails() {
     do_test(
         |t| {
-            t.expect_command_nodata()
-                .times(1)
-                .withf(|c| c[0] == 0)
-                .returning(command_nodata_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&TUR, overheat());
         },
         |mut f| {
             f.c.check_fails(f.d.test_unit_ready());
@@ -448,10 +500,7 @@ fn test_unit_ready_fails() {
 fn test_unit_ready_pends() {
     do_test(
         |t| {
-            t.expect_command_nodata()
-                .times(1)
-                .withf(|c| c[0] == 0)
-                .returning(command_nodata_pends);
+            t.expect_pending(&TUR);
         },
         |mut f| {
             f.c.check_pends(f.d.test_unit_ready());
@@ -463,14 +512,8 @@ fn test_unit_ready_pends() {
 fn test_unit_ready_error_pends() {
     do_test(
         |t| {
-            t.expect_command_nodata()
-                .times(1)
-                .withf(|c| c[0] == 0)
-                .returning(command_nodata_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_pends);
+            t.expect_error(&TUR, Error::CommandFailed)
+                .expect_pending(&testing::request_sense_cdb());
         },
         |mut f| {
             f.c.check_pends(f.d.test_unit_ready());
@@ -482,14 +525,8 @@ fn test_unit_ready_error_pends() {
 fn test_unit_ready_error_fails() {
     do_test(
         |t| {
-            t.expect_command_nodata()
-                .times(1)
-                .withf(|c| c[0] == 0)
-                .returning(command_nodata_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_ok_with([0u8; 2]));
+            t.expect_error(&TUR, Error::CommandFailed)
+                .expect_in(&testing::request_sense_cdb(), &[0u8; 2]);
         },
         |mut f| {
             f.c.check_fails_custom(
@@ -504,14 +541,10 @@ fn test_unit_ready_error_fails() {
 fn test_unit_ready_error_fails2() {
     do_test(
         |t| {
-            t.expect_command_nodata()
-                .times(1)
-                .withf(|c| c[0] == 0)
-                .returning(command_nodata_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_fails);
+            t.expect_error(&TUR, Error::CommandFailed).expect_error(
+                &testing::request_sense_cdb(),
+                Error::CommandFailed,
+            );
         },
         |mut f| {
             f.c.check_fails_custom(
@@ -522,16 +555,128 @@ fn test_unit_ready_error_fails2() {
     );
 }
 
+/// Make TEST UNIT READY fail `failures` times, then succeed
+fn expect_unit_ready_after(
+    t: &mut FakeScsiTransport,
+    failures: usize,
+    senses: &[(u8, u8, u8)],
+) {
+    for i in 0..failures {
+        let (key, asc, ascq) = senses[i.min(senses.len() - 1)];
+        t.expect_check_condition(&TUR, testing::sense(key, asc, ascq));
+    }
+    t.expect_no_data(&TUR);
+}
+
+#[test]
+fn test_wait_until_ready() {
+    let delays = std::rc::Rc::new(std::cell::Cell::new(0));
+    do_test(
+        |t| expect_unit_ready_after(t, 2, &[(2, 0, 0), (2, 4, 1)]),
+        |mut f| {
+            let d = delays.clone();
+            f.c.check_ok(f.d.wait_until_ready(
+                move |ms| {
+                    assert_eq!(ms, READY_POLL_INTERVAL_MS);
+                    d.set(d.get() + 1);
+                    future::ready(())
+                },
+                5,
+            ));
+        },
+    );
+    assert_eq!(delays.get(), 2);
+}
+
+#[test]
+fn test_wait_until_ready_starts_unit() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&TUR, testing::sense(2, 4, 2))
+                .expect_no_data(&START_UNIT)
+                .expect_no_data(&TUR);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.wait_until_ready(|_| future::ready(()), 5));
+        },
+    );
+}
+
+#[test]
+fn test_wait_until_ready_gives_up() {
+    do_test(
+        |t| {
+            for _ in 0..3 {
+                t.expect_check_condition(&TUR, testing::sense(2, 4, 1));
+            }
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.d.wait_until_ready(|_| future::ready(()), 3),
+                Error::Scsi(ScsiError::BecomingReady),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_wait_until_ready_no_medium() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&TUR, testing::sense(2, 0x3A, 0));
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.d.wait_until_ready(|_| future::ready(()), 3),
+                Error::Scsi(ScsiError::MediaNotPresent),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_wait_until_ready_pends() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&TUR, testing::sense(2, 4, 1));
+        },
+        |mut f| {
+            f.c.check_pends(
+                f.d.wait_until_ready(|_| future::pending::<()>(), 3),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_start_stop_unit() {
+    do_test(
+        |t| {
+            t.expect_no_data(&EJECT);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.start_stop_unit(false, true));
+        },
+    );
+}
+
+#[test]
+fn test_start_stop_unit_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&START_UNIT, overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.start_stop_unit(true, false));
+        },
+    );
+}
+
 #[test]
 fn test_read_10() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x28 && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_ok_with([42u8; 512]));
+            t.expect_in(&read_10(81, 1), &[42u8; 512]);
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -545,13 +690,7 @@ fn test_read_10() {
 fn test_read_10_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x28 && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&read_10(81, 1), overheat());
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -564,12 +703,7 @@ fn test_read_10_fails() {
 fn test_read_10_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x28 && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_in_pends);
+            t.expect_pending(&read_10(81, 1));
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -582,16 +716,8 @@ fn test_read_10_pends() {
 fn test_read_10_error_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x28 && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_in_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_pends);
+            t.expect_error(&read_10(81, 1), Error::CommandFailed)
+                .expect_pending(&testing::request_sense_cdb());
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -600,16 +726,67 @@ fn test_read_10_error_pends() {
     );
 }
 
+#[test]
+fn test_access_flags_cdbs() {
+    let cdb = Read10::new(0x0102_0304, 5, AccessFlags::DPO);
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0x28, 0x10, 1, 2, 3, 4, 0, 0, 5, 0]
+    );
+    let cdb = Write10::new(0x0102_0304, 5, AccessFlags::FUA);
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0x2A, 0x08, 1, 2, 3, 4, 0, 0, 5, 0]
+    );
+    let both = AccessFlags {
+        force_unit_access: true,
+        disable_page_out: true,
+    };
+    let cdb = Read16::new(0x1_0000_0002, 3, both);
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0x88, 0x18, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0]
+    );
+    let cdb = Write16::new(7, 0x10000, AccessFlags::default());
+    assert_eq!(
+        bytemuck::bytes_of(&cdb),
+        [0x8A, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 1, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_with_flags() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&[0x2A, 0x08, 0, 0, 0, 9, 0, 0, 1, 0], &[0x55; 512])
+        .expect_out(
+            &[0x8A, 0x08, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0],
+            &[0x66; 512],
+        )
+        .expect_in(&[0x28, 0x10, 0, 0, 0, 9, 0, 0, 1, 0], &[0x55; 512])
+        .expect_in(
+            &[0x88, 0x10, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0],
+            &[0x66; 512],
+        );
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.write_10_with_flags(9, 1, AccessFlags::FUA, &[0x55; 512]);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(512));
+    let rc = d.write_16_with_flags(1 << 32, 1, AccessFlags::FUA, &[0x66; 512]);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(512));
+    let mut buf = [0u8; 512];
+    let rc = d.read_10_with_flags(9, 1, AccessFlags::DPO, &mut buf);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(512));
+    assert_eq!(buf, [0x55; 512]);
+    let rc = d.read_16_with_flags(1 << 32, 1, AccessFlags::DPO, &mut buf);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(512));
+    assert_eq!(buf, [0x66; 512]);
+    assert!(d.transport().is_done());
+}
+
 #[test]
 fn test_read_16() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x88 && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_ok_with([42u8; 512]));
+            t.expect_in(&read_16(81, 1), &[42u8; 512]);
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -623,13 +800,7 @@ fn test_read_16() {
 fn test_read_16_fails() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x88 && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&read_16(81, 1), overheat());
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -642,12 +813,7 @@ fn test_read_16_fails() {
 fn test_read_16_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x88 && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_in_pends);
+            t.expect_pending(&read_16(81, 1));
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -660,16 +826,8 @@ fn test_read_16_pends() {
 fn test_read_16_error_pends() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x88 && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_in_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_pends);
+            t.expect_error(&read_16(81, 1), Error::CommandFailed)
+                .expect_pending(&testing::request_sense_cdb());
         },
         |mut f| {
             let mut buf = [0u8; 512];
@@ -682,12 +840,7 @@ fn test_read_16_error_pends() {
 fn test_write_10() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x2A && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_out_ok);
+            t.expect_out(&write_10(81, 1), &[0u8; 512]);
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -701,13 +854,7 @@ fn test_write_10() {
 fn test_write_10_fails() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x2A && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_out_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&write_10(81, 1), overheat());
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -720,12 +867,7 @@ fn test_write_10_fails() {
 fn test_write_10_pends() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x2A && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_out_pends);
+            t.expect_pending(&write_10(81, 1));
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -738,16 +880,8 @@ fn test_write_10_pends() {
 fn test_write_10_error_pends() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x2A && c[1] == 0 && c[5] == 81 && c[8] == 1
-                })
-                .returning(command_out_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_pends);
+            t.expect_error(&write_10(81, 1), Error::CommandFailed)
+                .expect_pending(&testing::request_sense_cdb());
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -760,12 +894,7 @@ fn test_write_10_error_pends() {
 fn test_write_16() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x8A && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_out_ok);
+            t.expect_out(&write_16(81, 1), &[0u8; 512]);
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -779,13 +908,7 @@ fn test_write_16() {
 fn test_write_16_fails() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x8A && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_out_fails);
-            t.expect_request_sense();
+            t.expect_check_condition(&write_16(81, 1), overheat());
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -798,12 +921,7 @@ fn test_write_16_fails() {
 fn test_write_16_pends() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x8A && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_out_pends);
+            t.expect_pending(&write_16(81, 1));
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -816,16 +934,8 @@ fn test_write_16_pends() {
 fn test_write_16_error_pends() {
     do_test(
         |t| {
-            t.expect_command_out()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x8A && c[1] == 0 && c[9] == 81 && c[13] == 1
-                })
-                .returning(command_out_fails);
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_in_pends);
+            t.expect_error(&write_16(81, 1), Error::CommandFailed)
+                .expect_pending(&testing::request_sense_cdb());
         },
         |mut f| {
             let buf = [0u8; 512];
@@ -834,245 +944,2718 @@ fn test_write_16_error_pends() {
     );
 }
 
+fn poll_transfer<F: Future<Output = Result<(), TransferError<()>>>>(
+    c: &mut core::task::Context,
+    fut: F,
+) -> Result<(), TransferError<()>> {
+    let fut = pin!(fut);
+    fut.poll(c).to_option().unwrap()
+}
+
 #[test]
-fn test_report_supported_operation_codes() {
+fn test_read_blocks_default_split() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0xA3
-                        && c[1] == 0xC
-                        && c[3] == 0xF0
-                        && c[4] == 0
-                        && c[5] == 0
-                })
-                .returning(command_ok_with(
-                    ReportSupportedOperationCodesReply {
-                        reserved: 0,
-                        support: 3,
-                        cdb_size: [0; 2],
-                    },
-                ));
+            t.expect_in(&read_10(1000, 128), &[0; 128 * 512])
+                .expect_in(&read_10(1128, 128), &[0; 128 * 512])
+                .expect_in(&read_10(1256, 44), &[0; 44 * 512]);
         },
         |mut f| {
-            let supported =
-                f.c.check_ok(f.d.report_supported_operation_codes(0xF0, None));
-            assert!(supported);
+            let mut buf = vec![0u8; 300 * 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(1000, 300, &mut buf)),
+                Ok(())
+            );
         },
     );
 }
 
 #[test]
-fn test_report_supported_operation_codes_fails() {
+fn test_read_blocks_transport_limit() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0xA3
-                        && c[1] == 0xC
-                        && c[3] == 0xF0
-                        && c[4] == 0
-                        && c[5] == 0
-                })
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_in(&read_10(0, 8), &[0; 8 * 512])
+                .expect_in(&read_10(8, 2), &[0; 2 * 512]);
         },
         |mut f| {
-            f.c.check_fails(f.d.report_supported_operation_codes(0xF0, None));
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let mut buf = vec![0u8; 10 * 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(0, 10, &mut buf)),
+                Ok(())
+            );
         },
     );
 }
 
 #[test]
-fn test_report_supported_operation_codes_pends() {
+fn test_read_blocks_device_limit() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0xA3
-                        && c[1] == 0xC
-                        && c[3] == 0xF0
-                        && c[4] == 0
-                        && c[5] == 0
-                })
-                .returning(command_in_pends);
+            t.expect_in(
+                &BLOCK_LIMITS,
+                bytemuck::bytes_of(&BlockLimitsPage {
+                    maximum_transfer_length: 16u32.to_be_bytes(),
+                    ..valid_block_limits()
+                }),
+            )
+            .expect_in(&read_10(0, 16), &[0; 16 * 512])
+            .expect_in(&read_10(16, 4), &[0; 4 * 512]);
         },
         |mut f| {
-            f.c.check_pends(f.d.report_supported_operation_codes(0xF0, None));
+            f.d.transport.max_transfer_bytes = Some(65536);
+            f.c.check_ok(f.d.block_limits_page());
+            let mut buf = vec![0u8; 20 * 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(0, 20, &mut buf)),
+                Ok(())
+            );
         },
     );
 }
 
 #[test]
-fn test_inquiry() {
+fn test_read_blocks_beyond_2tb() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x12 && c[1] == 0x0 && c[4] >= 36)
-                .returning(command_ok_with(StandardInquiryData {
-                    peripheral_device_type: 5,
-                    removable: 0x80,
-                    ..Default::default()
-                }));
+            t.expect_in(&read_10(0xFFFF_FF00, 128), &[0; 128 * 512])
+                .expect_in(&read_16(0xFFFF_FF80, 128), &[0; 128 * 512]);
         },
         |mut f| {
-            let data = f.c.check_ok(f.d.inquiry());
-            assert_eq!(data.peripheral_type, PeripheralType::Optical);
-            assert!(data.is_removable);
+            let mut buf = vec![0u8; 256 * 512];
+            assert_eq!(
+                poll_transfer(
+                    f.c,
+                    f.d.read_blocks(0xFFFF_FF00, 256, &mut buf)
+                ),
+                Ok(())
+            );
         },
     );
 }
 
 #[test]
-fn test_inquiry_fails() {
+fn test_read_blocks_partial_failure() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x12 && c[1] == 0x0 && c[4] >= 36)
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            t.expect_in(&read_10(0, 8), &[0; 8 * 512])
+                .expect_check_condition(&read_10(8, 8), overheat());
         },
         |mut f| {
-            f.c.check_fails(f.d.inquiry());
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let mut buf = vec![0u8; 20 * 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(0, 20, &mut buf)),
+                Err(TransferError {
+                    blocks_done: 8,
+                    error: Error::Scsi(ScsiError::Overheat),
+                })
+            );
         },
     );
 }
 
 #[test]
-fn test_inquiry_pends() {
+fn test_read_blocks_partial_short_read() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 0x12 && c[1] == 0x0 && c[4] >= 36)
-                .returning(command_in_pends);
+            t.expect_in(&read_10(0, 8), &[0; 1536]);
         },
         |mut f| {
-            f.c.check_pends(f.d.inquiry());
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let mut buf = vec![0u8; 20 * 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(0, 20, &mut buf)),
+                Err(TransferError {
+                    blocks_done: 3,
+                    error: Error::ProtocolError,
+                })
+            );
         },
     );
 }
 
 #[test]
-fn test_block_limits_page() {
+fn test_read_blocks_uninit() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x12 && c[1] == 1 && c[2] == 176 && c[4] >= 64
-                })
-                .returning(command_ok_with(BlockLimitsPage {
-                    peripheral_device_type: 5,
-                    optimal_transfer_length_granularity: 16384u16
-                        .to_be_bytes(),
-                    ..Default::default()
-                }));
+            t.expect_in(&read_10(0, 8), &[0x5A; 8 * 512])
+                .expect_in(&read_10(8, 2), &[0x5A; 2 * 512]);
         },
         |mut f| {
-            let data = f.c.check_ok(f.d.block_limits_page());
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let mut buf = vec![MaybeUninit::uninit(); 10 * 512];
+            let fut = pin!(f.d.read_blocks_uninit(0, 10, &mut buf));
+            let data = fut.poll(f.c).to_option().unwrap().unwrap();
+            assert_eq!(data.len(), 10 * 512);
+            assert!(data.iter().all(|b| *b == 0x5A));
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_uninit_short_read() {
+    do_test(
+        |t| {
+            t.expect_in(&read_10(0, 8), &[0; 1536]);
+        },
+        |mut f| {
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let mut buf = vec![MaybeUninit::uninit(); 20 * 512];
+            let fut = pin!(f.d.read_blocks_uninit(0, 20, &mut buf));
             assert_eq!(
-                u16::from_be_bytes(data.optimal_transfer_length_granularity),
-                16384
+                fut.poll(f.c).to_option().unwrap(),
+                Err(TransferError {
+                    blocks_done: 3,
+                    error: Error::ProtocolError,
+                })
             );
         },
     );
 }
 
 #[test]
-fn test_block_limits_page_fails() {
+fn test_command_in_uninit() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x12 && c[1] == 1 && c[2] == 176 && c[4] >= 64
+            t.expect_in(&INQUIRY, &[1, 2, 3]);
+        },
+        |mut f| {
+            let mut buf = [MaybeUninit::uninit(); 36];
+            {
+                let fut = pin!(f.d.command_in_uninit(INQUIRY, &mut buf));
+                // Only what was transferred is returned
+                assert_eq!(
+                    fut.poll(f.c).to_option().unwrap(),
+                    Ok(&mut [1, 2, 3][..])
+                );
+            }
+            assert_eq!(f.d.stats().bytes_read, 3);
+        },
+    );
+}
+
+#[test]
+fn test_read_blocks_bad_buffer() {
+    do_test(
+        |_| {},
+        |mut f| {
+            let mut buf = vec![0u8; 1000];
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(0, 3, &mut buf)),
+                Err(TransferError {
+                    blocks_done: 0,
+                    error: Error::BadBufferSize,
                 })
-                .returning(command_in_fails);
-            t.expect_request_sense();
+            );
         },
+    );
+}
+
+#[test]
+fn test_read_blocks_none() {
+    do_test(
+        |_| {},
         |mut f| {
-            f.c.check_fails(f.d.block_limits_page());
+            assert_eq!(
+                poll_transfer(f.c, f.d.read_blocks(0, 0, &mut [])),
+                Ok(())
+            );
         },
     );
 }
 
 #[test]
-fn test_block_limits_page_pends() {
+fn test_write_blocks_split() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| {
-                    c[0] == 0x12 && c[1] == 1 && c[2] == 176 && c[4] >= 64
-                })
-                .returning(command_in_pends);
+            t.expect_out(&write_10(5, 8), &[1; 8 * 512])
+                .expect_out(&write_10(13, 1), &[2; 512]);
         },
         |mut f| {
-            f.c.check_pends(f.d.block_limits_page());
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let mut buf = vec![1u8; 9 * 512];
+            buf[8 * 512..].fill(2);
+            assert_eq!(
+                poll_transfer(f.c, f.d.write_blocks(5, 9, &buf)),
+                Ok(())
+            );
         },
     );
 }
 
 #[test]
-fn test_two_factor_error() {
+fn test_write_blocks_partial_failure() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_ok_with(RequestSenseReply {
-                    sense_key: 5,
-                    additional_sense_code: 0x20,
-                    ..Default::default()
-                }));
+            t.expect_out(&write_10(0, 8), &[1; 8 * 512])
+                .expect_check_condition(&write_10(8, 1), overheat());
         },
         |mut f| {
-            let fut = pin!(f.d.try_upgrade_error(Error::CommandFailed));
-            let result = fut.poll(f.c).to_option().unwrap();
+            f.d.transport.max_transfer_bytes = Some(4096);
+            let buf = vec![1u8; 9 * 512];
             assert_eq!(
-                result,
-                Error::Scsi(ScsiError::InvalidCommandOperationCode),
+                poll_transfer(f.c, f.d.write_blocks(0, 9, &buf)),
+                Err(TransferError {
+                    blocks_done: 8,
+                    error: Error::Scsi(ScsiError::Overheat),
+                })
             );
         },
     );
 }
 
 #[test]
-fn test_one_factor_error() {
+fn test_read_blocks_16_unsupported() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.supports_16 = Some(false);
+            let mut buf = [0u8; 512];
+            assert_eq!(
+                poll_transfer(
+                    f.c,
+                    f.d.read_blocks(0x1_0000_0000, 1, &mut buf)
+                ),
+                Err(TransferError {
+                    blocks_done: 0,
+                    error: Error::Scsi(
+                        ScsiError::LogicalBlockAddressOutOfRange
+                    ),
+                })
+            );
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_16_unsupported() {
+    do_test(
+        |_| {},
+        |mut f| {
+            f.d.supports_16 = Some(false);
+            let buf = [0u8; 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.write_blocks(0x1_0000_0000, 1, &buf)),
+                Err(TransferError {
+                    blocks_done: 0,
+                    error: Error::Scsi(
+                        ScsiError::LogicalBlockAddressOutOfRange
+                    ),
+                })
+            );
+        },
+    );
+}
+
+#[test]
+fn test_write_blocks_too_large() {
+    do_test(
+        |_| {},
+        |mut f| {
+            let buf = [0u8; 512];
+            assert_eq!(
+                poll_transfer(f.c, f.d.write_blocks(u64::MAX, 1, &buf)),
+                Err(TransferError {
+                    blocks_done: 0,
+                    error: Error::Scsi(
+                        ScsiError::LogicalBlockAddressOutOfRange
+                    ),
+                })
+            );
+        },
+    );
+}
+
+#[test]
+fn test_report_supported_operation_codes() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &rsoc(0xF0),
+                bytemuck::bytes_of(&ReportSupportedOperationCodesReply {
+                    reserved: 0,
+                    support: 3,
+                    cdb_size: [0; 2],
+                }),
+            );
+        },
+        |mut f| {
+            let supported =
+                f.c.check_ok(f.d.report_supported_operation_codes(0xF0, None));
+            assert!(supported);
+        },
+    );
+}
+
+#[test]
+fn test_report_supported_operation_codes_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&rsoc(0xF0), overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.report_supported_operation_codes(0xF0, None));
+        },
+    );
+}
+
+#[test]
+fn test_report_supported_operation_codes_pends() {
+    do_test(
+        |t| {
+            t.expect_pending(&rsoc(0xF0));
+        },
+        |mut f| {
+            f.c.check_pends(f.d.report_supported_operation_codes(0xF0, None));
+        },
+    );
+}
+
+#[test]
+fn test_inquiry() {
     do_test(
         |t| {
-            t.expect_command_in()
-                .times(1)
-                .withf(|c, _| c[0] == 3)
-                .returning(command_ok_with(RequestSenseReply {
-                    sense_key: 7,
+            t.expect_in(
+                &INQUIRY,
+                bytemuck::bytes_of(&StandardInquiryData {
+                    peripheral_device_type: 5,
+                    removable: 0x80,
                     ..Default::default()
-                }));
+                }),
+            );
         },
         |mut f| {
-            let fut = pin!(f.d.try_upgrade_error(Error::CommandFailed));
-            let result = fut.poll(f.c).to_option().unwrap();
-            assert_eq!(result, Error::Scsi(ScsiError::DataProtect),);
+            let data = f.c.check_ok(f.d.inquiry());
+            assert_eq!(data.peripheral_type, PeripheralType::Optical);
+            assert!(data.is_removable);
         },
     );
 }
 
 #[test]
-fn test_protocol_error_not_sensed() {
+fn test_inquiry_fails() {
     do_test(
         |t| {
-            t.expect_command_in().times(0);
+            t.expect_check_condition(&INQUIRY, overheat());
         },
         |mut f| {
-            let fut = pin!(f.d.try_upgrade_error(Error::ProtocolError));
-            let result = fut.poll(f.c).to_option().unwrap();
-            assert_eq!(result, Error::ProtocolError,);
+            f.c.check_fails(f.d.inquiry());
+        },
+    );
+}
+
+#[test]
+fn test_inquiry_pends() {
+    do_test(
+        |t| {
+            t.expect_pending(&INQUIRY);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.inquiry());
+        },
+    );
+}
+
+#[test]
+fn test_inquiry_short() {
+    do_test(
+        |t| {
+            t.expect_in(&INQUIRY, &[0, 0x80, 0, 0, 0]);
+        },
+        |mut f| {
+            let data = f.c.check_ok(f.d.inquiry());
+            assert_eq!(data.peripheral_type, PeripheralType::Disk);
+            assert!(data.is_removable);
+            assert_eq!(data.vendor(), "");
+            assert_eq!(data.product(), "");
+        },
+    );
+}
+
+#[test]
+fn test_inquiry_too_short() {
+    do_test(
+        |t| {
+            t.expect_in(&INQUIRY, &[0]);
+        },
+        |mut f| {
+            f.c.check_fails_custom(f.d.inquiry(), Error::ProtocolError);
+        },
+    );
+}
+
+#[test]
+fn test_command_response_strict() {
+    do_test(
+        |t| {
+            t.expect_in(&INQUIRY, &[0u8; 8]);
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.d.command_response::<_, [u8; 16]>(Inquiry::new(None, 36)),
+                Error::ProtocolError,
+            );
+        },
+    );
+}
+
+#[test]
+fn test_command_response_partial() {
+    do_test(
+        |t| {
+            t.expect_in(&INQUIRY, &[5u8; 8]);
         },
+        |mut f| {
+            let (reply, sz) = f.c.check_ok(
+                f.d.command_response_partial::<_, StandardInquiryData>(
+                    Inquiry::new(None, 36),
+                ),
+            );
+            assert_eq!(sz, 8);
+            assert_eq!(reply.peripheral_device_type, 5);
+            assert_eq!(reply.vendor_id, [0u8; 8]);
+        },
+    );
+}
+
+#[test]
+fn test_command_out() {
+    do_test(
+        |t| {
+            t.expect_out(&[0x55; 10], &[1, 2, 3]);
+        },
+        |mut f| {
+            let n = f.c.check_ok(f.d.command_out([0x55u8; 10], &[1, 2, 3]));
+            assert_eq!(n, 3);
+        },
+    );
+}
+
+#[test]
+fn test_command_out_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&[0x55; 10], overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.command_out([0x55u8; 10], &[1, 2, 3]));
+        },
+    );
+}
+
+#[test]
+fn test_command_no_data() {
+    do_test(
+        |t| {
+            t.expect_no_data(&[0x35; 10]);
+        },
+        |mut f| {
+            f.c.check_ok(f.d.command_no_data([0x35u8; 10]));
+        },
+    );
+}
+
+#[test]
+fn test_command_no_data_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&[0x35; 10], overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.command_no_data([0x35u8; 10]));
+        },
+    );
+}
+
+/// An empty but valid Block Limits page
+fn valid_block_limits() -> BlockLimitsPage {
+    BlockLimitsPage {
+        page_code: 0xB0,
+        page_length: 0x3C_u16.to_be_bytes(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_block_limits_page() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &BLOCK_LIMITS,
+                bytemuck::bytes_of(&BlockLimitsPage {
+                    peripheral_device_type: 5,
+                    optimal_transfer_length_granularity: 16384u16
+                        .to_be_bytes(),
+                    ..valid_block_limits()
+                }),
+            );
+        },
+        |mut f| {
+            let data = f.c.check_ok(f.d.block_limits_page());
+            assert_eq!(
+                data.optimal_transfer_length_granularity(),
+                Some(16384)
+            );
+            assert_eq!(data.maximum_transfer_length_blocks(), None);
+        },
+    );
+}
+
+#[test]
+fn test_block_limits_page_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&BLOCK_LIMITS, overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.block_limits_page());
+        },
+    );
+}
+
+#[test]
+fn test_block_limits_page_pends() {
+    do_test(
+        |t| {
+            t.expect_pending(&BLOCK_LIMITS);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.block_limits_page());
+        },
+    );
+}
+
+#[test]
+fn test_block_limits_page_short() {
+    do_test(
+        |t| {
+            let mut reply = [0u8; 16];
+            reply[1] = 0xB0;
+            reply[3] = 0x0C;
+            reply[8..12].copy_from_slice(&256u32.to_be_bytes());
+            t.expect_in(&BLOCK_LIMITS, &reply);
+        },
+        |mut f| {
+            let data = f.c.check_ok(f.d.block_limits_page());
+            assert_eq!(data.maximum_transfer_length_blocks(), Some(256));
+            assert_eq!(data.unmap_granularity(), None);
+            assert_eq!(f.d.max_transfer_blocks, Some(256));
+        },
+    );
+}
+
+#[test]
+fn test_block_limits_page_too_short() {
+    do_test(
+        |t| {
+            t.expect_in(&BLOCK_LIMITS, &[0u8; 2]);
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.d.block_limits_page(),
+                Error::ProtocolError,
+            );
+        },
+    );
+}
+
+fn block_limits_from(reply: &[u8]) -> Result<BlockLimitsPage, TestError> {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&BLOCK_LIMITS, reply);
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.block_limits_page().now_or_never().unwrap();
+    if rc.is_err() {
+        assert_eq!(d.max_transfer_blocks, None);
+    }
+    rc
+}
+
+#[test]
+fn test_block_limits_page_accessors() {
+    #[rustfmt::skip]
+    let page = block_limits_from(&[
+        0, 0xB0, 0, 0x3C, 0, 0, 0, 8,
+        0, 0, 1, 0, 0, 0, 0, 0x80,
+        0, 0, 0, 0x40, 0xFF, 0xFF, 0xFF, 0xFF,
+        0, 0, 0, 1, 0, 0, 0, 8,
+        0x80, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ])
+    .unwrap();
+    assert_eq!(page.page_length(), 0x3C);
+    assert_eq!(page.optimal_transfer_length_granularity(), Some(8));
+    assert_eq!(page.maximum_transfer_length_blocks(), Some(256));
+    assert_eq!(page.optimal_transfer_length(), Some(128));
+    assert_eq!(page.maximum_prefetch_length(), Some(64));
+    assert_eq!(page.maximum_unmap_lba_count(), Some(u32::MAX));
+    assert_eq!(page.maximum_unmap_block_descriptor_count(), Some(1));
+    assert_eq!(page.unmap_granularity(), Some(8));
+    assert_eq!(page.unmap_granularity_alignment(), Some(2));
+    assert_eq!(page.maximum_write_same_length(), Some(1 << 32));
+    assert_eq!(page.maximum_atomic_transfer_length(), None);
+}
+
+#[test]
+fn test_block_limits_page_alignment_invalid() {
+    let mut reply = [0u8; 64];
+    reply[1] = 0xB0;
+    reply[3] = 0x3C;
+    reply[35] = 2; // but UGAVALID clear
+    let page = block_limits_from(&reply).unwrap();
+    assert_eq!(page.unmap_granularity_alignment(), None);
+}
+
+#[test]
+fn test_block_limits_page_wrong_page() {
+    let mut reply = [0u8; 64];
+    reply[1] = 0xB1;
+    reply[3] = 0x3C;
+    reply[8..12].copy_from_slice(&256u32.to_be_bytes());
+    assert_eq!(block_limits_from(&reply), Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_block_limits_page_bad_length() {
+    let mut reply = [0u8; 64];
+    reply[1] = 0xB0;
+    reply[3] = 0x08;
+    reply[8..12].copy_from_slice(&256u32.to_be_bytes());
+    assert_eq!(block_limits_from(&reply), Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_block_limits_page_beyond_length() {
+    // Only the first 16 bytes are valid, whatever else is sent
+    let mut reply = [0xFFu8; 64];
+    reply[0..4].copy_from_slice(&[0, 0xB0, 0, 0x0C]);
+    reply[8..12].copy_from_slice(&256u32.to_be_bytes());
+    let page = block_limits_from(&reply).unwrap();
+    assert_eq!(page.maximum_transfer_length_blocks(), Some(256));
+    assert_eq!(page.maximum_unmap_lba_count(), None);
+    assert_eq!(page.maximum_write_same_length(), None);
+}
+
+#[test]
+fn test_block_limits_page_debug() {
+    let mut reply = [0u8; 64];
+    reply[1] = 0xB0;
+    reply[3] = 0x3C;
+    reply[8..12].copy_from_slice(&256u32.to_be_bytes());
+    let page = block_limits_from(&reply).unwrap();
+    let s = format!("{page:?}");
+    assert!(s.starts_with(
+        "BlockLimitsPage { maximum_transfer_length: Some(256), "
+    ));
+    assert!(s.contains("unmap_granularity: None"));
+}
+
+/// The test devices listed in the ScsiDevice documentation
+struct Profile {
+    vendor: &'static [u8; 8],
+    product: &'static [u8; 16],
+    last_lba: u32,
+    removable: bool,
+    supports_16: bool,
+}
+
+#[rustfmt::skip]
+const PROFILES: &[Profile] = &[
+    Profile { vendor: b"Generic ", product: b"Black 4G        ",
+              last_lba: 7_864_319, removable: true, supports_16: true },
+    Profile { vendor: b"Generic ", product: b"Green 16G       ",
+              last_lba: 31_277_055, removable: true, supports_16: true },
+    Profile { vendor: b"Generic ", product: b"Handbag 8G      ",
+              last_lba: 15_728_639, removable: true, supports_16: false },
+    Profile { vendor: b"Generic ", product: b"Poker 1G        ",
+              last_lba: 1_966_079, removable: true, supports_16: false },
+    Profile { vendor: b"JMicron ", product: b"Kingston 120G   ",
+              last_lba: 234_441_647, removable: false, supports_16: true },
+    Profile { vendor: b"JMicron ", product: b"Sandisk 120G    ",
+              last_lba: 234_441_647, removable: false, supports_16: true },
+    Profile { vendor: b"JMicron ", product: b"WD 500G         ",
+              last_lba: 976_773_167, removable: false, supports_16: true },
+];
+
+fn expect_profile(t: &mut FakeScsiTransport, p: &Profile) {
+    // None of them support RSOC or the Block Limits Page
+    let unsupported = testing::sense(5, 0x24, 0);
+    t.expect_in(
+        &INQUIRY,
+        bytemuck::bytes_of(&StandardInquiryData {
+            peripheral_device_type: 0,
+            removable: if p.removable { 0x80 } else { 0 },
+            vendor_id: *p.vendor,
+            product_id: *p.product,
+            product_revision: *b"1.00",
+            ..Default::default()
+        }),
+    )
+    .expect_no_data(&TUR)
+    .expect_in(
+        &READ_CAPACITY_10,
+        bytemuck::bytes_of(&ReadCapacity10Reply {
+            lba: p.last_lba.to_be_bytes(),
+            block_size: 512_u32.to_be_bytes(),
+        }),
+    )
+    .expect_check_condition(&rsoc(0x88), unsupported);
+    if p.supports_16 {
+        t.expect_in(
+            &READ_CAPACITY_16,
+            bytemuck::bytes_of(&ReadCapacity16Reply {
+                lba: (p.last_lba as u64).to_be_bytes(),
+                block_size: 512_u32.to_be_bytes(),
+                ..Default::default()
+            }),
+        );
+    } else {
+        t.expect_check_condition(&READ_CAPACITY_16, unsupported);
+    }
+    t.expect_check_condition(&BLOCK_LIMITS, unsupported)
+        .expect_in(
+            &MODE_SENSE_6,
+            bytemuck::bytes_of(&ModeParameterHeader6::default()),
+        );
+}
+
+#[test]
+fn test_probe_profiles() {
+    for p in PROFILES {
+        do_test(
+            |t| expect_profile(t, p),
+            |mut f| {
+                let caps = f.c.check_ok(f.d.probe());
+                assert_eq!(caps.inquiry.peripheral_type, PeripheralType::Disk);
+                assert_eq!(caps.vendor().as_bytes(), p.vendor.trim_ascii());
+                assert_eq!(caps.product().as_bytes(), p.product.trim_ascii());
+                assert_eq!(caps.inquiry.revision(), "1.00");
+                assert_eq!(caps.blocks, p.last_lba as u64 + 1);
+                assert_eq!(caps.block_size, 512);
+                assert_eq!(caps.is_removable(), p.removable);
+                assert_eq!(caps.supports_16, p.supports_16);
+                assert_eq!(caps.max_transfer_blocks, None);
+                assert_eq!(caps.write_protected, Some(false));
+                assert_eq!(f.d.capabilities(), Some(&caps));
+            },
+        );
+    }
+}
+
+#[test]
+fn test_probe_everything_supported() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &INQUIRY,
+                bytemuck::bytes_of(&StandardInquiryData::default()),
+            )
+            .expect_no_data(&TUR)
+            .expect_in(
+                &READ_CAPACITY_10,
+                bytemuck::bytes_of(&ReadCapacity10Reply {
+                    lba: 0xFFFF_FFFF_u32.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                }),
+            )
+            .expect_in(
+                &READ_CAPACITY_16,
+                bytemuck::bytes_of(&ReadCapacity16Reply {
+                    lba: 0x1_0000_0000_u64.to_be_bytes(),
+                    block_size: 512_u32.to_be_bytes(),
+                    ..Default::default()
+                }),
+            )
+            .expect_in(
+                &BLOCK_LIMITS,
+                bytemuck::bytes_of(&BlockLimitsPage {
+                    maximum_transfer_length: 256u32.to_be_bytes(),
+                    ..valid_block_limits()
+                }),
+            )
+            .expect_in(
+                &MODE_SENSE_6,
+                bytemuck::bytes_of(&ModeParameterHeader6 {
+                    device_specific_parameter: 0x80,
+                    ..Default::default()
+                }),
+            );
+        },
+        |mut f| {
+            let caps = f.c.check_ok(f.d.probe());
+            assert_eq!(caps.blocks, 0x1_0000_0001);
+            assert!(caps.supports_16);
+            assert_eq!(caps.max_transfer_blocks, Some(256));
+            assert_eq!(caps.write_protected, Some(true));
+        },
+    );
+}
+
+/// The commands a probe can't do without: INQUIRY, TEST UNIT READY,
+/// READ CAPACITY(10) (of a 64-block disk), and MODE SENSE(6)
+fn expect_minimal_probe(fake: &mut FakeScsiTransport) {
+    let mut inquiry = [0u8; 36];
+    inquiry[4] = 31;
+    fake.expect_in(&INQUIRY, &inquiry)
+        .expect_no_data(&TUR)
+        .expect_in(&READ_CAPACITY_10, &[0, 0, 0, 63, 0, 0, 2, 0])
+        .expect_in(&MODE_SENSE_6, &[3, 0, 0, 0]);
+}
+
+#[test]
+fn test_probe_preloaded() {
+    // No RSOC, no READ CAPACITY(16), no Block Limits Page
+    let mut fake = FakeScsiTransport::new();
+    expect_minimal_probe(&mut fake);
+    let mut d = ScsiDevice::new(fake);
+    let hints = ProbeHints {
+        supports_16: false,
+        max_transfer_blocks: Some(128),
+    };
+    d.preload(hints);
+    let caps = d.probe().now_or_never().unwrap().unwrap();
+    assert_eq!(caps.blocks, 64);
+    assert!(!caps.supports_16);
+    assert_eq!(caps.max_transfer_blocks, Some(128));
+    assert_eq!(d.probe_hints(), Some(hints));
+}
+
+#[test]
+fn test_probe_hints() {
+    let mut fake = FakeScsiTransport::new();
+    fake.quirks.no_vpd = true;
+    fake.quirks.no_rsoc = true;
+    let mut inquiry = [0u8; 36];
+    inquiry[4] = 31;
+    // RSOC fails straight away (quirk), so READ CAPACITY(16) is tried
+    fake.expect_in(&[0x12, 0, 0, 0, 36, 0], &inquiry)
+        .expect_no_data(&[0; 6])
+        .expect_in(
+            &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 63, 0, 0, 2, 0],
+        )
+        .expect_check_condition(
+            &[0x9E, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0],
+            testing::sense(5, 0x20, 0),
+        )
+        .expect_in(&[0x1A, 8, 0x3F, 0, 4, 0], &[3, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.probe_hints(), None);
+    d.probe().now_or_never().unwrap().unwrap();
+    assert_eq!(
+        d.probe_hints(),
+        Some(ProbeHints {
+            supports_16: false,
+            max_transfer_blocks: None,
+        })
+    );
+}
+
+#[test]
+fn test_probe_becoming_ready() {
+    do_test(
+        |t| {
+            let becoming_ready = testing::sense(2, 4, 1);
+            t.expect_in(
+                &INQUIRY,
+                bytemuck::bytes_of(&StandardInquiryData::default()),
+            )
+            .expect_check_condition(&TUR, becoming_ready)
+            .expect_check_condition(&TUR, becoming_ready)
+            .expect_no_data(&TUR)
+            .expect_pending(&READ_CAPACITY_10);
+        },
+        |mut f| {
+            f.c.check_pends(f.d.probe());
+        },
+    );
+}
+
+#[test]
+fn test_probe_never_ready() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &INQUIRY,
+                bytemuck::bytes_of(&StandardInquiryData::default()),
+            );
+            for _ in 0..10 {
+                t.expect_check_condition(&TUR, testing::sense(2, 4, 1));
+            }
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.d.probe(),
+                Error::Scsi(ScsiError::BecomingReady),
+            );
+            assert_eq!(f.d.capabilities(), None);
+        },
+    );
+}
+
+#[test]
+fn test_probe_inquiry_fails() {
+    do_test(
+        |t| {
+            t.expect_check_condition(&INQUIRY, overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.probe());
+        },
+    );
+}
+
+#[test]
+fn test_probe_not_ready() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &INQUIRY,
+                bytemuck::bytes_of(&StandardInquiryData::default()),
+            )
+            .expect_check_condition(&TUR, overheat());
+        },
+        |mut f| {
+            f.c.check_fails(f.d.probe());
+        },
+    );
+}
+
+#[test]
+fn test_two_factor_error() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &testing::request_sense_cdb(),
+                &testing::fixed_sense(&testing::sense(5, 0x20, 0)),
+            );
+        },
+        |mut f| {
+            let fut = pin!(f.d.try_upgrade_error(Error::CommandFailed));
+            let result = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(
+                result,
+                Error::Scsi(ScsiError::InvalidCommandOperationCode),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_one_factor_error() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &testing::request_sense_cdb(),
+                &testing::fixed_sense(&testing::sense(7, 0, 0)),
+            );
+        },
+        |mut f| {
+            let fut = pin!(f.d.try_upgrade_error(Error::CommandFailed));
+            let result = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(result, Error::Scsi(ScsiError::DataProtect),);
+        },
+    );
+}
+
+#[test]
+fn test_last_sense() {
+    do_test(
+        |t| {
+            t.expect_error(&read_10(0x1234_5678, 1), Error::CommandFailed)
+                .expect_in(
+                    &testing::request_sense_cdb(),
+                    bytemuck::bytes_of(&RequestSenseReply {
+                        response_code: 0xF0,
+                        sense_key: 0x23, // ILI plus medium error
+                        information: 0x1234_5678_u32.to_be_bytes(),
+                        additional_sense_code: 0x11,
+                        additional_sense_code_qualifier: 0x87,
+                        ..Default::default()
+                    }),
+                );
+        },
+        |mut f| {
+            assert_eq!(f.d.last_sense(), None);
+            let mut buf = [0u8; 512];
+            f.c.check_fails_custom(
+                f.d.read_10(0x1234_5678, 1, &mut buf),
+                Error::Scsi(ScsiError::MediumError),
+            );
+            assert_eq!(
+                f.d.last_sense(),
+                Some(SenseData {
+                    key: 3,
+                    asc: 0x11,
+                    ascq: 0x87,
+                    information: Some(0x1234_5678),
+                    sense_key_specific: None,
+                })
+            );
+        },
+    );
+}
+
+#[test]
+fn test_last_sense_no_information() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &testing::request_sense_cdb(),
+                bytemuck::bytes_of(&RequestSenseReply {
+                    response_code: 0x70,
+                    sense_key: 5,
+                    information: 0x1234_5678_u32.to_be_bytes(),
+                    additional_sense_code: 0x20,
+                    ..Default::default()
+                }),
+            );
+        },
+        |mut f| {
+            let result = {
+                let fut = pin!(f.d.try_upgrade_error(Error::CommandFailed));
+                fut.poll(f.c).to_option().unwrap()
+            };
+            assert_eq!(
+                result,
+                Error::Scsi(ScsiError::InvalidCommandOperationCode)
+            );
+            assert_eq!(
+                f.d.last_sense(),
+                Some(SenseData {
+                    key: 5,
+                    asc: 0x20,
+                    ascq: 0,
+                    information: None,
+                    sense_key_specific: None,
+                })
+            );
+        },
+    );
+}
+
+#[test]
+fn test_unmapped_sense() {
+    do_test(
+        |t| {
+            t.expect_in(
+                &testing::request_sense_cdb(),
+                &testing::fixed_sense(&testing::sense(0xF, 0x80, 0x01)),
+            );
+        },
+        |mut f| {
+            let result = {
+                let fut = pin!(f.d.try_upgrade_error(Error::CommandFailed));
+                fut.poll(f.c).to_option().unwrap()
+            };
+            assert_eq!(result, Error::CommandFailed);
+            assert_eq!(f.d.last_sense().unwrap().asc, 0x80);
+        },
+    );
+}
+
+/// Fire a failure through try_upgrade_error, with the given reply to
+/// REQUEST SENSE, and return the upgraded error and resulting sense data
+fn sense_of(reply: &[u8]) -> (TestError, Option<SenseData>) {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&testing::request_sense_cdb(), reply);
+    let mut d = ScsiDevice::new(fake);
+    let e = d
+        .try_upgrade_error(Error::CommandFailed)
+        .now_or_never()
+        .unwrap();
+    (e, d.last_sense())
+}
+
+#[test]
+fn test_descriptor_sense() {
+    #[rustfmt::skip]
+    let (e, sense) = sense_of(&[
+        0x72, 3, 0x11, 0, 0, 0, 0, 24,
+        // Vendor-specific descriptor
+        0x80, 2, 0xAA, 0xBB,
+        // Information descriptor
+        0, 10, 0x80, 0, 0, 0, 0, 1, 0x23, 0x45, 0x67, 0x89,
+        // Sense-key-specific descriptor
+        2, 6, 0, 0, 0, 0, 0, 0,
+    ]);
+    assert_eq!(e, Error::Scsi(ScsiError::MediumError));
+    assert_eq!(
+        sense,
+        Some(SenseData {
+            key: 3,
+            asc: 0x11,
+            ascq: 0,
+            information: Some(0x1_2345_6789),
+            sense_key_specific: None,
+        })
+    );
+}
+
+#[test]
+fn test_descriptor_sense_deferred() {
+    let (e, sense) = sense_of(&[0x73, 5, 0x20, 0, 0, 0, 0, 0]);
+    assert_eq!(e, Error::Scsi(ScsiError::InvalidCommandOperationCode));
+    assert_eq!(sense.unwrap().information, None);
+}
+
+#[test]
+fn test_descriptor_sense_information_not_valid() {
+    #[rustfmt::skip]
+    let (_, sense) = sense_of(&[
+        0x72, 3, 0x11, 0, 0, 0, 0, 12,
+        0, 10, 0, 0, 0, 0, 0, 1, 0x23, 0x45, 0x67, 0x89,
+    ]);
+    assert_eq!(sense.unwrap().information, None);
+}
+
+#[test]
+fn test_descriptor_sense_truncated() {
+    // Additional length claims more than was sent
+    #[rustfmt::skip]
+    let (e, sense) = sense_of(&[
+        0x72, 3, 0x11, 0, 0, 0, 0, 40,
+        0, 10, 0x80, 0, 0, 0, 0, 1,
+    ]);
+    assert_eq!(e, Error::Scsi(ScsiError::MediumError));
+    assert_eq!(sense.unwrap().information, None);
+}
+
+#[test]
+fn test_descriptor_sense_too_short() {
+    let (e, sense) = sense_of(&[0x72, 3, 0x11, 0]);
+    assert_eq!(e, Error::CommandFailed);
+    assert_eq!(sense, None);
+}
+
+#[test]
+fn test_fixed_sense_too_short() {
+    let (e, sense) = sense_of(&[0x70, 0, 3, 0, 0, 0, 0, 10, 0, 0]);
+    assert_eq!(e, Error::CommandFailed);
+    assert_eq!(sense, None);
+}
+
+#[test]
+fn test_protocol_error_not_sensed() {
+    do_test(
+        |_| {},
+        |mut f| {
+            let fut = pin!(f.d.try_upgrade_error(Error::ProtocolError));
+            let result = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(result, Error::ProtocolError,);
+        },
+    );
+}
+
+pub const TUR: [u8; 6] = [0; 6];
+pub const INQUIRY: [u8; 6] = [0x12, 0, 0, 0, 36, 0];
+
+fn inquiry_reply(removable: bool) -> [u8; 36] {
+    let mut reply = [0u8; 36];
+    reply[1] = if removable { 0x80 } else { 0 };
+    reply
+}
+
+#[test]
+fn test_media_sense_mapping() {
+    assert_eq!(
+        sense_of(&testing::fixed_sense(&testing::sense(6, 0x28, 0))).0,
+        Error::Scsi(ScsiError::MediaChanged)
+    );
+    assert_eq!(
+        sense_of(&testing::fixed_sense(&testing::sense(6, 0x3A, 2))).0,
+        Error::Scsi(ScsiError::MediaNotPresent)
+    );
+    assert_eq!(
+        sense_of(&testing::fixed_sense(&testing::sense(2, 0x3A, 0))).0,
+        Error::Scsi(ScsiError::MediaNotPresent)
+    );
+    assert_eq!(
+        sense_of(&testing::fixed_sense(&testing::sense(6, 0x29, 0))).0,
+        Error::Scsi(ScsiError::ResetOccurred)
+    );
+    assert_eq!(
+        sense_of(&testing::fixed_sense(&testing::sense(6, 0x2A, 1))).0,
+        Error::Scsi(ScsiError::UnitAttention)
+    );
+}
+
+#[test]
+fn test_check_media() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR)
+        .expect_check_condition(&TUR, testing::sense(6, 0x28, 0))
+        .expect_check_condition(&TUR, testing::sense(6, 0x29, 0))
+        .expect_check_condition(&TUR, testing::sense(2, 0x3A, 0))
+        .expect_check_condition(&TUR, testing::sense(2, 4, 1))
+        .expect_check_condition(&TUR, testing::sense(2, 4, 2))
+        .expect_check_condition(&TUR, testing::sense(4, 0x44, 0));
+    let mut d = ScsiDevice::new(fake);
+    let mut check = || d.check_media().now_or_never().unwrap();
+    assert_eq!(check(), Ok(MediaState::Ready));
+    assert_eq!(check(), Ok(MediaState::Changed));
+    assert_eq!(check(), Ok(MediaState::Changed));
+    assert_eq!(check(), Ok(MediaState::NotPresent));
+    assert_eq!(check(), Ok(MediaState::NotReady));
+    assert_eq!(check(), Ok(MediaState::NotReady));
+    assert_eq!(check(), Err(Error::Scsi(ScsiError::HardwareError)));
+}
+
+#[test]
+fn test_check_media_forgets_capabilities() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR)
+        .expect_check_condition(&TUR, testing::sense(6, 0x28, 0));
+    let mut d = ScsiDevice::new(fake);
+    d.capabilities = Some(DeviceCapabilities::default());
+    assert_eq!(
+        d.check_media().now_or_never().unwrap(),
+        Ok(MediaState::Ready)
+    );
+    assert!(d.capabilities().is_some());
+    assert_eq!(
+        d.check_media().now_or_never().unwrap(),
+        Ok(MediaState::Changed)
+    );
+    assert!(d.capabilities().is_none());
+}
+
+#[test]
+fn test_wait_for_media_insertion() {
+    // A card reader with no card; then a card is inserted
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_check_condition(&TUR, testing::sense(2, 0x3A, 0))
+        .expect_check_condition(&TUR, testing::sense(2, 0x3A, 0))
+        .expect_check_condition(&TUR, testing::sense(6, 0x28, 0));
+    let mut d = ScsiDevice::new(fake);
+    let mut delays = 0;
+    let rc = d
+        .wait_for_media_change(MediaState::NotPresent, |ms| {
+            assert_eq!(ms, MEDIA_POLL_INTERVAL_MS);
+            delays += 1;
+            future::ready(())
+        })
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(Some(MediaState::Changed)));
+    assert_eq!(delays, 2);
+}
+
+#[test]
+fn test_wait_for_media_insertion_without_ua() {
+    // Some readers just go from "not present" to "ready", after a
+    // while becoming ready
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_check_condition(&TUR, testing::sense(2, 4, 1))
+        .expect_no_data(&TUR);
+    let mut d = ScsiDevice::new(fake);
+    let rc = d
+        .wait_for_media_change(MediaState::NotPresent, |_| future::ready(()))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(Some(MediaState::Changed)));
+}
+
+#[test]
+fn test_wait_for_media_removal() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR)
+        .expect_no_data(&TUR)
+        .expect_check_condition(&TUR, testing::sense(6, 0x3A, 0));
+    let mut d = ScsiDevice::new(fake);
+    d.capabilities = Some(DeviceCapabilities {
+        inquiry: InquiryData {
+            is_removable: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let rc = d
+        .wait_for_media_change(MediaState::Changed, |_| future::ready(()))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(Some(MediaState::NotPresent)));
+    assert!(d.capabilities().is_none());
+}
+
+#[test]
+fn test_wait_for_media_swap() {
+    // Card swapped between polls: the device never saw "not present"
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_no_data(&TUR)
+        .expect_check_condition(&TUR, testing::sense(6, 0x28, 0));
+    let mut d = ScsiDevice::new(fake);
+    let rc = d
+        .wait_for_media_change(MediaState::Ready, |_| future::ready(()))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(Some(MediaState::Changed)));
+}
+
+#[test]
+fn test_wait_for_media_becomes_ready() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_check_condition(&TUR, testing::sense(2, 4, 1))
+        .expect_no_data(&TUR);
+    let mut d = ScsiDevice::new(fake);
+    let rc = d
+        .wait_for_media_change(MediaState::NotReady, |_| future::ready(()))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(Some(MediaState::Ready)));
+}
+
+#[test]
+fn test_wait_for_media_not_removable() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(false));
+    let mut d = ScsiDevice::new(fake);
+    let rc = d
+        .wait_for_media_change(MediaState::Ready, |_| -> future::Ready<()> {
+            panic!("shouldn't poll")
+        })
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(None));
+}
+
+#[test]
+fn test_wait_for_media_error() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_error(&TUR, Error::Transport(()));
+    let mut d = ScsiDevice::new(fake);
+    let rc = d
+        .wait_for_media_change(MediaState::Ready, |_| future::ready(()))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::Transport(())));
+}
+
+#[test]
+fn test_probe_after_media_change() {
+    // The first TEST UNIT READY after insertion reports the change
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_check_condition(&TUR, testing::sense(6, 0x28, 0))
+        .expect_no_data(&TUR)
+        .expect_error(
+            &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            Error::Transport(()),
+        );
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.probe().now_or_never().unwrap();
+    assert_eq!(rc.err(), Some(Error::Transport(())));
+}
+
+const MODE_SENSE_6: [u8; 6] = [0x1A, 8, 0x3F, 0, 4, 0];
+const MODE_SENSE_10: [u8; 10] = [0x5A, 8, 0x3F, 0, 0, 0, 0, 0, 8, 0];
+
+fn write_protected_with(
+    fake: FakeScsiTransport,
+) -> Result<Option<bool>, TestError> {
+    ScsiDevice::new(fake)
+        .is_write_protected()
+        .now_or_never()
+        .unwrap()
+}
+
+#[test]
+fn test_write_protected_6() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&MODE_SENSE_6, &[3, 0, 0x80, 0]);
+    assert_eq!(write_protected_with(fake), Ok(Some(true)));
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&MODE_SENSE_6, &[3, 0, 0x10, 0]);
+    assert_eq!(write_protected_with(fake), Ok(Some(false)));
+}
+
+#[test]
+fn test_write_protected_10() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&MODE_SENSE_6, testing::sense(5, 0x20, 0))
+        .expect_in(&MODE_SENSE_10, &[0, 6, 0, 0x80, 0, 0, 0, 0]);
+    assert_eq!(write_protected_with(fake), Ok(Some(true)));
+}
+
+#[test]
+fn test_write_protected_10_after_short_reply() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&MODE_SENSE_6, &[3, 0])
+        .expect_in(&MODE_SENSE_10, &[0, 6, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(write_protected_with(fake), Ok(Some(false)));
+}
+
+#[test]
+fn test_write_protected_unknown() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&MODE_SENSE_6, testing::sense(5, 0x20, 0))
+        .expect_check_condition(&MODE_SENSE_10, testing::sense(5, 0x24, 0));
+    assert_eq!(write_protected_with(fake), Ok(None));
+}
+
+#[test]
+fn test_write_protected_fails() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&MODE_SENSE_6, testing::sense(2, 0x3A, 0));
+    assert_eq!(
+        write_protected_with(fake),
+        Err(Error::Scsi(ScsiError::MediaNotPresent))
+    );
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&MODE_SENSE_6, Error::CommandFailed)
+        .expect_in(&testing::request_sense_cdb(), &[])
+        .expect_error(&MODE_SENSE_10, Error::Transport(()));
+    assert_eq!(write_protected_with(fake), Err(Error::Transport(())));
+}
+
+#[test]
+fn test_probe_write_protected_10() {
+    let mut fake = FakeScsiTransport::new();
+    let mut capacity = [0u8; 8];
+    capacity[0..4].copy_from_slice(&99u32.to_be_bytes());
+    capacity[4..8].copy_from_slice(&512u32.to_be_bytes());
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_no_data(&TUR)
+        .expect_in(&[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0], &capacity)
+        .expect_check_condition(
+            &[0xA3, 0x0C, 3, 0x88, 0, 0, 0, 0, 0, 4, 0, 0],
+            testing::sense(5, 0x20, 0),
+        )
+        .expect_check_condition(
+            &[0x9E, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0],
+            testing::sense(5, 0x20, 0),
+        )
+        .expect_check_condition(
+            &[0x12, 1, 0xB0, 0, 64, 0],
+            testing::sense(5, 0x24, 0),
+        )
+        .expect_check_condition(&MODE_SENSE_6, testing::sense(5, 0x20, 0))
+        .expect_in(&MODE_SENSE_10, &[0, 6, 0, 0x80, 0, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    let caps = d.probe().now_or_never().unwrap().unwrap();
+    assert_eq!(caps.write_protected, Some(true));
+}
+
+#[test]
+fn test_probe_quirks_skip_rsoc_and_vpd() {
+    let mut fake = FakeScsiTransport::new();
+    let mut capacity = [0u8; 8];
+    capacity[0..4].copy_from_slice(&99u32.to_be_bytes());
+    capacity[4..8].copy_from_slice(&512u32.to_be_bytes());
+    // Compare test_probe_write_protected_10: no RSOC, no Block Limits
+    fake.expect_in(&INQUIRY, &inquiry_reply(true))
+        .expect_no_data(&TUR)
+        .expect_in(&[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0], &capacity)
+        .expect_check_condition(
+            &[0x9E, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0],
+            testing::sense(5, 0x20, 0),
+        )
+        .expect_in(&MODE_SENSE_6, &[3, 0, 0, 0]);
+    fake.quirks = ScsiQuirks {
+        no_vpd: true,
+        no_rsoc: true,
+        max_transfer_blocks: Some(8),
+    };
+    let mut d = ScsiDevice::new(fake);
+    assert!(d.quirks().no_vpd);
+    let caps = d.probe().now_or_never().unwrap().unwrap();
+    assert_eq!(caps.max_transfer_blocks, Some(8));
+    assert!(!caps.supports_16);
+}
+
+#[test]
+fn test_quirks_fail_without_issuing() {
+    let mut d = ScsiDevice::new(FakeScsiTransport::new());
+    d.set_quirks(ScsiQuirks {
+        no_vpd: true,
+        no_rsoc: true,
+        max_transfer_blocks: None,
+    });
+    assert_eq!(
+        d.block_limits_page().now_or_never().unwrap().err(),
+        Some(Error::Scsi(ScsiError::InvalidFieldInCDB))
+    );
+    assert_eq!(
+        d.block_device_characteristics_page()
+            .now_or_never()
+            .unwrap()
+            .err(),
+        Some(Error::Scsi(ScsiError::InvalidFieldInCDB))
+    );
+    assert_eq!(
+        d.report_supported_operation_codes(0x88, None)
+            .now_or_never()
+            .unwrap(),
+        Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+    );
+    assert_eq!(d.stats().commands, 0);
+}
+
+#[test]
+fn test_read_blocks_quirk_limit() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x28, 0, 0, 0, 0, 0, 0, 0, 4, 0], &[0u8; 4 * 512])
+        .expect_in(&[0x28, 0, 0, 0, 0, 4, 0, 0, 2, 0], &[0u8; 2 * 512]);
+    fake.quirks.max_transfer_blocks = Some(4);
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = vec![0u8; 6 * 512];
+    assert_eq!(
+        d.read_blocks(0, 6, &mut buf).now_or_never().unwrap(),
+        Ok(())
+    );
+}
+
+std::thread_local! {
+    static TRACE: std::cell::RefCell<Vec<String>> = Default::default();
+}
+
+fn record(e: &TraceEvent<'_, ()>) {
+    TRACE.with(|t| {
+        t.borrow_mut().push(format!(
+            "{:02x?} {:?} {:?}",
+            e.cdb,
+            e.result,
+            e.sense.map(|s| (s.key, s.asc))
+        ))
+    });
+}
+
+fn traced() -> Vec<String> {
+    TRACE.with(|t| t.take())
+}
+
+#[test]
+fn test_tracer() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR)
+        .expect_check_condition(&INQUIRY, testing::sense(5, 0x24, 0))
+        .expect_error(&TUR, Error::Transport(()));
+    let mut d = ScsiDevice::new(fake);
+    d.set_tracer(Some(record));
+    assert_eq!(d.test_unit_ready().now_or_never().unwrap(), Ok(()));
+    assert!(d.inquiry().now_or_never().unwrap().is_err());
+    assert!(d.test_unit_ready().now_or_never().unwrap().is_err());
+    // The REQUEST SENSE appears only as the INQUIRY's sense data
+    assert_eq!(
+        traced(),
+        [
+            "[00, 00, 00, 00, 00, 00] Ok(0) None",
+            "[12, 00, 00, 00, 24, 00] Err(Scsi(InvalidFieldInCDB)) \
+             Some((5, 36))",
+            "[00, 00, 00, 00, 00, 00] Err(Transport(())) None",
+        ]
+    );
+}
+
+#[test]
+fn test_tracer_unset() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR).expect_no_data(&TUR);
+    let mut d = ScsiDevice::new(fake);
+    d.set_tracer(Some(record));
+    d.set_tracer(None);
+    assert_eq!(d.test_unit_ready().now_or_never().unwrap(), Ok(()));
+    assert!(traced().is_empty());
+    d.set_tracer(Some(record));
+    assert_eq!(d.test_unit_ready().now_or_never().unwrap(), Ok(()));
+    assert_eq!(traced().len(), 1);
+}
+
+#[test]
+fn test_raw_command() {
+    let vendor = [0xC0, 1, 2, 3, 4, 5];
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&vendor, &[9, 8, 7])
+        .expect_out(&vendor, &[1, 2])
+        .expect_check_condition(&vendor, testing::sense(5, 0x24, 0));
+    let mut d = ScsiDevice::new(fake);
+    d.set_tracer(Some(record));
+    let mut buf = [0u8; 8];
+    let rc = d.raw_command(&vendor, DataPhase::In(&mut buf));
+    assert_eq!(rc.now_or_never().unwrap(), Ok(3));
+    assert_eq!(buf[0..3], [9, 8, 7]);
+    let rc = d.raw_command(&vendor, DataPhase::Out(&[1, 2]));
+    assert_eq!(rc.now_or_never().unwrap(), Ok(2));
+    // Failures are upgraded using the sense data, like any other command
+    let rc = d.raw_command(&vendor, DataPhase::None);
+    assert_eq!(
+        rc.now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::InvalidFieldInCDB))
+    );
+    assert_eq!(d.last_sense().map(|s| s.asc), Some(0x24));
+    assert_eq!(traced().len(), 3);
+    assert_eq!(d.stats().failures, 1);
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_into_transport() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR).expect_no_data(&TUR);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.test_unit_ready().now_or_never().unwrap(), Ok(()));
+    let mut transport = d.into_transport();
+    let rc = transport.command(&TUR, DataPhase::None);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(0));
+    assert!(transport.is_done());
+}
+
+#[test]
+fn test_format_unit() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&[0x04, 0x10, 0, 0, 0, 0], &[0, 0x02, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.format_unit().now_or_never().unwrap(), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+/// Fixed-format sense data for "format in progress", with progress
+fn format_sense(sksv: u8, progress: u16) -> [u8; 18] {
+    let mut reply = testing::fixed_sense(&testing::sense(2, 4, 4));
+    reply[15] = sksv;
+    reply[16..18].copy_from_slice(&progress.to_be_bytes());
+    reply
+}
+
+fn progress_with(reply: &[u8]) -> Result<Option<u8>, TestError> {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&testing::request_sense_cdb(), reply);
+    let mut d = ScsiDevice::new(fake);
+    d.format_progress().now_or_never().unwrap()
+}
+
+#[test]
+fn test_format_progress() {
+    assert_eq!(progress_with(&format_sense(0x80, 0)), Ok(Some(0)));
+    assert_eq!(progress_with(&format_sense(0x80, 0x8000)), Ok(Some(50)));
+    assert_eq!(progress_with(&format_sense(0x80, 0xFFFF)), Ok(Some(99)));
+}
+
+#[test]
+fn test_format_progress_not_valid() {
+    // SKSV clear: formatting, but progress unknown
+    assert_eq!(progress_with(&format_sense(0, 0x8000)), Ok(Some(0)));
+}
+
+#[test]
+fn test_format_progress_descriptor() {
+    let mut reply = testing::descriptor_sense(&testing::sense(2, 4, 4));
+    reply[7] = 8;
+    reply.truncate(8);
+    reply.extend_from_slice(&[2, 6, 0, 0, 0x80, 0x40, 0x00, 0]);
+    assert_eq!(progress_with(&reply), Ok(Some(25)));
+}
+
+#[test]
+fn test_format_progress_done() {
+    let no_sense = testing::fixed_sense(&testing::sense(0, 0, 0));
+    assert_eq!(progress_with(&no_sense), Ok(None));
+}
+
+#[test]
+fn test_format_progress_garbage() {
+    assert_eq!(progress_with(&[0x70, 0, 2]), Err(Error::ProtocolError));
+}
+
+/// Sense data with a progress indication
+fn progress_sense(key: u8, ascq: u8, progress: u16) -> SenseData {
+    let p = progress.to_be_bytes();
+    SenseData {
+        sense_key_specific: Some([0, p[0], p[1]]),
+        ..testing::sense(key, 4, ascq)
+    }
+}
+
+#[test]
+fn test_sense_key_specific() {
+    let sense = progress_sense(2, 4, 0x1234);
+    let fixed = testing::fixed_sense(&sense);
+    assert_eq!(fixed[15..18], [0x80, 0x12, 0x34]);
+    assert_eq!(parse_sense(&fixed), Some(sense));
+    let descriptor = testing::descriptor_sense(&sense);
+    assert_eq!(parse_sense(&descriptor), Some(sense));
+    assert_eq!(sense.progress(), Some(0x1234));
+
+    // SKSV clear
+    let mut fixed = fixed;
+    fixed[15] = 0;
+    assert_eq!(parse_sense(&fixed).unwrap().sense_key_specific, None);
+
+    // Not a progress indication for "illegal request"
+    let sense = SenseData {
+        sense_key_specific: Some([0x40, 0, 2]),
+        ..testing::sense(5, 0x24, 0)
+    };
+    assert_eq!(sense.progress(), None);
+}
+
+#[test]
+fn test_operation_progress() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&progress_sense(0, 9, 0xC000)),
+    )
+    .expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0, 0)),
+    );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.operation_progress().now_or_never().unwrap(),
+        Ok(Some(0xC000))
+    );
+    assert_eq!(d.operation_progress().now_or_never().unwrap(), Ok(None));
+    assert!(d.transport().is_done());
+    assert_eq!(d.last_sense(), None);
+}
+
+#[test]
+fn test_wait_for_completion() {
+    let mut fake = FakeScsiTransport::new();
+    for progress in [0x2000, 0x8000, 0xE000] {
+        fake.expect_in(
+            &testing::request_sense_cdb(),
+            &testing::fixed_sense(&progress_sense(2, 4, progress)),
+        );
+    }
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0, 0)),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut delays = Vec::new();
+    let rc = d.wait_for_completion(
+        |ms| {
+            delays.push(ms);
+            future::ready(())
+        },
+        250,
+        10_000,
+    );
+    assert_eq!(rc.now_or_never().unwrap(), Ok(()));
+    assert_eq!(delays, [250, 250, 250]);
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_wait_for_completion_without_progress() {
+    // Formatting, but SKSV clear: still counts as in progress
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(2, 4, 4)),
+    )
+    .expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&testing::sense(0, 0, 0)),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.wait_for_completion(|_| future::ready(()), 100, 1000);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_wait_for_completion_timeout() {
+    let mut fake = FakeScsiTransport::new();
+    for progress in [0x1000, 0x1001, 0x1002] {
+        fake.expect_in(
+            &testing::request_sense_cdb(),
+            &testing::fixed_sense(&progress_sense(2, 0x1B, progress)),
+        );
+    }
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.wait_for_completion(|_| future::ready(()), 500, 1000);
+    assert_eq!(rc.now_or_never().unwrap(), Err(Error::Timeout));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_wait_for_completion_failed() {
+    let failed = testing::sense(3, 0x31, 1); // format command failed
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&progress_sense(2, 4, 0xFF00)),
+    )
+    .expect_in(
+        &testing::request_sense_cdb(),
+        &testing::fixed_sense(&failed),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.wait_for_completion(|_| future::ready(()), 100, 1000);
+    assert_eq!(
+        rc.now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::MediumError))
+    );
+    assert_eq!(d.last_sense(), Some(failed));
+}
+
+#[test]
+fn test_format_unit_and_wait() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&[0x04, 0x10, 0, 0, 0, 0], &[0, 0x02, 0, 0])
+        .expect_in(
+            &testing::request_sense_cdb(),
+            &testing::fixed_sense(&progress_sense(2, 4, 0x4000)),
+        )
+        .expect_in(
+            &testing::request_sense_cdb(),
+            &testing::fixed_sense(&testing::sense(0, 0, 0)),
+        );
+    let mut d = ScsiDevice::new(fake);
+    let mut delays = Vec::new();
+    let rc = d.format_unit_and_wait(
+        |ms| {
+            delays.push(ms);
+            future::ready(())
+        },
+        60_000,
+    );
+    assert_eq!(rc.now_or_never().unwrap(), Ok(()));
+    assert_eq!(delays, [PROGRESS_POLL_INTERVAL_MS]);
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_self_test() {
+    // Background self-tests report "no sense", with progress
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&[0x1D, 0x40, 0, 0, 0, 0])
+        .expect_in(
+            &testing::request_sense_cdb(),
+            &testing::descriptor_sense(&progress_sense(0, 9, 0x0100)),
+        )
+        .expect_in(
+            &testing::request_sense_cdb(),
+            &testing::descriptor_sense(&testing::sense(0, 0, 0)),
+        )
+        .expect_no_data(&[0x1D, 0x20, 0, 0, 0, 0]);
+    let mut d = ScsiDevice::new(fake);
+    let rc = d.self_test(SelfTest::Extended, |_| future::ready(()), 60_000);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(()));
+    let rc = d.start_self_test(SelfTest::Short);
+    assert_eq!(rc.now_or_never().unwrap(), Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_encode_reassign() {
+    let mut buf = [0xAAu8; 32];
+    let n = encode_reassign(&mut buf, [0x01020304, 5].into_iter(), false);
+    assert_eq!(buf[0..n], [0, 0, 0, 8, 1, 2, 3, 4, 0, 0, 0, 5]);
+    let n = encode_reassign(&mut buf, [0x1_0000_0002].into_iter(), true);
+    assert_eq!(buf[0..n], [0, 0, 0, 8, 0, 0, 0, 1, 0, 0, 0, 2]);
+}
+
+#[test]
+fn test_reassign_blocks() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(
+        &[7, 0, 0, 0, 0, 0],
+        &[0, 0, 0, 8, 0, 0, 0, 9, 0, 1, 0, 0],
+    )
+    .expect_out(&[7, 3, 0, 0, 0, 0], &[0, 0, 0, 8, 0, 0, 0, 2, 0, 0, 0, 1]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.reassign_blocks(&[9, 0x10000]).now_or_never().unwrap(),
+        Ok(())
+    );
+    assert_eq!(
+        d.reassign_blocks_long(&[0x2_0000_0001])
+            .now_or_never()
+            .unwrap(),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_reassign_blocks_batches() {
+    let lbas: Vec<u32> = (0..REASSIGN_BATCH as u32 + 1).collect();
+    let mut first = vec![0, 0, 0, 4 * REASSIGN_BATCH as u8];
+    for lba in &lbas[..REASSIGN_BATCH] {
+        first.extend_from_slice(&lba.to_be_bytes());
+    }
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&[7, 0, 0, 0, 0, 0], &first).expect_out(
+        &[7, 0, 0, 0, 0, 0],
+        &[0, 0, 0, 4, 0, 0, 0, REASSIGN_BATCH as u8],
+    );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.reassign_blocks(&lbas).now_or_never().unwrap(), Ok(()));
+}
+
+#[test]
+fn test_reassign_from_sense() {
+    // The LBA of a failed read, from descriptor-format sense data,
+    // is what gets reassigned
+    let sense = SenseData {
+        information: Some(0x1_2345_6789),
+        ..testing::sense(3, 0x11, 0)
+    };
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(
+        &[
+            0x88, 0, 0, 0, 0, 1, 0x23, 0x45, 0x67, 0x80, 0, 0, 0, 16, 0, 0,
+        ],
+        Error::CommandFailed,
+    )
+    .expect_in(
+        &testing::request_sense_cdb(),
+        &testing::descriptor_sense(&sense),
+    )
+    .expect_out(
+        &[7, 3, 0, 0, 0, 0],
+        &[0, 0, 0, 8, 0, 0, 0, 1, 0x23, 0x45, 0x67, 0x89],
+    )
+    .expect_check_condition(&[7, 3, 0, 0, 0, 0], testing::sense(5, 0x20, 0));
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = vec![0u8; 512 * 16];
+    let rc = d
+        .read_16(0x1_2345_6780, 16, &mut buf)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::Scsi(ScsiError::MediumError)));
+    let lba = d.last_sense().unwrap().information.unwrap();
+    assert_eq!(
+        d.reassign_blocks_long(&[lba]).now_or_never().unwrap(),
+        Ok(())
+    );
+    // Devices without spare blocks don't support the command at all
+    assert_eq!(
+        d.reassign_blocks_long(&[lba]).now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+    );
+}
+
+const BDC: [u8; 6] = [0x12, 1, 0xB1, 0, 64, 0];
+
+fn characteristics_from(
+    reply: &[u8],
+) -> Result<BlockDeviceCharacteristics, TestError> {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&BDC, reply);
+    let mut d = ScsiDevice::new(fake);
+    d.block_device_characteristics_page()
+        .now_or_never()
+        .unwrap()
+}
+
+fn characteristics_page(rate: u16, form_factor: u8, zoned: u8) -> Vec<u8> {
+    let mut page = vec![0u8; 64];
+    page[1] = 0xB1;
+    page[3] = 0x3C;
+    page[4..6].copy_from_slice(&rate.to_be_bytes());
+    page[7] = form_factor;
+    page[8] = zoned;
+    page
+}
+
+#[test]
+fn test_characteristics_ssd() {
+    // M.2 SSD behind a USB bridge
+    let page =
+        characteristics_from(&characteristics_page(1, 0x05, 0)).unwrap();
+    assert_eq!(page.medium_rotation_rate(), Some(1));
+    assert!(page.is_solid_state());
+    assert_eq!(
+        page.nominal_form_factor(),
+        Some(FormFactor::SmallerThanOnePointEightInch)
+    );
+    assert_eq!(page.zoned(), None);
+    assert_eq!(page.product_type(), None);
+}
+
+#[test]
+fn test_characteristics_spinning() {
+    // 2.5" SATA winchester, drive-managed SMR
+    let page =
+        characteristics_from(&characteristics_page(5400, 0x03, 0x20)).unwrap();
+    assert_eq!(page.medium_rotation_rate(), Some(5400));
+    assert!(!page.is_solid_state());
+    assert_eq!(
+        page.nominal_form_factor(),
+        Some(FormFactor::TwoAndAHalfInch)
+    );
+    assert_eq!(page.zoned(), Some(Zoned::DeviceManaged));
+    assert_eq!(
+        format!("{page:?}"),
+        "BlockDeviceCharacteristics { medium_rotation_rate: Some(5400), \
+         product_type: None, nominal_form_factor: Some(TwoAndAHalfInch), \
+         zoned: Some(DeviceManaged) }"
+    );
+}
+
+#[test]
+fn test_characteristics_zeroes() {
+    // Some bridges return the page, but all zeroes
+    let page = characteristics_from(&characteristics_page(0, 0, 0)).unwrap();
+    assert_eq!(page.medium_rotation_rate(), None);
+    assert!(!page.is_solid_state());
+    assert_eq!(page.nominal_form_factor(), None);
+    assert_eq!(page.zoned(), None);
+}
+
+#[test]
+fn test_characteristics_short() {
+    // Fields beyond the stated length are ignored
+    let mut reply = characteristics_page(7200, 0x02, 0x10);
+    reply[3] = 4;
+    let page = characteristics_from(&reply).unwrap();
+    assert_eq!(page.medium_rotation_rate(), Some(7200));
+    assert_eq!(
+        page.nominal_form_factor(),
+        Some(FormFactor::ThreeAndAHalfInch)
+    );
+    assert_eq!(page.zoned(), None);
+}
+
+#[test]
+fn test_characteristics_invalid() {
+    let mut reply = characteristics_page(1, 0, 0);
+    reply[1] = 0xB0;
+    assert_eq!(characteristics_from(&reply), Err(Error::ProtocolError));
+    let mut reply = characteristics_page(1, 0, 0);
+    reply[3] = 1;
+    assert_eq!(characteristics_from(&reply), Err(Error::ProtocolError));
+    assert_eq!(characteristics_from(&[0, 0xB1]), Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_characteristics_unsupported() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&BDC, testing::sense(5, 0x24, 0));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.block_device_characteristics_page()
+            .now_or_never()
+            .unwrap(),
+        Err(Error::Scsi(ScsiError::InvalidFieldInCDB))
+    );
+}
+
+const COMPARE_AND_WRITE: [u8; 16] =
+    [0x89, 0, 0, 0, 0, 0, 0, 0, 0x12, 0x34, 0, 0, 0, 2, 0, 0];
+
+#[test]
+fn test_compare_and_write() {
+    let verify = [1u8; 1024];
+    let write = [2u8; 1024];
+    let mut both = verify.to_vec();
+    both.extend_from_slice(&write);
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&COMPARE_AND_WRITE, &both);
+    let mut d = ScsiDevice::new(fake);
+    let mut scratch = [0u8; 2048];
+    let rc = d
+        .compare_and_write(0x1234, 2, &verify, &write, &mut scratch)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(()));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_compare_and_write_miscompare() {
+    let sense = SenseData {
+        information: Some(517),
+        ..testing::sense(0xE, 0x1D, 0)
+    };
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&COMPARE_AND_WRITE, sense)
+        .expect_check_condition(&COMPARE_AND_WRITE, testing::sense(0xE, 0, 0));
+    let mut d = ScsiDevice::new(fake);
+    let mut scratch = [0u8; 2048];
+    let rc = d
+        .compare_and_write(0x1234, 2, &[0; 1024], &[1; 1024], &mut scratch)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::Miscompare(Some(517))));
+    let rc = d
+        .compare_and_write(0x1234, 2, &[0; 1024], &[1; 1024], &mut scratch)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::Miscompare(None)));
+}
+
+#[test]
+fn test_compare_and_write_bad_buffers() {
+    let mut d = ScsiDevice::new(FakeScsiTransport::new());
+    let mut scratch = [0u8; 2048];
+    for (count, verify, write, scratch_len) in [
+        (0, 1024, 1024, 2048),
+        (2, 0, 0, 2048),
+        (2, 1024, 512, 2048),
+        (3, 1024, 1024, 2048),
+        (2, 1024, 1024, 2047),
+    ] {
+        let rc = d
+            .compare_and_write(
+                0,
+                count,
+                &vec![0; verify],
+                &vec![0; write],
+                &mut scratch[..scratch_len],
+            )
+            .now_or_never()
+            .unwrap();
+        assert_eq!(rc, Err(Error::ProtocolError));
+    }
+}
+
+#[test]
+fn test_compare_and_write_limit() {
+    let mut page = valid_block_limits();
+    page.max_compare_and_write = 1;
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x12, 1, 0xB0, 0, 64, 0], bytemuck::bytes_of(&page))
+        .expect_in(
+            &[0x12, 1, 0xB0, 0, 64, 0],
+            bytemuck::bytes_of(&valid_block_limits()),
+        );
+    let mut d = ScsiDevice::new(fake);
+    let mut scratch = [0u8; 2048];
+    let page = d.block_limits_page().now_or_never().unwrap().unwrap();
+    assert_eq!(page.maximum_compare_and_write_length(), Some(1));
+    let rc = d
+        .compare_and_write(0, 2, &[0; 1024], &[0; 1024], &mut scratch)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::ProtocolError));
+
+    // Not supported at all
+    let page = d.block_limits_page().now_or_never().unwrap().unwrap();
+    assert_eq!(page.maximum_compare_and_write_length(), None);
+    let rc = d
+        .compare_and_write(0, 1, &[0; 512], &[0; 512], &mut scratch)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::ProtocolError));
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_pre_fetch() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&[0x34, 2, 0, 0, 0x12, 0x34, 0, 0, 8, 0])
+        .expect_error(
+            &[0x34, 0, 0, 0, 0x12, 0x34, 0, 0, 8, 0],
+            Error::ConditionMet,
+        )
+        .expect_error(
+            &[0x90, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0],
+            Error::ConditionMet,
+        );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.pre_fetch(0x1234, 8).now_or_never().unwrap(), Ok(false));
+    assert_eq!(
+        d.pre_fetch_10(0x1234, 8, false).now_or_never().unwrap(),
+        Ok(true)
+    );
+    assert_eq!(
+        d.pre_fetch(0x1_0000_0000, 8).now_or_never().unwrap(),
+        Ok(true)
+    );
+    // CONDITION MET isn't a failure, so doesn't need REQUEST SENSE
+    assert!(d.transport().is_done());
+    assert!(d.last_sense().is_none());
+}
+
+#[test]
+fn test_pre_fetch_fails() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(
+        &[0x34, 2, 0, 0, 0, 0, 0, 0, 1, 0],
+        testing::sense(5, 0x20, 0),
+    );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.pre_fetch(0, 1).now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+    );
+}
+
+#[test]
+fn test_pre_fetch_overflow() {
+    let fake = FakeScsiTransport::new();
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.pre_fetch(u64::MAX, 1).now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::LogicalBlockAddressOutOfRange))
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_unit_attention_surfaces() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&TUR, testing::sense(6, 0x29, 0));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.unit_attention_policy(), UnitAttentionPolicy::Surface);
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::ResetOccurred))
+    );
+}
+
+#[test]
+fn test_unit_attention_retry() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&INQUIRY, testing::sense(6, 0x29, 0))
+        .expect_in(&INQUIRY, &inquiry_reply(false));
+    let mut d = ScsiDevice::new(fake);
+    d.set_unit_attention_policy(UnitAttentionPolicy::Retry { max: 2 });
+    let data = d.inquiry().now_or_never().unwrap().unwrap();
+    assert_eq!(data.peripheral_type, PeripheralType::Disk);
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_unit_attention_retry_limit() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&TUR, testing::sense(6, 0x29, 0))
+        .expect_check_condition(&TUR, testing::sense(6, 0x29, 2))
+        .expect_check_condition(&TUR, testing::sense(6, 0x29, 0));
+    let mut d = ScsiDevice::new(fake);
+    d.set_unit_attention_policy(UnitAttentionPolicy::Retry { max: 2 });
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::ResetOccurred))
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_unit_attention_media_changed() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&TUR, testing::sense(6, 0x28, 0));
+    let mut d = ScsiDevice::new(fake);
+    d.set_unit_attention_policy(UnitAttentionPolicy::Retry { max: 2 });
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::MediaChanged))
+    );
+    assert!(d.transport().is_done());
+}
+
+/// A transport which can wait, recording each wait (and, as it's a
+/// [`TimedTransport`], each command's timeout) in `log`
+fn delaying(
+    fake: FakeScsiTransport,
+    log: &Rc<RefCell<Vec<usize>>>,
+) -> TimedTransport<FakeScsiTransport, impl FnMut(usize) -> future::Ready<()>>
+{
+    let log = log.clone();
+    TimedTransport::new(fake, move |ms| {
+        log.borrow_mut().push(ms);
+        future::ready(())
+    })
+}
+
+#[test]
+fn test_busy_retry() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::Busy)
+        .expect_error(&TUR, Error::Busy)
+        .expect_no_data(&TUR);
+    let log = Rc::default();
+    let mut d = ScsiDevice::new(delaying(fake, &log));
+    assert_eq!(d.busy_retries(), 3);
+    assert_eq!(d.test_unit_ready().now_or_never().unwrap(), Ok(()));
+    // No REQUEST SENSE for BUSY
+    assert!(d.transport().inner().is_done());
+    assert_eq!(d.stats().retries, 2);
+    assert_eq!(d.last_status(), Some(ScsiStatus::Good));
+    // Backing off between attempts
+    assert_eq!(*log.borrow(), [5_000, 10, 5_000, 20, 5_000]);
+}
+
+#[test]
+fn test_busy_retry_limit() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::Busy)
+        .expect_error(&TUR, Error::Busy);
+    let log = Rc::default();
+    let mut d = ScsiDevice::new(delaying(fake, &log));
+    d.set_busy_retries(1);
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::Busy)
+    );
+    assert!(d.transport().inner().is_done());
+    assert_eq!(d.last_status(), Some(ScsiStatus::Busy));
+    assert!(d.last_sense().is_none());
+    assert_eq!(*log.borrow(), [5_000, 10, 5_000]);
+}
+
+#[test]
+fn test_busy_retry_without_delay() {
+    // A transport which can't wait still gets the command reissued
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::Busy)
+        .expect_error(&TUR, Error::Busy)
+        .expect_no_data(&TUR);
+    let mut d = ScsiDevice::new(fake);
+    assert!(d.transport_mut().delay_ms(10).is_none());
+    assert_eq!(d.test_unit_ready().now_or_never().unwrap(), Ok(()));
+    assert!(d.transport().is_done());
+    assert_eq!(d.stats().retries, 2);
+}
+
+#[test]
+fn test_busy_retry_limit_without_delay() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::Busy)
+        .expect_error(&TUR, Error::Busy)
+        .expect_error(&TUR, Error::Busy)
+        .expect_error(&TUR, Error::Busy);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::Busy)
+    );
+    assert!(d.transport().is_done());
+    assert_eq!(d.stats().retries, 3);
+}
+
+#[test]
+fn test_status_from_transport() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::CommandFailed);
+    fake.last_status = Some(ScsiStatus::ReservationConflict);
+    let mut d = ScsiDevice::new(fake);
+    // Not CHECK CONDITION, so no REQUEST SENSE
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::CommandFailed)
+    );
+    assert!(d.transport().is_done());
+    assert_eq!(d.last_status(), Some(ScsiStatus::ReservationConflict));
+}
+
+#[test]
+fn test_status_check_condition() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&TUR, testing::sense(2, 0x3A, 0));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(d.last_status(), None);
+    assert_eq!(
+        d.test_unit_ready().now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::MediaNotPresent))
+    );
+    // The status of the command itself, not of REQUEST SENSE
+    assert_eq!(d.last_status(), Some(ScsiStatus::CheckCondition));
+}
+
+#[test]
+fn test_stats() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&TUR, testing::sense(6, 0x29, 0))
+        .expect_no_data(&TUR)
+        .expect_in(&INQUIRY, &inquiry_reply(false))
+        .expect_out(&[0x2A, 0, 0, 0, 0, 1, 0, 0, 1, 0], &[0; 512])
+        .expect_check_condition(
+            &[0x28, 0, 0, 0, 0, 2, 0, 0, 1, 0],
+            testing::sense(3, 0x11, 0),
+        );
+    let mut d = ScsiDevice::new(fake);
+    d.set_unit_attention_policy(UnitAttentionPolicy::Retry { max: 1 });
+    assert_eq!(d.stats(), ScsiStats::default());
+    d.test_unit_ready().now_or_never().unwrap().unwrap();
+    d.inquiry().now_or_never().unwrap().unwrap();
+    d.write_10(1, 1, &[0; 512]).now_or_never().unwrap().unwrap();
+    let mut buf = [0u8; 512];
+    assert!(d.read_10(2, 1, &mut buf).now_or_never().unwrap().is_err());
+
+    let stats = d.stats();
+    // Including the two REQUEST SENSEs
+    assert_eq!(stats.commands, 7);
+    assert_eq!(stats.bytes_read, 36);
+    assert_eq!(stats.bytes_written, 512);
+    assert_eq!(stats.failures, 2);
+    assert_eq!(stats.failures_by_sense_key[3], 1);
+    assert_eq!(stats.failures_by_sense_key[6], 1);
+    assert_eq!(stats.retries, 1);
+    assert_eq!(stats.last_sense, Some((3, 0x11, 0)));
+    assert_eq!(stats.last_sense_sequence, 7);
+
+    d.reset_stats();
+    assert_eq!(d.stats(), ScsiStats::default());
+}
+
+#[test]
+fn test_bad_buffer_size() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[0, 0, 0x10, 0, 0, 0, 2, 0],
+    )
+    .expect_in(&[0x28, 0, 0, 0, 0, 1, 0, 0, 2, 0], &[0; 1024])
+    .expect_out(&[0x2A, 0, 0, 0, 0, 1, 0, 0, 2, 0], &[0; 1024]);
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 2048];
+
+    // Zero-length transfers are refused even before the block size is
+    // known
+    assert_eq!(d.block_size(), None);
+    assert_eq!(
+        d.read_10(1, 0, &mut buf).now_or_never().unwrap(),
+        Err(Error::BadBufferSize)
+    );
+    assert_eq!(
+        d.write_16(1, 0, &buf).now_or_never().unwrap(),
+        Err(Error::BadBufferSize)
+    );
+
+    assert_eq!(
+        d.read_capacity_10().now_or_never().unwrap(),
+        Ok((0x1000, 512))
+    );
+    assert_eq!(d.block_size(), Some(512));
+    assert_eq!(
+        d.read_10(1, 2, &mut buf[..1023]).now_or_never().unwrap(),
+        Err(Error::BadBufferSize)
+    );
+    assert_eq!(
+        d.read_16(1, 2, &mut buf[..1023]).now_or_never().unwrap(),
+        Err(Error::BadBufferSize)
+    );
+    // A larger buffer is fine for reading, but not for writing
+    assert_eq!(d.read_10(1, 2, &mut buf).now_or_never().unwrap(), Ok(1024));
+    assert_eq!(
+        d.write_10(1, 2, &buf).now_or_never().unwrap(),
+        Err(Error::BadBufferSize)
+    );
+    assert_eq!(
+        d.write_10(1, 2, &buf[..1024]).now_or_never().unwrap(),
+        Ok(1024)
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_set_block_size() {
+    let mut d = ScsiDevice::new(FakeScsiTransport::new());
+    d.set_block_size(4096);
+    assert_eq!(d.block_size(), Some(4096));
+    assert_eq!(
+        d.write_16(0, 1, &[0; 512]).now_or_never().unwrap(),
+        Err(Error::BadBufferSize)
+    );
+    d.set_block_size(0);
+    assert_eq!(d.block_size(), Some(4096));
+}
+
+#[test]
+fn test_blocks_use_known_block_size() {
+    let mut d = ScsiDevice::new(FakeScsiTransport::new());
+    d.set_block_size(4096);
+    // Would be two 512-byte blocks, if the block size weren't known
+    let mut buf = [0u8; 1024];
+    let bad = Err(TransferError {
+        blocks_done: 0,
+        error: Error::BadBufferSize,
+    });
+    assert_eq!(d.read_blocks(0, 2, &mut buf).now_or_never().unwrap(), bad);
+    assert_eq!(d.write_blocks(0, 2, &buf).now_or_never().unwrap(), bad);
+    let mut buf = [MaybeUninit::uninit(); 1024];
+    assert_eq!(
+        d.read_blocks_uninit(0, 2, &mut buf)
+            .now_or_never()
+            .unwrap()
+            .map(|_| ()),
+        bad
+    );
+    assert!(d.transport().is_done());
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct StallError;
+
+impl std::fmt::Display for StallError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("endpoint stalled")
+    }
+}
+
+impl std::error::Error for StallError {}
+
+#[test]
+fn test_error_display() {
+    assert_eq!(
+        ScsiError::LogicalBlockAddressOutOfRange.to_string(),
+        "LBA out of range"
+    );
+    let e: Error<StallError> = Error::Scsi(ScsiError::MediaNotPresent);
+    assert_eq!(e.to_string(), "no medium");
+    assert_eq!(
+        Error::<StallError>::Miscompare(Some(12)).to_string(),
+        "miscompare at byte 12"
+    );
+    assert_eq!(Error::Transport(StallError).to_string(), "transport error");
+    let e = TransferError {
+        blocks_done: 3,
+        error: Error::<StallError>::Timeout,
+    };
+    assert_eq!(e.to_string(), "transfer failed after 3 blocks");
+}
+
+#[test]
+fn test_error_source() {
+    use std::error::Error as _;
+    let e = Error::Transport(StallError);
+    assert_eq!(e.source().unwrap().to_string(), "endpoint stalled");
+    assert!(Error::<StallError>::CommandFailed.source().is_none());
+    let e = TransferError {
+        blocks_done: 0,
+        error: Error::<StallError>::Scsi(ScsiError::MediumError),
+    };
+    assert_eq!(e.source().unwrap().to_string(), "medium error");
+    let boxed: Box<dyn std::error::Error> = Box::new(e);
+    assert!(boxed.source().is_some());
+}
+
+#[test]
+fn test_sense_table() {
+    let cases = [
+        ((2, 0x3A, 0), ScsiError::MediaNotPresent),
+        ((6, 0x3A, 1), ScsiError::MediaNotPresent),
+        ((6, 0x28, 0), ScsiError::MediaChanged),
+        ((6, 0x29, 0), ScsiError::ResetOccurred),
+        ((6, 0x29, 3), ScsiError::ResetOccurred),
+        ((7, 0x27, 0), ScsiError::WriteProtected),
+        ((5, 0x21, 4), ScsiError::UnalignedWrite),
+        ((1, 0x0B, 5), ScsiError::SelfTestFailed),
+        ((5, 0x55, 0), ScsiError::InsufficientResources),
+        ((5, 0x55, 3), ScsiError::InsufficientResources),
+        // Failure prediction, whatever the sense key
+        ((1, 0x5D, 0x10), ScsiError::FailurePredicted(0x10)),
+        ((6, 0x5D, 0x00), ScsiError::FailurePredicted(0)),
+        ((0, 0x5D, 0xFF), ScsiError::FailurePredicted(0xFF)),
+        // More specific entries still win...
+        ((5, 0x21, 0), ScsiError::LogicalBlockAddressOutOfRange),
+        ((1, 0x0B, 1), ScsiError::Overheat),
+        // ...and unknown combinations fall back to the sense key
+        ((6, 0x2A, 1), ScsiError::UnitAttention),
+        ((7, 0x26, 0), ScsiError::DataProtect),
+        ((5, 0x56, 0), ScsiError::IllegalRequest),
+        ((2, 0x28, 0), ScsiError::NotReady),
+    ];
+    for ((key, asc, ascq), expected) in cases {
+        assert_eq!(
+            upgrade_sense(&testing::sense(key, asc, ascq)),
+            Some(expected),
+            "{key:x}/{asc:x}/{ascq:x}"
+        );
+    }
+    assert_eq!(upgrade_sense(&testing::sense(1, 0x0B, 0x99)), None);
+}
+
+#[test]
+fn test_peripheral_type_from_bits() {
+    for n in 0..=255u8 {
+        assert_eq!(PeripheralType::from_bits(n) as u8, n & 0x1F);
+    }
+}
+
+#[test]
+fn test_inquiry_qualifier_and_version() {
+    let mut empty_slot = inquiry_reply(true);
+    empty_slot[0] = 0x7F;
+    let mut spc4 = inquiry_reply(false);
+    spc4[2] = 6;
+    spc4[3] = 0x12;
+    spc4[7] = 0x02;
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &empty_slot)
+        .expect_in(&INQUIRY, &spc4);
+    let mut d = ScsiDevice::new(fake);
+
+    let data = d.inquiry().now_or_never().unwrap().unwrap();
+    assert_eq!(data.peripheral_qualifier, PeripheralQualifier::NotCapable);
+    assert!(!data.is_connected());
+
+    let data = d.inquiry().now_or_never().unwrap().unwrap();
+    assert_eq!(data.peripheral_qualifier, PeripheralQualifier::Connected);
+    assert!(data.is_connected());
+    assert_eq!(data.peripheral_type, PeripheralType::Disk);
+    assert_eq!(data.version, SpcVersion::Spc4);
+    assert_eq!(data.response_data_format, 2);
+    assert!(data.command_queueing);
+}
+
+pub const SYNC: [u8; 10] = [0x35, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const ALLOW: [u8; 6] = [0x1E, 0, 0, 0, 0, 0];
+const EJECT: [u8; 6] = [0x1B, 0, 0, 0, 2, 0];
+
+#[test]
+fn test_eject() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&SYNC)
+        .expect_no_data(&ALLOW)
+        .expect_no_data(&EJECT);
+    let mut d = ScsiDevice::new(fake);
+    d.capabilities = Some(DeviceCapabilities::default());
+    assert_eq!(
+        d.eject().now_or_never().unwrap(),
+        Ok(EjectSummary {
+            cache_flushed: true,
+            removal_allowed: true,
+            stopped: true,
+        })
+    );
+    assert!(d.capabilities().is_none());
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_eject_no_prevent_allow() {
+    // A bridge which lacks PREVENT ALLOW MEDIUM REMOVAL
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&SYNC)
+        .expect_check_condition(&ALLOW, testing::sense(5, 0x20, 0))
+        .expect_no_data(&EJECT);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.eject().now_or_never().unwrap(),
+        Ok(EjectSummary {
+            cache_flushed: true,
+            removal_allowed: false,
+            stopped: true,
+        })
+    );
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_eject_flush_fails() {
+    // A real failure to write back the cache means no eject
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(&SYNC, testing::sense(3, 0x0C, 0));
+    let mut d = ScsiDevice::new(fake);
+    d.capabilities = Some(DeviceCapabilities::default());
+    assert_eq!(
+        d.eject().now_or_never().unwrap(),
+        Err(Error::Scsi(ScsiError::WriteError))
+    );
+    assert!(d.capabilities().is_some());
+    assert!(d.transport().is_done());
+}
+
+#[test]
+fn test_prevent_medium_removal() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&[0x1E, 0, 0, 0, 1, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        d.prevent_medium_removal(true).now_or_never().unwrap(),
+        Ok(())
     );
 }
@@ -0,0 +1,82 @@
+use super::*;
+
+#[test]
+fn test_status_bytes() {
+    for b in 0..=255u8 {
+        assert_eq!(ScsiStatus::from_byte(b).to_byte(), b);
+    }
+    assert_eq!(ScsiStatus::from_byte(2), ScsiStatus::CheckCondition);
+    assert_eq!(ScsiStatus::from_byte(8), ScsiStatus::Busy);
+    assert_eq!(ScsiStatus::from_byte(0x18), ScsiStatus::ReservationConflict);
+    assert_eq!(ScsiStatus::from_byte(0x22), ScsiStatus::Other(0x22));
+}
+
+#[test]
+fn test_status_from_result() {
+    assert_eq!(
+        ScsiStatus::from_result::<()>(&Ok(5)),
+        Some(ScsiStatus::Good)
+    );
+    assert_eq!(
+        ScsiStatus::from_result::<()>(&Err(Error::CommandFailed)),
+        Some(ScsiStatus::CheckCondition)
+    );
+    assert_eq!(
+        ScsiStatus::from_result::<()>(&Err(Error::ConditionMet)),
+        Some(ScsiStatus::ConditionMet)
+    );
+    assert_eq!(
+        ScsiStatus::from_result::<()>(&Err(Error::Busy)),
+        Some(ScsiStatus::Busy)
+    );
+    assert_eq!(ScsiStatus::from_result(&Err(Error::Transport(()))), None);
+    assert_eq!(ScsiStatus::from_result::<()>(&Err(Error::Timeout)), None);
+}
+
+#[test]
+fn test_status_to_result() {
+    assert_eq!(ScsiStatus::Good.to_result::<()>(5), Ok(5));
+    assert_eq!(
+        ScsiStatus::ConditionMet.to_result::<()>(0),
+        Err(Error::ConditionMet)
+    );
+    assert_eq!(ScsiStatus::Busy.to_result::<()>(0), Err(Error::Busy));
+    assert_eq!(ScsiStatus::TaskSetFull.to_result::<()>(0), Err(Error::Busy));
+    assert_eq!(
+        ScsiStatus::ReservationConflict.to_result::<()>(0),
+        Err(Error::CommandFailed)
+    );
+    assert_eq!(
+        ScsiStatus::CheckCondition.to_result::<()>(0),
+        Err(Error::CommandFailed)
+    );
+}
+
+#[test]
+fn test_busy_display() {
+    assert_eq!(format!("{}", Error::<u8>::Busy), "device busy");
+}
+
+#[test]
+fn test_failure_predicted_display() {
+    assert_eq!(
+        format!("{}", ScsiError::FailurePredicted(0x10)),
+        "failure predicted (0x10)"
+    );
+}
+
+#[test]
+fn test_sense_data_parse() {
+    let fixed = [
+        0xF0, 0, 3, 0, 0, 0x12, 0x34, 10, 0, 0, 0, 0, 0x11, 0, 0, 0, 0, 0,
+    ];
+    let sense = SenseData::parse(&fixed).unwrap();
+    assert_eq!((sense.key, sense.asc, sense.ascq), (3, 0x11, 0));
+    assert_eq!(sense.information, Some(0x1234));
+    assert_eq!(SenseData::parse(&fixed[0..17]), None);
+    assert_eq!(SenseData::parse(&[0x72, 2, 4, 4, 0, 0, 0]), None);
+    assert_eq!(SenseData::parse(&[]), None);
+    // Descriptor length running off the end is ignored
+    let sense = SenseData::parse(&[0x72, 2, 4, 4, 0, 0, 0, 200, 2, 6]);
+    assert_eq!(sense.map(|s| s.sense_key_specific), Some(None));
+}
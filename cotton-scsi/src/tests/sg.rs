@@ -0,0 +1,91 @@
+use super::*;
+use futures::FutureExt;
+
+fn dev_null() -> SgTransport {
+    SgTransport::open("/dev/null").unwrap()
+}
+
+#[test]
+fn test_not_sg_device() {
+    let mut t = dev_null();
+    let rc = t.command(&[0; 6], DataPhase::None).now_or_never().unwrap();
+    assert_eq!(rc, Err(Error::Transport(SgError::Io(libc::ENOTTY))));
+    assert_eq!(t.last_status(), None);
+}
+
+#[test]
+fn test_cdb_too_long() {
+    let mut t = dev_null();
+    let rc = t
+        .command(&[0; 256], DataPhase::None)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_stored_sense() {
+    let mut t = dev_null();
+    let mut sense = [0u8; SENSE_BUFFER_SIZE];
+    sense[0] = 0x70;
+    sense[2] = 3;
+    t.sense = Some((sense, 18));
+    let mut buf = [0u8; 64];
+    let rc = t
+        .command(&[3, 0, 0, 0, 64, 0], DataPhase::In(&mut buf))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Ok(18));
+    assert_eq!(buf[0..3], [0x70, 0, 3]);
+    assert!(t.sense.is_none());
+    assert_eq!(t.last_status(), Some(ScsiStatus::Good));
+}
+
+#[test]
+fn test_stored_sense_discarded() {
+    let mut t = dev_null();
+    t.sense = Some(([0u8; SENSE_BUFFER_SIZE], 18));
+    let _ = t.command(&[0; 6], DataPhase::None).now_or_never();
+    assert!(t.sense.is_none());
+}
+
+#[test]
+fn test_command_with_timeout() {
+    let mut t = dev_null();
+    let rc = t
+        .command_with_timeout(&[0; 6], DataPhase::None, 3_600_000)
+        .now_or_never()
+        .unwrap();
+    assert_eq!(rc, Err(Error::Transport(SgError::Io(libc::ENOTTY))));
+}
+
+#[test]
+fn test_delay_ms() {
+    let mut t = dev_null();
+    let start = std::time::Instant::now();
+    t.delay_ms(20).unwrap().now_or_never().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+fn test_host_error() {
+    assert_eq!(host_error(DID_TIME_OUT), Error::Timeout);
+    assert_eq!(host_error(1), Error::Transport(SgError::Host(1)));
+}
+
+#[test]
+fn test_timeout() {
+    let mut t = dev_null();
+    assert_eq!(t.timeout_ms, SgTransport::DEFAULT_TIMEOUT_MS);
+    t.set_timeout_ms(100);
+    assert_eq!(t.timeout_ms, 100);
+}
+
+#[test]
+fn test_error_display() {
+    assert_eq!(SgError::Host(7).to_string(), "host adapter error 0x7");
+    let e = Error::Transport(SgError::Io(libc::ENOTTY));
+    assert_eq!(e.to_string(), "transport error");
+    let source = std::error::Error::source(&e).unwrap();
+    assert!(source.to_string().starts_with("SG_IO failed: "));
+}
@@ -0,0 +1,315 @@
+use super::*;
+use crate::image_transport::ImageTransport;
+use crate::testing::FakeScsiTransport;
+use crate::{Error, ScsiBlockDevice, ScsiDevice};
+use futures::FutureExt;
+
+type Image = ScsiBlockDevice<ImageTransport>;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 512) as u8 | 1).collect()
+}
+
+fn image(block_size: u32, blocks: u64) -> BlockStorage<Image> {
+    let d = ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(
+        block_size, blocks,
+    )));
+    run(BlockStorage::new(d)).unwrap()
+}
+
+fn contents<const N: usize>(s: &mut BlockStorage<Image, N>) -> Vec<u8> {
+    s.device().scsi.transport().data().unwrap().to_vec()
+}
+
+const fn read_10(lba: u8, blocks: u8) -> [u8; 10] {
+    [0x28, 0, 0, 0, 0, lba, 0, 0, blocks, 0]
+}
+
+const fn write_10(lba: u8, blocks: u8) -> [u8; 10] {
+    [0x2A, 0, 0, 0, 0, lba, 0, 0, blocks, 0]
+}
+
+/// A device with 16 blocks of 512 bytes, and READ CAPACITY scripted
+fn fake() -> FakeScsiTransport {
+    let mut f = FakeScsiTransport::new();
+    f.expect_in(
+        &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[0, 0, 0, 15, 0, 0, 2, 0],
+    );
+    f
+}
+
+fn fake_storage(
+    f: FakeScsiTransport,
+) -> BlockStorage<ScsiBlockDevice<FakeScsiTransport>> {
+    run(BlockStorage::new(ScsiBlockDevice::new(ScsiDevice::new(f)))).unwrap()
+}
+
+#[test]
+fn test_capacity() {
+    let s = image(512, 100);
+    assert_eq!(s.capacity(), 51200);
+}
+
+#[test]
+fn test_capacity_4g() {
+    let s = fake_storage({
+        let mut f = FakeScsiTransport::new();
+        f.expect_in(
+            &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 2, 0],
+        )
+        .expect_in(
+            &[0x9E, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0],
+            &[[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 2, 0].as_slice(), &[0; 20]]
+                .concat(),
+        );
+        f
+    });
+    assert_eq!(s.capacity, 1u64 << 32);
+}
+
+#[test]
+fn test_block_size_unsupported() {
+    let d =
+        ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(4096, 8)));
+    let rc = run(BlockStorage::<_, 512>::new(d));
+    assert_eq!(rc.err(), Some(StorageError::UnsupportedBlockSize(4096)));
+
+    let d = ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(512, 8)));
+    let rc = run(BlockStorage::<_, 768>::new(d));
+    assert_eq!(rc.err(), Some(StorageError::UnsupportedBlockSize(512)));
+}
+
+#[test]
+fn test_aligned() {
+    let mut s = image(512, 100);
+    let data = pattern(512 * 3);
+    run(s.write(1024, &data)).unwrap();
+    let mut buf = vec![0u8; 512 * 3];
+    run(s.read(1024, &mut buf)).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(contents(&mut s)[1024..1024 + 1536], data);
+}
+
+#[test]
+fn test_aligned_is_direct() {
+    // Whole blocks go straight to the device, in one command
+    let mut f = fake();
+    f.expect_out(&write_10(2, 2), &[0xA5; 1024])
+        .expect_in(&read_10(2, 2), &[0x5A; 1024]);
+    let mut s = fake_storage(f);
+    run(s.write(1024, &[0xA5; 1024])).unwrap();
+    let mut buf = [0u8; 1024];
+    run(s.read(1024, &mut buf)).unwrap();
+    assert_eq!(buf, [0x5A; 1024]);
+}
+
+#[test]
+fn test_write_within_block() {
+    let mut s = image(512, 10);
+    let before = pattern(5120);
+    run(s.write(0, &before)).unwrap();
+    run(s.write(1000, &[0; 10])).unwrap();
+
+    let mut expected = before.clone();
+    expected[1000..1010].fill(0);
+    assert_eq!(contents(&mut s), expected);
+    let mut buf = [1u8; 12];
+    run(s.read(999, &mut buf)).unwrap();
+    assert_eq!(buf[0], before[999]);
+    assert_eq!(buf[1..11], [0; 10]);
+    assert_eq!(buf[11], before[1010]);
+}
+
+#[test]
+fn test_write_within_block_is_rmw() {
+    let mut old = [0x11u8; 512];
+    let mut f = fake();
+    f.expect_in(&read_10(3, 1), &old);
+    old[500..510].fill(0x22);
+    f.expect_out(&write_10(3, 1), &old);
+    let mut s = fake_storage(f);
+    run(s.write(512 * 3 + 500, &[0x22; 10])).unwrap();
+}
+
+#[test]
+fn test_write_single_byte() {
+    let mut s = image(512, 4);
+    run(s.write(511, &[0xAB])).unwrap();
+    run(s.write(512, &[0xCD])).unwrap();
+    let data = contents(&mut s);
+    assert_eq!(data[510..514], [0, 0xAB, 0xCD, 0]);
+}
+
+#[test]
+fn test_write_unaligned_head() {
+    let mut s = image(512, 10);
+    let data = pattern(512 * 2 + 100);
+    run(s.write(412, &data)).unwrap();
+    let image = contents(&mut s);
+    assert_eq!(image[..412], [0u8; 412]);
+    assert_eq!(image[412..412 + data.len()], data);
+    assert!(image[412 + data.len()..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_write_unaligned_tail() {
+    let mut s = image(512, 10);
+    let data = pattern(512 * 2 + 100);
+    run(s.write(1024, &data)).unwrap();
+    let image = contents(&mut s);
+    assert_eq!(image[..1024], [0u8; 1024]);
+    assert_eq!(image[1024..1024 + data.len()], data);
+    assert!(image[1024 + data.len()..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_write_unaligned_both() {
+    // RMW of block 1, whole blocks 2-3, RMW of block 4
+    let mut f = fake();
+    let data = pattern(1024 + 200);
+    let mut head = [0xEEu8; 512];
+    let mut tail = [0xFFu8; 512];
+    f.expect_in(&read_10(1, 1), &head);
+    head[400..].copy_from_slice(&data[..112]);
+    f.expect_out(&write_10(1, 1), &head)
+        .expect_out(&write_10(2, 2), &data[112..1136])
+        .expect_in(&read_10(4, 1), &tail);
+    tail[..88].copy_from_slice(&data[1136..]);
+    f.expect_out(&write_10(4, 1), &tail);
+    let mut s = fake_storage(f);
+    run(s.write(912, &data)).unwrap();
+}
+
+#[test]
+fn test_read_unaligned() {
+    let mut s = image(512, 10);
+    let data = pattern(5120);
+    run(s.write(0, &data)).unwrap();
+    for (offset, len) in [(1, 1), (511, 2), (100, 1500), (700, 3000)] {
+        let mut buf = vec![0u8; len];
+        run(s.read(offset, &mut buf)).unwrap();
+        assert_eq!(buf, data[offset as usize..offset as usize + len]);
+    }
+}
+
+#[test]
+fn test_large_bounce_buffer() {
+    let d =
+        ScsiBlockDevice::new(ScsiDevice::new(ImageTransport::new(512, 32)));
+    let mut s = run(BlockStorage::<_, 2048>::new(d)).unwrap();
+    let data = pattern(512 * 16);
+    run(s.write(3, &data)).unwrap();
+    let mut buf = vec![0u8; data.len()];
+    run(s.read(3, &mut buf)).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn test_out_of_bounds() {
+    let mut s = image(512, 4);
+    assert_eq!(run(s.write(2047, &[1, 2])), Err(StorageError::OutOfBounds));
+    let mut buf = [0u8; 1];
+    assert_eq!(run(s.read(2048, &mut buf)), Err(StorageError::OutOfBounds));
+    assert_eq!(run(s.read(2047, &mut buf)), Ok(()));
+    assert_eq!(run(s.read(2048, &mut [])), Ok(()));
+    assert_eq!(
+        StorageError::<()>::OutOfBounds.kind(),
+        NorFlashErrorKind::OutOfBounds
+    );
+}
+
+#[test]
+fn test_device_error() {
+    let mut f = fake();
+    f.expect_error(&read_10(0, 1), Error::Transport(()));
+    let mut s = fake_storage(f);
+    let rc = run(s.write(0, &[1]));
+    assert_eq!(rc, Err(StorageError::Device(Error::Transport(()))));
+    assert_eq!(rc.unwrap_err().kind(), NorFlashErrorKind::Other);
+}
+
+/// A probed "512e" device: 512-byte blocks, 4096-byte physical blocks
+fn image_512e<const N: usize>(blocks: u64) -> BlockStorage<Image, N> {
+    let mut t = ImageTransport::new(512, blocks);
+    t.set_physical_block_exponent(3);
+    let mut scsi = ScsiDevice::new(t);
+    run(scsi.probe()).unwrap();
+    run(BlockStorage::new(ScsiBlockDevice::new(scsi))).unwrap()
+}
+
+#[test]
+fn test_write_physical_block() {
+    let mut s = image_512e::<4096>(20);
+    assert_eq!(s.per_physical, 8);
+    assert_eq!(s.rmw_unit(10), (8, 8));
+    run(s.write(0, &pattern(20 * 512))).unwrap();
+    run(s.write(4096 + 1000, &[0; 10])).unwrap();
+    let image = contents(&mut s);
+    let mut expected = pattern(20 * 512);
+    expected[5096..5106].fill(0);
+    assert_eq!(image, expected);
+}
+
+#[test]
+fn test_write_physical_block_at_end() {
+    // The last physical block is incomplete, so falls back to one block
+    let mut s = image_512e::<4096>(20);
+    assert_eq!(s.rmw_unit(17), (17, 1));
+    run(s.write(17 * 512 + 3, &[1; 2])).unwrap();
+    assert_eq!(contents(&mut s)[17 * 512 + 3..17 * 512 + 5], [1; 2]);
+}
+
+#[test]
+fn test_physical_block_too_large() {
+    // The bounce buffer can't hold a physical block
+    let s = image_512e::<512>(20);
+    assert_eq!(s.per_physical, 1);
+    assert_eq!(s.rmw_unit(10), (10, 1));
+}
+
+#[test]
+fn test_erase() {
+    let mut s = image(512, 8);
+    run(s.write(0, &pattern(4096))).unwrap();
+    run(s.erase(1024, 2048)).unwrap();
+    let image = contents(&mut s);
+    assert_eq!(image[..1024], pattern(4096)[..1024]);
+    assert!(image[1024..2048].iter().all(|b| *b == 0xFF));
+    assert_eq!(image[2048..], pattern(4096)[2048..]);
+}
+
+#[test]
+fn test_erase_errors() {
+    let mut s = image(512, 8);
+    assert_eq!(run(s.erase(100, 512)), Err(StorageError::NotAligned));
+    assert_eq!(run(s.erase(0, 1000)), Err(StorageError::NotAligned));
+    assert_eq!(run(s.erase(1024, 512)), Err(StorageError::OutOfBounds));
+    assert_eq!(run(s.erase(0, 4608)), Err(StorageError::OutOfBounds));
+    assert_eq!(
+        StorageError::<()>::NotAligned.kind(),
+        NorFlashErrorKind::NotAligned
+    );
+}
+
+#[test]
+fn test_flush() {
+    let mut f = fake();
+    f.expect_no_data(&[0x35, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let mut s = fake_storage(f);
+    assert_eq!(run(s.flush()), Ok(()));
+    assert!(s.into_inner().scsi.transport().is_done());
+}
+
+#[test]
+fn test_debug() {
+    let e = StorageError::Device(());
+    assert_eq!(format!("{e:?}"), "Device(..)");
+    let e = StorageError::<()>::UnsupportedBlockSize(4096);
+    assert_eq!(format!("{e:?}"), "UnsupportedBlockSize(4096)");
+}
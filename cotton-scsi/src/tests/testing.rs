@@ -0,0 +1,159 @@
+use super::*;
+use crate::scsi_device::ScsiDevice;
+use crate::scsi_transport::ScsiError;
+use futures::FutureExt;
+
+const TUR: [u8; 6] = [0; 6];
+
+fn run<F: Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+#[test]
+fn test_script() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x12, 0, 0, 0, 2, 0], &[1, 2, 3])
+        .expect_out(&[0x2A; 10], &[4, 5])
+        .expect_no_data(&TUR);
+
+    let mut buf = [0u8; 2];
+    let n = run(fake.command(&[0x12, 0, 0, 0, 2, 0], DataPhase::In(&mut buf)));
+    assert_eq!(n, Ok(2));
+    assert_eq!(buf, [1, 2]);
+    assert!(!fake.is_done());
+    let n = run(fake.command(&[0x2A; 10], DataPhase::Out(&[4, 5])));
+    assert_eq!(n, Ok(2));
+    let n = run(fake.command(&TUR, DataPhase::None));
+    assert_eq!(n, Ok(0));
+    assert!(fake.is_done());
+}
+
+#[test]
+fn test_error() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::Transport(()));
+    let rc = run(fake.command(&TUR, DataPhase::None));
+    assert_eq!(rc, Err(Error::Transport(())));
+}
+
+#[test]
+fn test_pending() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_pending(&TUR);
+    assert!(fake.command(&TUR, DataPhase::None).now_or_never().is_none());
+    assert!(fake.is_done());
+}
+
+#[test]
+#[should_panic(expected = "command #1 mismatch")]
+fn test_wrong_cdb() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR).expect_no_data(&TUR);
+    let _ = run(fake.command(&TUR, DataPhase::None));
+    let _ = run(fake.command(&[0, 0, 0, 0, 1, 0], DataPhase::None));
+}
+
+#[test]
+#[should_panic(expected = "unexpected command #0")]
+fn test_unscripted() {
+    let mut fake = FakeScsiTransport::new();
+    let _ = run(fake.command(&TUR, DataPhase::None));
+}
+
+#[test]
+#[should_panic(expected = "expected NoData")]
+fn test_wrong_direction() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR);
+    let mut buf = [0u8; 2];
+    let _ = run(fake.command(&TUR, DataPhase::In(&mut buf)));
+}
+
+#[test]
+#[should_panic(expected = "data mismatch")]
+fn test_wrong_data() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_out(&[0x2A; 10], &[4, 5]);
+    let _ = run(fake.command(&[0x2A; 10], DataPhase::Out(&[4, 6])));
+}
+
+#[test]
+#[should_panic(expected = "1 command(s) not issued")]
+fn test_unused() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&TUR);
+}
+
+#[test]
+fn test_diff_marker() {
+    assert_eq!(diff_marker(&[1, 2, 3], &[1, 5]), "    ^^ ^^");
+}
+
+#[test]
+fn test_check_condition() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(
+        &TUR,
+        SenseData {
+            information: Some(0x1234),
+            ..sense(3, 0x11, 0)
+        },
+    );
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.test_unit_ready()),
+        Err(Error::Scsi(ScsiError::MediumError))
+    );
+    assert_eq!(
+        d.last_sense(),
+        Some(SenseData {
+            key: 3,
+            asc: 0x11,
+            ascq: 0,
+            information: Some(0x1234),
+            sense_key_specific: None,
+        })
+    );
+}
+
+#[test]
+fn test_sense_key_specific_encoding() {
+    let s = SenseData {
+        information: Some(7),
+        sense_key_specific: Some([0, 0x12, 0x34]),
+        ..sense(2, 4, 4)
+    };
+    let fixed = fixed_sense(&s);
+    assert_eq!(fixed[15..18], [0x80, 0x12, 0x34]);
+    let descriptor = descriptor_sense(&s);
+    assert_eq!(descriptor[7], 20);
+    assert_eq!(descriptor[20..28], [2, 6, 0, 0, 0x80, 0x12, 0x34, 0]);
+}
+
+#[test]
+fn test_descriptor_sense() {
+    let s = SenseData {
+        information: Some(0x1_2345_6789),
+        ..sense(3, 0x11, 0)
+    };
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::CommandFailed)
+        .expect_in(&request_sense_cdb(), &descriptor_sense(&s));
+    let mut d = ScsiDevice::new(fake);
+    assert!(run(d.test_unit_ready()).is_err());
+    assert_eq!(d.last_sense(), Some(s));
+}
+
+#[test]
+fn test_descriptor_sense_no_information() {
+    let s = sense(5, 0x20, 0);
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_error(&TUR, Error::CommandFailed)
+        .expect_in(&request_sense_cdb(), &descriptor_sense(&s));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(
+        run(d.test_unit_ready()),
+        Err(Error::Scsi(ScsiError::InvalidCommandOperationCode))
+    );
+    assert_eq!(d.last_sense(), Some(s));
+}
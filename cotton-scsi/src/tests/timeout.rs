@@ -0,0 +1,153 @@
+use super::*;
+use crate::testing::FakeScsiTransport;
+use crate::ScsiDevice;
+use futures::FutureExt;
+use std::cell::RefCell;
+use std::future::{pending, ready, Pending, Ready};
+use std::mem::MaybeUninit;
+use std::rc::Rc;
+
+fn run<F: Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+/// A transport whose commands never complete
+#[derive(Default)]
+struct Hung {
+    commands: Vec<Vec<u8>>,
+}
+
+impl ScsiTransport for Hung {
+    type Error = ();
+
+    fn command(
+        &mut self,
+        cmd: &[u8],
+        _data: DataPhase,
+    ) -> impl Future<Output = Result<usize, Error<()>>> {
+        self.commands.push(cmd.to_vec());
+        pending()
+    }
+}
+
+/// A delay which completes at once, recording how long it was asked for
+fn instant(log: &Rc<RefCell<Vec<usize>>>) -> impl FnMut(usize) -> Ready<()> {
+    let log = log.clone();
+    move |ms| {
+        log.borrow_mut().push(ms);
+        ready(())
+    }
+}
+
+fn never(_: usize) -> Pending<()> {
+    pending()
+}
+
+#[test]
+fn test_for_command() {
+    let t = Timeouts::DEFAULT;
+    assert_eq!(t.for_command(&[0; 6]), 5_000);
+    assert_eq!(t.for_command(&[0x12, 0, 0, 0, 36, 0]), 5_000);
+    assert_eq!(t.for_command(&[0x28, 0, 0, 0, 0, 0, 0, 0, 1, 0]), 30_000);
+    assert_eq!(t.for_command(&[0x2A, 0, 0, 0, 0, 0, 0, 0, 1, 0]), 30_000);
+    assert_eq!(t.for_command(&[0x04, 0, 0, 0, 0, 0]), 7_200_000);
+    assert_eq!(t.for_command(&[0x1D, 0x80, 0, 0, 0, 0]), 7_200_000);
+    assert_eq!(t.for_command(&[]), 30_000);
+    assert_eq!(Timeouts::default(), t);
+}
+
+#[test]
+fn test_timeout() {
+    let log = Rc::default();
+    let mut t = TimedTransport::new(Hung::default(), instant(&log));
+    let rc = run(t.command(&[0; 6], DataPhase::None));
+    assert_eq!(rc, Err(Error::Timeout));
+    assert_eq!(*log.borrow(), [5_000]);
+    assert_eq!(t.inner().commands.len(), 1);
+}
+
+#[test]
+fn test_timeout_hint() {
+    let log = Rc::default();
+    let mut t = TimedTransport::new(Hung::default(), instant(&log));
+    let rc = run(t.command_with_timeout(&[0; 6], DataPhase::None, 123));
+    assert_eq!(rc, Err(Error::Timeout));
+    assert_eq!(*log.borrow(), [123]);
+}
+
+#[test]
+fn test_timeout_uninit() {
+    let log = Rc::default();
+    let mut t = TimedTransport::new(Hung::default(), instant(&log));
+    let mut data = [MaybeUninit::uninit(); 36];
+    let mut buf = ReadBuffer::new(&mut data);
+    let rc = run(t.command_in_uninit(&[0x12, 0, 0, 0, 36, 0], &mut buf, 123));
+    assert_eq!(rc, Err(Error::Timeout));
+    assert_eq!(*log.borrow(), [123]);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_no_timeout_uninit() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x12, 0, 0, 0, 36, 0], &[1, 2, 3]);
+    let mut t = TimedTransport::new(fake, never);
+    let mut data = [MaybeUninit::uninit(); 36];
+    let mut buf = ReadBuffer::new(&mut data);
+    let rc = run(t.command_in_uninit(&[0x12, 0, 0, 0, 36, 0], &mut buf, 0));
+    assert_eq!(rc, Ok(3));
+    assert_eq!(buf.filled(), [1, 2, 3]);
+    assert!(t.into_inner().is_done());
+}
+
+#[test]
+fn test_no_timeout() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&[0x12, 0, 0, 0, 36, 0], &[1, 2, 3]);
+    fake.max_transfer_bytes = Some(4096);
+    fake.quirks.no_vpd = true;
+    let mut t = TimedTransport::new(fake, never);
+    assert_eq!(t.max_transfer_bytes(), Some(4096));
+    assert!(t.quirks().no_vpd);
+    let mut buf = [0u8; 36];
+    let rc = run(t.command(&[0x12, 0, 0, 0, 36, 0], DataPhase::In(&mut buf)));
+    assert_eq!(rc, Ok(3));
+    assert_eq!(buf[0..3], [1, 2, 3]);
+    assert!(t.into_inner().is_done());
+}
+
+#[test]
+fn test_device_timeout_no_sense() {
+    let log = Rc::default();
+    let mut d =
+        ScsiDevice::new(TimedTransport::new(Hung::default(), instant(&log)));
+    assert_eq!(run(d.test_unit_ready()), Err(Error::Timeout));
+    // No REQUEST SENSE
+    assert_eq!(d.transport().inner().commands, [vec![0; 6]]);
+    assert!(d.last_sense().is_none());
+}
+
+#[test]
+fn test_device_timeouts() {
+    let log = Rc::default();
+    let mut d =
+        ScsiDevice::new(TimedTransport::new(Hung::default(), instant(&log)));
+    assert_eq!(d.timeouts(), &Timeouts::DEFAULT);
+    d.set_timeouts(Timeouts {
+        short_ms: 1,
+        medium_ms: 2,
+        long_ms: 3,
+    });
+    let _ = run(d.test_unit_ready());
+    let _ = run(d.read_10(0, 1, &mut [0u8; 512]));
+    let _ = run(d.command_no_data([0x04u8, 0, 0, 0, 0, 0]));
+    assert_eq!(*log.borrow(), [1, 2, 3]);
+}
+
+#[test]
+fn test_default_ignores_hint() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&[0; 6]);
+    let rc = run(fake.command_with_timeout(&[0; 6], DataPhase::None, 0));
+    assert_eq!(rc, Ok(0));
+}
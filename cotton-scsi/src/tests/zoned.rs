@@ -0,0 +1,185 @@
+use super::*;
+use crate::scsi_device::BlockDeviceCharacteristics;
+use crate::testing::{self, FakeScsiTransport};
+use futures::FutureExt;
+
+fn run<F: core::future::Future>(f: F) -> F::Output {
+    f.now_or_never().unwrap()
+}
+
+const ZONE: u64 = 0x8_0000;
+
+fn report_zones_cdb(start: u64, filter: u8, len: u32) -> Vec<u8> {
+    let mut cdb = vec![0x95, 0];
+    cdb.extend_from_slice(&start.to_be_bytes());
+    cdb.extend_from_slice(&len.to_be_bytes());
+    cdb.extend_from_slice(&[filter, 0]);
+    cdb
+}
+
+fn descriptor(zone_type: u8, condition: u8, start: u64, wp: u64) -> Vec<u8> {
+    let mut d = vec![0u8; 64];
+    d[0] = zone_type;
+    d[1] = condition << 4;
+    d[8..16].copy_from_slice(&ZONE.to_be_bytes());
+    d[16..24].copy_from_slice(&start.to_be_bytes());
+    d[24..32].copy_from_slice(&wp.to_be_bytes());
+    d
+}
+
+/// A reply header, plus descriptors for these zones
+fn reply(remaining: usize, zones: &[(u8, u8, u64)]) -> Vec<u8> {
+    let mut r = vec![0u8; 64];
+    r[0..4].copy_from_slice(&(remaining as u32 * 64).to_be_bytes());
+    r[8..16].copy_from_slice(&(ZONE * 4 - 1).to_be_bytes());
+    for (t, c, start) in zones {
+        r.extend_from_slice(&descriptor(*t, *c, *start, start + 8));
+    }
+    r
+}
+
+#[test]
+fn test_parse_descriptor() {
+    let mut d = descriptor(2, 3, ZONE, ZONE + 100);
+    d[1] |= 3;
+    let z = ZoneDescriptor::parse(&d).unwrap();
+    assert_eq!(
+        z,
+        ZoneDescriptor {
+            zone_type: ZoneType::SequentialWriteRequired,
+            condition: ZoneCondition::ExplicitlyOpened,
+            reset_recommended: true,
+            non_sequential: true,
+            start: ZONE,
+            length: ZONE,
+            write_pointer: Some(ZONE + 100),
+        }
+    );
+
+    let z = ZoneDescriptor::parse(&descriptor(1, 0, 0, 0x1234)).unwrap();
+    assert_eq!(z.zone_type, ZoneType::Conventional);
+    assert_eq!(z.condition, ZoneCondition::NotWritePointer);
+    assert_eq!(z.write_pointer, None);
+
+    let z = ZoneDescriptor::parse(&descriptor(9, 0xE, 0, 0)).unwrap();
+    assert_eq!(z.zone_type, ZoneType::Other(9));
+    assert_eq!(z.condition, ZoneCondition::Full);
+    assert_eq!(z.write_pointer, None);
+
+    assert!(ZoneDescriptor::parse(&d[0..63]).is_none());
+}
+
+#[test]
+fn test_report_zones() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &report_zones_cdb(0, 0, 192),
+        &reply(4, &[(1, 0, 0), (2, 1, ZONE)]),
+    )
+    .expect_in(
+        &report_zones_cdb(2 * ZONE, 0, 192),
+        &reply(2, &[(2, 4, 2 * ZONE), (2, 0xE, 3 * ZONE)]),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 200];
+    let mut zones = Vec::new();
+    let rc =
+        run(d.report_zones(0, ZoneFilter::All, &mut buf, |z| zones.push(*z)));
+    assert_eq!(rc, Ok(4));
+    assert!(d.transport().is_done());
+    let starts: Vec<u64> = zones.iter().map(|z| z.start).collect();
+    assert_eq!(starts, [0, ZONE, 2 * ZONE, 3 * ZONE]);
+    assert_eq!(zones[2].condition, ZoneCondition::Closed);
+    assert_eq!(zones[2].write_pointer, Some(2 * ZONE + 8));
+}
+
+#[test]
+fn test_report_zones_filtered() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(
+        &report_zones_cdb(ZONE + 5, 1, 4096),
+        &reply(1, &[(2, 1, 3 * ZONE)]),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 4096];
+    let mut zones = Vec::new();
+    let rc = run(d.report_zones(ZONE + 5, ZoneFilter::Empty, &mut buf, |z| {
+        zones.push(*z)
+    }));
+    assert_eq!(rc, Ok(1));
+    assert_eq!(zones[0].start, 3 * ZONE);
+}
+
+#[test]
+fn test_report_zones_no_progress() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&report_zones_cdb(0, 0, 128), &reply(4, &[]));
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 128];
+    let rc = run(d.report_zones(0, ZoneFilter::All, &mut buf, |_| {}));
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_report_zones_small_buffer() {
+    let mut d = ScsiDevice::new(FakeScsiTransport::new());
+    let mut buf = [0u8; 127];
+    let rc = run(d.report_zones(0, ZoneFilter::All, &mut buf, |_| {}));
+    assert_eq!(rc, Err(Error::ProtocolError));
+}
+
+#[test]
+fn test_report_zones_unsupported() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_check_condition(
+        &report_zones_cdb(0, 0, 128),
+        testing::sense(5, 0x20, 0),
+    );
+    let mut d = ScsiDevice::new(fake);
+    let mut buf = [0u8; 128];
+    let rc = run(d.report_zones(0, ZoneFilter::All, &mut buf, |_| {}));
+    assert!(matches!(rc, Err(Error::Scsi(_))));
+}
+
+#[test]
+fn test_manage_zones() {
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_no_data(&[0x94, 3, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0])
+        .expect_no_data(&[0x94, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]);
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.manage_zones(ZoneAction::Open, Some(ZONE))), Ok(()));
+    assert_eq!(
+        run(d.manage_zones(ZoneAction::ResetWritePointer, None)),
+        Ok(())
+    );
+    assert!(d.transport().is_done());
+}
+
+const INQUIRY: [u8; 6] = [0x12, 0, 0, 0, 36, 0];
+const BDC: [u8; 6] = [0x12, 1, 0xB1, 0, 64, 0];
+
+fn inquiry_reply(peripheral_type: u8) -> [u8; 36] {
+    let mut r = [0u8; 36];
+    r[0] = peripheral_type;
+    r[4] = 31;
+    r
+}
+
+#[test]
+fn test_zone_model() {
+    let mut bdc = [0u8; 64];
+    bdc[1] = BlockDeviceCharacteristics::PAGE_CODE;
+    bdc[3] = 0x3C;
+    bdc[8] = 0x10;
+    let mut fake = FakeScsiTransport::new();
+    fake.expect_in(&INQUIRY, &inquiry_reply(0x14))
+        .expect_in(&INQUIRY, &inquiry_reply(0))
+        .expect_in(&BDC, &bdc)
+        .expect_in(&INQUIRY, &inquiry_reply(0))
+        .expect_check_condition(&BDC, testing::sense(5, 0x24, 0));
+    let mut d = ScsiDevice::new(fake);
+    assert_eq!(run(d.zone_model()), Ok(Some(Zoned::HostManaged)));
+    assert_eq!(run(d.zone_model()), Ok(Some(Zoned::HostAware)));
+    assert_eq!(run(d.zone_model()), Ok(None));
+    assert!(d.transport().is_done());
+}
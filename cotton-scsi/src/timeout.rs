@@ -0,0 +1,187 @@
+use crate::scsi_transport::{
+    DataPhase, Error, ReadBuffer, ScsiQuirks, ScsiStatus, ScsiTransport,
+};
+use core::future::Future;
+use core::pin::pin;
+use futures::future::{select, Either};
+
+/// How long, in milliseconds, each type of command is allowed to take
+///
+/// A [`ScsiDevice`](crate::ScsiDevice) passes the appropriate one of
+/// these to [`ScsiTransport::command_with_timeout()`] with each command;
+/// see [`ScsiDevice::set_timeouts()`](crate::ScsiDevice::set_timeouts).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    /// For commands which just report the device's state, such as
+    /// TEST UNIT READY, INQUIRY or READ CAPACITY
+    pub short_ms: u32,
+
+    /// For reads, writes, and everything not otherwise classified
+    pub medium_ms: u32,
+
+    /// For commands which work on the whole medium, such as FORMAT
+    /// UNIT, SANITIZE, or a self-test (SEND DIAGNOSTIC)
+    pub long_ms: u32,
+}
+
+impl Timeouts {
+    /// The default timeouts: 5 seconds, 30 seconds, and 2 hours
+    pub const DEFAULT: Self = Self {
+        short_ms: 5_000,
+        medium_ms: 30_000,
+        long_ms: 2 * 60 * 60 * 1000,
+    };
+
+    /// The timeout for the command block `cmd`, according to its
+    /// operation code
+    pub fn for_command(&self, cmd: &[u8]) -> u32 {
+        match cmd.first() {
+            // TEST UNIT READY, REQUEST SENSE, INQUIRY, MODE SENSE(6),
+            // PREVENT ALLOW MEDIUM REMOVAL, READ CAPACITY(10), GET
+            // CONFIGURATION, GET EVENT STATUS NOTIFICATION, MODE
+            // SENSE(10), READ CAPACITY(16), REPORT SUPPORTED
+            // OPERATION CODES
+            Some(
+                0x00 | 0x03 | 0x12 | 0x1A | 0x1E | 0x25 | 0x46 | 0x4A | 0x5A
+                | 0x9E | 0xA3,
+            ) => self.short_ms,
+
+            // FORMAT UNIT, SEND DIAGNOSTIC, VERIFY(10), WRITE SAME(10),
+            // SANITIZE, VERIFY(16), WRITE SAME(16)
+            Some(0x04 | 0x1D | 0x2F | 0x41 | 0x48 | 0x8F | 0x93) => {
+                self.long_ms
+            }
+
+            _ => self.medium_ms,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Adding timeouts to any [`ScsiTransport`]
+///
+/// Without timeouts, a device (or USB bridge) which stops responding
+/// leaves [`ScsiTransport::command()`] waiting for ever. This wrapper
+/// races each command against a delay, and fails it with
+/// [`Error::Timeout`] if the delay finishes first.
+///
+/// To stay independent of any particular async runtime, the delay is
+/// supplied by the caller: `delay_ms` is called with a number of
+/// milliseconds, and returns a future which completes after that long
+/// (for instance, Embassy's `Timer::after_millis()`, or Tokio's
+/// `sleep()`).
+///
+/// The same delay function is used by a
+/// [`ScsiDevice`](crate::ScsiDevice) to back off between retries of a
+/// command which the device was too busy to accept (see
+/// [`ScsiTransport::delay_ms()`]).
+///
+/// The timeout for each command is the hint passed to
+/// [`ScsiTransport::command_with_timeout()`] -- so, when used by a
+/// [`ScsiDevice`](crate::ScsiDevice), that device's
+/// [`Timeouts`]. Commands issued via plain
+/// [`ScsiTransport::command()`] use [`Timeouts::DEFAULT`].
+///
+/// An abandoned command may still be in progress on the device, so
+/// after a timeout the transport usually needs resetting before it's
+/// used again, in whatever way is appropriate to it.
+pub struct TimedTransport<T, F> {
+    inner: T,
+    delay_ms: F,
+}
+
+impl<T, F> TimedTransport<T, F> {
+    /// Wrap a transport, using `delay_ms` to time its commands
+    pub fn new(inner: T, delay_ms: F) -> Self {
+        Self { inner, delay_ms }
+    }
+
+    /// The underlying transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// The underlying transport (mutable)
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Stop using this wrapper, and return the underlying transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, F, D> ScsiTransport for TimedTransport<T, F>
+where
+    T: ScsiTransport,
+    F: FnMut(usize) -> D,
+    D: Future<Output = ()>,
+{
+    type Error = T::Error;
+
+    fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase,
+    ) -> impl Future<Output = Result<usize, Error<Self::Error>>> {
+        let timeout_ms = Timeouts::DEFAULT.for_command(cmd);
+        self.command_with_timeout(cmd, data, timeout_ms)
+    }
+
+    async fn command_with_timeout(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase<'_>,
+        timeout_ms: u32,
+    ) -> Result<usize, Error<Self::Error>> {
+        let command = pin!(self.inner.command(cmd, data));
+        let timeout = pin!((self.delay_ms)(timeout_ms as usize));
+        match select(command, timeout).await {
+            Either::Left((rc, _)) => rc,
+            Either::Right(_) => Err(Error::Timeout),
+        }
+    }
+
+    async fn command_in_uninit(
+        &mut self,
+        cmd: &[u8],
+        data: &mut ReadBuffer<'_>,
+        timeout_ms: u32,
+    ) -> Result<usize, Error<Self::Error>> {
+        let command =
+            pin!(self.inner.command_in_uninit(cmd, data, timeout_ms));
+        let timeout = pin!((self.delay_ms)(timeout_ms as usize));
+        match select(command, timeout).await {
+            Either::Left((rc, _)) => rc,
+            Either::Right(_) => Err(Error::Timeout),
+        }
+    }
+
+    fn last_status(&self) -> Option<ScsiStatus> {
+        self.inner.last_status()
+    }
+
+    fn max_transfer_bytes(&self) -> Option<usize> {
+        self.inner.max_transfer_bytes()
+    }
+
+    fn quirks(&self) -> ScsiQuirks {
+        self.inner.quirks()
+    }
+
+    fn delay_ms(&mut self, ms: u32) -> Option<impl Future<Output = ()>> {
+        Some((self.delay_ms)(ms as usize))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/timeout.rs"]
+mod tests;
@@ -0,0 +1,362 @@
+use super::scsi_device::{
+    PeripheralType, ScsiDevice, Zoned, DEFAULT_MAX_TRANSFER_BYTES,
+};
+use super::scsi_transport::{Error, ScsiTransport};
+
+/// The size of the REPORT ZONES header, and of each zone descriptor
+const DESCRIPTOR_SIZE: usize = 64;
+
+/// REPORT ZONES (ZBC IN)
+/// Zoned Block Commands (ZBC) s5.3
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ReportZones {
+    operation_code: u8,
+    service_action: u8,
+    zone_start_lba_be: [u8; 8],
+    allocation_length_be: [u8; 4],
+    reporting_options: u8,
+    control: u8,
+}
+
+impl ReportZones {
+    fn new(start: u64, filter: ZoneFilter, len: u32) -> Self {
+        assert!(core::mem::size_of::<Self>() == 16);
+        Self {
+            operation_code: 0x95,
+            service_action: 0x00,
+            zone_start_lba_be: start.to_be_bytes(),
+            allocation_length_be: len.to_be_bytes(),
+            reporting_options: filter as u8, // PARTIAL=0
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ReportZones {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ReportZones {}
+
+/// CLOSE ZONE, FINISH ZONE, OPEN ZONE, RESET WRITE POINTER (ZBC OUT)
+/// Zoned Block Commands (ZBC) s5.2
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ZbcOut {
+    operation_code: u8,
+    service_action: u8,
+    zone_id_be: [u8; 8],
+    reserved: [u8; 4],
+    all: u8,
+    control: u8,
+}
+
+impl ZbcOut {
+    fn new(action: ZoneAction, zone: Option<u64>) -> Self {
+        assert!(core::mem::size_of::<Self>() == 16);
+        Self {
+            operation_code: 0x94,
+            service_action: action as u8,
+            zone_id_be: zone.unwrap_or(0).to_be_bytes(),
+            reserved: [0; 4],
+            all: if zone.is_none() { 1 } else { 0 },
+            control: 0,
+        }
+    }
+}
+
+// SAFETY: all fields zeroable
+unsafe impl bytemuck::Zeroable for ZbcOut {}
+// SAFETY: no padding, no disallowed bit patterns
+unsafe impl bytemuck::Pod for ZbcOut {}
+
+/// Which zones REPORT ZONES should report
+/// Zoned Block Commands (ZBC) s5.3, table 36
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ZoneFilter {
+    /// All zones
+    All = 0x00,
+    /// Zones in [`ZoneCondition::Empty`]
+    Empty = 0x01,
+    /// Zones in [`ZoneCondition::ImplicitlyOpened`]
+    ImplicitlyOpened = 0x02,
+    /// Zones in [`ZoneCondition::ExplicitlyOpened`]
+    ExplicitlyOpened = 0x03,
+    /// Zones in [`ZoneCondition::Closed`]
+    Closed = 0x04,
+    /// Zones in [`ZoneCondition::Full`]
+    Full = 0x05,
+    /// Zones in [`ZoneCondition::ReadOnly`]
+    ReadOnly = 0x06,
+    /// Zones in [`ZoneCondition::Offline`]
+    Offline = 0x07,
+    /// Zones which the device recommends resetting
+    ResetRecommended = 0x10,
+    /// Zones which have seen non-sequential writes
+    NonSequential = 0x11,
+    /// Zones in [`ZoneCondition::NotWritePointer`]
+    NotWritePointer = 0x3F,
+}
+
+/// The actions which can be taken on zones
+/// Zoned Block Commands (ZBC) s5.2
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ZoneAction {
+    /// Close an open zone, freeing up the device's resources for it
+    Close = 0x01,
+    /// Make a zone full, as if it had been written to the end
+    Finish = 0x02,
+    /// Explicitly open a zone for writing
+    Open = 0x03,
+    /// Make a zone empty, discarding its contents, with its write
+    /// pointer back at the start
+    ResetWritePointer = 0x04,
+}
+
+/// The type of a zone
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ZoneType {
+    /// Random writes allowed; no write pointer
+    Conventional,
+    /// Writes must be sequential, at the write pointer
+    SequentialWriteRequired,
+    /// Writes should be sequential, but needn't be (host-aware devices)
+    SequentialWritePreferred,
+    /// Writes must be at or before the write pointer (ZBC-2)
+    SequentialOrBeforeRequired,
+    /// A gap between zones, which can't be read or written (ZBC-2)
+    Gap,
+    /// Reserved or unknown type
+    Other(u8),
+}
+
+/// The condition (state) of a zone
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ZoneCondition {
+    /// The zone has no write pointer (conventional zones)
+    NotWritePointer,
+    /// Nothing has been written since the zone was last reset
+    Empty,
+    /// Open, because it was written to
+    ImplicitlyOpened,
+    /// Open, because of [`ZoneAction::Open`]
+    ExplicitlyOpened,
+    /// Partly written, but not open
+    Closed,
+    /// Not currently usable (ZBC-2)
+    Inactive,
+    /// Can only be read
+    ReadOnly,
+    /// Written to the end, or finished with [`ZoneAction::Finish`]
+    Full,
+    /// Can be neither read nor written
+    Offline,
+    /// Reserved or unknown condition
+    Other(u8),
+}
+
+/// One zone of a zoned device
+///
+/// As reported by [`ScsiDevice::report_zones()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ZoneDescriptor {
+    /// The type of the zone
+    pub zone_type: ZoneType,
+
+    /// The condition of the zone
+    pub condition: ZoneCondition,
+
+    /// Whether the device recommends resetting the zone's write pointer
+    pub reset_recommended: bool,
+
+    /// Whether the zone has seen non-sequential writes
+    pub non_sequential: bool,
+
+    /// The first LBA of the zone
+    pub start: u64,
+
+    /// The size of the zone, in blocks
+    pub length: u64,
+
+    /// Where the next write to the zone must go, or `None` if the zone
+    /// doesn't currently have a valid write pointer (because it's
+    /// conventional, full, read-only, inactive or offline)
+    pub write_pointer: Option<u64>,
+}
+
+fn be64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[0..8].try_into().unwrap())
+}
+
+impl ZoneDescriptor {
+    /// Decode one 64-byte zone descriptor
+    ///
+    /// Zoned Block Commands (ZBC) s5.3, table 39. Returns `None` if
+    /// `bytes` is too short.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < DESCRIPTOR_SIZE {
+            return None;
+        }
+        let zone_type = match bytes[0] & 0xF {
+            1 => ZoneType::Conventional,
+            2 => ZoneType::SequentialWriteRequired,
+            3 => ZoneType::SequentialWritePreferred,
+            4 => ZoneType::SequentialOrBeforeRequired,
+            5 => ZoneType::Gap,
+            n => ZoneType::Other(n),
+        };
+        let condition = match bytes[1] >> 4 {
+            0x0 => ZoneCondition::NotWritePointer,
+            0x1 => ZoneCondition::Empty,
+            0x2 => ZoneCondition::ImplicitlyOpened,
+            0x3 => ZoneCondition::ExplicitlyOpened,
+            0x4 => ZoneCondition::Closed,
+            0x5 => ZoneCondition::Inactive,
+            0xD => ZoneCondition::ReadOnly,
+            0xE => ZoneCondition::Full,
+            0xF => ZoneCondition::Offline,
+            n => ZoneCondition::Other(n),
+        };
+        let write_pointer = match condition {
+            ZoneCondition::Empty
+            | ZoneCondition::ImplicitlyOpened
+            | ZoneCondition::ExplicitlyOpened
+            | ZoneCondition::Closed => Some(be64(&bytes[24..])),
+            _ => None,
+        };
+        Some(Self {
+            zone_type,
+            condition,
+            reset_recommended: (bytes[1] & 1) != 0,
+            non_sequential: (bytes[1] & 2) != 0,
+            length: be64(&bytes[8..]),
+            start: be64(&bytes[16..]),
+            write_pointer,
+        })
+    }
+}
+
+impl<T: ScsiTransport> ScsiDevice<T> {
+    /// Find out whether, and how, the device is zoned
+    ///
+    /// Host-managed devices are recognised by their
+    /// [`PeripheralType::HostManagedZoned`]; others by the Block Device
+    /// Characteristics page, see [`ScsiDevice::block_device_characteristics_page()`].
+    /// Returns `None` for devices which aren't zoned, or don't support
+    /// that page. Only for host-managed or host-aware devices is
+    /// [`ScsiDevice::report_zones()`] worth trying.
+    pub async fn zone_model(
+        &mut self,
+    ) -> Result<Option<Zoned>, Error<T::Error>> {
+        let inquiry = self.inquiry().await?;
+        if inquiry.peripheral_type == PeripheralType::HostManagedZoned {
+            return Ok(Some(Zoned::HostManaged));
+        }
+        match self.block_device_characteristics_page().await {
+            Ok(page) => Ok(page.zoned()),
+            Err(
+                Error::Scsi(_) | Error::CommandFailed | Error::ProtocolError,
+            ) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Report the zones of a zoned device, starting with the one
+    /// containing `start`
+    ///
+    /// Calls `f` with each zone, in order, which matches `filter`. As
+    /// there can be tens of thousands of zones, they're fetched in
+    /// chunks, with as many REPORT ZONES commands as it takes, each
+    /// using `buf` (which must be at least 128 bytes, and is best made
+    /// somewhat larger) for the reply. Returns the number of zones
+    /// reported.
+    ///
+    /// Fails with `ProtocolError` if `buf` is too small, or if the
+    /// device's replies don't make progress through its zones.
+    pub async fn report_zones<F: FnMut(&ZoneDescriptor)>(
+        &mut self,
+        start: u64,
+        filter: ZoneFilter,
+        buf: &mut [u8],
+        mut f: F,
+    ) -> Result<u64, Error<T::Error>> {
+        let len = buf
+            .len()
+            .min(
+                self.transport()
+                    .max_transfer_bytes()
+                    .unwrap_or(DEFAULT_MAX_TRANSFER_BYTES),
+            )
+            .min(u32::MAX as usize);
+        let len = len - len % DESCRIPTOR_SIZE;
+        if len < 2 * DESCRIPTOR_SIZE {
+            return Err(Error::ProtocolError);
+        }
+        let buf = &mut buf[..len];
+
+        let mut start = start;
+        let mut count = 0;
+        loop {
+            let sz = self
+                .command_in(ReportZones::new(start, filter, len as u32), buf)
+                .await?;
+            if sz < DESCRIPTOR_SIZE {
+                return Err(Error::ProtocolError);
+            }
+            let list_length =
+                u32::from_be_bytes(buf[0..4].try_into().unwrap()) as usize;
+            let max_lba = be64(&buf[8..]);
+            let available = sz.min(DESCRIPTOR_SIZE + list_length);
+            let mut next = None;
+            for d in buf[DESCRIPTOR_SIZE..available]
+                .chunks_exact(DESCRIPTOR_SIZE)
+                .filter_map(ZoneDescriptor::parse)
+            {
+                f(&d);
+                count += 1;
+                next = d.start.checked_add(d.length);
+            }
+            if list_length <= available - DESCRIPTOR_SIZE {
+                return Ok(count);
+            }
+            match next {
+                Some(n) if n > start && n <= max_lba => start = n,
+                Some(n) if n > max_lba => return Ok(count),
+                _ => return Err(Error::ProtocolError),
+            }
+        }
+    }
+
+    /// Open, close, finish, or reset a zone, or all zones
+    ///
+    /// `zone` is the start LBA of the zone, or `None` to act on all
+    /// zones (all for which the action makes sense, that is: for
+    /// instance, [`ZoneAction::Close`] closes all open zones).
+    pub async fn manage_zones(
+        &mut self,
+        action: ZoneAction,
+        zone: Option<u64>,
+    ) -> Result<(), Error<T::Error>> {
+        self.command_no_data(ZbcOut::new(action, zone)).await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/zoned.rs"]
+mod tests;
@@ -0,0 +1,80 @@
+//! Tests against the Linux `scsi_debug` simulated device
+//!
+//! These are ignored by default, as they need the scsi_debug module
+//! loaded, and read-write access to its /dev/sg* node:
+//!
+//! ```sh
+//! sudo modprobe scsi_debug dev_size_mb=16 sector_size=512
+//! sudo cargo test -p cotton-scsi --features sg --test sg -- --ignored
+//! ```
+//!
+//! Alternatively, set `COTTON_SCSI_SG_DEVICE` to the path of any other
+//! SCSI generic device to test against.
+use cotton_scsi::sg::SgTransport;
+use cotton_scsi::{PeripheralType, ScsiDevice};
+use futures::executor::block_on;
+use std::path::PathBuf;
+
+/// Find the /dev/sg* node of a scsi_debug device
+fn scsi_debug_device() -> PathBuf {
+    if let Ok(path) = std::env::var("COTTON_SCSI_SG_DEVICE") {
+        return path.into();
+    }
+    for entry in std::fs::read_dir("/sys/class/scsi_generic")
+        .expect("no SCSI generic devices")
+    {
+        let entry = entry.unwrap();
+        let model = std::fs::read_to_string(entry.path().join("device/model"))
+            .unwrap_or_default();
+        if model.trim() == "scsi_debug" {
+            return PathBuf::from("/dev").join(entry.file_name());
+        }
+    }
+    panic!("no scsi_debug device found (is the module loaded?)");
+}
+
+fn device() -> ScsiDevice<SgTransport> {
+    ScsiDevice::new(SgTransport::open(scsi_debug_device()).unwrap())
+}
+
+#[test]
+#[ignore]
+fn sg_inquiry() {
+    let mut d = device();
+    let data = block_on(d.inquiry()).unwrap();
+    assert_eq!(data.peripheral_type, PeripheralType::Disk);
+    assert_eq!(data.vendor(), "Linux");
+    assert_eq!(data.product(), "scsi_debug");
+}
+
+#[test]
+#[ignore]
+fn sg_read_capacity() {
+    let mut d = device();
+    block_on(d.wait_until_ready(|_| async {}, 10)).unwrap();
+    let (blocks, block_size) = block_on(d.read_capacity()).unwrap();
+    assert_eq!(block_size, 512);
+    assert_eq!(blocks, 16 * 1024 * 1024 / 512);
+}
+
+#[test]
+#[ignore]
+fn sg_read_back() {
+    let mut d = device();
+    block_on(d.probe()).unwrap();
+    let data = [0x5Au8; 512];
+    block_on(d.write_10(7, 1, &data)).unwrap();
+    let mut buf = [0u8; 512];
+    block_on(d.read_10(7, 1, &mut buf)).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+#[ignore]
+fn sg_illegal_request() {
+    let mut d = device();
+    let mut buf = [0u8; 512];
+    // Invalid LBA: sense data must make it through the autosense path
+    assert!(block_on(d.read_10(u32::MAX - 1, 1, &mut buf)).is_err());
+    assert_eq!(d.last_sense().unwrap().key, 5);
+}
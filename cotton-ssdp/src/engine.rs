@@ -583,18 +583,27 @@ impl<CB: Callback, T: Timebase> Engine<CB, T> {
         search: &SCK,
     ) -> Result<(), udp::Error> {
         match e {
-            NetworkEvent::NewLink(ix, _name, flags) => {
+            NetworkEvent::NewLink(ix, _, flags, _)
+            | NetworkEvent::LinkChanged(ix, flags) => {
                 self.on_new_link_event(ix, flags, multicast, search)?;
             }
             NetworkEvent::DelLink(ix) => {
                 self.on_del_link_event(ix, multicast)?;
             }
-            NetworkEvent::NewAddr(ix, addr, _prefix) => {
+            NetworkEvent::NewAddr(ix, addr, _prefix, _info) => {
                 self.on_new_addr_event(ix, addr, search);
             }
             NetworkEvent::DelAddr(ix, addr, _prefix) => {
                 self.on_del_addr_event(ix, addr);
             }
+            NetworkEvent::EnumerationComplete => {}
+            NetworkEvent::Overflow => {
+                // Fresh NewLink/NewAddr events follow; until then,
+                // nothing is known
+                while let Some((ix, _)) = self.interfaces.pop_first() {
+                    Self::leave_multicast(ix, multicast)?;
+                }
+            }
         }
         Ok(())
     }
@@ -1016,6 +1025,7 @@ mod tests {
             cotton_netif::Flags::UP
                 | cotton_netif::Flags::RUNNING
                 | cotton_netif::Flags::MULTICAST,
+            cotton_netif::LinkInfo::default(),
         )
     }
 
@@ -1024,6 +1034,7 @@ mod tests {
             LOCAL_IX,
             "jeth0".to_string(),
             cotton_netif::Flags::MULTICAST,
+            cotton_netif::LinkInfo::default(),
         )
     }
 
@@ -1032,6 +1043,7 @@ mod tests {
             LOCAL_IX,
             "jeth0".to_string(),
             cotton_netif::Flags::UP | cotton_netif::Flags::RUNNING,
+            cotton_netif::LinkInfo::default(),
         )
     }
 
@@ -1039,17 +1051,28 @@ mod tests {
         NetworkEvent::DelLink(LOCAL_IX)
     }
 
+    const NO_ADDR_INFO: cotton_netif::AddrInfo = cotton_netif::AddrInfo {
+        broadcast: None,
+        peer: None,
+        flags: cotton_netif::AddressFlags::empty(),
+        scope: cotton_netif::Scope::Global,
+    };
+
     const NEW_ETH0_ADDR: NetworkEvent =
-        NetworkEvent::NewAddr(LOCAL_IX, LOCAL_SRC, 8);
+        NetworkEvent::NewAddr(LOCAL_IX, LOCAL_SRC, 8, NO_ADDR_INFO);
     const NEW_ETH0_ADDR_2: NetworkEvent =
-        NetworkEvent::NewAddr(LOCAL_IX, LOCAL_SRC_2, 8);
+        NetworkEvent::NewAddr(LOCAL_IX, LOCAL_SRC_2, 8, NO_ADDR_INFO);
     const DEL_ETH0_ADDR: NetworkEvent =
         NetworkEvent::DelAddr(LOCAL_IX, LOCAL_SRC, 8);
     const DEL_ETH0_ADDR_2: NetworkEvent =
         NetworkEvent::DelAddr(LOCAL_IX, LOCAL_SRC_2, 8);
 
-    const NEW_IPV6_ADDR: NetworkEvent =
-        NetworkEvent::NewAddr(LOCAL_IX, IpAddr::V6(Ipv6Addr::LOCALHOST), 64);
+    const NEW_IPV6_ADDR: NetworkEvent = NetworkEvent::NewAddr(
+        LOCAL_IX,
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+        64,
+        NO_ADDR_INFO,
+    );
 
     fn root_advert() -> Advertisement {
         Advertisement {
@@ -1227,6 +1250,37 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn search_sent_on_interface_changed_to_up() {
+        let mut f = Fixture::new_with(|f| {
+            f.e.subscribe("ssdp:all".to_string(), f.c.clone(), &f.s);
+            f.e.on_network_event(&new_eth0_if_down(), &f.s, &f.s)
+                .unwrap();
+            f.e.on_network_event(&NEW_ETH0_ADDR, &f.s, &f.s).unwrap();
+        });
+
+        f.e.on_network_event(
+            &NetworkEvent::LinkChanged(
+                LOCAL_IX,
+                cotton_netif::Flags::UP
+                    | cotton_netif::Flags::RUNNING
+                    | cotton_netif::Flags::MULTICAST,
+            ),
+            &f.s,
+            &f.s,
+        )
+        .unwrap();
+
+        assert!(f.s.send_count() == 1);
+        assert!(f.s.contains_send(
+            multicast_dest(),
+            LOCAL_SRC,
+            |m| matches!(m,
+                         Message::Search { search_target, .. }
+                         if search_target == "ssdp:all")
+        ));
+    }
+
     #[test]
     fn only_one_ssdpall_search_is_sent() {
         let mut f = Fixture::new_with(|f| {
@@ -1688,6 +1742,34 @@ mod tests {
         assert!(f.s.contains_mcast(MULTICAST_IP, LOCAL_IX, false));
     }
 
+    #[test]
+    fn leave_multicast_on_overflow() {
+        let mut f = Fixture::new_with(|f| {
+            f.e.on_network_event(&new_eth0_if(), &f.s, &f.s).unwrap();
+            f.e.on_network_event(&NEW_ETH0_ADDR, &f.s, &f.s).unwrap();
+        });
+
+        f.e.on_network_event(&NetworkEvent::Overflow, &f.s, &f.s)
+            .unwrap();
+
+        assert!(f.s.mcast_count() == 1);
+        assert!(f.s.contains_mcast(MULTICAST_IP, LOCAL_IX, false));
+    }
+
+    #[test]
+    fn rejoin_multicast_after_overflow() {
+        let mut f = Fixture::new_with(|f| {
+            f.e.on_network_event(&new_eth0_if(), &f.s, &f.s).unwrap();
+            f.e.on_network_event(&NetworkEvent::Overflow, &f.s, &f.s)
+                .unwrap();
+        });
+
+        f.e.on_network_event(&new_eth0_if(), &f.s, &f.s).unwrap();
+
+        assert!(f.s.mcast_count() == 1);
+        assert!(f.s.contains_mcast(MULTICAST_IP, LOCAL_IX, true));
+    }
+
     /* ==== Tests for multicast error handling ==== */
 
     #[test]
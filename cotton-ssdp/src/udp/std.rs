@@ -369,7 +369,12 @@ mod tests {
 
     fn local_ipv4() -> Option<Ipv4Addr> {
         cotton_netif::get_interfaces().unwrap().find_map(|e| {
-            if let cotton_netif::NetworkEvent::NewAddr(_, IpAddr::V4(a), _) = e
+            if let cotton_netif::NetworkEvent::NewAddr(
+                _,
+                IpAddr::V4(a),
+                _,
+                _,
+            ) = e
             {
                 if a == Ipv4Addr::LOCALHOST {
                     None
@@ -0,0 +1,41 @@
+use cotton_scsi::AsyncBlockDevice;
+use cotton_usb_host::bench::Throughput;
+
+/// Time sequential reads through a block device
+///
+/// Reads `total_blocks` blocks, starting at block `start`, in pieces
+/// as large as `buf` (rounded down to a whole number of blocks), and
+/// times the lot using `now`, a clock counting in microseconds. The
+/// device's block size is asked for before the clock starts.
+///
+/// For a sweep of chunk sizes, call this repeatedly with differently
+/// sized slices of one buffer, and report each result with the chunk
+/// size as the [`Report`](cotton_usb_host::bench::Report) parameter.
+pub async fn read_throughput<D: AsyncBlockDevice>(
+    device: &mut D,
+    start: u64,
+    total_blocks: u32,
+    buf: &mut [u8],
+    now: impl Fn() -> u64,
+) -> Result<Throughput, D::E> {
+    let block_size = device.device_info().await?.block_size;
+    let chunk_blocks = (buf.len() as u32 / block_size).max(1);
+    let t0 = now();
+    let mut done = 0;
+    while done < total_blocks {
+        let count = chunk_blocks.min(total_blocks - done);
+        let len = (count * block_size) as usize;
+        device
+            .read_blocks(start + done as u64, count, &mut buf[0..len])
+            .await?;
+        done += count;
+    }
+    Ok(Throughput {
+        bytes: total_blocks as u64 * block_size as u64,
+        elapsed_us: now().wrapping_sub(t0),
+    })
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/bench.rs"]
+mod tests;
@@ -1,4 +1,11 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+#[cfg(feature = "bench")]
+pub mod bench;
 mod debug;
 pub mod mass_storage;
-pub use mass_storage::{IdentifyMassStorage, MassStorage};
+pub mod uas;
+pub use mass_storage::{
+    probe_with_identity, IdentifyMassStorage, MassStorage,
+    MassStorageTransport,
+};
+pub use uas::{UasInterface, UasTransport};
@@ -1,21 +1,40 @@
 use super::debug;
-use cotton_scsi::scsi_transport::DataPhase;
-use cotton_scsi::{Error, ScsiTransport};
-use cotton_usb_host::device::identify::IdentifyFromDescriptors;
-use cotton_usb_host::host_controller::{HostController, UsbError};
-use cotton_usb_host::usb_bus::{
-    BulkIn, BulkOut, TransferType, UsbBus, UsbDevice,
+use crate::uas::{UasInterface, UasTransport};
+use cotton_scsi::scsi_transport::{DataPhase, ReadBuffer};
+use cotton_scsi::{
+    DeviceCapabilities, Error, ProbeHints, ScsiDevice, ScsiQuirks, ScsiStatus,
+    ScsiTransport,
 };
+use cotton_usb_host::device::identify::IdentifyFromDescriptors;
+use cotton_usb_host::host_controller::{self, HostController, UsbError};
+use cotton_usb_host::identity::{DeviceIdentity, ScsiCapabilities};
+use cotton_usb_host::quirks::Quirks;
+use cotton_usb_host::usb_bus::{BulkIn, BulkOut, UsbBus, UsbDevice};
 use cotton_usb_host::wire::{
-    ConfigurationDescriptor, DescriptorVisitor, InterfaceDescriptor,
+    ConfigurationDescriptor, DescriptorVisitor, Direction, EndpointDescriptor,
+    InterfaceDescriptor, Recipient, RequestType, RequestTypeType, SetupPacket,
 };
 
+/// GET MAX LUN class request (USB MSC BOT section 3.2)
+const GET_MAX_LUN: u8 = 0xFE;
+
+/// The interface protocol for USB Attached SCSI (UAS section 5.3.2)
+const UAS_PROTOCOL: u8 = 0x62;
+
+/// The descriptor type of a UAS pipe usage descriptor (UAS 5.3.3.1)
+const PIPE_USAGE_DESCRIPTOR: u8 = 0x24;
+
+/// "USBS", the first four bytes of every command status wrapper (BOT 5.2)
+const CSW_SIGNATURE: u32 = 0x53425355;
+
 pub struct MassStorage<'a, HC: HostController> {
     bus: &'a UsbBus<HC>,
-    //device: UsbDevice,
+    device: UsbDevice,
     bulk_in: BulkIn,
     bulk_out: BulkOut,
     tag: u32,
+    quirks: Quirks,
+    last_status: Option<ScsiStatus>,
 }
 
 impl<'a, HC: HostController> MassStorage<'a, HC> {
@@ -23,32 +42,102 @@ impl<'a, HC: HostController> MassStorage<'a, HC> {
         bus: &'a UsbBus<HC>,
         mut device: UsbDevice,
     ) -> Result<Self, UsbError> {
+        if !bus.capabilities().bulk {
+            return Err(UsbError::Unsupported);
+        }
         let in_ep = device.in_endpoints().iter().next().unwrap_or_default();
         let bulk_in = device.open_in_endpoint(in_ep)?;
         let out_ep = device.out_endpoints().iter().next().unwrap_or_default();
         let bulk_out = device.open_out_endpoint(out_ep)?;
+        let quirks = device.quirks();
         Ok(Self {
             bus,
-            //device,
+            device,
             bulk_in,
             bulk_out,
             tag: 1,
+            quirks,
+            last_status: None,
         })
     }
+
+    /// The highest logical unit number on the device (usually 0)
+    ///
+    /// Asks the device with GET MAX LUN, unless its quirks say not to.
+    /// Devices with only one LUN are allowed to stall the request,
+    /// which is also reported as 0. The mass-storage interface is
+    /// assumed to be interface 0.
+    pub async fn max_lun(&self) -> Result<u8, UsbError> {
+        if self.quirks.force_single_lun {
+            return Ok(0);
+        }
+        let mut data = [0u8; 1];
+        let rc = self
+            .bus
+            .control_transfer(
+                &self.device,
+                SetupPacket::new(
+                    RequestType::new(Direction::In)
+                        .kind(RequestTypeType::Class)
+                        .recipient(Recipient::Interface),
+                    GET_MAX_LUN,
+                    0,
+                    0,
+                    1,
+                ),
+                host_controller::DataPhase::In(&mut data),
+            )
+            .await;
+        match rc {
+            Ok(1) => Ok(data[0]),
+            Ok(_) | Err(UsbError::Stall) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct IdentifyMassStorage {
     current_configuration: Option<u8>,
     msc_configuration: Option<u8>,
+    in_uas_interface: bool,
+    last_endpoint: u8,
+    uas: Option<UasInterface>,
+}
+
+impl IdentifyMassStorage {
+    /// The device's UAS interface, if it has one
+    ///
+    /// Only a UAS interface in the configuration returned by
+    /// [`identify()`](IdentifyFromDescriptors::identify), and whose
+    /// four pipes were all found, counts.
+    pub fn uas(&self) -> Option<UasInterface> {
+        self.uas.filter(|u| {
+            u.is_complete() && Some(u.configuration) == self.msc_configuration
+        })
+    }
 }
 
 impl DescriptorVisitor for IdentifyMassStorage {
     fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
         self.current_configuration = Some(c.bConfigurationValue);
+        self.in_uas_interface = false;
     }
     fn on_interface(&mut self, i: &InterfaceDescriptor) {
-        if i.bInterfaceClass == 8 && i.bInterfaceProtocol == 0x50 {
+        self.in_uas_interface = false;
+        if i.bInterfaceClass == 8 && i.bInterfaceProtocol == UAS_PROTOCOL {
+            self.msc_configuration = self.current_configuration;
+            // The first complete one wins
+            if !self.uas.is_some_and(|u| u.is_complete()) {
+                self.in_uas_interface = true;
+                self.uas = Some(UasInterface {
+                    configuration: self.current_configuration.unwrap_or(0),
+                    interface: i.bInterfaceNumber,
+                    alternate_setting: i.bAlternateSetting,
+                    ..Default::default()
+                });
+            }
+        } else if i.bInterfaceClass == 8 && i.bInterfaceProtocol == 0x50 {
             self.msc_configuration = self.current_configuration;
         } else {
             debug::println!(
@@ -59,6 +148,19 @@ impl DescriptorVisitor for IdentifyMassStorage {
             );
         }
     }
+    fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+        self.last_endpoint = e.bEndpointAddress;
+    }
+    fn on_other(&mut self, d: &[u8]) {
+        if self.in_uas_interface
+            && d.len() >= 3
+            && d[1] == PIPE_USAGE_DESCRIPTOR
+        {
+            if let Some(uas) = self.uas.as_mut() {
+                uas.set_pipe(d[2], self.last_endpoint);
+            }
+        }
+    }
 }
 
 impl IdentifyFromDescriptors for IdentifyMassStorage {
@@ -108,16 +210,19 @@ impl CommandBlockWrapper {
 impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
     type Error = UsbError;
 
+    fn last_status(&self) -> Option<ScsiStatus> {
+        self.last_status
+    }
+
+    fn quirks(&self) -> ScsiQuirks {
+        scsi_quirks(&self.quirks)
+    }
+
     async fn command(
         &mut self,
         cmd: &[u8],
         data: DataPhase<'_>,
     ) -> Result<usize, Error<Self::Error>> {
-        //let rc = self.bus.clear_halt(&self.bulk_in).await;
-        //debug::println!("clear {:?}", rc);
-
-        self.tag += 2;
-
         let len = match data {
             DataPhase::In(ref buf) => buf.len(),
             DataPhase::Out(buf) => buf.len(),
@@ -127,65 +232,151 @@ impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
             DataPhase::In(_) => 0x80,
             _ => 0,
         };
+        self.send_cbw(cmd, len, flags).await?;
+
+        let response = match data {
+            DataPhase::In(buf) => self.bulk_in.read_exact(self.bus, buf).await,
+            DataPhase::Out(buf) => {
+                self.bulk_out.write_all(self.bus, buf).await
+            }
+            DataPhase::None => Ok(0),
+        };
+        self.finish(response).await
+    }
+
+    async fn command_in_uninit(
+        &mut self,
+        cmd: &[u8],
+        data: &mut ReadBuffer<'_>,
+        _timeout_ms: u32,
+    ) -> Result<usize, Error<Self::Error>> {
+        self.send_cbw(cmd, data.remaining(), 0x80).await?;
+
+        let before = data.len();
+        let response = read_uninit(self.bus, &self.bulk_in, data).await;
+        let n = data.len() - before;
+        // Even if the device stalled part-way, what was received
+        // before that is in `data`, so report it
+        self.finish(response).await.map(|_| n)
+    }
+}
+
+/// The SCSI-level workarounds among a USB device's quirks
+pub(crate) fn scsi_quirks(quirks: &Quirks) -> ScsiQuirks {
+    ScsiQuirks {
+        no_vpd: quirks.no_vpd,
+        no_rsoc: quirks.no_rsoc,
+        max_transfer_blocks: quirks.max_transfer_blocks,
+    }
+}
+
+/// Probe a mass-storage device, using and completing its identity record
+///
+/// If `identity` (from
+/// [`UsbBus::device_identity()`](cotton_usb_host::usb_bus::UsbBus::device_identity),
+/// called before creating the transport, so that any preloaded quirks
+/// are in force) already holds the results of an earlier probe, they
+/// are passed to [`ScsiDevice::preload()`], so that the probe skips
+/// the optional steps. Either way, the results of this probe are put
+/// into `identity`, which is then reported with
+/// [`UsbBus::report_identity()`].
+pub async fn probe_with_identity<HC: HostController, T: ScsiTransport>(
+    bus: &UsbBus<HC>,
+    scsi: &mut ScsiDevice<T>,
+    identity: &mut DeviceIdentity,
+) -> Result<DeviceCapabilities, Error<T::Error>> {
+    if let Some(known) = identity.capabilities.scsi {
+        scsi.preload(ProbeHints {
+            supports_16: known.supports_16,
+            max_transfer_blocks: known.max_transfer_blocks,
+        });
+    }
+    let caps = scsi.probe().await?;
+    identity.capabilities.scsi =
+        scsi.probe_hints().map(|h| ScsiCapabilities {
+            supports_16: h.supports_16,
+            max_transfer_blocks: h.max_transfer_blocks,
+        });
+    bus.report_identity(identity);
+    Ok(caps)
+}
+
+/// Read from a bulk IN endpoint into the unfilled part of a SCSI
+/// `ReadBuffer`
+///
+/// cotton-usb-host has its own `ReadBuffer`, so this lends the unfilled
+/// part of `data` to one of those. Whatever is received is added to
+/// the filled part of `data`, even if the transfer then fails.
+pub(crate) async fn read_uninit<HC: HostController>(
+    bus: &UsbBus<HC>,
+    bulk_in: &BulkIn,
+    data: &mut ReadBuffer<'_>,
+) -> Result<usize, UsbError> {
+    let init = data.init_len() - data.len();
+    // SAFETY: the USB ReadBuffer never de-initialises bytes
+    let mut buf =
+        host_controller::ReadBuffer::new(unsafe { data.unfilled_mut() });
+    // SAFETY: these bytes were already initialised in `data`
+    unsafe { buf.set_init(init) };
+    let response = bulk_in.read_exact_uninit(bus, &mut buf).await;
+    let n = buf.len();
+    // SAFETY: `buf` covers the start of data's unfilled part, and
+    // its filled part is initialised (ReadBuffer's invariant)
+    unsafe { data.assume_filled(n) };
+    response
+}
+
+impl<HC: HostController> MassStorage<'_, HC> {
+    /// Send the command block wrapper for a command (BOT section 5.1)
+    async fn send_cbw(
+        &mut self,
+        cmd: &[u8],
+        len: usize,
+        flags: u8,
+    ) -> Result<(), Error<UsbError>> {
+        //let rc = self.bus.clear_halt(&self.bulk_in).await;
+        //debug::println!("clear {:?}", rc);
+
+        self.tag += 2;
+        self.last_status = None;
+
         let cbw = CommandBlockWrapper::new(self.tag, len as u32, flags, cmd);
         // NB the CommandBlockWrapper struct has no padding as
         // defined, but it's one byte too long (an actual, on-the-wire
         // command block wrapper is 31 bytes). So we only send a
         // partial slice of it.
         if self
-            .bus
-            .bulk_out_transfer(
-                &self.bulk_out,
-                &bytemuck::bytes_of(&cbw)[0..31],
-                TransferType::FixedSize,
-            )
+            .bulk_out
+            .write_all(self.bus, &bytemuck::bytes_of(&cbw)[0..31])
             .await
             .map_err(Error::Transport)?
             < 31
         {
-            return Err(Error::ProtocolError);
+            return Err(Error::Transport(UsbError::Protocol("short CBW")));
         }
-        //debug::println!("bot {:?}", rc);
-        //rc?;
+        Ok(())
+    }
 
-        let response = match data {
-            DataPhase::In(buf) => {
-                // let rc=
+    /// Deal with the outcome of a command's data phase, and collect
+    /// its command status wrapper (BOT section 5.2)
+    async fn finish(
+        &mut self,
+        response: Result<usize, UsbError>,
+    ) -> Result<usize, Error<UsbError>> {
+        let response = if response == Err(UsbError::Stall) {
+            debug::println!("msc bulk stall");
+            // BOT section 6.7.2/6.7.3: clear whichever pipe stalled
+            if self.bulk_out.is_halted() {
                 self.bus
-                    .bulk_in_transfer(
-                        &self.bulk_in,
-                        buf,
-                        TransferType::FixedSize,
-                    )
+                    .clear_out_halt(&self.bulk_out)
                     .await
-                /*
-                if let Ok(n) = rc {
-                    if n > 128 {
-                        debug::println!("{}: [...]", n);
-                    } else {
-                        debug::println!("{}: {:?}", n, buf);
-                    }
-                }
-                rc
-                */
-            }
-            DataPhase::Out(buf) => {
+                    .map_err(Error::Transport)?;
+            } else {
                 self.bus
-                    .bulk_out_transfer(
-                        &self.bulk_out,
-                        buf,
-                        TransferType::FixedSize,
-                    )
+                    .clear_halt(&self.bulk_in)
                     .await
+                    .map_err(Error::Transport)?;
             }
-            DataPhase::None => Ok(0),
-        };
-        let response = if response == Err(UsbError::Stall) {
-            debug::println!("msc bulk stall");
-            self.bus
-                .clear_halt(&self.bulk_in)
-                .await
-                .map_err(Error::Transport)?;
             // TODO: partial result THEN stall
             0
         } else {
@@ -194,27 +385,126 @@ impl<HC: HostController> ScsiTransport for MassStorage<'_, HC> {
 
         let mut csw = [0u8; 13];
         let sz = self
-            .bus
-            .bulk_in_transfer(&self.bulk_in, &mut csw, TransferType::FixedSize)
+            .bulk_in
+            .read_exact(self.bus, &mut csw)
             .await
             .map_err(Error::Transport)?;
         if sz < 13 {
             debug::println!("Bad CSW {}/13", sz);
-            return Err(Error::ProtocolError);
+            return Err(Error::Transport(UsbError::Protocol("short CSW")));
+        }
+        if u32::from_le_bytes(csw[0..4].try_into().unwrap()) != CSW_SIGNATURE {
+            return Err(Error::Transport(UsbError::Protocol(
+                "bad CSW signature",
+            )));
         }
-        /*
-        let sig = u32::from_le_bytes(&csw[0..4]);
-        let tag = u32::from_le_bytes(&csw[4..8]);
-         */
         let residue = u32::from_le_bytes(csw[8..12].try_into().unwrap());
         let status = csw[12];
         if status != 0 || residue != 0 {
             debug::println!("status {} residue {}", status, residue);
         }
+        // BOT can't see the actual status byte: "passed" also covers
+        // CONDITION MET, and "failed" covers BUSY and the rest
+        // (whereupon REQUEST SENSE is all we can do)
+        self.last_status = match status {
+            0 => Some(ScsiStatus::Good),
+            1 => Some(ScsiStatus::CheckCondition),
+            _ => None,
+        };
         match status {
             0 => Ok(response),
             1 => Err(Error::CommandFailed),
-            _ => Err(Error::ProtocolError),
+            2 => Err(Error::Transport(UsbError::Protocol("CSW phase error"))),
+            _ => Err(Error::Transport(UsbError::Protocol("bad CSW status"))),
+        }
+    }
+}
+
+/// A mass-storage device, using UAS if it can, or Bulk-Only Transport
+/// if not
+///
+/// Devices which support USB Attached SCSI usually still offer BOT,
+/// as the default alternate setting of their mass-storage interface,
+/// for the benefit of hosts without UAS. This picks UAS if
+/// [`IdentifyMassStorage`] found a UAS interface whose pipes can be
+/// opened, and the device accepts SET_INTERFACE for it, and falls back
+/// to BOT otherwise.
+#[allow(clippy::large_enum_variant)] // no_std: can't box, and there's only one
+pub enum MassStorageTransport<'a, HC: HostController> {
+    /// Bulk-Only Transport
+    Bot(MassStorage<'a, HC>),
+    /// USB Attached SCSI
+    Uas(UasTransport<'a, HC>),
+}
+
+impl<'a, HC: HostController> MassStorageTransport<'a, HC> {
+    /// Open a configured mass-storage device, preferring UAS
+    ///
+    /// The `identity` should be the result of passing the device's
+    /// configuration descriptors to an [`IdentifyMassStorage`], as
+    /// must be done anyway to find the configuration to configure.
+    pub async fn new(
+        bus: &'a UsbBus<HC>,
+        device: UsbDevice,
+        identity: &IdentifyMassStorage,
+    ) -> Result<Self, UsbError> {
+        if let Some(uas) = identity.uas() {
+            // Checked first, so that the device is never left in the
+            // UAS alternate setting without a UasTransport to use it
+            if UasTransport::check(bus, &device, &uas).is_err() {
+                debug::println!("UAS pipes unavailable, using BOT");
+            } else if UasTransport::select(bus, &device, &uas).await.is_ok() {
+                return Ok(Self::Uas(UasTransport::new(bus, device, &uas)?));
+            } else {
+                debug::println!("SET_INTERFACE refused, using BOT");
+            }
+        }
+        Ok(Self::Bot(MassStorage::new(bus, device)?))
+    }
+
+    /// Is this using UAS (rather than Bulk-Only Transport)?
+    pub fn is_uas(&self) -> bool {
+        matches!(self, Self::Uas(_))
+    }
+}
+
+impl<HC: HostController> ScsiTransport for MassStorageTransport<'_, HC> {
+    type Error = UsbError;
+
+    fn last_status(&self) -> Option<ScsiStatus> {
+        match self {
+            Self::Bot(m) => m.last_status(),
+            Self::Uas(u) => u.last_status(),
+        }
+    }
+
+    fn quirks(&self) -> ScsiQuirks {
+        match self {
+            Self::Bot(m) => m.quirks(),
+            Self::Uas(u) => u.quirks(),
+        }
+    }
+
+    async fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase<'_>,
+    ) -> Result<usize, Error<Self::Error>> {
+        match self {
+            Self::Bot(m) => m.command(cmd, data).await,
+            Self::Uas(u) => u.command(cmd, data).await,
+        }
+    }
+
+    async fn command_in_uninit(
+        &mut self,
+        cmd: &[u8],
+        data: &mut ReadBuffer<'_>,
+        timeout_ms: u32,
+    ) -> Result<usize, Error<Self::Error>> {
+        match self {
+            Self::Bot(m) => m.command_in_uninit(cmd, data, timeout_ms).await,
+            Self::Uas(u) => u.command_in_uninit(cmd, data, timeout_ms).await,
         }
     }
 }
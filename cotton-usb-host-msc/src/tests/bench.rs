@@ -0,0 +1,77 @@
+use super::*;
+use cotton_scsi::DeviceInfo;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Records the reads it's asked to do
+#[derive(Default)]
+struct FakeDisk {
+    reads: Vec<(u64, u32, usize)>,
+}
+
+impl AsyncBlockDevice for FakeDisk {
+    type E = ();
+
+    async fn device_info(&mut self) -> Result<DeviceInfo, ()> {
+        Ok(DeviceInfo {
+            blocks: 1000,
+            block_size: 512,
+            physical_block_size: 512,
+        })
+    }
+
+    async fn read_blocks(
+        &mut self,
+        offset: u64,
+        count: u32,
+        data: &mut [u8],
+    ) -> Result<(), ()> {
+        self.reads.push((offset, count, data.len()));
+        Ok(())
+    }
+
+    async fn write_blocks(
+        &mut self,
+        _offset: u64,
+        _count: u32,
+        _data: &[u8],
+    ) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+#[test]
+fn read_throughput_chunks() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let t = Cell::new(0u64);
+    let now = || {
+        t.set(t.get() + 500_000);
+        t.get()
+    };
+
+    let mut disk = FakeDisk::default();
+    let mut buf = [0u8; 4096];
+    let rc = pin!(read_throughput(&mut disk, 100, 20, &mut buf[0..2000], now))
+        .poll(&mut c);
+    let Poll::Ready(Ok(result)) = rc else {
+        panic!("{:?}", rc);
+    };
+    assert_eq!(result.bytes, 10240);
+    assert_eq!(result.elapsed_us, 500_000);
+    assert_eq!(result.kbytes_per_sec(), 20);
+    // 2000 bytes rounds down to 3 blocks
+    assert_eq!(disk.reads.len(), 7);
+    assert_eq!(disk.reads[0], (100, 3, 1536));
+    assert_eq!(disk.reads[5], (115, 3, 1536));
+    assert_eq!(disk.reads[6], (118, 2, 1024));
+}
@@ -1,11 +1,14 @@
 use super::*;
 use cotton_scsi::scsi_transport;
+use cotton_scsi::testing::FakeScsiTransport;
+use cotton_usb_host::identity::Capabilities;
 use cotton_usb_host::mocks::{MockHostController, MockHostControllerInner};
-use cotton_usb_host::usb_bus::{create_test_device, UsbBus};
+use cotton_usb_host::usb_bus::{create_test_device, TransferType, UsbBus};
 use cotton_usb_host::wire::SetupPacket;
-use futures::{future, Future};
+use futures::{future, Future, FutureExt};
 use std::cell::Cell;
 use std::fmt::Debug;
+use std::mem::MaybeUninit;
 use std::pin::{pin, Pin};
 use std::sync::Arc;
 use std::task::{Poll, Wake, Waker};
@@ -155,6 +158,17 @@ fn do_test<
     SetupFn: FnMut(&mut MockHostControllerInner),
     TestFn: FnMut(Fixture),
 >(
+    setup: SetupFn,
+    test: TestFn,
+) {
+    do_test_with_quirks(Quirks::NONE, setup, test);
+}
+
+fn do_test_with_quirks<
+    SetupFn: FnMut(&mut MockHostControllerInner),
+    TestFn: FnMut(Fixture),
+>(
+    quirks: Quirks,
     mut setup: SetupFn,
     mut test: TestFn,
 ) {
@@ -166,7 +180,8 @@ fn do_test<
     setup(&mut hc.inner);
     let bus = UsbBus::new(hc);
     // SAFETY: we don't use this with a non-mock bus
-    let device = unsafe { create_test_device(2, 2) };
+    let mut device = unsafe { create_test_device(2, 2) };
+    device.set_quirks(quirks);
 
     let f = Fixture {
         c: &mut c,
@@ -177,6 +192,7 @@ fn do_test<
 }
 
 fn status_ok(data: &mut [u8]) -> usize {
+    data[0..4].copy_from_slice(b"USBS");
     data.len()
 }
 
@@ -278,6 +294,20 @@ fn test_new_fails() {
     assert!(MassStorage::new(&bus, device).is_err());
 }
 
+#[test]
+fn test_new_without_bulk() {
+    let mut hc = MockHostController::default();
+    hc.capabilities.bulk = false;
+    let bus = UsbBus::new(hc);
+
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(2, 2) };
+    assert!(matches!(
+        MassStorage::new(&bus, device),
+        Err(UsbError::Unsupported)
+    ));
+}
+
 #[test]
 fn test_command_nodata() {
     do_test(
@@ -327,7 +357,7 @@ fn test_command_nodata_short() {
         |mut f| {
             f.c.check_fails_custom(
                 f.m.command(&[42u8], DataPhase::None),
-                Error::ProtocolError,
+                Error::Transport(UsbError::Protocol("short CBW")),
             );
         },
     );
@@ -402,7 +432,7 @@ fn test_command_nodata_reply_short() {
         |mut f| {
             f.c.check_fails_custom(
                 f.m.command(&[42u8], DataPhase::None),
-                Error::ProtocolError,
+                Error::Transport(UsbError::Protocol("short CSW")),
             );
         },
     );
@@ -488,9 +518,74 @@ fn test_command_in() {
         },
         |mut f| {
             let mut buf = [0; 512];
+            assert_eq!(f.m.last_status(), None);
             let result =
                 f.c.check_ok(f.m.command(&[43, 43], DataPhase::In(&mut buf)));
             assert_eq!(result, 512);
+            assert_eq!(f.m.last_status(), Some(ScsiStatus::Good));
+        },
+    );
+}
+
+#[test]
+fn test_command_in_uninit() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| {
+                    d.len() == 31 && d[8..12] == 512u32.to_le_bytes()
+                })
+                .returning(bulk_out_ok::<31>);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 512)
+                .returning(bulk_in_ok_with(|d| {
+                    d[0..100].fill(0x33);
+                    100
+                }));
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 13)
+                .returning(bulk_in_ok_with(status_ok));
+        },
+        |mut f| {
+            let mut data = [MaybeUninit::uninit(); 512];
+            let mut buf = ReadBuffer::new(&mut data);
+            let result =
+                f.c.check_ok(f.m.command_in_uninit(&[43, 43], &mut buf, 0));
+            assert_eq!(result, 100);
+            assert_eq!(buf.filled(), [0x33; 100]);
+        },
+    );
+}
+
+#[test]
+fn test_command_in_uninit_stalls() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .returning(bulk_out_ok::<31>);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 512)
+                .returning(bulk_in_stalls);
+            hc.expect_control_transfer()
+                .times(1)
+                .returning(control_transfer_ok::<0>);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 13)
+                .returning(bulk_in_ok_with(status_ok));
+        },
+        |mut f| {
+            let mut data = [MaybeUninit::uninit(); 512];
+            let mut buf = ReadBuffer::new(&mut data);
+            let result =
+                f.c.check_ok(f.m.command_in_uninit(&[43, 43], &mut buf, 0));
+            assert_eq!(result, 0);
+            assert!(buf.is_empty());
         },
     );
 }
@@ -690,6 +785,41 @@ fn test_command_out() {
     );
 }
 
+#[test]
+fn test_command_out_stalls() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 31)
+                .returning(bulk_out_ok::<31>);
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 512)
+                .returning(|_, _, _, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                });
+            // CLEAR_FEATURE(ENDPOINT_HALT) on the OUT endpoint, not the IN
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(|_, _, s, _| {
+                    s.to_bytes() == [0x02, 0x01, 0, 0, 1, 0, 0, 0]
+                })
+                .returning(control_transfer_ok::<0>);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 13)
+                .returning(bulk_in_ok_with(status_ok));
+        },
+        |mut f| {
+            let buf = [0; 512];
+            let result =
+                f.c.check_ok(f.m.command(&[44, 44, 44], DataPhase::Out(&buf)));
+            assert_eq!(result, 0);
+        },
+    );
+}
+
 #[test]
 fn test_command_out_pends() {
     do_test(
@@ -745,7 +875,7 @@ fn test_command_out_fail_status() {
                 .withf(|_, _, _, d, _, _| d.len() == 13)
                 .returning(bulk_in_ok_with(|d| {
                     d[12] = 1;
-                    13
+                    status_ok(d)
                 }));
         },
         |mut f| {
@@ -754,6 +884,7 @@ fn test_command_out_fail_status() {
                 f.m.command(&[44, 44, 44], DataPhase::Out(&buf)),
                 Error::CommandFailed,
             );
+            assert_eq!(f.m.last_status(), Some(ScsiStatus::CheckCondition));
         },
     );
 }
@@ -784,19 +915,59 @@ fn test_command_out_wild_status() {
                 .withf(|_, _, _, d, _, _| d.len() == 13)
                 .returning(bulk_in_ok_with(|d| {
                     d[12] = 135;
-                    13
+                    status_ok(d)
                 }));
         },
         |mut f| {
             let buf = [0; 512];
             f.c.check_fails_custom(
                 f.m.command(&[44, 44, 44], DataPhase::Out(&buf)),
-                Error::ProtocolError,
+                Error::Transport(UsbError::Protocol("bad CSW status")),
             );
         },
     );
 }
 
+fn do_test_bad_csw<F: FnMut(&mut [u8]) -> usize + Send + 'static>(
+    csw: F,
+    e: UsbError,
+) {
+    let mut csw = Some(csw);
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .returning(bulk_out_ok::<31>);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, _, _, d, _, _| d.len() == 13)
+                .returning(bulk_in_ok_with(csw.take().unwrap()));
+        },
+        |mut f| {
+            f.c.check_fails_custom(
+                f.m.command(&[42u8], DataPhase::None),
+                Error::Transport(e),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_command_bad_csw_signature() {
+    do_test_bad_csw(|d| d.len(), UsbError::Protocol("bad CSW signature"));
+}
+
+#[test]
+fn test_command_csw_phase_error() {
+    do_test_bad_csw(
+        |d| {
+            d[12] = 2;
+            status_ok(d)
+        },
+        UsbError::Protocol("CSW phase error"),
+    );
+}
+
 const HANDBAG: &[u8] = &[
     9, 2, 32, 0, 1, 1, 0, 128, 50, 9, 4, 0, 0, 2, 8, 6, 80, 0, 7, 5, 1, 2, 0,
     2, 0, 7, 5, 129, 2, 0, 2, 0,
@@ -837,3 +1008,207 @@ fn test_dont_identify_mass_storage() {
     cotton_usb_host::wire::parse_descriptors(ELLA, &mut ims);
     assert_eq!(ims.identify(), None);
 }
+
+fn is_get_max_lun(
+    _: &u8,
+    _: &u8,
+    s: &SetupPacket,
+    d: &cotton_usb_host::host_controller::DataPhase,
+) -> bool {
+    s.to_bytes() == [0xA1, 0xFE, 0, 0, 0, 0, 1, 0] && d.is_in()
+}
+
+fn control_transfer_stalls(
+    _: u8,
+    _: u8,
+    _: SetupPacket,
+    _: cotton_usb_host::host_controller::DataPhase,
+) -> Pin<Box<dyn Future<Output = Result<usize, UsbError>>>> {
+    Box::pin(future::ready(Err(UsbError::Stall)))
+}
+
+#[test]
+fn test_max_lun() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_max_lun)
+                .returning(|_, _, _, mut d| {
+                    d.in_with(|b| b[0] = 3);
+                    Box::pin(future::ready(Ok(1)))
+                });
+        },
+        |f| {
+            let rc = pin!(f.m.max_lun()).poll(f.c).to_option().unwrap();
+            assert_eq!(rc, Ok(3));
+        },
+    );
+}
+
+#[test]
+fn test_max_lun_stall() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_max_lun)
+                .returning(control_transfer_stalls);
+        },
+        |f| {
+            let rc = pin!(f.m.max_lun()).poll(f.c).to_option().unwrap();
+            assert_eq!(rc, Ok(0));
+        },
+    );
+}
+
+#[test]
+fn test_max_lun_fails() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_max_lun)
+                .returning(control_transfer_fails);
+        },
+        |f| {
+            let rc = pin!(f.m.max_lun()).poll(f.c).to_option().unwrap();
+            assert_eq!(rc, Err(UsbError::Timeout));
+        },
+    );
+}
+
+#[test]
+fn test_max_lun_quirk() {
+    // No GET MAX LUN at all
+    do_test_with_quirks(
+        Quirks {
+            force_single_lun: true,
+            ..Quirks::NONE
+        },
+        |hc| {
+            hc.expect_control_transfer().times(0);
+        },
+        |f| {
+            let rc = pin!(f.m.max_lun()).poll(f.c).to_option().unwrap();
+            assert_eq!(rc, Ok(0));
+        },
+    );
+}
+
+#[test]
+fn test_quirks_reach_scsi_device() {
+    do_test_with_quirks(
+        Quirks {
+            no_vpd: true,
+            max_transfer_blocks: Some(16),
+            ..Quirks::NONE
+        },
+        |hc| {
+            // The Block Limits Page isn't asked for
+            hc.expect_bulk_out_transfer().times(0);
+            hc.expect_bulk_in_transfer().times(0);
+        },
+        |f| {
+            let mut d = cotton_scsi::ScsiDevice::new(f.m);
+            assert_eq!(
+                d.quirks(),
+                ScsiQuirks {
+                    no_vpd: true,
+                    no_rsoc: false,
+                    max_transfer_blocks: Some(16),
+                }
+            );
+            let rc = pin!(d.block_limits_page()).poll(f.c).to_option();
+            assert!(rc.unwrap().is_err());
+        },
+    );
+}
+
+thread_local! {
+    static REPORTED: std::cell::RefCell<Vec<DeviceIdentity>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+fn on_identity(id: &DeviceIdentity) {
+    REPORTED.with(|r| r.borrow_mut().push(*id));
+}
+
+/// INQUIRY, TEST UNIT READY, READ CAPACITY(10): the probe steps that
+/// are never skipped
+fn expect_probe_start(fake: &mut FakeScsiTransport) {
+    let mut inquiry = [0u8; 36];
+    inquiry[4] = 31;
+    fake.expect_in(&[0x12, 0, 0, 0, 36, 0], &inquiry)
+        .expect_no_data(&[0; 6])
+        .expect_in(
+            &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 63, 0, 0, 2, 0],
+        );
+}
+
+#[test]
+fn test_probe_with_preloaded_identity() {
+    let mut bus = UsbBus::new(MockHostController::default());
+    bus.set_on_identity(Some(on_identity));
+
+    // A known device: no RSOC, no READ CAPACITY(16), no Block Limits
+    let mut fake = FakeScsiTransport::new();
+    expect_probe_start(&mut fake);
+    fake.expect_in(&[0x1A, 8, 0x3F, 0, 4, 0], &[3, 0, 0, 0]);
+    let mut scsi = ScsiDevice::new(fake);
+    let known = ScsiCapabilities {
+        supports_16: false,
+        max_transfer_blocks: Some(32),
+    };
+    let mut identity = DeviceIdentity {
+        vid: 0x0781,
+        pid: 0x5567,
+        serial_hash: 42,
+        capabilities: Capabilities {
+            quirks: Quirks::NONE,
+            scsi: Some(known),
+        },
+    };
+
+    let caps = probe_with_identity(&bus, &mut scsi, &mut identity)
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    assert_eq!(caps.blocks, 64);
+    assert_eq!(caps.max_transfer_blocks, Some(32));
+    assert_eq!(identity.capabilities.scsi, Some(known));
+    REPORTED.with(|r| assert_eq!(*r.borrow(), vec![identity]));
+}
+
+#[test]
+fn test_probe_with_new_identity() {
+    let bus = UsbBus::new(MockHostController::default());
+
+    let mut fake = FakeScsiTransport::new();
+    fake.quirks.no_rsoc = true;
+    fake.quirks.no_vpd = true;
+    expect_probe_start(&mut fake);
+    let mut capacity_16 = [0u8; 32];
+    capacity_16[7] = 63;
+    capacity_16[10] = 2;
+    fake.expect_in(
+        &[0x9E, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0],
+        &capacity_16,
+    )
+    .expect_in(&[0x1A, 8, 0x3F, 0, 4, 0], &[3, 0, 0, 0]);
+    let mut scsi = ScsiDevice::new(fake);
+    let mut identity = DeviceIdentity::default();
+
+    probe_with_identity(&bus, &mut scsi, &mut identity)
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        identity.capabilities.scsi,
+        Some(ScsiCapabilities {
+            supports_16: true,
+            max_transfer_blocks: None,
+        })
+    );
+}
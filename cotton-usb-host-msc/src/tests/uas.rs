@@ -0,0 +1,836 @@
+use super::*;
+use crate::{IdentifyMassStorage, MassStorageTransport};
+use cotton_scsi::scsi_transport;
+use cotton_usb_host::device::identify::IdentifyFromDescriptors;
+use cotton_usb_host::mocks::{MockHostController, MockHostControllerInner};
+use cotton_usb_host::usb_bus::{
+    create_test_device, create_test_unconfigured_device, TransferType,
+};
+use futures::{future, Future};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+type MockError = scsi_transport::Error<UsbError>;
+
+fn poll_once<T>(f: impl Future<Output = T>) -> T {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    match pin!(f).poll(&mut c) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future pended"),
+    }
+}
+
+const DATA_IN: u8 = 1;
+const DATA_OUT: u8 = 2;
+const STATUS: u8 = 3;
+const COMMAND: u8 = 4;
+
+const PIPES: UasInterface = UasInterface {
+    configuration: 1,
+    interface: 0,
+    alternate_setting: 1,
+    command: COMMAND,
+    status: STATUS,
+    data_in: DATA_IN,
+    data_out: DATA_OUT,
+};
+
+fn sense_iu(tag: u16, status: u8, sense: &[u8]) -> Vec<u8> {
+    let mut iu = vec![0u8; 16];
+    iu[0] = 3;
+    iu[2..4].copy_from_slice(&tag.to_be_bytes());
+    iu[6] = status;
+    iu[14..16].copy_from_slice(&(sense.len() as u16).to_be_bytes());
+    iu.extend_from_slice(sense);
+    iu
+}
+
+fn ready_iu(id: u8, tag: u16) -> Vec<u8> {
+    let mut iu = vec![id, 0, 0, 0];
+    iu[2..4].copy_from_slice(&tag.to_be_bytes());
+    iu
+}
+
+/// The status pipe sends each of `ius` in turn
+fn expect_status(hc: &mut MockHostControllerInner, ius: Vec<Vec<u8>>) {
+    let n = ius.len();
+    let mut ius = VecDeque::from(ius);
+    hc.expect_bulk_in_transfer()
+        .times(n)
+        .withf(|_, e, _, _, _, _| *e == STATUS)
+        .returning(move |_, _, _, d, _, _| {
+            let iu = ius.pop_front().unwrap();
+            d[0..iu.len()].copy_from_slice(&iu);
+            Box::pin(future::ready(Ok(iu.len())))
+        });
+}
+
+fn expect_command(hc: &mut MockHostControllerInner, tag: u16, opcode: u8) {
+    hc.expect_bulk_out_transfer()
+        .times(1)
+        .withf(move |_, e, _, d, _, _| {
+            *e == COMMAND
+                && d.len() == 32
+                && d[0] == 1
+                && d[2..4] == tag.to_be_bytes()
+                && d[16] == opcode
+        })
+        .returning(|_, _, _, d, _, _| Box::pin(future::ready(Ok(d.len()))));
+}
+
+fn do_test<SetupFn, TestFn>(mut setup: SetupFn, mut test: TestFn)
+where
+    SetupFn: FnMut(&mut MockHostControllerInner),
+    TestFn: FnMut(UasTransport<MockHostController>),
+{
+    let mut hc = MockHostController::default();
+    setup(&mut hc.inner);
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0x0A, 0x14) };
+    test(UasTransport::new(&bus, device, &PIPES).unwrap());
+}
+
+#[test]
+fn command_iu_layout() {
+    let iu = command_iu(0x1234, &[0x28, 0, 0, 0, 0, 5, 0, 0, 1, 0]);
+    assert_eq!(iu[0], 1);
+    assert_eq!(iu[2..4], [0x12, 0x34]);
+    // SIMPLE task attribute, no additional CDB, LUN 0
+    assert_eq!(iu[4..16], [0; 12]);
+    assert_eq!(iu[16..26], [0x28, 0, 0, 0, 0, 5, 0, 0, 1, 0]);
+    assert_eq!(iu[26..32], [0; 6]);
+}
+
+#[test]
+fn parse_sense() {
+    let iu = sense_iu(7, 2, &[0x70, 0, 5]);
+    assert_eq!(
+        parse_status_iu(&iu),
+        Some(StatusIu::Sense {
+            tag: 7,
+            status: 2,
+            sense: &[0x70, 0, 5]
+        })
+    );
+}
+
+#[test]
+fn parse_sense_clipped() {
+    let mut iu = sense_iu(7, 2, &[0x70, 0, 5]);
+    iu[15] = 18; // claims more than was sent
+    assert_eq!(
+        parse_status_iu(&iu),
+        Some(StatusIu::Sense {
+            tag: 7,
+            status: 2,
+            sense: &[0x70, 0, 5]
+        })
+    );
+    assert_eq!(parse_status_iu(&iu[0..12]), None);
+}
+
+#[test]
+fn parse_ready() {
+    assert_eq!(
+        parse_status_iu(&ready_iu(6, 0x102)),
+        Some(StatusIu::ReadReady { tag: 0x102 })
+    );
+    assert_eq!(
+        parse_status_iu(&ready_iu(7, 3)),
+        Some(StatusIu::WriteReady { tag: 3 })
+    );
+}
+
+#[test]
+fn parse_response() {
+    assert_eq!(
+        parse_status_iu(&[4, 0, 0, 9, 0, 0, 0, 2]),
+        Some(StatusIu::Response { tag: 9, code: 2 })
+    );
+    assert_eq!(parse_status_iu(&[4, 0, 0, 9, 0]), None);
+}
+
+#[test]
+fn parse_garbage() {
+    assert_eq!(parse_status_iu(&[]), None);
+    assert_eq!(parse_status_iu(&[6, 0, 0]), None);
+    assert_eq!(parse_status_iu(&[0x55, 0x53, 0x42, 0x53]), None);
+}
+
+#[test]
+fn pipes_complete() {
+    let mut u = UasInterface::default();
+    assert!(!u.is_complete());
+    u.set_pipe(1, 0x04);
+    u.set_pipe(2, 0x83);
+    u.set_pipe(3, 0x81);
+    assert!(!u.is_complete());
+    u.set_pipe(9, 0x05);
+    u.set_pipe(4, 0x02);
+    assert!(u.is_complete());
+    assert_eq!(u.status, 3);
+    assert_eq!(u.data_in, 1);
+}
+
+/// A flash drive offering BOT as alternate setting 0, UAS as 1
+const UAS_DRIVE: &[u8] = &[
+    9, 2, 85, 0, 1, 1, 0, 128, 50, // configuration
+    9, 4, 0, 0, 2, 8, 6, 0x50, 0, // interface 0 alt 0, BOT
+    7, 5, 0x81, 2, 64, 0, 0, //
+    7, 5, 0x02, 2, 64, 0, 0, //
+    9, 4, 0, 1, 4, 8, 6, 0x62, 0, // interface 0 alt 1, UAS
+    7, 5, 0x04, 2, 64, 0, 0, 4, 0x24, 1, 0, // command
+    7, 5, 0x83, 2, 64, 0, 0, 4, 0x24, 2, 0, // status
+    7, 5, 0x81, 2, 64, 0, 0, 4, 0x24, 3, 0, // data in
+    7, 5, 0x02, 2, 64, 0, 0, 4, 0x24, 4, 0, // data out
+];
+
+#[test]
+fn identify_uas() {
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(UAS_DRIVE, &mut ims);
+    assert_eq!(ims.identify(), Some(1));
+    assert_eq!(ims.uas(), Some(PIPES));
+}
+
+#[test]
+fn identify_uas_via_bus() {
+    // The descriptors are longer than one 64-byte packet, so this checks
+    // that get_configuration() reads all wTotalLength bytes of them
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(2)
+        .withf(|_, _, s, _| s.bRequest == 6 && s.wValue == 0x200)
+        .returning(|_, _, s, mut d| -> ControlResult {
+            let mut n = 0;
+            d.in_with(|buf| {
+                n = UAS_DRIVE.len().min(s.wLength as usize);
+                buf[0..n].copy_from_slice(&UAS_DRIVE[0..n]);
+            });
+            Box::pin(future::ready(Ok(n)))
+        });
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_unconfigured_device() };
+    let mut ims = IdentifyMassStorage::default();
+    assert_eq!(poll_once(bus.get_configuration(&device, &mut ims)), Ok(()));
+    assert_eq!(ims.identify(), Some(1));
+    assert_eq!(ims.uas(), Some(PIPES));
+}
+
+#[test]
+fn identify_uas_incomplete() {
+    // Missing the data-out pipe usage descriptor
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(
+        &UAS_DRIVE[0..UAS_DRIVE.len() - 4],
+        &mut ims,
+    );
+    assert_eq!(ims.identify(), Some(1));
+    assert_eq!(ims.uas(), None);
+}
+
+#[test]
+fn identify_bot_only() {
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(&UAS_DRIVE[0..32], &mut ims);
+    assert_eq!(ims.identify(), Some(1));
+    assert_eq!(ims.uas(), None);
+}
+
+#[test]
+fn new_fails_without_endpoints() {
+    let bus = UsbBus::new(MockHostController::default());
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0x0A, 0x04) };
+    assert_eq!(
+        UasTransport::new(&bus, device, &PIPES).err(),
+        Some(UsbError::NoSuchEndpoint)
+    );
+}
+
+#[test]
+fn new_fails_without_bulk() {
+    let mut hc = MockHostController::default();
+    hc.capabilities.bulk = false;
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0x0A, 0x14) };
+    assert_eq!(
+        UasTransport::new(&bus, device, &PIPES).err(),
+        Some(UsbError::Unsupported)
+    );
+}
+
+#[test]
+fn command_nodata() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![sense_iu(1, 0, &[])]);
+        },
+        |mut t| {
+            assert_eq!(t.last_status(), None);
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(rc, Ok(0));
+            assert_eq!(t.last_status(), Some(ScsiStatus::Good));
+        },
+    );
+}
+
+#[test]
+fn tags_advance() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_command(hc, 2, 0);
+            expect_status(hc, vec![sense_iu(1, 0, &[]), sense_iu(2, 0, &[])]);
+        },
+        |mut t| {
+            assert_eq!(poll_once(t.command(&[0; 6], DataPhase::None)), Ok(0));
+            assert_eq!(poll_once(t.command(&[0; 6], DataPhase::None)), Ok(0));
+        },
+    );
+}
+
+#[test]
+fn tag_wraps_past_zero() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![sense_iu(1, 0, &[])]);
+        },
+        |mut t| {
+            t.tag = u16::MAX;
+            assert_eq!(poll_once(t.command(&[0; 6], DataPhase::None)), Ok(0));
+        },
+    );
+}
+
+#[test]
+fn command_in() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x12);
+            expect_status(hc, vec![ready_iu(6, 1), sense_iu(1, 0, &[])]);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, e, _, d, _, _| *e == DATA_IN && d.len() == 36)
+                .returning(|_, _, _, d, _, _| {
+                    d.fill(0x33);
+                    Box::pin(future::ready(Ok(36)))
+                });
+        },
+        |mut t| {
+            let mut buf = [0u8; 36];
+            let rc = poll_once(
+                t.command(&[0x12, 0, 0, 0, 36, 0], DataPhase::In(&mut buf)),
+            );
+            assert_eq!(rc, Ok(36));
+            assert_eq!(buf, [0x33; 36]);
+        },
+    );
+}
+
+#[test]
+fn command_in_uninit() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x28);
+            expect_status(hc, vec![ready_iu(6, 1), sense_iu(1, 0, &[])]);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, e, _, d, _, _| *e == DATA_IN && d.len() == 512)
+                .returning(|_, _, _, d, _, _| {
+                    d[0..100].fill(0x44);
+                    Box::pin(future::ready(Ok(100)))
+                });
+        },
+        |mut t| {
+            let mut data = [MaybeUninit::uninit(); 512];
+            let mut buf = ReadBuffer::new(&mut data);
+            let rc = poll_once(t.command_in_uninit(&[0x28; 10], &mut buf, 0));
+            assert_eq!(rc, Ok(100));
+            assert_eq!(buf.filled(), [0x44; 100]);
+        },
+    );
+}
+
+#[test]
+fn command_out() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x2A);
+            expect_status(hc, vec![ready_iu(7, 1), sense_iu(1, 0, &[])]);
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .withf(|_, e, _, d, _, _| *e == DATA_OUT && d.len() == 512)
+                .returning(|_, _, _, _, _, _| {
+                    Box::pin(future::ready(Ok(512)))
+                });
+        },
+        |mut t| {
+            let buf = [0u8; 512];
+            let rc = poll_once(t.command(&[0x2A; 10], DataPhase::Out(&buf)));
+            assert_eq!(rc, Ok(512));
+        },
+    );
+}
+
+#[test]
+fn wrong_data_direction() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x2A);
+            expect_status(hc, vec![ready_iu(6, 1)]);
+        },
+        |mut t| {
+            let buf = [0u8; 512];
+            let rc = poll_once(t.command(&[0x2A; 10], DataPhase::Out(&buf)));
+            assert_eq!(
+                rc,
+                Err(MockError::Transport(UsbError::Protocol(
+                    "unexpected UAS data phase"
+                )))
+            );
+        },
+    );
+}
+
+#[test]
+fn check_condition_keeps_sense() {
+    const SENSE: [u8; 18] =
+        [0x70, 0, 5, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0x24, 0, 0, 0, 0, 0];
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x28);
+            expect_status(hc, vec![sense_iu(1, 2, &SENSE)]);
+        },
+        |mut t| {
+            let mut buf = [0u8; 512];
+            let rc =
+                poll_once(t.command(&[0x28; 10], DataPhase::In(&mut buf)));
+            assert_eq!(rc, Err(MockError::CommandFailed));
+            assert_eq!(t.last_status(), Some(ScsiStatus::CheckCondition));
+
+            // Answered without asking the device
+            let mut sense = [0u8; 252];
+            let rc = poll_once(
+                t.command(&[3, 0, 0, 0, 252, 0], DataPhase::In(&mut sense)),
+            );
+            assert_eq!(rc, Ok(18));
+            assert_eq!(sense[0..18], SENSE);
+            assert_eq!(t.last_status(), Some(ScsiStatus::Good));
+        },
+    );
+}
+
+/// The status pipe sends `iu`, split into packets (with no
+/// zero-length packet after a full one)
+fn expect_status_packets(hc: &mut MockHostControllerInner, iu: Vec<u8>) {
+    let packets = iu.len().div_ceil(64);
+    let mut offset = 0;
+    hc.expect_bulk_in_transfer()
+        .times(packets)
+        .withf(|_, e, _, d, _, _| *e == STATUS && d.len() <= 64)
+        .returning(move |_, _, _, d, _, _| {
+            let n = (iu.len() - offset).min(d.len());
+            d[0..n].copy_from_slice(&iu[offset..offset + n]);
+            offset += n;
+            Box::pin(future::ready(Ok(n)))
+        });
+}
+
+#[test]
+fn sense_iu_of_whole_packet() {
+    let sense: Vec<u8> = (0..48).collect();
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status_packets(hc, sense_iu(1, 2, &sense));
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(rc, Err(MockError::CommandFailed));
+            let mut buf = [0u8; 252];
+            let rc = poll_once(
+                t.command(&[3, 0, 0, 0, 252, 0], DataPhase::In(&mut buf)),
+            );
+            assert_eq!(rc, Ok(48));
+            assert_eq!(buf[0..48], sense[..]);
+        },
+    );
+}
+
+#[test]
+fn sense_iu_of_several_packets() {
+    let sense: Vec<u8> = (0..100).collect();
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status_packets(hc, sense_iu(1, 2, &sense));
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(rc, Err(MockError::CommandFailed));
+            let mut buf = [0u8; 252];
+            let rc = poll_once(
+                t.command(&[3, 0, 0, 0, 252, 0], DataPhase::In(&mut buf)),
+            );
+            assert_eq!(rc, Ok(100));
+            assert_eq!(buf[0..100], sense[..]);
+        },
+    );
+}
+
+#[test]
+fn stored_sense_uninit() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![sense_iu(1, 2, &[0x70, 0, 6])]);
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(rc, Err(MockError::CommandFailed));
+
+            let mut data = [MaybeUninit::uninit(); 2];
+            let mut buf = ReadBuffer::new(&mut data);
+            let rc = poll_once(t.command_in_uninit(
+                &[3, 0, 0, 0, 2, 0],
+                &mut buf,
+                0,
+            ));
+            assert_eq!(rc, Ok(2));
+            assert_eq!(buf.filled(), [0x70, 0]);
+        },
+    );
+}
+
+#[test]
+fn busy() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![sense_iu(1, 8, &[])]);
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(rc, Err(MockError::Busy));
+            assert_eq!(t.last_status(), Some(ScsiStatus::Busy));
+        },
+    );
+}
+
+#[test]
+fn busy_is_retried() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_command(hc, 2, 0);
+            expect_status(hc, vec![sense_iu(1, 8, &[]), sense_iu(2, 0, &[])]);
+        },
+        |t| {
+            let mut d = cotton_scsi::ScsiDevice::new(t);
+            assert_eq!(poll_once(d.test_unit_ready()), Ok(()));
+            assert_eq!(d.stats().retries, 1);
+        },
+    );
+}
+
+#[test]
+fn tag_mismatch() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![sense_iu(5, 0, &[])]);
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(
+                rc,
+                Err(MockError::Transport(UsbError::Protocol(
+                    "UAS tag mismatch"
+                )))
+            );
+            assert_eq!(t.last_status(), None);
+        },
+    );
+}
+
+#[test]
+fn response_iu() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![vec![4, 0, 0, 1, 0, 0, 0, 2]]);
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(
+                rc,
+                Err(MockError::Transport(UsbError::Protocol(
+                    "UAS response IU"
+                )))
+            );
+        },
+    );
+}
+
+#[test]
+fn bad_status_iu() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            expect_status(hc, vec![vec![0x55, 0x53, 0x42, 0x53]]);
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(
+                rc,
+                Err(MockError::Transport(UsbError::Protocol("bad status IU")))
+            );
+        },
+    );
+}
+
+#[test]
+fn cdb_too_long() {
+    do_test(
+        |_| {},
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 17], DataPhase::None));
+            assert_eq!(
+                rc,
+                Err(MockError::Transport(UsbError::Protocol("CDB too long")))
+            );
+        },
+    );
+}
+
+#[test]
+fn short_command_iu() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer()
+                .times(1)
+                .returning(|_, _, _, _, _, _| Box::pin(future::ready(Ok(8))));
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(
+                rc,
+                Err(MockError::Transport(UsbError::Protocol(
+                    "short command IU"
+                )))
+            );
+        },
+    );
+}
+
+#[test]
+fn data_in_stalls() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x28);
+            expect_status(hc, vec![ready_iu(6, 1), sense_iu(1, 2, &[0x70])]);
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, e, _, _, _, _| *e == DATA_IN)
+                .returning(|_, _, _, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                });
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(|_, _, s, _| {
+                    s.bRequest == 1 && s.wIndex == 0x80 | DATA_IN as u16
+                })
+                .returning(|_, _, _, _| Box::pin(future::ready(Ok(0))));
+        },
+        |mut t| {
+            let mut buf = [0u8; 512];
+            let rc =
+                poll_once(t.command(&[0x28; 10], DataPhase::In(&mut buf)));
+            assert_eq!(rc, Err(MockError::CommandFailed));
+        },
+    );
+}
+
+#[test]
+fn status_pipe_fails() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0);
+            hc.expect_bulk_in_transfer().times(1).returning(
+                |_, _, _, _, _: TransferType, _: &Cell<bool>| {
+                    Box::pin(future::ready(Err(UsbError::Timeout)))
+                },
+            );
+        },
+        |mut t| {
+            let rc = poll_once(t.command(&[0; 6], DataPhase::None));
+            assert_eq!(rc, Err(MockError::Transport(UsbError::Timeout)));
+        },
+    );
+}
+
+fn is_set_interface(
+    _: &u8,
+    _: &u8,
+    s: &cotton_usb_host::wire::SetupPacket,
+    _: &cotton_usb_host::host_controller::DataPhase,
+) -> bool {
+    s.bmRequestType == 1 && s.bRequest == 11 && s.wValue == 1 && s.wIndex == 0
+}
+
+type ControlResult = Pin<Box<dyn Future<Output = Result<usize, UsbError>>>>;
+
+fn open_with<F: FnMut(&mut MockHostControllerInner)>(
+    identity: &IdentifyMassStorage,
+    setup: F,
+) -> bool {
+    open_device_with(identity, 0x0A, 0x14, setup)
+}
+
+fn open_device_with<F: FnMut(&mut MockHostControllerInner)>(
+    identity: &IdentifyMassStorage,
+    in_endpoints: u16,
+    out_endpoints: u16,
+    mut setup: F,
+) -> bool {
+    let mut hc = MockHostController::default();
+    setup(&mut hc.inner);
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(in_endpoints, out_endpoints) };
+    poll_once(MassStorageTransport::new(&bus, device, identity))
+        .unwrap()
+        .is_uas()
+}
+
+#[test]
+fn transport_prefers_uas() {
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(UAS_DRIVE, &mut ims);
+    assert!(open_with(&ims, |hc| {
+        hc.expect_control_transfer()
+            .times(1)
+            .withf(is_set_interface)
+            .returning(|_, _, _, _| -> ControlResult {
+                Box::pin(future::ready(Ok(0)))
+            });
+    }));
+}
+
+#[test]
+fn transport_falls_back_if_refused() {
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(UAS_DRIVE, &mut ims);
+    assert!(!open_with(&ims, |hc| {
+        hc.expect_control_transfer()
+            .times(1)
+            .withf(is_set_interface)
+            .returning(|_, _, _, _| -> ControlResult {
+                Box::pin(future::ready(Err(UsbError::Stall)))
+            });
+    }));
+}
+
+#[test]
+fn transport_falls_back_without_uas() {
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(&UAS_DRIVE[0..32], &mut ims);
+    assert!(!open_with(&ims, |hc| {
+        hc.expect_control_transfer().times(0);
+    }));
+}
+
+#[test]
+fn transport_falls_back_without_uas_pipes() {
+    let mut ims = IdentifyMassStorage::default();
+    cotton_usb_host::wire::parse_descriptors(UAS_DRIVE, &mut ims);
+    // Not left in the UAS alternate setting
+    assert!(!open_device_with(&ims, 0x02, 0x04, |hc| {
+        hc.expect_control_transfer().times(0);
+    }));
+}
+
+#[test]
+fn check_pipes() {
+    let bus = UsbBus::new(MockHostController::default());
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0x0A, 0x14) };
+    assert_eq!(UasTransport::check(&bus, &device, &PIPES), Ok(()));
+    let shared = UasInterface {
+        data_out: COMMAND,
+        ..PIPES
+    };
+    assert_eq!(
+        UasTransport::check(&bus, &device, &shared),
+        Err(UsbError::NoSuchEndpoint)
+    );
+}
+
+#[test]
+fn transport_delegates() {
+    let mut hc = MockHostController::default();
+    expect_command(&mut hc.inner, 1, 0);
+    expect_status(&mut hc.inner, vec![sense_iu(1, 0, &[])]);
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0x0A, 0x14) };
+    let mut t = MassStorageTransport::Uas(
+        UasTransport::new(&bus, device, &PIPES).unwrap(),
+    );
+    assert_eq!(poll_once(t.command(&[0; 6], DataPhase::None)), Ok(0));
+    assert_eq!(t.last_status(), Some(ScsiStatus::Good));
+    assert_eq!(t.quirks(), ScsiQuirks::default());
+}
+
+#[test]
+fn data_in_in_two_phases() {
+    do_test(
+        |hc| {
+            expect_command(hc, 1, 0x28);
+            expect_status(
+                hc,
+                vec![ready_iu(6, 1), ready_iu(6, 1), sense_iu(1, 0, &[])],
+            );
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, e, _, d, _, _| *e == DATA_IN && d.len() == 1024)
+                .returning(|_, _, _, d, _, _| {
+                    d[0..512].fill(1);
+                    Box::pin(future::ready(Ok(512)))
+                });
+            hc.expect_bulk_in_transfer()
+                .times(1)
+                .withf(|_, e, _, d, _, _| *e == DATA_IN && d.len() == 512)
+                .returning(|_, _, _, d, _, _| {
+                    d.fill(2);
+                    Box::pin(future::ready(Ok(512)))
+                });
+        },
+        |mut t| {
+            let mut buf = [0u8; 1024];
+            let rc =
+                poll_once(t.command(&[0x28; 10], DataPhase::In(&mut buf)));
+            assert_eq!(rc, Ok(1024));
+            assert_eq!(buf[0..512], [1; 512]);
+            assert_eq!(buf[512..], [2; 512]);
+        },
+    );
+}
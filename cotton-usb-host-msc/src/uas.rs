@@ -0,0 +1,506 @@
+use super::debug;
+use crate::mass_storage::{read_uninit, scsi_quirks};
+use cotton_scsi::scsi_transport::{DataPhase, ReadBuffer};
+use cotton_scsi::{Error, ScsiQuirks, ScsiStatus, ScsiTransport};
+use cotton_usb_host::bitset::BitSet;
+use cotton_usb_host::host_controller::{self, HostController, UsbError};
+use cotton_usb_host::quirks::Quirks;
+use cotton_usb_host::usb_bus::{BulkIn, BulkOut, UsbBus, UsbDevice};
+use cotton_usb_host::wire::{Direction, SetupPacket};
+
+/// Information unit IDs (UAS section 6.2.1)
+const IU_COMMAND: u8 = 0x01;
+const IU_SENSE: u8 = 0x03;
+const IU_RESPONSE: u8 = 0x04;
+const IU_READ_READY: u8 = 0x06;
+const IU_WRITE_READY: u8 = 0x07;
+
+/// The size of a command IU with no additional CDB bytes (UAS 6.2.2)
+const COMMAND_IU_SIZE: usize = 32;
+
+/// The largest CDB which fits in a command IU without additional bytes
+const MAX_CDB_SIZE: usize = 16;
+
+/// The size of a sense IU before its sense data (UAS 6.2.4)
+const SENSE_IU_HEADER_SIZE: usize = 16;
+
+/// The largest sense data a device may return (SPC-4 4.5.1)
+const MAX_SENSE_SIZE: usize = 252;
+
+/// Large enough for any IU the device sends on the status pipe
+const STATUS_IU_SIZE: usize = SENSE_IU_HEADER_SIZE + MAX_SENSE_SIZE;
+
+/// The size of a response IU (UAS 6.2.5)
+const RESPONSE_IU_SIZE: usize = 8;
+
+/// The size of a READ READY or WRITE READY IU (UAS 6.2.6, 6.2.7)
+const READY_IU_SIZE: usize = 4;
+
+/// The packet size of the status pipe (full-speed bulk, as elsewhere
+/// in cotton-usb-host)
+const STATUS_PACKET_SIZE: usize = 64;
+
+/// Where to find the UAS interface of a mass-storage device
+///
+/// UAS devices also offer Bulk-Only Transport, as alternate setting 0
+/// of the same interface; UAS is a further alternate setting, whose
+/// four endpoints are told apart by the pipe usage descriptors (UAS
+/// section 5.3.3.1) following their endpoint descriptors. Found by
+/// [`IdentifyMassStorage`](crate::IdentifyMassStorage).
+///
+/// The endpoints are endpoint numbers, without the direction bit.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct UasInterface {
+    /// The configuration containing the interface
+    pub configuration: u8,
+    /// The interface number (`bInterfaceNumber`)
+    pub interface: u8,
+    /// The alternate setting which selects UAS
+    pub alternate_setting: u8,
+    /// The command pipe (OUT)
+    pub command: u8,
+    /// The status pipe (IN)
+    pub status: u8,
+    /// The data-in pipe (IN)
+    pub data_in: u8,
+    /// The data-out pipe (OUT)
+    pub data_out: u8,
+}
+
+impl UasInterface {
+    /// Have all four pipes been found?
+    pub fn is_complete(&self) -> bool {
+        self.command != 0
+            && self.status != 0
+            && self.data_in != 0
+            && self.data_out != 0
+    }
+
+    /// Note the endpoint which a pipe usage descriptor refers to
+    pub(crate) fn set_pipe(&mut self, pipe_id: u8, endpoint: u8) {
+        let ep = endpoint & 0xF;
+        match pipe_id {
+            1 => self.command = ep,
+            2 => self.status = ep,
+            3 => self.data_in = ep,
+            4 => self.data_out = ep,
+            _ => {}
+        }
+    }
+}
+
+/// Build a command IU (UAS section 6.2.2)
+///
+/// The task attribute is SIMPLE, and the LUN is zero.
+fn command_iu(tag: u16, cdb: &[u8]) -> [u8; COMMAND_IU_SIZE] {
+    let mut iu = [0u8; COMMAND_IU_SIZE];
+    iu[0] = IU_COMMAND;
+    iu[2..4].copy_from_slice(&tag.to_be_bytes());
+    iu[16..16 + cdb.len()].copy_from_slice(cdb);
+    iu
+}
+
+/// The IUs which a device sends on the status pipe
+#[derive(Debug, PartialEq, Eq)]
+enum StatusIu<'a> {
+    /// The command has completed (UAS section 6.2.4)
+    Sense {
+        tag: u16,
+        status: u8,
+        sense: &'a [u8],
+    },
+    /// A task management function, or an invalid IU (UAS 6.2.5)
+    Response { tag: u16, code: u8 },
+    /// The device is ready to send data (UAS 6.2.6)
+    ReadReady { tag: u16 },
+    /// The device is ready to receive data (UAS 6.2.7)
+    WriteReady { tag: u16 },
+}
+
+/// Decode an IU received on the status pipe
+///
+/// Returns `None` if it's truncated or of an unknown type. Sense data
+/// is clipped to what was actually received.
+fn parse_status_iu(iu: &[u8]) -> Option<StatusIu<'_>> {
+    if iu.len() < 4 {
+        return None;
+    }
+    let tag = u16::from_be_bytes([iu[2], iu[3]]);
+    match iu[0] {
+        IU_SENSE if iu.len() >= SENSE_IU_HEADER_SIZE => {
+            let len = u16::from_be_bytes([iu[14], iu[15]]) as usize;
+            let end = (SENSE_IU_HEADER_SIZE + len).min(iu.len());
+            Some(StatusIu::Sense {
+                tag,
+                status: iu[6],
+                sense: &iu[SENSE_IU_HEADER_SIZE..end],
+            })
+        }
+        IU_RESPONSE if iu.len() >= 8 => {
+            Some(StatusIu::Response { tag, code: iu[7] })
+        }
+        IU_READ_READY => Some(StatusIu::ReadReady { tag }),
+        IU_WRITE_READY => Some(StatusIu::WriteReady { tag }),
+        _ => None,
+    }
+}
+
+/// The length of a status-pipe IU, from its first few bytes
+///
+/// Returns `None` if not enough has been received to tell, or if the
+/// IU is of an unknown type.
+fn status_iu_len(iu: &[u8]) -> Option<usize> {
+    match *iu.first()? {
+        IU_SENSE if iu.len() >= SENSE_IU_HEADER_SIZE => {
+            let len = u16::from_be_bytes([iu[14], iu[15]]) as usize;
+            Some(SENSE_IU_HEADER_SIZE + len)
+        }
+        IU_RESPONSE => Some(RESPONSE_IU_SIZE),
+        IU_READ_READY | IU_WRITE_READY => Some(READY_IU_SIZE),
+        _ => None,
+    }
+}
+
+/// The data phase of a command, as UasTransport sees it
+enum Payload<'a, 'b> {
+    In(&'a mut [u8]),
+    InUninit(&'a mut ReadBuffer<'b>),
+    Out(&'a [u8]),
+    None,
+}
+
+/// A [`ScsiTransport`] using USB Attached SCSI (UAS)
+///
+/// UAS sends each command as a "command IU" on one bulk pipe, and
+/// receives its outcome as a "sense IU" on another, with the data
+/// phase, if any, on a third or fourth. Commands are tagged, so that
+/// several can be outstanding at once -- but, as a `ScsiDevice` only
+/// ever issues one at a time, this transport has a queue depth of one.
+///
+/// On a USB 2.0 bus, the device says when it's ready for each data
+/// phase, by sending a READ READY or WRITE READY IU on the status pipe
+/// first. (USB 3 streams, which do away with that, aren't supported.)
+///
+/// The sense IU carries the actual SCSI status byte (see
+/// [`ScsiTransport::last_status()`]) and, for a failed command, the
+/// sense data -- after which the device no longer has it. So the sense
+/// data is retained, and used to answer the REQUEST SENSE which
+/// [`ScsiDevice`](cotton_scsi::ScsiDevice) issues after a failure.
+///
+/// Usually obtained via
+/// [`MassStorageTransport::new()`](crate::MassStorageTransport::new),
+/// which falls back to Bulk-Only Transport for devices without UAS.
+pub struct UasTransport<'a, HC: HostController> {
+    bus: &'a UsbBus<HC>,
+    command: BulkOut,
+    status: BulkIn,
+    data_in: BulkIn,
+    data_out: BulkOut,
+    tag: u16,
+    quirks: Quirks,
+    last_status: Option<ScsiStatus>,
+    iu: [u8; STATUS_IU_SIZE],
+    sense_len: Option<usize>,
+}
+
+impl<'a, HC: HostController> UasTransport<'a, HC> {
+    /// Select the UAS alternate setting of a device's interface
+    ///
+    /// This must succeed before a `UasTransport` is created with
+    /// [`UasTransport::new()`].
+    pub async fn select(
+        bus: &UsbBus<HC>,
+        device: &UsbDevice,
+        uas: &UasInterface,
+    ) -> Result<(), UsbError> {
+        bus.control_transfer(
+            device,
+            SetupPacket::set_interface(uas.interface, uas.alternate_setting),
+            host_controller::DataPhase::None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Check that a `UasTransport` could be created on the pipes of
+    /// `uas`
+    ///
+    /// Fails as [`UasTransport::new()`] would, but without consuming
+    /// the device; so it's worth calling before
+    /// [`UasTransport::select()`], which is hard to undo.
+    pub fn check(
+        bus: &UsbBus<HC>,
+        device: &UsbDevice,
+        uas: &UasInterface,
+    ) -> Result<(), UsbError> {
+        if !bus.capabilities().bulk {
+            return Err(UsbError::Unsupported);
+        }
+        let ins = device.in_endpoints();
+        let outs = device.out_endpoints();
+        let has = |set: BitSet, ep: u8| ep != 0 && set.contains(ep);
+        if has(outs, uas.command)
+            && has(ins, uas.status)
+            && has(ins, uas.data_in)
+            && has(outs, uas.data_out)
+            && uas.command != uas.data_out
+            && uas.status != uas.data_in
+        {
+            Ok(())
+        } else {
+            Err(UsbError::NoSuchEndpoint)
+        }
+    }
+
+    /// Create a new `UasTransport` on the pipes of `uas`
+    ///
+    /// The interface's UAS alternate setting must already have been
+    /// selected, see [`UasTransport::select()`].
+    pub fn new(
+        bus: &'a UsbBus<HC>,
+        mut device: UsbDevice,
+        uas: &UasInterface,
+    ) -> Result<Self, UsbError> {
+        Self::check(bus, &device, uas)?;
+        let command = device.open_out_endpoint(uas.command)?;
+        let status = device.open_in_endpoint(uas.status)?;
+        let data_in = device.open_in_endpoint(uas.data_in)?;
+        let data_out = device.open_out_endpoint(uas.data_out)?;
+        Ok(Self {
+            bus,
+            command,
+            status,
+            data_in,
+            data_out,
+            tag: 0,
+            quirks: device.quirks(),
+            last_status: None,
+            iu: [0u8; STATUS_IU_SIZE],
+            sense_len: None,
+        })
+    }
+
+    fn next_tag(&mut self) -> u16 {
+        // Tag zero is avoided, in case a device takes it as "untagged"
+        self.tag = self.tag.checked_add(1).unwrap_or(1);
+        self.tag
+    }
+
+    /// Answer REQUEST SENSE from retained sense data
+    fn stored_sense(
+        &mut self,
+        cmd: &[u8],
+        data: &mut Payload,
+    ) -> Option<usize> {
+        if cmd.first() != Some(&3) {
+            return None;
+        }
+        let len = self.sense_len.take()?;
+        let sense = &self.iu[SENSE_IU_HEADER_SIZE..SENSE_IU_HEADER_SIZE + len];
+        match data {
+            Payload::In(buf) => {
+                let n = len.min(buf.len());
+                buf[0..n].copy_from_slice(&sense[0..n]);
+                Some(n)
+            }
+            Payload::InUninit(buf) => {
+                let n = len.min(buf.remaining());
+                buf.push_slice(&sense[0..n]);
+                Some(n)
+            }
+            _ => None,
+        }
+    }
+
+    /// Receive one IU on the status pipe
+    ///
+    /// An IU which is a whole number of packets long needn't be
+    /// followed by a zero-length packet, so this reads a packet at a
+    /// time, and stops at the IU's own length (once that's known) as
+    /// well as at a short packet.
+    async fn read_status_iu(&mut self) -> Result<usize, Error<UsbError>> {
+        let mut total = 0;
+        while total < STATUS_IU_SIZE {
+            let end = (total + STATUS_PACKET_SIZE).min(STATUS_IU_SIZE);
+            let n = self
+                .status
+                .read_exact(self.bus, &mut self.iu[total..end])
+                .await
+                .map_err(Error::Transport)?;
+            let short = n < end - total;
+            total += n;
+            if short
+                || status_iu_len(&self.iu[..total])
+                    .is_some_and(|len| total >= len)
+            {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    async fn run(
+        &mut self,
+        cmd: &[u8],
+        mut data: Payload<'_, '_>,
+    ) -> Result<usize, Error<UsbError>> {
+        if let Some(n) = self.stored_sense(cmd, &mut data) {
+            self.last_status = Some(ScsiStatus::Good);
+            return Ok(n);
+        }
+        self.sense_len = None;
+        self.last_status = None;
+
+        if cmd.len() > MAX_CDB_SIZE {
+            return Err(Error::Transport(UsbError::Protocol("CDB too long")));
+        }
+        let tag = self.next_tag();
+        let iu = command_iu(tag, cmd);
+        if self
+            .command
+            .write_all(self.bus, &iu)
+            .await
+            .map_err(Error::Transport)?
+            < COMMAND_IU_SIZE
+        {
+            return Err(Error::Transport(UsbError::Protocol(
+                "short command IU",
+            )));
+        }
+
+        let mut transferred = 0;
+        loop {
+            let n = self.read_status_iu().await?;
+            let direction = match parse_status_iu(&self.iu[0..n]) {
+                Some(StatusIu::Sense {
+                    tag: t,
+                    status,
+                    sense,
+                }) if t == tag => {
+                    let status = ScsiStatus::from_byte(status);
+                    if status != ScsiStatus::Good {
+                        debug::println!("uas status {:x}", status.to_byte());
+                    }
+                    self.last_status = Some(status);
+                    if status == ScsiStatus::CheckCondition
+                        && !sense.is_empty()
+                    {
+                        self.sense_len = Some(sense.len());
+                    }
+                    return status.to_result(transferred);
+                }
+                Some(StatusIu::ReadReady { tag: t }) if t == tag => {
+                    Direction::In
+                }
+                Some(StatusIu::WriteReady { tag: t }) if t == tag => {
+                    Direction::Out
+                }
+                Some(StatusIu::Response { .. }) => {
+                    return Err(Error::Transport(UsbError::Protocol(
+                        "UAS response IU",
+                    )));
+                }
+                Some(_) => {
+                    return Err(Error::Transport(UsbError::Protocol(
+                        "UAS tag mismatch",
+                    )));
+                }
+                None => {
+                    return Err(Error::Transport(UsbError::Protocol(
+                        "bad status IU",
+                    )));
+                }
+            };
+            transferred +=
+                self.data_phase(direction, &mut data, transferred).await?;
+        }
+    }
+
+    /// Run the data phase the device has just said it's ready for
+    ///
+    /// The device may split the data into several phases, each with
+    /// its own READ READY or WRITE READY; `offset` is how much has
+    /// been transferred already.
+    async fn data_phase(
+        &mut self,
+        direction: Direction,
+        data: &mut Payload<'_, '_>,
+        offset: usize,
+    ) -> Result<usize, Error<UsbError>> {
+        let response = match (direction, data) {
+            (Direction::In, Payload::In(buf)) => {
+                self.data_in.read_exact(self.bus, &mut buf[offset..]).await
+            }
+            (Direction::In, Payload::InUninit(buf)) => {
+                let before = buf.len();
+                read_uninit(self.bus, &self.data_in, buf)
+                    .await
+                    .map(|_| buf.len() - before)
+            }
+            (Direction::Out, Payload::Out(buf)) => {
+                self.data_out.write_all(self.bus, &buf[offset..]).await
+            }
+            _ => {
+                return Err(Error::Transport(UsbError::Protocol(
+                    "unexpected UAS data phase",
+                )))
+            }
+        };
+        match response {
+            Ok(n) => Ok(n),
+            Err(UsbError::Stall) => {
+                debug::println!("uas data stall");
+                // The device will still send a sense IU, once the
+                // pipe is cleared
+                if self.data_out.is_halted() {
+                    self.bus.clear_out_halt(&self.data_out).await
+                } else {
+                    self.bus.clear_halt(&self.data_in).await
+                }
+                .map_err(Error::Transport)?;
+                Ok(0)
+            }
+            Err(e) => Err(Error::Transport(e)),
+        }
+    }
+}
+
+impl<HC: HostController> ScsiTransport for UasTransport<'_, HC> {
+    type Error = UsbError;
+
+    fn last_status(&self) -> Option<ScsiStatus> {
+        self.last_status
+    }
+
+    fn quirks(&self) -> ScsiQuirks {
+        scsi_quirks(&self.quirks)
+    }
+
+    async fn command(
+        &mut self,
+        cmd: &[u8],
+        data: DataPhase<'_>,
+    ) -> Result<usize, Error<Self::Error>> {
+        let data = match data {
+            DataPhase::In(buf) => Payload::In(buf),
+            DataPhase::Out(buf) => Payload::Out(buf),
+            DataPhase::None => Payload::None,
+        };
+        self.run(cmd, data).await
+    }
+
+    async fn command_in_uninit(
+        &mut self,
+        cmd: &[u8],
+        data: &mut ReadBuffer<'_>,
+        _timeout_ms: u32,
+    ) -> Result<usize, Error<Self::Error>> {
+        self.run(cmd, Payload::InUninit(data)).await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/uas.rs"]
+mod tests;
@@ -8,6 +8,9 @@ use std::fmt::{self, Display};
 
 /// Managing access to N equivalent resources
 ///
+/// The number of resources, `N`, is fixed at compile time, and must
+/// be no more than 32; a larger `N` fails to compile.
+///
 /// Callers who wish to access a resource (but don't care which one of the N)
 /// can call the async function [`Pool::alloc`] which will return (awakening
 /// the task) as soon as a resource is available.
@@ -22,7 +25,7 @@ use std::fmt::{self, Display};
 /// # Example
 /// ```rust
 /// use cotton_usb_host::async_pool::Pool;
-/// let mut pool = Pool::new(2); // this pool has two resources
+/// let mut pool = Pool::<2>::new(); // this pool has two resources
 /// let res = pool.try_alloc().unwrap(); // obtain a resource
 /// println!("I got resource {}", res.which());
 /// {
@@ -38,16 +41,37 @@ use std::fmt::{self, Display};
 ///
 /// For a larger example, see how the RP2040 USB host-controller driver
 /// shares out its USB endpoints.
-pub struct Pool {
-    total: u8,
+pub struct Pool<const N: usize> {
+    state: PoolState,
+}
+
+/// The parts of a [`Pool`] which don't depend on its size
+///
+/// These are all that's needed to give a resource back, so a
+/// [`Pooled`] refers only to these, and `Pooled`s from pools of
+/// different sizes are the same type.
+struct PoolState {
     allocated: Cell<BitSet>,
     waker: RefCell<Option<Waker>>,
 }
 
+impl PoolState {
+    fn dealloc_internal(&self, n: u8) {
+        let mut bits = self.allocated.get();
+        debug_assert!(bits.contains(n));
+        bits.clear(n);
+        self.allocated.replace(bits);
+
+        if let Some(w) = self.waker.take() {
+            w.wake();
+        }
+    }
+}
+
 /// Representing ownership of one of the resources in a [`Pool`]
 pub struct Pooled<'a> {
     n: u8,
-    pool: &'a Pool,
+    pool: &'a PoolState,
 }
 
 impl Pooled<'_> {
@@ -64,6 +88,12 @@ impl Display for Pooled<'_> {
     }
 }
 
+impl core::fmt::Debug for Pooled<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Pooled({})", self.n)
+    }
+}
+
 #[cfg(feature = "defmt")]
 impl defmt::Format for Pooled<'_> {
     fn format(&self, f: defmt::Formatter) {
@@ -77,61 +107,196 @@ impl Drop for Pooled<'_> {
     }
 }
 
-struct PoolFuture<'a> {
-    pool: &'a Pool,
+/// Representing ownership of several of the resources in a [`Pool`]
+///
+/// Resources are added one at a time with [`MultiPooled::try_alloc`],
+/// and can be handed back individually with [`MultiPooled::release`];
+/// any still held are returned to the pool when the `MultiPooled` is
+/// dropped.
+///
+/// # Example
+/// ```rust
+/// use cotton_usb_host::async_pool::{MultiPooled, Pool};
+/// let pool = Pool::<3>::new();
+/// let mut mp = MultiPooled::new(&pool);
+/// let a = mp.try_alloc().unwrap();
+/// let _b = mp.try_alloc().unwrap();
+/// assert_eq!(mp.len(), 2);
+/// assert_eq!(mp.remaining(), 1);
+/// mp.release(a);
+/// assert!(!mp.contains(a));
+/// assert_eq!(mp.remaining(), 2);
+/// ```
+pub struct MultiPooled<'a, const N: usize> {
+    bits: BitSet,
+    pool: &'a Pool<N>,
+}
+
+impl<'a, const N: usize> MultiPooled<'a, N> {
+    /// Create a new `MultiPooled`, initially holding none of `pool`'s
+    /// resources
+    pub const fn new(pool: &'a Pool<N>) -> Self {
+        Self {
+            bits: BitSet::new(),
+            pool,
+        }
+    }
+
+    /// Obtain one more resource, if one is immediately available
+    ///
+    /// Returns which resource it is, or `None` if they're all in use.
+    pub fn try_alloc(&mut self) -> Option<u8> {
+        let n = self.pool.alloc_internal()?;
+        self.bits.set(n);
+        Some(n)
+    }
+
+    /// Return resource `n` to the pool
+    ///
+    /// Does nothing if `n` isn't one of the resources held.
+    pub fn release(&mut self, n: u8) {
+        if (n as usize) < N && self.bits.contains(n) {
+            self.bits.clear(n);
+            self.pool.state.dealloc_internal(n);
+        }
+    }
+
+    /// An iterator over the resources currently held
+    pub fn iter(&self) -> impl Iterator<Item = u8> {
+        self.bits.iter()
+    }
+
+    /// The resources currently held, as a set
+    pub fn bits(&self) -> BitSet {
+        self.bits
+    }
+
+    /// How many resources are currently held
+    pub fn len(&self) -> u8 {
+        self.bits.len()
+    }
+
+    /// Are no resources currently held?
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// Is resource `n` one of those currently held?
+    pub fn contains(&self, n: u8) -> bool {
+        (n as usize) < N && self.bits.contains(n)
+    }
+
+    /// How many resources are still available in the pool
+    ///
+    /// See [`Pool::remaining()`].
+    pub fn remaining(&self) -> u8 {
+        self.pool.remaining()
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for MultiPooled<'_, N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("MultiPooled")?;
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for MultiPooled<'_, N> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "MultiPooled({=u32:#010x})", self.bits.0);
+    }
 }
 
-impl<'a> Future for PoolFuture<'a> {
+impl<const N: usize> Drop for MultiPooled<'_, N> {
+    fn drop(&mut self) {
+        for n in self.bits.iter() {
+            self.pool.state.dealloc_internal(n);
+        }
+    }
+}
+
+struct PoolFuture<'a, const N: usize> {
+    pool: &'a Pool<N>,
+}
+
+impl<'a, const N: usize> Future for PoolFuture<'a, N> {
     type Output = Pooled<'a>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.pool.waker.replace(Some(cx.waker().clone()));
+        self.pool.state.waker.replace(Some(cx.waker().clone()));
 
         if let Some(n) = self.pool.alloc_internal() {
-            Poll::Ready(Pooled { n, pool: self.pool })
+            Poll::Ready(Pooled {
+                n,
+                pool: &self.pool.state,
+            })
         } else {
             Poll::Pending
         }
     }
 }
 
-impl Pool {
-    /// Create a new Pool, sharing out a number of equivalent resources
-    ///
-    /// # Parameters
-    /// - `total`: The number of resources (must be 0-32)
+impl<const N: usize> core::fmt::Debug for Pool<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Pool {{ total: {}, allocated: {:#010x} }}",
+            N,
+            self.state.allocated.get().0
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for Pool<N> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Pool {{ total: {}, allocated: {=u32:#010x} }}",
+            N,
+            self.state.allocated.get().0
+        );
+    }
+}
+
+impl<const N: usize> Default for Pool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Pool<N> {
+    /// Create a new Pool, sharing out `N` equivalent resources
     ///
-    /// # Panics
-    /// Will panic if `total`>32.
-    pub const fn new(total: u8) -> Self {
-        assert!(total <= 32);
+    /// `N` must be no more than 32; this is checked at compile time.
+    pub const fn new() -> Self {
+        const { assert!(N <= 32, "a Pool holds at most 32 resources") };
         Self {
-            total,
-            allocated: Cell::new(BitSet::new()),
-            waker: RefCell::new(None),
+            state: PoolState {
+                allocated: Cell::new(BitSet::new()),
+                waker: RefCell::new(None),
+            },
         }
     }
 
     fn alloc_internal(&self) -> Option<u8> {
-        let mut bits = self.allocated.get();
+        let mut bits = self.state.allocated.get();
         let n = bits.set_any()?;
-        if n >= self.total {
+        if n as usize >= N {
             None
         } else {
-            self.allocated.replace(bits);
+            self.state.allocated.replace(bits);
             Some(n)
         }
     }
 
-    fn dealloc_internal(&self, n: u8) {
-        let mut bits = self.allocated.get();
-        debug_assert!(bits.contains(n));
-        bits.clear(n);
-        self.allocated.replace(bits);
-
-        if let Some(w) = self.waker.take() {
-            w.wake();
-        }
+    /// How many of the resources are currently idle (unused)
+    ///
+    /// This is a snapshot: with several tasks using the pool, it may
+    /// be out of date as soon as it's returned.
+    pub fn remaining(&self) -> u8 {
+        N as u8 - self.state.allocated.get().len()
     }
 
     /// Obtain one of the resources
@@ -150,7 +315,7 @@ impl Pool {
     ///
     /// # See also
     /// [`Pool::try_alloc()`] for a synchronous version
-    pub async fn alloc(&self) -> Pooled {
+    pub async fn alloc(&self) -> Pooled<'_> {
         let fut = PoolFuture { pool: self };
         fut.await
     }
@@ -162,10 +327,10 @@ impl Pool {
     ///
     /// # See also
     /// [`Pool::alloc()`] for an asynchronous version
-    pub fn try_alloc(&self) -> Option<Pooled> {
+    pub fn try_alloc(&self) -> Option<Pooled<'_>> {
         Some(Pooled {
             n: self.alloc_internal()?,
-            pool: self,
+            pool: &self.state,
         })
     }
 }
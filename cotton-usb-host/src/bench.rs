@@ -0,0 +1,253 @@
+use crate::host_controller::{DataPhase, HostController, UsbError};
+use crate::usb_bus::{UsbBus, UsbDevice};
+use crate::wire::{Direction, RequestType, SetupPacket};
+use core::pin::pin;
+use futures::StreamExt;
+
+/// Summary statistics of a set of timings, in microseconds
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    count: u32,
+    min_us: u32,
+    max_us: u32,
+    total_us: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    /// No samples yet
+    pub const fn new() -> Self {
+        Self {
+            count: 0,
+            min_us: u32::MAX,
+            max_us: 0,
+            total_us: 0,
+        }
+    }
+
+    /// Add one sample
+    pub fn add(&mut self, us: u32) {
+        self.count += 1;
+        self.min_us = self.min_us.min(us);
+        self.max_us = self.max_us.max(us);
+        self.total_us += us as u64;
+    }
+
+    /// The number of samples
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The smallest sample (zero if there are none)
+    pub fn min_us(&self) -> u32 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min_us
+        }
+    }
+
+    /// The largest sample (zero if there are none)
+    pub fn max_us(&self) -> u32 {
+        self.max_us
+    }
+
+    /// The mean of the samples, rounded down (zero if there are none)
+    pub fn mean_us(&self) -> u32 {
+        if self.count == 0 {
+            0
+        } else {
+            (self.total_us / self.count as u64) as u32
+        }
+    }
+}
+
+/// The result of timing a bulk transfer
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Throughput {
+    /// How much data was transferred
+    pub bytes: u64,
+    /// How long it took, in microseconds
+    pub elapsed_us: u64,
+}
+
+impl Throughput {
+    /// The transfer rate in kilobytes (1024 bytes) per second, rounded down
+    pub fn kbytes_per_sec(&self) -> u64 {
+        if self.elapsed_us == 0 {
+            0
+        } else {
+            self.bytes * 1_000_000 / (self.elapsed_us * 1024)
+        }
+    }
+}
+
+/// One line of benchmark output
+///
+/// The formatting -- via `Display`, or via `defmt::Format` if the
+/// `defmt` feature is enabled -- is a single line of space-separated
+/// `key=value` fields after a `BENCH` tag, for instance:
+///
+/// ```text
+/// BENCH name=control_latency param=0 n=100 min=480 mean=495 max=1020
+/// BENCH name=msc_read param=4096 bytes=1048576 us=2100000 kbps=487
+/// ```
+///
+/// All times are in microseconds. This format is intended to be
+/// stable, so that results can be scraped from logs and compared
+/// across commits.
+pub struct Report<'a, T> {
+    /// Which benchmark this is
+    pub name: &'a str,
+    /// The parameter being varied (such as a chunk size), or zero
+    pub param: u32,
+    /// The measurements
+    pub result: T,
+}
+
+impl core::fmt::Display for Report<'_, Stats> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "BENCH name={} param={} n={} min={} mean={} max={}",
+            self.name,
+            self.param,
+            self.result.count(),
+            self.result.min_us(),
+            self.result.mean_us(),
+            self.result.max_us()
+        )
+    }
+}
+
+impl core::fmt::Display for Report<'_, Throughput> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "BENCH name={} param={} bytes={} us={} kbps={}",
+            self.name,
+            self.param,
+            self.result.bytes,
+            self.result.elapsed_us,
+            self.result.kbytes_per_sec()
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Report<'_, Stats> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "BENCH name={=str} param={} n={} min={} mean={} max={}",
+            self.name,
+            self.param,
+            self.result.count(),
+            self.result.min_us(),
+            self.result.mean_us(),
+            self.result.max_us()
+        );
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Report<'_, Throughput> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "BENCH name={=str} param={} bytes={} us={} kbps={}",
+            self.name,
+            self.param,
+            self.result.bytes,
+            self.result.elapsed_us,
+            self.result.kbytes_per_sec()
+        );
+    }
+}
+
+/// Microseconds between two readings of a clock, saturating
+pub fn elapsed_us(start: u64, end: u64) -> u32 {
+    end.wrapping_sub(start).try_into().unwrap_or(u32::MAX)
+}
+
+/// Time control-transfer round trips to a device
+///
+/// Issues `iterations` standard GET_STATUS requests (USB 2.0 section
+/// 9.4.5), which every device must answer, timing each one from
+/// submission to completion using `now`, a clock counting in
+/// microseconds.
+pub async fn control_latency<HC: HostController>(
+    bus: &UsbBus<HC>,
+    device: &UsbDevice,
+    iterations: u32,
+    now: impl Fn() -> u64,
+) -> Result<Stats, UsbError> {
+    let mut stats = Stats::new();
+    let mut status = [0u8; 2];
+    for _ in 0..iterations {
+        let start = now();
+        bus.control_transfer(
+            device,
+            SetupPacket::get_status(RequestType::new(Direction::In), 0, 2),
+            DataPhase::In(&mut status),
+        )
+        .await?;
+        stats.add(elapsed_us(start, now()));
+    }
+    Ok(stats)
+}
+
+/// Time the gaps between packets on an interrupt IN endpoint
+///
+/// Collects `samples` gaps (so waits for one more packet than that),
+/// timed using `now`, a clock counting in microseconds. The spread
+/// between the minimum and maximum gaps is the polling jitter.
+///
+/// A device only sends interrupt packets when it has something to
+/// say, so this needs a device which reports continuously -- for
+/// instance, a HID device which has been sent a SET_IDLE request
+/// with a non-zero duration (HID 1.11 section 7.2.4), or a mouse
+/// which is being waggled.
+pub async fn interrupt_intervals<HC: HostController>(
+    bus: &UsbBus<HC>,
+    device: &UsbDevice,
+    endpoint: u8,
+    max_packet_size: u16,
+    interval_ms: u8,
+    samples: u32,
+    now: impl Fn() -> u64,
+) -> Stats {
+    let mut stats = Stats::new();
+    let mut packets = pin!(bus.interrupt_endpoint_in(
+        device.address(),
+        endpoint,
+        max_packet_size,
+        interval_ms
+    ));
+    if packets.next().await.is_none() {
+        return stats;
+    }
+    let mut last = now();
+    while stats.count() < samples {
+        if packets.next().await.is_none() {
+            break;
+        }
+        let t = now();
+        stats.add(elapsed_us(last, t));
+        last = t;
+    }
+    stats
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/bench.rs"]
+mod tests;
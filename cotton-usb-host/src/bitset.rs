@@ -52,6 +52,16 @@ impl BitSet {
         assert!(n < 32);
         (self.0 & (1 << n)) != 0
     }
+
+    /// How many integers are present in the set
+    pub const fn len(&self) -> u8 {
+        self.0.count_ones() as u8
+    }
+
+    /// Is the set empty?
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
 }
 
 struct BitIterator(u32);
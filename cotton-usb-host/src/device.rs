@@ -1,2 +1,5 @@
 /// Identifying which driver to use for a particular USB device
 pub mod identify;
+
+/// Device drivers which can be registered with a [`UsbHost`](crate::usb_host::UsbHost)
+pub mod driver;
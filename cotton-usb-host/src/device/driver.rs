@@ -0,0 +1,31 @@
+use crate::usb_bus::{DeviceInfo, UsbDevice};
+
+/// Trait for USB device drivers registered with a [`UsbHost`](crate::usb_host::UsbHost)
+///
+/// The host offers each newly-connected device to its drivers in turn;
+/// the first to accept it gets the device, once configured, via
+/// [`Driver::start()`]. Drivers are shared (`&self`) and never awaited,
+/// so a typical driver just stashes the [`UsbDevice`] -- in a `Cell`, or
+/// a channel or signal from its executor -- for its own task to pick up.
+pub trait Driver {
+    /// A short name for the driver, for diagnostics
+    fn name(&self) -> &'static str;
+
+    /// Is this USB device capable of being driven by this driver?
+    ///
+    /// The `descriptors` are the device's configuration descriptors,
+    /// suitable for [`parse_descriptors()`](crate::wire::parse_descriptors)
+    /// (so an [`IdentifyFromDescriptors`](super::identify::IdentifyFromDescriptors)
+    /// implementation can be reused here).
+    ///
+    /// Returns:
+    /// - `None`: no, it isn't
+    /// - `Some(N)`: yes, it is -- _if_ configured with configuration `N`
+    fn probe(&self, info: &DeviceInfo, descriptors: &[u8]) -> Option<u8>;
+
+    /// The device has been configured as requested by [`Driver::probe()`]
+    fn start(&self, device: UsbDevice, info: &DeviceInfo);
+
+    /// A device previously passed to [`Driver::start()`] has been disconnected
+    fn stop(&self, address: u8);
+}
@@ -0,0 +1,212 @@
+use crate::bitset::BitSet;
+use crate::host_controller::{UsbError, UsbSpeed};
+use crate::wire::EndpointType;
+
+/// How many claimed interfaces are remembered per device
+pub const MAX_INTERFACES: usize = 4;
+
+/// How many open pipes are remembered per device
+pub const MAX_PIPES: usize = 6;
+
+const MAX_DEVICES: usize = 32;
+
+/// An interface which a driver has claimed, see
+/// [`UsbBus::claim_interface()`](crate::usb_bus::UsbBus::claim_interface)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct InterfaceClaim {
+    /// Interface number (`bInterfaceNumber`)
+    pub interface: u8,
+    /// Which driver claimed it (a tag chosen by the driver)
+    pub driver: &'static str,
+}
+
+/// A pipe which has been used to talk to a device
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PipeInfo {
+    /// Endpoint address, including the direction bit (0x80 for IN)
+    pub endpoint: u8,
+    /// Bulk or interrupt
+    pub endpoint_type: EndpointType,
+    /// Maximum packet size, in bytes
+    pub max_packet_size: u16,
+    /// Polling interval in milliseconds (interrupt pipes only, else 0)
+    pub interval_ms: u8,
+}
+
+/// Counts of failed transfers, by kind of failure
+///
+/// The counts saturate rather than wrapping.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct ErrorCounters {
+    /// Transfers which ended in [`UsbError::Stall`]
+    pub stalls: u16,
+    /// Transfers which ended in [`UsbError::Timeout`]
+    pub timeouts: u16,
+    /// Transfers which ended in any other error
+    pub others: u16,
+    /// The most recent error, if any
+    pub last: Option<UsbError>,
+}
+
+impl ErrorCounters {
+    fn record(&mut self, e: UsbError) {
+        let counter = match e {
+            UsbError::Stall => &mut self.stalls,
+            UsbError::Timeout => &mut self.timeouts,
+            _ => &mut self.others,
+        };
+        *counter = counter.saturating_add(1);
+        self.last = Some(e);
+    }
+}
+
+/// A snapshot of what cotton-usb-host knows about one device
+///
+/// Obtained from
+/// [`UsbBus::diagnostics()`](crate::usb_bus::UsbBus::diagnostics).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct DeviceDiagnostics {
+    /// USB device address
+    pub address: u8,
+    /// Negotiated connection speed
+    pub speed: UsbSpeed,
+    /// Configuration value, if the device has been configured
+    pub configuration: Option<u8>,
+    /// Transfer failures since the device was addressed
+    pub errors: ErrorCounters,
+    interfaces: [Option<InterfaceClaim>; MAX_INTERFACES],
+    pipes: [Option<PipeInfo>; MAX_PIPES],
+}
+
+impl DeviceDiagnostics {
+    fn new(address: u8, speed: UsbSpeed) -> Self {
+        Self {
+            address,
+            speed,
+            configuration: None,
+            errors: ErrorCounters::default(),
+            interfaces: [None; MAX_INTERFACES],
+            pipes: [None; MAX_PIPES],
+        }
+    }
+
+    /// The interfaces claimed by drivers
+    pub fn interfaces(&self) -> impl Iterator<Item = &InterfaceClaim> {
+        self.interfaces.iter().flatten()
+    }
+
+    /// The pipes in use (at most [`MAX_PIPES`] are remembered)
+    pub fn pipes(&self) -> impl Iterator<Item = &PipeInfo> {
+        self.pipes.iter().flatten()
+    }
+}
+
+/// The records behind [`DeviceDiagnostics`], for every address
+#[derive(Clone)]
+pub(crate) struct Registry {
+    devices: [Option<DeviceDiagnostics>; MAX_DEVICES],
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            devices: [None; MAX_DEVICES],
+        }
+    }
+}
+
+impl Registry {
+    fn entry(&mut self, address: u8) -> Option<&mut DeviceDiagnostics> {
+        self.devices.get_mut(address as usize)?.as_mut()
+    }
+
+    pub(crate) fn get(&self, address: u8) -> Option<DeviceDiagnostics> {
+        *self.devices.get(address as usize)?
+    }
+
+    /// A device has been given an address; anything previously known
+    /// about that address is forgotten
+    pub(crate) fn addressed(&mut self, address: u8, speed: UsbSpeed) {
+        if let Some(d) = self.devices.get_mut(address as usize) {
+            *d = Some(DeviceDiagnostics::new(address, speed));
+        }
+    }
+
+    pub(crate) fn configured(&mut self, address: u8, configuration: u8) {
+        if let Some(d) = self.entry(address) {
+            d.configuration = Some(configuration);
+            d.interfaces = [None; MAX_INTERFACES];
+            d.pipes = [None; MAX_PIPES];
+        }
+    }
+
+    /// Record a claim; claims beyond the first [`MAX_INTERFACES`], or for
+    /// unknown addresses, aren't recorded (and so aren't checked)
+    pub(crate) fn claim(
+        &mut self,
+        address: u8,
+        interface: u8,
+        driver: &'static str,
+    ) -> Result<(), UsbError> {
+        let Some(d) = self.entry(address) else {
+            return Ok(());
+        };
+        if d.interfaces().any(|c| c.interface == interface) {
+            return Err(UsbError::InterfaceClaimed);
+        }
+        if let Some(slot) = d.interfaces.iter_mut().find(|c| c.is_none()) {
+            *slot = Some(InterfaceClaim { interface, driver });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn release(&mut self, address: u8, interface: u8) {
+        if let Some(d) = self.entry(address) {
+            for c in d.interfaces.iter_mut() {
+                if c.is_some_and(|c| c.interface == interface) {
+                    *c = None;
+                }
+            }
+        }
+    }
+
+    /// A pipe has been used; pipes already recorded are left alone, and
+    /// pipes beyond the first [`MAX_PIPES`] aren't recorded
+    pub(crate) fn pipe(&mut self, address: u8, pipe: PipeInfo) {
+        if let Some(d) = self.entry(address) {
+            if d.pipes().any(|p| p.endpoint == pipe.endpoint) {
+                return;
+            }
+            if let Some(slot) = d.pipes.iter_mut().find(|p| p.is_none()) {
+                *slot = Some(pipe);
+            }
+        }
+    }
+
+    pub(crate) fn error(&mut self, address: u8, e: UsbError) {
+        if let Some(d) = self.entry(address) {
+            d.errors.record(e);
+        }
+    }
+
+    /// Some devices have been disconnected
+    pub(crate) fn forget(&mut self, addresses: BitSet) {
+        for a in addresses.iter() {
+            if let Some(d) = self.devices.get_mut(a as usize) {
+                *d = None;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/diagnostics.rs"]
+mod tests;
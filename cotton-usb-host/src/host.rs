@@ -1,3 +1,7 @@
+/// Interrupt-enable bookkeeping for the RP2040 driver (hardware-independent)
+#[cfg(any(feature = "rp2040", all(test, feature = "std")))]
+pub(crate) mod interrupt_enables;
+
 /// HostController implementation for Raspberry Pi Pico / RP2040
 #[cfg(feature = "rp2040")]
 pub mod rp2040;
@@ -0,0 +1,157 @@
+use crate::bitset::BitSet;
+use core::cell::Cell;
+use critical_section::Mutex;
+
+/// The "buffer status" bit in the RP2040's INTE/INTS registers
+pub const BUFF_STATUS: u32 = 1 << 4;
+
+/// The BUFF_STATUS bits belonging to the control pipe (EPX)
+pub const CONTROL_BUFFERS: u32 = 0x3;
+
+/// Bookkeeping for sharing the BUFF_STATUS interrupt between pipes
+///
+/// On the RP2040, one interrupt-enable bit covers buffer completions
+/// on every pipe. Interrupt (periodic) pipes need it enabled for as
+/// long as they exist, but control transfers only want it for the
+/// duration of a multi-packet data stage -- and the IRQ handler, which
+/// masks off any interrupt it can't clear, would otherwise drop it as
+/// soon as a control-pipe buffer completion was left pending.
+///
+/// This keeps track of which pipes are "armed" (need BUFF_STATUS),
+/// and computes the register values that the driver writes, so that
+/// the rules can be tested without hardware. It also latches which
+/// interrupt pipes the device has STALLed, as the IRQ handler must
+/// acknowledge EP_STATUS_STALL_NAK before the pipes get to look at it.
+pub struct InterruptEnables {
+    armed: Mutex<Cell<BitSet>>,
+    control_buffers: Mutex<Cell<bool>>,
+    stalled: Mutex<Cell<BitSet>>,
+}
+
+impl InterruptEnables {
+    /// Create a new `InterruptEnables` with no pipes armed
+    pub const fn new() -> Self {
+        Self {
+            armed: Mutex::new(Cell::new(BitSet::new())),
+            control_buffers: Mutex::new(Cell::new(false)),
+            stalled: Mutex::new(Cell::new(BitSet::new())),
+        }
+    }
+
+    /// Record that pipe `n` needs BUFF_STATUS enabled
+    pub fn arm(&self, n: u8) {
+        critical_section::with(|cs| {
+            let cell = self.armed.borrow(cs);
+            let mut bits = cell.get();
+            bits.set(n);
+            cell.set(bits);
+        });
+    }
+
+    /// Record that pipe `n` no longer needs BUFF_STATUS enabled
+    ///
+    /// Also forgets any stall latched for it.
+    pub fn disarm(&self, n: u8) {
+        critical_section::with(|cs| {
+            for cell in [self.armed.borrow(cs), self.stalled.borrow(cs)] {
+                let mut bits = cell.get();
+                bits.clear(n);
+                cell.set(bits);
+            }
+        });
+    }
+
+    /// The set of pipes currently needing BUFF_STATUS enabled
+    pub fn armed(&self) -> BitSet {
+        critical_section::with(|cs| self.armed.borrow(cs).get())
+    }
+
+    /// Handle a BUFF_STATUS interrupt (called from the IRQ handler)
+    ///
+    /// Returns the set of pipes to wake, and the bits to write to
+    /// BUFF_STATUS to acknowledge them. While no pipes are armed, the
+    /// control pipe's bits are left for thread-mode code to acknowledge
+    /// (and BUFF_STATUS gets masked off in the meantime); while any are
+    /// armed, the control pipe's bits are latched in software instead
+    /// (see [`InterruptEnables::take_control_buffers()`]) so that the
+    /// interrupt can stay enabled without storming.
+    pub fn on_buff_status(&self, buff_status: u32) -> (BitSet, u32) {
+        let mut wake = BitSet::new();
+        for i in 0..16 {
+            if (buff_status & (3 << (i * 2))) != 0 {
+                wake.set(i);
+            }
+        }
+
+        if self.armed().is_empty() {
+            (wake, !CONTROL_BUFFERS)
+        } else {
+            if (buff_status & CONTROL_BUFFERS) != 0 {
+                critical_section::with(|cs| {
+                    self.control_buffers.borrow(cs).set(true)
+                });
+            }
+            (wake, 0xFFFF_FFFF)
+        }
+    }
+
+    /// Consume any control-pipe buffer completion latched by the IRQ
+    pub fn take_control_buffers(&self) -> bool {
+        critical_section::with(|cs| self.control_buffers.borrow(cs).take())
+    }
+
+    /// Handle an EP_STALL_NAK interrupt (called from the IRQ handler)
+    ///
+    /// `status` is the value of EP_STATUS_STALL_NAK, which has two bits
+    /// per pipe (IN, then OUT); interrupt pipes are all IN. Returns the
+    /// set of (armed) pipes which have stalled, and so need waking.
+    pub fn on_stall_nak(&self, status: u32) -> BitSet {
+        let mut stalled = BitSet::new();
+        for i in self.armed().iter() {
+            if (status & (1 << (i * 2))) != 0 {
+                stalled.set(i);
+            }
+        }
+        if !stalled.is_empty() {
+            critical_section::with(|cs| {
+                let cell = self.stalled.borrow(cs);
+                cell.set(BitSet(cell.get().0 | stalled.0));
+            });
+        }
+        stalled
+    }
+
+    /// Consume any stall of pipe `n` latched by the IRQ
+    pub fn take_stall(&self, n: u8) -> bool {
+        critical_section::with(|cs| {
+            let cell = self.stalled.borrow(cs);
+            let mut bits = cell.get();
+            let stalled = bits.contains(n);
+            bits.clear(n);
+            cell.set(bits);
+            stalled
+        })
+    }
+
+    /// The new INTE value after the IRQ handler masks `pending` interrupts
+    ///
+    /// BUFF_STATUS stays enabled while any pipe is armed.
+    pub fn after_irq(&self, inte: u32, pending: u32) -> u32 {
+        let inte = inte & !pending;
+        if self.armed().is_empty() {
+            inte
+        } else {
+            inte | BUFF_STATUS
+        }
+    }
+}
+
+impl Default for InterruptEnables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "../tests/interrupt_enables.rs"]
+mod tests;
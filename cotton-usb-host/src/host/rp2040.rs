@@ -1,22 +1,33 @@
 use crate::async_pool::Pool;
 use crate::debug;
+use crate::host::interrupt_enables::InterruptEnables;
 use crate::host_controller::{
-    DataPhase, DeviceStatus, HostController, InterruptPacket, TransferType,
-    UsbError, UsbSpeed,
+    ControllerCapabilities, DataPhase, DeviceStatus, HostController,
+    InterruptPacket, ReadBuffer, TransferType, UsbError, UsbSpeed,
 };
 use crate::wire::{Direction, EndpointType, SetupPacket};
 use core::cell::Cell;
 use core::future::Future;
+use core::mem::MaybeUninit;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 use futures::Stream;
 use rp2040_pac as pac;
 use rtic_common::waker_registration::CriticalSectionWakerRegistration;
 
+/// Glue for using the RP2040 host controller from RTIC
+#[cfg(feature = "rtic")]
+pub mod rtic;
+
+/// Glue for using the RP2040 host controller from Embassy
+#[cfg(feature = "embassy")]
+pub mod embassy;
+
 /// Data shared between interrupt handler and thread-mode code
 pub struct UsbShared {
     device_waker: CriticalSectionWakerRegistration,
     pipe_wakers: [CriticalSectionWakerRegistration; 16],
+    enables: InterruptEnables,
 }
 
 impl UsbShared {
@@ -32,13 +43,20 @@ impl UsbShared {
 
         if ints.buff_status().bit() {
             let bs = regs.buff_status().read().bits();
-            for i in 0..15 {
-                if (bs & (3 << (i * 2))) != 0 {
-                    defmt::info!("IRQ wakes {}", i);
-                    self.pipe_wakers[i].wake();
-                }
+            let (wake, clear) = self.enables.on_buff_status(bs);
+            for i in wake.iter() {
+                defmt::info!("IRQ wakes {}", i);
+                self.pipe_wakers[i as usize].wake();
+            }
+            regs.buff_status().write(|w| unsafe { w.bits(clear) });
+        }
+        if ints.ep_stall_nak().bit() {
+            let status = regs.ep_status_stall_nak().read().bits();
+            regs.ep_status_stall_nak()
+                .write(|w| unsafe { w.bits(status) });
+            for i in self.enables.on_stall_nak(status).iter() {
+                self.pipe_wakers[i as usize].wake();
             }
-            regs.buff_status().write(|w| unsafe { w.bits(0xFFFF_FFFC) });
         }
         if (ints.bits() & 1) != 0 {
             // This clears the interrupt but does NOT clear sie_status.speed!
@@ -51,9 +69,11 @@ impl UsbShared {
         }
 
         // Disable any remaining interrupts so we don't have an IRQ storm
+        // (but not BUFF_STATUS while interrupt pipes are relying on it)
         let bits = regs.ints().read().bits();
         unsafe {
-            regs.inte().modify(|r, w| w.bits(r.bits() & !bits));
+            regs.inte()
+                .modify(|r, w| w.bits(self.enables.after_irq(r.bits(), bits)));
         }
         /*        defmt::info!(
             "IRQ2 ints={:x} inte={:x}",
@@ -74,6 +94,7 @@ impl UsbShared {
         Self {
             device_waker: CriticalSectionWakerRegistration::new(),
             pipe_wakers: [Self::W; 16],
+            enables: InterruptEnables::new(),
         }
     }
 }
@@ -84,18 +105,24 @@ impl Default for UsbShared {
     }
 }
 
+/// The pool of bulk/interrupt pipes (hardware endpoints 1-15)
+type BulkPipePool = Pool<15>;
+
+/// The pool of control pipes (hardware endpoint 0)
+type ControlPipePool = Pool<1>;
+
 /// Data that isn't shared with the IRQ handler, but must be 'static anyway
 pub struct UsbStatics {
-    bulk_pipes: Pool,
-    control_pipes: Pool,
+    bulk_pipes: BulkPipePool,
+    control_pipes: ControlPipePool,
 }
 
 impl UsbStatics {
     /// Crate a new `UsbStatics` (nb, is const, unlike `default()`)
     pub const fn new() -> Self {
         Self {
-            bulk_pipes: Pool::new(15),
-            control_pipes: Pool::new(1),
+            bulk_pipes: BulkPipePool::new(),
+            control_pipes: ControlPipePool::new(),
         }
     }
 }
@@ -106,6 +133,83 @@ impl Default for UsbStatics {
     }
 }
 
+/// Everything the RP2040 host controller needs to be 'static, together
+///
+/// A `UsbShared` and a `UsbStatics`, in a form that can be declared as
+/// an ordinary `static` (no `StaticCell` needed), and which hands out
+/// at most one [`Rp2040HostController`]:
+///
+/// ```ignore
+/// static USB: Rp2040Usb = Rp2040Usb::new();
+///
+/// // in the USBCTRL_IRQ handler, however that's bound:
+/// USB.on_irq();
+///
+/// // in the task which uses the USB bus:
+/// let driver = USB.host_controller(&mut resets, regs, dpram);
+/// ```
+///
+/// See the `rtic` and `embassy` modules for executor-specific glue.
+pub struct Rp2040Usb {
+    shared: UsbShared,
+    statics: UsbStatics,
+    taken: critical_section::Mutex<Cell<bool>>,
+}
+
+// SAFETY: `shared` is Sync anyway; `statics` is reachable only through
+// the one Rp2040HostController that `host_controller()` hands out,
+// which isn't Send (because UsbStatics isn't Sync) and so stays in
+// whichever context created it.
+unsafe impl Sync for Rp2040Usb {}
+
+impl Rp2040Usb {
+    /// Create a new `Rp2040Usb` (nb, is const, unlike `default()`)
+    pub const fn new() -> Self {
+        Self {
+            shared: UsbShared::new(),
+            statics: UsbStatics::new(),
+            taken: critical_section::Mutex::new(Cell::new(false)),
+        }
+    }
+
+    /// IRQ handler: call this from the USBCTRL_IRQ interrupt
+    pub fn on_irq(&self) {
+        self.shared.on_irq();
+    }
+
+    /// Create the host controller
+    ///
+    /// As [`Rp2040HostController::new()`], but using the statics in
+    /// `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once on the same `Rp2040Usb`.
+    pub fn host_controller(
+        &'static self,
+        resets: &mut pac::RESETS,
+        regs: pac::USBCTRL_REGS,
+        dpram: pac::USBCTRL_DPRAM,
+    ) -> Rp2040HostController {
+        let taken =
+            critical_section::with(|cs| self.taken.borrow(cs).replace(true));
+        assert!(!taken, "Rp2040Usb::host_controller called twice");
+        Rp2040HostController::new(
+            resets,
+            regs,
+            dpram,
+            &self.shared,
+            &self.statics,
+        )
+    }
+}
+
+impl Default for Rp2040Usb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Implementation of `HostController::DeviceDetect` for RP2040
 #[derive(Copy, Clone)]
 pub struct Rp2040DeviceDetect {
@@ -164,12 +268,12 @@ impl Stream for Rp2040DeviceDetect {
 }
 
 struct Rp2040ControlEndpoint<'a> {
-    waker: &'a CriticalSectionWakerRegistration,
+    shared: &'a UsbShared,
 }
 
 impl<'a> Rp2040ControlEndpoint<'a> {
-    fn new(waker: &'a CriticalSectionWakerRegistration) -> Self {
-        Self { waker }
+    fn new(shared: &'a UsbShared) -> Self {
+        Self { shared }
     }
 }
 
@@ -178,13 +282,15 @@ impl Future for Rp2040ControlEndpoint<'_> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         //defmt::trace!("CE register");
-        self.waker.register(cx.waker());
+        self.shared.pipe_wakers[0].register(cx.waker());
 
         let regs = unsafe { pac::USBCTRL_REGS::steal() };
         let status = regs.sie_status().read();
         let intr = regs.intr().read();
         let bcsh = regs.buff_cpu_should_handle().read();
-        if (intr.bits() & 0x458) != 0 {
+        if (intr.bits() & 0x458) != 0
+            || self.shared.enables.take_control_buffers()
+        {
             defmt::info!(
                 "CE ready {:x} {:x} {:x}",
                 status.bits(),
@@ -292,6 +398,66 @@ pub struct Rp2040InterruptPipe {
     pipe: Pipe,
     max_packet_size: u16,
     data_toggle: Cell<bool>,
+    stalled: Cell<bool>,
+}
+
+/// Point an interrupt pipe at a particular (IN) endpoint
+fn program_interrupt_endpoint(
+    which: u8,
+    address: u8,
+    endpoint: u8,
+    interval_ms: u8,
+) {
+    let regs = unsafe { pac::USBCTRL_REGS::steal() };
+    let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
+    regs.host_addr_endp((which - 1) as usize).write(|w| unsafe {
+        w.address()
+            .bits(address)
+            .endpoint()
+            .bits(endpoint)
+            .intep_dir()
+            .clear_bit() // IN
+    });
+
+    dpram
+        .ep_control((which * 2 - 2) as usize)
+        .write(|w| unsafe {
+            w.enable()
+                .set_bit()
+                .interrupt_per_buff()
+                .set_bit()
+                .interrupt_on_stall()
+                .set_bit()
+                .endpoint_type()
+                .interrupt()
+                .buffer_address()
+                .bits(0x200 + (which as u16) * 128)
+                .host_poll_interval()
+                .bits(core::cmp::min(interval_ms as u16, 9))
+        });
+}
+
+/// Hand an interrupt pipe's (single) buffer to the hardware
+fn arm_interrupt_buffer(which: u8, max_packet_size: u16, pid: bool) {
+    let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
+    dpram
+        .ep_buffer_control((which * 2) as usize)
+        .write(|w| unsafe {
+            w.full_0()
+                .clear_bit()
+                .pid_0()
+                .bit(pid)
+                .length_0()
+                .bits(max_packet_size)
+                .last_0()
+                .set_bit()
+        });
+
+    cortex_m::asm::delay(12);
+
+    dpram
+        .ep_buffer_control((which * 2) as usize)
+        .modify(|_, w| w.available_0().set_bit());
 }
 
 impl Rp2040InterruptPipe {
@@ -299,6 +465,25 @@ impl Rp2040InterruptPipe {
         self.shared.pipe_wakers[self.pipe.which() as usize].register(waker);
     }
 
+    /// Has the device STALLed the endpoint since we last looked?
+    ///
+    /// If so, stop polling it.
+    fn check_stall(&self) -> bool {
+        let regs = unsafe { pac::USBCTRL_REGS::steal() };
+        let which = self.pipe.which();
+        let bit = 1 << (which * 2);
+        let stalled = self.shared.enables.take_stall(which)
+            || (regs.ep_status_stall_nak().read().bits() & bit) != 0;
+        if stalled {
+            regs.ep_status_stall_nak().write(|w| unsafe { w.bits(bit) });
+            regs.int_ep_ctrl()
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << which)) });
+            self.stalled.set(true);
+            debug::println!("IE {} stalled", which);
+        }
+        stalled
+    }
+
     fn poll(&self) -> Option<InterruptPacket> {
         let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
         let regs = unsafe { pac::USBCTRL_REGS::steal() };
@@ -320,24 +505,11 @@ impl Rp2040InterruptPipe {
                 )
             };
             self.data_toggle.set(!self.data_toggle.get());
-            dpram
-                .ep_buffer_control((which * 2) as usize)
-                .write(|w| unsafe {
-                    w.full_0()
-                        .clear_bit()
-                        .pid_0()
-                        .bit(self.data_toggle.get())
-                        .length_0()
-                        .bits(self.max_packet_size)
-                        .last_0()
-                        .set_bit()
-                });
-
-            cortex_m::asm::delay(12);
-
-            dpram
-                .ep_buffer_control((which * 2) as usize)
-                .modify(|_, w| w.available_0().set_bit());
+            arm_interrupt_buffer(
+                which,
+                self.max_packet_size,
+                self.data_toggle.get(),
+            );
             defmt::println!(
                 "IE ready inte {:x} iec {:x} ecr {:x} epbc {:x}",
                 regs.inte().read().bits(),
@@ -348,7 +520,9 @@ impl Rp2040InterruptPipe {
 
             Some(result)
         } else {
-            regs.inte().modify(|_, w| w.buff_status().set_bit());
+            regs.inte().modify(|_, w| {
+                w.buff_status().set_bit().ep_stall_nak().set_bit()
+            });
             regs.int_ep_ctrl()
                 .modify(|r, w| unsafe { w.bits(r.bits() | (1 << which)) });
             defmt::trace!(
@@ -358,14 +532,18 @@ impl Rp2040InterruptPipe {
                 dpram.ep_control((which * 2) as usize - 2).read().bits(),
                 dpram.ep_buffer_control((which * 2) as usize).read().bits(),
             );
-            regs.ep_status_stall_nak()
-                .write(|w| unsafe { w.bits(3 << (which * 2)) });
 
             None
         }
     }
 }
 
+impl Drop for Rp2040InterruptPipe {
+    fn drop(&mut self) {
+        self.shared.enables.disarm(self.pipe.which());
+    }
+}
+
 impl Stream for Rp2040InterruptPipe {
     type Item = InterruptPacket;
 
@@ -375,14 +553,72 @@ impl Stream for Rp2040InterruptPipe {
     ) -> Poll<Option<Self::Item>> {
         self.set_waker(cx.waker());
 
-        if let Some(packet) = self.poll() {
+        if self.stalled.get() || self.check_stall() {
+            Poll::Ready(None)
+        } else if let Some(packet) = self.poll() {
             Poll::Ready(Some(packet))
+        } else if self.check_stall() {
+            // Stalled between the first check and enabling the IRQ
+            Poll::Ready(None)
         } else {
             Poll::Pending
         }
     }
 }
 
+impl crate::host_controller::InterruptPipe for Rp2040InterruptPipe {
+    fn is_stalled(&self) -> bool {
+        self.stalled.get() || self.check_stall()
+    }
+
+    fn resume(&mut self) {
+        if self.stalled.take() {
+            // The device restarts at DATA0 after CLEAR_FEATURE(HALT);
+            // the next poll re-enables the hardware's polling
+            self.data_toggle.set(false);
+            arm_interrupt_buffer(
+                self.pipe.which(),
+                self.max_packet_size,
+                false,
+            );
+        }
+    }
+
+    fn retarget(
+        &mut self,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+        data_toggle: bool,
+    ) {
+        let regs = unsafe { pac::USBCTRL_REGS::steal() };
+        let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
+        let which = self.pipe.which();
+
+        // Stop the hardware polling the old endpoint while it's
+        // reprogrammed; the next poll re-enables it
+        regs.int_ep_ctrl()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << which)) });
+        dpram
+            .ep_buffer_control((which * 2) as usize)
+            .write(|w| unsafe { w.bits(0) });
+        regs.ep_status_stall_nak()
+            .write(|w| unsafe { w.bits(1 << (which * 2)) });
+        self.shared.enables.take_stall(which);
+        self.stalled.set(false);
+
+        program_interrupt_endpoint(which, address, endpoint, interval_ms);
+        self.max_packet_size = max_packet_size;
+        self.data_toggle.set(data_toggle);
+        arm_interrupt_buffer(which, max_packet_size, data_toggle);
+    }
+
+    fn data_toggle(&self) -> bool {
+        self.data_toggle.get()
+    }
+}
+
 enum ZeroLengthPacket {
     AsNeeded,
     Never,
@@ -636,16 +872,21 @@ trait Depacketiser {
     fn retire(&mut self, reg: &pac::usbctrl_dpram::EP_BUFFER_CONTROL) -> bool;
 }
 
+/// Copies received packets out of DPRAM
+///
+/// The buffer needn't be initialised: only bytes copied from DPRAM are
+/// ever written into it, contiguously from the start, so after the
+/// transfer the first `total()` bytes are initialised.
 struct InDepacketiser<'a> {
     next_retire: u8,
     packet_parity: bool,
     remain: usize,
     offset: usize,
-    buf: &'a mut [u8],
+    buf: &'a mut [MaybeUninit<u8>],
 }
 
 impl<'a> InDepacketiser<'a> {
-    fn new(size: u16, buf: &'a mut [u8]) -> Self {
+    fn new(size: u16, buf: &'a mut [MaybeUninit<u8>]) -> Self {
         Self {
             next_retire: 0,
             packet_parity: false,
@@ -677,10 +918,14 @@ impl Depacketiser for InDepacketiser<'_> {
                         val.length_0().bits() as usize,
                     );
                     if this_packet > 0 {
+                        let dest = &mut self.buf[self.offset..][..this_packet];
+                        // SAFETY: `dest` is exactly this_packet bytes
+                        // long (the slicing checks that), and the
+                        // packet buffer in DPRAM is at least that long
                         unsafe {
                             core::ptr::copy_nonoverlapping(
                                 (0x5010_0000 + 0x180) as *const u8,
-                                &mut self.buf[self.offset] as *mut u8,
+                                dest.as_mut_ptr().cast::<u8>(),
                                 this_packet,
                             );
                         }
@@ -705,10 +950,14 @@ impl Depacketiser for InDepacketiser<'_> {
                         val.length_1().bits() as usize,
                     );
                     if this_packet > 0 {
+                        let dest = &mut self.buf[self.offset..][..this_packet];
+                        // SAFETY: `dest` is exactly this_packet bytes
+                        // long (the slicing checks that), and the
+                        // packet buffer in DPRAM is at least that long
                         unsafe {
                             core::ptr::copy_nonoverlapping(
                                 (0x5010_0000 + 0x1C0) as *const u8,
-                                &mut self.buf[self.offset] as *mut u8,
+                                dest.as_mut_ptr().cast::<u8>(),
                                 this_packet,
                             );
                         }
@@ -764,6 +1013,201 @@ impl Depacketiser for OutDepacketiser {
     }
 }
 
+/// Names of the fields of a register: (shift, width in bits, name)
+type FieldNames = [(u8, u8, &'static str)];
+
+const SIE_STATUS_FIELDS: &FieldNames = &[
+    (0, 1, "VBUS_DETECTED"),
+    (2, 2, "LINE_STATE"),
+    (4, 1, "SUSPENDED"),
+    (8, 2, "SPEED"),
+    (10, 1, "VBUS_OVER_CURR"),
+    (11, 1, "RESUME"),
+    (16, 1, "CONNECTED"),
+    (17, 1, "SETUP_REC"),
+    (18, 1, "TRANS_COMPLETE"),
+    (19, 1, "BUS_RESET"),
+    (24, 1, "CRC_ERROR"),
+    (25, 1, "BIT_STUFF_ERROR"),
+    (26, 1, "RX_OVERFLOW"),
+    (27, 1, "RX_TIMEOUT"),
+    (28, 1, "NAK_REC"),
+    (29, 1, "STALL_REC"),
+    (30, 1, "ACK_REC"),
+    (31, 1, "DATA_SEQ_ERROR"),
+];
+
+const SIE_CTRL_FIELDS: &FieldNames = &[
+    (0, 1, "START_TRANS"),
+    (1, 1, "SEND_SETUP"),
+    (2, 1, "SEND_DATA"),
+    (3, 1, "RECEIVE_DATA"),
+    (4, 1, "STOP_TRANS"),
+    (6, 1, "PREAMBLE_EN"),
+    (8, 1, "SOF_SYNC"),
+    (9, 1, "SOF_EN"),
+    (10, 1, "KEEP_ALIVE_EN"),
+    (11, 1, "VBUS_EN"),
+    (12, 1, "RESUME"),
+    (13, 1, "RESET_BUS"),
+    (15, 1, "PULLDOWN_EN"),
+    (16, 1, "PULLUP_EN"),
+    (18, 1, "TRANSCEIVER_PD"),
+    (27, 1, "EP0_INT_NAK"),
+    (28, 1, "EP0_INT_2BUF"),
+    (29, 1, "EP0_INT_1BUF"),
+    (30, 1, "EP0_DOUBLE_BUF"),
+    (31, 1, "EP0_INT_STALL"),
+];
+
+/// Shared by INTE and INTS (and INTR)
+const INT_FIELDS: &FieldNames = &[
+    (0, 1, "HOST_CONN_DIS"),
+    (1, 1, "HOST_RESUME"),
+    (2, 1, "HOST_SOF"),
+    (3, 1, "TRANS_COMPLETE"),
+    (4, 1, "BUFF_STATUS"),
+    (5, 1, "ERROR_DATA_SEQ"),
+    (6, 1, "ERROR_RX_TIMEOUT"),
+    (7, 1, "ERROR_RX_OVERFLOW"),
+    (8, 1, "ERROR_BIT_STUFF"),
+    (9, 1, "ERROR_CRC"),
+    (10, 1, "STALL"),
+    (11, 1, "VBUS_DETECT"),
+    (12, 1, "BUS_RESET"),
+    (19, 1, "EP_STALL_NAK"),
+];
+
+const BUFFER_CONTROL_FIELDS: &FieldNames = &[
+    (0, 10, "LENGTH_0"),
+    (10, 1, "AVAILABLE_0"),
+    (11, 1, "STALL"),
+    (12, 1, "RESET"),
+    (13, 1, "PID_0"),
+    (14, 1, "LAST_0"),
+    (15, 1, "FULL_0"),
+    (16, 10, "LENGTH_1"),
+    (26, 1, "AVAILABLE_1"),
+    (29, 1, "PID_1"),
+    (30, 1, "LAST_1"),
+    (31, 1, "FULL_1"),
+];
+
+const ADDR_ENDP_FIELDS: &FieldNames =
+    &[(0, 7, "ADDRESS"), (16, 4, "ENDPOINT")];
+
+/// A raw register value, formatted along with its named fields
+///
+/// Single-bit fields are listed by name if set; wider fields are
+/// always listed, as `NAME=value`.
+struct Decoded(u32, &'static FieldNames);
+
+impl Decoded {
+    fn fields(&self) -> impl Iterator<Item = (&'static str, Option<u32>)> {
+        let value = self.0;
+        self.1.iter().filter_map(move |&(shift, width, name)| {
+            let field = (value >> shift) & ((1u32 << width) - 1);
+            if width > 1 {
+                Some((name, Some(field)))
+            } else if field != 0 {
+                Some((name, None))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl core::fmt::Debug for Decoded {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:#010x} [", self.0)?;
+        for (name, value) in self.fields() {
+            match value {
+                Some(v) => write!(f, " {}={}", name, v)?,
+                None => write!(f, " {}", name)?,
+            }
+        }
+        f.write_str(" ]")
+    }
+}
+
+impl defmt::Format for Decoded {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=u32:#010x} [", self.0);
+        for (name, value) in self.fields() {
+            match value {
+                Some(v) => defmt::write!(f, " {=str}={}", name, v),
+                None => defmt::write!(f, " {=str}", name),
+            }
+        }
+        defmt::write!(f, " ]");
+    }
+}
+
+/// The USB controller registers most useful in diagnosing failures
+///
+/// Obtained from [`Rp2040HostController::debug_snapshot()`]. These are
+/// the raw register values; the `Debug` and `defmt::Format`
+/// implementations print them on one line, along with the names of
+/// the bits which are set (and the values of multi-bit fields such as
+/// `SPEED` and `ADDRESS`), so that the line can be pasted straight
+/// into a bug report. See the register descriptions in the USB
+/// chapter of the RP2040 datasheet for the meanings.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ControllerSnapshot {
+    /// SIE_STATUS
+    pub sie_status: u32,
+    /// SIE_CTRL
+    pub sie_ctrl: u32,
+    /// INTE (interrupts enabled)
+    pub inte: u32,
+    /// INTS (interrupts pending, after masking by INTE)
+    pub ints: u32,
+    /// BUFF_STATUS (one bit per endpoint direction)
+    pub buff_status: u32,
+    /// INT_EP_CTRL (one bit per active interrupt endpoint)
+    pub int_ep_ctrl: u32,
+    /// EPX buffer control, in DPRAM
+    pub epx_buffer_control: u32,
+    /// ADDR_ENDP (address and endpoint of the EPX transfer)
+    pub addr_endp: u32,
+}
+
+impl core::fmt::Debug for ControllerSnapshot {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("ControllerSnapshot")
+            .field("sie_status", &Decoded(self.sie_status, SIE_STATUS_FIELDS))
+            .field("sie_ctrl", &Decoded(self.sie_ctrl, SIE_CTRL_FIELDS))
+            .field("inte", &Decoded(self.inte, INT_FIELDS))
+            .field("ints", &Decoded(self.ints, INT_FIELDS))
+            .field("buff_status", &format_args!("{:#010x}", self.buff_status))
+            .field("int_ep_ctrl", &format_args!("{:#010x}", self.int_ep_ctrl))
+            .field(
+                "epx_buffer_control",
+                &Decoded(self.epx_buffer_control, BUFFER_CONTROL_FIELDS),
+            )
+            .field("addr_endp", &Decoded(self.addr_endp, ADDR_ENDP_FIELDS))
+            .finish()
+    }
+}
+
+impl defmt::Format for ControllerSnapshot {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "ControllerSnapshot {{ sie_status: {}, sie_ctrl: {}, inte: {}, ints: {}, buff_status: {=u32:#010x}, int_ep_ctrl: {=u32:#010x}, epx_buffer_control: {}, addr_endp: {} }}",
+            Decoded(self.sie_status, SIE_STATUS_FIELDS),
+            Decoded(self.sie_ctrl, SIE_CTRL_FIELDS),
+            Decoded(self.inte, INT_FIELDS),
+            Decoded(self.ints, INT_FIELDS),
+            self.buff_status,
+            self.int_ep_ctrl,
+            Decoded(self.epx_buffer_control, BUFFER_CONTROL_FIELDS),
+            Decoded(self.addr_endp, ADDR_ENDP_FIELDS),
+        );
+    }
+}
+
 /// Implementation of HostController for RP2040
 pub struct Rp2040HostController {
     shared: &'static UsbShared,
@@ -825,6 +1269,31 @@ impl Rp2040HostController {
         }
     }
 
+    /// Capture the controller registers, for diagnosing failures
+    ///
+    /// This only reads registers, so it's safe to call at any time --
+    /// but it's most useful straight after something has gone wrong.
+    /// The snapshot is logged automatically (at trace level) whenever
+    /// a control transfer fails.
+    pub fn debug_snapshot(&self) -> ControllerSnapshot {
+        ControllerSnapshot {
+            sie_status: self.regs.sie_status().read().bits(),
+            sie_ctrl: self.regs.sie_ctrl().read().bits(),
+            inte: self.regs.inte().read().bits(),
+            ints: self.regs.ints().read().bits(),
+            buff_status: self.regs.buff_status().read().bits(),
+            int_ep_ctrl: self.regs.int_ep_ctrl().read().bits(),
+            epx_buffer_control: self.dpram.ep_buffer_control(0).read().bits(),
+            addr_endp: self.regs.addr_endp().read().bits(),
+        }
+    }
+
+    /// Log the controller state alongside an error that's about to be returned
+    fn traced(&self, e: UsbError) -> UsbError {
+        defmt::trace!("{} {}", e, self.debug_snapshot());
+        e
+    }
+
     async fn alloc_pipe(&self, endpoint_type: EndpointType) -> Pipe {
         if endpoint_type == EndpointType::Control {
             Pipe::new(self.statics.control_pipes.alloc().await, 0)
@@ -893,7 +1362,7 @@ impl Rp2040HostController {
             .modify(|_, w| w.start_trans().set_bit());
 
         loop {
-            let f = Rp2040ControlEndpoint::new(&self.shared.pipe_wakers[0]);
+            let f = Rp2040ControlEndpoint::new(self.shared);
 
             let status = f.await;
 
@@ -1009,6 +1478,7 @@ impl Rp2040HostController {
                 .sie_status()
                 .write(|w| unsafe { w.bits(0xFF00_0000) });
             self.regs.buff_status().write(|w| unsafe { w.bits(0x3) });
+            self.shared.enables.take_control_buffers();
             self.regs.inte().modify(|_, w| {
                 if packets > 2 {
                     w.buff_status().set_bit();
@@ -1065,13 +1535,14 @@ impl Rp2040HostController {
                     .modify(|_, w| w.start_trans().set_bit());
             }
 
-            let f = Rp2040ControlEndpoint::new(&self.shared.pipe_wakers[0]);
+            let f = Rp2040ControlEndpoint::new(self.shared);
 
             let status = f.await;
 
             defmt::trace!("awaited {}", in_flight);
 
             self.regs.buff_status().write(|w| unsafe { w.bits(0x3) });
+            self.shared.enables.take_control_buffers();
 
             self.regs.inte().modify(|_, w| {
                 w.trans_complete()
@@ -1114,30 +1585,30 @@ impl Rp2040HostController {
             */
             if status.data_seq_error().bit() {
                 defmt::println!("DataSeqError");
-                return Err(UsbError::DataSeqError);
+                return Err(self.traced(UsbError::DataSeqError));
             }
             if status.stall_rec().bit() {
                 defmt::println!("Stall");
-                return Err(UsbError::Stall);
+                return Err(self.traced(UsbError::Stall));
             }
             // if status.nak_rec().bit() {
             //     return Err(UsbError::Nak);
             // }
             if status.rx_overflow().bit() {
                 defmt::println!("Overflow");
-                return Err(UsbError::Overflow);
+                return Err(self.traced(UsbError::Overflow));
             }
             if status.rx_timeout().bit() {
                 defmt::println!("Timeout");
-                return Err(UsbError::Timeout);
+                return Err(self.traced(UsbError::Timeout));
             }
             if status.bit_stuff_error().bit() {
                 defmt::println!("BitStuff");
-                return Err(UsbError::BitStuffError);
+                return Err(self.traced(UsbError::BitStuffError));
             }
             if status.crc_error().bit() {
                 defmt::println!("CRCError");
-                return Err(UsbError::CrcError);
+                return Err(self.traced(UsbError::CrcError));
             }
 
             if in_flight > 0 {
@@ -1187,7 +1658,11 @@ impl Rp2040HostController {
             true,
             ZeroLengthPacket::Never,
         ); // setup is PID0 so data starts with PID1
-        let mut depacketiser = InDepacketiser::new(size as u16, buf);
+        let mut buf = ReadBuffer::from(buf);
+        // SAFETY: the depacketiser only writes received bytes, never
+        // uninitialised ones
+        let mut depacketiser =
+            InDepacketiser::new(size as u16, unsafe { buf.unfilled_mut() });
 
         self.control_transfer_inner(
             address,
@@ -1245,52 +1720,16 @@ impl Rp2040HostController {
         interval_ms: u8,
     ) -> Rp2040InterruptPipe {
         let n = pipe.which();
-        let regs = unsafe { pac::USBCTRL_REGS::steal() };
-        let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
-        regs.host_addr_endp((n - 1) as usize).write(|w| unsafe {
-            w.address()
-                .bits(address)
-                .endpoint()
-                .bits(endpoint)
-                .intep_dir()
-                .clear_bit() // IN
-        });
-
-        dpram.ep_control((n * 2 - 2) as usize).write(|w| unsafe {
-            w.enable()
-                .set_bit()
-                .interrupt_per_buff()
-                .set_bit()
-                .endpoint_type()
-                .interrupt()
-                .buffer_address()
-                .bits(0x200 + (n as u16) * 128)
-                .host_poll_interval()
-                .bits(core::cmp::min(interval_ms as u16, 9))
-        });
-
-        dpram.ep_buffer_control((n * 2) as usize).write(|w| unsafe {
-            w.full_0()
-                .clear_bit()
-                .length_0()
-                .bits(max_packet_size)
-                .pid_0()
-                .clear_bit()
-                .last_0()
-                .set_bit()
-        });
-
-        cortex_m::asm::delay(12);
-
-        dpram
-            .ep_buffer_control((n * 2) as usize)
-            .modify(|_, w| w.available_0().set_bit());
+        self.shared.enables.arm(n);
+        program_interrupt_endpoint(n, address, endpoint, interval_ms);
+        arm_interrupt_buffer(n, max_packet_size, false);
 
         Rp2040InterruptPipe {
             shared: self.shared,
             pipe,
             max_packet_size,
             data_toggle: Cell::new(false),
+            stalled: Cell::new(false),
         }
     }
 
@@ -1425,6 +1864,17 @@ impl HostController for Rp2040HostController {
         setup: SetupPacket,
         data_phase: DataPhase<'a>,
     ) -> Result<usize, UsbError> {
+        let device_to_host = (setup.bmRequestType & 0x80) != 0;
+        match data_phase {
+            DataPhase::In(_) if !device_to_host => {
+                return Err(UsbError::Protocol("IN data phase, OUT request"));
+            }
+            DataPhase::Out(_) if device_to_host => {
+                return Err(UsbError::Protocol("OUT data phase, IN request"));
+            }
+            _ => {}
+        }
+
         let _pipe = self.alloc_pipe(EndpointType::Control).await;
 
         self.send_setup(address, &setup).await?;
@@ -1470,6 +1920,26 @@ impl HostController for Rp2040HostController {
         data: &mut [u8],
         transfer_type: TransferType,
         data_toggle: &Cell<bool>,
+    ) -> Result<usize, UsbError> {
+        self.bulk_in_transfer_uninit(
+            address,
+            endpoint,
+            packet_size,
+            &mut ReadBuffer::from(data),
+            transfer_type,
+            data_toggle,
+        )
+        .await
+    }
+
+    async fn bulk_in_transfer_uninit(
+        &self,
+        address: u8,
+        endpoint: u8,
+        packet_size: u16,
+        data: &mut ReadBuffer<'_>,
+        transfer_type: TransferType,
+        data_toggle: &Cell<bool>,
     ) -> Result<usize, UsbError> {
         let _pipe = self.alloc_pipe(EndpointType::Control).await;
         /*
@@ -1478,8 +1948,9 @@ impl HostController for Rp2040HostController {
                         _pipe.n,
                         data_toggle.get());
          */
+        let length = data.remaining() as u16;
         let mut packetiser = InPacketiser::new(
-            data.len() as u16,
+            length,
             packet_size as u16,
             data_toggle.get(),
             match transfer_type {
@@ -1487,8 +1958,10 @@ impl HostController for Rp2040HostController {
                 TransferType::VariableSize => ZeroLengthPacket::AsNeeded,
             },
         );
-        let length = data.len() as u16;
-        let mut depacketiser = InDepacketiser::new(length, data);
+        // SAFETY: the depacketiser only writes received bytes, never
+        // uninitialised ones
+        let mut depacketiser =
+            InDepacketiser::new(length, unsafe { data.unfilled_mut() });
 
         self.control_transfer_inner(
             address,
@@ -1513,7 +1986,11 @@ impl HostController for Rp2040HostController {
             data_toggle.get()
         );
          */
-        Ok(depacketiser.total())
+        let total = depacketiser.total();
+        // SAFETY: the depacketiser copied exactly `total` bytes,
+        // contiguously from the start of the unfilled part
+        unsafe { data.assume_filled(total) };
+        Ok(total)
     }
 
     async fn bulk_out_transfer(
@@ -1568,6 +2045,24 @@ impl HostController for Rp2040HostController {
         Ok(data.len())
     }
 
+    fn max_transfer_size(&self) -> usize {
+        // The packetisers count in u16
+        u16::MAX as usize
+    }
+
+    fn capabilities(&self) -> ControllerCapabilities {
+        ControllerCapabilities {
+            bulk: true,
+            interrupt_out: false,
+            isochronous: false,
+            // Full speed only, so bulk and interrupt packets are 64 bytes max
+            max_packet_size: 64,
+            // The whole BulkPipePool (which bulk transfers borrow from too)
+            interrupt_pipes: 15,
+            needs_polling: false,
+        }
+    }
+
     // The trait defines this with "-> impl Future"-style syntax, but the one
     // is just sugar for the other according to Clippy.
     async fn alloc_interrupt_pipe(
@@ -1605,7 +2100,7 @@ impl HostController for Rp2040HostController {
                 interval_ms,
             ))
         } else {
-            Err(UsbError::TooManyDevices)
+            Err(UsbError::AllPipesInUse)
         }
     }
 }
@@ -0,0 +1,83 @@
+//! Embassy's HAL for RP2040, `embassy-rp`, owns the peripherals and
+//! has its own PAC, and doesn't know about this host controller. The
+//! [`rp2040_embassy_host!`](crate::rp2040_embassy_host) macro bridges
+//! the gap: given `embassy-rp`'s USB peripheral (to prove that nothing
+//! else is using it), it declares the statics, binds `USBCTRL_IRQ`, and
+//! returns a ready [`Rp2040HostController`]:
+//!
+//! ```ignore
+//! #[embassy_executor::main]
+//! async fn main(_spawner: Spawner) {
+//!     let p = embassy_rp::init(Default::default());
+//!     let driver = cotton_usb_host::rp2040_embassy_host!(p.USB);
+//!     let hub_state = HubState::default();
+//!     let stack = UsbBus::new(driver);
+//!     let events = stack.device_events(&hub_state, |ms| {
+//!         embassy_time::Timer::after_millis(ms as u64)
+//!     });
+//!     // ...
+//! }
+//! ```
+//!
+//! Don't also bind `USBCTRL_IRQ` in `embassy_rp::bind_interrupts!`;
+//! the two would clash at link time.
+//!
+//! See `cross/rp2040-embassy` in the cotton repository for a
+//! complete working example.
+
+use super::{pac, Rp2040HostController, Rp2040Usb};
+
+/// Create the host controller, stealing its registers from the PAC
+///
+/// Used by [`rp2040_embassy_host!`](crate::rp2040_embassy_host),
+/// which is usually what you want instead.
+///
+/// # Safety
+///
+/// The caller must own the USB peripheral: nothing else may be using
+/// `USBCTRL_REGS` or `USBCTRL_DPRAM`.
+///
+/// # Panics
+///
+/// As [`Rp2040Usb::host_controller()`].
+pub unsafe fn host_controller(
+    usb: &'static Rp2040Usb,
+) -> Rp2040HostController {
+    // The USBCTRL reset bit shares a register with every other
+    // peripheral's, which embassy-rp may be modifying too
+    critical_section::with(|_| {
+        let mut resets = unsafe { pac::RESETS::steal() };
+        let regs = unsafe { pac::USBCTRL_REGS::steal() };
+        let dpram = unsafe { pac::USBCTRL_DPRAM::steal() };
+        usb.host_controller(&mut resets, regs, dpram)
+    })
+}
+
+/// Declare the statics, bind `USBCTRL_IRQ`, and create the host
+/// controller
+///
+/// Takes (and consumes) the USB peripheral, usually `p.USB` from
+/// `embassy_rp::init()`, and returns a
+/// [`Rp2040HostController`](crate::host::rp2040::Rp2040HostController).
+///
+/// As this defines the `USBCTRL_IRQ` handler, it can only appear once
+/// in a program; and, as the controller uses the same statics each
+/// time, it panics if it's run more than once.
+#[macro_export]
+macro_rules! rp2040_embassy_host {
+    ($usb:expr) => {{
+        let _usb = $usb;
+
+        static USB: $crate::host::rp2040::Rp2040Usb =
+            $crate::host::rp2040::Rp2040Usb::new();
+
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        extern "C" fn USBCTRL_IRQ() {
+            USB.on_irq();
+        }
+
+        // SAFETY: we have the USB peripheral, in `_usb`
+        unsafe { $crate::host::rp2040::embassy::host_controller(&USB) }
+    }};
+}
@@ -0,0 +1,71 @@
+//! In RTIC, interrupt handlers are tasks bound in the `#[rtic::app]`
+//! itself, so the glue can't bind `USBCTRL_IRQ` for you -- but with
+//! [`Rp2040Usb`](crate::host::rp2040::Rp2040Usb) declared as a plain
+//! `static`, the bound task is one line, and needs no shared resources:
+//!
+//! ```ignore
+//! #[rtic::app(device = rp_pico::hal::pac, dispatchers = [ADC_IRQ_FIFO])]
+//! mod app {
+//!     use cotton_usb_host::host::rp2040::Rp2040Usb;
+//!     use cotton_usb_host::usb_bus::{HubState, UsbBus};
+//!
+//!     static USB: Rp2040Usb = Rp2040Usb::new();
+//!
+//!     rp2040_timer_monotonic!(Mono);
+//!
+//!     #[local]
+//!     struct Local {
+//!         resets: pac::RESETS,
+//!         regs: Option<pac::USBCTRL_REGS>,
+//!         dpram: Option<pac::USBCTRL_DPRAM>,
+//!     }
+//!
+//!     // ...init() moves the peripherals into Local...
+//!
+//!     #[task(local = [resets, regs, dpram], priority = 2)]
+//!     async fn usb_task(cx: usb_task::Context) {
+//!         let driver = USB.host_controller(
+//!             cx.local.resets,
+//!             cx.local.regs.take().unwrap(),
+//!             cx.local.dpram.take().unwrap(),
+//!         );
+//!         let hub_state = HubState::default();
+//!         let stack = UsbBus::new(driver);
+//!         let events = stack.device_events(
+//!             &hub_state,
+//!             cotton_usb_host::rtic_delay_ms!(Mono),
+//!         );
+//!         // ...
+//!     }
+//!
+//!     #[task(binds = USBCTRL_IRQ, priority = 2)]
+//!     fn usb_interrupt(_: usb_interrupt::Context) {
+//!         USB.on_irq();
+//!     }
+//! }
+//! ```
+//!
+//! The host controller isn't `Send`, so it can't be created in `init`
+//! and handed to a task as a resource; create it in the task that
+//! uses it, as above.
+//!
+//! See `cross/rp2040-w5500-rtic2/src/bin/rp2040-usb-capacity.rs` in
+//! the cotton repository for a complete working example.
+
+/// A delay function, as needed by
+/// [`UsbBus::device_events()`](crate::usb_bus::UsbBus::device_events),
+/// from an RTIC monotonic
+///
+/// The calling crate must depend on `rtic-monotonics`.
+#[macro_export]
+macro_rules! rtic_delay_ms {
+    ($mono:ty) => {
+        |ms: usize| {
+            <$mono as rtic_monotonics::Monotonic>::delay(
+                <$mono as rtic_monotonics::Monotonic>::Duration::millis(
+                    ms as u64,
+                ),
+            )
+        }
+    };
+}
@@ -1,5 +1,6 @@
 use crate::wire::SetupPacket;
 use core::cell::Cell;
+use core::mem::MaybeUninit;
 use core::ops::Deref;
 use futures::Stream;
 
@@ -48,17 +49,28 @@ pub enum UsbError {
     /// next pipe to be available.
     AllPipesInUse,
     /// The device has reacted in a way contrary to the expected protocol
-    ProtocolError,
+    ///
+    /// The string says what, in particular, was wrong -- for instance,
+    /// "device descriptor too short" -- and is intended for logs
+    /// rather than for matching on.
+    Protocol(&'static str),
     /// The limit of attached USB devices has been reached
     TooManyDevices,
     /// [`UsbDevice::open_in_endpoint()`](crate::usb_bus::UsbDevice::open_in_endpoint) was called with a bogus endpoint number
     NoSuchEndpoint,
+    /// [`UsbBus::claim_interface()`](crate::usb_bus::UsbBus::claim_interface) was called for an interface that another driver already claimed
+    InterfaceClaimed,
+    /// The host controller can't do what was asked
+    ///
+    /// For instance, a driver for an audio device needs isochronous
+    /// endpoints; if [`HostController::capabilities()`] says they
+    /// aren't supported, it returns this error up front.
+    Unsupported,
 }
 
 /// Connection speed for a USB device
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum UsbSpeed {
     /// USB 1.1 Low Speed (1.5Mbits/s)
     Low1_5,
@@ -72,8 +84,7 @@ pub enum UsbSpeed {
 ///
 /// See [`HostController::device_detect`]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum DeviceStatus {
     /// A device is connected (and negotiated a certain speed)
     Present(UsbSpeed),
@@ -81,6 +92,52 @@ pub enum DeviceStatus {
     Absent,
 }
 
+/// What a particular host controller can and can't do
+///
+/// See [`HostController::capabilities()`]. Device drivers check these
+/// before starting work, so that (for instance) a driver for a
+/// mass-storage device fails cleanly, with [`UsbError::Unsupported`],
+/// on a host controller without bulk endpoints. The [`Default`] is
+/// what every host controller must support anyway: control, bulk, and
+/// interrupt IN endpoints, with 64-byte packets, and at least one
+/// interrupt pipe.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ControllerCapabilities {
+    /// Are bulk endpoints supported?
+    pub bulk: bool,
+    /// Are interrupt OUT endpoints supported? (Interrupt IN endpoints
+    /// always are.)
+    pub interrupt_out: bool,
+    /// Are isochronous endpoints supported?
+    pub isochronous: bool,
+    /// The largest packet, in bytes, on any endpoint
+    pub max_packet_size: u16,
+    /// How many interrupt pipes can be in use at once
+    ///
+    /// Beyond this many,
+    /// [`HostController::try_alloc_interrupt_pipe()`] fails with
+    /// [`UsbError::AllPipesInUse`]; see also
+    /// [`SharedInterruptScheduler`](crate::interrupt_scheduler::SharedInterruptScheduler).
+    pub interrupt_pipes: u8,
+    /// Does the host controller need polling, rather than waking
+    /// tasks from its interrupt handler?
+    pub needs_polling: bool,
+}
+
+impl Default for ControllerCapabilities {
+    fn default() -> Self {
+        Self {
+            bulk: true,
+            interrupt_out: false,
+            isochronous: false,
+            max_packet_size: 64,
+            interrupt_pipes: 1,
+            needs_polling: false,
+        }
+    }
+}
+
 /// The data phase of a USB control-endpoint transaction
 ///
 /// A transaction on a control endpoint involves one of:
@@ -125,6 +182,224 @@ impl DataPhase<'_> {
     }
 }
 
+/// A buffer for received data, which needn't be initialised beforehand
+///
+/// Zeroing a large buffer before every read, only for the host
+/// controller to overwrite it straight away, wastes time on a small
+/// microcontroller. A `ReadBuffer` wraps a buffer of
+/// [`MaybeUninit<u8>`], and keeps track of how much of it has been
+/// filled with received data, and how much of it is known to be
+/// initialised (like the standard library's `BorrowedBuf`). Its
+/// invariant is `filled <= init <= capacity`, where the first `init`
+/// bytes really are initialised.
+///
+/// Safe code can only grow the filled part over bytes which are known
+/// to be initialised, so a host controller which misreports how much
+/// it received can't expose uninitialised memory to the caller: that
+/// needs one of the `unsafe` methods, whose safety requirements say
+/// exactly what must have been written.
+pub struct ReadBuffer<'a> {
+    buf: &'a mut [MaybeUninit<u8>],
+    filled: usize,
+    init: usize,
+}
+
+impl<'a> ReadBuffer<'a> {
+    /// Wrap a buffer, none of which is (as far as we know) initialised
+    pub fn new(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self {
+            buf,
+            filled: 0,
+            init: 0,
+        }
+    }
+
+    /// The total size of the buffer
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The number of bytes filled so far
+    pub fn len(&self) -> usize {
+        self.filled
+    }
+
+    /// Has nothing been filled yet?
+    pub fn is_empty(&self) -> bool {
+        self.filled == 0
+    }
+
+    /// The number of bytes still to be filled
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.filled
+    }
+
+    /// The number of bytes, from the start of the buffer, known to be
+    /// initialised (never less than [`ReadBuffer::len()`])
+    pub fn init_len(&self) -> usize {
+        self.init
+    }
+
+    /// The bytes filled so far
+    pub fn filled(&self) -> &[u8] {
+        // SAFETY: filled <= init, and the first `init` bytes are
+        // initialised; MaybeUninit<u8> has the same layout as u8
+        unsafe {
+            core::slice::from_raw_parts(self.buf.as_ptr().cast(), self.filled)
+        }
+    }
+
+    /// The bytes filled so far (mutable)
+    pub fn filled_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for filled()
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.buf.as_mut_ptr().cast(),
+                self.filled,
+            )
+        }
+    }
+
+    /// Give up the buffer, returning the bytes filled so far
+    pub fn into_filled(self) -> &'a mut [u8] {
+        // SAFETY: as for filled(); the lifetime is that of the
+        // original buffer, which `self` no longer borrows
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.buf.as_mut_ptr().cast(),
+                self.filled,
+            )
+        }
+    }
+
+    /// Forget the bytes filled so far (they stay initialised)
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+
+    /// The unfilled part of the buffer, zeroed where necessary
+    ///
+    /// This is the slow-but-safe way to fill the buffer: receive into
+    /// the returned slice, then call [`ReadBuffer::add_filled()`].
+    /// Only the bytes not already known to be initialised are zeroed.
+    pub fn unfilled_zeroed(&mut self) -> &mut [u8] {
+        for b in &mut self.buf[self.init..] {
+            b.write(0);
+        }
+        self.init = self.buf.len();
+        let unfilled = &mut self.buf[self.filled..];
+        // SAFETY: the whole buffer has now been initialised
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                unfilled.as_mut_ptr().cast(),
+                unfilled.len(),
+            )
+        }
+    }
+
+    /// Mark the next `n` bytes as filled
+    ///
+    /// # Panics
+    ///
+    /// If those bytes aren't known to be initialised: for instance,
+    /// if `n` is bigger than the slice returned by
+    /// [`ReadBuffer::unfilled_zeroed()`].
+    pub fn add_filled(&mut self, n: usize) {
+        assert!(n <= self.init - self.filled);
+        self.filled += n;
+    }
+
+    /// Copy `data` onto the end of the filled part
+    ///
+    /// # Panics
+    ///
+    /// If there isn't room for it.
+    pub fn push_slice(&mut self, data: &[u8]) {
+        let dest = &mut self.buf[self.filled..(self.filled + data.len())];
+        for (d, s) in dest.iter_mut().zip(data) {
+            d.write(*s);
+        }
+        self.filled += data.len();
+        self.init = self.init.max(self.filled);
+    }
+
+    /// Another `ReadBuffer`, over at most `max` bytes of this one's
+    /// unfilled part
+    ///
+    /// This is for splitting up a large read into pieces: fill the
+    /// returned buffer, then pass its length to
+    /// [`ReadBuffer::assume_filled()`] on this one. (What this one
+    /// knows about which of those bytes are initialised is passed on
+    /// to the new one, but can't be passed back.)
+    pub fn unfilled(&mut self, max: usize) -> ReadBuffer<'_> {
+        let max = max.min(self.remaining());
+        ReadBuffer {
+            init: (self.init - self.filled).min(max),
+            buf: &mut self.buf[self.filled..(self.filled + max)],
+            filled: 0,
+        }
+    }
+
+    /// The unfilled part of the buffer, for receiving into directly
+    ///
+    /// # Safety
+    ///
+    /// Some of the returned bytes may be initialised, and the caller
+    /// mustn't de-initialise them (for instance, by writing
+    /// `MaybeUninit::uninit()` into them).
+    pub unsafe fn unfilled_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        &mut self.buf[self.filled..]
+    }
+
+    /// Mark the next `n` bytes as filled, without checking that
+    /// they're initialised
+    ///
+    /// # Safety
+    ///
+    /// The first `n` bytes of the unfilled part must have been
+    /// initialised (for instance, via [`ReadBuffer::unfilled_mut()`]).
+    ///
+    /// # Panics
+    ///
+    /// If there are fewer than `n` bytes remaining.
+    pub unsafe fn assume_filled(&mut self, n: usize) {
+        assert!(n <= self.remaining());
+        self.filled += n;
+        self.init = self.init.max(self.filled);
+    }
+
+    /// Mark the first `n` bytes as initialised, even though they
+    /// haven't been filled
+    ///
+    /// # Safety
+    ///
+    /// The first `n` bytes of the buffer must have been initialised.
+    pub unsafe fn set_init(&mut self, n: usize) {
+        self.init = self.init.max(n.min(self.buf.len()));
+    }
+}
+
+impl<'a> From<&'a mut [u8]> for ReadBuffer<'a> {
+    /// Wrap a buffer which is already initialised (so never needs
+    /// zeroing)
+    fn from(buf: &'a mut [u8]) -> Self {
+        let len = buf.len();
+        // SAFETY: MaybeUninit<u8> has the same layout as u8. Treating
+        // initialised bytes as maybe-uninitialised is always allowed,
+        // and the caller gets its buffer back (when the borrow ends)
+        // still initialised, because the only way to write
+        // uninitialised bytes into a ReadBuffer is through
+        // unfilled_mut(), whose safety requirements forbid it.
+        let buf =
+            unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        Self {
+            buf,
+            filled: 0,
+            init: len,
+        }
+    }
+}
+
 /// Is this a fixed-size transfer or variable-size transfer?
 ///
 /// According to USB 2.0 s5.3.2, the host must behave differently in
@@ -183,6 +458,104 @@ impl Deref for InterruptPacket {
     }
 }
 
+impl InterruptPacket {
+    /// How many bytes of the data are shown by Debug and defmt::Format
+    const PREVIEW_BYTES: usize = 16;
+
+    /// The start of the valid data, for printing
+    fn preview(&self) -> (&[u8], bool) {
+        let valid = &self.data[..(self.size as usize).min(self.data.len())];
+        if valid.len() > Self::PREVIEW_BYTES {
+            (&valid[..Self::PREVIEW_BYTES], true)
+        } else {
+            (valid, false)
+        }
+    }
+}
+
+impl core::fmt::Debug for InterruptPacket {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let (preview, more) = self.preview();
+        write!(
+            f,
+            "InterruptPacket {{ address: {}, endpoint: {}, size: {}, data: [",
+            self.address, self.endpoint, self.size
+        )?;
+        for (i, b) in preview.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        if more {
+            f.write_str(" ...")?;
+        }
+        f.write_str("] }")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for InterruptPacket {
+    fn format(&self, f: defmt::Formatter) {
+        let (preview, more) = self.preview();
+        defmt::write!(
+            f,
+            "InterruptPacket {{ address: {}, endpoint: {}, size: {}, data: {=[u8]:02x}{} }}",
+            self.address,
+            self.endpoint,
+            self.size,
+            preview,
+            if more { "..." } else { "" }
+        );
+    }
+}
+
+/// An interrupt pipe, as returned by [`HostController::alloc_interrupt_pipe`]
+///
+/// The pipe is a stream of the [`InterruptPacket`]s received. If the
+/// device stalls the endpoint (as, for instance, HID devices may do
+/// after a protocol error), the stream ends -- it returns `None` --
+/// and [`InterruptPipe::is_stalled()`] becomes true. The pipe then
+/// stays paused until the halt condition is cleared on the device
+/// (USB 2.0 section 9.4.5) and [`InterruptPipe::resume()`] is called,
+/// after which the stream continues; see
+/// [`UsbBus::clear_interrupt_halt()`](crate::usb_bus::UsbBus::clear_interrupt_halt),
+/// which does both.
+pub trait InterruptPipe: Stream<Item = InterruptPacket> + Unpin {
+    /// Has the device stalled the endpoint?
+    fn is_stalled(&self) -> bool;
+
+    /// Start polling the endpoint again after a stall
+    ///
+    /// The data toggle is reset to DATA0 (USB 2.0 section 5.8.5), so
+    /// call this only once the halt has been cleared on the device.
+    fn resume(&mut self);
+
+    /// Point the pipe at a different endpoint, perhaps on a different device
+    ///
+    /// This is how a
+    /// [`SharedInterruptScheduler`](crate::interrupt_scheduler::SharedInterruptScheduler)
+    /// time-multiplexes one pipe across several endpoints. Any packet
+    /// received, but not yet collected, from the previous endpoint is
+    /// lost, as is any stall; `data_toggle` is the DATA0/DATA1 state
+    /// (false for DATA0) to expect next from the new endpoint.
+    fn retarget(
+        &mut self,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+        data_toggle: bool,
+    );
+
+    /// The DATA0/DATA1 state (false for DATA0) expected next from the
+    /// endpoint
+    ///
+    /// Store this before retargeting the pipe, and pass it back in when
+    /// retargeting it to this endpoint again.
+    fn data_toggle(&self) -> bool;
+}
+
 /// Encapsulating a particular USB hardware host controller
 ///
 /// This trait can be implemented for different USB hardware (e.g.,
@@ -190,7 +563,7 @@ impl Deref for InterruptPacket {
 /// particularly [`UsbBus`](crate::usb_bus::UsbBus) -- to be hardware-agnostic.
 pub trait HostController {
     /// The concrete type returned by [`HostController::alloc_interrupt_pipe`]
-    type InterruptPipe: Stream<Item = InterruptPacket> + Unpin;
+    type InterruptPipe: InterruptPipe;
     /// The concrete type returned by [`HostController::device_detect`]
     type DeviceDetect: Stream<Item = DeviceStatus>;
 
@@ -237,6 +610,46 @@ pub trait HostController {
         data_toggle: &Cell<bool>,
     ) -> impl core::future::Future<Output = Result<usize, UsbError>>;
 
+    /// Perform a USB bulk in transfer into a buffer which needn't be
+    /// initialised
+    ///
+    /// As [`HostController::bulk_in_transfer`], but the transfer size
+    /// is `data.remaining()`, and the data received is added to the
+    /// filled part of `data`. Returns the number of bytes received,
+    /// which is always the amount by which `data.len()` grew.
+    ///
+    /// The default implementation zeroes the buffer (where necessary)
+    /// and calls [`HostController::bulk_in_transfer`]; host controllers
+    /// which can receive straight into uninitialised memory should
+    /// override it.
+    fn bulk_in_transfer_uninit(
+        &self,
+        address: u8,
+        endpoint: u8,
+        packet_size: u16,
+        data: &mut ReadBuffer<'_>,
+        transfer_type: TransferType,
+        data_toggle: &Cell<bool>,
+    ) -> impl core::future::Future<Output = Result<usize, UsbError>> {
+        async move {
+            let buf = data.unfilled_zeroed();
+            let len = buf.len();
+            let n = self
+                .bulk_in_transfer(
+                    address,
+                    endpoint,
+                    packet_size,
+                    buf,
+                    transfer_type,
+                    data_toggle,
+                )
+                .await?
+                .min(len);
+            data.add_filled(n);
+            Ok(n)
+        }
+    }
+
     /// Perform a USB bulk out transfer
     ///
     /// A bulk-capable pipe is allocated for the duration of the
@@ -255,6 +668,26 @@ pub trait HostController {
         data_toggle: &Cell<bool>,
     ) -> impl core::future::Future<Output = Result<usize, UsbError>>;
 
+    /// The largest bulk transfer, in bytes, that can be passed to
+    /// [`HostController::bulk_in_transfer`] or
+    /// [`HostController::bulk_out_transfer`] in one go
+    ///
+    /// Larger transfers are split up by
+    /// [`BulkIn::read_exact()`](crate::usb_bus::BulkIn::read_exact) and
+    /// [`BulkOut::write_all()`](crate::usb_bus::BulkOut::write_all).
+    fn max_transfer_size(&self) -> usize {
+        usize::MAX
+    }
+
+    /// What this host controller can and can't do
+    ///
+    /// The default implementation returns
+    /// [`ControllerCapabilities::default()`], which is no more than
+    /// every host controller has to support anyway.
+    fn capabilities(&self) -> ControllerCapabilities {
+        ControllerCapabilities::default()
+    }
+
     /// Allocate an interrupt pipe
     ///
     /// The pipe is owned by the returned object, and remains
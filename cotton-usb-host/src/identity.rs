@@ -0,0 +1,192 @@
+use crate::quirks::Quirks;
+
+/// What a SCSI probe found out about a mass-storage device
+///
+/// The same information as cotton-scsi's `ProbeHints`, which the
+/// cotton-usb-host-msc crate converts to and from.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ScsiCapabilities {
+    /// Whether the device supports the 16-byte READ/WRITE/READ
+    /// CAPACITY commands
+    pub supports_16: bool,
+    /// The device's own transfer limit (from the Block Limits Page), if
+    /// it reports one
+    pub max_transfer_blocks: Option<u32>,
+}
+
+/// Everything learned about a device that's worth remembering
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Capabilities {
+    /// The workarounds the device needs
+    pub quirks: Quirks,
+    /// The results of probing it as a SCSI device, if it is one and
+    /// has been probed
+    pub scsi: Option<ScsiCapabilities>,
+}
+
+/// A record identifying one particular device, and what's known about it
+///
+/// Produced by [`UsbBus::device_identity()`], completed by higher
+/// layers (such as the cotton-usb-host-msc crate's SCSI probe), and
+/// reported via the callback set with [`UsbBus::set_on_identity()`].
+/// The crate doesn't store these records anywhere: an application
+/// which power-cycles frequently can save them (see
+/// [`DeviceIdentity::to_bytes()`]) in its own flash, and hand them
+/// back at boot using [`UsbBus::preload()`], so that known devices
+/// get their quirks straight away and needn't be probed again.
+///
+/// [`UsbBus::device_identity()`]: crate::usb_bus::UsbBus::device_identity
+/// [`UsbBus::set_on_identity()`]: crate::usb_bus::UsbBus::set_on_identity
+/// [`UsbBus::preload()`]: crate::usb_bus::UsbBus::preload
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct DeviceIdentity {
+    /// Vendor ID
+    pub vid: u16,
+    /// Product ID
+    pub pid: u16,
+    /// A hash of the device's serial number (see [`serial_hash()`]), or
+    /// zero if it hasn't got one
+    pub serial_hash: u32,
+    /// What's known about the device
+    pub capabilities: Capabilities,
+}
+
+/// Called with each completed [`DeviceIdentity`]
+pub type OnIdentity = fn(&DeviceIdentity);
+
+const VERSION: u8 = 1;
+
+const NO_VPD: u8 = 1 << 0;
+const NO_RSOC: u8 = 1 << 1;
+const FORCE_SINGLE_LUN: u8 = 1 << 2;
+const MAX_TRANSFER: u8 = 1 << 3;
+const SCSI: u8 = 1 << 4;
+const SUPPORTS_16: u8 = 1 << 5;
+const SCSI_MAX_TRANSFER: u8 = 1 << 6;
+const RESERVED: u8 = 1 << 7;
+
+impl DeviceIdentity {
+    /// The size of the serialised form, see [`DeviceIdentity::to_bytes()`]
+    pub const SIZE: usize = 20;
+
+    /// Is this the record for the device with these IDs?
+    pub fn matches(&self, vid: u16, pid: u16, serial_hash: u32) -> bool {
+        self.vid == vid && self.pid == pid && self.serial_hash == serial_hash
+    }
+
+    /// Serialise the record, for storing
+    ///
+    /// The format is compact, little-endian, and starts with a version
+    /// byte, so that records written by one version of this crate are
+    /// either understood, or cleanly rejected, by later ones.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let quirks = &self.capabilities.quirks;
+        let scsi = self.capabilities.scsi;
+        let mut flags = 0;
+        for (set, flag) in [
+            (quirks.no_vpd, NO_VPD),
+            (quirks.no_rsoc, NO_RSOC),
+            (quirks.force_single_lun, FORCE_SINGLE_LUN),
+            (quirks.max_transfer_blocks.is_some(), MAX_TRANSFER),
+            (scsi.is_some(), SCSI),
+            (scsi.is_some_and(|s| s.supports_16), SUPPORTS_16),
+            (
+                scsi.is_some_and(|s| s.max_transfer_blocks.is_some()),
+                SCSI_MAX_TRANSFER,
+            ),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0] = VERSION;
+        bytes[1..3].copy_from_slice(&self.vid.to_le_bytes());
+        bytes[3..5].copy_from_slice(&self.pid.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.serial_hash.to_le_bytes());
+        bytes[9] = flags;
+        bytes[10..12]
+            .copy_from_slice(&quirks.delay_after_set_address_ms.to_le_bytes());
+        bytes[12..16].copy_from_slice(
+            &quirks.max_transfer_blocks.unwrap_or(0).to_le_bytes(),
+        );
+        bytes[16..20].copy_from_slice(
+            &scsi
+                .and_then(|s| s.max_transfer_blocks)
+                .unwrap_or(0)
+                .to_le_bytes(),
+        );
+        bytes
+    }
+
+    /// Deserialise a record written by [`DeviceIdentity::to_bytes()`]
+    ///
+    /// Returns `None` if `bytes` is too short, or isn't a record this
+    /// version of the crate understands.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE || bytes[0] != VERSION {
+            return None;
+        }
+        let flags = bytes[9];
+        if (flags & RESERVED) != 0 {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| {
+            u32::from_le_bytes([
+                bytes[i],
+                bytes[i + 1],
+                bytes[i + 2],
+                bytes[i + 3],
+            ])
+        };
+        let has = |flag: u8| (flags & flag) != 0;
+
+        let quirks = Quirks {
+            no_vpd: has(NO_VPD),
+            no_rsoc: has(NO_RSOC),
+            force_single_lun: has(FORCE_SINGLE_LUN),
+            delay_after_set_address_ms: u16_at(10),
+            max_transfer_blocks: has(MAX_TRANSFER).then(|| u32_at(12)),
+        };
+        let scsi = has(SCSI).then(|| ScsiCapabilities {
+            supports_16: has(SUPPORTS_16),
+            max_transfer_blocks: has(SCSI_MAX_TRANSFER).then(|| u32_at(16)),
+        });
+        Some(Self {
+            vid: u16_at(1),
+            pid: u16_at(3),
+            serial_hash: u32_at(5),
+            capabilities: Capabilities { quirks, scsi },
+        })
+    }
+}
+
+/// Hash a serial-number string descriptor (USB 2.0 section 9.6.7)
+///
+/// The whole descriptor is passed in, header and all; the hash covers
+/// the string itself (32-bit FNV-1a over the UTF-16LE bytes). It's
+/// never zero, as zero means "no serial number".
+pub fn serial_hash(descriptor: &[u8]) -> u32 {
+    let len = descriptor
+        .first()
+        .map_or(0, |n| *n as usize)
+        .min(descriptor.len());
+    let mut hash = 0x811C_9DC5_u32;
+    for b in descriptor.get(2..len).unwrap_or_default() {
+        hash = (hash ^ *b as u32).wrapping_mul(0x0100_0193);
+    }
+    if hash == 0 {
+        1
+    } else {
+        hash
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/identity.rs"]
+mod tests;
@@ -0,0 +1,320 @@
+use crate::host_controller::{
+    HostController, InterruptPacket, InterruptPipe, UsbError,
+};
+use crate::usb_bus::UsbBus;
+use core::cell::{Cell, RefCell};
+use core::pin::pin;
+use futures::future::{self, Either};
+use futures::{Future, FutureExt, Stream, StreamExt};
+
+/// How long to leave the pipe pointing at each endpoint, in milliseconds
+///
+/// While it's there, the hardware polls the endpoint every frame, so
+/// this is enough for at least one poll.
+const DWELL_MS: u32 = 2;
+
+/// How long to wait before looking again, if no endpoints are added
+const IDLE_MS: u32 = 10;
+
+/// One endpoint sharing the pipe
+#[derive(Copy, Clone)]
+struct SharedEndpoint {
+    address: u8,
+    endpoint: u8,
+    max_packet_size: u16,
+    interval_ms: u8,
+    data_toggle: bool,
+    /// When (by the scheduler's clock) the endpoint is next due a poll
+    due_ms: u32,
+}
+
+impl SharedEndpoint {
+    /// How long ago the endpoint fell due, or `None` if it isn't due yet
+    fn overdue(&self, now: u32) -> Option<u32> {
+        let late = now.wrapping_sub(self.due_ms);
+        (late as i32 >= 0).then_some(late)
+    }
+}
+
+/// Time-multiplexing one interrupt pipe across many interrupt endpoints
+///
+/// Host controllers have only a few interrupt-capable pipes (the
+/// RP2040 has 15), so with a big hub full of HID devices,
+/// [`HostController::try_alloc_interrupt_pipe()`] can return
+/// [`UsbError::AllPipesInUse`] (and
+/// [`UsbBus::interrupt_endpoint_in()`] can wait forever). The
+/// scheduler is the overflow strategy: endpoints for which no pipe
+/// was available are added to it, and it polls all of them using just
+/// one pipe, by retargeting it (see [`InterruptPipe::retarget()`]) at
+/// each endpoint in turn.
+///
+/// ```no_run
+/// # use cotton_usb_host::host_controller::{HostController, UsbError};
+/// # use cotton_usb_host::interrupt_scheduler::SharedInterruptScheduler;
+/// # use cotton_usb_host::usb_bus::UsbBus;
+/// # async fn foo<HC: HostController>(bus: &UsbBus<HC>) {
+/// let shared = SharedInterruptScheduler::<32>::new();
+/// match bus.try_interrupt_pipe_in(5, 1, 8, 10) {
+///     Ok(pipe) => { /* use the pipe directly */ }
+///     Err(UsbError::AllPipesInUse) => shared.add(5, 1, 8, 10).unwrap(),
+///     Err(_) => { /* ... */ }
+/// }
+/// # }
+/// ```
+///
+/// This isn't free: retargeting the pipe costs time, and an endpoint
+/// only gets polled when it's its turn, so its packets may arrive
+/// later than its interval would suggest. So nothing uses the
+/// scheduler unless the application sets one up.
+///
+/// Each endpoint falls due for a poll once its interval has elapsed
+/// since its last turn; the endpoint which has been due for longest
+/// goes next, with ties broken round-robin, so no endpoint is starved
+/// however many others there are. Each endpoint's DATA0/DATA1 toggle
+/// is stored while it's not using the pipe.
+///
+/// The scheduler has no clock of its own: it counts the time it has
+/// spent waiting, using the "delay" function passed to
+/// [`SharedInterruptScheduler::packets()`], so its idea of when each
+/// endpoint is due is only approximate.
+pub struct SharedInterruptScheduler<const N: usize> {
+    endpoints: RefCell<[Option<SharedEndpoint>; N]>,
+    /// Where the round-robin search for the next endpoint starts
+    next: Cell<usize>,
+    now_ms: Cell<u32>,
+}
+
+impl<const N: usize> Default for SharedInterruptScheduler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SharedInterruptScheduler<N> {
+    /// Create a new scheduler, with room for up to N endpoints
+    pub const fn new() -> Self {
+        Self {
+            endpoints: RefCell::new([None; N]),
+            next: Cell::new(0),
+            now_ms: Cell::new(0),
+        }
+    }
+
+    /// Add an interrupt IN endpoint to the schedule
+    ///
+    /// Its packets are returned from the stream returned by
+    /// [`SharedInterruptScheduler::packets()`]. It's due for a poll
+    /// straight away, starting at DATA0.
+    ///
+    /// Returns `Err(UsbError::TooManyDevices)` if there are already N
+    /// endpoints in the schedule.
+    ///
+    /// # Parameters
+    ///  - address: USB device address (1-127)
+    ///  - endpoint: endpoint number (1-15)
+    ///  - max_packet_size: maximum expected packet size, in bytes
+    ///  - interval_ms: polling interval, in milliseconds
+    pub fn add(
+        &self,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<(), UsbError> {
+        let mut endpoints = self.endpoints.borrow_mut();
+        let slot = endpoints
+            .iter_mut()
+            .find(|e| e.is_none())
+            .ok_or(UsbError::TooManyDevices)?;
+        *slot = Some(SharedEndpoint {
+            address,
+            endpoint,
+            max_packet_size,
+            interval_ms,
+            data_toggle: false,
+            due_ms: self.now_ms.get(),
+        });
+        Ok(())
+    }
+
+    /// Remove an endpoint from the schedule
+    pub fn remove(&self, address: u8, endpoint: u8) {
+        for e in self.endpoints.borrow_mut().iter_mut() {
+            if e.is_some_and(|e| {
+                e.address == address && e.endpoint == endpoint
+            }) {
+                *e = None;
+            }
+        }
+    }
+
+    /// Remove all of a device's endpoints from the schedule
+    ///
+    /// Call this when the device is disconnected.
+    pub fn remove_device(&self, address: u8) {
+        for e in self.endpoints.borrow_mut().iter_mut() {
+            if e.is_some_and(|e| e.address == address) {
+                *e = None;
+            }
+        }
+    }
+
+    /// Is this endpoint in the schedule?
+    ///
+    /// An endpoint which the device stalls is taken out of the
+    /// schedule; once the halt is cleared (USB 2.0 section 9.4.5), it
+    /// can be added again.
+    pub fn contains(&self, address: u8, endpoint: u8) -> bool {
+        self.endpoints
+            .borrow()
+            .iter()
+            .flatten()
+            .any(|e| e.address == address && e.endpoint == endpoint)
+    }
+
+    fn advance(&self, ms: u32) {
+        self.now_ms.set(self.now_ms.get().wrapping_add(ms));
+    }
+
+    /// Pick the endpoint to poll next, if any are due
+    fn choose(&self) -> Option<(usize, SharedEndpoint)> {
+        let now = self.now_ms.get();
+        let endpoints = self.endpoints.borrow();
+        let mut best: Option<(usize, SharedEndpoint, u32)> = None;
+        for k in 0..N {
+            let i = (self.next.get() + k) % N;
+            if let Some(e) = endpoints[i] {
+                if let Some(late) = e.overdue(now) {
+                    if !matches!(best, Some((_, _, b)) if b >= late) {
+                        best = Some((i, e, late));
+                    }
+                }
+            }
+        }
+        let (i, e, _) = best?;
+        self.next.set((i + 1) % N);
+        Some((i, e))
+    }
+
+    /// How long until the next endpoint falls due (if there are any)
+    fn until_due(&self) -> Option<u32> {
+        let now = self.now_ms.get();
+        self.endpoints
+            .borrow()
+            .iter()
+            .flatten()
+            .map(|e| e.due_ms.wrapping_sub(now))
+            .min()
+    }
+
+    /// Record the end of an endpoint's turn (which started at `now_ms`)
+    ///
+    /// The endpoint might have been removed, or even replaced, in the
+    /// meantime.
+    fn finish(
+        &self,
+        i: usize,
+        turn: &SharedEndpoint,
+        data_toggle: bool,
+        stalled: bool,
+    ) {
+        let mut endpoints = self.endpoints.borrow_mut();
+        let slot = &mut endpoints[i];
+        if !slot.is_some_and(|e| {
+            e.address == turn.address && e.endpoint == turn.endpoint
+        }) {
+            return;
+        }
+        if stalled {
+            *slot = None;
+        } else if let Some(e) = slot {
+            e.data_toggle = data_toggle;
+            e.due_ms = self
+                .now_ms
+                .get()
+                .wrapping_add(turn.interval_ms.max(1) as u32);
+        }
+    }
+
+    /// Obtain a stream of the packets received from all the endpoints
+    ///
+    /// The endpoints are only polled while this stream is being
+    /// polled. It allocates one interrupt pipe, using
+    /// [`UsbBus::interrupt_pipe_in()`], when the first endpoint is
+    /// added, and keeps it until the stream is dropped.
+    ///
+    /// The "delay" function is used to wait while the pipe is pointing
+    /// at an endpoint, and when no endpoints are due; see
+    /// [`UsbBus::device_events()`].
+    pub fn packets<
+        'a,
+        HC: HostController,
+        D: Future<Output = ()>,
+        F: Fn(usize) -> D + 'static + Clone,
+    >(
+        &'a self,
+        bus: &'a UsbBus<HC>,
+        delay_ms: F,
+    ) -> impl Stream<Item = InterruptPacket> + 'a {
+        futures::stream::unfold(None, move |mut pipe| {
+            let delay_ms = delay_ms.clone();
+            async move {
+                loop {
+                    let Some((i, turn)) = self.choose() else {
+                        let wait = self.until_due().unwrap_or(IDLE_MS);
+                        delay_ms(wait as usize).await;
+                        self.advance(wait);
+                        continue;
+                    };
+
+                    let p: &mut HC::InterruptPipe = match &mut pipe {
+                        Some(p) => p,
+                        None => pipe.insert(
+                            bus.interrupt_pipe_in(
+                                turn.address,
+                                turn.endpoint,
+                                turn.max_packet_size,
+                                1,
+                            )
+                            .await,
+                        ),
+                    };
+                    p.retarget(
+                        turn.address,
+                        turn.endpoint,
+                        turn.max_packet_size,
+                        1,
+                        turn.data_toggle,
+                    );
+
+                    // Outer None: no packet this time (the device
+                    // NAKed); inner None: the device stalled
+                    let result = match future::select(
+                        p.next(),
+                        pin!(delay_ms(DWELL_MS as usize)),
+                    )
+                    .await
+                    {
+                        Either::Left((r, _)) => Some(r),
+                        Either::Right(((), next)) => next.now_or_never(),
+                    };
+                    self.finish(
+                        i,
+                        &turn,
+                        p.data_toggle(),
+                        matches!(result, Some(None)),
+                    );
+                    self.advance(DWELL_MS);
+
+                    if let Some(Some(packet)) = result {
+                        return Some((packet, pipe));
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/interrupt_scheduler.rs"]
+mod tests;
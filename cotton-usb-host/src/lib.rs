@@ -9,10 +9,17 @@
 /// Encapsulates waiting for any one of N resources to become available
 pub mod async_pool;
 
+/// Measuring performance on real hardware
+#[cfg(feature = "bench")]
+pub mod bench;
+
 /// A compact representation of a set of 32 booleans
 pub mod bitset;
 mod debug;
 
+/// Snapshots of per-device state, for logging and debugging
+pub mod diagnostics;
+
 /// Example device-drivers for USB devices
 pub mod device;
 
@@ -22,12 +29,24 @@ pub mod host;
 /// Abstraction over host-controller drivers
 pub mod host_controller;
 
+/// Sharing one interrupt pipe between many endpoints
+pub mod interrupt_scheduler;
+
+/// Remembering devices, and what's been learned about them, across reboots
+pub mod identity;
+
+/// Workarounds for known-bad devices
+pub mod quirks;
+
 /// Encapsulating the layout of a USB bus
 pub mod topology;
 
 /// Main encapsulation of a USB bus and all its devices
 pub mod usb_bus;
 
+/// A whole USB host: bus, hubs, and device drivers together
+pub mod usb_host;
+
 /// Data representations straight from the USB standards
 pub mod wire;
 
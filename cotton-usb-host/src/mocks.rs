@@ -1,6 +1,6 @@
 use crate::host_controller::{
-    DataPhase, DeviceStatus, HostController, InterruptPacket, TransferType,
-    UsbError,
+    ControllerCapabilities, DataPhase, DeviceStatus, HostController,
+    InterruptPacket, InterruptPipe, TransferType, UsbError,
 };
 use crate::wire::SetupPacket;
 use futures::Future;
@@ -21,6 +21,20 @@ mock! {
             cx: &mut Context<'a>
         ) -> Poll<Option<<Self as Stream>::Item>>;
     }
+
+    impl InterruptPipe for InterruptPipe {
+        fn is_stalled(&self) -> bool;
+        fn resume(&mut self);
+        fn retarget(
+            &mut self,
+            address: u8,
+            endpoint: u8,
+            max_packet_size: u16,
+            interval_ms: u8,
+            data_toggle: bool,
+        );
+        fn data_toggle(&self) -> bool;
+    }
 }
 
 mock! {
@@ -106,12 +120,26 @@ pub struct MockHostController {
     ///
     /// See src/tests/usb_bus.rs for widespread use of this facility.
     pub inner: MockHostControllerInner,
+
+    /// Value returned from [`HostController::max_transfer_size()`]
+    ///
+    /// Not forwarded to the inner mock, so that tests needn't set an
+    /// expectation for it; defaults to `usize::MAX`.
+    pub max_transfer_size: usize,
+
+    /// Value returned from [`HostController::capabilities()`]
+    ///
+    /// Not forwarded to the inner mock either; tests can change it to
+    /// exercise drivers' handling of less capable host controllers.
+    pub capabilities: ControllerCapabilities,
 }
 
 impl Default for MockHostController {
     fn default() -> Self {
         Self {
             inner: MockHostControllerInner::new(),
+            max_transfer_size: usize::MAX,
+            capabilities: ControllerCapabilities::default(),
         }
     }
 }
@@ -177,6 +205,14 @@ impl HostController for MockHostController {
         )
     }
 
+    fn max_transfer_size(&self) -> usize {
+        self.max_transfer_size
+    }
+
+    fn capabilities(&self) -> ControllerCapabilities {
+        self.capabilities
+    }
+
     fn alloc_interrupt_pipe(
         &self,
         address: u8,
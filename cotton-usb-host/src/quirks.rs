@@ -0,0 +1,195 @@
+/// One workaround for a misbehaving device
+///
+/// See [`QuirkEntry`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Quirk {
+    /// Never ask for SCSI vital product data pages (INQUIRY with the
+    /// EVPD bit set), which some devices hang on
+    NoVpd,
+    /// Never issue SCSI REPORT SUPPORTED OPERATION CODES, which some
+    /// devices stall rather than reject
+    NoRsoc,
+    /// Assume the device has a single logical unit, without asking it
+    /// with GET MAX LUN
+    ForceSingleLun,
+    /// Wait this many milliseconds after SET_ADDRESS before talking to
+    /// the device at its new address (USB 2.0 section 9.2.6.3 allows
+    /// only 2ms)
+    DelayAfterSetAddressMs(u16),
+    /// Never transfer more than this many blocks in one SCSI command
+    MaxTransferBlocks(u32),
+}
+
+/// All the workarounds which apply to a particular device
+///
+/// Found, during enumeration, by looking up the device's VID, PID and
+/// `bcdDevice` in [`BUILTIN_QUIRKS`] and in any table set with
+/// [`UsbBus::set_quirks()`](crate::usb_bus::UsbBus::set_quirks); see
+/// [`UsbDevice::quirks()`](crate::usb_bus::UsbDevice::quirks).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    /// See [`Quirk::NoVpd`]
+    pub no_vpd: bool,
+    /// See [`Quirk::NoRsoc`]
+    pub no_rsoc: bool,
+    /// See [`Quirk::ForceSingleLun`]
+    pub force_single_lun: bool,
+    /// See [`Quirk::DelayAfterSetAddressMs`] (zero if none)
+    pub delay_after_set_address_ms: u16,
+    /// See [`Quirk::MaxTransferBlocks`]
+    pub max_transfer_blocks: Option<u32>,
+}
+
+impl Quirks {
+    /// No workarounds at all: a well-behaved device
+    pub const NONE: Self = Self {
+        no_vpd: false,
+        no_rsoc: false,
+        force_single_lun: false,
+        delay_after_set_address_ms: 0,
+        max_transfer_blocks: None,
+    };
+
+    /// Add one more workaround
+    ///
+    /// Where two quirks give different values, the more cautious one
+    /// wins: the longer delay, or the smaller transfer.
+    pub fn add(&mut self, quirk: Quirk) {
+        match quirk {
+            Quirk::NoVpd => self.no_vpd = true,
+            Quirk::NoRsoc => self.no_rsoc = true,
+            Quirk::ForceSingleLun => self.force_single_lun = true,
+            Quirk::DelayAfterSetAddressMs(ms) => {
+                self.delay_after_set_address_ms =
+                    self.delay_after_set_address_ms.max(ms)
+            }
+            Quirk::MaxTransferBlocks(n) => {
+                self.max_transfer_blocks =
+                    Some(self.max_transfer_blocks.map_or(n, |m| m.min(n)))
+            }
+        }
+    }
+
+    /// Add all of another set of workarounds
+    ///
+    /// As with [`Quirks::add()`], the more cautious values win.
+    pub fn merge(&mut self, other: &Quirks) {
+        for (set, quirk) in [
+            (other.no_vpd, Quirk::NoVpd),
+            (other.no_rsoc, Quirk::NoRsoc),
+            (other.force_single_lun, Quirk::ForceSingleLun),
+            (
+                other.delay_after_set_address_ms != 0,
+                Quirk::DelayAfterSetAddressMs(
+                    other.delay_after_set_address_ms,
+                ),
+            ),
+        ] {
+            if set {
+                self.add(quirk);
+            }
+        }
+        if let Some(n) = other.max_transfer_blocks {
+            self.add(Quirk::MaxTransferBlocks(n));
+        }
+    }
+
+    /// Are there no workarounds at all?
+    pub fn is_none(&self) -> bool {
+        *self == Self::NONE
+    }
+}
+
+/// Which devices a set of quirks applies to
+///
+/// ```
+/// # use cotton_usb_host::quirks::{Quirk, QuirkEntry};
+/// static MY_QUIRKS: &[QuirkEntry] = &[
+///     // All firmware versions
+///     QuirkEntry::new(0x1234, 0x5678, &[Quirk::NoVpd]),
+///     // Only firmware versions 1.00 to 1.09
+///     QuirkEntry::new(0x1234, 0x5679, &[Quirk::DelayAfterSetAddressMs(20)])
+///         .bcd_device(0x0100, 0x0109),
+/// ];
+/// ```
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct QuirkEntry {
+    /// Vendor ID
+    pub vid: u16,
+    /// Product ID
+    pub pid: u16,
+    /// Lowest matching `bcdDevice` (device release number), inclusive
+    pub bcd_device_min: u16,
+    /// Highest matching `bcdDevice`, inclusive
+    pub bcd_device_max: u16,
+    /// The workarounds to apply
+    pub quirks: &'static [Quirk],
+}
+
+impl QuirkEntry {
+    /// Quirks for every release of a particular VID/PID
+    pub const fn new(vid: u16, pid: u16, quirks: &'static [Quirk]) -> Self {
+        Self {
+            vid,
+            pid,
+            bcd_device_min: 0,
+            bcd_device_max: 0xFFFF,
+            quirks,
+        }
+    }
+
+    /// Restrict the entry to a range of `bcdDevice` values (inclusive)
+    pub const fn bcd_device(self, min: u16, max: u16) -> Self {
+        Self {
+            bcd_device_min: min,
+            bcd_device_max: max,
+            ..self
+        }
+    }
+
+    /// Does this entry apply to the given device?
+    pub fn matches(&self, vid: u16, pid: u16, bcd_device: u16) -> bool {
+        self.vid == vid
+            && self.pid == pid
+            && (self.bcd_device_min..=self.bcd_device_max)
+                .contains(&bcd_device)
+    }
+}
+
+/// Devices known to need workarounds
+///
+/// Deliberately short: entries are only added for devices which
+/// have actually been seen to misbehave.
+pub static BUILTIN_QUIRKS: &[QuirkEntry] = &[
+    // JMicron JM20337 USB-to-SATA bridge: supports neither VPD pages
+    // nor RSOC (see the table in cotton-scsi's ScsiDevice docs)
+    QuirkEntry::new(0x152D, 0x2338, &[Quirk::NoVpd, Quirk::NoRsoc]),
+];
+
+/// Find all the workarounds for a device
+///
+/// Every matching entry, in either `extra` or [`BUILTIN_QUIRKS`],
+/// contributes.
+pub fn lookup(
+    extra: &[QuirkEntry],
+    vid: u16,
+    pid: u16,
+    bcd_device: u16,
+) -> Quirks {
+    let mut quirks = Quirks::NONE;
+    for entry in extra.iter().chain(BUILTIN_QUIRKS) {
+        if entry.matches(vid, pid, bcd_device) {
+            for q in entry.quirks {
+                quirks.add(*q);
+            }
+        }
+    }
+    quirks
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/quirks.rs"]
+mod tests;
@@ -15,19 +15,19 @@ mock! {
 
 #[test]
 fn alloc_dealloc() {
-    let p = Pool::new(2);
-    assert_eq!(p.allocated.get().0, 0);
+    let p = Pool::<2>::new();
+    assert_eq!(p.state.allocated.get().0, 0);
     {
         let pp = p.try_alloc().unwrap();
         assert_eq!(pp.which(), 0);
-        assert_eq!(p.allocated.get().0, 1);
+        assert_eq!(p.state.allocated.get().0, 1);
     }
-    assert_eq!(p.allocated.get().0, 0);
+    assert_eq!(p.state.allocated.get().0, 0);
 }
 
 #[test]
 fn alloc_fails() {
-    let p = Pool::new(2);
+    let p = Pool::<2>::new();
     let _p1 = p.try_alloc().unwrap();
     let _p2 = p.try_alloc().unwrap();
     let r = p.try_alloc();
@@ -36,7 +36,7 @@ fn alloc_fails() {
 
 #[test]
 fn display_pooled() {
-    let p = Pool::new(2);
+    let p = Pool::<2>::new();
     let pp = p.try_alloc().unwrap();
     assert_eq!(format!("{}", pp), "Pooled(0)");
 }
@@ -44,14 +44,14 @@ fn display_pooled() {
 #[test]
 fn alloc_setany_fails() {
     // setany only fails if we fill all 32 bits of the bitset
-    let p = Pool::new(32);
+    let p = Pool::<32>::new();
     let _a: [Pooled; 32] = core::array::from_fn(|_| p.try_alloc().unwrap());
     assert!(p.try_alloc().is_none());
 }
 
 #[test]
 fn dealloc_wakes_waker() {
-    let p = Pool::new(2);
+    let p = Pool::<2>::new();
     let mut w = MockTestWaker::new();
     w.expect_wake().return_const(());
 
@@ -70,3 +70,168 @@ fn dealloc_wakes_waker() {
     let r = pf.poll(&mut c);
     assert!(r.is_ready());
 }
+
+#[test]
+fn remaining() {
+    let p = Pool::<3>::new();
+    assert_eq!(p.remaining(), 3);
+    let p1 = p.try_alloc().unwrap();
+    let _p2 = p.try_alloc().unwrap();
+    assert_eq!(p.remaining(), 1);
+    drop(p1);
+    assert_eq!(p.remaining(), 2);
+}
+
+#[test]
+fn multi_alloc_release() {
+    let p = Pool::<4>::new();
+    let mut mp = MultiPooled::new(&p);
+    assert!(mp.is_empty());
+    assert_eq!(mp.try_alloc(), Some(0));
+    assert_eq!(mp.try_alloc(), Some(1));
+    assert_eq!(mp.try_alloc(), Some(2));
+    assert_eq!(mp.len(), 3);
+    assert_eq!(mp.remaining(), 1);
+
+    mp.release(1);
+    assert!(!mp.contains(1));
+    assert!(mp.contains(0) && mp.contains(2));
+    assert_eq!(mp.remaining(), 2);
+
+    // The lowest free slot is reused
+    assert_eq!(mp.try_alloc(), Some(1));
+    assert_eq!(mp.try_alloc(), Some(3));
+    assert_eq!(mp.try_alloc(), None);
+    assert_eq!(mp.len(), 4);
+    assert_eq!(mp.remaining(), 0);
+}
+
+#[test]
+fn multi_release_non_member() {
+    let p = Pool::<2>::new();
+    let mut mp = MultiPooled::new(&p);
+    let other = p.try_alloc().unwrap();
+    mp.release(other.which());
+    mp.release(31);
+    mp.release(200);
+    assert_eq!(p.remaining(), 1);
+    assert!(!mp.contains(200));
+}
+
+#[test]
+fn multi_interleaved_with_pooled() {
+    let p = Pool::<4>::new();
+    let mut mp = MultiPooled::new(&p);
+    let a = p.try_alloc().unwrap();
+    assert_eq!(mp.try_alloc(), Some(1));
+    let b = p.try_alloc().unwrap();
+    assert_eq!(mp.try_alloc(), Some(3));
+    drop(a);
+    assert_eq!(mp.try_alloc(), Some(0));
+    mp.release(3);
+    assert_eq!(b.which(), 2);
+    assert_eq!(p.remaining(), 1);
+    assert_eq!(mp.iter().collect::<Vec<_>>(), vec![0, 1]);
+}
+
+#[test]
+fn multi_bits_match_iter() {
+    let p = Pool::<32>::new();
+    let mut mp = MultiPooled::new(&p);
+    for i in 0..32u8 {
+        if i % 3 == 2 {
+            mp.release(i - 1);
+        } else {
+            mp.try_alloc().unwrap();
+        }
+        let from_iter = mp.iter().fold(0u32, |acc, n| acc | (1 << n));
+        assert_eq!(mp.bits().0, from_iter);
+        assert_eq!(mp.len() as usize, mp.iter().count());
+        assert_eq!(mp.remaining(), 32 - mp.len());
+    }
+}
+
+#[test]
+fn multi_drop_returns_all() {
+    let p = Pool::<3>::new();
+    {
+        let mut mp = MultiPooled::new(&p);
+        mp.try_alloc().unwrap();
+        mp.try_alloc().unwrap();
+        assert_eq!(p.remaining(), 1);
+    }
+    assert_eq!(p.remaining(), 3);
+    assert_eq!(p.state.allocated.get().0, 0);
+}
+
+#[test]
+fn debug_formats() {
+    let p = Pool::<4>::new();
+    assert_eq!(
+        format!("{:?}", p),
+        "Pool { total: 4, allocated: 0x00000000 }"
+    );
+    let a = p.try_alloc().unwrap();
+    assert_eq!(format!("{:?}", a), "Pooled(0)");
+    let mut mp = MultiPooled::new(&p);
+    assert_eq!(format!("{:?}", mp), "MultiPooled[]");
+    mp.try_alloc().unwrap();
+    mp.try_alloc().unwrap();
+    assert_eq!(format!("{:?}", mp), "MultiPooled[1, 2]");
+    assert_eq!(
+        format!("{:?}", p),
+        "Pool { total: 4, allocated: 0x00000007 }"
+    );
+}
+
+#[test]
+fn single() {
+    let p = Pool::<1>::new();
+    assert_eq!(p.remaining(), 1);
+    {
+        let a = p.try_alloc().unwrap();
+        assert_eq!(a.which(), 0);
+        assert!(p.try_alloc().is_none());
+        assert_eq!(p.remaining(), 0);
+    }
+    let mut mp = MultiPooled::new(&p);
+    assert_eq!(mp.try_alloc(), Some(0));
+    assert_eq!(mp.try_alloc(), None);
+    // Out of range for this pool, so not a member
+    mp.release(1);
+    assert!(!mp.contains(1));
+    assert_eq!(mp.len(), 1);
+    mp.release(0);
+    assert_eq!(p.remaining(), 1);
+}
+
+#[test]
+fn full_width() {
+    let p = Pool::<32>::new();
+    let mut mp = MultiPooled::new(&p);
+    for i in 0..32u8 {
+        assert_eq!(mp.try_alloc(), Some(i));
+    }
+    assert_eq!(mp.try_alloc(), None);
+    assert_eq!(mp.bits().0, u32::MAX);
+    assert_eq!(p.remaining(), 0);
+    mp.release(31);
+    assert!(!mp.contains(31));
+    let last = p.try_alloc().unwrap();
+    assert_eq!(last.which(), 31);
+    drop(mp);
+    assert_eq!(p.remaining(), 31);
+}
+
+#[test]
+fn pooled_is_independent_of_size() {
+    let small = Pool::<1>::new();
+    let large = Pool::<15>::new();
+    let v: [Pooled; 2] =
+        [small.try_alloc().unwrap(), large.try_alloc().unwrap()];
+    assert_eq!(v[0].which(), 0);
+    assert_eq!(v[1].which(), 0);
+    drop(v);
+    assert_eq!(small.remaining(), 1);
+    assert_eq!(large.remaining(), 15);
+}
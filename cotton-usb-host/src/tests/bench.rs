@@ -0,0 +1,202 @@
+use super::*;
+use crate::host_controller::InterruptPacket;
+use crate::mocks::{MockHostController, MockInterruptPipe};
+use crate::usb_bus::create_test_device;
+use futures::{future, Future};
+use std::cell::Cell;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn poll_once<T>(f: impl Future<Output = T>) -> Option<T> {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    match pin!(f).poll(&mut c) {
+        Poll::Ready(t) => Some(t),
+        Poll::Pending => None,
+    }
+}
+
+/// A clock which advances by `step` microseconds every time it's read
+fn clock(step: u64) -> impl Fn() -> u64 {
+    let t = Cell::new(1000u64);
+    move || {
+        t.set(t.get() + step);
+        t.get()
+    }
+}
+
+#[test]
+fn stats() {
+    let mut s = Stats::default();
+    assert_eq!(s.count(), 0);
+    assert_eq!(s.min_us(), 0);
+    assert_eq!(s.mean_us(), 0);
+    assert_eq!(s.max_us(), 0);
+    s.add(10);
+    s.add(30);
+    s.add(21);
+    assert_eq!(s.count(), 3);
+    assert_eq!(s.min_us(), 10);
+    assert_eq!(s.mean_us(), 20);
+    assert_eq!(s.max_us(), 30);
+}
+
+#[test]
+fn throughput() {
+    let t = Throughput {
+        bytes: 1 << 20,
+        elapsed_us: 2_000_000,
+    };
+    assert_eq!(t.kbytes_per_sec(), 512);
+    assert_eq!(Throughput::default().kbytes_per_sec(), 0);
+}
+
+#[test]
+fn elapsed() {
+    assert_eq!(elapsed_us(5, 12), 7);
+    assert_eq!(elapsed_us(u64::MAX, 1), 2);
+    assert_eq!(elapsed_us(0, 1 << 40), u32::MAX);
+}
+
+#[test]
+fn report_format() {
+    let mut s = Stats::new();
+    s.add(480);
+    s.add(520);
+    let r = Report {
+        name: "control_latency",
+        param: 0,
+        result: s,
+    };
+    assert_eq!(
+        format!("{}", r),
+        "BENCH name=control_latency param=0 n=2 min=480 mean=500 max=520"
+    );
+
+    let r = Report {
+        name: "msc_read",
+        param: 4096,
+        result: Throughput {
+            bytes: 1 << 20,
+            elapsed_us: 2_000_000,
+        },
+    };
+    assert_eq!(
+        format!("{}", r),
+        "BENCH name=msc_read param=4096 bytes=1048576 us=2000000 kbps=512"
+    );
+}
+
+#[test]
+fn control_latency_ok() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(3)
+        .withf(|a, _, s, d| {
+            *a == 255
+                && s.to_bytes() == [0x80, 0, 0, 0, 0, 0, 2, 0]
+                && matches!(d, DataPhase::In(b) if b.len() == 2)
+        })
+        .returning(|_, _, _, _| Box::pin(future::ready(Ok(2))));
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0, 0) };
+
+    let rc = poll_once(control_latency(&bus, &device, 3, clock(7)));
+    let stats = rc.unwrap().unwrap();
+    assert_eq!(stats.count(), 3);
+    assert_eq!(stats.min_us(), 7);
+    assert_eq!(stats.max_us(), 7);
+}
+
+#[test]
+fn control_latency_fails() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_control_transfer()
+        .times(1)
+        .returning(|_, _, _, _| Box::pin(future::ready(Err(UsbError::Stall))));
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0, 0) };
+
+    let rc = poll_once(control_latency(&bus, &device, 3, clock(7)));
+    assert_eq!(rc.unwrap(), Err(UsbError::Stall));
+}
+
+#[test]
+fn interrupt_intervals_ok() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .times(1)
+        .withf(|a, e, m, i| *a == 255 && *e == 1 && *m == 8 && *i == 10)
+        .returning(|_, _, _, _| {
+            Box::pin(future::ready({
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().times(5).returning(|_| {
+                    Poll::Ready(Some(InterruptPacket::default()))
+                });
+                ip
+            }))
+        });
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0, 0) };
+
+    let rc = poll_once(interrupt_intervals(
+        &bus,
+        &device,
+        1,
+        8,
+        10,
+        4,
+        clock(10_000),
+    ));
+    let stats = rc.unwrap();
+    assert_eq!(stats.count(), 4);
+    assert_eq!(stats.mean_us(), 10_000);
+}
+
+#[test]
+fn interrupt_intervals_stream_ends() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .returning(|_, _, _, _| {
+            Box::pin(future::ready({
+                let mut ip = MockInterruptPipe::new();
+                let mut n = 0;
+                ip.expect_poll_next().returning(move |_| {
+                    n += 1;
+                    if n <= 3 {
+                        Poll::Ready(Some(InterruptPacket::default()))
+                    } else {
+                        Poll::Ready(None)
+                    }
+                });
+                ip
+            }))
+        });
+    let bus = UsbBus::new(hc);
+    // SAFETY: we don't use this with a non-mock bus
+    let device = unsafe { create_test_device(0, 0) };
+
+    let rc = poll_once(interrupt_intervals(
+        &bus,
+        &device,
+        1,
+        8,
+        10,
+        100,
+        clock(10_000),
+    ));
+    assert_eq!(rc.unwrap().count(), 2);
+}
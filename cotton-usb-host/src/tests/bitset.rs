@@ -58,3 +58,15 @@ fn set_any_fail() {
     let n = bs.set_any();
     assert_eq!(n, None);
 }
+
+#[test]
+fn len() {
+    let mut bs = BitSet::new();
+    assert!(bs.is_empty());
+    assert_eq!(bs.len(), 0);
+    bs.set(3);
+    bs.set(31);
+    assert!(!bs.is_empty());
+    assert_eq!(bs.len(), 2);
+    assert_eq!(BitSet(u32::MAX).len(), 32);
+}
@@ -0,0 +1,189 @@
+use super::*;
+
+const BULK_IN: PipeInfo = PipeInfo {
+    endpoint: 0x81,
+    endpoint_type: EndpointType::Bulk,
+    max_packet_size: 64,
+    interval_ms: 0,
+};
+
+#[test]
+fn empty() {
+    let r = Registry::default();
+    for i in 0..=255 {
+        assert!(r.get(i).is_none());
+    }
+}
+
+#[test]
+fn addressed() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::High480);
+    let d = r.get(3).unwrap();
+    assert_eq!(d.address, 3);
+    assert_eq!(d.speed, UsbSpeed::High480);
+    assert_eq!(d.configuration, None);
+    assert_eq!(d.errors, ErrorCounters::default());
+    assert_eq!(d.interfaces().count(), 0);
+    assert_eq!(d.pipes().count(), 0);
+}
+
+#[test]
+fn out_of_range_ignored() {
+    let mut r = Registry::default();
+    r.addressed(32, UsbSpeed::Full12);
+    r.configured(32, 1);
+    r.pipe(32, BULK_IN);
+    r.error(32, UsbError::Stall);
+    r.release(32, 0);
+    assert_eq!(r.claim(32, 0, "x"), Ok(()));
+    assert!(r.get(32).is_none());
+}
+
+#[test]
+fn unknown_address_ignored() {
+    let mut r = Registry::default();
+    r.configured(5, 1);
+    r.pipe(5, BULK_IN);
+    r.error(5, UsbError::Stall);
+    assert_eq!(r.claim(5, 0, "x"), Ok(()));
+    assert!(r.get(5).is_none());
+}
+
+#[test]
+fn configured() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    r.configured(3, 2);
+    assert_eq!(r.get(3).unwrap().configuration, Some(2));
+}
+
+#[test]
+fn reconfigure_drops_claims_and_pipes() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    r.configured(3, 1);
+    r.claim(3, 0, "msc").unwrap();
+    r.pipe(3, BULK_IN);
+    r.configured(3, 2);
+    let d = r.get(3).unwrap();
+    assert_eq!(d.interfaces().count(), 0);
+    assert_eq!(d.pipes().count(), 0);
+}
+
+#[test]
+fn claim_and_release() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    r.claim(3, 0, "msc").unwrap();
+    r.claim(3, 1, "hid").unwrap();
+    assert_eq!(r.claim(3, 0, "hid"), Err(UsbError::InterfaceClaimed));
+    let d = r.get(3).unwrap();
+    let claims = d.interfaces().copied().collect::<Vec<_>>();
+    assert_eq!(
+        claims,
+        vec![
+            InterfaceClaim {
+                interface: 0,
+                driver: "msc"
+            },
+            InterfaceClaim {
+                interface: 1,
+                driver: "hid"
+            }
+        ]
+    );
+
+    r.release(3, 0);
+    r.claim(3, 0, "hid").unwrap();
+    let d = r.get(3).unwrap();
+    assert!(d.interfaces().all(|c| c.driver == "hid"));
+}
+
+#[test]
+fn too_many_claims_not_recorded() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    for i in 0..(MAX_INTERFACES as u8 + 2) {
+        r.claim(3, i, "drv").unwrap();
+    }
+    assert_eq!(r.get(3).unwrap().interfaces().count(), MAX_INTERFACES);
+}
+
+#[test]
+fn pipes_recorded_once() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    r.pipe(3, BULK_IN);
+    r.pipe(3, BULK_IN);
+    let d = r.get(3).unwrap();
+    assert_eq!(d.pipes().copied().collect::<Vec<_>>(), vec![BULK_IN]);
+}
+
+#[test]
+fn too_many_pipes_not_recorded() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    for i in 1..16 {
+        r.pipe(
+            3,
+            PipeInfo {
+                endpoint: i,
+                ..BULK_IN
+            },
+        );
+    }
+    let d = r.get(3).unwrap();
+    assert_eq!(d.pipes().count(), MAX_PIPES);
+    assert_eq!(d.pipes().next().unwrap().endpoint, 1);
+}
+
+#[test]
+fn errors_counted() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    r.error(3, UsbError::Stall);
+    r.error(3, UsbError::Timeout);
+    r.error(3, UsbError::Timeout);
+    r.error(3, UsbError::CrcError);
+    let e = r.get(3).unwrap().errors;
+    assert_eq!(e.stalls, 1);
+    assert_eq!(e.timeouts, 2);
+    assert_eq!(e.others, 1);
+    assert_eq!(e.last, Some(UsbError::CrcError));
+}
+
+#[test]
+fn errors_saturate() {
+    let mut e = ErrorCounters {
+        stalls: u16::MAX,
+        ..Default::default()
+    };
+    e.record(UsbError::Stall);
+    assert_eq!(e.stalls, u16::MAX);
+}
+
+#[test]
+fn readdress_forgets() {
+    let mut r = Registry::default();
+    r.addressed(3, UsbSpeed::Full12);
+    r.configured(3, 1);
+    r.error(3, UsbError::Stall);
+    r.addressed(3, UsbSpeed::Low1_5);
+    let d = r.get(3).unwrap();
+    assert_eq!(d.speed, UsbSpeed::Low1_5);
+    assert_eq!(d.configuration, None);
+    assert_eq!(d.errors.stalls, 0);
+}
+
+#[test]
+fn forget() {
+    let mut r = Registry::default();
+    r.addressed(1, UsbSpeed::Full12);
+    r.addressed(2, UsbSpeed::Full12);
+    r.addressed(3, UsbSpeed::Full12);
+    r.forget(BitSet(0b1010));
+    assert!(r.get(1).is_none());
+    assert!(r.get(2).is_some());
+    assert!(r.get(3).is_none());
+}
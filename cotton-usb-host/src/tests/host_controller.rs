@@ -21,6 +21,51 @@ fn packet_deref() {
     assert_eq!((&p)[9], 1);
 }
 
+#[test]
+fn packet_debug() {
+    let mut p = InterruptPacket::new();
+    p.address = 3;
+    p.endpoint = 1;
+    assert_eq!(
+        format!("{:?}", p),
+        "InterruptPacket { address: 3, endpoint: 1, size: 0, data: [] }"
+    );
+    p.size = 2;
+    p.data[0] = 0xAB;
+    p.data[1] = 1;
+    assert_eq!(
+        format!("{:?}", p),
+        "InterruptPacket { address: 3, endpoint: 1, size: 2, data: [ab 01] }"
+    );
+}
+
+#[test]
+fn packet_debug_long() {
+    let mut p = InterruptPacket::new();
+    p.size = 20;
+    assert_eq!(
+        format!("{:?}", p),
+        "InterruptPacket { address: 0, endpoint: 0, size: 20, data: \
+         [00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 ...] }"
+    );
+}
+
+#[test]
+fn packet_debug_oversize() {
+    let mut p = InterruptPacket::new();
+    p.size = 200;
+    assert!(format!("{:?}", p).ends_with(" ...] }"));
+}
+
+#[test]
+fn speed_and_status_debug() {
+    assert_eq!(format!("{:?}", UsbSpeed::Full12), "Full12");
+    assert_eq!(
+        format!("{:?}", DeviceStatus::Present(UsbSpeed::Low1_5)),
+        "Present(Low1_5)"
+    );
+}
+
 fn add_one(b: &mut [u8]) {
     b[0] += 1;
 }
@@ -47,3 +92,74 @@ fn dataphase_accessors() {
     d1.in_with(add_one);
     assert_eq!(b[0], 2); // not IN, nothing added
 }
+
+#[test]
+fn read_buffer_uninit() {
+    let mut data = [MaybeUninit::uninit(); 8];
+    let mut buf = ReadBuffer::new(&mut data);
+    assert_eq!(buf.capacity(), 8);
+    assert!(buf.is_empty());
+    assert_eq!(buf.remaining(), 8);
+    assert_eq!(buf.init_len(), 0);
+
+    buf.push_slice(&[1, 2, 3]);
+    assert_eq!(buf.filled(), &[1, 2, 3]);
+    assert_eq!(buf.init_len(), 3);
+
+    let unfilled = buf.unfilled_zeroed();
+    assert_eq!(unfilled, &[0; 5]);
+    unfilled[0] = 4;
+    buf.add_filled(1);
+    assert_eq!(buf.filled(), &[1, 2, 3, 4]);
+    assert_eq!(buf.init_len(), 8);
+
+    buf.filled_mut()[0] = 9;
+    assert_eq!(buf.into_filled(), &[9, 2, 3, 4]);
+}
+
+#[test]
+#[should_panic]
+fn read_buffer_add_filled_checks_init() {
+    let mut data = [MaybeUninit::uninit(); 8];
+    let mut buf = ReadBuffer::new(&mut data);
+    buf.add_filled(1);
+}
+
+#[test]
+fn read_buffer_from_init() {
+    let mut data = [7u8; 4];
+    let mut buf = ReadBuffer::from(&mut data[..]);
+    assert_eq!(buf.init_len(), 4);
+    // Already initialised, so can be filled without zeroing
+    buf.add_filled(4);
+    assert_eq!(buf.filled(), &[7; 4]);
+}
+
+#[test]
+fn read_buffer_unfilled() {
+    let mut data = [MaybeUninit::uninit(); 8];
+    let mut buf = ReadBuffer::new(&mut data);
+    buf.push_slice(&[1, 2, 3, 4]);
+    buf.clear();
+    {
+        let mut chunk = buf.unfilled(6);
+        assert_eq!(chunk.capacity(), 6);
+        // What the outer buffer knows is passed on
+        assert_eq!(chunk.init_len(), 4);
+        chunk.push_slice(&[5, 6]);
+    }
+    // SAFETY: the chunk filled the first two bytes
+    unsafe { buf.assume_filled(2) };
+    assert_eq!(buf.filled(), &[5, 6]);
+    assert_eq!(buf.unfilled(100).capacity(), 6);
+}
+
+#[test]
+fn default_capabilities() {
+    let c = ControllerCapabilities::default();
+    assert!(c.bulk);
+    assert!(!c.isochronous);
+    assert!(!c.interrupt_out);
+    assert_eq!(c.max_packet_size, 64);
+    assert_eq!(c.interrupt_pipes, 1);
+}
@@ -0,0 +1,95 @@
+use super::*;
+
+const EXAMPLE: DeviceIdentity = DeviceIdentity {
+    vid: 0x0781,
+    pid: 0x5567,
+    serial_hash: 0xDEAD_BEEF,
+    capabilities: Capabilities {
+        quirks: Quirks {
+            no_vpd: true,
+            no_rsoc: false,
+            force_single_lun: true,
+            delay_after_set_address_ms: 20,
+            max_transfer_blocks: Some(64),
+        },
+        scsi: Some(ScsiCapabilities {
+            supports_16: true,
+            max_transfer_blocks: Some(256),
+        }),
+    },
+};
+
+#[test]
+fn round_trip() {
+    let bytes = EXAMPLE.to_bytes();
+    assert_eq!(bytes[0], VERSION);
+    assert_eq!(DeviceIdentity::from_bytes(&bytes), Some(EXAMPLE));
+}
+
+#[test]
+fn round_trip_unprobed() {
+    let id = DeviceIdentity {
+        vid: 1,
+        pid: 2,
+        serial_hash: 0,
+        capabilities: Capabilities::default(),
+    };
+    assert_eq!(DeviceIdentity::from_bytes(&id.to_bytes()), Some(id));
+}
+
+#[test]
+fn round_trip_scsi_without_limit() {
+    let mut id = EXAMPLE;
+    id.capabilities.scsi = Some(ScsiCapabilities::default());
+    id.capabilities.quirks.max_transfer_blocks = None;
+    assert_eq!(DeviceIdentity::from_bytes(&id.to_bytes()), Some(id));
+}
+
+#[test]
+fn from_bytes_rejects_short() {
+    let bytes = EXAMPLE.to_bytes();
+    assert_eq!(DeviceIdentity::from_bytes(&bytes[0..19]), None);
+}
+
+#[test]
+fn from_bytes_rejects_other_versions() {
+    let mut bytes = EXAMPLE.to_bytes();
+    bytes[0] = 2;
+    assert_eq!(DeviceIdentity::from_bytes(&bytes), None);
+    bytes[0] = 0xFF; // erased flash
+    assert_eq!(DeviceIdentity::from_bytes(&bytes), None);
+}
+
+#[test]
+fn from_bytes_rejects_reserved_flags() {
+    let mut bytes = EXAMPLE.to_bytes();
+    bytes[9] |= RESERVED;
+    assert_eq!(DeviceIdentity::from_bytes(&bytes), None);
+}
+
+#[test]
+fn matches() {
+    assert!(EXAMPLE.matches(0x0781, 0x5567, 0xDEAD_BEEF));
+    assert!(!EXAMPLE.matches(0x0781, 0x5567, 0));
+    assert!(!EXAMPLE.matches(0x0781, 0x5568, 0xDEAD_BEEF));
+    assert!(!EXAMPLE.matches(0x0782, 0x5567, 0xDEAD_BEEF));
+}
+
+#[test]
+fn serial_hash_covers_string_only() {
+    let a = [8, 3, b'A', 0, b'B', 0, b'C', 0];
+    let b = [8, 3, b'A', 0, b'B', 0, b'D', 0];
+    assert_ne!(serial_hash(&a), serial_hash(&b));
+    // Trailing bytes beyond bLength don't count
+    let mut c = [0u8; 12];
+    c[0..8].copy_from_slice(&a);
+    c[8] = 0x55;
+    assert_eq!(serial_hash(&a), serial_hash(&c));
+}
+
+#[test]
+fn serial_hash_never_zero() {
+    assert_ne!(serial_hash(&[]), 0);
+    assert_ne!(serial_hash(&[2, 3]), 0);
+    assert_ne!(serial_hash(&[200, 3, 1]), 0);
+}
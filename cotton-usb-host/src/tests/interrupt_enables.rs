@@ -0,0 +1,211 @@
+use super::*;
+
+const TRANS_COMPLETE: u32 = 1 << 3;
+const STALL: u32 = 1 << 10;
+
+/// Just enough of the RP2040's interrupt registers to replay the driver's
+/// INTE/BUFF_STATUS handling against a script
+#[derive(Default)]
+struct FakeRegs {
+    inte: u32,
+    intr: u32,
+    buff_status: u32,
+}
+
+impl FakeRegs {
+    fn ints(&self) -> u32 {
+        let buff = if self.buff_status != 0 {
+            BUFF_STATUS
+        } else {
+            0
+        };
+        (self.intr | buff) & self.inte
+    }
+
+    /// The same steps as `UsbShared::on_irq()`
+    fn irq(&mut self, e: &InterruptEnables) -> BitSet {
+        let mut woken = BitSet::new();
+        if (self.ints() & BUFF_STATUS) != 0 {
+            let (wake, clear) = e.on_buff_status(self.buff_status);
+            woken = wake;
+            self.buff_status &= !clear;
+        }
+        if (self.ints() & TRANS_COMPLETE) != 0 {
+            woken.set(0);
+        }
+        let pending = self.ints();
+        self.inte = e.after_irq(self.inte, pending);
+        woken
+    }
+}
+
+#[test]
+fn new_is_unarmed() {
+    let e = InterruptEnables::new();
+    assert!(e.armed().is_empty());
+    assert!(!e.take_control_buffers());
+}
+
+#[test]
+fn arm_disarm() {
+    let e = InterruptEnables::default();
+    e.arm(1);
+    e.arm(3);
+    assert_eq!(e.armed(), BitSet(0b1010));
+    e.disarm(1);
+    assert_eq!(e.armed(), BitSet(0b1000));
+    e.disarm(3);
+    assert!(e.armed().is_empty());
+}
+
+#[test]
+fn wakes_pipes_with_completed_buffers() {
+    let e = InterruptEnables::new();
+    let (wake, _) = e.on_buff_status(0b10_0000_0001);
+    assert_eq!(wake, BitSet(0b10001));
+}
+
+#[test]
+fn unarmed_leaves_control_buffers_to_thread_mode() {
+    let e = InterruptEnables::new();
+    let (_, clear) = e.on_buff_status(0x3 | 0x4);
+    assert_eq!(clear & CONTROL_BUFFERS, 0);
+    assert_eq!(clear & 0x4, 0x4);
+    assert!(!e.take_control_buffers());
+}
+
+#[test]
+fn armed_latches_control_buffers() {
+    let e = InterruptEnables::new();
+    e.arm(1);
+    let (wake, clear) = e.on_buff_status(0x1);
+    assert!(wake.contains(0));
+    assert_eq!(clear, 0xFFFF_FFFF);
+    assert!(e.take_control_buffers());
+    assert!(!e.take_control_buffers());
+}
+
+#[test]
+fn stall_latched_for_armed_pipes_only() {
+    let e = InterruptEnables::new();
+    e.arm(1);
+    e.arm(3);
+    // IN bits for pipes 1, 2 (unarmed) and 3; OUT bit for pipe 1
+    let stalled = e.on_stall_nak((1 << 2) | (1 << 3) | (1 << 4) | (1 << 6));
+    assert_eq!(stalled, BitSet(0b1010));
+    assert!(e.take_stall(1));
+    assert!(!e.take_stall(1));
+    assert!(!e.take_stall(2));
+    assert!(e.take_stall(3));
+}
+
+#[test]
+fn disarm_forgets_stall() {
+    let e = InterruptEnables::new();
+    e.arm(2);
+    e.on_stall_nak(1 << 4);
+    e.disarm(2);
+    e.arm(2);
+    assert!(!e.take_stall(2));
+}
+
+#[test]
+fn after_irq_masks_pending() {
+    let e = InterruptEnables::new();
+    assert_eq!(
+        e.after_irq(BUFF_STATUS | TRANS_COMPLETE | STALL, BUFF_STATUS | STALL),
+        TRANS_COMPLETE
+    );
+}
+
+#[test]
+fn after_irq_keeps_buff_status_while_armed() {
+    let e = InterruptEnables::new();
+    e.arm(2);
+    assert_eq!(
+        e.after_irq(BUFF_STATUS | STALL, BUFF_STATUS | STALL),
+        BUFF_STATUS
+    );
+    e.disarm(2);
+    assert_eq!(e.after_irq(BUFF_STATUS | STALL, BUFF_STATUS | STALL), 0);
+}
+
+#[test]
+fn unarmed_irq_masks_leftover_control_buffers() {
+    // The original behaviour: with no interrupt pipes, a control-pipe
+    // buffer completion is masked rather than storming
+    let e = InterruptEnables::new();
+    let mut r = FakeRegs {
+        inte: BUFF_STATUS | TRANS_COMPLETE,
+        buff_status: 0x1,
+        ..Default::default()
+    };
+    let woken = r.irq(&e);
+    assert!(woken.contains(0));
+    assert_eq!(r.buff_status, 0x1);
+    assert_eq!(r.inte & BUFF_STATUS, 0);
+    assert_eq!(r.ints(), 0);
+}
+
+#[test]
+fn control_transfer_never_drops_buff_status_for_interrupt_pipe() {
+    let e = InterruptEnables::new();
+    let mut r = FakeRegs::default();
+
+    // Interrupt pipe 1 registered and polled (Pending): enables BUFF_STATUS
+    e.arm(1);
+    r.inte |= BUFF_STATUS;
+
+    // Multi-packet control transfer starts: thread mode enables
+    // completion/error interrupts
+    r.buff_status &= !CONTROL_BUFFERS;
+    e.take_control_buffers();
+    r.inte |= BUFF_STATUS | TRANS_COMPLETE | STALL;
+
+    // Scripted hardware events during the control transfer; the last
+    // is an interrupt-pipe packet arriving alongside transfer completion
+    let script: [(u32, u32); 5] = [
+        (0x1, 0),
+        (0x2, 0),
+        (0x1 | 0x4, 0),
+        (0x2, 0),
+        (0x4, TRANS_COMPLETE),
+    ];
+    let mut control_wakes = 0;
+    let mut pipe_wakes = 0;
+    for (buffers, intr) in script {
+        r.buff_status |= buffers;
+        r.intr |= intr;
+        let woken = r.irq(&e);
+        assert_ne!(r.inte & BUFF_STATUS, 0, "BUFF_STATUS dropped");
+        assert_eq!(r.ints(), 0, "IRQ storm");
+
+        if woken.contains(0) {
+            control_wakes += 1;
+            // Control endpoint future: ready because of the latch (or
+            // because TRANS_COMPLETE was seen)
+            assert!(
+                e.take_control_buffers() || (r.intr & TRANS_COMPLETE) != 0
+            );
+            // ...and the transfer loop acknowledges and re-enables
+            r.buff_status &= !CONTROL_BUFFERS;
+            r.inte &= !(TRANS_COMPLETE | STALL);
+            if (r.intr & TRANS_COMPLETE) == 0 {
+                r.inte |= TRANS_COMPLETE | STALL;
+            }
+            assert_ne!(r.inte & BUFF_STATUS, 0, "BUFF_STATUS dropped");
+        }
+        if woken.contains(1) {
+            pipe_wakes += 1;
+        }
+    }
+    assert_eq!(control_wakes, 5);
+    assert_eq!(pipe_wakes, 2);
+
+    // Pipe dropped: next stray control-buffer completion masks it again
+    e.disarm(1);
+    r.intr = 0;
+    r.buff_status |= 0x1;
+    r.irq(&e);
+    assert_eq!(r.inte & BUFF_STATUS, 0);
+}
@@ -0,0 +1,202 @@
+use super::*;
+use crate::mocks::{MockHostController, MockInterruptPipe};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn no_delay(_ms: usize) -> impl Future<Output = ()> {
+    future::ready(())
+}
+
+/// The devices at the far end of the shared pipe
+#[derive(Default)]
+struct FakeEndpoints {
+    /// Where the pipe is pointing
+    current: (u8, u8),
+    /// Packets sent by each endpoint
+    sent: HashMap<(u8, u8), usize>,
+    /// Endpoints which always NAK
+    silent: Vec<(u8, u8)>,
+    /// Endpoints which stall
+    stalled: Vec<(u8, u8)>,
+}
+
+impl FakeEndpoints {
+    fn sent(&self, address: u8, endpoint: u8) -> usize {
+        self.sent.get(&(address, endpoint)).copied().unwrap_or(0)
+    }
+}
+
+fn shared_pipe(state: &Rc<RefCell<FakeEndpoints>>) -> MockInterruptPipe {
+    let mut pipe = MockInterruptPipe::new();
+    let s = state.clone();
+    pipe.expect_retarget()
+        .returning_st(move |a, e, m, i, toggle| {
+            let mut s = s.borrow_mut();
+            assert_eq!(m, 8);
+            assert_eq!(i, 1);
+            // Each endpoint's toggle was kept while it wasn't using the pipe
+            assert_eq!(toggle, s.sent(a, e) % 2 == 1);
+            s.current = (a, e);
+        });
+    let s = state.clone();
+    pipe.expect_poll_next().returning_st(move |_| {
+        let mut s = s.borrow_mut();
+        let (address, endpoint) = s.current;
+        if s.silent.contains(&s.current) {
+            Poll::Pending
+        } else if s.stalled.contains(&s.current) {
+            Poll::Ready(None)
+        } else {
+            *s.sent.entry((address, endpoint)).or_default() += 1;
+            Poll::Ready(Some(InterruptPacket {
+                address,
+                endpoint,
+                size: 1,
+                ..Default::default()
+            }))
+        }
+    });
+    let s = state.clone();
+    pipe.expect_data_toggle().returning_st(move || {
+        let s = s.borrow();
+        s.sent(s.current.0, s.current.1) % 2 == 1
+    });
+    pipe
+}
+
+fn bus_with_shared_pipe(
+    state: &Rc<RefCell<FakeEndpoints>>,
+) -> UsbBus<MockHostController> {
+    let mut hc = MockHostController::default();
+    let s = state.clone();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .times(1)
+        .returning_st(move |_, _, _, _| {
+            Box::pin(future::ready(shared_pipe(&s)))
+        });
+    UsbBus::new(hc)
+}
+
+fn take_packets<const N: usize, HC: HostController>(
+    scheduler: &SharedInterruptScheduler<N>,
+    bus: &UsbBus<HC>,
+    n: usize,
+) -> Vec<(u8, u8)> {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = Context::from_waker(&w);
+    let mut packets = pin!(scheduler.packets(bus, no_delay));
+    (0..n)
+        .map(|_| match packets.as_mut().poll_next(&mut c) {
+            Poll::Ready(Some(p)) => (p.address, p.endpoint),
+            _ => panic!("no packet"),
+        })
+        .collect()
+}
+
+#[test]
+fn twenty_endpoints_all_receive_data() {
+    let state = Rc::new(RefCell::new(FakeEndpoints::default()));
+    let bus = bus_with_shared_pipe(&state);
+    let scheduler = SharedInterruptScheduler::<20>::new();
+    for address in 1..=10 {
+        for endpoint in 1..=2 {
+            scheduler.add(address, endpoint, 8, 10).unwrap();
+        }
+    }
+
+    let packets = take_packets(&scheduler, &bus, 60);
+
+    let state = state.borrow();
+    for address in 1..=10 {
+        for endpoint in 1..=2 {
+            assert_eq!(state.sent(address, endpoint), 3);
+        }
+    }
+    // Round-robin: everyone gets a turn before anyone gets a second one
+    assert_eq!(packets[0..20], packets[20..40]);
+}
+
+#[test]
+fn silent_endpoint_doesnt_block_others() {
+    let state = Rc::new(RefCell::new(FakeEndpoints {
+        silent: vec![(1, 1)],
+        ..Default::default()
+    }));
+    let bus = bus_with_shared_pipe(&state);
+    let scheduler = SharedInterruptScheduler::<4>::new();
+    scheduler.add(1, 1, 8, 10).unwrap();
+    scheduler.add(2, 1, 8, 10).unwrap();
+    scheduler.add(3, 1, 8, 10).unwrap();
+
+    take_packets(&scheduler, &bus, 10);
+
+    let state = state.borrow();
+    assert_eq!(state.sent(1, 1), 0);
+    assert_eq!(state.sent(2, 1), 5);
+    assert_eq!(state.sent(3, 1), 5);
+    assert!(scheduler.contains(1, 1));
+}
+
+#[test]
+fn shorter_interval_polled_more_often() {
+    let state = Rc::new(RefCell::new(FakeEndpoints::default()));
+    let bus = bus_with_shared_pipe(&state);
+    let scheduler = SharedInterruptScheduler::<4>::new();
+    scheduler.add(1, 1, 8, 2).unwrap();
+    scheduler.add(2, 1, 8, 20).unwrap();
+
+    take_packets(&scheduler, &bus, 20);
+
+    let state = state.borrow();
+    assert!(state.sent(2, 1) > 0);
+    assert!(state.sent(1, 1) > 4 * state.sent(2, 1));
+}
+
+#[test]
+fn stalled_endpoint_leaves_schedule() {
+    let state = Rc::new(RefCell::new(FakeEndpoints {
+        stalled: vec![(2, 1)],
+        ..Default::default()
+    }));
+    let bus = bus_with_shared_pipe(&state);
+    let scheduler = SharedInterruptScheduler::<4>::new();
+    scheduler.add(1, 1, 8, 10).unwrap();
+    scheduler.add(2, 1, 8, 10).unwrap();
+
+    let packets = take_packets(&scheduler, &bus, 3);
+
+    assert_eq!(packets, [(1, 1); 3]);
+    assert!(scheduler.contains(1, 1));
+    assert!(!scheduler.contains(2, 1));
+}
+
+#[test]
+fn add_when_full() {
+    let scheduler = SharedInterruptScheduler::<2>::new();
+    scheduler.add(1, 1, 8, 10).unwrap();
+    scheduler.add(1, 2, 8, 10).unwrap();
+    assert_eq!(scheduler.add(2, 1, 8, 10), Err(UsbError::TooManyDevices));
+    scheduler.remove(1, 1);
+    scheduler.add(2, 1, 8, 10).unwrap();
+}
+
+#[test]
+fn remove_device() {
+    let scheduler = SharedInterruptScheduler::<4>::default();
+    scheduler.add(1, 1, 8, 10).unwrap();
+    scheduler.add(1, 2, 8, 10).unwrap();
+    scheduler.add(2, 1, 8, 10).unwrap();
+    scheduler.remove_device(1);
+    assert!(!scheduler.contains(1, 1));
+    assert!(!scheduler.contains(1, 2));
+    assert!(scheduler.contains(2, 1));
+}
@@ -0,0 +1,81 @@
+use super::*;
+
+static TABLE: &[QuirkEntry] = &[
+    QuirkEntry::new(0x1234, 0x5678, &[Quirk::NoVpd]),
+    QuirkEntry::new(0x1234, 0x5678, &[Quirk::MaxTransferBlocks(64)])
+        .bcd_device(0x0100, 0x0199),
+    QuirkEntry::new(0x1234, 0x5678, &[Quirk::MaxTransferBlocks(128)]),
+    QuirkEntry::new(
+        0x1234,
+        0x9ABC,
+        &[
+            Quirk::DelayAfterSetAddressMs(10),
+            Quirk::DelayAfterSetAddressMs(5),
+            Quirk::ForceSingleLun,
+        ],
+    ),
+];
+
+#[test]
+fn none() {
+    assert!(Quirks::NONE.is_none());
+    assert_eq!(Quirks::default(), Quirks::NONE);
+    assert!(lookup(TABLE, 0x1234, 0x0001, 0).is_none());
+}
+
+#[test]
+fn bcd_device_range() {
+    let e = TABLE[1];
+    assert!(!e.matches(0x1234, 0x5678, 0x00FF));
+    assert!(e.matches(0x1234, 0x5678, 0x0100));
+    assert!(e.matches(0x1234, 0x5678, 0x0199));
+    assert!(!e.matches(0x1234, 0x5678, 0x0200));
+    assert!(!e.matches(0x1235, 0x5678, 0x0150));
+}
+
+#[test]
+fn all_matches_contribute() {
+    let q = lookup(TABLE, 0x1234, 0x5678, 0x0150);
+    assert!(q.no_vpd);
+    assert!(!q.no_rsoc);
+    assert_eq!(q.max_transfer_blocks, Some(64));
+
+    let q = lookup(TABLE, 0x1234, 0x5678, 0x0200);
+    assert!(q.no_vpd);
+    assert_eq!(q.max_transfer_blocks, Some(128));
+}
+
+#[test]
+fn most_cautious_wins() {
+    let q = lookup(TABLE, 0x1234, 0x9ABC, 0);
+    assert_eq!(q.delay_after_set_address_ms, 10);
+    assert!(q.force_single_lun);
+}
+
+#[test]
+fn builtin() {
+    let q = lookup(&[], 0x152D, 0x2338, 0x0100);
+    assert!(q.no_vpd);
+    assert!(q.no_rsoc);
+    assert!(!q.force_single_lun);
+}
+
+#[test]
+fn merge() {
+    let mut q = lookup(TABLE, 0x1234, 0x5678, 0x0150);
+    q.merge(&Quirks {
+        no_rsoc: true,
+        delay_after_set_address_ms: 5,
+        max_transfer_blocks: Some(128),
+        ..Quirks::NONE
+    });
+    assert!(q.no_vpd);
+    assert!(q.no_rsoc);
+    assert!(!q.force_single_lun);
+    assert_eq!(q.delay_after_set_address_ms, 5);
+    assert_eq!(q.max_transfer_blocks, Some(64));
+
+    let mut none = Quirks::NONE;
+    none.merge(&Quirks::NONE);
+    assert!(none.is_none());
+}
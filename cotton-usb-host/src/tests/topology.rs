@@ -142,3 +142,25 @@ fn ludicrous_input_rejected() {
     assert!(bus.device_connect(100, 100, true).is_none());
     assert_eq!(bus.device_disconnect(100, 100).0, 0);
 }
+
+#[test]
+fn depth() {
+    let mut bus = Topology::new();
+    assert_eq!(bus.depth(0), Some(0));
+    assert_eq!(bus.depth(1), None);
+    bus.device_connect(0, 1, true); // 1
+    bus.device_connect(1, 1, true); // 2
+    bus.device_connect(2, 3, false); // 31
+    bus.device_connect(1, 2, false); // 30
+    assert_eq!(bus.depth(1), Some(1));
+    assert_eq!(bus.depth(2), Some(2));
+    assert_eq!(bus.depth(31), Some(3));
+    assert_eq!(bus.depth(30), Some(2));
+    assert_eq!(bus.depth(29), None);
+    assert_eq!(bus.depth(100), None);
+
+    bus.device_disconnect(1, 1);
+    assert_eq!(bus.depth(2), None);
+    assert_eq!(bus.depth(31), None);
+    assert_eq!(bus.depth(30), Some(2));
+}
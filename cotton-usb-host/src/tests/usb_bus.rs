@@ -4,11 +4,16 @@ use crate::mocks::{
     MockInterruptPipe,
 };
 use crate::wire::{
-    EndpointDescriptor, InterfaceDescriptor, ENDPOINT_DESCRIPTOR,
-    INTERFACE_DESCRIPTOR, RECIPIENT_ENDPOINT, VENDOR_REQUEST,
+    EndpointDescriptor, InterfaceDescriptor, CLASS_REQUEST,
+    CONFIGURATION_DESCRIPTOR, DEVICE_DESCRIPTOR, DEVICE_TO_HOST,
+    ENDPOINT_DESCRIPTOR, GET_DESCRIPTOR, HUB_DESCRIPTOR, INTERFACE_DESCRIPTOR,
+    VENDOR_REQUEST,
 };
 use futures::{future, Future};
+use std::cell::RefCell;
+use std::mem::MaybeUninit;
 use std::pin::{pin, Pin};
+use std::rc::Rc;
 use std::sync::Arc;
 use std::task::{Poll, Wake, Waker};
 extern crate alloc;
@@ -209,6 +214,7 @@ const UNCONFIGURED_DEVICE: UnconfiguredDevice = UnconfiguredDevice {
     usb_address: 5,
     usb_speed: UsbSpeed::Full12,
     packet_size_ep0: 8,
+    quirks: Quirks::NONE,
 };
 
 fn unconfigured_device() -> UnconfiguredDevice {
@@ -216,6 +222,7 @@ fn unconfigured_device() -> UnconfiguredDevice {
         usb_address: 5,
         usb_speed: UsbSpeed::Full12,
         packet_size_ep0: 8,
+        quirks: Quirks::NONE,
     }
 }
 
@@ -223,6 +230,7 @@ fn unaddressed_device() -> UnaddressedDevice {
     UnaddressedDevice {
         usb_speed: UsbSpeed::Full12,
         packet_size_ep0: 8,
+        quirks: Quirks::NONE,
     }
 }
 
@@ -232,6 +240,7 @@ const EXAMPLE_DEVICE: UsbDevice = UsbDevice {
     packet_size_ep0: 8,
     in_endpoints_bitmap: 4,
     out_endpoints_bitmap: 2,
+    quirks: Quirks::NONE,
 };
 
 // Not sure why this isn't in the standard library
@@ -278,19 +287,26 @@ fn basic_configuration() {
     assert_eq!(bc.out_endpoints, 0b1100000100);
 }
 
+#[test]
+fn basic_configuration_many() {
+    // A malicious device can send any number of configuration descriptors
+    let descriptors = [9, 2, 9, 0, 0, 3, 0, 0x80, 50].repeat(300);
+    let mut bc = BasicConfiguration::default();
+    crate::wire::parse_descriptors(&descriptors, &mut bc);
+    assert_eq!(bc.num_configurations, 255);
+    assert_eq!(bc.configuration_value, 3);
+}
+
 fn is_set_configuration<const ADDR: u8, const N: u16>(
     a: &u8,
     p: &u8,
     s: &SetupPacket,
     d: &DataPhase,
 ) -> bool {
+    let [v0, v1] = N.to_le_bytes();
     *a == ADDR
         && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE
-        && s.bRequest == SET_CONFIGURATION
-        && s.wValue == N
-        && s.wIndex == 0
-        && s.wLength == 0
+        && s.to_bytes() == [0x00, 0x09, v0, v1, 0x00, 0x00, 0x00, 0x00]
         && d.is_none()
 }
 
@@ -315,8 +331,14 @@ fn control_transfer_ok_with<F: FnMut(&mut [u8]) -> usize>(
     DataPhase,
 ) -> Pin<Box<dyn Future<Output = Result<usize, UsbError>>>> {
     move |_, _, _, mut d| {
+        // Like a real device, send only as much as was asked for
         let mut n = 0;
-        d.in_with(|bytes| n = f(bytes));
+        d.in_with(|bytes| {
+            let mut all = [0u8; 256];
+            all[0..bytes.len()].copy_from_slice(bytes);
+            n = f(&mut all).min(bytes.len());
+            bytes[0..n].copy_from_slice(&all[0..n]);
+        });
         Box::pin(future::ready(Ok(n)))
     }
 }
@@ -427,14 +449,14 @@ impl ExtraExpectations for MockHostControllerInner {
 
     fn expect_get_configuration<const ADDR: u8>(&mut self) {
         self.expect_control_transfer()
-            .times(1)
+            .times(2)
             .withf(is_get_configuration_descriptor::<ADDR>)
             .returning(control_transfer_ok_with(example_config_descriptor));
     }
 
     fn expect_get_double_configuration<const ADDR: u8>(&mut self) {
         self.expect_control_transfer()
-            .times(1)
+            .times(2)
             .withf(is_get_configuration_descriptor::<ADDR>)
             .returning(control_transfer_ok_with(double_config_descriptor));
     }
@@ -530,6 +552,16 @@ impl ExtraExpectations for MockHostControllerInner {
     }
 }
 
+/// The timings these tests were written against: no debounce or settle
+/// delays, a short reset recovery, and no retries
+const QUICK: EnumerationConfig = EnumerationConfig {
+    debounce_ms: 0,
+    reset_ms: 50,
+    reset_recovery_ms: 10,
+    vbus_settle_ms: 0,
+    attempts: 1,
+};
+
 struct Fixture<'a> {
     c: &'a mut core::task::Context<'a>,
     hub_state: HubState<MockHostController>,
@@ -540,20 +572,46 @@ fn do_test<
     SetupFn: FnMut(&mut MockHostControllerInner),
     TestFn: FnMut(Fixture),
 >(
+    setup: SetupFn,
+    test: TestFn,
+) {
+    do_test_with_config(QUICK, setup, test);
+}
+
+fn do_test_with_config<
+    SetupFn: FnMut(&mut MockHostControllerInner),
+    TestFn: FnMut(Fixture),
+>(
+    config: EnumerationConfig,
+    setup: SetupFn,
+    test: TestFn,
+) {
+    do_test_with_controller(config, usize::MAX, setup, test);
+}
+
+fn do_test_with_controller<
+    SetupFn: FnMut(&mut MockHostControllerInner),
+    TestFn: FnMut(Fixture),
+>(
+    config: EnumerationConfig,
+    max_transfer_size: usize,
     mut setup: SetupFn,
     mut test: TestFn,
 ) {
     let w = Waker::from(Arc::new(NoOpWaker));
     let mut c = core::task::Context::from_waker(&w);
 
-    let mut hc = MockHostController::default();
+    let mut hc = MockHostController {
+        max_transfer_size,
+        ..Default::default()
+    };
 
     setup(&mut hc.inner);
 
     let f = Fixture {
         c: &mut c,
         hub_state: HubState::default(),
-        bus: UsbBus::new(hc),
+        bus: UsbBus::with_config(hc, config),
     };
 
     test(f);
@@ -712,7 +770,10 @@ fn get_basic_configuration_bad_descriptors() {
 
     let r = pin!(bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
     let rr = r.poll(&mut c);
-    assert_eq!(rr, Poll::Ready(Err(UsbError::ProtocolError)));
+    assert_eq!(
+        rr,
+        Poll::Ready(Err(UsbError::Protocol("no usable configuration")))
+    );
 }
 
 #[test]
@@ -725,7 +786,7 @@ fn get_basic_configuration_bad_configuration_value() {
 
     hc.inner
         .expect_control_transfer()
-        .times(1)
+        .times(2)
         .withf(is_get_configuration_descriptor::<5>)
         .returning(control_transfer_ok_with(|bytes| {
             example_config_descriptor(bytes);
@@ -737,7 +798,10 @@ fn get_basic_configuration_bad_configuration_value() {
 
     let r = pin!(bus.get_basic_configuration(&UNCONFIGURED_DEVICE));
     let rr = r.poll(&mut c);
-    assert_eq!(rr, Poll::Ready(Err(UsbError::ProtocolError)));
+    assert_eq!(
+        rr,
+        Poll::Ready(Err(UsbError::Protocol("no usable configuration")))
+    );
 }
 
 #[test]
@@ -792,11 +856,7 @@ fn is_set_address<const N: u8>(
 ) -> bool {
     *a == 0
         && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE
-        && s.bRequest == SET_ADDRESS
-        && s.wValue == N as u16
-        && s.wIndex == 0
-        && s.wLength == 0
+        && s.to_bytes() == [0x00, 0x05, N, 0x00, 0x00, 0x00, 0x00, 0x00]
         && d.is_none()
 }
 
@@ -902,19 +962,62 @@ fn interrupt_endpoint_in_pends() {
     assert!(rr.is_pending());
 }
 
+#[test]
+fn interrupt_endpoint_in_ends_on_stall() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .withf(|a, e, m, i| *a == 5 && *e == 2 && *m == 8 && *i == 10)
+        .returning(|_, _, _, _| {
+            Box::pin(future::ready({
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_poll_next().returning(|_| Poll::Ready(None));
+                ip
+            }))
+        });
+    let bus = UsbBus::new(hc);
+
+    let r = pin!(bus.interrupt_endpoint_in(5, 2, 8, 10));
+    let rr = r.poll_next(&mut c);
+    assert!(matches!(rr, Poll::Ready(None)));
+}
+
+#[test]
+fn interrupt_pipe_in() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_alloc_interrupt_pipe()
+        .withf(|a, e, m, i| *a == 5 && *e == 2 && *m == 8 && *i == 10)
+        .returning(|_, _, _, _| {
+            Box::pin(future::ready({
+                let mut ip = MockInterruptPipe::new();
+                ip.expect_is_stalled().returning(|| true);
+                ip
+            }))
+        });
+    let bus = UsbBus::new(hc);
+
+    let r = pin!(bus.interrupt_pipe_in(5, 2, 8, 10));
+    let pipe = r.poll(&mut c).to_option().unwrap();
+    assert!(pipe.is_stalled());
+}
+
 fn is_get_device_descriptor<const N: u16>(
     a: &u8,
     p: &u8,
     s: &SetupPacket,
     d: &DataPhase,
 ) -> bool {
+    let [l0, l1] = N.to_le_bytes();
     *a == 0
         && *p == 8
-        && s.bmRequestType == DEVICE_TO_HOST
-        && s.bRequest == GET_DESCRIPTOR
-        && s.wValue == 0x100
-        && s.wIndex == 0
-        && s.wLength == N
+        && s.to_bytes() == [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, l0, l1]
         && d.is_in()
 }
 
@@ -1009,7 +1112,10 @@ fn new_device_first_call_short() {
     let r = pin!(bus.new_device(UsbSpeed::Full12));
     let rr = r.poll(&mut c);
     let rc = unwrap_poll(rr).unwrap();
-    assert_eq!(rc.unwrap_err(), UsbError::ProtocolError);
+    assert_eq!(
+        rc.unwrap_err(),
+        UsbError::Protocol("device descriptor too short")
+    );
 }
 
 #[test]
@@ -1097,7 +1203,10 @@ fn new_device_second_call_short() {
     let r = pin!(bus.new_device(UsbSpeed::Full12));
     let rr = r.poll(&mut c);
     let rc = unwrap_poll(rr).unwrap();
-    assert_eq!(rc.unwrap_err(), UsbError::ProtocolError);
+    assert_eq!(
+        rc.unwrap_err(),
+        UsbError::Protocol("device descriptor too short")
+    );
 }
 
 fn is_get_hub_descriptor<const ADDR: u8>(
@@ -1138,11 +1247,7 @@ fn is_set_port_power<const ADDR: u8, const N: u8>(
 ) -> bool {
     *a == ADDR
         && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER
-        && s.bRequest == SET_FEATURE
-        && s.wValue == PORT_POWER
-        && s.wIndex == N.into()
-        && s.wLength == 0
+        && s.to_bytes() == [0x23, 0x03, 0x08, 0x00, N, 0x00, 0x00, 0x00]
         && d.is_none()
 }
 
@@ -1321,7 +1426,10 @@ fn new_hub_get_descriptor_short() {
             let r = pin!(f.bus.new_hub(&f.hub_state, unconfigured_device()));
             let rr = r.poll(f.c);
             let rc = unwrap_poll(rr).unwrap();
-            assert_eq!(rc, Err(UsbError::ProtocolError));
+            assert_eq!(
+                rc,
+                Err(UsbError::Protocol("hub descriptor too short"))
+            );
         },
     );
 }
@@ -1431,11 +1539,7 @@ fn is_get_port_status<const N: u8>(
 ) -> bool {
     *a == 5
         && *p == 8
-        && s.bmRequestType == DEVICE_TO_HOST | CLASS_REQUEST | RECIPIENT_OTHER
-        && s.bRequest == GET_STATUS
-        && s.wValue == 0
-        && s.wIndex == N as u16
-        && s.wLength == 4
+        && s.to_bytes() == [0xA3, 0x00, 0x00, 0x00, N, 0x00, 0x04, 0x00]
         && d.is_in()
 }
 
@@ -1453,13 +1557,10 @@ fn is_clear_port_feature<const PORT: u8, const FEATURE: u16>(
     s: &SetupPacket,
     d: &DataPhase,
 ) -> bool {
+    let [f0, f1] = FEATURE.to_le_bytes();
     *a == 5
         && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER
-        && s.bRequest == 1
-        && s.wValue == FEATURE
-        && s.wIndex == PORT as u16
-        && s.wLength == 0
+        && s.to_bytes() == [0x23, 0x01, f0, f1, PORT, 0x00, 0x00, 0x00]
         && d.is_none()
 }
 
@@ -1469,13 +1570,10 @@ fn is_set_port_feature<const PORT: u8, const FEATURE: u16>(
     s: &SetupPacket,
     d: &DataPhase,
 ) -> bool {
+    let [f0, f1] = FEATURE.to_le_bytes();
     *a == 5
         && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER
-        && s.bRequest == 3
-        && s.wValue == FEATURE
-        && s.wIndex == PORT as u16
-        && s.wLength == 0
+        && s.to_bytes() == [0x23, 0x03, f0, f1, PORT, 0x00, 0x00, 0x00]
         && d.is_none()
 }
 
@@ -1508,7 +1606,8 @@ fn handle_hub_packet_connection() {
                     UnconfiguredDevice {
                         usb_address: 31,
                         usb_speed: UsbSpeed::Full12,
-                        packet_size_ep0: 8
+                        packet_size_ep0: 8,
+                        quirks: Quirks::NONE,
                     },
                     DeviceInfo {
                         vid: 0x1234,
@@ -1919,7 +2018,8 @@ fn handle_hub_packet_connected_high_speed() {
                     UnconfiguredDevice {
                         usb_address: 31,
                         usb_speed: UsbSpeed::High480,
-                        packet_size_ep0: 8
+                        packet_size_ep0: 8,
+                        quirks: Quirks::NONE,
                     },
                     DeviceInfo {
                         vid: 0x1234,
@@ -1962,7 +2062,8 @@ fn handle_hub_packet_connected_low_speed() {
                     UnconfiguredDevice {
                         usb_address: 31,
                         usb_speed: UsbSpeed::Low1_5,
-                        packet_size_ep0: 8
+                        packet_size_ep0: 8,
+                        quirks: Quirks::NONE,
                     },
                     DeviceInfo {
                         vid: 0x1234,
@@ -2219,6 +2320,7 @@ fn handle_hub_packet_connected_hub() {
                     packet_size_ep0: 8,
                     in_endpoints_bitmap: 4,
                     out_endpoints_bitmap: 2,
+                    quirks: Quirks::NONE,
                 },))
             );
         },
@@ -2294,6 +2396,95 @@ fn handle_hub_packet_connected_hub_new_hub_pends() {
     );
 }
 
+/// A delay which takes one extra poll, so that other futures get a look-in
+fn yield_delay(_ms: usize) -> impl Future<Output = ()> {
+    let mut yielded = false;
+    future::poll_fn(move |cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+}
+
+/// Expect a hub port to be reset, and the new device at address zero
+/// to be given an address
+fn expect_enumeration_in_sequence<const PORT: u8, const ADDR: u8>(
+    hc: &mut MockHostControllerInner,
+    seq: &mut mockall::Sequence,
+) {
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_set_port_feature::<PORT, 4>) // PORT_RESET
+        .in_sequence(seq)
+        .returning(control_transfer_ok::<0>);
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor::<8>)
+        .in_sequence(seq)
+        .returning(control_transfer_ok_with(device_descriptor_prefix));
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_get_device_descriptor::<18>)
+        .in_sequence(seq)
+        .returning(control_transfer_ok_with(device_descriptor));
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_set_address::<ADDR>)
+        .in_sequence(seq)
+        .returning(control_transfer_ok::<0>);
+}
+
+#[test]
+fn handle_hub_packet_two_ports_enumerate_in_turn() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_get_port_status::<1, 1, 1>(); // CONNECTION, C_PORT_CONNECTION
+            hc.expect_get_port_status::<2, 1, 1>();
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<2, 16>();
+            hc.expect_get_port_status::<1, 3, 0>(); // ENABLED
+            hc.expect_get_port_status::<2, 3, 0>();
+
+            // Everything from port reset to SET_ADDRESS for one port
+            // must be over before the other port is reset
+            let mut seq = mockall::Sequence::new();
+            expect_enumeration_in_sequence::<1, 31>(hc, &mut seq);
+            expect_enumeration_in_sequence::<2, 30>(hc, &mut seq);
+        },
+        |f| {
+            let mut p1 = InterruptPacket::new();
+            p1.address = 5;
+            p1.size = 1;
+            p1.data[0] = 0b10; // port 1
+            let mut p2 = InterruptPacket::new();
+            p2.address = 5;
+            p2.size = 1;
+            p2.data[0] = 0b100; // port 2
+            let mut fut = pin!(future::join(
+                f.bus.handle_hub_packet(&f.hub_state, &p1, yield_delay),
+                f.bus.handle_hub_packet(&f.hub_state, &p2, yield_delay),
+            ));
+
+            let (r1, r2) = (0..20)
+                .find_map(|_| fut.as_mut().poll(f.c).to_option())
+                .unwrap();
+            let Ok(DeviceEvent::Connect(d1, _)) = r1 else {
+                panic!("{:?}", r1);
+            };
+            let Ok(DeviceEvent::Connect(d2, _)) = r2 else {
+                panic!("{:?}", r2);
+            };
+            assert_eq!(d1.address(), 31);
+            assert_eq!(d2.address(), 30);
+        },
+    );
+}
+
 #[test]
 fn handle_hub_packet_enabled_too_many_devices() {
     do_test(
@@ -2355,7 +2546,8 @@ fn device_events_nh() {
                     UnconfiguredDevice {
                         usb_address: 1,
                         usb_speed: UsbSpeed::Full12,
-                        packet_size_ep0: 8
+                        packet_size_ep0: 8,
+                        quirks: Quirks::NONE,
                     },
                     DeviceInfo {
                         vid: 0x1234,
@@ -2369,6 +2561,249 @@ fn device_events_nh() {
     );
 }
 
+fn recording_delay(
+    log: &Rc<RefCell<Vec<usize>>>,
+) -> impl Fn(usize) -> future::Ready<()> + Clone + 'static {
+    let log = log.clone();
+    move |ms| {
+        log.borrow_mut().push(ms);
+        future::ready(())
+    }
+}
+
+fn expect_root_connect(hc: &mut MockHostControllerInner, resets: usize) {
+    hc.expect_multi_interrupt_pipe_ignored();
+    hc.expect_device_detect().returning(|| {
+        let mut mdd = MockDeviceDetect::new();
+        mdd.expect_poll_next().returning(|_| {
+            Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+        });
+        mdd
+    });
+    hc.expect_reset_root_port()
+        .times(resets)
+        .withf(|r| *r)
+        .return_const(());
+    hc.expect_reset_root_port()
+        .times(resets)
+        .withf(|r| !*r)
+        .return_const(());
+}
+
+#[test]
+fn new_bus_has_default_config() {
+    let mut hc = MockHostController::default();
+    hc.inner.expect_multi_interrupt_pipe_ignored();
+    let bus = UsbBus::new(hc);
+    assert_eq!(*bus.config(), EnumerationConfig::default());
+    assert_eq!(bus.config().reset_recovery_ms, 100);
+    assert_eq!(bus.config().vbus_settle_ms, 100);
+}
+
+#[test]
+fn device_events_nh_default_timings() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    do_test_with_config(
+        EnumerationConfig::default(),
+        |hc| {
+            expect_root_connect(hc, 1);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<1>();
+        },
+        |f| {
+            let stream =
+                pin!(f.bus.device_events_no_hubs(recording_delay(&log)));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Connect(_, _))));
+        },
+    );
+    assert_eq!(*log.borrow(), vec![100, 50, 100, 100]);
+}
+
+static DELAY_QUIRK: &[QuirkEntry] = &[QuirkEntry::new(
+    0x1234,
+    0x5678,
+    &[crate::quirks::Quirk::DelayAfterSetAddressMs(20)],
+)];
+
+#[test]
+fn device_events_nh_quirk_delays_after_set_address() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    do_test(
+        |hc| {
+            expect_root_connect(hc, 1);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<1>();
+        },
+        |mut f| {
+            f.bus.set_quirks(DELAY_QUIRK);
+            let stream =
+                pin!(f.bus.device_events_no_hubs(recording_delay(&log)));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            let Some(DeviceEvent::Connect(device, _)) = result else {
+                panic!("expected Connect, got {:?}", result);
+            };
+            assert_eq!(device.quirks().delay_after_set_address_ms, 20);
+        },
+    );
+    assert_eq!(*log.borrow(), vec![50, 10, 20]);
+}
+
+#[test]
+fn device_events_quirk_delays_after_set_address() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    do_test(
+        |hc| {
+            expect_root_connect(hc, 1);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<31>();
+        },
+        |mut f| {
+            f.bus.set_quirks(DELAY_QUIRK);
+            let stream =
+                pin!(f.bus.device_events(&f.hub_state, recording_delay(&log)));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Connect(_, _))));
+        },
+    );
+    assert_eq!(*log.borrow(), vec![50, 10, 20]);
+}
+
+#[test]
+fn configure_keeps_quirks() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_set_configuration::<5, 1>();
+            hc.expect_get_double_configuration::<5>();
+        },
+        |f| {
+            let quirks = Quirks {
+                no_vpd: true,
+                ..Quirks::NONE
+            };
+            let device = UnconfiguredDevice {
+                quirks,
+                ..unconfigured_device()
+            };
+            let r = pin!(f.bus.configure(device, 1));
+            let device = unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            assert_eq!(device.quirks(), quirks);
+        },
+    );
+}
+
+#[test]
+fn device_events_nh_retries() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    do_test_with_config(
+        EnumerationConfig::default(),
+        |hc| {
+            expect_root_connect(hc, 2);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<1>();
+        },
+        |f| {
+            let stream =
+                pin!(f.bus.device_events_no_hubs(recording_delay(&log)));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Connect(_, _))));
+        },
+    );
+    // Debounce and settle only happen once
+    assert_eq!(*log.borrow(), vec![100, 50, 100, 100, 50, 100]);
+}
+
+#[test]
+fn device_events_nh_gives_up() {
+    do_test_with_config(
+        EnumerationConfig {
+            attempts: 2,
+            ..QUICK
+        },
+        |hc| {
+            expect_root_connect(hc, 2);
+            hc.expect_control_transfer()
+                .times(2)
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
+            );
+        },
+    );
+}
+
+#[test]
+fn device_events_nh_zero_attempts_means_one() {
+    do_test_with_config(
+        EnumerationConfig {
+            attempts: 0,
+            ..QUICK
+        },
+        |hc| {
+            expect_root_connect(hc, 1);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_device_descriptor::<8>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
+            );
+        },
+    );
+}
+
+#[test]
+fn device_events_zero_delays_skipped() {
+    do_test_with_config(
+        EnumerationConfig {
+            debounce_ms: 0,
+            reset_ms: 0,
+            reset_recovery_ms: 0,
+            vbus_settle_ms: 0,
+            attempts: 1,
+        },
+        |hc| {
+            expect_root_connect(hc, 1);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<1>();
+        },
+        |f| {
+            // long_delay would never come ready
+            let stream = pin!(f.bus.device_events_no_hubs(long_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert!(matches!(result, Some(DeviceEvent::Connect(_, _))));
+        },
+    );
+}
+
 #[test]
 fn device_events_nh_first_delay_pends() {
     do_test(
@@ -2604,7 +3039,8 @@ fn device_events_root_connect() {
                     UnconfiguredDevice {
                         usb_address: 31,
                         usb_speed: UsbSpeed::Low1_5,
-                        packet_size_ep0: 8
+                        packet_size_ep0: 8,
+                        quirks: Quirks::NONE,
                     },
                     DeviceInfo {
                         vid: 0x1234,
@@ -2864,6 +3300,7 @@ fn device_events_root_connect_is_hub() {
                     packet_size_ep0: 8,
                     in_endpoints_bitmap: 4,
                     out_endpoints_bitmap: 2,
+                    quirks: Quirks::NONE,
                 },))
             );
         },
@@ -2995,7 +3432,7 @@ fn device_events_hub_packet_fails() {
                 Some(DeviceEvent::EnumerationError(
                     0,
                     1,
-                    UsbError::ProtocolError
+                    UsbError::Protocol("empty hub interrupt packet")
                 ))
             );
         },
@@ -3038,20 +3475,105 @@ fn device_events_hub_packet_pends() {
     );
 }
 
-fn is_read_mac_address(
-    a: &u8,
-    p: &u8,
-    s: &SetupPacket,
-    d: &DataPhase,
-) -> bool {
-    *a == 5
-        && *p == 8
-        && s.bmRequestType == DEVICE_TO_HOST | VENDOR_REQUEST
-        && s.bRequest == 0x13
-        && s.wValue == 0
-        && s.wIndex == 0
-        && s.wLength == 6
-        && d.is_in()
+fn stalled_pipe(resumes: usize) -> MockInterruptPipe {
+    let mut mip = MockInterruptPipe::new();
+    mip.expect_poll_next()
+        .times(1)
+        .returning(|_| Poll::Ready(None));
+    mip.expect_is_stalled().times(1).returning(|| true);
+    mip.expect_resume().times(resumes).return_const(());
+    mip.expect_poll_next().returning(|_| Poll::Pending);
+    mip
+}
+
+#[test]
+fn device_events_hub_pipe_stalls() {
+    do_test(
+        |hc| {
+            expect_root_pending(hc);
+            hc.expect_clear_endpoint_feature::<0x81, 0>();
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = Some(stalled_pipe(1));
+            f.hub_state.endpoints.borrow_mut()[0] = (5, 1);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(result, Some(DeviceEvent::None));
+            assert!(f.hub_state.pipes.borrow()[0].is_some());
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[test]
+fn device_events_hub_pipe_stall_clear_fails() {
+    do_test(
+        |hc| {
+            expect_root_pending(hc);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_clear_endpoint_feature::<0x81, 0>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = Some(stalled_pipe(0));
+            f.hub_state.endpoints.borrow_mut()[0] = (5, 1);
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+
+            let poll = stream.as_mut().poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::EnumerationError(0, 1, UsbError::Timeout))
+            );
+            assert!(f.hub_state.pipes.borrow()[0].is_none());
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+        },
+    );
+}
+
+#[test]
+fn device_events_hub_pipe_ends() {
+    do_test(
+        |hc| {
+            expect_root_pending(hc);
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next().returning(|_| Poll::Ready(None));
+                mip.expect_is_stalled().returning(|| false);
+                Some(mip)
+            };
+            f.hub_state.pipes.borrow_mut()[1] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next().times(1).returning(|_| Poll::Pending);
+                Some(mip)
+            };
+            let mut stream = pin!(f.bus.device_events(&f.hub_state, no_delay));
+
+            // Ended pipe is dropped, and the next one still gets polled
+            let poll = stream.as_mut().poll_next(f.c);
+            assert!(poll.is_pending());
+            assert!(f.hub_state.pipes.borrow()[0].is_none());
+        },
+    );
+}
+
+fn is_read_mac_address(
+    a: &u8,
+    p: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    *a == 5
+        && *p == 8
+        && s.to_bytes() == [0xC0, 0x13, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00]
+        && d.is_in()
 }
 
 #[test]
@@ -3174,6 +3696,7 @@ fn device_has_in_endpoints() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     let in_endpoints = d.in_endpoints();
@@ -3191,6 +3714,7 @@ fn open_in_endpoint() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x100,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     let _r = d.open_in_endpoint(8).unwrap();
@@ -3204,6 +3728,7 @@ fn open_in_endpoint_zero() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x1,
         out_endpoints_bitmap: 0x1,
+        quirks: Quirks::NONE,
     };
 
     // EP0 is always control, not bulk
@@ -3218,6 +3743,7 @@ fn open_in_endpoint_fails() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x100,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     assert!(d.open_in_endpoint(7).is_err());
@@ -3231,6 +3757,7 @@ fn open_in_endpoint_ludicrous() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x100,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     assert!(d.open_in_endpoint(70).is_err());
@@ -3244,6 +3771,7 @@ fn open_out_endpoint() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x100,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     let _r = d.open_out_endpoint(15).unwrap();
@@ -3257,6 +3785,7 @@ fn open_out_endpoint_zero() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x1,
         out_endpoints_bitmap: 0x1,
+        quirks: Quirks::NONE,
     };
 
     // EP0 is always control, not bulk
@@ -3271,6 +3800,7 @@ fn open_out_endpoint_fails() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x100,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     assert!(d.open_out_endpoint(7).is_err());
@@ -3284,6 +3814,7 @@ fn open_out_endpoint_ludicrous() {
         packet_size_ep0: 8,
         in_endpoints_bitmap: 0x100,
         out_endpoints_bitmap: 0x8001,
+        quirks: Quirks::NONE,
     };
 
     assert!(d.open_out_endpoint(70).is_err());
@@ -3295,13 +3826,10 @@ fn is_clear_endpoint_feature<const EP: u8, const FEATURE: u16>(
     s: &SetupPacket,
     d: &DataPhase,
 ) -> bool {
+    let [f0, f1] = FEATURE.to_le_bytes();
     *a == 5
         && *p == 8
-        && s.bmRequestType == HOST_TO_DEVICE | RECIPIENT_ENDPOINT
-        && s.bRequest == 1
-        && s.wValue == FEATURE
-        && s.wIndex == EP as u16
-        && s.wLength == 0
+        && s.to_bytes() == [0x02, 0x01, f0, f1, EP, 0x00, 0x00, 0x00]
         && d.is_none()
 }
 
@@ -3318,6 +3846,7 @@ fn clear_halt() {
                 packet_size_ep0: 8,
                 in_endpoints_bitmap: 0x100,
                 out_endpoints_bitmap: 0x8001,
+                quirks: Quirks::NONE,
             };
 
             let ep = d.open_in_endpoint(8).unwrap();
@@ -3344,6 +3873,7 @@ fn clear_halt_fails() {
                 packet_size_ep0: 8,
                 in_endpoints_bitmap: 0x100,
                 out_endpoints_bitmap: 0x8001,
+                quirks: Quirks::NONE,
             };
 
             let ep = d.open_in_endpoint(8).unwrap();
@@ -3370,6 +3900,7 @@ fn clear_halt_pends() {
                 packet_size_ep0: 8,
                 in_endpoints_bitmap: 0x100,
                 out_endpoints_bitmap: 0x8001,
+                quirks: Quirks::NONE,
             };
 
             let ep = d.open_in_endpoint(8).unwrap();
@@ -3383,6 +3914,278 @@ fn clear_halt_pends() {
     );
 }
 
+fn is_get_descriptor_5<const TYPE: u8, const INDEX: u8>(
+    a: &u8,
+    _: &u8,
+    s: &SetupPacket,
+    d: &DataPhase,
+) -> bool {
+    let b = s.to_bytes();
+    *a == 5 && b[0..4] == [0x80, 0x06, INDEX, TYPE] && d.is_in()
+}
+
+fn serial_device_descriptor(bytes: &mut [u8]) -> usize {
+    device_descriptor(bytes);
+    bytes[16] = 3;
+    18
+}
+
+fn serial_string(bytes: &mut [u8]) -> usize {
+    bytes[0..6].copy_from_slice(&[6, 3, b'4', 0, b'2', 0]);
+    6
+}
+
+fn test_usb_device() -> UsbDevice {
+    UsbDevice {
+        usb_address: 5,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x2,
+        out_endpoints_bitmap: 0x4,
+        quirks: Quirks {
+            no_vpd: true,
+            ..Quirks::NONE
+        },
+    }
+}
+
+#[test]
+fn device_identity_no_serial() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<1, 0>)
+                .returning(control_transfer_ok_with(device_descriptor));
+        },
+        |f| {
+            let mut d = test_usb_device();
+            let id = {
+                let r = pin!(f.bus.device_identity(&mut d));
+                r.poll(f.c).to_option().unwrap().unwrap()
+            };
+            assert_eq!(id.vid, 0x1234);
+            assert_eq!(id.pid, 0x5678);
+            assert_eq!(id.serial_hash, 0);
+            assert_eq!(id.capabilities.quirks, d.quirks());
+            assert_eq!(id.capabilities.scsi, None);
+        },
+    );
+}
+
+#[test]
+fn device_identity_serial() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<1, 0>)
+                .returning(control_transfer_ok_with(serial_device_descriptor));
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(|a, p, s, d| {
+                    is_get_descriptor_5::<3, 3>(a, p, s, d)
+                        && s.wIndex == 0x0409
+                })
+                .returning(control_transfer_ok_with(serial_string));
+        },
+        |f| {
+            let mut d = test_usb_device();
+            let r = pin!(f.bus.device_identity(&mut d));
+            let id = r.poll(f.c).to_option().unwrap().unwrap();
+            assert_eq!(
+                id.serial_hash,
+                identity::serial_hash(&[6, 3, b'4', 0, b'2', 0])
+            );
+            assert_eq!(id.capabilities.scsi, None);
+        },
+    );
+}
+
+#[test]
+fn device_identity_serial_stalls() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<1, 0>)
+                .returning(control_transfer_ok_with(serial_device_descriptor));
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<3, 3>)
+                .returning(|_, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                });
+        },
+        |f| {
+            let mut d = test_usb_device();
+            let r = pin!(f.bus.device_identity(&mut d));
+            let id = r.poll(f.c).to_option().unwrap().unwrap();
+            assert_eq!(id.serial_hash, 0);
+        },
+    );
+}
+
+#[test]
+fn device_identity_serial_fails() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<1, 0>)
+                .returning(control_transfer_ok_with(serial_device_descriptor));
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<3, 3>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let mut d = test_usb_device();
+            let r = pin!(f.bus.device_identity(&mut d));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Timeout));
+        },
+    );
+}
+
+#[test]
+fn device_identity_short_descriptor() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<1, 0>)
+                .returning(control_transfer_ok_with(device_descriptor_prefix));
+        },
+        |f| {
+            let mut d = test_usb_device();
+            let r = pin!(f.bus.device_identity(&mut d));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(
+                rr,
+                Err(UsbError::Protocol("device descriptor too short"))
+            );
+        },
+    );
+}
+
+#[test]
+fn device_identity_preloaded() {
+    static KNOWN: [DeviceIdentity; 2] = [
+        DeviceIdentity {
+            vid: 0x1234,
+            pid: 0x5678,
+            serial_hash: 99, // different device, same model
+            capabilities: Capabilities {
+                quirks: Quirks {
+                    no_rsoc: true,
+                    ..Quirks::NONE
+                },
+                scsi: None,
+            },
+        },
+        DeviceIdentity {
+            vid: 0x1234,
+            pid: 0x5678,
+            serial_hash: 0,
+            capabilities: Capabilities {
+                quirks: Quirks {
+                    force_single_lun: true,
+                    ..Quirks::NONE
+                },
+                scsi: Some(identity::ScsiCapabilities {
+                    supports_16: true,
+                    max_transfer_blocks: None,
+                }),
+            },
+        },
+    ];
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_get_descriptor_5::<1, 0>)
+                .returning(control_transfer_ok_with(device_descriptor));
+        },
+        |mut f| {
+            f.bus.preload(&KNOWN);
+            let mut d = test_usb_device();
+            let id = {
+                let r = pin!(f.bus.device_identity(&mut d));
+                r.poll(f.c).to_option().unwrap().unwrap()
+            };
+            let expected = Quirks {
+                no_vpd: true,
+                force_single_lun: true,
+                ..Quirks::NONE
+            };
+            assert_eq!(d.quirks(), expected);
+            assert_eq!(id.capabilities.quirks, expected);
+            assert_eq!(id.capabilities.scsi, KNOWN[1].capabilities.scsi);
+        },
+    );
+}
+
+#[test]
+fn report_identity() {
+    thread_local! {
+        static REPORTED: RefCell<Vec<DeviceIdentity>> =
+            const { RefCell::new(Vec::new()) };
+    }
+    fn on_identity(id: &DeviceIdentity) {
+        REPORTED.with(|r| r.borrow_mut().push(*id));
+    }
+
+    do_test(
+        |_| {},
+        |mut f| {
+            let id = DeviceIdentity {
+                vid: 1,
+                ..Default::default()
+            };
+            f.bus.report_identity(&id); // no callback yet
+            f.bus.set_on_identity(Some(on_identity));
+            f.bus.report_identity(&id);
+            REPORTED.with(|r| assert_eq!(*r.borrow(), vec![id]));
+        },
+    );
+}
+
+#[test]
+fn clear_interrupt_halt() {
+    do_test(
+        |hc| {
+            hc.expect_clear_endpoint_feature::<0x82, 0>();
+        },
+        |f| {
+            let mut pipe = MockInterruptPipe::new();
+            pipe.expect_resume().times(1).return_const(());
+            let r = pin!(f.bus.clear_interrupt_halt(&mut pipe, 5, 2));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(()));
+        },
+    );
+}
+
+#[test]
+fn clear_interrupt_halt_fails() {
+    do_test(
+        |hc| {
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_clear_endpoint_feature::<0x82, 0>)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let mut pipe = MockInterruptPipe::new();
+            pipe.expect_resume().times(0);
+            let r = pin!(f.bus.clear_interrupt_halt(&mut pipe, 5, 2));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Timeout));
+        },
+    );
+}
+
 #[test]
 fn bulk_in_transfer() {
     do_test(
@@ -3404,6 +4207,7 @@ fn bulk_in_transfer() {
                 packet_size_ep0: 8,
                 in_endpoints_bitmap: 0x100,
                 out_endpoints_bitmap: 0x8001,
+                quirks: Quirks::NONE,
             };
 
             let ep = d.open_in_endpoint(8).unwrap();
@@ -3440,6 +4244,7 @@ fn bulk_out_transfer() {
                 packet_size_ep0: 8,
                 in_endpoints_bitmap: 0x100,
                 out_endpoints_bitmap: 0x8102,
+                quirks: Quirks::NONE,
             };
 
             let ep = d.open_out_endpoint(8).unwrap();
@@ -3454,3 +4259,708 @@ fn bulk_out_transfer() {
         },
     );
 }
+
+fn bulk_device() -> UsbDevice {
+    UsbDevice {
+        usb_address: 5,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 8,
+        in_endpoints_bitmap: 0x100,
+        out_endpoints_bitmap: 0x100,
+        quirks: Quirks::NONE,
+    }
+}
+
+/// A bulk IN transfer which expects `len` bytes and the given data
+/// toggle, and returns `n` bytes and flips the toggle
+fn expect_bulk_in_chunk(
+    hc: &mut MockHostControllerInner,
+    seq: &mut mockall::Sequence,
+    len: usize,
+    toggle: bool,
+    n: usize,
+) {
+    hc.expect_bulk_in_transfer()
+        .times(1)
+        .in_sequence(seq)
+        .withf(move |a, e, _, d, t, p| {
+            *a == 5
+                && *e == 8
+                && d.len() == len
+                && *t == TransferType::FixedSize
+                && p.get() == toggle
+        })
+        .returning(move |_, _, _, _, _, p| {
+            p.set(!p.get());
+            Box::pin(future::ready(Ok(n)))
+        });
+}
+
+#[test]
+fn read_exact_chunks() {
+    do_test_with_controller(
+        QUICK,
+        128,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            expect_bulk_in_chunk(hc, &mut seq, 128, false, 128);
+            expect_bulk_in_chunk(hc, &mut seq, 128, true, 128);
+            expect_bulk_in_chunk(hc, &mut seq, 44, false, 44);
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 300];
+            let fut = pin!(ep.read_exact(&f.bus, &mut data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(300));
+            assert!(ep.data_toggle.get());
+            assert!(!ep.is_halted());
+        },
+    );
+}
+
+#[test]
+fn read_exact_chunk_is_whole_packets() {
+    do_test_with_controller(
+        QUICK,
+        100,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            expect_bulk_in_chunk(hc, &mut seq, 64, false, 64);
+            expect_bulk_in_chunk(hc, &mut seq, 36, true, 36);
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 100];
+            let fut = pin!(ep.read_exact(&f.bus, &mut data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(100));
+        },
+    );
+}
+
+#[test]
+fn read_exact_ends_on_short_packet() {
+    do_test_with_controller(
+        QUICK,
+        128,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            expect_bulk_in_chunk(hc, &mut seq, 128, false, 128);
+            expect_bulk_in_chunk(hc, &mut seq, 128, true, 20);
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 300];
+            let fut = pin!(ep.read_exact(&f.bus, &mut data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(148));
+        },
+    );
+}
+
+#[test]
+fn read_exact_ends_on_zlp() {
+    do_test_with_controller(
+        QUICK,
+        128,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            expect_bulk_in_chunk(hc, &mut seq, 128, false, 128);
+            expect_bulk_in_chunk(hc, &mut seq, 128, true, 0);
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 300];
+            let fut = pin!(ep.read_exact(&f.bus, &mut data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(128));
+            assert!(!ep.data_toggle.get());
+        },
+    );
+}
+
+#[test]
+fn read_exact_stall_halts() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_in_transfer().times(1).returning(
+                |_, _, _, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                },
+            );
+            hc.expect_clear_endpoint_feature::<0x88, 0>();
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [0u8; 300];
+            {
+                let fut = pin!(ep.read_exact(&f.bus, &mut data));
+                let rr = fut.poll(f.c).to_option().unwrap();
+                assert_eq!(rr, Err(UsbError::Stall));
+                assert!(ep.is_halted());
+            }
+
+            // No bus traffic this time
+            let fut = pin!(ep.read_exact(&f.bus, &mut data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Stall));
+
+            let r = pin!(f.bus.clear_halt(&ep));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(()));
+            assert!(!ep.is_halted());
+        },
+    );
+}
+
+#[test]
+fn read_exact_uninit_chunks() {
+    do_test_with_controller(
+        QUICK,
+        128,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            for (len, toggle, n) in
+                [(128, false, 128), (128, true, 128), (44, false, 44)]
+            {
+                hc.expect_bulk_in_transfer()
+                    .times(1)
+                    .in_sequence(&mut seq)
+                    .withf(move |_, _, _, d, _, p| {
+                        d.len() == len && p.get() == toggle
+                    })
+                    .returning(move |_, _, _, d, _, p| {
+                        d[..n].fill(0xA5);
+                        p.set(!p.get());
+                        Box::pin(future::ready(Ok(n)))
+                    });
+            }
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [MaybeUninit::uninit(); 300];
+            let mut buf = ReadBuffer::new(&mut data);
+            let rr = {
+                let fut = pin!(ep.read_exact_uninit(&f.bus, &mut buf));
+                fut.poll(f.c).to_option().unwrap()
+            };
+            assert_eq!(rr, Ok(300));
+            assert_eq!(buf.len(), 300);
+            assert!(buf.filled().iter().all(|b| *b == 0xA5));
+        },
+    );
+}
+
+#[test]
+fn read_exact_uninit_ends_on_short_packet() {
+    do_test_with_controller(
+        QUICK,
+        128,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            expect_bulk_in_chunk(hc, &mut seq, 128, false, 128);
+            expect_bulk_in_chunk(hc, &mut seq, 128, true, 20);
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [MaybeUninit::uninit(); 300];
+            let mut buf = ReadBuffer::new(&mut data);
+            let rr = {
+                let fut = pin!(ep.read_exact_uninit(&f.bus, &mut buf));
+                fut.poll(f.c).to_option().unwrap()
+            };
+            assert_eq!(rr, Ok(148));
+            // Only what was received counts as filled
+            assert_eq!(buf.len(), 148);
+        },
+    );
+}
+
+#[test]
+fn read_exact_uninit_stall_halts() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_in_transfer().times(1).returning(
+                |_, _, _, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                },
+            );
+        },
+        |f| {
+            let ep = bulk_device().open_in_endpoint(8).unwrap();
+            let mut data = [MaybeUninit::uninit(); 300];
+            let mut buf = ReadBuffer::new(&mut data);
+            let rr = {
+                let fut = pin!(ep.read_exact_uninit(&f.bus, &mut buf));
+                fut.poll(f.c).to_option().unwrap()
+            };
+            assert_eq!(rr, Err(UsbError::Stall));
+            assert!(ep.is_halted());
+            assert!(buf.is_empty());
+        },
+    );
+}
+
+#[test]
+fn write_all_chunks() {
+    do_test_with_controller(
+        QUICK,
+        128,
+        |hc| {
+            let mut seq = mockall::Sequence::new();
+            for (len, toggle) in [(128, false), (72, true)] {
+                hc.expect_bulk_out_transfer()
+                    .times(1)
+                    .in_sequence(&mut seq)
+                    .withf(move |a, e, _, d, t, p| {
+                        *a == 5
+                            && *e == 8
+                            && d.len() == len
+                            && d[0] == 0
+                            && *t == TransferType::FixedSize
+                            && p.get() == toggle
+                    })
+                    .returning(|_, _, _, d, _, p| {
+                        p.set(!p.get());
+                        Box::pin(future::ready(Ok(d.len())))
+                    });
+            }
+        },
+        |f| {
+            let ep = bulk_device().open_out_endpoint(8).unwrap();
+            let data = [0u8; 200];
+            let fut = pin!(ep.write_all(&f.bus, &data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(200));
+            assert!(!ep.data_toggle.get());
+        },
+    );
+}
+
+#[test]
+fn write_all_stall_halts() {
+    do_test(
+        |hc| {
+            hc.expect_bulk_out_transfer().times(1).returning(
+                |_, _, _, _, _, _| {
+                    Box::pin(future::ready(Err(UsbError::Stall)))
+                },
+            );
+            hc.expect_clear_endpoint_feature::<0x08, 0>();
+        },
+        |f| {
+            let ep = bulk_device().open_out_endpoint(8).unwrap();
+            let data = [0u8; 16];
+            let fut = pin!(ep.write_all(&f.bus, &data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Stall));
+            assert!(ep.is_halted());
+
+            let fut = pin!(ep.write_all(&f.bus, &data));
+            let rr = fut.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Err(UsbError::Stall));
+
+            let r = pin!(f.bus.clear_out_halt(&ep));
+            let rr = r.poll(f.c).to_option().unwrap();
+            assert_eq!(rr, Ok(()));
+            assert!(!ep.is_halted());
+        },
+    );
+}
+
+#[test]
+fn diagnostics_empty() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+        },
+        |f| {
+            assert_eq!(f.bus.diagnostics().count(), 0);
+            assert!(f.bus.device_diagnostics(5).is_none());
+        },
+    );
+}
+
+#[test]
+fn diagnostics_follow_device() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_set_address::<5>();
+            hc.expect_set_configuration::<5, 1>();
+            hc.expect_get_double_configuration::<5>();
+            hc.expect_bulk_in_transfer().returning(|_, _, _, _, _, _| {
+                Box::pin(future::ready(Err(UsbError::Stall)))
+            });
+        },
+        |f| {
+            let r = pin!(f.bus.set_address(unaddressed_device(), 5));
+            let device = unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            let d = f.bus.device_diagnostics(5).unwrap();
+            assert_eq!(d.speed, UsbSpeed::Full12);
+            assert_eq!(d.configuration, None);
+
+            let r = pin!(f.bus.configure(device, 1));
+            let mut device = unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            assert_eq!(
+                f.bus.device_diagnostics(5).unwrap().configuration,
+                Some(1)
+            );
+
+            f.bus.claim_interface(&device, 0, "test").unwrap();
+            assert_eq!(
+                f.bus.claim_interface(&device, 0, "other"),
+                Err(UsbError::InterfaceClaimed)
+            );
+
+            let ep = device.open_in_endpoint(2).unwrap();
+            let mut data = [0u8; 16];
+            let fut = pin!(f.bus.bulk_in_transfer(
+                &ep,
+                &mut data,
+                TransferType::VariableSize
+            ));
+            let rr = unwrap_poll(fut.poll(f.c)).unwrap();
+            assert_eq!(rr, Err(UsbError::Stall));
+
+            let all = f.bus.diagnostics().collect::<Vec<_>>();
+            assert_eq!(all.len(), 1);
+            let d = all[0];
+            assert_eq!(d.address, 5);
+            assert_eq!(d.errors.stalls, 1);
+            assert_eq!(d.errors.last, Some(UsbError::Stall));
+            let claim = d.interfaces().next().unwrap();
+            assert_eq!(claim.interface, 0);
+            assert_eq!(claim.driver, "test");
+            let pipe = d.pipes().next().unwrap();
+            assert_eq!(pipe.endpoint, 0x82);
+            assert_eq!(pipe.endpoint_type, EndpointType::Bulk);
+
+            f.bus.release_interface(&device, 0);
+            f.bus.claim_interface(&device, 0, "other").unwrap();
+        },
+    );
+}
+
+#[test]
+fn diagnostics_count_control_errors() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_set_address::<5>();
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_read_mac_address)
+                .returning(control_transfer_timeout);
+        },
+        |f| {
+            let r = pin!(f.bus.set_address(unaddressed_device(), 5));
+            unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+
+            let mut data = [0u8; 6];
+            let fut = pin!(f.bus.control_transfer(
+                &EXAMPLE_DEVICE,
+                SetupPacket {
+                    bmRequestType: DEVICE_TO_HOST | VENDOR_REQUEST,
+                    bRequest: 0x13,
+                    wValue: 0,
+                    wIndex: 0,
+                    wLength: 6,
+                },
+                DataPhase::In(&mut data),
+            ));
+            let rr = unwrap_poll(fut.poll(f.c)).unwrap();
+            assert_eq!(rr, Err(UsbError::Timeout));
+            assert_eq!(
+                f.bus.device_diagnostics(5).unwrap().errors.timeouts,
+                1
+            );
+        },
+    );
+}
+
+#[test]
+fn diagnostics_forgotten_on_disconnect() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_set_address::<1>();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next()
+                    .returning(|_| Poll::Ready(Some(DeviceStatus::Absent)));
+                mdd
+            });
+        },
+        |f| {
+            let r = pin!(f.bus.set_address(unaddressed_device(), 1));
+            unwrap_poll(r.poll(f.c)).unwrap().unwrap();
+            assert_eq!(f.bus.diagnostics().count(), 1);
+
+            let stream = pin!(f.bus.device_events_no_hubs(no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF)))
+            );
+            assert_eq!(f.bus.diagnostics().count(), 0);
+        },
+    );
+}
+
+fn hub_packet_pipe(address: u8, port: u8) -> Option<MockInterruptPipe> {
+    let mut mip = MockInterruptPipe::new();
+    mip.expect_poll_next().times(1).returning(move |_| {
+        let mut ip = InterruptPacket::new();
+        ip.size = 1;
+        ip.address = address;
+        ip.data[0] = 1 << port;
+        Poll::Ready(Some(ip))
+    });
+    mip.expect_poll_next().returning(|_| Poll::Pending);
+    Some(mip)
+}
+
+fn expect_root_pending(hc: &mut MockHostControllerInner) {
+    hc.expect_device_detect().returning(|| {
+        let mut mdd = MockDeviceDetect::new();
+        mdd.expect_poll_next().returning(|_| Poll::Pending);
+        mdd
+    });
+}
+
+#[test]
+fn events_hub_unplugged() {
+    do_test(
+        |hc| {
+            expect_root_pending(hc);
+            hc.expect_get_port_status::<1, 0, 1>(); // C_PORT_CONNECTION
+            hc.expect_clear_port_feature::<1, 16>(); // C_PORT_CONNECTION
+        },
+        |f| {
+            {
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, true); // 2
+                b.device_connect(1, 2, true); // 3
+                b.device_connect(1, 3, true); // 4
+                b.device_connect(1, 4, true); // 5
+                b.device_connect(5, 1, true); // 6
+                b.device_connect(6, 1, false); // 31
+                b.device_connect(6, 2, false); // 30
+            }
+            assert_eq!(
+                format!("{:?}", f.hub_state.topology()),
+                "0:(1:(2 3 4 5:(6:(30 31))))"
+            );
+            f.hub_state.pipes.borrow_mut()[0] = hub_packet_pipe(5, 1);
+
+            let mut stream = pin!(f.bus.events(&f.hub_state, no_delay));
+            let mut events = Vec::new();
+            for _ in 0..3 {
+                let poll = stream.as_mut().poll_next(f.c);
+                events.push(unwrap_poll(poll).unwrap().unwrap());
+            }
+            assert_eq!(
+                events,
+                vec![
+                    BusEvent::Detached(30),
+                    BusEvent::Detached(31),
+                    BusEvent::Detached(6),
+                ]
+            );
+            assert!(stream.as_mut().poll_next(f.c).is_pending());
+            assert_eq!(
+                format!("{:?}", f.hub_state.topology()),
+                "0:(1:(2 3 4 5))"
+            );
+        },
+    );
+}
+
+#[test]
+fn events_root_unplugged() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next()
+                    .returning(|_| Poll::Ready(Some(DeviceStatus::Absent)));
+                mdd
+            });
+        },
+        |f| {
+            {
+                let mut b = f.hub_state.topology.borrow_mut();
+                b.device_connect(0, 1, true); // 1
+                b.device_connect(1, 1, false); // 31
+                b.device_connect(1, 2, true); // 2
+                b.device_connect(2, 1, false); // 30
+            }
+
+            let mut stream = pin!(f.bus.events(&f.hub_state, no_delay));
+            let mut events = Vec::new();
+            for _ in 0..4 {
+                let poll = stream.as_mut().poll_next(f.c);
+                events.push(unwrap_poll(poll).unwrap().unwrap());
+            }
+            assert_eq!(
+                events,
+                vec![
+                    BusEvent::Detached(30),
+                    BusEvent::Detached(2),
+                    BusEvent::Detached(31),
+                    BusEvent::Detached(1),
+                ]
+            );
+        },
+    );
+}
+
+#[test]
+fn events_nothing_attached_unplugged() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            hc.expect_device_detect().returning(|| {
+                let mut mdd = MockDeviceDetect::new();
+                mdd.expect_poll_next()
+                    .times(1)
+                    .returning(|_| Poll::Ready(Some(DeviceStatus::Absent)));
+                mdd.expect_poll_next().returning(|_| Poll::Pending);
+                mdd
+            });
+        },
+        |f| {
+            let stream = pin!(f.bus.events(&f.hub_state, no_delay));
+            assert!(stream.poll_next(f.c).is_pending());
+        },
+    );
+}
+
+#[test]
+fn events_root_connect() {
+    do_test(
+        |hc| {
+            hc.expect_multi_interrupt_pipe_ignored();
+            expect_root_connect(hc, 1);
+            hc.expect_get_device_descriptor_prefix();
+            hc.expect_get_device_descriptor();
+            hc.expect_set_address::<31>();
+        },
+        |f| {
+            let stream = pin!(f.bus.events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(BusEvent::Attached(
+                    UnconfiguredDevice {
+                        usb_address: 31,
+                        usb_speed: UsbSpeed::Full12,
+                        packet_size_ep0: 8,
+                        quirks: Quirks::NONE,
+                    },
+                    DeviceInfo {
+                        vid: 0x1234,
+                        pid: 0x5678,
+                        class: 0,
+                        subclass: 0,
+                    }
+                ))
+            );
+        },
+    );
+}
+
+#[test]
+fn events_enumeration_failed() {
+    do_test(
+        |hc| {
+            expect_root_pending(hc);
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next()
+                    .returning(|_| Poll::Ready(Some(InterruptPacket::new())));
+                Some(mip)
+            };
+            let stream = pin!(f.bus.events(&f.hub_state, no_delay));
+            let poll = stream.poll_next(f.c);
+            let result = unwrap_poll(poll).unwrap();
+            assert_eq!(
+                result,
+                Some(BusEvent::EnumerationFailed(
+                    0,
+                    1,
+                    UsbError::Protocol("empty hub interrupt packet")
+                ))
+            );
+        },
+    );
+}
+
+#[test]
+fn events_skip_none() {
+    do_test(
+        |hc| {
+            expect_root_pending(hc);
+        },
+        |f| {
+            f.hub_state.pipes.borrow_mut()[0] = {
+                let mut mip = MockInterruptPipe::new();
+                mip.expect_poll_next().times(1).returning(|_| {
+                    let mut ip = InterruptPacket::new();
+                    ip.size = 1;
+                    Poll::Ready(Some(ip))
+                });
+                mip.expect_poll_next().returning(|_| Poll::Pending);
+                Some(mip)
+            };
+            let stream = pin!(f.bus.events(&f.hub_state, no_delay));
+            assert!(stream.poll_next(f.c).is_pending());
+        },
+    );
+}
+
+#[test]
+fn try_interrupt_pipe_in() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_try_alloc_interrupt_pipe()
+        .withf(|a, e, m, i| *a == 5 && *e == 2 && *m == 8 && *i == 10)
+        .returning(|_, _, _, _| Ok(MockInterruptPipe::new()));
+    let bus = UsbBus::new(hc);
+
+    assert!(bus.try_interrupt_pipe_in(5, 2, 8, 10).is_ok());
+}
+
+#[test]
+fn try_interrupt_pipe_in_all_in_use() {
+    let mut hc = MockHostController::default();
+    hc.inner
+        .expect_try_alloc_interrupt_pipe()
+        .returning(|_, _, _, _| Err(UsbError::AllPipesInUse));
+    let bus = UsbBus::new(hc);
+
+    assert!(matches!(
+        bus.try_interrupt_pipe_in(5, 2, 8, 10),
+        Err(UsbError::AllPipesInUse)
+    ));
+}
+
+#[test]
+fn capabilities() {
+    let mut hc = MockHostController::default();
+    hc.capabilities.isochronous = true;
+    hc.capabilities.interrupt_pipes = 15;
+    let bus = UsbBus::new(hc);
+
+    let c = bus.capabilities();
+    assert!(c.isochronous);
+    assert_eq!(c.interrupt_pipes, 15);
+}
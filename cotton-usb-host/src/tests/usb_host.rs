@@ -0,0 +1,555 @@
+use super::*;
+use crate::mocks::{
+    MockDeviceDetect, MockHostController, MockHostControllerInner,
+};
+use crate::usb_bus::{
+    DataPhase, DeviceStatus, EnumerationConfig, UsbDevice, UsbSpeed,
+};
+use crate::wire::{InterfaceDescriptor, SetupPacket};
+use futures::future;
+use std::cell::{Cell, RefCell};
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{Poll, Wake, Waker};
+extern crate alloc;
+
+struct NoOpWaker;
+
+impl Wake for NoOpWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn no_delay(_ms: usize) -> impl Future<Output = ()> {
+    future::ready(())
+}
+
+const QUICK: EnumerationConfig = EnumerationConfig {
+    debounce_ms: 0,
+    reset_ms: 50,
+    reset_recovery_ms: 10,
+    vbus_settle_ms: 0,
+    attempts: 1,
+};
+
+const INFO: DeviceInfo = DeviceInfo {
+    vid: 0x1234,
+    pid: 0x5678,
+    class: 0,
+    subclass: 0,
+};
+
+/// One configuration (value 1) with one mass-storage interface
+const CONFIGURATION: [u8; 18] = [
+    9, 2, 18, 0, 1, 1, 0, 0x80, 50, // configuration
+    9, 4, 0, 0, 0, 8, 6, 0x50, 0, // interface
+];
+
+/// As CONFIGURATION, but followed by more descriptors than fit in
+/// CONFIGURATION_BUFFER_SIZE (all of them unknown class-specific ones)
+const LONG_CONFIGURATION: [u8; 300] = {
+    let mut c = [0u8; 300];
+    let mut i = 0;
+    while i < CONFIGURATION.len() {
+        c[i] = CONFIGURATION[i];
+        i += 1;
+    }
+    while i < 300 {
+        c[i] = 6;
+        c[i + 1] = 0x24;
+        i += 6;
+    }
+    c[2] = 44; // wTotalLength = 300
+    c[3] = 1;
+    c
+};
+
+type Transfer = Pin<Box<dyn Future<Output = Result<usize, UsbError>>>>;
+
+fn is_request<const REQUEST: u8, const VALUE_HI: u8>(
+    _: &u8,
+    _: &u8,
+    s: &SetupPacket,
+    _: &DataPhase,
+) -> bool {
+    let b = s.to_bytes();
+    b[1] == REQUEST && b[3] == VALUE_HI
+}
+
+fn reply_with(
+    bytes: &'static [u8],
+) -> impl FnMut(u8, u8, SetupPacket, DataPhase) -> Transfer {
+    move |_, _, _, mut d| {
+        let mut n = 0;
+        d.in_with(|buf| {
+            n = bytes.len().min(buf.len());
+            buf[0..n].copy_from_slice(&bytes[0..n]);
+        });
+        Box::pin(future::ready(Ok(n)))
+    }
+}
+
+fn fail(_: u8, _: u8, _: SetupPacket, _: DataPhase) -> Transfer {
+    Box::pin(future::ready(Err(UsbError::Stall)))
+}
+
+fn ok(_: u8, _: u8, _: SetupPacket, _: DataPhase) -> Transfer {
+    Box::pin(future::ready(Ok(0)))
+}
+
+const DEVICE_DESCRIPTOR: [u8; 18] = [
+    18, 1, 0, 2, 0, 0, 0, 8, 0x34, 0x12, 0x78, 0x56, 0, 1, 0, 0, 0, 1,
+];
+
+/// Root port reports a device, then `then` forever after
+fn expect_root(hc: &mut MockHostControllerInner, then: Option<DeviceStatus>) {
+    hc.expect_try_alloc_interrupt_pipe().times(0);
+    hc.expect_device_detect().returning(move || {
+        let mut mdd = MockDeviceDetect::new();
+        mdd.expect_poll_next().times(1).returning(|_| {
+            Poll::Ready(Some(DeviceStatus::Present(UsbSpeed::Full12)))
+        });
+        if let Some(status) = then {
+            mdd.expect_poll_next()
+                .times(1)
+                .returning(move |_| Poll::Ready(Some(status)));
+        }
+        mdd.expect_poll_next().returning(|_| Poll::Pending);
+        mdd
+    });
+    hc.expect_reset_root_port().withf(|r| *r).return_const(());
+    hc.expect_reset_root_port().withf(|r| !*r).return_const(());
+}
+
+/// GET_DESCRIPTOR(Device) twice, then SET_ADDRESS
+fn expect_enumeration(hc: &mut MockHostControllerInner) {
+    hc.expect_control_transfer()
+        .times(2)
+        .withf(is_request::<6, 1>)
+        .returning(reply_with(&DEVICE_DESCRIPTOR));
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_request::<5, 0>)
+        .returning(ok);
+}
+
+fn expect_get_configuration(hc: &mut MockHostControllerInner) {
+    // Header first, then the whole sequence
+    hc.expect_control_transfer()
+        .times(2)
+        .withf(is_request::<6, 2>)
+        .returning(reply_with(&CONFIGURATION));
+}
+
+fn expect_set_configuration(hc: &mut MockHostControllerInner) {
+    hc.expect_control_transfer()
+        .times(1)
+        .withf(is_request::<9, 0>)
+        .returning(ok);
+}
+
+#[derive(Default)]
+struct InterfaceClass(Option<u8>);
+
+impl DescriptorVisitor for InterfaceClass {
+    fn on_interface(&mut self, i: &InterfaceDescriptor) {
+        self.0 = Some(i.bInterfaceClass);
+    }
+}
+
+/// A driver for one interface class
+struct TestDriver {
+    name: &'static str,
+    class: u8,
+    probes: Cell<usize>,
+    started: RefCell<Option<UsbDevice>>,
+    stopped: Cell<Option<u8>>,
+}
+
+impl TestDriver {
+    fn new(name: &'static str, class: u8) -> Self {
+        Self {
+            name,
+            class,
+            probes: Cell::new(0),
+            started: RefCell::new(None),
+            stopped: Cell::new(None),
+        }
+    }
+}
+
+impl Driver for TestDriver {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn probe(&self, info: &DeviceInfo, descriptors: &[u8]) -> Option<u8> {
+        assert_eq!(*info, INFO);
+        self.probes.set(self.probes.get() + 1);
+        let mut ic = InterfaceClass::default();
+        crate::wire::parse_descriptors(descriptors, &mut ic);
+        (ic.0 == Some(self.class)).then_some(1)
+    }
+
+    fn start(&self, device: UsbDevice, info: &DeviceInfo) {
+        assert_eq!(*info, INFO);
+        *self.started.borrow_mut() = Some(device);
+    }
+
+    fn stop(&self, address: u8) {
+        self.stopped.set(Some(address));
+    }
+}
+
+fn do_test<
+    SetupFn: FnMut(&mut MockHostControllerInner),
+    TestFn: FnMut(&UsbHost<MockHostController>, &mut core::task::Context),
+>(
+    drivers: &[&dyn Driver],
+    mut setup: SetupFn,
+    mut test: TestFn,
+) {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+
+    let mut hc = MockHostController::default();
+    setup(&mut hc.inner);
+
+    let host = UsbHost::with_bus(UsbBus::with_config(hc, QUICK), drivers);
+    test(&host, &mut c);
+}
+
+#[test]
+fn new_host() {
+    let mut hc = MockHostController::default();
+    hc.inner.expect_try_alloc_interrupt_pipe().times(0);
+    let host = UsbHost::new(hc, &[]);
+    assert_eq!(*host.bus().config(), EnumerationConfig::default());
+    assert_eq!(host.topology().depth(1), None);
+    assert_eq!(host.device_info(1), None);
+    assert_eq!(host.driver_name(1), None);
+    assert_eq!(
+        host.parse_configuration(1, &mut InterfaceClass::default()),
+        None
+    );
+}
+
+#[test]
+fn driver_started() {
+    let hid = TestDriver::new("hid", 3);
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&hid, &msc],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+            expect_set_configuration(hc);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(HostEvent::Started {
+                    address: 31,
+                    info: INFO,
+                    driver: "msc",
+                }))
+            );
+            assert!(events.as_mut().poll_next(c).is_pending());
+        },
+    );
+    assert_eq!(hid.probes.get(), 1);
+    assert!(hid.started.borrow().is_none());
+    assert_eq!(msc.probes.get(), 1);
+    assert_eq!(msc.started.borrow().as_ref().unwrap().address(), 31);
+}
+
+#[test]
+fn first_driver_wins() {
+    let first = TestDriver::new("first", 8);
+    let second = TestDriver::new("second", 8);
+    do_test(
+        &[&first, &second],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+            expect_set_configuration(hc);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            assert!(matches!(
+                poll,
+                Poll::Ready(Some(HostEvent::Started {
+                    driver: "first",
+                    ..
+                }))
+            ));
+        },
+    );
+    assert_eq!(second.probes.get(), 0);
+    assert!(second.started.borrow().is_none());
+}
+
+#[test]
+fn accessors_after_start() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+            expect_set_configuration(hc);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let _ = events.as_mut().poll_next(c);
+
+            assert_eq!(host.device_info(31), Some(INFO));
+            assert_eq!(host.driver_name(31), Some("msc"));
+            let mut ic = InterfaceClass::default();
+            assert_eq!(host.parse_configuration(31, &mut ic), Some(true));
+            assert_eq!(ic.0, Some(8));
+            assert_eq!(host.topology().depth(31), Some(1));
+        },
+    );
+}
+
+#[test]
+fn unclaimed() {
+    let hid = TestDriver::new("hid", 3);
+    do_test(
+        &[&hid],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            let Poll::Ready(Some(HostEvent::Unclaimed(device, info))) = poll
+            else {
+                panic!("{:?}", poll);
+            };
+            assert_eq!(device.address(), 31);
+            assert_eq!(info, INFO);
+            assert_eq!(host.device_info(31), Some(INFO));
+            assert_eq!(host.driver_name(31), None);
+        },
+    );
+    assert_eq!(hid.probes.get(), 1);
+}
+
+#[test]
+fn get_configuration_fails() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_request::<6, 2>)
+                .returning(fail);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(HostEvent::StartFailed(31, UsbError::Stall)))
+            );
+            assert_eq!(host.device_info(31), Some(INFO));
+            assert_eq!(
+                host.parse_configuration(31, &mut InterfaceClass::default()),
+                Some(true)
+            );
+        },
+    );
+    assert_eq!(msc.probes.get(), 0);
+}
+
+#[test]
+fn configuration_too_long() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            hc.expect_control_transfer()
+                .times(2)
+                .withf(is_request::<6, 2>)
+                .returning(reply_with(&LONG_CONFIGURATION));
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(HostEvent::StartFailed(
+                    31,
+                    UsbError::BufferTooSmall
+                )))
+            );
+            let mut ic = InterfaceClass::default();
+            assert_eq!(host.parse_configuration(31, &mut ic), Some(false));
+            assert_eq!(ic.0, Some(8));
+        },
+    );
+    assert_eq!(msc.probes.get(), 0);
+}
+
+#[test]
+fn configuration_long_but_fits() {
+    let w = Waker::from(Arc::new(NoOpWaker));
+    let mut c = core::task::Context::from_waker(&w);
+    let mut hc = MockHostController::default();
+    expect_root(&mut hc.inner, None);
+    expect_enumeration(&mut hc.inner);
+    hc.inner
+        .expect_control_transfer()
+        .times(2)
+        .withf(is_request::<6, 2>)
+        .returning(reply_with(&LONG_CONFIGURATION));
+    expect_set_configuration(&mut hc.inner);
+    let msc = TestDriver::new("msc", 8);
+    let drivers: [&dyn Driver; 1] = [&msc];
+    let host = UsbHost::<_, 512>::with_bus_sized(
+        UsbBus::with_config(hc, QUICK),
+        &drivers,
+    );
+    let mut events = pin!(host.events(no_delay));
+    assert!(matches!(
+        events.as_mut().poll_next(&mut c),
+        Poll::Ready(Some(HostEvent::Started { address: 31, .. }))
+    ));
+    let mut ic = InterfaceClass::default();
+    assert_eq!(host.parse_configuration(31, &mut ic), Some(true));
+    assert_eq!(ic.0, Some(8));
+    assert_eq!(msc.probes.get(), 1);
+}
+
+#[test]
+fn configure_fails() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+            hc.expect_control_transfer()
+                .times(1)
+                .withf(is_request::<9, 0>)
+                .returning(fail);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(HostEvent::StartFailed(31, UsbError::Stall)))
+            );
+            assert_eq!(host.driver_name(31), None);
+        },
+    );
+    assert!(msc.started.borrow().is_none());
+}
+
+#[test]
+fn enumeration_fails() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, None);
+            hc.expect_control_transfer()
+                .withf(is_request::<6, 1>)
+                .returning(fail);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(
+                poll,
+                Poll::Ready(Some(HostEvent::EnumerationFailed(
+                    0,
+                    1,
+                    UsbError::Stall
+                )))
+            );
+        },
+    );
+    assert_eq!(msc.probes.get(), 0);
+}
+
+#[test]
+fn driver_stopped_on_detach() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, Some(DeviceStatus::Absent));
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+            expect_set_configuration(hc);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let _ = events.as_mut().poll_next(c);
+            assert_eq!(msc.stopped.get(), None);
+
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(poll, Poll::Ready(Some(HostEvent::Detached(31))));
+            assert_eq!(msc.stopped.get(), Some(31));
+            assert_eq!(host.device_info(31), None);
+            assert_eq!(host.driver_name(31), None);
+        },
+    );
+}
+
+#[test]
+fn unclaimed_detach_stops_nothing() {
+    let hid = TestDriver::new("hid", 3);
+    do_test(
+        &[&hid],
+        |hc| {
+            expect_root(hc, Some(DeviceStatus::Absent));
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+        },
+        |host, c| {
+            let mut events = pin!(host.events(no_delay));
+            let _ = events.as_mut().poll_next(c);
+            let poll = events.as_mut().poll_next(c);
+            assert_eq!(poll, Poll::Ready(Some(HostEvent::Detached(31))));
+            assert_eq!(host.device_info(31), None);
+        },
+    );
+    assert_eq!(hid.stopped.get(), None);
+}
+
+#[test]
+fn run_starts_drivers() {
+    let msc = TestDriver::new("msc", 8);
+    do_test(
+        &[&msc],
+        |hc| {
+            expect_root(hc, None);
+            expect_enumeration(hc);
+            expect_get_configuration(hc);
+            expect_set_configuration(hc);
+        },
+        |host, c| {
+            let run = pin!(host.run(no_delay));
+            assert!(run.poll(c).is_pending());
+        },
+    );
+    assert_eq!(msc.started.borrow().as_ref().unwrap().address(), 31);
+}
@@ -10,6 +10,7 @@ struct Interface {
 struct TestVisitor {
     configuration: Option<ConfigurationDescriptor>,
     interfaces: Vec<Interface>,
+    others: Vec<Vec<u8>>,
 }
 
 impl DescriptorVisitor for TestVisitor {
@@ -31,7 +32,9 @@ impl DescriptorVisitor for TestVisitor {
         self.interfaces.last_mut().unwrap().endpoints.push(*e);
     }
 
-    fn on_other(&mut self, _d: &[u8]) {}
+    fn on_other(&mut self, d: &[u8]) {
+        self.others.push(d.to_vec());
+    }
 }
 
 struct IgnoreVisitor;
@@ -97,8 +100,274 @@ fn invalid_descriptors() {
     parse_descriptors(&[3, 5, 1], &mut ShowDescriptors);
 }
 
+#[test]
+fn long_descriptors() {
+    // Audio-class endpoint descriptors have two extra bytes
+    let mut v = TestVisitor::default();
+    parse_descriptors(
+        &[
+            9, 2, 27, 0, 1, 1, 0, 0x80, 50, // configuration
+            9, 4, 1, 0, 1, 1, 2, 0, 0, // interface
+            9, 5, 0x81, 5, 0xC0, 0, 1, 0, 0, // endpoint
+        ],
+        &mut v,
+    );
+    assert_eq!(v.interfaces.len(), 1);
+    assert_eq!(v.interfaces[0].endpoints.len(), 1);
+    assert_eq!(v.interfaces[0].endpoints[0].bEndpointAddress, 0x81);
+    assert_eq!(v.interfaces[0].endpoints[0].bInterval, 1);
+}
+
+#[test]
+fn short_descriptors() {
+    // Too short to be an interface: skipped, but parsing carries on, as
+    // far as a minimal (two-byte) descriptor right at the end
+    let mut v = TestVisitor::default();
+    parse_descriptors(
+        &[9, 2, 16, 0, 1, 1, 0, 0x80, 50, 5, 4, 1, 0, 1, 2, 0x30],
+        &mut v,
+    );
+    assert!(v.configuration.is_some());
+    assert!(v.interfaces.is_empty());
+    assert_eq!(v.others, [[2, 0x30]]);
+}
+
+#[test]
+fn bad_lengths() {
+    // Length running off the end
+    let mut v = TestVisitor::default();
+    parse_descriptors(&[9, 2, 16, 0, 1, 1, 0, 0x80, 50, 7, 5, 0x81], &mut v);
+    assert!(v.configuration.is_some());
+    assert!(v.others.is_empty());
+
+    // Zero length: would otherwise loop forever
+    let mut v = TestVisitor::default();
+    parse_descriptors(&[0, 36, 9, 2, 9, 0, 1, 1, 0, 0x80, 50], &mut v);
+    assert!(v.configuration.is_none());
+}
+
 #[test]
 fn reserved_descriptor() {
     // Mostly a test for Miri
     parse_descriptors(&[3, 96, 1], &mut ShowDescriptors);
 }
+
+#[test]
+fn setup_packet_debug() {
+    let s = SetupPacket {
+        bmRequestType: DEVICE_TO_HOST,
+        bRequest: GET_DESCRIPTOR,
+        wValue: (DEVICE_DESCRIPTOR as u16) << 8,
+        wIndex: 0,
+        wLength: 18,
+    };
+    assert_eq!(
+        format!("{:?}", s),
+        "SetupPacket { bmRequestType: 0x80, bRequest: 0x06, \
+         wValue: 0x0100, wIndex: 0x0000, wLength: 0x0012 }"
+    );
+}
+
+#[test]
+fn setup_packet_to_bytes() {
+    let s = SetupPacket {
+        bmRequestType: DEVICE_TO_HOST,
+        bRequest: GET_DESCRIPTOR,
+        wValue: 0x0100,
+        wIndex: 0x0409,
+        wLength: 0x0012,
+    };
+    assert_eq!(
+        s.to_bytes(),
+        [0x80, 0x06, 0x00, 0x01, 0x09, 0x04, 0x12, 0x00]
+    );
+}
+
+#[test]
+fn setup_packet_from_bytes() {
+    // SET_ADDRESS(5), as seen in a USB capture
+    let s = SetupPacket::from_bytes([0x00, 0x05, 0x05, 0x00, 0, 0, 0, 0]);
+    assert_eq!(s.bmRequestType, HOST_TO_DEVICE);
+    assert_eq!(s.bRequest, SET_ADDRESS);
+    assert_eq!(s.wValue, 5);
+    assert_eq!(s.wIndex, 0);
+    assert_eq!(s.wLength, 0);
+}
+
+#[test]
+fn setup_packet_endianness() {
+    let s =
+        SetupPacket::from_bytes([1, 2, 0x34, 0x12, 0x78, 0x56, 0xBC, 0x9A]);
+    assert_eq!(s.wValue, 0x1234);
+    assert_eq!(s.wIndex, 0x5678);
+    assert_eq!(s.wLength, 0x9ABC);
+}
+
+#[test]
+fn setup_packet_round_trip() {
+    let s = SetupPacket {
+        bmRequestType: DEVICE_TO_HOST | CLASS_REQUEST | RECIPIENT_OTHER,
+        bRequest: GET_STATUS,
+        wValue: 0xFEDC,
+        wIndex: 3,
+        wLength: 4,
+    };
+    assert_eq!(SetupPacket::from_bytes(s.to_bytes()), s);
+    for i in 0..=255u8 {
+        let b = [i, !i, i, i ^ 0x55, !i, i, i.rotate_left(3), i];
+        assert_eq!(SetupPacket::from_bytes(b).to_bytes(), b);
+    }
+}
+
+#[test]
+fn descriptor_type_try_from() {
+    let mut known = 0;
+    for i in 0..=255u8 {
+        match DescriptorType::try_from(i) {
+            Ok(t) => {
+                assert_eq!(u8::from(t), i);
+                known += 1;
+            }
+            Err(e) => assert_eq!(e, i),
+        }
+    }
+    assert_eq!(known, 18);
+    assert_eq!(DescriptorType::try_from(0x29), Ok(DescriptorType::Hub));
+    assert_eq!(DescriptorType::try_from(0x0F), Ok(DescriptorType::Bos));
+    assert_eq!(DescriptorType::try_from(0), Err(0));
+}
+
+#[test]
+fn standard_request_try_from() {
+    let mut known = 0;
+    for i in 0..=255u8 {
+        match StandardRequest::try_from(i) {
+            Ok(r) => {
+                assert_eq!(u8::from(r), i);
+                known += 1;
+            }
+            Err(e) => assert_eq!(e, i),
+        }
+    }
+    assert_eq!(known, 11);
+    assert_eq!(StandardRequest::try_from(2), Err(2));
+    assert_eq!(StandardRequest::try_from(4), Err(4));
+}
+
+#[test]
+fn recipient_try_from() {
+    for i in 0..=255u8 {
+        match Recipient::try_from(i) {
+            Ok(r) => assert_eq!(r as u8, i),
+            Err(e) => {
+                assert_eq!(e, i);
+                assert!(i > 3);
+            }
+        }
+    }
+}
+
+#[test]
+fn request_type_type_try_from() {
+    for i in 0..=255u8 {
+        match RequestTypeType::try_from(i) {
+            Ok(t) => assert_eq!(t as u8, i),
+            Err(e) => {
+                assert_eq!(e, i);
+                assert!(i > 2);
+            }
+        }
+    }
+}
+
+#[test]
+fn request_type_bits() {
+    assert_eq!(RequestType::new(Direction::In).bits(), DEVICE_TO_HOST);
+    assert_eq!(RequestType::new(Direction::Out).bits(), HOST_TO_DEVICE);
+    assert_eq!(
+        u8::from(
+            RequestType::new(Direction::Out)
+                .kind(RequestTypeType::Class)
+                .recipient(Recipient::Other)
+        ),
+        HOST_TO_DEVICE | CLASS_REQUEST | RECIPIENT_OTHER
+    );
+    assert_eq!(
+        RequestType::new(Direction::Out)
+            .kind(RequestTypeType::Vendor)
+            .recipient(Recipient::Interface)
+            .direction(Direction::In)
+            .bits(),
+        DEVICE_TO_HOST | VENDOR_REQUEST | RECIPIENT_INTERFACE
+    );
+}
+
+#[test]
+fn request_type_try_from() {
+    let mut valid = 0;
+    for i in 0..=255u8 {
+        match RequestType::try_from(i) {
+            Ok(rt) => {
+                assert_eq!(rt.bits(), i);
+                valid += 1;
+            }
+            Err(e) => assert_eq!(e, i),
+        }
+    }
+    // 2 directions x 3 types x 4 recipients
+    assert_eq!(valid, 24);
+    assert_eq!(RequestType::try_from(0x60), Err(0x60));
+    assert_eq!(RequestType::try_from(0x04), Err(0x04));
+}
+
+#[test]
+fn setup_packet_constructors() {
+    assert_eq!(
+        SetupPacket::get_descriptor(
+            RequestType::new(Direction::In),
+            DescriptorType::String,
+            2,
+            0x0409,
+            255
+        )
+        .to_bytes(),
+        [0x80, 0x06, 0x02, 0x03, 0x09, 0x04, 0xFF, 0x00]
+    );
+    assert_eq!(
+        SetupPacket::set_address(7).to_bytes(),
+        [0x00, 0x05, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(
+        SetupPacket::set_configuration(1).to_bytes(),
+        [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(
+        SetupPacket::set_interface(1, 2).to_bytes(),
+        [0x01, 0x0B, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(
+        SetupPacket::get_status(RequestType::new(Direction::In), 0, 2)
+            .to_bytes(),
+        [0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]
+    );
+    assert_eq!(
+        SetupPacket::clear_feature(
+            RequestType::new(Direction::Out).recipient(Recipient::Endpoint),
+            ENDPOINT_HALT,
+            0x81
+        )
+        .to_bytes(),
+        [0x02, 0x01, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(
+        SetupPacket::set_feature(
+            RequestType::new(Direction::Out)
+                .kind(RequestTypeType::Class)
+                .recipient(Recipient::Other),
+            PORT_RESET,
+            3
+        )
+        .to_bytes(),
+        [0x23, 0x03, 0x04, 0x00, 0x03, 0x00, 0x00, 0x00]
+    );
+}
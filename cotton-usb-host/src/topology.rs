@@ -105,6 +105,24 @@ impl Topology {
         self.parent.get(device as usize).is_some_and(|x| *x > 0)
     }
 
+    /// How many hubs away from the root is this device?
+    ///
+    /// A device attached directly to the root port is at depth 1, a
+    /// device attached to a hub on the root port is at depth 2, and so
+    /// on. Returns `None` if the device is not present.
+    pub fn depth(&self, device: u8) -> Option<u8> {
+        let mut depth = 0;
+        let mut current = device;
+        while current != 0 {
+            if !self.is_present(current) || depth >= MAX_HUBS {
+                return None;
+            }
+            depth += 1;
+            current = self.parent[current as usize] & 15;
+        }
+        Some(depth)
+    }
+
     /// A new USB device has been connected
     ///
     /// # Parameters
@@ -1,13 +1,15 @@
+use crate::async_pool::Pool;
 use crate::bitset::BitSet;
 use crate::debug;
+use crate::diagnostics::{DeviceDiagnostics, PipeInfo, Registry};
+use crate::identity::{self, Capabilities, DeviceIdentity, OnIdentity};
+use crate::quirks::{self, QuirkEntry, Quirks};
 use crate::topology::Topology;
 use crate::wire::{
-    ConfigurationDescriptor, DescriptorVisitor, EndpointDescriptor,
-    HubDescriptor, SetupPacket, CLASS_REQUEST, CLEAR_FEATURE,
-    CONFIGURATION_DESCRIPTOR, DEVICE_DESCRIPTOR, DEVICE_TO_HOST,
-    GET_DESCRIPTOR, GET_STATUS, HOST_TO_DEVICE, HUB_CLASSCODE, HUB_DESCRIPTOR,
-    PORT_POWER, PORT_RESET, RECIPIENT_OTHER, SET_ADDRESS, SET_CONFIGURATION,
-    SET_FEATURE,
+    ConfigurationDescriptor, DescriptorType, DescriptorVisitor, Direction,
+    EndpointDescriptor, EndpointType, HubDescriptor, Recipient, RequestType,
+    RequestTypeType, SetupPacket, ENDPOINT_HALT, HUB_CLASSCODE, PORT_POWER,
+    PORT_RESET,
 };
 use core::cell::{Cell, RefCell};
 use core::pin::Pin;
@@ -16,10 +18,42 @@ use futures::future::FutureExt;
 use futures::{Future, Stream, StreamExt};
 
 pub use crate::host_controller::{
-    DataPhase, DeviceStatus, HostController, InterruptPacket, TransferType,
-    UsbError, UsbSpeed,
+    ControllerCapabilities, DataPhase, DeviceStatus, HostController,
+    InterruptPacket, InterruptPipe, ReadBuffer, TransferType, UsbError,
+    UsbSpeed,
 };
 
+/// The language ID for US English (USB LANGIDs, version 1.0)
+const LANGID_EN_US: u16 = 0x0409;
+
+/// The size of a configuration descriptor (not including the
+/// interface and endpoint descriptors which follow it)
+const CONFIGURATION_DESCRIPTOR_SIZE: usize = 9;
+
+/// How much of a configuration-descriptor sequence
+/// [`UsbBus::get_configuration()`] can read
+///
+/// Enough for most devices, including composite devices and UAS
+/// drives; some, such as webcams, have longer sequences.
+pub const CONFIGURATION_BUFFER_SIZE: usize = 256;
+
+/// The total length of a configuration-descriptor sequence
+///
+/// Taken from wTotalLength in the configuration descriptor at the
+/// start of `bytes`; returns `bytes.len()` if there's no (complete)
+/// configuration descriptor there.
+pub(crate) fn configuration_total_length(bytes: &[u8]) -> usize {
+    if bytes.len() < CONFIGURATION_DESCRIPTOR_SIZE {
+        return bytes.len();
+    }
+    u16::from_le_bytes([bytes[2], bytes[3]]) as usize
+}
+
+/// Request type of hub-port requests (USB 2.0 table 11-15)
+const HUB_PORT_REQUEST: RequestType = RequestType::new(Direction::Out)
+    .kind(RequestTypeType::Class)
+    .recipient(Recipient::Other);
+
 /// Basic information about a USB device, perhaps sufficient to select a driver
 ///
 /// The `vid` and `pid` fields between them should uniquely identify a
@@ -47,6 +81,7 @@ pub struct DeviceInfo {
 struct UnaddressedDevice {
     usb_speed: UsbSpeed,
     packet_size_ep0: u8,
+    quirks: Quirks,
 }
 
 /// A USB device which is attached, and has an address, but isn't yet configured
@@ -64,6 +99,7 @@ pub struct UnconfiguredDevice {
     usb_address: u8,
     usb_speed: UsbSpeed,
     packet_size_ep0: u8,
+    quirks: Quirks,
 }
 
 impl UnconfiguredDevice {
@@ -74,11 +110,19 @@ impl UnconfiguredDevice {
     pub fn address(&self) -> u8 {
         self.usb_address
     }
+
+    /// The workarounds which apply to this device, see [`crate::quirks`]
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
 }
 
 /// A Bulk IN endpoint on a particular USB device
 ///
-/// For use with [`UsbBus::bulk_in_transfer`].
+/// For use with [`UsbBus::bulk_in_transfer`] or [`BulkIn::read_exact`].
+/// The endpoint's state -- its data toggle, and whether the device
+/// has halted it -- is kept here, and carries over from one transfer
+/// to the next.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(PartialEq, Eq)]
@@ -87,11 +131,97 @@ pub struct BulkIn {
     usb_speed: UsbSpeed,
     endpoint: u8,
     data_toggle: Cell<bool>,
+    halted: Cell<bool>,
+}
+
+impl BulkIn {
+    /// Has the device stalled this endpoint since it was opened, or
+    /// since the last [`UsbBus::clear_halt`]?
+    pub fn is_halted(&self) -> bool {
+        self.halted.get()
+    }
+
+    /// Read into all of `data`, unless the device ends the transfer early
+    ///
+    /// Unlike [`UsbBus::bulk_in_transfer`], the buffer can be larger
+    /// than the host controller can manage in one transfer (see
+    /// [`HostController::max_transfer_size()`]); it is read in
+    /// pieces, each a whole number of packets long.
+    ///
+    /// A short packet, including a zero-length packet, ends a bulk IN
+    /// transfer (USB 2.0 section 5.8.3), so the number of bytes read
+    /// (which is returned) is less than `data.len()` if the device
+    /// had less data to send than was asked for.
+    ///
+    /// While the endpoint is halted, this fails with
+    /// [`UsbError::Stall`] without involving the device at all.
+    pub async fn read_exact<HC: HostController>(
+        &self,
+        bus: &UsbBus<HC>,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        if self.is_halted() {
+            return Err(UsbError::Stall);
+        }
+        let mut total = 0;
+        for chunk in data.chunks_mut(bus.bulk_chunk_size()) {
+            let n = bus
+                .bulk_in_transfer(self, chunk, TransferType::FixedSize)
+                .await?;
+            total += n;
+            if n < chunk.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Read into all of `data`'s unfilled part, which needn't be
+    /// initialised, unless the device ends the transfer early
+    ///
+    /// As [`BulkIn::read_exact`], but using
+    /// [`UsbBus::bulk_in_transfer_uninit`]. Returns the number of
+    /// bytes read, which is always the amount by which `data.len()`
+    /// grew.
+    pub async fn read_exact_uninit<HC: HostController>(
+        &self,
+        bus: &UsbBus<HC>,
+        data: &mut ReadBuffer<'_>,
+    ) -> Result<usize, UsbError> {
+        if self.is_halted() {
+            return Err(UsbError::Stall);
+        }
+        let chunk_size = bus.bulk_chunk_size();
+        let mut total = 0;
+        while data.remaining() > 0 {
+            let mut chunk = data.unfilled(chunk_size);
+            let want = chunk.remaining();
+            bus.bulk_in_transfer_uninit(
+                self,
+                &mut chunk,
+                TransferType::FixedSize,
+            )
+            .await?;
+            let n = chunk.len();
+            // SAFETY: `chunk` covers the start of data's unfilled
+            // part, and its filled part is initialised (ReadBuffer's
+            // invariant)
+            unsafe { data.assume_filled(n) };
+            total += n;
+            if n < want {
+                break;
+            }
+        }
+        Ok(total)
+    }
 }
 
 /// A Bulk OUT endpoint on a particular USB device
 ///
-/// For use with [`UsbBus::bulk_out_transfer`].
+/// For use with [`UsbBus::bulk_out_transfer`] or [`BulkOut::write_all`].
+/// The endpoint's state -- its data toggle, and whether the device
+/// has halted it -- is kept here, and carries over from one transfer
+/// to the next.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(PartialEq, Eq)]
@@ -100,6 +230,50 @@ pub struct BulkOut {
     usb_speed: UsbSpeed,
     endpoint: u8,
     data_toggle: Cell<bool>,
+    halted: Cell<bool>,
+}
+
+impl BulkOut {
+    /// Has the device stalled this endpoint since it was opened, or
+    /// since the last [`UsbBus::clear_out_halt`]?
+    pub fn is_halted(&self) -> bool {
+        self.halted.get()
+    }
+
+    /// Write all of `data`
+    ///
+    /// Unlike [`UsbBus::bulk_out_transfer`], the data can be larger
+    /// than the host controller can manage in one transfer (see
+    /// [`HostController::max_transfer_size()`]); it is written in
+    /// pieces, each (except perhaps the last) a whole number of
+    /// packets long, so that the device sees a single transfer.
+    ///
+    /// Returns the number of bytes written, which is less than
+    /// `data.len()` only if the host controller reported a short
+    /// write.
+    ///
+    /// While the endpoint is halted, this fails with
+    /// [`UsbError::Stall`] without involving the device at all.
+    pub async fn write_all<HC: HostController>(
+        &self,
+        bus: &UsbBus<HC>,
+        data: &[u8],
+    ) -> Result<usize, UsbError> {
+        if self.is_halted() {
+            return Err(UsbError::Stall);
+        }
+        let mut total = 0;
+        for chunk in data.chunks(bus.bulk_chunk_size()) {
+            let n = bus
+                .bulk_out_transfer(self, chunk, TransferType::FixedSize)
+                .await?;
+            total += n;
+            if n < chunk.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
 }
 
 /// A USB device which is attached, addressed, configured, and ready to use
@@ -116,6 +290,7 @@ pub struct UsbDevice {
     packet_size_ep0: u8,
     in_endpoints_bitmap: u16,
     out_endpoints_bitmap: u16,
+    quirks: Quirks,
 }
 
 impl UsbDevice {
@@ -127,6 +302,18 @@ impl UsbDevice {
         self.usb_address
     }
 
+    /// The workarounds which apply to this device, see [`crate::quirks`]
+    ///
+    /// Device drivers, such as the mass-storage one, consult these.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Override the workarounds found during enumeration
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
     /// Return a bitmap of available IN endpoints
     pub fn in_endpoints(&self) -> BitSet {
         BitSet(self.in_endpoints_bitmap as u32)
@@ -152,6 +339,7 @@ impl UsbDevice {
                 usb_speed: self.usb_speed,
                 endpoint: ep,
                 data_toggle: Cell::new(false),
+                halted: Cell::new(false),
             })
         } else {
             Err(UsbError::NoSuchEndpoint)
@@ -173,6 +361,7 @@ impl UsbDevice {
                 usb_speed: self.usb_speed,
                 endpoint: ep,
                 data_toggle: Cell::new(false),
+                halted: Cell::new(false),
             })
         } else {
             Err(UsbError::NoSuchEndpoint)
@@ -247,6 +436,91 @@ pub enum DeviceEvent {
     None,
 }
 
+/// A device has arrived or departed, as reported by [`UsbBus::events()`]
+///
+/// Unlike [`DeviceEvent`], departures are reported one device at a time.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq)]
+pub enum BusEvent {
+    /// A new device has been connected and given an address; see
+    /// [`DeviceEvent::Connect`]
+    Attached(UnconfiguredDevice, DeviceInfo),
+
+    /// A new hub has been connected and configured; see
+    /// [`DeviceEvent::HubConnect`]
+    HubAttached(UsbDevice),
+
+    /// The device with this USB address has been disconnected
+    ///
+    /// When a hub is disconnected, every device downstream of it is
+    /// reported before the hub itself (the most deeply-nested first).
+    Detached(u8),
+
+    /// A device could not be enumerated; see
+    /// [`DeviceEvent::EnumerationError`]
+    EnumerationFailed(u8, u8, UsbError),
+}
+
+/// The [`BusEvent`]s corresponding to one [`DeviceEvent`]
+struct BusEvents {
+    event: Option<BusEvent>,
+    detached: [u8; 32],
+    len: usize,
+    next: usize,
+}
+
+impl BusEvents {
+    /// Translate `event`, using the topology from before it happened
+    fn new(event: DeviceEvent, before: &Topology) -> Self {
+        let mut r = Self {
+            event: None,
+            detached: [0; 32],
+            len: 0,
+            next: 0,
+        };
+        match event {
+            DeviceEvent::Connect(device, info) => {
+                r.event = Some(BusEvent::Attached(device, info))
+            }
+            DeviceEvent::HubConnect(device) => {
+                r.event = Some(BusEvent::HubAttached(device))
+            }
+            DeviceEvent::EnumerationError(hub, port, e) => {
+                r.event = Some(BusEvent::EnumerationFailed(hub, port, e))
+            }
+            DeviceEvent::Disconnect(mask) => {
+                // Deepest first, so children precede their hubs
+                for depth in (1..=16).rev() {
+                    for address in mask.iter() {
+                        if before.depth(address) == Some(depth) {
+                            r.detached[r.len] = address;
+                            r.len += 1;
+                        }
+                    }
+                }
+            }
+            DeviceEvent::None => {}
+        }
+        r
+    }
+}
+
+impl Iterator for BusEvents {
+    type Item = BusEvent;
+
+    fn next(&mut self) -> Option<BusEvent> {
+        if let Some(e) = self.event.take() {
+            return Some(e);
+        }
+        if self.next < self.len {
+            self.next += 1;
+            return Some(BusEvent::Detached(self.detached[self.next - 1]));
+        }
+        None
+    }
+}
+
 /// A simplified version of USB configuration descriptors
 ///
 /// Suitable for simple devices. Can be obtained from [`UsbBus::get_basic_configuration()`].
@@ -282,7 +556,7 @@ pub struct BasicConfiguration {
 
 impl DescriptorVisitor for BasicConfiguration {
     fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
-        self.num_configurations += 1;
+        self.num_configurations = self.num_configurations.saturating_add(1);
         self.configuration_value = c.bConfigurationValue;
     }
     fn on_endpoint(&mut self, i: &EndpointDescriptor) {
@@ -335,6 +609,14 @@ impl DescriptorVisitor for SpecificConfiguration {
 pub struct HubState<HC: HostController> {
     topology: RefCell<Topology>,
     pipes: RefCell<[Option<HC::InterruptPipe>; 15]>,
+    endpoints: RefCell<[(u8, u8); 15]>,
+    /// Held from resetting a port until the new device has an address
+    ///
+    /// A newly-reset device answers at address zero, so if two were
+    /// being enumerated at once -- say, a multi-port hub reporting two
+    /// connections together -- both would answer, and enumeration
+    /// would go wrong. Other ports wait their turn.
+    enumeration: Pool<1>,
 }
 
 impl<HC: HostController> Default for HubState<HC> {
@@ -342,6 +624,8 @@ impl<HC: HostController> Default for HubState<HC> {
         Self {
             topology: Default::default(),
             pipes: Default::default(),
+            endpoints: Default::default(),
+            enumeration: Pool::new(),
         }
     }
 }
@@ -365,7 +649,7 @@ impl<HC: HostController> HubState<HC> {
         max_packet_size: u8,
         interval_ms: u8,
     ) -> Result<(), UsbError> {
-        for p in self.pipes.borrow_mut().iter_mut() {
+        for (i, p) in self.pipes.borrow_mut().iter_mut().enumerate() {
             if p.is_none() {
                 *p = Some(hc.try_alloc_interrupt_pipe(
                     address,
@@ -373,6 +657,7 @@ impl<HC: HostController> HubState<HC> {
                     max_packet_size as u16,
                     interval_ms,
                 )?);
+                self.endpoints.borrow_mut()[i] = (address, endpoint);
                 return Ok(());
             }
         }
@@ -380,27 +665,91 @@ impl<HC: HostController> HubState<HC> {
     }
 }
 
+/// Something has happened on one of the hubs' interrupt pipes
+enum HubPipeEvent {
+    Packet(InterruptPacket),
+    /// The hub stalled the interrupt pipe in this slot
+    Stalled(usize),
+}
+
 struct HubStateStream<'a, HC: HostController> {
     state: &'a HubState<HC>,
 }
 
 impl<HC: HostController> Stream for HubStateStream<'_, HC> {
-    type Item = InterruptPacket;
+    type Item = HubPipeEvent;
 
     fn poll_next(
         self: Pin<&mut Self>,
         cx: &mut Context,
     ) -> Poll<Option<Self::Item>> {
-        for pipe in self.state.pipes.borrow_mut().iter_mut().flatten() {
-            let poll = pipe.poll_next_unpin(cx);
-            if poll.is_ready() {
-                return poll;
+        for (i, p) in self.state.pipes.borrow_mut().iter_mut().enumerate() {
+            let Some(pipe) = p else {
+                continue;
+            };
+            match pipe.poll_next_unpin(cx) {
+                Poll::Ready(Some(packet)) => {
+                    return Poll::Ready(Some(HubPipeEvent::Packet(packet)))
+                }
+                Poll::Ready(None) => {
+                    if pipe.is_stalled() {
+                        return Poll::Ready(Some(HubPipeEvent::Stalled(i)));
+                    }
+                    // Pipe has gone away altogether
+                    *p = None;
+                }
+                Poll::Pending => {}
             }
         }
         Poll::Pending
     }
 }
 
+/// Timings and retry policy for enumerating newly-connected devices
+///
+/// The defaults follow the USB 2.0 specification, but some devices
+/// (slow-booting SSD enclosures, for instance) take longer than the
+/// specification allows before answering their first GET_DESCRIPTOR;
+/// for those, increase `vbus_settle_ms` and/or `attempts`.
+///
+/// All waiting is done via the "delay" function passed to
+/// [`UsbBus::device_events()`] or [`UsbBus::device_events_no_hubs()`],
+/// so this crate stays independent of any particular executor. A
+/// delay of zero is skipped altogether.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EnumerationConfig {
+    /// Wait after a connect, before resetting the port (USB 2.0 section
+    /// 7.1.7.3, T<sub>ATTDB</sub>)
+    pub debounce_ms: usize,
+    /// How long to hold the port in reset (USB 2.0 section 7.1.7.5,
+    /// T<sub>DRST</sub>)
+    pub reset_ms: usize,
+    /// Wait after a reset, before the next transfer (USB 2.0 section
+    /// 9.2.6.2, T<sub>RSTRCY</sub>)
+    pub reset_recovery_ms: usize,
+    /// Extra wait after the first reset following a connect, to let the
+    /// device finish its own power-on reset (USB 2.0 section 9.1.2)
+    pub vbus_settle_ms: usize,
+    /// How many times to reset the port and read the device
+    /// descriptor before reporting [`DeviceEvent::EnumerationError`]
+    ///
+    /// Zero is treated as one.
+    pub attempts: u8,
+}
+
+impl Default for EnumerationConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 100,
+            reset_ms: 50,
+            reset_recovery_ms: 100,
+            vbus_settle_ms: 100,
+            attempts: 3,
+        }
+    }
+}
+
 /// A USB host bus.
 ///
 /// This object represents the (portable) concept of a host's view of
@@ -417,12 +766,162 @@ impl<HC: HostController> Stream for HubStateStream<'_, HC> {
 ///
 pub struct UsbBus<HC: HostController> {
     driver: HC,
+    config: EnumerationConfig,
+    diagnostics: RefCell<Registry>,
+    quirks: &'static [QuirkEntry],
+    identities: &'static [DeviceIdentity],
+    on_identity: Option<OnIdentity>,
 }
 
 impl<HC: HostController> UsbBus<HC> {
     /// Create a new USB host bus from a host-controller driver
+    ///
+    /// Uses the default [`EnumerationConfig`].
     pub fn new(driver: HC) -> Self {
-        Self { driver }
+        Self::with_config(driver, EnumerationConfig::default())
+    }
+
+    /// Create a new USB host bus, with non-default enumeration timings
+    pub fn with_config(driver: HC, config: EnumerationConfig) -> Self {
+        Self {
+            driver,
+            config,
+            diagnostics: Default::default(),
+            quirks: &[],
+            identities: &[],
+            on_identity: None,
+        }
+    }
+
+    /// The enumeration timings in use
+    pub fn config(&self) -> &EnumerationConfig {
+        &self.config
+    }
+
+    /// What the host controller can and can't do
+    ///
+    /// Device drivers should check this before starting work, and
+    /// fail with [`UsbError::Unsupported`] if they need something
+    /// that's missing.
+    pub fn capabilities(&self) -> ControllerCapabilities {
+        self.driver.capabilities()
+    }
+
+    /// Add workarounds for devices not listed in [`quirks::BUILTIN_QUIRKS`]
+    ///
+    /// Call this at startup, before handling any device events; the
+    /// quirks are looked up when each device is enumerated, and apply
+    /// in addition to the built-in ones.
+    pub fn set_quirks(&mut self, quirks: &'static [QuirkEntry]) {
+        self.quirks = quirks;
+    }
+
+    /// Supply records of devices seen before, e.g. on a previous boot
+    ///
+    /// When [`UsbBus::device_identity()`] finds one of these devices,
+    /// its quirks are applied straight away, and its record -- with
+    /// anything higher layers had learned about it -- is returned. See
+    /// [`DeviceIdentity`].
+    pub fn preload(&mut self, identities: &'static [DeviceIdentity]) {
+        self.identities = identities;
+    }
+
+    /// Set the function to be called with each completed
+    /// [`DeviceIdentity`] (or `None` for no function)
+    ///
+    /// It's called from [`UsbBus::report_identity()`], whether or not
+    /// the record was preloaded, so an application which stores the
+    /// records should compare them with what it already has, rather
+    /// than wearing out its flash by rewriting each one every time.
+    pub fn set_on_identity(&mut self, on_identity: Option<OnIdentity>) {
+        self.on_identity = on_identity;
+    }
+
+    /// Report a completed [`DeviceIdentity`] to the application
+    ///
+    /// See [`UsbBus::set_on_identity()`]. Drivers call this once they
+    /// have finished probing a device and filled in what they found.
+    pub fn report_identity(&self, identity: &DeviceIdentity) {
+        if let Some(f) = self.on_identity {
+            f(identity);
+        }
+    }
+
+    /// A snapshot of the state of every known device
+    ///
+    /// Devices appear from when they are given an address until they
+    /// are disconnected, in address order. Each snapshot is taken as
+    /// the iterator reaches it, so it is fine to hold the iterator
+    /// across other calls on the bus.
+    ///
+    /// ```no_run
+    /// # use cotton_usb_host::host_controller::HostController;
+    /// # use cotton_usb_host::usb_bus::UsbBus;
+    /// # fn foo<HC: HostController>(bus: UsbBus<HC>) {
+    /// for d in bus.diagnostics() {
+    ///     println!(
+    ///         "{}: {:?} config {:?} errors {:?}",
+    ///         d.address, d.speed, d.configuration, d.errors
+    ///     );
+    ///     for i in d.interfaces() {
+    ///         println!("  interface {} claimed by {}", i.interface, i.driver);
+    ///     }
+    ///     for p in d.pipes() {
+    ///         println!(
+    ///             "  endpoint {:#04x} {:?} max {} every {}ms",
+    ///             p.endpoint, p.endpoint_type, p.max_packet_size, p.interval_ms
+    ///         );
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn diagnostics(&self) -> impl Iterator<Item = DeviceDiagnostics> + '_ {
+        (0..32).filter_map(|a| self.device_diagnostics(a))
+    }
+
+    /// A snapshot of the state of one device, if it is known
+    pub fn device_diagnostics(
+        &self,
+        address: u8,
+    ) -> Option<DeviceDiagnostics> {
+        self.diagnostics.borrow().get(address)
+    }
+
+    /// Record that a driver is using an interface of a device
+    ///
+    /// This is for diagnostics (see [`UsbBus::diagnostics()`]), but also
+    /// stops two drivers claiming the same interface: the second claim
+    /// gets [`UsbError::InterfaceClaimed`]. Claims are forgotten when the
+    /// device is reconfigured or disconnected.
+    ///
+    /// # Parameters
+    ///  - device: The device whose interface it is
+    ///  - interface: The interface number (`bInterfaceNumber`)
+    ///  - driver: A short name for the driver, for display
+    pub fn claim_interface(
+        &self,
+        device: &UsbDevice,
+        interface: u8,
+        driver: &'static str,
+    ) -> Result<(), UsbError> {
+        self.diagnostics.borrow_mut().claim(
+            device.usb_address,
+            interface,
+            driver,
+        )
+    }
+
+    /// Undo [`UsbBus::claim_interface()`]
+    pub fn release_interface(&self, device: &UsbDevice, interface: u8) {
+        self.diagnostics
+            .borrow_mut()
+            .release(device.usb_address, interface);
+    }
+
+    fn record<T>(&self, address: u8, result: &Result<T, UsbError>) {
+        if let Err(e) = result {
+            self.diagnostics.borrow_mut().error(address, *e);
+        }
     }
 
     /// Obtain a stream of hotplug/hot-unplug events
@@ -457,7 +956,8 @@ impl<HC: HostController> UsbBus<HC> {
     /// given a parameter in milliseconds, returns a Future that waits for
     /// that long before coming ready. See the examples for how to implement
     /// that (simple!) function for RTIC2 and for Embassy; other executors
-    /// will require their own implementations.
+    /// will require their own implementations. The lengths of the waits
+    /// are set by the bus's [`EnumerationConfig`].
     ///
     /// When using this method, the cotton-usb-host crate itself takes
     /// care of detecting and configuring hubs, and of detecting
@@ -484,7 +984,7 @@ impl<HC: HostController> UsbBus<HC> {
 
         enum InternalEvent {
             Root(DeviceStatus),
-            Packet(InterruptPacket),
+            Hub(HubPipeEvent),
         }
 
         futures::stream::select(
@@ -494,7 +994,7 @@ impl<HC: HostController> UsbBus<HC> {
                 MultiInterruptStream::<HC::MultiInterruptPipe> {
                     pipe: &hub_state.pipes,
                 } */
-                .map(InternalEvent::Packet),
+                .map(InternalEvent::Hub),
         )
         .then(move |ev| {
             let delay_ms = delay_ms_in.clone();
@@ -502,19 +1002,19 @@ impl<HC: HostController> UsbBus<HC> {
                 match ev {
                     InternalEvent::Root(status) => {
                         if let DeviceStatus::Present(speed) = status {
-                            self.driver.reset_root_port(true);
-                            delay_ms(50).await;
-                            self.driver.reset_root_port(false);
-                            delay_ms(10).await;
-                            let (device, info) =
-                                match self.new_device(speed).await {
-                                    Ok((device, info)) => (device, info),
-                                    Err(e) => {
-                                        return DeviceEvent::EnumerationError(
-                                            0, 1, e,
-                                        )
-                                    }
-                                };
+                            let enumerating =
+                                hub_state.enumeration.alloc().await;
+                            let (device, info) = match self
+                                .new_root_device(speed, &delay_ms)
+                                .await
+                            {
+                                Ok((device, info)) => (device, info),
+                                Err(e) => {
+                                    return DeviceEvent::EnumerationError(
+                                        0, 1, e,
+                                    )
+                                }
+                            };
                             let is_hub = info.class == HUB_CLASSCODE;
                             let address = hub_state
                                 .topology
@@ -522,7 +1022,9 @@ impl<HC: HostController> UsbBus<HC> {
                                 .device_connect(0, 1, is_hub)
                                 .expect("Root connect should always succeed");
                             let device = match self
-                                .set_address(device, address)
+                                .set_address_and_settle(
+                                    device, address, &delay_ms,
+                                )
                                 .await
                             {
                                 Ok(device) => device,
@@ -532,6 +1034,7 @@ impl<HC: HostController> UsbBus<HC> {
                                     );
                                 }
                             };
+                            drop(enumerating);
                             if is_hub {
                                 debug::println!("It's a hub");
                                 match self.new_hub(hub_state, device).await {
@@ -551,20 +1054,78 @@ impl<HC: HostController> UsbBus<HC> {
                                 .topology
                                 .borrow_mut()
                                 .device_disconnect(0, 1);
+                            self.diagnostics
+                                .borrow_mut()
+                                .forget(BitSet(0xFFFF_FFFF));
                             DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF))
                         }
                     }
-                    InternalEvent::Packet(packet) => self
+                    InternalEvent::Hub(HubPipeEvent::Packet(packet)) => self
                         .handle_hub_packet(hub_state, &packet, delay_ms)
                         .await
                         .unwrap_or_else(|e| {
                             DeviceEvent::EnumerationError(0, 1, e)
                         }),
+                    InternalEvent::Hub(HubPipeEvent::Stalled(slot)) => self
+                        .recover_hub_pipe(hub_state, slot)
+                        .await
+                        .map(|_| DeviceEvent::None)
+                        .unwrap_or_else(|e| {
+                            DeviceEvent::EnumerationError(0, 1, e)
+                        }),
                 }
             }
         })
     }
 
+    /// Obtain a stream of device arrivals and departures across the bus
+    ///
+    /// This is [`UsbBus::device_events()`], but with each disconnected
+    /// device reported separately, so that an application's main loop
+    /// can be a single `while let`:
+    ///
+    /// ```no_run
+    /// # use cotton_usb_host::host_controller::HostController;
+    /// # use std::pin::pin;
+    /// # use cotton_usb_host::usb_bus::{BusEvent, HubState, UsbBus};
+    /// # use futures::{future, Future, StreamExt};
+    /// # fn delay_ms(_ms: usize) -> impl Future<Output = ()> {
+    /// #  future::ready(())
+    /// # }
+    /// # async fn foo<D: HostController>(driver: D) -> () {
+    /// let hub_state = HubState::default();
+    /// let bus = UsbBus::new(driver);
+    /// let mut events = pin!(bus.events(&hub_state, delay_ms));
+    /// while let Some(ev) = events.next().await {
+    ///     match ev {
+    ///         BusEvent::Attached(device, info) => { /* ... */ }
+    ///         BusEvent::Detached(address) => { /* ... */ }
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// When a hub is unplugged, every device that was downstream of it
+    /// is reported as [`BusEvent::Detached`] before the hub itself.
+    pub fn events<
+        'a,
+        D: Future<Output = ()>,
+        F: Fn(usize) -> D + 'static + Clone,
+    >(
+        &'a self,
+        hub_state: &'a HubState<HC>,
+        delay_ms: F,
+    ) -> impl Stream<Item = BusEvent> + 'a {
+        let mut before = hub_state.topology();
+        self.device_events(hub_state, delay_ms)
+            .flat_map(move |event| {
+                let events = BusEvents::new(event, &before);
+                before = hub_state.topology();
+                futures::stream::iter(events)
+            })
+    }
+
     /// Obtain a stream of hotplug/hot-unplug events
     ///
     /// This stream is how the USB host stack informs your code that a
@@ -596,7 +1157,8 @@ impl<HC: HostController> UsbBus<HC> {
     /// given a parameter in milliseconds, returns a Future that waits for
     /// that long before coming ready. See the examples for how to implement
     /// that (simple!) function for RTIC2 and for Embassy; other executors
-    /// will require their own implementations.
+    /// will require their own implementations. The lengths of the waits
+    /// are set by the bus's [`EnumerationConfig`].
     ///
     /// When using this method, the cotton-usb-host crate deals only with
     /// a single USB device attached directly to the USB host controller,
@@ -619,13 +1181,9 @@ impl<HC: HostController> UsbBus<HC> {
             let delay_ms = delay_ms_in.clone();
             async move {
                 if let DeviceStatus::Present(speed) = status {
-                    self.driver.reset_root_port(true);
-                    delay_ms(50).await;
-                    self.driver.reset_root_port(false);
-                    delay_ms(10).await;
-                    match self.new_device(speed).await {
+                    match self.new_root_device(speed, &delay_ms).await {
                         Ok((device, info)) => match self
-                            .set_address(device, 1)
+                            .set_address_and_settle(device, 1, &delay_ms)
                             .await
                         {
                             Ok(device) => DeviceEvent::Connect(device, info),
@@ -634,6 +1192,7 @@ impl<HC: HostController> UsbBus<HC> {
                         Err(e) => DeviceEvent::EnumerationError(0, 1, e),
                     }
                 } else {
+                    self.diagnostics.borrow_mut().forget(BitSet(0xFFFF_FFFF));
                     DeviceEvent::Disconnect(BitSet(0xFFFF_FFFF))
                 }
             }
@@ -649,29 +1208,96 @@ impl<HC: HostController> UsbBus<HC> {
         device: UnconfiguredDevice,
         configuration_value: u8,
     ) -> Result<UsbDevice, UsbError> {
+        self.set_configuration(&device, configuration_value).await?;
+        let mut endpoints = SpecificConfiguration::new(configuration_value);
+        self.get_configuration(&device, &mut endpoints).await?;
+        Ok(Self::configured(device, &endpoints))
+    }
+
+    /// As [`UsbBus::configure()`], but with the descriptors already to hand
+    pub(crate) async fn configure_with_descriptors(
+        &self,
+        device: UnconfiguredDevice,
+        configuration_value: u8,
+        descriptors: &[u8],
+    ) -> Result<UsbDevice, UsbError> {
+        self.set_configuration(&device, configuration_value).await?;
+        let mut endpoints = SpecificConfiguration::new(configuration_value);
+        crate::wire::parse_descriptors(descriptors, &mut endpoints);
+        Ok(Self::configured(device, &endpoints))
+    }
+
+    async fn set_configuration(
+        &self,
+        device: &UnconfiguredDevice,
+        configuration_value: u8,
+    ) -> Result<(), UsbError> {
         self.driver
             .control_transfer(
                 device.address(),
                 device.packet_size_ep0,
-                SetupPacket {
-                    bmRequestType: HOST_TO_DEVICE,
-                    bRequest: SET_CONFIGURATION,
-                    wValue: configuration_value as u16,
-                    wIndex: 0,
-                    wLength: 0,
-                },
+                SetupPacket::set_configuration(configuration_value),
                 DataPhase::None,
             )
             .await?;
-        let mut endpoints = SpecificConfiguration::new(configuration_value);
-        self.get_configuration(&device, &mut endpoints).await?;
-        Ok(UsbDevice {
+        self.diagnostics
+            .borrow_mut()
+            .configured(device.address(), configuration_value);
+        Ok(())
+    }
+
+    fn configured(
+        device: UnconfiguredDevice,
+        endpoints: &SpecificConfiguration,
+    ) -> UsbDevice {
+        UsbDevice {
             usb_address: device.usb_address,
             usb_speed: device.usb_speed,
             packet_size_ep0: device.packet_size_ep0,
             in_endpoints_bitmap: endpoints.in_endpoints,
             out_endpoints_bitmap: endpoints.out_endpoints,
-        })
+            quirks: device.quirks,
+        }
+    }
+
+    /// Wait, unless the wait is zero
+    async fn delay<D: Future<Output = ()>, F: Fn(usize) -> D>(
+        delay_ms: &F,
+        ms: usize,
+    ) {
+        if ms > 0 {
+            delay_ms(ms).await;
+        }
+    }
+
+    /// Reset the root port and read the device descriptor, with retries
+    ///
+    /// See [`EnumerationConfig`] for the timings.
+    async fn new_root_device<D: Future<Output = ()>, F: Fn(usize) -> D>(
+        &self,
+        speed: UsbSpeed,
+        delay_ms: F,
+    ) -> Result<(UnaddressedDevice, DeviceInfo), UsbError> {
+        let config = self.config;
+        Self::delay(&delay_ms, config.debounce_ms).await;
+        let mut settle_ms = config.vbus_settle_ms;
+        let mut attempt = 1;
+        loop {
+            self.driver.reset_root_port(true);
+            Self::delay(&delay_ms, config.reset_ms).await;
+            self.driver.reset_root_port(false);
+            Self::delay(&delay_ms, config.reset_recovery_ms).await;
+            Self::delay(&delay_ms, settle_ms).await;
+            settle_ms = 0;
+            match self.new_device(speed).await {
+                Ok(r) => return Ok(r),
+                Err(e) if attempt >= config.attempts => return Err(e),
+                Err(_) => {
+                    debug::println!("enumeration attempt {} failed", attempt);
+                    attempt += 1;
+                }
+            }
+        }
     }
 
     async fn new_device(
@@ -685,19 +1311,19 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 0,
                 8,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST,
-                    bRequest: GET_DESCRIPTOR,
-                    wValue: ((DEVICE_DESCRIPTOR as u16) << 8),
-                    wIndex: 0,
-                    wLength: 8,
-                },
+                SetupPacket::get_descriptor(
+                    RequestType::new(Direction::In),
+                    DescriptorType::Device,
+                    0,
+                    0,
+                    8,
+                ),
                 DataPhase::In(&mut descriptors),
             )
             .await?;
         if sz < 8 {
             debug::println!("control in {}/8", sz);
-            return Err(UsbError::ProtocolError);
+            return Err(UsbError::Protocol("device descriptor too short"));
         }
 
         let packet_size_ep0 = descriptors[7];
@@ -708,28 +1334,31 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 0,
                 packet_size_ep0,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST,
-                    bRequest: GET_DESCRIPTOR,
-                    wValue: ((DEVICE_DESCRIPTOR as u16) << 8),
-                    wIndex: 0,
-                    wLength: 18,
-                },
+                SetupPacket::get_descriptor(
+                    RequestType::new(Direction::In),
+                    DescriptorType::Device,
+                    0,
+                    0,
+                    18,
+                ),
                 DataPhase::In(&mut descriptors),
             )
             .await?;
         if sz < 18 {
             debug::println!("control in {}/18", sz);
-            return Err(UsbError::ProtocolError);
+            return Err(UsbError::Protocol("device descriptor too short"));
         }
 
         let vid = u16::from_le_bytes([descriptors[8], descriptors[9]]);
         let pid = u16::from_le_bytes([descriptors[10], descriptors[11]]);
+        let bcd_device =
+            u16::from_le_bytes([descriptors[12], descriptors[13]]);
 
         Ok((
             UnaddressedDevice {
                 usb_speed: speed,
                 packet_size_ep0,
+                quirks: quirks::lookup(self.quirks, vid, pid, bcd_device),
             },
             DeviceInfo {
                 vid,
@@ -749,23 +1378,40 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 0,
                 device.packet_size_ep0,
-                SetupPacket {
-                    bmRequestType: HOST_TO_DEVICE,
-                    bRequest: SET_ADDRESS,
-                    wValue: address as u16,
-                    wIndex: 0,
-                    wLength: 0,
-                },
+                SetupPacket::set_address(address),
                 DataPhase::None,
             )
             .await?;
+        self.diagnostics
+            .borrow_mut()
+            .addressed(address, device.usb_speed);
         Ok(UnconfiguredDevice {
             usb_address: address,
             usb_speed: device.usb_speed,
             packet_size_ep0: device.packet_size_ep0,
+            quirks: device.quirks,
         })
     }
 
+    /// Set the address, then wait as long as the device's quirks say
+    async fn set_address_and_settle<
+        D: Future<Output = ()>,
+        F: Fn(usize) -> D,
+    >(
+        &self,
+        device: UnaddressedDevice,
+        address: u8,
+        delay_ms: &F,
+    ) -> Result<UnconfiguredDevice, UsbError> {
+        let device = self.set_address(device, address).await?;
+        Self::delay(
+            delay_ms,
+            device.quirks.delay_after_set_address_ms as usize,
+        )
+        .await;
+        Ok(device)
+    }
+
     /// Perform a USB control-endpoint transaction, USB 2.0 section 5.5
     ///
     /// # Example
@@ -805,14 +1451,98 @@ impl<HC: HostController> UsbBus<HC> {
         setup: SetupPacket,
         data_phase: DataPhase<'_>,
     ) -> Result<usize, UsbError> {
-        self.driver
+        let result = self
+            .driver
             .control_transfer(
                 device.usb_address,
                 device.packet_size_ep0,
                 setup,
                 data_phase,
             )
-            .await
+            .await;
+        self.record(device.usb_address, &result);
+        result
+    }
+
+    /// Find out which particular device this is
+    ///
+    /// Reads the device descriptor and, if it has one, the serial
+    /// number (in US English), to produce a [`DeviceIdentity`]. If
+    /// the device matches a record supplied with [`UsbBus::preload()`],
+    /// that record's quirks are added to the device's own, and the
+    /// record (with the combined quirks) is returned; otherwise, the
+    /// new record just has the device's quirks.
+    ///
+    /// Devices whose serial-number descriptor can't be read (some
+    /// stall the request) are treated as having no serial number, so
+    /// all such devices with the same VID and PID share a record.
+    pub async fn device_identity(
+        &self,
+        device: &mut UsbDevice,
+    ) -> Result<DeviceIdentity, UsbError> {
+        let mut descriptor = [0u8; 18];
+        let sz = self
+            .control_transfer(
+                device,
+                SetupPacket::get_descriptor(
+                    RequestType::new(Direction::In),
+                    DescriptorType::Device,
+                    0,
+                    0,
+                    18,
+                ),
+                DataPhase::In(&mut descriptor),
+            )
+            .await?;
+        if sz < 18 {
+            return Err(UsbError::Protocol("device descriptor too short"));
+        }
+        let vid = u16::from_le_bytes([descriptor[8], descriptor[9]]);
+        let pid = u16::from_le_bytes([descriptor[10], descriptor[11]]);
+
+        let serial_hash = match descriptor[16] {
+            0 => 0,
+            index => {
+                let mut string = [0u8; 128];
+                let result = self
+                    .control_transfer(
+                        device,
+                        SetupPacket::get_descriptor(
+                            RequestType::new(Direction::In),
+                            DescriptorType::String,
+                            index,
+                            LANGID_EN_US,
+                            string.len() as u16,
+                        ),
+                        DataPhase::In(&mut string),
+                    )
+                    .await;
+                match result {
+                    Ok(n) => identity::serial_hash(&string[0..n]),
+                    Err(UsbError::Stall) => 0,
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+
+        let mut quirks = device.quirks();
+        let known = self
+            .identities
+            .iter()
+            .find(|i| i.matches(vid, pid, serial_hash));
+        if let Some(known) = known {
+            quirks.merge(&known.capabilities.quirks);
+            device.set_quirks(quirks);
+        }
+        Ok(DeviceIdentity {
+            vid,
+            pid,
+            serial_hash,
+            capabilities: Capabilities {
+                quirks,
+                scsi: known.and_then(|i| i.capabilities.scsi),
+            },
+        })
     }
 
     /// Clear a halt (stall) condition on an IN endpoint
@@ -821,26 +1551,79 @@ impl<HC: HostController> UsbBus<HC> {
     /// cotton-usb-host-msc crate for how to deal with a prolific user
     /// of stall conditions.
     ///
-    /// TODO: clear halts on OUT endpoints?
+    /// For OUT endpoints, see [`UsbBus::clear_out_halt`].
     pub async fn clear_halt(&self, ep: &BulkIn) -> Result<(), UsbError> {
+        self.clear_endpoint_halt(ep.usb_address, ep.endpoint | 0x80)
+            .await?;
+        ep.data_toggle.set(false); // USB 2.0 s5.8.5
+        ep.halted.set(false);
+        Ok(())
+    }
+
+    /// Clear a halt (stall) condition on an OUT endpoint
+    ///
+    /// See [`UsbBus::clear_halt`].
+    pub async fn clear_out_halt(&self, ep: &BulkOut) -> Result<(), UsbError> {
+        self.clear_endpoint_halt(ep.usb_address, ep.endpoint)
+            .await?;
+        ep.data_toggle.set(false);
+        ep.halted.set(false);
+        Ok(())
+    }
+
+    /// Clear a halt (stall) condition on an interrupt IN endpoint
+    ///
+    /// Once an interrupt pipe has been stalled by the device (see
+    /// [`InterruptPipe::is_stalled()`]), it stays paused until this
+    /// is called, which clears the halt on the device and then resumes
+    /// the pipe (from DATA0).
+    ///
+    /// # Parameters
+    ///  - pipe: the stalled pipe, from [`UsbBus::interrupt_pipe_in()`]
+    ///  - address: USB device address (1-127)
+    ///  - endpoint: endpoint number (1-15)
+    pub async fn clear_interrupt_halt(
+        &self,
+        pipe: &mut HC::InterruptPipe,
+        address: u8,
+        endpoint: u8,
+    ) -> Result<(), UsbError> {
+        self.clear_endpoint_halt(address, endpoint | 0x80).await?;
+        pipe.resume();
+        Ok(())
+    }
+
+    /// Send CLEAR_FEATURE(ENDPOINT_HALT); `endpoint` includes the
+    /// direction bit
+    async fn clear_endpoint_halt(
+        &self,
+        address: u8,
+        endpoint: u8,
+    ) -> Result<(), UsbError> {
         self.driver
             .control_transfer(
-                ep.usb_address,
+                address,
                 8,
-                SetupPacket {
-                    bmRequestType: 2,
-                    bRequest: CLEAR_FEATURE,
-                    wValue: 0, // EP_HALT
-                    wIndex: (ep.endpoint | 0x80) as u16,
-                    wLength: 0,
-                },
+                SetupPacket::clear_feature(
+                    RequestType::new(Direction::Out)
+                        .recipient(Recipient::Endpoint),
+                    ENDPOINT_HALT,
+                    endpoint as u16,
+                ),
                 DataPhase::None,
             )
             .await?;
-        ep.data_toggle.set(false); // USB 2.0 s5.8.5
         Ok(())
     }
 
+    /// The size of the pieces used by [`BulkIn::read_exact`] and
+    /// [`BulkOut::write_all`]: as big as the host controller allows,
+    /// but a whole number of packets
+    fn bulk_chunk_size(&self) -> usize {
+        const PACKET_SIZE: usize = 64; // @TODO max packet size
+        (self.driver.max_transfer_size() / PACKET_SIZE).max(1) * PACKET_SIZE
+    }
+
     /// Perform a bulk IN transfer
     ///
     /// # Parameters
@@ -858,14 +1641,69 @@ impl<HC: HostController> UsbBus<HC> {
         data: &'a mut [u8],
         transfer_type: TransferType,
     ) -> impl Future<Output = Result<usize, UsbError>> + 'a {
-        self.driver.bulk_in_transfer(
+        self.note_bulk_in(ep);
+        self.driver
+            .bulk_in_transfer(
+                ep.usb_address,
+                ep.endpoint,
+                64, // @TODO max packet size
+                data,
+                transfer_type,
+                &ep.data_toggle,
+            )
+            .map(move |result| self.bulk_in_result(ep, result))
+    }
+
+    /// Perform a bulk IN transfer into a buffer which needn't be
+    /// initialised
+    ///
+    /// As [`UsbBus::bulk_in_transfer`], but the transaction size is
+    /// `data.remaining()`, and the data received is added to the
+    /// filled part of `data` (see
+    /// [`HostController::bulk_in_transfer_uninit`]).
+    pub async fn bulk_in_transfer_uninit(
+        &self,
+        ep: &BulkIn,
+        data: &mut ReadBuffer<'_>,
+        transfer_type: TransferType,
+    ) -> Result<usize, UsbError> {
+        self.note_bulk_in(ep);
+        let result = self
+            .driver
+            .bulk_in_transfer_uninit(
+                ep.usb_address,
+                ep.endpoint,
+                64, // @TODO max packet size
+                data,
+                transfer_type,
+                &ep.data_toggle,
+            )
+            .await;
+        self.bulk_in_result(ep, result)
+    }
+
+    fn note_bulk_in(&self, ep: &BulkIn) {
+        self.diagnostics.borrow_mut().pipe(
             ep.usb_address,
-            ep.endpoint,
-            64, // @TODO max packet size
-            data,
-            transfer_type,
-            &ep.data_toggle,
-        )
+            PipeInfo {
+                endpoint: ep.endpoint | 0x80,
+                endpoint_type: EndpointType::Bulk,
+                max_packet_size: 64,
+                interval_ms: 0,
+            },
+        );
+    }
+
+    fn bulk_in_result(
+        &self,
+        ep: &BulkIn,
+        result: Result<usize, UsbError>,
+    ) -> Result<usize, UsbError> {
+        self.record(ep.usb_address, &result);
+        if result == Err(UsbError::Stall) {
+            ep.halted.set(true);
+        }
+        result
     }
 
     /// Perform a bulk OUT transfer
@@ -886,18 +1724,38 @@ impl<HC: HostController> UsbBus<HC> {
         data: &'a [u8],
         transfer_type: TransferType,
     ) -> impl Future<Output = Result<usize, UsbError>> + 'a {
-        self.driver.bulk_out_transfer(
+        self.diagnostics.borrow_mut().pipe(
             ep.usb_address,
-            ep.endpoint,
-            64, // @TODO max packet size
-            data,
-            transfer_type,
-            &ep.data_toggle,
-        )
+            PipeInfo {
+                endpoint: ep.endpoint,
+                endpoint_type: EndpointType::Bulk,
+                max_packet_size: 64,
+                interval_ms: 0,
+            },
+        );
+        self.driver
+            .bulk_out_transfer(
+                ep.usb_address,
+                ep.endpoint,
+                64, // @TODO max packet size
+                data,
+                transfer_type,
+                &ep.data_toggle,
+            )
+            .map(move |result| {
+                self.record(ep.usb_address, &result);
+                if result == Err(UsbError::Stall) {
+                    ep.halted.set(true);
+                }
+                result
+            })
     }
 
     /// Open an interrupt endpoint for reading
     ///
+    /// The stream ends if the device stalls the endpoint; to be able to
+    /// recover from that, use [`UsbBus::interrupt_pipe_in()`] instead.
+    ///
     /// # Parameters
     ///  - address: USB device address (1-127)
     ///  - endpoint: endpoint number (1-15)
@@ -910,14 +1768,77 @@ impl<HC: HostController> UsbBus<HC> {
         max_packet_size: u16,
         interval_ms: u8,
     ) -> impl Stream<Item = InterruptPacket> + '_ {
-        self.driver
-            .alloc_interrupt_pipe(
-                address,
-                endpoint,
+        self.interrupt_pipe_in(address, endpoint, max_packet_size, interval_ms)
+            .flatten_stream()
+    }
+
+    /// Open an interrupt endpoint for reading, as a pipe
+    ///
+    /// The pipe is a stream of packets, just like
+    /// [`UsbBus::interrupt_endpoint_in()`], but if the device stalls the
+    /// endpoint, the pipe can be recovered using
+    /// [`UsbBus::clear_interrupt_halt()`] and then polled again.
+    ///
+    /// # Parameters
+    ///  - address: USB device address (1-127)
+    ///  - endpoint: endpoint number (1-15)
+    ///  - max_packet_size: maximum expected packet size, in bytes
+    ///  - interval_ms: polling interval, in milliseconds
+    pub fn interrupt_pipe_in(
+        &self,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> impl Future<Output = HC::InterruptPipe> + '_ {
+        self.diagnostics.borrow_mut().pipe(
+            address,
+            PipeInfo {
+                endpoint: endpoint | 0x80,
+                endpoint_type: EndpointType::Interrupt,
                 max_packet_size,
                 interval_ms,
-            )
-            .flatten_stream()
+            },
+        );
+        self.driver.alloc_interrupt_pipe(
+            address,
+            endpoint,
+            max_packet_size,
+            interval_ms,
+        )
+    }
+
+    /// Open an interrupt endpoint for reading, if a pipe is available
+    ///
+    /// As [`UsbBus::interrupt_pipe_in()`], but if all the host
+    /// controller's interrupt-capable pipes are in use, returns
+    /// `Err(UsbError::AllPipesInUse)` straight away instead of
+    /// waiting. The endpoint can then be polled using a
+    /// [`SharedInterruptScheduler`](crate::interrupt_scheduler::SharedInterruptScheduler)
+    /// instead.
+    pub fn try_interrupt_pipe_in(
+        &self,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<HC::InterruptPipe, UsbError> {
+        let pipe = self.driver.try_alloc_interrupt_pipe(
+            address,
+            endpoint,
+            max_packet_size,
+            interval_ms,
+        )?;
+        self.diagnostics.borrow_mut().pipe(
+            address,
+            PipeInfo {
+                endpoint: endpoint | 0x80,
+                endpoint_type: EndpointType::Interrupt,
+                max_packet_size,
+                interval_ms,
+            },
+        );
+        Ok(pipe)
     }
 
     /// Fetch configuration descriptors and report them via a callback
@@ -927,6 +1848,10 @@ impl<HC: HostController> UsbBus<HC> {
     /// which driver to use for a device (if it's not obvious from the simpler
     /// [`UsbBus::get_basic_configuration()`] call).
     ///
+    /// Sequences longer than [`CONFIGURATION_BUFFER_SIZE`] are cut
+    /// short, and only the descriptors which fit are reported; use
+    /// [`UsbBus::get_configuration_with_buffer()`] to read longer ones.
+    ///
     /// # Parameters
     ///  - device: The device to read from
     ///  - visitor: An implementation of [`DescriptorVisitor`] that receives
@@ -936,25 +1861,75 @@ impl<HC: HostController> UsbBus<HC> {
         device: &UnconfiguredDevice,
         visitor: &mut impl DescriptorVisitor,
     ) -> Result<(), UsbError> {
-        // TODO: descriptor suites >64 byte (Ella!)
-        let mut buf = [0u8; 64];
-        let sz = self
-            .driver
+        let mut buf = [0u8; CONFIGURATION_BUFFER_SIZE];
+        let sz = self.read_configuration(device, &mut buf).await?;
+        crate::wire::parse_descriptors(&buf[0..sz], visitor);
+        Ok(())
+    }
+
+    /// Fetch configuration descriptors, using a caller-supplied buffer
+    ///
+    /// As [`UsbBus::get_configuration()`], but reading into `buf`
+    /// (which must be at least 9 bytes long), so that longer
+    /// sequences can be read. Fails with [`UsbError::BufferTooSmall`],
+    /// without reporting any descriptors, if the sequence doesn't fit.
+    pub async fn get_configuration_with_buffer(
+        &self,
+        device: &UnconfiguredDevice,
+        buf: &mut [u8],
+        visitor: &mut impl DescriptorVisitor,
+    ) -> Result<(), UsbError> {
+        let sz = self.read_configuration(device, buf).await?;
+        if configuration_total_length(&buf[0..sz]) > sz {
+            return Err(UsbError::BufferTooSmall);
+        }
+        crate::wire::parse_descriptors(&buf[0..sz], visitor);
+        Ok(())
+    }
+
+    /// Read the raw configuration-descriptor sequence into `buf`
+    ///
+    /// Reads just the configuration descriptor itself first, to find
+    /// out the length of the whole sequence (wTotalLength), then reads
+    /// the whole sequence -- or as much of it as fits in `buf`.
+    /// Returns the number of bytes read; if that's less than
+    /// [`configuration_total_length()`], the sequence was truncated.
+    pub(crate) async fn read_configuration(
+        &self,
+        device: &UnconfiguredDevice,
+        buf: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        let header = buf.len().min(CONFIGURATION_DESCRIPTOR_SIZE);
+        let n = self
+            .get_configuration_descriptor(device, &mut buf[0..header])
+            .await?;
+        let len = configuration_total_length(&buf[0..n]).min(buf.len());
+        if len <= n {
+            return Ok(n);
+        }
+        self.get_configuration_descriptor(device, &mut buf[0..len])
+            .await
+    }
+
+    async fn get_configuration_descriptor(
+        &self,
+        device: &UnconfiguredDevice,
+        buf: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        self.driver
             .control_transfer(
                 device.address(),
                 device.packet_size_ep0,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST,
-                    bRequest: GET_DESCRIPTOR,
-                    wValue: ((CONFIGURATION_DESCRIPTOR as u16) << 8),
-                    wIndex: 0,
-                    wLength: 64,
-                },
-                DataPhase::In(&mut buf),
+                SetupPacket::get_descriptor(
+                    RequestType::new(Direction::In),
+                    DescriptorType::Configuration,
+                    0,
+                    0,
+                    buf.len() as u16,
+                ),
+                DataPhase::In(buf),
             )
-            .await?;
-        crate::wire::parse_descriptors(&buf[0..sz], visitor);
-        Ok(())
+            .await
     }
 
     /// Obtain simplified version of USB configuration descriptors
@@ -969,7 +1944,7 @@ impl<HC: HostController> UsbBus<HC> {
         let mut bd = BasicConfiguration::default();
         self.get_configuration(device, &mut bd).await?;
         if bd.num_configurations == 0 || bd.configuration_value == 0 {
-            Err(UsbError::ProtocolError)
+            Err(UsbError::Protocol("no usable configuration"))
         } else {
             Ok(bd)
         }
@@ -984,13 +1959,23 @@ impl<HC: HostController> UsbBus<HC> {
         let bc = self.get_basic_configuration(&device).await?;
         debug::println!("cfg: {:?}", &bc);
         let device = self.configure(device, bc.configuration_value).await?;
+        let endpoint = bc.in_endpoints.trailing_zeros() as u8;
         hub_state.try_add(
             &self.driver,
             device.address(),
-            bc.in_endpoints.trailing_zeros() as u8,
+            endpoint,
             device.packet_size_ep0,
             9,
         )?;
+        self.diagnostics.borrow_mut().pipe(
+            device.address(),
+            PipeInfo {
+                endpoint: endpoint | 0x80,
+                endpoint_type: EndpointType::Interrupt,
+                max_packet_size: device.packet_size_ep0 as u16,
+                interval_ms: 9,
+            },
+        );
 
         let mut descriptors = [0u8; 64];
         let sz = self
@@ -998,19 +1983,20 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 device.address(),
                 device.packet_size_ep0,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST | CLASS_REQUEST,
-                    bRequest: GET_DESCRIPTOR,
-                    wValue: (HUB_DESCRIPTOR as u16) << 8,
-                    wIndex: 0,
-                    wLength: 64,
-                },
+                SetupPacket::get_descriptor(
+                    RequestType::new(Direction::In)
+                        .kind(RequestTypeType::Class),
+                    DescriptorType::Hub,
+                    0,
+                    0,
+                    64,
+                ),
                 DataPhase::In(&mut descriptors),
             )
             .await?;
 
         if sz < core::mem::size_of::<HubDescriptor>() {
-            return Err(UsbError::ProtocolError);
+            return Err(UsbError::Protocol("hub descriptor too short"));
         }
 
         let ports = descriptors[2];
@@ -1025,6 +2011,29 @@ impl<HC: HostController> UsbBus<HC> {
         Ok(device)
     }
 
+    /// Clear a stall on a hub's interrupt pipe, and resume polling it
+    ///
+    /// If that doesn't work, the pipe is abandoned.
+    async fn recover_hub_pipe(
+        &self,
+        hub_state: &HubState<HC>,
+        slot: usize,
+    ) -> Result<(), UsbError> {
+        let (address, endpoint) = hub_state.endpoints.borrow()[slot];
+        debug::println!("hub {} pipe stalled", address);
+        let result = self.clear_endpoint_halt(address, endpoint | 0x80).await;
+        let mut pipes = hub_state.pipes.borrow_mut();
+        match result {
+            Ok(()) => {
+                if let Some(pipe) = pipes[slot].as_mut() {
+                    pipe.resume();
+                }
+            }
+            Err(_) => pipes[slot] = None,
+        }
+        result
+    }
+
     async fn get_hub_port_status(
         &self,
         hub_address: u8,
@@ -1035,15 +2044,11 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 hub_address,
                 8,
-                SetupPacket {
-                    bmRequestType: DEVICE_TO_HOST
-                        | CLASS_REQUEST
-                        | RECIPIENT_OTHER,
-                    bRequest: GET_STATUS,
-                    wValue: 0,
-                    wIndex: port as u16,
-                    wLength: 4,
-                },
+                SetupPacket::get_status(
+                    HUB_PORT_REQUEST.direction(Direction::In),
+                    port as u16,
+                    4,
+                ),
                 DataPhase::In(&mut data),
             )
             .await?;
@@ -1066,15 +2071,11 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 hub_address,
                 8,
-                SetupPacket {
-                    bmRequestType: HOST_TO_DEVICE
-                        | CLASS_REQUEST
-                        | RECIPIENT_OTHER,
-                    bRequest: CLEAR_FEATURE,
-                    wValue: feature,
-                    wIndex: port as u16,
-                    wLength: 0,
-                },
+                SetupPacket::clear_feature(
+                    HUB_PORT_REQUEST,
+                    feature,
+                    port as u16,
+                ),
                 DataPhase::None,
             )
             .await?;
@@ -1091,15 +2092,11 @@ impl<HC: HostController> UsbBus<HC> {
             .control_transfer(
                 hub_address,
                 8,
-                SetupPacket {
-                    bmRequestType: HOST_TO_DEVICE
-                        | CLASS_REQUEST
-                        | RECIPIENT_OTHER,
-                    bRequest: SET_FEATURE,
-                    wValue: feature,
-                    wIndex: port as u16,
-                    wLength: 0,
-                },
+                SetupPacket::set_feature(
+                    HUB_PORT_REQUEST,
+                    feature,
+                    port as u16,
+                ),
                 DataPhase::None,
             )
             .await?;
@@ -1118,8 +2115,8 @@ impl<HC: HostController> UsbBus<HC> {
         // Hub state machine: each hub must have each port powered,
         // then reset. But only one hub port on the whole *bus* can be
         // in reset at any one time, because it becomes sensitive to
-        // address zero. So there needs to be a bus-wide hub state
-        // machine.
+        // address zero. So there is a bus-wide lock,
+        // HubState::enumeration.
 
         debug::println!(
             "Hub int {} [{}; {}]",
@@ -1129,7 +2126,7 @@ impl<HC: HostController> UsbBus<HC> {
         );
 
         if packet.size == 0 {
-            return Err(UsbError::ProtocolError);
+            return Err(UsbError::Protocol("empty hub interrupt packet"));
         }
 
         let mut port_bitmap = packet.data[0] as u32;
@@ -1170,15 +2167,17 @@ impl<HC: HostController> UsbBus<HC> {
                             .topology
                             .borrow_mut()
                             .device_disconnect(packet.address, port);
+                        self.diagnostics.borrow_mut().forget(mask);
 
                         return Ok(DeviceEvent::Disconnect(mask));
                     }
 
                     // now connected
+                    let enumerating = hub_state.enumeration.alloc().await;
                     self.set_port_feature(packet.address, port, PORT_RESET)
                         .await?;
 
-                    delay_ms(50).await;
+                    Self::delay(&delay_ms, self.config.reset_ms).await;
 
                     let (state, _changes) =
                         self.get_hub_port_status(packet.address, port).await?;
@@ -1193,6 +2192,8 @@ impl<HC: HostController> UsbBus<HC> {
                             _ => UsbSpeed::Low1_5,
                         };
 
+                        Self::delay(&delay_ms, self.config.reset_recovery_ms)
+                            .await;
                         let (device, info) = self.new_device(speed).await?;
                         let is_hub = info.class == HUB_CLASSCODE;
                         let address = hub_state
@@ -1200,7 +2201,10 @@ impl<HC: HostController> UsbBus<HC> {
                             .borrow_mut()
                             .device_connect(packet.address, port, is_hub)
                             .ok_or(UsbError::TooManyDevices)?;
-                        let device = self.set_address(device, address).await?;
+                        let device = self
+                            .set_address_and_settle(device, address, &delay_ms)
+                            .await?;
+                        drop(enumerating);
                         if is_hub {
                             debug::println!("It's a hub");
                             return Ok(DeviceEvent::HubConnect(
@@ -1233,6 +2237,22 @@ pub unsafe fn create_test_device(
         packet_size_ep0: 64,
         in_endpoints_bitmap,
         out_endpoints_bitmap,
+        quirks: Quirks::NONE,
+    }
+}
+
+/// Create an [`UnconfiguredDevice`] object for testing purposes only
+///
+/// # Safety
+///
+/// The device is not valid (it has a bogus address) and will not do anything
+/// useful if passed to a non-mock [`UsbBus`].
+pub unsafe fn create_test_unconfigured_device() -> UnconfiguredDevice {
+    UnconfiguredDevice {
+        usb_address: 255,
+        usb_speed: UsbSpeed::Full12,
+        packet_size_ep0: 64,
+        quirks: Quirks::NONE,
     }
 }
 
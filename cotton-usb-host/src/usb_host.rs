@@ -0,0 +1,337 @@
+use crate::device::driver::Driver;
+use crate::topology::Topology;
+use crate::usb_bus::{
+    configuration_total_length, BusEvent, DeviceInfo, HostController,
+    HubState, UnconfiguredDevice, UsbBus, UsbError, CONFIGURATION_BUFFER_SIZE,
+};
+use crate::wire::DescriptorVisitor;
+use core::cell::RefCell;
+use futures::{Future, Stream, StreamExt};
+
+const MAX_DEVICES: usize = 32;
+
+/// What a [`UsbHost`] remembers about each device it has enumerated
+#[derive(Copy, Clone)]
+struct CachedDevice<const N: usize> {
+    info: DeviceInfo,
+    driver: Option<usize>,
+    descriptors: [u8; N],
+    len: usize,
+    total_len: usize,
+}
+
+/// Something has happened on a bus managed by a [`UsbHost`]
+///
+/// This is the type of events returned from [`UsbHost::events()`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq)]
+pub enum HostEvent {
+    /// A device has been claimed by a driver, configured, and passed to
+    /// that driver's [`Driver::start()`]
+    Started {
+        /// USB device address
+        address: u8,
+        /// Basic information about the device
+        info: DeviceInfo,
+        /// The [`Driver::name()`] of the driver now using it
+        driver: &'static str,
+    },
+
+    /// A device has been enumerated, but no registered driver wants it
+    ///
+    /// It is handed back, unconfigured, in case the application wants
+    /// to deal with it directly; see [`BusEvent::Attached`].
+    Unclaimed(UnconfiguredDevice, DeviceInfo),
+
+    /// A hub has been connected and configured (with this address);
+    /// hubs are handled internally and need no driver
+    HubAttached(u8),
+
+    /// The device with this address has been disconnected, and its
+    /// driver (if any) told via [`Driver::stop()`]
+    Detached(u8),
+
+    /// A device could not be enumerated; see [`BusEvent::EnumerationFailed`]
+    EnumerationFailed(u8, u8, UsbError),
+
+    /// A device (with this address) was enumerated, but reading its
+    /// configuration descriptors, or configuring it for its driver, failed
+    ///
+    /// Devices whose configuration descriptors are longer than the
+    /// `UsbHost`'s cache (by default [`CONFIGURATION_BUFFER_SIZE`]
+    /// bytes, see [`UsbHost::with_bus_sized()`]) fail with
+    /// [`UsbError::BufferTooSmall`], rather than being offered to
+    /// drivers with some of their descriptors missing.
+    StartFailed(u8, UsbError),
+}
+
+/// A USB host: a whole bus of devices, and the drivers for them
+///
+/// This bundles together a [`UsbBus`] (and so the host-controller
+/// driver, quirk table, and diagnostics), the [`HubState`] (the bus
+/// topology and address allocation), a cache of each device's
+/// descriptors, and a registry of [`Driver`]s. Its
+/// [`run()`](UsbHost::run()) future does everything else: detecting
+/// and enumerating devices (and hubs), starting the right driver for
+/// each, and stopping it again on disconnect.
+///
+/// ```no_run
+/// # use cotton_usb_host::device::driver::Driver;
+/// # use cotton_usb_host::host_controller::HostController;
+/// # use cotton_usb_host::usb_host::UsbHost;
+/// # use futures::{future, Future};
+/// # fn delay_ms(_ms: usize) -> impl Future<Output = ()> {
+/// #  future::ready(())
+/// # }
+/// # async fn foo<D: HostController>(driver: D, keyboard: &dyn Driver, disk: &dyn Driver) -> () {
+/// let drivers = [keyboard, disk];
+/// let host = UsbHost::new(driver, &drivers);
+/// host.run(delay_ms).await;
+/// # }
+/// ```
+///
+/// Nothing here assumes any particular executor: like
+/// [`UsbBus::device_events()`], all waiting is done through the "delay"
+/// function supplied. Use [`UsbHost::events()`] instead of `run()` to
+/// also see what is happening (for logging, say, or to handle devices
+/// which no driver claims).
+///
+/// Up to `N` bytes of each device's configuration descriptors are
+/// cached, for each of up to 32 devices; see
+/// [`UsbHost::with_bus_sized()`] for devices with longer descriptors.
+pub struct UsbHost<
+    'a,
+    HC: HostController,
+    const N: usize = CONFIGURATION_BUFFER_SIZE,
+> {
+    bus: UsbBus<HC>,
+    hub_state: HubState<HC>,
+    drivers: &'a [&'a dyn Driver],
+    devices: RefCell<[Option<CachedDevice<N>>; MAX_DEVICES]>,
+}
+
+impl<'a, HC: HostController> UsbHost<'a, HC> {
+    /// Create a new USB host from a host-controller driver and device drivers
+    ///
+    /// Devices are offered to the `drivers` in order, so put more
+    /// specific ones (e.g. matching on VID/PID) before more general
+    /// ones (e.g. matching on class).
+    pub fn new(driver: HC, drivers: &'a [&'a dyn Driver]) -> Self {
+        Self::with_bus(UsbBus::new(driver), drivers)
+    }
+
+    /// Create a new USB host from an existing [`UsbBus`]
+    ///
+    /// For instance, one with non-default [`EnumerationConfig`] or
+    /// extra quirks.
+    ///
+    /// [`EnumerationConfig`]: crate::usb_bus::EnumerationConfig
+    pub fn with_bus(bus: UsbBus<HC>, drivers: &'a [&'a dyn Driver]) -> Self {
+        Self::with_bus_sized(bus, drivers)
+    }
+}
+
+impl<'a, HC: HostController, const N: usize> UsbHost<'a, HC, N> {
+    /// Create a new USB host, caching up to `N` bytes of each device's
+    /// configuration descriptors
+    ///
+    /// As [`UsbHost::with_bus()`], which uses
+    /// [`CONFIGURATION_BUFFER_SIZE`]; a larger `N` admits devices with
+    /// more, or longer, descriptors (a dock's can be over 400 bytes),
+    /// at the cost of 32 times that much RAM:
+    ///
+    /// ```no_run
+    /// # use cotton_usb_host::host_controller::HostController;
+    /// # use cotton_usb_host::usb_bus::UsbBus;
+    /// # use cotton_usb_host::usb_host::UsbHost;
+    /// # fn foo<D: HostController>(driver: D) {
+    /// let host =
+    ///     UsbHost::<_, 1024>::with_bus_sized(UsbBus::new(driver), &[]);
+    /// # }
+    /// ```
+    pub fn with_bus_sized(
+        bus: UsbBus<HC>,
+        drivers: &'a [&'a dyn Driver],
+    ) -> Self {
+        Self {
+            bus,
+            hub_state: HubState::default(),
+            drivers,
+            devices: RefCell::new([None; MAX_DEVICES]),
+        }
+    }
+
+    /// The underlying bus, for drivers to communicate with their devices
+    pub fn bus(&self) -> &UsbBus<HC> {
+        &self.bus
+    }
+
+    /// Return a snapshot of the current physical bus layout
+    pub fn topology(&self) -> Topology {
+        self.hub_state.topology()
+    }
+
+    /// Basic information about the device with this address, if known
+    pub fn device_info(&self, address: u8) -> Option<DeviceInfo> {
+        self.cached(address).map(|d| d.info)
+    }
+
+    /// The name of the driver using the device with this address, if any
+    pub fn driver_name(&self, address: u8) -> Option<&'static str> {
+        self.cached(address)
+            .and_then(|d| d.driver)
+            .map(|i| self.drivers[i].name())
+    }
+
+    /// Replay the (cached) configuration descriptors of a device
+    ///
+    /// Returns `None`, making no callbacks, if the device is not
+    /// known. Otherwise returns whether the descriptors replayed were
+    /// complete: only the first `N` bytes of them are cached (see
+    /// [`UsbHost::with_bus_sized()`]), so for devices with longer
+    /// descriptors this
+    /// replays only those which fit, and returns `Some(false)`.
+    pub fn parse_configuration(
+        &self,
+        address: u8,
+        visitor: &mut impl DescriptorVisitor,
+    ) -> Option<bool> {
+        let d = self.cached(address)?;
+        crate::wire::parse_descriptors(&d.descriptors[0..d.len], visitor);
+        Some(d.total_len <= d.len)
+    }
+
+    fn remember(&self, address: u8, device: CachedDevice<N>) {
+        if let Some(d) = self.devices.borrow_mut().get_mut(address as usize) {
+            *d = Some(device);
+        }
+    }
+
+    fn cached(&self, address: u8) -> Option<CachedDevice<N>> {
+        self.devices
+            .borrow()
+            .get(address as usize)
+            .copied()
+            .flatten()
+    }
+
+    /// Obtain a stream of events, starting and stopping drivers as it goes
+    ///
+    /// Devices are only detected, and drivers only started, while this
+    /// stream is being polled. See [`UsbBus::device_events()`] for the
+    /// "delay" function.
+    pub fn events<
+        D: Future<Output = ()>,
+        F: Fn(usize) -> D + 'static + Clone,
+    >(
+        &'a self,
+        delay_ms: F,
+    ) -> impl Stream<Item = HostEvent> + 'a {
+        self.bus
+            .events(&self.hub_state, delay_ms)
+            .then(move |event| self.handle(event))
+    }
+
+    /// Manage the bus: as [`UsbHost::events()`], but discarding the events
+    ///
+    /// This future never completes, so typically it is the last thing
+    /// a USB task awaits.
+    pub async fn run<
+        D: Future<Output = ()>,
+        F: Fn(usize) -> D + 'static + Clone,
+    >(
+        &'a self,
+        delay_ms: F,
+    ) {
+        self.events(delay_ms)
+            .for_each(|_| futures::future::ready(()))
+            .await
+    }
+
+    async fn handle(&self, event: BusEvent) -> HostEvent {
+        match event {
+            BusEvent::Attached(device, info) => {
+                self.attach(device, info).await
+            }
+            BusEvent::HubAttached(device) => {
+                HostEvent::HubAttached(device.address())
+            }
+            BusEvent::Detached(address) => {
+                let cached = self
+                    .devices
+                    .borrow_mut()
+                    .get_mut(address as usize)
+                    .and_then(Option::take);
+                if let Some(i) = cached.and_then(|d| d.driver) {
+                    self.drivers[i].stop(address);
+                }
+                HostEvent::Detached(address)
+            }
+            BusEvent::EnumerationFailed(hub, port, e) => {
+                HostEvent::EnumerationFailed(hub, port, e)
+            }
+        }
+    }
+
+    async fn attach(
+        &self,
+        device: UnconfiguredDevice,
+        info: DeviceInfo,
+    ) -> HostEvent {
+        let address = device.address();
+        let mut cached = CachedDevice {
+            info,
+            driver: None,
+            descriptors: [0; N],
+            len: 0,
+            total_len: 0,
+        };
+        let result = self
+            .bus
+            .read_configuration(&device, &mut cached.descriptors)
+            .await;
+        if let Ok(len) = result {
+            cached.len = len.min(cached.descriptors.len());
+            cached.total_len =
+                configuration_total_length(&cached.descriptors[0..cached.len]);
+        }
+        self.remember(address, cached);
+        if let Err(e) = result {
+            return HostEvent::StartFailed(address, e);
+        }
+        if cached.total_len > cached.len {
+            return HostEvent::StartFailed(address, UsbError::BufferTooSmall);
+        }
+
+        let descriptors = &cached.descriptors[0..cached.len];
+        let Some((i, configuration)) =
+            self.drivers.iter().enumerate().find_map(|(i, d)| {
+                d.probe(&info, descriptors).map(|c| (i, c))
+            })
+        else {
+            return HostEvent::Unclaimed(device, info);
+        };
+
+        let device = match self
+            .bus
+            .configure_with_descriptors(device, configuration, descriptors)
+            .await
+        {
+            Ok(device) => device,
+            Err(e) => return HostEvent::StartFailed(address, e),
+        };
+        cached.driver = Some(i);
+        self.remember(address, cached);
+        self.drivers[i].start(device, &info);
+        HostEvent::Started {
+            address,
+            info,
+            driver: self.drivers[i].name(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[path = "tests/usb_host.rs"]
+mod tests;
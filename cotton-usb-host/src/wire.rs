@@ -45,8 +45,7 @@ use crate::debug;
 /// in `wLength`.
 ///
 #[repr(C)]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Copy, Clone, PartialEq, Eq)]
 #[allow(non_snake_case)] // These names are from USB 2.0 table 9-2
 pub struct SetupPacket {
     /// The type and specific target of the request.
@@ -63,6 +62,198 @@ pub struct SetupPacket {
     pub wLength: u16,
 }
 
+impl SetupPacket {
+    /// The size of a setup packet on the wire (USB 2.0 section 9.3)
+    pub const SIZE: usize = 8;
+
+    /// Construct a setup packet from its parts
+    pub const fn new(
+        request_type: RequestType,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Self {
+        Self {
+            bmRequestType: request_type.bits(),
+            bRequest: request,
+            wValue: value,
+            wIndex: index,
+            wLength: length,
+        }
+    }
+
+    /// A GET_DESCRIPTOR request (USB 2.0 section 9.4.3)
+    ///
+    /// The `request_type` is usually `RequestType::new(Direction::In)`,
+    /// but class-specific descriptors (such as the hub descriptor) need
+    /// a class request instead. `index` selects among several
+    /// descriptors of the same type; `language` is only relevant for
+    /// string descriptors.
+    pub const fn get_descriptor(
+        request_type: RequestType,
+        descriptor: DescriptorType,
+        index: u8,
+        language: u16,
+        length: u16,
+    ) -> Self {
+        Self::new(
+            request_type,
+            StandardRequest::GetDescriptor as u8,
+            ((descriptor as u16) << 8) | (index as u16),
+            language,
+            length,
+        )
+    }
+
+    /// A SET_ADDRESS request (USB 2.0 section 9.4.6)
+    pub const fn set_address(address: u8) -> Self {
+        Self::new(
+            RequestType::new(Direction::Out),
+            StandardRequest::SetAddress as u8,
+            address as u16,
+            0,
+            0,
+        )
+    }
+
+    /// A SET_CONFIGURATION request (USB 2.0 section 9.4.7)
+    pub const fn set_configuration(configuration_value: u8) -> Self {
+        Self::new(
+            RequestType::new(Direction::Out),
+            StandardRequest::SetConfiguration as u8,
+            configuration_value as u16,
+            0,
+            0,
+        )
+    }
+
+    /// A SET_INTERFACE request (USB 2.0 section 9.4.10)
+    pub const fn set_interface(interface: u8, alternate_setting: u8) -> Self {
+        Self::new(
+            RequestType::new(Direction::Out).recipient(Recipient::Interface),
+            StandardRequest::SetInterface as u8,
+            alternate_setting as u16,
+            interface as u16,
+            0,
+        )
+    }
+
+    /// A GET_STATUS request (USB 2.0 section 9.4.5)
+    ///
+    /// Standard requests return two bytes of status; some classes
+    /// (such as hubs, with their port status) return more.
+    pub const fn get_status(
+        request_type: RequestType,
+        index: u16,
+        length: u16,
+    ) -> Self {
+        Self::new(
+            request_type,
+            StandardRequest::GetStatus as u8,
+            0,
+            index,
+            length,
+        )
+    }
+
+    /// A CLEAR_FEATURE request (USB 2.0 section 9.4.1)
+    pub const fn clear_feature(
+        request_type: RequestType,
+        feature: u16,
+        index: u16,
+    ) -> Self {
+        Self::new(
+            request_type,
+            StandardRequest::ClearFeature as u8,
+            feature,
+            index,
+            0,
+        )
+    }
+
+    /// A SET_FEATURE request (USB 2.0 section 9.4.9)
+    pub const fn set_feature(
+        request_type: RequestType,
+        feature: u16,
+        index: u16,
+    ) -> Self {
+        Self::new(
+            request_type,
+            StandardRequest::SetFeature as u8,
+            feature,
+            index,
+            0,
+        )
+    }
+
+    /// Encode as the 8 bytes sent on the wire
+    ///
+    /// The 16-bit fields are little-endian, as throughout USB. This is
+    /// explicit packing, so doesn't depend on the in-memory layout.
+    pub const fn to_bytes(&self) -> [u8; 8] {
+        let v = self.wValue.to_le_bytes();
+        let i = self.wIndex.to_le_bytes();
+        let l = self.wLength.to_le_bytes();
+        [
+            self.bmRequestType,
+            self.bRequest,
+            v[0],
+            v[1],
+            i[0],
+            i[1],
+            l[0],
+            l[1],
+        ]
+    }
+
+    /// Decode from the 8 bytes sent on the wire
+    ///
+    /// The inverse of [`SetupPacket::to_bytes`]; every 8-byte sequence
+    /// is a valid setup packet, at least syntactically.
+    pub const fn from_bytes(b: [u8; 8]) -> Self {
+        Self {
+            bmRequestType: b[0],
+            bRequest: b[1],
+            wValue: u16::from_le_bytes([b[2], b[3]]),
+            wIndex: u16::from_le_bytes([b[4], b[5]]),
+            wLength: u16::from_le_bytes([b[6], b[7]]),
+        }
+    }
+}
+
+/// Formatted with the fields in hex, as they usually appear in
+/// specifications
+impl core::fmt::Debug for SetupPacket {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "SetupPacket {{ bmRequestType: {:#04x}, bRequest: {:#04x}, \
+             wValue: {:#06x}, wIndex: {:#06x}, wLength: {:#06x} }}",
+            self.bmRequestType,
+            self.bRequest,
+            self.wValue,
+            self.wIndex,
+            self.wLength
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SetupPacket {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "SetupPacket {{ bmRequestType: {=u8:#04x}, bRequest: {=u8:#04x}, wValue: {=u16:#06x}, wIndex: {=u16:#06x}, wLength: {=u16:#06x} }}",
+            self.bmRequestType,
+            self.bRequest,
+            self.wValue,
+            self.wIndex,
+            self.wLength
+        );
+    }
+}
+
 /// A device descriptor, see USB 2.0 section 9.6.1
 #[repr(C)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -187,74 +378,79 @@ pub const DEVICE_TO_HOST: u8 = 0x80;
 pub const HOST_TO_DEVICE: u8 = 0;
 
 /// Control transfer: request defined by USB standard
-pub const STANDARD_REQUEST: u8 = 0;
+pub const STANDARD_REQUEST: u8 = (RequestTypeType::Standard as u8) << 5;
 
 /// Control transfer: request defined by USB class definition
-pub const CLASS_REQUEST: u8 = 0x20;
+pub const CLASS_REQUEST: u8 = (RequestTypeType::Class as u8) << 5;
 
 /// Control transfer: request is vendor-specific
-pub const VENDOR_REQUEST: u8 = 0x40;
+pub const VENDOR_REQUEST: u8 = (RequestTypeType::Vendor as u8) << 5;
 
 /// Control transfer: request targets entire device
-pub const RECIPIENT_DEVICE: u8 = 0;
+pub const RECIPIENT_DEVICE: u8 = Recipient::Device as u8;
 
 /// Control transfer: request targets a particular interface
-pub const RECIPIENT_INTERFACE: u8 = 1;
+pub const RECIPIENT_INTERFACE: u8 = Recipient::Interface as u8;
 
 /// Control transfer: request targets a particular endpoing
-pub const RECIPIENT_ENDPOINT: u8 = 2;
+pub const RECIPIENT_ENDPOINT: u8 = Recipient::Endpoint as u8;
 
 /// Control transfer: request targets something else
-pub const RECIPIENT_OTHER: u8 = 3;
+pub const RECIPIENT_OTHER: u8 = Recipient::Other as u8;
 
 // For request (USB 2.0 table 9-4)
 
 /// Request status (USB 2.0 section 9.4.5)
-pub const GET_STATUS: u8 = 0;
+pub const GET_STATUS: u8 = StandardRequest::GetStatus as u8;
 
 /// Clear feature (USB 2.0 section 9.4.1)
-pub const CLEAR_FEATURE: u8 = 1;
+pub const CLEAR_FEATURE: u8 = StandardRequest::ClearFeature as u8;
 
 /// Set feature (USB 2.0 section 9.4.9)
-pub const SET_FEATURE: u8 = 3;
+pub const SET_FEATURE: u8 = StandardRequest::SetFeature as u8;
 
 /// Set address (USB 2.0 section 9.4.6)
-pub const SET_ADDRESS: u8 = 5;
+pub const SET_ADDRESS: u8 = StandardRequest::SetAddress as u8;
 
 /// Get descriptor (USB 2.0 section 9.4.3)
-pub const GET_DESCRIPTOR: u8 = 6;
+pub const GET_DESCRIPTOR: u8 = StandardRequest::GetDescriptor as u8;
 
 /// Set descriptor (rarely used)
-pub const SET_DESCRIPTOR: u8 = 7;
+pub const SET_DESCRIPTOR: u8 = StandardRequest::SetDescriptor as u8;
 
 /// Set configuration (USB 2.0 section 9.4.7)
-pub const SET_CONFIGURATION: u8 = 9;
+pub const SET_CONFIGURATION: u8 = StandardRequest::SetConfiguration as u8;
 
 // Descriptor types (USB 2.0 table 9-5)
 
 /// Device descriptor (USB 2.0 section 9.6.1)
-pub const DEVICE_DESCRIPTOR: u8 = 1;
+pub const DEVICE_DESCRIPTOR: u8 = DescriptorType::Device as u8;
 
 /// Configuration descriptor (USB 2.0 section 9.6.3)
-pub const CONFIGURATION_DESCRIPTOR: u8 = 2;
+pub const CONFIGURATION_DESCRIPTOR: u8 = DescriptorType::Configuration as u8;
 
 /// String descriptor (USB 2.0 section 9.6.7)
-pub const STRING_DESCRIPTOR: u8 = 3;
+pub const STRING_DESCRIPTOR: u8 = DescriptorType::String as u8;
 
 /// Interface descriptor (USB 2.0 section 9.6.5)
-pub const INTERFACE_DESCRIPTOR: u8 = 4;
+pub const INTERFACE_DESCRIPTOR: u8 = DescriptorType::Interface as u8;
 
 /// Endpoint descriptor (USB 2.0 section 9.6.6)
-pub const ENDPOINT_DESCRIPTOR: u8 = 5;
+pub const ENDPOINT_DESCRIPTOR: u8 = DescriptorType::Endpoint as u8;
 
 /// Hub descriptor (USB 2.0 section 11.23.3.1 and table 11-13)
-pub const HUB_DESCRIPTOR: u8 = 0x29;
+pub const HUB_DESCRIPTOR: u8 = DescriptorType::Hub as u8;
 
 // Class codes (DeviceDescriptor.bDeviceClass)
 
 /// Class code for USB hubs (USB 2.0 section 11.23.1)
 pub const HUB_CLASSCODE: u8 = 9;
 
+// Standard feature selectors (USB 2.0 table 9-6)
+
+/// Halt (stall) an endpoint (USB 2.0 section 9.4.5)
+pub const ENDPOINT_HALT: u16 = 0;
+
 // Values for SET_FEATURE for hubs (USB 2.0 table 11-17)
 
 /// Reset a port (USB 2.0 section 11.5.1.5)
@@ -263,6 +459,289 @@ pub const PORT_RESET: u16 = 4;
 /// Power-on a port (USB 2.0 section 11.5.1.13)
 pub const PORT_POWER: u16 = 8;
 
+/// Descriptor type, see USB 2.0 table 9-5 and successors
+///
+/// Sent in the high byte of `wValue` of a GET_DESCRIPTOR request, and
+/// received as `bDescriptorType` in every descriptor.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum DescriptorType {
+    /// Device descriptor (USB 2.0 section 9.6.1)
+    Device = 1,
+    /// Configuration descriptor (USB 2.0 section 9.6.3)
+    Configuration = 2,
+    /// String descriptor (USB 2.0 section 9.6.7)
+    String = 3,
+    /// Interface descriptor (USB 2.0 section 9.6.5)
+    Interface = 4,
+    /// Endpoint descriptor (USB 2.0 section 9.6.6)
+    Endpoint = 5,
+    /// Device qualifier descriptor (USB 2.0 section 9.6.2)
+    DeviceQualifier = 6,
+    /// Other-speed configuration descriptor (USB 2.0 section 9.6.4)
+    OtherSpeedConfiguration = 7,
+    /// Interface power descriptor (USB Interface Power Management)
+    InterfacePower = 8,
+    /// OTG descriptor (USB OTG and Embedded Host supplement)
+    Otg = 9,
+    /// Debug descriptor (USB 2.0 Debug Device functional specification)
+    Debug = 0x0A,
+    /// Interface association descriptor (USB 2.0 ECN "IAD")
+    InterfaceAssociation = 0x0B,
+    /// Binary device object store (USB 3.2 section 9.6.2)
+    Bos = 0x0F,
+    /// Device capability descriptor (USB 3.2 section 9.6.2)
+    DeviceCapability = 0x10,
+    /// HID descriptor (HID 1.11 section 6.2.1)
+    Hid = 0x21,
+    /// HID report descriptor (HID 1.11 section 6.2.2)
+    Report = 0x22,
+    /// Hub descriptor (USB 2.0 section 11.23.2.1)
+    Hub = 0x29,
+    /// SuperSpeed hub descriptor (USB 3.2 section 10.15.2.1)
+    SuperSpeedHub = 0x2A,
+    /// SuperSpeed endpoint companion descriptor (USB 3.2 section 9.6.7)
+    SuperSpeedEndpointCompanion = 0x30,
+}
+
+impl TryFrom<u8> for DescriptorType {
+    /// The unrecognised value
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => Self::Device,
+            2 => Self::Configuration,
+            3 => Self::String,
+            4 => Self::Interface,
+            5 => Self::Endpoint,
+            6 => Self::DeviceQualifier,
+            7 => Self::OtherSpeedConfiguration,
+            8 => Self::InterfacePower,
+            9 => Self::Otg,
+            0x0A => Self::Debug,
+            0x0B => Self::InterfaceAssociation,
+            0x0F => Self::Bos,
+            0x10 => Self::DeviceCapability,
+            0x21 => Self::Hid,
+            0x22 => Self::Report,
+            0x29 => Self::Hub,
+            0x2A => Self::SuperSpeedHub,
+            0x30 => Self::SuperSpeedEndpointCompanion,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl From<DescriptorType> for u8 {
+    fn from(value: DescriptorType) -> Self {
+        value as u8
+    }
+}
+
+/// Standard request code, see USB 2.0 table 9-4
+///
+/// Sent as `bRequest` in a [`SetupPacket`] whose request type is
+/// [`RequestTypeType::Standard`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum StandardRequest {
+    /// Request status (USB 2.0 section 9.4.5)
+    GetStatus = 0,
+    /// Clear feature (USB 2.0 section 9.4.1)
+    ClearFeature = 1,
+    /// Set feature (USB 2.0 section 9.4.9)
+    SetFeature = 3,
+    /// Set address (USB 2.0 section 9.4.6)
+    SetAddress = 5,
+    /// Get descriptor (USB 2.0 section 9.4.3)
+    GetDescriptor = 6,
+    /// Set descriptor (USB 2.0 section 9.4.8, rarely used)
+    SetDescriptor = 7,
+    /// Get configuration (USB 2.0 section 9.4.2)
+    GetConfiguration = 8,
+    /// Set configuration (USB 2.0 section 9.4.7)
+    SetConfiguration = 9,
+    /// Get interface alternate setting (USB 2.0 section 9.4.4)
+    GetInterface = 10,
+    /// Set interface alternate setting (USB 2.0 section 9.4.10)
+    SetInterface = 11,
+    /// Synch frame, for isochronous endpoints (USB 2.0 section 9.4.11)
+    SynchFrame = 12,
+}
+
+impl TryFrom<u8> for StandardRequest {
+    /// The unrecognised value
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::GetStatus,
+            1 => Self::ClearFeature,
+            3 => Self::SetFeature,
+            5 => Self::SetAddress,
+            6 => Self::GetDescriptor,
+            7 => Self::SetDescriptor,
+            8 => Self::GetConfiguration,
+            9 => Self::SetConfiguration,
+            10 => Self::GetInterface,
+            11 => Self::SetInterface,
+            12 => Self::SynchFrame,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl From<StandardRequest> for u8 {
+    fn from(value: StandardRequest) -> Self {
+        value as u8
+    }
+}
+
+/// Recipient of a control request, see USB 2.0 table 9-2 (bits 0-4)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Recipient {
+    /// Request targets entire device
+    Device = 0,
+    /// Request targets a particular interface
+    Interface = 1,
+    /// Request targets a particular endpoint
+    Endpoint = 2,
+    /// Request targets something else (e.g., a hub port)
+    Other = 3,
+}
+
+impl TryFrom<u8> for Recipient {
+    /// The unrecognised value
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Device,
+            1 => Self::Interface,
+            2 => Self::Endpoint,
+            3 => Self::Other,
+            _ => return Err(value),
+        })
+    }
+}
+
+/// Type of a control request, see USB 2.0 table 9-2 (bits 5-6)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum RequestTypeType {
+    /// Request defined by USB standard
+    Standard = 0,
+    /// Request defined by USB class definition
+    Class = 1,
+    /// Request is vendor-specific
+    Vendor = 2,
+}
+
+impl TryFrom<u8> for RequestTypeType {
+    /// The unrecognised value
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Standard,
+            1 => Self::Class,
+            2 => Self::Vendor,
+            _ => return Err(value),
+        })
+    }
+}
+
+/// The `bmRequestType` field of a [`SetupPacket`], unpacked
+///
+/// Built up from its parts, starting with a direction:
+///
+/// ```
+/// # use cotton_usb_host::wire::{Direction, Recipient, RequestType, RequestTypeType};
+/// let rt = RequestType::new(Direction::In)
+///     .kind(RequestTypeType::Class)
+///     .recipient(Recipient::Other);
+/// assert_eq!(rt.bits(), 0xA3);
+/// ```
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RequestType {
+    /// Direction of the data phase, if any
+    pub direction: Direction,
+    /// Who defines the request
+    pub kind: RequestTypeType,
+    /// What the request targets
+    pub recipient: Recipient,
+}
+
+impl RequestType {
+    /// A standard request to the device, in the given direction
+    pub const fn new(direction: Direction) -> Self {
+        Self {
+            direction,
+            kind: RequestTypeType::Standard,
+            recipient: Recipient::Device,
+        }
+    }
+
+    /// Change the direction of the request
+    pub const fn direction(self, direction: Direction) -> Self {
+        Self { direction, ..self }
+    }
+
+    /// Change the type of request
+    pub const fn kind(self, kind: RequestTypeType) -> Self {
+        Self { kind, ..self }
+    }
+
+    /// Change the recipient of the request
+    pub const fn recipient(self, recipient: Recipient) -> Self {
+        Self { recipient, ..self }
+    }
+
+    /// Pack into a `bmRequestType` byte
+    pub const fn bits(self) -> u8 {
+        let direction = match self.direction {
+            Direction::In => DEVICE_TO_HOST,
+            Direction::Out => HOST_TO_DEVICE,
+        };
+        direction | ((self.kind as u8) << 5) | (self.recipient as u8)
+    }
+}
+
+impl From<RequestType> for u8 {
+    fn from(value: RequestType) -> Self {
+        value.bits()
+    }
+}
+
+impl TryFrom<u8> for RequestType {
+    /// The unrecognised value (with a reserved type or recipient)
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let direction = if (value & DEVICE_TO_HOST) != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        };
+        let kind =
+            RequestTypeType::try_from((value >> 5) & 3).map_err(|_| value)?;
+        let recipient =
+            Recipient::try_from(value & 0x1F).map_err(|_| value)?;
+        Ok(Self {
+            direction,
+            kind,
+            recipient,
+        })
+    }
+}
+
 /// Endpoint type, see USB 2.0 sections 9.3.6 and 5.3.1
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "std", derive(Debug))]
@@ -277,8 +756,7 @@ pub enum EndpointType {
 
 /// Direction of a USB transfer
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Direction {
     /// IN transactions are device-to-host transfers
     In,
@@ -329,44 +807,50 @@ impl DescriptorVisitor for ShowDescriptors {
     }
 }
 
+/// The start of a descriptor, as a `T`, if it's long enough
+///
+/// Descriptors may be longer than the standard says (for instance, audio
+/// class endpoint descriptors are 9 bytes, not 7); the extra bytes are
+/// ignored, see USB 2.0 section 9.5.
+fn descriptor_as<T: bytemuck::Pod>(d: &[u8]) -> Option<&T> {
+    bytemuck::try_from_bytes(d.get(0..core::mem::size_of::<T>())?).ok()
+}
+
 /// Parse a configuration-descriptor sequence
 ///
 /// And make callbacks via the [`DescriptorVisitor`] for everything
-/// that's found.
+/// that's found. Parsing stops at the first descriptor whose length
+/// field is impossible (less than 2, or running off the end of `buf`);
+/// standard descriptors which are too short for their type are skipped.
 pub fn parse_descriptors(buf: &[u8], v: &mut impl DescriptorVisitor) {
     let mut index = 0;
 
-    while buf.len() > index + 2 {
+    while buf.len() >= index + 2 {
         let dlen = buf[index] as usize;
         let dtype = buf[index + 1];
 
         if dlen < 2 || buf.len() < index + dlen {
             return;
         }
+        let d = &buf[index..index + dlen];
 
-        match dtype {
-            CONFIGURATION_DESCRIPTOR => {
-                if let Ok(c) =
-                    bytemuck::try_from_bytes(&buf[index..index + dlen])
-                {
+        match DescriptorType::try_from(dtype) {
+            Ok(DescriptorType::Configuration) => {
+                if let Some(c) = descriptor_as(d) {
                     v.on_configuration(c);
                 }
             }
-            INTERFACE_DESCRIPTOR => {
-                if let Ok(i) =
-                    bytemuck::try_from_bytes(&buf[index..index + dlen])
-                {
+            Ok(DescriptorType::Interface) => {
+                if let Some(i) = descriptor_as(d) {
                     v.on_interface(i);
                 }
             }
-            ENDPOINT_DESCRIPTOR => {
-                if let Ok(e) =
-                    bytemuck::try_from_bytes(&buf[index..index + dlen])
-                {
+            Ok(DescriptorType::Endpoint) => {
+                if let Some(e) = descriptor_as(d) {
                     v.on_endpoint(e);
                 }
             }
-            _ => v.on_other(&buf[index..(index + dlen)]),
+            _ => v.on_other(d),
         }
 
         index += dlen;
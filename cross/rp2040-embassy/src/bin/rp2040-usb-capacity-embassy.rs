@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+use core::pin::pin;
+use cotton_scsi::{AsyncBlockDevice, ScsiBlockDevice, ScsiDevice};
+use cotton_usb_host::device::identify::IdentifyFromDescriptors;
+use cotton_usb_host::usb_bus::{DeviceEvent, HubState, UsbBus};
+use cotton_usb_host_msc::{IdentifyMassStorage, MassStorage};
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use futures_util::StreamExt;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    defmt::println!(
+        "{} from {} {}",
+        env!("CARGO_BIN_NAME"),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    );
+
+    let p = embassy_rp::init(Default::default());
+
+    // Declares the statics, binds USBCTRL_IRQ, and resets the
+    // controller into host mode
+    let driver = cotton_usb_host::rp2040_embassy_host!(p.USB);
+    let hub_state = HubState::default();
+    let stack = UsbBus::new(driver);
+
+    let delay = |ms: usize| Timer::after_millis(ms as u64);
+    let mut events = pin!(stack.device_events(&hub_state, delay));
+
+    loop {
+        let Some(DeviceEvent::Connect(device, info)) = events.next().await
+        else {
+            continue;
+        };
+        defmt::println!("Got device {:x} {:x}", device, info);
+
+        let mut ims = IdentifyMassStorage::default();
+        let Ok(()) = stack.get_configuration(&device, &mut ims).await else {
+            continue;
+        };
+        let Some(cfg) = ims.identify() else {
+            defmt::println!("Not mass-storage");
+            continue;
+        };
+        let Ok(device) = stack.configure(device, cfg).await else {
+            continue;
+        };
+        let Ok(ms) = MassStorage::new(&stack, device) else {
+            continue;
+        };
+        let mut abd = ScsiBlockDevice::new(ScsiDevice::new(ms));
+        match abd.device_info().await {
+            Ok(info) => {
+                let capacity = info.blocks * (info.block_size as u64);
+                defmt::println!(
+                    "{} blocks x {} bytes = {} MB",
+                    info.blocks,
+                    info.block_size,
+                    (capacity + (1 << 19)) >> 20,
+                );
+            }
+            Err(e) => defmt::println!("device_info: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _; // global logger
+use panic_probe as _;
+use rp_pico as _; // includes boot2
+
+#[rtic::app(device = rp_pico::hal::pac, dispatchers = [ADC_IRQ_FIFO])]
+mod app {
+    use core::future::Future;
+    use core::pin::pin;
+    use cotton_scsi::{
+        AsyncBlockDevice, PeripheralType, ScsiBlockDevice, ScsiDevice,
+    };
+    use cotton_usb_host::bench::{self, Report};
+    use cotton_usb_host::device::identify::IdentifyFromDescriptors;
+    use cotton_usb_host::host::rp2040::{UsbShared, UsbStatics};
+    use cotton_usb_host::host_controller::HostController;
+    use cotton_usb_host::usb_bus::{
+        DataPhase, DeviceEvent, HubState, UsbBus, UsbDevice,
+    };
+    use cotton_usb_host::wire::{
+        ConfigurationDescriptor, DescriptorVisitor, Direction,
+        EndpointDescriptor, InterfaceDescriptor, Recipient, RequestType,
+        RequestTypeType, SetupPacket,
+    };
+    use cotton_usb_host_msc::{IdentifyMassStorage, MassStorage};
+    use futures_util::StreamExt;
+    use rp_pico::pac;
+    use rtic_monotonics::rp2040::prelude::*;
+    use static_cell::ConstStaticCell;
+
+    /// Control-transfer round trips per device
+    const LATENCY_ITERATIONS: u32 = 200;
+
+    /// Interrupt packets timed per HID device
+    const JITTER_SAMPLES: u32 = 200;
+
+    /// Bytes read from a mass-storage device per chunk size
+    const THROUGHPUT_BYTES: u32 = 1 << 20;
+
+    /// Largest chunk size in the sweep (the sweep starts at 512)
+    const MAX_CHUNK: usize = 16384;
+
+    #[shared]
+    struct Shared {
+        shared: &'static UsbShared,
+    }
+
+    #[local]
+    struct Local {
+        resets: pac::RESETS,
+        regs: Option<pac::USBCTRL_REGS>,
+        dpram: Option<pac::USBCTRL_DPRAM>,
+    }
+
+    rp2040_timer_monotonic!(Mono); // 1MHz!
+
+    #[init()]
+    fn init(c: init::Context) -> (Shared, Local) {
+        defmt::println!(
+            "{} from {} {}-g{}",
+            env!("CARGO_BIN_NAME"),
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            git_version::git_version!()
+        );
+
+        let device = c.device;
+        let mut resets = device.RESETS;
+        let mut watchdog =
+            rp2040_hal::watchdog::Watchdog::new(device.WATCHDOG);
+
+        let _clocks = rp2040_hal::clocks::init_clocks_and_plls(
+            rp_pico::XOSC_CRYSTAL_FREQ,
+            device.XOSC,
+            device.CLOCKS,
+            device.PLL_SYS,
+            device.PLL_USB,
+            &mut resets,
+            &mut watchdog,
+        )
+        .ok()
+        .unwrap();
+
+        Mono::start(device.TIMER, &resets);
+
+        // See rp2040-usb-msc.rs
+        unsafe {
+            rp2040_hal::pac::TIMER::steal()
+                .dbgpause()
+                .write(|w| w.bits(0));
+        }
+
+        usb_task::spawn().unwrap();
+
+        static USB_SHARED: UsbShared = UsbShared::new();
+
+        (
+            Shared {
+                shared: &USB_SHARED,
+            },
+            Local {
+                regs: Some(device.USBCTRL_REGS),
+                dpram: Some(device.USBCTRL_DPRAM),
+                resets,
+            },
+        )
+    }
+
+    fn rtic_delay(ms: usize) -> impl Future<Output = ()> {
+        Mono::delay(<Mono as rtic_monotonics::Monotonic>::Duration::millis(
+            ms as u64,
+        ))
+    }
+
+    /// Microseconds since boot
+    fn now_us() -> u64 {
+        Mono::now().ticks()
+    }
+
+    /// Find the first interrupt IN endpoint of a HID interface
+    #[derive(Default)]
+    struct IdentifyHid {
+        current_configuration: Option<u8>,
+        in_hid_interface: bool,
+        interface: u8,
+        hid: Option<(u8, u8, EndpointDescriptor)>,
+    }
+
+    impl DescriptorVisitor for IdentifyHid {
+        fn on_configuration(&mut self, c: &ConfigurationDescriptor) {
+            self.current_configuration = Some(c.bConfigurationValue);
+        }
+        fn on_interface(&mut self, i: &InterfaceDescriptor) {
+            self.in_hid_interface = i.bInterfaceClass == 3;
+            self.interface = i.bInterfaceNumber;
+        }
+        fn on_endpoint(&mut self, e: &EndpointDescriptor) {
+            if self.in_hid_interface
+                && self.hid.is_none()
+                && (e.bEndpointAddress & 0x80) != 0
+                && (e.bmAttributes & 3) == 3
+            {
+                if let Some(cfg) = self.current_configuration {
+                    self.hid = Some((cfg, self.interface, *e));
+                }
+            }
+        }
+    }
+
+    impl IdentifyFromDescriptors for IdentifyHid {
+        fn identify(&self) -> Option<u8> {
+            self.hid.map(|(cfg, _, _)| cfg)
+        }
+    }
+
+    async fn bench_hid<HC: HostController>(
+        stack: &UsbBus<HC>,
+        device: &UsbDevice,
+        interface: u8,
+        endpoint: &EndpointDescriptor,
+    ) {
+        // SET_IDLE with the shortest non-zero duration (4ms), so that
+        // the device reports every time it's polled, changes or no
+        // (HID 1.11 section 7.2.4)
+        let _ = stack
+            .control_transfer(
+                device,
+                SetupPacket::new(
+                    RequestType::new(Direction::Out)
+                        .kind(RequestTypeType::Class)
+                        .recipient(Recipient::Interface),
+                    0x0A,
+                    1 << 8,
+                    interface as u16,
+                    0,
+                ),
+                DataPhase::None,
+            )
+            .await;
+
+        let stats = bench::interrupt_intervals(
+            stack,
+            device,
+            endpoint.bEndpointAddress & 0xF,
+            u16::from_le_bytes(endpoint.wMaxPacketSize),
+            endpoint.bInterval,
+            JITTER_SAMPLES,
+            now_us,
+        )
+        .await;
+        defmt::println!(
+            "{}",
+            Report {
+                name: "interrupt_interval",
+                param: endpoint.bInterval as u32,
+                result: stats,
+            }
+        );
+    }
+
+    async fn bench_msc<HC: HostController>(
+        stack: &UsbBus<HC>,
+        device: UsbDevice,
+        buf: &mut [u8; MAX_CHUNK],
+    ) {
+        let Ok(ms) = MassStorage::new(stack, device) else {
+            return;
+        };
+        let mut device = ScsiDevice::new(ms);
+        let Ok(info) = device.inquiry().await else {
+            return;
+        };
+        if info.peripheral_type != PeripheralType::Disk {
+            return;
+        }
+        let Ok(()) = device.test_unit_ready().await else {
+            defmt::println!("Unit NOT ready");
+            return;
+        };
+
+        let mut abd = ScsiBlockDevice::new(device);
+        let Ok(device_info) = abd.device_info().await else {
+            return;
+        };
+        let total_blocks = THROUGHPUT_BYTES / device_info.block_size;
+
+        let mut chunk = 512;
+        while chunk <= MAX_CHUNK {
+            match cotton_usb_host_msc::bench::read_throughput(
+                &mut abd,
+                0,
+                total_blocks,
+                &mut buf[0..chunk],
+                now_us,
+            )
+            .await
+            {
+                Ok(result) => defmt::println!(
+                    "{}",
+                    Report {
+                        name: "msc_read",
+                        param: chunk as u32,
+                        result,
+                    }
+                ),
+                Err(e) => defmt::println!("msc_read {}: {}", chunk, e),
+            }
+            chunk *= 2;
+        }
+    }
+
+    #[task(local = [regs, dpram, resets], shared = [&shared], priority = 2)]
+    async fn usb_task(cx: usb_task::Context) {
+        static USB_STATICS: ConstStaticCell<UsbStatics> =
+            ConstStaticCell::new(UsbStatics::new());
+        let statics = USB_STATICS.take();
+
+        static BUFFER: ConstStaticCell<[u8; MAX_CHUNK]> =
+            ConstStaticCell::new([0u8; MAX_CHUNK]);
+        let buf = BUFFER.take();
+
+        let driver = cotton_usb_host::host::rp2040::Rp2040HostController::new(
+            cx.local.resets,
+            cx.local.regs.take().unwrap(),
+            cx.local.dpram.take().unwrap(),
+            cx.shared.shared,
+            statics,
+        );
+        let hub_state = HubState::default();
+        let stack = UsbBus::new(driver);
+
+        let mut p = pin!(stack.device_events(&hub_state, rtic_delay));
+
+        loop {
+            let Some(DeviceEvent::Connect(device, info)) = p.next().await
+            else {
+                continue;
+            };
+            defmt::println!("Got device {:x} {:x}", device, info);
+
+            let mut ims = IdentifyMassStorage::default();
+            let mut hid = IdentifyHid::default();
+            let Ok(()) = stack.get_configuration(&device, &mut ims).await
+            else {
+                continue;
+            };
+            let Ok(()) = stack.get_configuration(&device, &mut hid).await
+            else {
+                continue;
+            };
+
+            let cfg = ims.identify().or(hid.identify()).unwrap_or(1);
+            let Ok(device) = stack.configure(device, cfg).await else {
+                continue;
+            };
+
+            match bench::control_latency(
+                &stack,
+                &device,
+                LATENCY_ITERATIONS,
+                now_us,
+            )
+            .await
+            {
+                Ok(stats) => defmt::println!(
+                    "{}",
+                    Report {
+                        name: "control_latency",
+                        param: 0,
+                        result: stats,
+                    }
+                ),
+                Err(e) => defmt::println!("control_latency: {}", e),
+            }
+
+            if ims.identify().is_some() {
+                bench_msc(&stack, device, buf).await;
+            } else if let Some((_, interface, ep)) = hid.hid {
+                bench_hid(&stack, &device, interface, &ep).await;
+            }
+            defmt::println!("BENCH done");
+        }
+    }
+
+    #[task(binds = USBCTRL_IRQ, shared = [&shared], priority = 2)]
+    fn usb_interrupt(cx: usb_interrupt::Context) {
+        cx.shared.shared.on_irq();
+    }
+}
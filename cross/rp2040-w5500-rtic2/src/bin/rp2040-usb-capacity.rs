@@ -0,0 +1,134 @@
+#![no_std]
+#![no_main]
+
+use cotton_usb_host::host::rp2040::Rp2040Usb;
+use defmt_rtt as _; // global logger
+use panic_probe as _;
+use rp_pico as _; // includes boot2
+
+/// Everything the USB host needs to be 'static
+static USB: Rp2040Usb = Rp2040Usb::new();
+
+#[rtic::app(device = rp_pico::hal::pac, dispatchers = [ADC_IRQ_FIFO])]
+mod app {
+    use super::USB;
+    use core::pin::pin;
+    use cotton_scsi::{AsyncBlockDevice, ScsiBlockDevice, ScsiDevice};
+    use cotton_usb_host::device::identify::IdentifyFromDescriptors;
+    use cotton_usb_host::usb_bus::{DeviceEvent, HubState, UsbBus};
+    use cotton_usb_host_msc::{IdentifyMassStorage, MassStorage};
+    use futures_util::StreamExt;
+    use rp_pico::pac;
+    use rtic_monotonics::rp2040::prelude::*;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        resets: pac::RESETS,
+        regs: Option<pac::USBCTRL_REGS>,
+        dpram: Option<pac::USBCTRL_DPRAM>,
+    }
+
+    rp2040_timer_monotonic!(Mono); // 1MHz!
+
+    #[init()]
+    fn init(c: init::Context) -> (Shared, Local) {
+        defmt::println!(
+            "{} from {} {}-g{}",
+            env!("CARGO_BIN_NAME"),
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            git_version::git_version!()
+        );
+
+        let device = c.device;
+        let mut resets = device.RESETS;
+        let mut watchdog =
+            rp2040_hal::watchdog::Watchdog::new(device.WATCHDOG);
+
+        let _clocks = rp2040_hal::clocks::init_clocks_and_plls(
+            rp_pico::XOSC_CRYSTAL_FREQ,
+            device.XOSC,
+            device.CLOCKS,
+            device.PLL_SYS,
+            device.PLL_USB,
+            &mut resets,
+            &mut watchdog,
+        )
+        .ok()
+        .unwrap();
+
+        Mono::start(device.TIMER, &resets);
+
+        usb_task::spawn().unwrap();
+
+        (
+            Shared {},
+            Local {
+                regs: Some(device.USBCTRL_REGS),
+                dpram: Some(device.USBCTRL_DPRAM),
+                resets,
+            },
+        )
+    }
+
+    #[task(local = [regs, dpram, resets], priority = 2)]
+    async fn usb_task(cx: usb_task::Context) {
+        // The controller isn't Send, so it's created here rather than
+        // in init()
+        let driver = USB.host_controller(
+            cx.local.resets,
+            cx.local.regs.take().unwrap(),
+            cx.local.dpram.take().unwrap(),
+        );
+        let hub_state = HubState::default();
+        let stack = UsbBus::new(driver);
+
+        let delay = cotton_usb_host::rtic_delay_ms!(Mono);
+        let mut p = pin!(stack.device_events(&hub_state, delay));
+
+        loop {
+            let Some(DeviceEvent::Connect(device, info)) = p.next().await
+            else {
+                continue;
+            };
+            defmt::println!("Got device {:x} {:x}", device, info);
+
+            let mut ims = IdentifyMassStorage::default();
+            let Ok(()) = stack.get_configuration(&device, &mut ims).await
+            else {
+                continue;
+            };
+            let Some(cfg) = ims.identify() else {
+                defmt::println!("Not mass-storage");
+                continue;
+            };
+            let Ok(device) = stack.configure(device, cfg).await else {
+                continue;
+            };
+            let Ok(ms) = MassStorage::new(&stack, device) else {
+                continue;
+            };
+            let mut abd = ScsiBlockDevice::new(ScsiDevice::new(ms));
+            match abd.device_info().await {
+                Ok(info) => {
+                    let capacity = info.blocks * (info.block_size as u64);
+                    defmt::println!(
+                        "{} blocks x {} bytes = {} MB",
+                        info.blocks,
+                        info.block_size,
+                        (capacity + (1 << 19)) >> 20,
+                    );
+                }
+                Err(e) => defmt::println!("device_info: {:?}", e),
+            }
+        }
+    }
+
+    #[task(binds = USBCTRL_IRQ, priority = 2)]
+    fn usb_interrupt(_: usb_interrupt::Context) {
+        USB.on_irq();
+    }
+}
@@ -7,14 +7,16 @@ use rp_pico as _; // includes boot2
 
 #[rtic::app(device = rp_pico::hal::pac, dispatchers = [ADC_IRQ_FIFO])]
 mod app {
+    use core::cell::Cell;
     use core::future::Future;
     use core::pin::pin;
+    use cotton_usb_host::device::driver::Driver;
     use cotton_usb_host::host::rp2040::{UsbShared, UsbStatics};
     use cotton_usb_host::host_controller::HostController;
     use cotton_usb_host::usb_bus::{
-        DataPhase, DeviceEvent, DeviceInfo, HubState, UsbBus, UsbDevice,
-        UsbError,
+        DataPhase, DeviceInfo, UsbBus, UsbDevice, UsbError,
     };
+    use cotton_usb_host::usb_host::UsbHost;
     use cotton_usb_host::wire::{
         SetupPacket, DEVICE_TO_HOST, HOST_TO_DEVICE, VENDOR_REQUEST,
     };
@@ -228,6 +230,28 @@ mod app {
         }
     }
 
+    /// Hands the device over from the UsbHost to usb_task
+    #[derive(Default)]
+    struct Ax88772Driver {
+        device: Cell<Option<UsbDevice>>,
+    }
+
+    impl Driver for Ax88772Driver {
+        fn name(&self) -> &'static str {
+            "ax88772"
+        }
+
+        fn probe(&self, info: &DeviceInfo, _: &[u8]) -> Option<u8> {
+            identify_ax88772(info)
+        }
+
+        fn start(&self, device: UsbDevice, _: &DeviceInfo) {
+            self.device.set(Some(device));
+        }
+
+        fn stop(&self, _: u8) {}
+    }
+
     fn rtic_delay(ms: usize) -> impl Future<Output = ()> {
         Mono::delay(<Mono as rtic_monotonics::Monotonic>::Duration::millis(
             ms as u64,
@@ -247,37 +271,18 @@ mod app {
             cx.shared.shared,
             statics,
         );
-        let hub_state = HubState::default();
-        let stack = UsbBus::new(driver);
-
-        let mut p = pin!(stack.device_events(&hub_state, rtic_delay));
-
-        loop {
-            defmt::println!("loop");
-            let device = p.next().await;
-
-            if let Some(DeviceEvent::EnumerationError(h, p, e)) = device {
-                defmt::println!(
-                    "Enumeration error {} on hub {} port {}",
-                    e,
-                    h,
-                    p
-                );
-            }
-
-            defmt::println!("{:?}", hub_state.topology());
-
-            if let Some(DeviceEvent::Connect(device, info)) = device {
-                defmt::println!("Got device {:x} {:x}", device, info);
-
-                if let Some(cfg) = identify_ax88772(&info) {
-                    let Ok(device) = stack.configure(device, cfg).await else {
-                        continue;
-                    };
-                    let otge = AX88772::new(&stack, device);
-                    if let Err(e) = otge.init().await {
-                        defmt::println!("error {}", e);
-                    }
+        let ax88772 = Ax88772Driver::default();
+        let drivers: [&dyn Driver; 1] = [&ax88772];
+        let host = UsbHost::new(driver, &drivers);
+
+        let mut events = pin!(host.events(rtic_delay));
+        while let Some(event) = events.next().await {
+            defmt::println!("{:?} {:?}", event, host.topology());
+
+            if let Some(device) = ax88772.device.take() {
+                let otge = AX88772::new(host.bus(), device);
+                if let Err(e) = otge.init().await {
+                    defmt::println!("error {}", e);
                 }
             }
         }
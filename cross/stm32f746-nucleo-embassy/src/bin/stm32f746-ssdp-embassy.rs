@@ -290,6 +290,7 @@ async fn main(spawner: Spawner) -> ! {
         cotton_netif::Flags::UP
             | cotton_netif::Flags::RUNNING
             | cotton_netif::Flags::MULTICAST,
+        cotton_netif::LinkInfo::default(),
     );
 
     {
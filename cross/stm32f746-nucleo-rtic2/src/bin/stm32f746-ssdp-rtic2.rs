@@ -129,6 +129,7 @@ mod app {
             cotton_netif::Flags::UP
                 | cotton_netif::Flags::RUNNING
                 | cotton_netif::Flags::MULTICAST,
+            cotton_netif::LinkInfo::default(),
         );
 
         {
@@ -0,0 +1,18 @@
+#![no_main]
+
+use cotton_usb_host::device::identify::IdentifyFromDescriptors;
+use cotton_usb_host::usb_bus::BasicConfiguration;
+use cotton_usb_host::wire::{parse_descriptors, ShowDescriptors};
+use cotton_usb_host_msc::mass_storage::IdentifyMassStorage;
+use libfuzzer_sys::fuzz_target;
+
+// Configuration-descriptor sequences, as returned by GET_DESCRIPTOR,
+// through each of the visitors which interpret them
+fuzz_target!(|data: &[u8]| {
+    parse_descriptors(data, &mut ShowDescriptors);
+    parse_descriptors(data, &mut BasicConfiguration::default());
+    let mut msc = IdentifyMassStorage::default();
+    parse_descriptors(data, &mut msc);
+    let _ = msc.identify();
+    let _ = msc.uas();
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use cotton_scsi::image_transport::ImageTransport;
+use cotton_scsi::{gpt, mbr, ScsiBlockDevice, ScsiDevice};
+use futures::FutureExt;
+use libfuzzer_sys::fuzz_target;
+
+// Disk images (padded to whole 512-byte blocks), read as MBR and as GPT
+fuzz_target!(|data: &[u8]| {
+    let mut image = data.to_vec();
+    image.resize(data.len().div_ceil(512).max(1) * 512, 0);
+    let mut dev = ScsiBlockDevice::new(ScsiDevice::new(
+        ImageTransport::from_vec(image, 512),
+    ));
+
+    let mut partitions = [mbr::PartitionEntry::default(); 8];
+    let _ = mbr::read_partitions(&mut dev, &mut partitions).now_or_never();
+
+    let mut partitions = [gpt::GptEntry::default(); 8];
+    if let Some(Ok(n)) =
+        gpt::read_partitions(&mut dev, &mut partitions).now_or_never()
+    {
+        for p in &partitions[..n] {
+            let _ = p.blocks();
+            let _ = p.name(&mut [0u8; 16]);
+        }
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use cotton_scsi::SenseData;
+use libfuzzer_sys::fuzz_target;
+
+// REQUEST SENSE replies, in fixed or descriptor format
+fuzz_target!(|data: &[u8]| {
+    if let Some(sense) = SenseData::parse(data) {
+        let _ = sense.progress();
+    }
+});